@@ -1,6 +1,6 @@
 use crate::Paddle;
-use cgmath::{InnerSpace, Vector2, Zero};
-use crystal_engine::*;
+use cgmath::{InnerSpace, Vector2, Vector3, Zero};
+use crystal_engine::{state::Collider, *};
 use rand::{thread_rng, Rng};
 
 pub struct Ball {
@@ -16,15 +16,14 @@ impl Ball {
             direction: Vector2::zero(),
             handle: state
                 .new_obj_model("examples/pong/assets/ball.obj")
+                .with_collider(Collider::sphere(Vector3::new(0.0, 0.0, 0.0), 0.1))
                 .build()
                 .unwrap(),
         }
     }
 
     fn hits(&self, paddle: &Paddle) -> bool {
-        let diff = self.position - paddle.position;
-
-        diff.x.abs() < 0.2 && diff.y.abs() < 0.3
+        self.handle.intersects(&paddle.handle)
     }
 
     pub fn start(&mut self) {
@@ -1,9 +1,9 @@
-use cgmath::{Deg, Euler, Rad, Vector2};
-use crystal_engine::*;
+use cgmath::{Deg, Euler, Rad, Vector2, Vector3};
+use crystal_engine::{state::Collider, *};
 
 pub struct Paddle {
     pub position: Vector2<f32>,
-    handle: ModelHandle,
+    pub handle: ModelHandle,
 }
 
 impl Paddle {
@@ -11,6 +11,11 @@ impl Paddle {
         let handle = state
             .new_obj_model("examples/pong/assets/paddle.obj")
             .with_rotation(Euler::new(Deg(90.0).into(), Rad(0.0), Rad(0.0)))
+            // The paddle is rotated, so its long side is along the Z axis of the model
+            .with_collider(Collider::Box {
+                min: Vector3::new(-0.1, -0.1, -0.2),
+                max: Vector3::new(0.1, 0.1, 0.2),
+            })
             .build()
             .unwrap();
         let left = Paddle {
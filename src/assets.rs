@@ -0,0 +1,93 @@
+use std::path::{Path, PathBuf};
+
+/// The directories that assets are loaded from. This makes it possible for mods to replace textures, models and fonts without changes to the game.
+///
+/// Every relative path that is passed to the engine, like the path of a model in [GameState::new_obj_model] or a texture in [ModelBuilder::with_texture_from_file], is looked up in the registered directories. Directories that are added later override the directories that were added before them. If none of the directories contain the file, the path is used as-is, relative to the working directory of the game.
+///
+/// ```no_run
+/// # use crystal_engine::*;
+/// # let mut state: GameState = unsafe { std::mem::zeroed() };
+/// state.assets.add_directory("assets");
+/// // Every mod can replace the files in `assets`, and the mods that are loaded later win
+/// for entry in std::fs::read_dir("mods").unwrap() {
+///     state.assets.add_directory(entry.unwrap().path());
+/// }
+///
+/// // Loads `mods/<mod>/models/player.obj` if a mod has this file, or `assets/models/player.obj` otherwise
+/// let player = state.new_obj_model("models/player.obj").build().unwrap();
+/// ```
+///
+/// Absolute paths are never overridden. Files that are loaded by another file, e.g. the materials of an OBJ file, are loaded relative to the file that loads them.
+///
+/// [GameState::new_obj_model]: ../struct.GameState.html#method.new_obj_model
+/// [ModelBuilder::with_texture_from_file]: ../struct.ModelBuilder.html#method.with_texture_from_file
+#[derive(Debug, Clone, Default)]
+pub struct AssetPaths {
+    directories: Vec<PathBuf>,
+}
+
+impl AssetPaths {
+    /// Add a directory that assets are loaded from. Files in this directory override the files in every directory that was added before.
+    ///
+    /// Adding a directory that was already added moves it to the top, so it overrides every other directory.
+    pub fn add_directory(&mut self, directory: impl Into<PathBuf>) {
+        let directory = directory.into();
+        self.directories.retain(|d| d != &directory);
+        self.directories.push(directory);
+    }
+
+    /// Remove a directory that was added with [add_directory](#method.add_directory). Returns `true` if the directory was registered.
+    ///
+    /// This only affects assets that are loaded afterwards, assets that are already loaded are not reloaded.
+    pub fn remove_directory(&mut self, directory: impl AsRef<Path>) -> bool {
+        let directory = directory.as_ref();
+        let count = self.directories.len();
+        self.directories.retain(|d| d != directory);
+        self.directories.len() != count
+    }
+
+    /// The registered directories, from the lowest to the highest priority.
+    pub fn directories(&self) -> &[PathBuf] {
+        &self.directories
+    }
+
+    /// Find the file that the engine loads for the given path. This is the path in the last added directory that contains the file, or `path` itself if no directory contains it.
+    pub fn resolve(&self, path: impl AsRef<Path>) -> PathBuf {
+        let path = path.as_ref();
+        if path.is_absolute() {
+            return path.to_owned();
+        }
+        self.directories
+            .iter()
+            .rev()
+            .map(|directory| directory.join(path))
+            .find(|candidate| candidate.is_file())
+            .unwrap_or_else(|| path.to_owned())
+    }
+}
+
+#[test]
+fn test_asset_paths_override() {
+    let root = std::env::temp_dir().join(format!("crystal-assets-{}", std::process::id()));
+    let (base, mod_directory) = (root.join("base"), root.join("mod"));
+    std::fs::create_dir_all(&base).unwrap();
+    std::fs::create_dir_all(&mod_directory).unwrap();
+    std::fs::write(base.join("a.png"), &[]).unwrap();
+    std::fs::write(base.join("b.png"), &[]).unwrap();
+    std::fs::write(mod_directory.join("a.png"), &[]).unwrap();
+
+    let mut assets = AssetPaths::default();
+    assets.add_directory(&base);
+    assets.add_directory(&mod_directory);
+    assert_eq!(mod_directory.join("a.png"), assets.resolve("a.png"));
+    assert_eq!(base.join("b.png"), assets.resolve("b.png"));
+    assert_eq!(Path::new("c.png"), assets.resolve("c.png"));
+
+    // Adding the base directory again gives it the highest priority
+    assets.add_directory(&base);
+    assert_eq!(base.join("a.png"), assets.resolve("a.png"));
+    assert!(assets.remove_directory(&base));
+    assert_eq!(mod_directory.join("a.png"), assets.resolve("a.png"));
+
+    std::fs::remove_dir_all(root).unwrap();
+}
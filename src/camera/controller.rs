@@ -0,0 +1,350 @@
+use crate::game_state::KeyboardState;
+use cgmath::{Angle, EuclideanSpace, InnerSpace, Matrix4, Point3, Rad, Vector3, Zero};
+use std::time::Duration;
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent};
+
+/// The pitch is kept just below straight up or down, because the camera can not look along its up vector.
+const MAX_PITCH: Rad<f32> = Rad(std::f32::consts::FRAC_PI_2 - 0.01);
+/// The amount of pixels of a `MouseScrollDelta::PixelDelta` that count as a single line.
+const PIXELS_PER_LINE: f32 = 20.0;
+
+/// A camera that is controlled by the player. When [GameState::camera_controller] is set, the controller writes [GameState::camera] every frame, before [Game::update] is called. Set it to `None` to control the camera yourself again.
+///
+/// ```no_run
+/// # use crystal_engine::*;
+/// # use crystal_engine::state::{CameraController, OrbitCamera};
+/// # use cgmath::Vector3;
+/// # let mut state: GameState = unsafe { std::mem::zeroed() };
+/// // Look at the origin from 10 units away
+/// state.camera_controller = Some(OrbitCamera::new(Vector3::new(0.0, 0.0, 0.0), 10.0).into());
+///
+/// // Later on, change the target of the camera
+/// if let Some(CameraController::Orbit(orbit)) = &mut state.camera_controller {
+///     orbit.target = Vector3::new(5.0, 0.0, 0.0);
+/// }
+/// ```
+///
+/// [GameState::camera_controller]: ../struct.GameState.html#structfield.camera_controller
+/// [GameState::camera]: ../struct.GameState.html#structfield.camera
+/// [Game::update]: ../trait.Game.html#tymethod.update
+#[derive(Debug, Clone)]
+pub enum CameraController {
+    /// Rotate around, zoom to and pan a target with the mouse. See [OrbitCamera].
+    ///
+    /// [OrbitCamera]: ./struct.OrbitCamera.html
+    Orbit(OrbitCamera),
+    /// Fly through the world with the keyboard, and look around with the mouse. See [FlyCamera].
+    ///
+    /// [FlyCamera]: ./struct.FlyCamera.html
+    Fly(FlyCamera),
+}
+
+impl CameraController {
+    pub(crate) fn handle_window_event(&mut self, event: &WindowEvent) {
+        if let CameraController::Orbit(orbit) = self {
+            orbit.handle_window_event(event);
+        }
+    }
+
+    /// Handle the raw motion of the mouse, which is only received while the [MouseMode] is `Relative`.
+    ///
+    /// [MouseMode]: ./enum.MouseMode.html
+    pub(crate) fn mouse_moved(&mut self, delta: (f64, f64)) {
+        if let CameraController::Fly(fly) = self {
+            fly.motion.0 += delta.0;
+            fly.motion.1 += delta.1;
+        }
+    }
+
+    /// Apply the input since the last frame, and return the new camera matrix.
+    pub(crate) fn update(&mut self, keyboard: &KeyboardState, delta: Duration) -> Matrix4<f32> {
+        match self {
+            CameraController::Orbit(orbit) => {
+                orbit.apply_input();
+                orbit.matrix()
+            }
+            CameraController::Fly(fly) => {
+                fly.apply_input(keyboard, delta);
+                fly.matrix()
+            }
+        }
+    }
+}
+
+impl From<OrbitCamera> for CameraController {
+    fn from(orbit: OrbitCamera) -> Self {
+        CameraController::Orbit(orbit)
+    }
+}
+
+impl From<FlyCamera> for CameraController {
+    fn from(fly: FlyCamera) -> Self {
+        CameraController::Fly(fly)
+    }
+}
+
+/// A camera that looks at a target. Dragging with the [rotate_button](#structfield.rotate_button) rotates around the target, dragging with the [pan_button](#structfield.pan_button) moves the target and the mouse wheel zooms in and out.
+///
+/// This works in every [MouseMode](./enum.MouseMode.html), but is meant to be used with a visible cursor.
+#[derive(Debug, Clone)]
+pub struct OrbitCamera {
+    /// The point that the camera looks at and rotates around.
+    pub target: Vector3<f32>,
+    /// The distance between the camera and the target, in world units.
+    pub distance: f32,
+    /// The rotation around the target, around the y-axis. At a yaw of 0 the camera is on the positive z-axis of the target.
+    pub yaw: Rad<f32>,
+    /// The rotation above or below the target. A positive pitch looks down on the target.
+    pub pitch: Rad<f32>,
+    /// The closest the camera can zoom in. Defaults to `0.1`.
+    pub min_distance: f32,
+    /// The farthest the camera can zoom out. Defaults to `1000.0`.
+    pub max_distance: f32,
+    /// The rotation for every pixel that the cursor moves, in radians. Defaults to `0.005`.
+    pub rotate_speed: f32,
+    /// The fraction of the distance that the camera moves for every line that is scrolled. Defaults to `0.1`.
+    pub zoom_speed: f32,
+    /// The movement of the target for every pixel that the cursor moves, as a fraction of the distance. Defaults to `0.002`.
+    pub pan_speed: f32,
+    /// The mouse button to rotate the camera with. Defaults to `MouseButton::Left`.
+    pub rotate_button: MouseButton,
+    /// The mouse button to pan the camera with. Defaults to `MouseButton::Right`.
+    pub pan_button: MouseButton,
+
+    cursor: Option<(f64, f64)>,
+    rotating: bool,
+    panning: bool,
+    rotation: (f64, f64),
+    pan: (f64, f64),
+    scroll: f32,
+}
+
+impl OrbitCamera {
+    /// Create a camera that looks at the target from the given distance, slightly from above.
+    pub fn new(target: Vector3<f32>, distance: f32) -> Self {
+        Self {
+            target,
+            distance,
+            yaw: Rad(0.0),
+            pitch: Rad(0.5),
+            min_distance: 0.1,
+            max_distance: 1000.0,
+            rotate_speed: 0.005,
+            zoom_speed: 0.1,
+            pan_speed: 0.002,
+            rotate_button: MouseButton::Left,
+            pan_button: MouseButton::Right,
+            cursor: None,
+            rotating: false,
+            panning: false,
+            rotation: (0.0, 0.0),
+            pan: (0.0, 0.0),
+            scroll: 0.0,
+        }
+    }
+
+    /// The position of the camera in the world.
+    pub fn eye(&self) -> Vector3<f32> {
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+        self.target
+            + Vector3::new(sin_yaw * cos_pitch, sin_pitch, cos_yaw * cos_pitch) * self.distance
+    }
+
+    /// The view matrix of the camera.
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::look_at(
+            Point3::from_vec(self.eye()),
+            Point3::from_vec(self.target),
+            Vector3::unit_y(),
+        )
+    }
+
+    fn handle_window_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                if let Some((x, y)) = self.cursor {
+                    let delta = (position.x - x, position.y - y);
+                    if self.rotating {
+                        self.rotation.0 += delta.0;
+                        self.rotation.1 += delta.1;
+                    }
+                    if self.panning {
+                        self.pan.0 += delta.0;
+                        self.pan.1 += delta.1;
+                    }
+                }
+                self.cursor = Some((position.x, position.y));
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.cursor = None;
+            }
+            // The release of a button is not received when the window loses focus while dragging
+            WindowEvent::Focused(false) => {
+                self.rotating = false;
+                self.panning = false;
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                let pressed = *state == ElementState::Pressed;
+                if *button == self.rotate_button {
+                    self.rotating = pressed;
+                }
+                if *button == self.pan_button {
+                    self.panning = pressed;
+                }
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.scroll += match delta {
+                    MouseScrollDelta::LineDelta(_, lines) => *lines,
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / PIXELS_PER_LINE,
+                };
+            }
+            _ => {}
+        }
+    }
+
+    fn apply_input(&mut self) {
+        let rotation = std::mem::replace(&mut self.rotation, (0.0, 0.0));
+        self.yaw -= Rad(rotation.0 as f32 * self.rotate_speed);
+        self.pitch = clamp_pitch(self.pitch + Rad(rotation.1 as f32 * self.rotate_speed));
+
+        let pan = std::mem::replace(&mut self.pan, (0.0, 0.0));
+        if pan != (0.0, 0.0) {
+            let forward = (self.target - self.eye()).normalize();
+            let right = forward.cross(Vector3::unit_y()).normalize();
+            let up = right.cross(forward);
+            let scale = self.pan_speed * self.distance;
+            self.target += (up * pan.1 as f32 - right * pan.0 as f32) * scale;
+        }
+
+        let scroll = std::mem::replace(&mut self.scroll, 0.0);
+        self.distance = (self.distance * (1.0 - self.zoom_speed).powf(scroll))
+            .max(self.min_distance)
+            .min(self.max_distance);
+    }
+}
+
+/// A first-person camera that flies through the world. The keys move the camera in the direction it is looking, and the mouse turns the camera.
+///
+/// The camera only turns while the [MouseMode](./enum.MouseMode.html) is `Relative`, as it uses the raw motion of the mouse. Set it with `GameState::set_mouse_mode`.
+#[derive(Debug, Clone)]
+pub struct FlyCamera {
+    /// The position of the camera in the world.
+    pub position: Vector3<f32>,
+    /// The rotation around the y-axis. At a yaw of 0 the camera looks towards the negative z-axis, a positive yaw turns right.
+    pub yaw: Rad<f32>,
+    /// The rotation up or down. A positive pitch looks up.
+    pub pitch: Rad<f32>,
+    /// The speed of the camera in world units per second. Defaults to `5.0`.
+    pub speed: f32,
+    /// The speed is multiplied with this while the [fast](#structfield.fast) key is pressed. Defaults to `4.0`.
+    pub fast_multiplier: f32,
+    /// The rotation for every unit of raw mouse motion, in radians. Defaults to `0.002`.
+    pub mouse_sensitivity: f32,
+    /// Move in the direction the camera is looking. Defaults to `W`.
+    pub forward: VirtualKeyCode,
+    /// Move away from the direction the camera is looking. Defaults to `S`.
+    pub backward: VirtualKeyCode,
+    /// Move to the left. Defaults to `A`.
+    pub left: VirtualKeyCode,
+    /// Move to the right. Defaults to `D`.
+    pub right: VirtualKeyCode,
+    /// Move straight up. Defaults to `E`.
+    pub up: VirtualKeyCode,
+    /// Move straight down. Defaults to `Q`.
+    pub down: VirtualKeyCode,
+    /// Move faster while this key is pressed. Defaults to `LShift`.
+    pub fast: VirtualKeyCode,
+
+    motion: (f64, f64),
+}
+
+impl FlyCamera {
+    /// Create a camera at the given position, looking towards the negative z-axis.
+    pub fn new(position: Vector3<f32>) -> Self {
+        Self {
+            position,
+            yaw: Rad(0.0),
+            pitch: Rad(0.0),
+            speed: 5.0,
+            fast_multiplier: 4.0,
+            mouse_sensitivity: 0.002,
+            forward: VirtualKeyCode::W,
+            backward: VirtualKeyCode::S,
+            left: VirtualKeyCode::A,
+            right: VirtualKeyCode::D,
+            up: VirtualKeyCode::E,
+            down: VirtualKeyCode::Q,
+            fast: VirtualKeyCode::LShift,
+            motion: (0.0, 0.0),
+        }
+    }
+
+    /// The direction the camera is looking in, as a unit vector.
+    pub fn direction(&self) -> Vector3<f32> {
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+        Vector3::new(sin_yaw * cos_pitch, sin_pitch, -cos_yaw * cos_pitch)
+    }
+
+    /// The view matrix of the camera.
+    pub fn matrix(&self) -> Matrix4<f32> {
+        let eye = Point3::from_vec(self.position);
+        Matrix4::look_at(eye, eye + self.direction(), Vector3::unit_y())
+    }
+
+    fn apply_input(&mut self, keyboard: &KeyboardState, delta: Duration) {
+        let motion = std::mem::replace(&mut self.motion, (0.0, 0.0));
+        self.yaw += Rad(motion.0 as f32 * self.mouse_sensitivity);
+        self.pitch = clamp_pitch(self.pitch - Rad(motion.1 as f32 * self.mouse_sensitivity));
+
+        let axis = |positive: VirtualKeyCode, negative: VirtualKeyCode| {
+            let value = |key| if keyboard.is_pressed(key) { 1.0 } else { 0.0 };
+            value(positive) - value(negative)
+        };
+        let forward = self.direction();
+        let right = forward.cross(Vector3::unit_y()).normalize();
+        let movement = forward * axis(self.forward, self.backward)
+            + right * axis(self.right, self.left)
+            + Vector3::unit_y() * axis(self.up, self.down);
+        if movement.is_zero() {
+            return;
+        }
+        let speed = if keyboard.is_pressed(self.fast) {
+            self.speed * self.fast_multiplier
+        } else {
+            self.speed
+        };
+        self.position += movement.normalize() * speed * delta.as_secs_f32();
+    }
+}
+
+fn clamp_pitch(pitch: Rad<f32>) -> Rad<f32> {
+    Rad(pitch.0.max(-MAX_PITCH.0).min(MAX_PITCH.0))
+}
+
+#[test]
+fn test_camera_controllers() {
+    use cgmath::Transform;
+
+    let mut orbit = OrbitCamera::new(Vector3::new(1.0, 0.0, 0.0), 2.0);
+    orbit.pitch = Rad(0.0);
+    assert!((orbit.eye() - Vector3::new(1.0, 0.0, 2.0)).magnitude() < 1e-5);
+    // The target is straight in front of the camera
+    let target = orbit.matrix().transform_point(Point3::new(1.0, 0.0, 0.0));
+    assert!((target.to_vec() - Vector3::new(0.0, 0.0, -2.0)).magnitude() < 1e-5);
+
+    orbit.scroll = 1.0;
+    orbit.rotation = (0.0, 1e6);
+    orbit.apply_input();
+    assert!((orbit.distance - 1.8).abs() < 1e-5);
+    assert_eq!(MAX_PITCH, orbit.pitch);
+
+    let mut fly = FlyCamera::new(Vector3::new(0.0, 1.0, 0.0));
+    let mut keyboard = KeyboardState::default();
+    keyboard.press(VirtualKeyCode::W);
+    keyboard.press(VirtualKeyCode::D);
+    fly.apply_input(&keyboard, Duration::from_secs(1));
+    let expected = Vector3::new(1.0, 0.0, -1.0).normalize() * 5.0 + Vector3::unit_y();
+    assert!((fly.position - expected).magnitude() < 1e-5);
+}
@@ -0,0 +1,156 @@
+use cgmath::{Matrix4, Rad, SquareMatrix, Vector3, Zero};
+use std::time::Duration;
+
+/// Effects that are applied to the camera, like screen shakes and punches.
+///
+/// The effects are applied as an offset on top of [GameState::camera] every frame, so you can
+/// keep updating the camera as you normally would.
+///
+/// ```no_run
+/// # use crystal_engine::*;
+/// # use std::time::Duration;
+/// # let mut state: GameState = unsafe { std::mem::zeroed() };
+/// // The player got hit, shake the screen for half a second
+/// state.camera_effects.shake(0.1, Duration::from_millis(500));
+/// ```
+///
+/// [GameState::camera]: ../struct.GameState.html#structfield.camera
+#[derive(Default, Clone)]
+pub struct CameraEffects {
+    effects: Vec<Effect>,
+    time: f32,
+}
+
+#[derive(Clone)]
+struct Effect {
+    kind: EffectKind,
+    duration: Duration,
+    elapsed: Duration,
+}
+
+#[derive(Clone)]
+enum EffectKind {
+    Shake { amplitude: f32 },
+    Punch { offset: Vector3<f32> },
+}
+
+impl Effect {
+    /// The strength of the effect, going from `1.0` at the start to `0.0` at the end.
+    fn strength(&self) -> f32 {
+        if self.duration == Duration::from_secs(0) {
+            return 0.0;
+        }
+        let progress = self.elapsed.as_secs_f32() / self.duration.as_secs_f32();
+        let remaining = (1.0 - progress).max(0.0);
+        remaining * remaining
+    }
+}
+
+impl CameraEffects {
+    /// Shake the camera for the given duration. The amplitude is the maximum offset of the camera
+    /// in world units. The shake will slowly fade out over the given duration.
+    ///
+    /// Multiple shakes can be active at the same time, in which case their offsets are added together.
+    pub fn shake(&mut self, amplitude: f32, duration: Duration) {
+        self.effects.push(Effect {
+            kind: EffectKind::Shake { amplitude },
+            duration,
+            elapsed: Duration::from_secs(0),
+        });
+    }
+
+    /// Punch the camera in the given direction, in view space. The camera will immediately be
+    /// moved by `offset`, and will ease back into place over the given duration.
+    ///
+    /// e.g. `punch(Vector3::new(0.0, -0.1, 0.0), ..)` will make the camera dip down, like a recoil or a landing.
+    pub fn punch(&mut self, offset: impl Into<Vector3<f32>>, duration: Duration) {
+        self.effects.push(Effect {
+            kind: EffectKind::Punch {
+                offset: offset.into(),
+            },
+            duration,
+            elapsed: Duration::from_secs(0),
+        });
+    }
+
+    /// Kick the camera forward, making it look like the camera zoomed in. Negative values will
+    /// make the camera move backwards. The camera will ease back into place over the given duration.
+    ///
+    /// This is short for `punch(Vector3::new(0.0, 0.0, -amount), duration)`, because the camera looks down -z.
+    pub fn zoom_kick(&mut self, amount: f32, duration: Duration) {
+        self.punch(Vector3::new(0.0, 0.0, -amount), duration);
+    }
+
+    /// Stop all active effects immediately.
+    pub fn clear(&mut self) {
+        self.effects.clear();
+    }
+
+    /// Returns `true` if there are any active effects.
+    pub fn is_active(&self) -> bool {
+        !self.effects.is_empty()
+    }
+
+    pub(crate) fn update(&mut self, delta: Duration) {
+        self.time += delta.as_secs_f32();
+        for effect in &mut self.effects {
+            effect.elapsed += delta;
+        }
+        self.effects.retain(|e| e.elapsed < e.duration);
+    }
+
+    /// The offset that should be applied on top of the view matrix of the camera.
+    pub(crate) fn matrix(&self) -> Matrix4<f32> {
+        if self.effects.is_empty() {
+            return Matrix4::identity();
+        }
+        let mut translation = Vector3::zero();
+        let mut roll = 0.0;
+        for effect in &self.effects {
+            let strength = effect.strength();
+            match effect.kind {
+                EffectKind::Shake { amplitude } => {
+                    let amplitude = amplitude * strength;
+                    // A couple of sine waves with frequencies that are not multiples of each
+                    // other gives a pseudo-random motion that is still smooth
+                    let t = self.time;
+                    translation.x += amplitude * ((t * 47.0).sin() + (t * 31.0).sin()) / 2.0;
+                    translation.y += amplitude * ((t * 41.0).cos() + (t * 23.0).sin()) / 2.0;
+                    roll += amplitude * 0.1 * (t * 37.0).sin();
+                }
+                EffectKind::Punch { offset } => {
+                    translation += offset * strength;
+                }
+            }
+        }
+        // The camera moves in the opposite direction of the world
+        Matrix4::from_angle_z(Rad(roll)) * Matrix4::from_translation(-translation)
+    }
+}
+
+#[test]
+fn test_camera_effects_expire() {
+    let mut effects = CameraEffects::default();
+    effects.shake(1.0, Duration::from_millis(100));
+    effects.zoom_kick(1.0, Duration::from_millis(200));
+    assert!(effects.is_active());
+
+    effects.update(Duration::from_millis(150));
+    assert_eq!(1, effects.effects.len());
+
+    effects.update(Duration::from_millis(100));
+    assert!(!effects.is_active());
+    assert_eq!(Matrix4::identity(), effects.matrix());
+}
+
+#[test]
+fn test_zoom_kick_moves_forward() {
+    let mut effects = CameraEffects::default();
+    effects.zoom_kick(1.0, Duration::from_millis(100));
+    // The camera moved forward, so a point in front of it is now closer, which is towards +z in
+    // view space
+    let point = effects.matrix() * cgmath::Vector4::new(0.0, 0.0, -5.0, 1.0);
+    assert!(point.z > -5.0);
+    assert_eq!(0.0, point.x);
+    assert_eq!(0.0, point.y);
+}
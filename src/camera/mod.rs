@@ -0,0 +1,9 @@
+mod controller;
+mod effects;
+mod timeline;
+
+pub use self::{
+    controller::{CameraController, FlyCamera, OrbitCamera},
+    effects::CameraEffects,
+    timeline::{Easing, Timeline},
+};
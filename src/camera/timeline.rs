@@ -0,0 +1,309 @@
+use crate::GameState;
+use cgmath::{InnerSpace, Matrix3, Matrix4, Point3, Quaternion, Rad, Vector3, VectorSpace};
+use std::time::Duration;
+
+/// A keyframed sequence of camera movements and events. This can be used to create cutscenes.
+///
+/// A timeline consists of three camera tracks (position, orientation and field of view) and a list
+/// of events. Each track is interpolated separately, so you can e.g. change the field of view
+/// without moving the camera.
+///
+/// The timeline is owned by your game. Call [update](#method.update) every frame to move the camera.
+///
+/// ```no_run
+/// # use crystal_engine::*;
+/// # use crystal_engine::state::Timeline;
+/// # use cgmath::Deg;
+/// # use std::time::Duration;
+/// # let mut state: GameState = unsafe { std::mem::zeroed() };
+/// let mut cutscene = Timeline::new()
+///     .with_look_at_key(Duration::from_secs(0), (0.0, 2.0, 10.0), (0.0, 0.0, 0.0))
+///     .with_look_at_key(Duration::from_secs(4), (5.0, 1.0, 2.0), (0.0, 0.0, 0.0))
+///     .with_fov_key(Duration::from_secs(2), Deg(90.0))
+///     .with_fov_key(Duration::from_secs(4), Deg(60.0))
+///     .with_event(Duration::from_secs(1), |_state| println!("Boss appears"));
+/// cutscene.play();
+///
+/// // In `Game::update`:
+/// cutscene.update(&mut state);
+/// ```
+#[derive(Default)]
+pub struct Timeline {
+    position: Track<Vector3<f32>>,
+    orientation: Track<Quaternion<f32>>,
+    fov: Track<Rad<f32>>,
+    events: Vec<(Duration, EventCallback)>,
+    time: Duration,
+    playing: bool,
+    /// Whether the timeline has played since it was created or seeked, so the events at the start time have been triggered
+    started: bool,
+}
+
+type EventCallback = Box<dyn FnMut(&mut GameState)>;
+
+/// The easing of a keyframe. This determines how the value moves from this keyframe to the next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Easing {
+    /// Move to the next keyframe at a constant speed.
+    Linear,
+    /// Start slow, speed up, and slow down again near the next keyframe.
+    Smooth,
+}
+
+impl Easing {
+    fn apply(self, t: f32) -> f32 {
+        match self {
+            Easing::Linear => t,
+            Easing::Smooth => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+struct Track<T> {
+    keys: Vec<(Duration, T, Easing)>,
+}
+
+impl<T> Default for Track<T> {
+    fn default() -> Self {
+        Self { keys: Vec::new() }
+    }
+}
+
+impl<T: Interpolate> Track<T> {
+    fn insert(&mut self, time: Duration, value: T, easing: Easing) {
+        let index = self.keys.iter().position(|k| k.0 > time);
+        let index = index.unwrap_or(self.keys.len());
+        self.keys.insert(index, (time, value, easing));
+    }
+
+    fn end(&self) -> Duration {
+        self.keys.last().map(|k| k.0).unwrap_or_default()
+    }
+
+    fn sample(&self, time: Duration) -> Option<T> {
+        let next = self.keys.iter().position(|k| k.0 > time);
+        match next {
+            None => self.keys.last().map(|k| k.1),
+            Some(0) => self.keys.first().map(|k| k.1),
+            Some(next) => {
+                let (start_time, start, easing) = self.keys[next - 1];
+                let (end_time, end, _) = self.keys[next];
+                let t = (time - start_time).as_secs_f32() / (end_time - start_time).as_secs_f32();
+                Some(start.interpolate(end, easing.apply(t)))
+            }
+        }
+    }
+}
+
+trait Interpolate: Copy {
+    fn interpolate(self, other: Self, amount: f32) -> Self;
+}
+
+impl Interpolate for Vector3<f32> {
+    fn interpolate(self, other: Self, amount: f32) -> Self {
+        self.lerp(other, amount)
+    }
+}
+
+impl Interpolate for Quaternion<f32> {
+    fn interpolate(self, other: Self, amount: f32) -> Self {
+        self.slerp(other, amount)
+    }
+}
+
+impl Interpolate for Rad<f32> {
+    fn interpolate(self, other: Self, amount: f32) -> Self {
+        self + (other - self) * amount
+    }
+}
+
+impl Timeline {
+    /// Create a new empty timeline. The timeline is paused by default.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a keyframe to the position track of the camera.
+    pub fn with_position_key(mut self, time: Duration, position: impl Into<Vector3<f32>>) -> Self {
+        self.position.insert(time, position.into(), Easing::Smooth);
+        self
+    }
+
+    /// Add a keyframe to the orientation track of the camera. The orientation is the rotation of the camera in the world.
+    /// A camera without rotation looks towards negative Z.
+    pub fn with_orientation_key(mut self, time: Duration, orientation: Quaternion<f32>) -> Self {
+        self.orientation.insert(time, orientation, Easing::Smooth);
+        self
+    }
+
+    /// Add a keyframe to both the position and orientation track, so that the camera is at `position` looking at `target`.
+    pub fn with_look_at_key(
+        self,
+        time: Duration,
+        position: impl Into<Point3<f32>>,
+        target: impl Into<Point3<f32>>,
+    ) -> Self {
+        let position = position.into();
+        let orientation = look_at_orientation(target.into() - position, Vector3::unit_y());
+        self.with_position_key(time, Vector3::new(position.x, position.y, position.z))
+            .with_orientation_key(time, orientation)
+    }
+
+    /// Add a keyframe to the field of view track of the camera.
+    pub fn with_fov_key(mut self, time: Duration, fov: impl Into<Rad<f32>>) -> Self {
+        self.fov.insert(time, fov.into(), Easing::Smooth);
+        self
+    }
+
+    /// Change the easing of all keyframes at the given time. By default all keyframes use [Easing::Smooth].
+    pub fn with_easing_at(mut self, time: Duration, easing: Easing) -> Self {
+        for key in self.position.keys.iter_mut().filter(|k| k.0 == time) {
+            key.2 = easing;
+        }
+        for key in self.orientation.keys.iter_mut().filter(|k| k.0 == time) {
+            key.2 = easing;
+        }
+        for key in self.fov.keys.iter_mut().filter(|k| k.0 == time) {
+            key.2 = easing;
+        }
+        self
+    }
+
+    /// Add an event to the timeline. The callback is called once when the timeline plays past the given time.
+    ///
+    /// Events are not triggered when [seek](#method.seek)ing past them.
+    pub fn with_event(
+        mut self,
+        time: Duration,
+        callback: impl FnMut(&mut GameState) + 'static,
+    ) -> Self {
+        self.events.push((time, Box::new(callback)));
+        self
+    }
+
+    /// Start or resume playing the timeline.
+    pub fn play(&mut self) {
+        self.playing = true;
+    }
+
+    /// Pause the timeline. The camera will stay where it is.
+    pub fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    /// Jump to the given time in the timeline. The camera is moved on the next [update](#method.update), and the events at this time are triggered if the timeline is playing.
+    pub fn seek(&mut self, time: Duration) {
+        self.time = time.min(self.duration());
+        self.started = false;
+    }
+
+    /// Returns `true` if the timeline is currently playing.
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Returns `true` if the timeline has played until the last keyframe or event.
+    pub fn is_finished(&self) -> bool {
+        self.time >= self.duration()
+    }
+
+    /// Get the current time of the timeline.
+    pub fn time(&self) -> Duration {
+        self.time
+    }
+
+    /// Get the total duration of the timeline. This is the time of the last keyframe or event.
+    pub fn duration(&self) -> Duration {
+        let last_event = self.events.iter().map(|e| e.0).max().unwrap_or_default();
+        self.position
+            .end()
+            .max(self.orientation.end())
+            .max(self.fov.end())
+            .max(last_event)
+    }
+
+    /// Advance the timeline by the delta time of the game, trigger any events that have passed and update the camera.
+    ///
+    /// The camera is only updated if both the position and orientation tracks have keyframes.
+    /// The field of view is only updated if the field of view track has keyframes.
+    pub fn update(&mut self, state: &mut GameState) {
+        for index in self.advance(state.time.delta()) {
+            (self.events[index].1)(state);
+        }
+
+        if let (Some(position), Some(orientation)) = (
+            self.position.sample(self.time),
+            self.orientation.sample(self.time),
+        ) {
+            state.camera =
+                Matrix4::from(orientation.conjugate()) * Matrix4::from_translation(-position);
+        }
+        if let Some(fov) = self.fov.sample(self.time) {
+            state.camera_fov = fov;
+        }
+    }
+
+    /// Advance the time of the timeline if it is playing, and return the indices of the events that have passed. The events at the start time are included on the first update after the timeline was created or seeked.
+    fn advance(&mut self, delta: Duration) -> Vec<usize> {
+        if !self.playing {
+            return Vec::new();
+        }
+        let previous = self.time;
+        let started = std::mem::replace(&mut self.started, true);
+        self.time = (self.time + delta).min(self.duration());
+        if self.is_finished() {
+            self.playing = false;
+        }
+        let time = self.time;
+        self.events
+            .iter()
+            .enumerate()
+            .filter(|(_, (event, _))| {
+                (*event > previous || (!started && *event == previous)) && *event <= time
+            })
+            .map(|(index, _)| index)
+            .collect()
+    }
+}
+
+fn look_at_orientation(direction: Vector3<f32>, up: Vector3<f32>) -> Quaternion<f32> {
+    let forward = direction.normalize();
+    let side = forward.cross(up).normalize();
+    let up = side.cross(forward);
+    Quaternion::from(Matrix3::from_cols(side, up, -forward))
+}
+
+#[test]
+fn test_track_sample() {
+    let mut track = Track::default();
+    track.insert(Duration::from_secs(2), Rad(2.0f32), Easing::Linear);
+    track.insert(Duration::from_secs(0), Rad(0.0f32), Easing::Linear);
+
+    assert_eq!(Some(Rad(0.0)), track.sample(Duration::from_secs(0)));
+    assert_eq!(Some(Rad(1.0)), track.sample(Duration::from_secs(1)));
+    assert_eq!(Some(Rad(2.0)), track.sample(Duration::from_secs(5)));
+}
+
+#[test]
+fn test_timeline_events() {
+    let secs = Duration::from_secs;
+    let mut timeline = Timeline::new()
+        .with_event(secs(0), |_| {})
+        .with_event(secs(1), |_| {})
+        .with_event(secs(2), |_| {});
+    assert!(timeline.advance(secs(1)).is_empty());
+
+    // An event at the start is triggered on the first update, and only once
+    timeline.play();
+    assert_eq!(vec![0], timeline.advance(Duration::from_millis(500)));
+    assert_eq!(vec![1], timeline.advance(secs(1)));
+    timeline.seek(secs(1));
+    assert_eq!(vec![1, 2], timeline.advance(secs(5)));
+    assert!(!timeline.is_playing());
+
+    // A timeline with only an event at the start finishes on the first update
+    let mut timeline = Timeline::new().with_event(secs(0), |_| {});
+    timeline.play();
+    assert_eq!(vec![0], timeline.advance(Duration::default()));
+    assert!(timeline.is_finished());
+}
@@ -0,0 +1,293 @@
+use crate::model::transform_box;
+use cgmath::{InnerSpace, Matrix4, Transform, Vector3};
+
+/// The amount of steps that is used to find the point of a capsule that is closest to a box
+const CAPSULE_SEARCH_STEPS: usize = 32;
+
+/// A simple shape that is attached to a model to test if it touches other models, see [ModelData::collider](../models/struct.ModelData.html#structfield.collider). This is meant for games that need simple hit tests, like a ball that bounces off a paddle, and is not a replacement for a physics engine.
+///
+/// The collider is in the local space of the model. The position, rotation and scale of the model are applied when the collider is tested, see [ModelHandle::collider](../struct.ModelHandle.html#method.collider). A rotated box is wrapped in a new axis-aligned box, so boxes are most accurate for models that are not rotated.
+///
+/// ```no_run
+/// # use crystal_engine::*;
+/// # use crystal_engine::state::Collider;
+/// # use cgmath::Vector3;
+/// # let mut state: GameState = unsafe { std::mem::zeroed() };
+/// let ball = state
+///     .new_uv_sphere_model(16, 8)
+///     .with_collider(Collider::sphere(Vector3::new(0.0, 0.0, 0.0), 1.0))
+///     .build()
+///     .unwrap();
+/// let wall = state
+///     .new_cube_model()
+///     .with_collider(Collider::Box {
+///         min: Vector3::new(-1.0, -1.0, -1.0),
+///         max: Vector3::new(1.0, 1.0, 1.0),
+///     })
+///     .build()
+///     .unwrap();
+/// if ball.intersects(&wall) {
+///     // bounce
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum Collider {
+    /// A sphere around a center point.
+    Sphere {
+        /// The center of the sphere
+        center: Vector3<f32>,
+        /// The radius of the sphere
+        radius: f32,
+    },
+    /// An axis-aligned box.
+    Box {
+        /// The corner of the box with the lowest coordinates
+        min: Vector3<f32>,
+        /// The corner of the box with the highest coordinates
+        max: Vector3<f32>,
+    },
+    /// A line segment with a radius around it, like a pill. This fits characters and long thin objects better than a sphere or a box.
+    Capsule {
+        /// The center of one of the round ends of the capsule
+        start: Vector3<f32>,
+        /// The center of the other round end of the capsule
+        end: Vector3<f32>,
+        /// The radius of the capsule
+        radius: f32,
+    },
+}
+
+impl Collider {
+    /// Create a [Sphere](#variant.Sphere) collider.
+    pub fn sphere(center: impl Into<Vector3<f32>>, radius: f32) -> Self {
+        Collider::Sphere {
+            center: center.into(),
+            radius,
+        }
+    }
+
+    /// Create a [Box](#variant.Box) collider from the bounding box of a model, see [ModelHandle::bounding_box](../struct.ModelHandle.html#method.bounding_box).
+    pub fn from_bounding_box((min, max): (Vector3<f32>, Vector3<f32>)) -> Self {
+        Collider::Box { min, max }
+    }
+
+    /// Create a [Capsule](#variant.Capsule) collider.
+    pub fn capsule(
+        start: impl Into<Vector3<f32>>,
+        end: impl Into<Vector3<f32>>,
+        radius: f32,
+    ) -> Self {
+        Collider::Capsule {
+            start: start.into(),
+            end: end.into(),
+            radius,
+        }
+    }
+
+    /// Check if this collider touches or overlaps another collider. Both colliders have to be in the same space, e.g. both in world space.
+    pub fn intersects(&self, other: &Collider) -> bool {
+        use Collider::*;
+        match (*self, *other) {
+            (
+                Sphere {
+                    center: a,
+                    radius: ra,
+                },
+                Sphere {
+                    center: b,
+                    radius: rb,
+                },
+            ) => (a - b).magnitude2() <= (ra + rb) * (ra + rb),
+            (Sphere { center, radius }, Box { min, max })
+            | (Box { min, max }, Sphere { center, radius }) => {
+                (closest_on_box(center, (min, max)) - center).magnitude2() <= radius * radius
+            }
+            (
+                Box {
+                    min: a_min,
+                    max: a_max,
+                },
+                Box {
+                    min: b_min,
+                    max: b_max,
+                },
+            ) => {
+                a_min.x <= b_max.x
+                    && b_min.x <= a_max.x
+                    && a_min.y <= b_max.y
+                    && b_min.y <= a_max.y
+                    && a_min.z <= b_max.z
+                    && b_min.z <= a_max.z
+            }
+            (
+                Sphere { center, radius },
+                Capsule {
+                    start,
+                    end,
+                    radius: capsule_radius,
+                },
+            )
+            | (
+                Capsule {
+                    start,
+                    end,
+                    radius: capsule_radius,
+                },
+                Sphere { center, radius },
+            ) => {
+                let closest = closest_on_segment(center, (start, end));
+                let distance = radius + capsule_radius;
+                (closest - center).magnitude2() <= distance * distance
+            }
+            (Box { min, max }, Capsule { start, end, radius })
+            | (Capsule { start, end, radius }, Box { min, max }) => {
+                let distance = segment_distance((start, end), |p| {
+                    (closest_on_box(p, (min, max)) - p).magnitude()
+                });
+                distance <= radius
+            }
+            (
+                Capsule {
+                    start: a_start,
+                    end: a_end,
+                    radius: ra,
+                },
+                Capsule {
+                    start: b_start,
+                    end: b_end,
+                    radius: rb,
+                },
+            ) => {
+                let distance = segment_distance((a_start, a_end), |p| {
+                    (closest_on_segment(p, (b_start, b_end)) - p).magnitude()
+                });
+                distance <= ra + rb
+            }
+        }
+    }
+
+    /// Move the collider into the space of the given matrix, e.g. from the local space of a model to world space. Spheres and capsules are scaled by the largest scale of the matrix, and boxes are wrapped in a new axis-aligned box.
+    pub(crate) fn transform(&self, matrix: Matrix4<f32>) -> Collider {
+        let point = |p: Vector3<f32>| matrix.transform_vector(p) + matrix.w.truncate();
+        let scale = matrix
+            .x
+            .truncate()
+            .magnitude()
+            .max(matrix.y.truncate().magnitude())
+            .max(matrix.z.truncate().magnitude());
+        match *self {
+            Collider::Sphere { center, radius } => Collider::Sphere {
+                center: point(center),
+                radius: radius * scale,
+            },
+            Collider::Box { min, max } => {
+                let (min, max) = transform_box((min, max), matrix);
+                Collider::Box { min, max }
+            }
+            Collider::Capsule { start, end, radius } => Collider::Capsule {
+                start: point(start),
+                end: point(end),
+                radius: radius * scale,
+            },
+        }
+    }
+}
+
+/// Get the point in or on the box that is closest to the given point.
+fn closest_on_box(point: Vector3<f32>, (min, max): (Vector3<f32>, Vector3<f32>)) -> Vector3<f32> {
+    Vector3::new(
+        point.x.max(min.x).min(max.x),
+        point.y.max(min.y).min(max.y),
+        point.z.max(min.z).min(max.z),
+    )
+}
+
+/// Get the point on the line segment that is closest to the given point.
+fn closest_on_segment(
+    point: Vector3<f32>,
+    (start, end): (Vector3<f32>, Vector3<f32>),
+) -> Vector3<f32> {
+    let direction = end - start;
+    let length2 = direction.magnitude2();
+    if length2 <= std::f32::EPSILON {
+        return start;
+    }
+    let t = ((point - start).dot(direction) / length2).max(0.0).min(1.0);
+    start + direction * t
+}
+
+/// Find the smallest distance between a line segment and a convex shape, given the distance from a point to that shape. The distance along the segment is convex, so it can be found with a ternary search.
+fn segment_distance(
+    (start, end): (Vector3<f32>, Vector3<f32>),
+    distance: impl Fn(Vector3<f32>) -> f32,
+) -> f32 {
+    let at = |t: f32| distance(start + (end - start) * t);
+    let (mut low, mut high) = (0.0f32, 1.0f32);
+    for _ in 0..CAPSULE_SEARCH_STEPS {
+        let a = low + (high - low) / 3.0;
+        let b = high - (high - low) / 3.0;
+        if at(a) < at(b) {
+            high = b;
+        } else {
+            low = a;
+        }
+    }
+    at((low + high) / 2.0).min(at(0.0)).min(at(1.0))
+}
+
+#[test]
+fn test_intersections() {
+    let v = Vector3::new;
+    let sphere = Collider::sphere(v(0.0, 0.0, 0.0), 1.0);
+    let unit_box = |x: f32| Collider::Box {
+        min: v(x - 0.5, -0.5, -0.5),
+        max: v(x + 0.5, 0.5, 0.5),
+    };
+
+    assert!(sphere.intersects(&Collider::sphere(v(1.9, 0.0, 0.0), 1.0)));
+    assert!(!sphere.intersects(&Collider::sphere(v(2.1, 0.0, 0.0), 1.0)));
+
+    assert!(sphere.intersects(&unit_box(1.4)));
+    assert!(!unit_box(1.6).intersects(&sphere));
+    assert!(unit_box(0.0).intersects(&unit_box(0.9)));
+    assert!(!unit_box(0.0).intersects(&unit_box(1.1)));
+
+    let capsule = Collider::capsule(v(0.0, -2.0, 0.0), v(0.0, 2.0, 0.0), 0.5);
+    assert!(capsule.intersects(&Collider::sphere(v(1.4, 1.5, 0.0), 1.0)));
+    assert!(!capsule.intersects(&Collider::sphere(v(0.0, 3.6, 0.0), 1.0)));
+    assert!(capsule.intersects(&unit_box(0.9)));
+    assert!(!unit_box(1.1).intersects(&capsule));
+
+    // Two capsules that cross each other in an X shape, and two that are parallel
+    let crossing = Collider::capsule(v(-2.0, 0.0, 0.9), v(2.0, 0.0, 0.9), 0.5);
+    assert!(capsule.intersects(&crossing));
+    let parallel = Collider::capsule(v(1.1, -1.0, 0.0), v(1.1, 1.0, 0.0), 0.5);
+    assert!(!capsule.intersects(&parallel));
+}
+
+#[test]
+fn test_transform() {
+    use cgmath::{Deg, Euler};
+
+    let matrix = Matrix4::from_translation(Vector3::new(1.0, 2.0, 3.0)) * Matrix4::from_scale(2.0);
+    assert_eq!(
+        Collider::sphere(Vector3::new(1.0, 4.0, 3.0), 2.0),
+        Collider::sphere(Vector3::new(0.0, 1.0, 0.0), 1.0).transform(matrix)
+    );
+
+    let rotation = Matrix4::from(Euler::new(Deg(0.0), Deg(0.0), Deg(90.0)));
+    let rotated = Collider::capsule(
+        Vector3::new(0.0, 0.0, 0.0),
+        Vector3::new(1.0, 0.0, 0.0),
+        0.5,
+    )
+    .transform(rotation);
+    match rotated {
+        Collider::Capsule { end, radius, .. } => {
+            assert!((end - Vector3::new(0.0, 1.0, 0.0)).magnitude() < 0.0001);
+            assert!((radius - 0.5).abs() < 0.0001);
+        }
+        _ => panic!("Expected a capsule"),
+    }
+}
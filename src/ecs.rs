@@ -0,0 +1,132 @@
+//! Components that connect an entity-component-system to the engine, see [GameState::world](../struct.GameState.html#structfield.world).
+//!
+//! This is only available if the `ecs` feature is enabled.
+
+pub use hecs;
+
+use crate::{
+    render::lights::{DirectionalLight, LightState, PointLight},
+    GameState, ModelHandle,
+};
+use cgmath::{Euler, Rad, Vector3, Zero};
+use hecs::World;
+use parking_lot::Mutex;
+
+/// The position, rotation and scale of an entity. The transform of an entity with a [ModelComponent] is copied to the model every frame, and the position of an entity with a [LightComponent::Point] is copied to the light.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    /// The position of the entity in the world.
+    pub position: Vector3<f32>,
+    /// The rotation of the entity.
+    pub rotation: Euler<Rad<f32>>,
+    /// The scale of the entity. `1.0` is the original size.
+    pub scale: f32,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            position: Vector3::zero(),
+            rotation: Euler::new(Rad(0.0), Rad(0.0), Rad(0.0)),
+            scale: 1.0,
+        }
+    }
+}
+
+impl Transform {
+    /// Create a transform at the given position, without rotation and with a scale of `1.0`.
+    pub fn at(position: Vector3<f32>) -> Self {
+        Self {
+            position,
+            ..Self::default()
+        }
+    }
+}
+
+/// A model that is rendered at the [Transform] of its entity.
+///
+/// The model is removed from the world when the component is dropped, like when the [ModelHandle] is dropped.
+pub struct ModelComponent {
+    // Components have to be `Sync`, which the handle is not
+    handle: Mutex<ModelHandle>,
+}
+
+impl ModelComponent {
+    /// Create a component for a model that was built with e.g. [GameState::new_obj_model](../struct.GameState.html#method.new_obj_model).
+    pub fn new(handle: ModelHandle) -> Self {
+        Self {
+            handle: Mutex::new(handle),
+        }
+    }
+
+    /// Get the handle of the model, e.g. to change the material of the model.
+    pub fn handle_mut(&mut self) -> &mut ModelHandle {
+        self.handle.get_mut()
+    }
+
+    /// Take the handle out of the component. The model stays in the world until the handle is dropped.
+    pub fn into_handle(self) -> ModelHandle {
+        self.handle.into_inner()
+    }
+}
+
+impl From<ModelHandle> for ModelComponent {
+    fn from(handle: ModelHandle) -> Self {
+        Self::new(handle)
+    }
+}
+
+/// A light source of an entity. When the [world](../struct.GameState.html#structfield.world) contains any lights, the lights of [LightState] are replaced by these lights every frame.
+///
+/// [LightState]: ../state/struct.LightState.html
+#[derive(Clone)]
+pub enum LightComponent {
+    /// A directional light, like the sun. The [Transform] of the entity is ignored.
+    ///
+    /// Lights that do not fit in [LightState::directional](../state/struct.LightState.html#structfield.directional) are ignored.
+    Directional(DirectionalLight),
+    /// A point light. When the entity has a [Transform], the light is placed at the position of the entity.
+    Point(PointLight),
+}
+
+/// Copy the components of the world to the models and lights of the game.
+pub(crate) fn sync(game_state: &mut GameState) {
+    sync_models(&game_state.world);
+    sync_lights(&game_state.world, &mut game_state.light);
+}
+
+fn sync_models(world: &World) {
+    for (_, (transform, model)) in world.query::<(&Transform, &mut ModelComponent)>().iter() {
+        model.handle_mut().modify(|data| {
+            data.position = transform.position;
+            data.rotation = transform.rotation;
+            data.scale = transform.scale;
+        });
+    }
+}
+
+fn sync_lights(world: &World, light: &mut LightState) {
+    let mut query = world.query::<(&LightComponent, Option<&Transform>)>();
+    let mut lights = query.iter().peekable();
+    if lights.peek().is_none() {
+        // Games that only use the world for their models keep their own lights
+        return;
+    }
+    light.directional.clear();
+    light.point.clear();
+    for (_, (component, transform)) in lights {
+        match component {
+            LightComponent::Directional(directional) => {
+                // Lights that do not fit are ignored, see the documentation of `Directional`
+                let _ = light.directional.push(directional.clone());
+            }
+            LightComponent::Point(point) => {
+                let mut point = point.clone();
+                if let Some(transform) = transform {
+                    point.position = transform.position;
+                }
+                light.point.push(point);
+            }
+        }
+    }
+}
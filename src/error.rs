@@ -3,6 +3,7 @@ pub use crate::model::FbxError;
 #[cfg(feature = "format-obj")]
 pub use crate::model::ObjError;
 
+use crate::render::monitor::VideoModeInfo;
 use thiserror::Error;
 
 /// Errors generated when loading a model
@@ -21,6 +22,13 @@ pub enum ModelError {
     #[error("Model has no valid vertex buffer")]
     InvalidModelVertexBuffer,
 
+    /// The model was given no texture layers, or more than [MAX_TEXTURE_LAYERS](../models/constant.MAX_TEXTURE_LAYERS.html)
+    #[error("A model can have 1 to 4 texture layers, but {count} were given")]
+    InvalidTextureLayerCount {
+        /// The amount of texture layers that were given
+        count: usize,
+    },
+
     /// The error that was thrown whilst loading an .obj file.
     ///
     /// This error can only be thrown if the `format-obj` feature is enabled
@@ -34,6 +42,77 @@ pub enum ModelError {
     #[cfg(feature = "format-fbx")]
     #[error("Could not load FBX model: {0:?}")]
     Fbx(FbxError),
+
+    /// Could not upload the mesh or the textures of the model to the GPU
+    #[error("Could not upload model: {0}")]
+    CouldNotUpload(UploadError),
+
+    /// The model that was given to [GameState::new_model](../struct.GameState.html#method.new_model) is invalid
+    #[error("Invalid parsed model: {0}")]
+    InvalidParsedModel(ParsedModelError),
+}
+
+/// Errors generated when validating a [ParsedModel](../models/struct.ParsedModel.html), see [ParsedModel::validate](../models/struct.ParsedModel.html#method.validate) and [ParsedModelBuilder::build](../models/struct.ParsedModelBuilder.html#method.build). A model without parts is validated as a single part `0` with the vertices of the model.
+#[derive(Error, Debug)]
+pub enum ParsedModelError {
+    /// A method of [ParsedModelBuilder](../models/struct.ParsedModelBuilder.html) that changes the last part was called before any part was added
+    #[error("{method} was called before a part was added")]
+    NoPart {
+        /// The name of the method
+        method: &'static str,
+    },
+    /// The part has no vertices, and the model has no vertices that are shared by its parts
+    #[error("Part {part} has no vertices, and the model has no shared vertices")]
+    NoVertices {
+        /// The index of the part
+        part: usize,
+    },
+    /// An index of the part refers to a vertex that does not exist
+    #[error("Part {part} has index {index}, but only {vertex_count} vertices")]
+    IndexOutOfRange {
+        /// The index of the part
+        part: usize,
+        /// The invalid index
+        index: u32,
+        /// The amount of vertices that the part can use
+        vertex_count: usize,
+    },
+    /// The part is drawn as a list of triangles, but the amount of indices is not a multiple of 3. Parts without indices are drawn with their vertices, so then this is the amount of vertices.
+    #[error("Part {part} has {count} indices, which is not a multiple of 3")]
+    IncompleteTriangle {
+        /// The index of the part
+        part: usize,
+        /// The amount of indices, or vertices if the part has no indices
+        count: usize,
+    },
+    /// A morph target of the part does not have an offset for every vertex of the part. The normals can also be left empty.
+    #[error("Morph target {name:?} of part {part} has {positions} positions and {normals} normals, but the part has {vertex_count} vertices")]
+    InvalidMorphTarget {
+        /// The index of the part
+        part: usize,
+        /// The name of the morph target
+        name: String,
+        /// The amount of position offsets
+        positions: usize,
+        /// The amount of normal offsets
+        normals: usize,
+        /// The amount of vertices that the part uses
+        vertex_count: usize,
+    },
+    /// A texture of the part is empty, or its RGBA data does not have 4 bytes for every pixel
+    #[error(
+        "A texture of part {part} is {width}x{height} pixels, but has {len} bytes of RGBA data"
+    )]
+    InvalidTextureSize {
+        /// The index of the part
+        part: usize,
+        /// The width of the texture
+        width: u32,
+        /// The height of the texture
+        height: u32,
+        /// The length of the RGBA data
+        len: usize,
+    },
 }
 
 /// Errors generated when creating GUI elements
@@ -53,6 +132,12 @@ pub enum GuiError {
         /// The inner error
         inner: vulkano::image::ImageCreationError,
     },
+    /// Could not allocate a buffer to upload the texture
+    #[error("Could not allocate buffer: {inner:?}")]
+    CouldNotAllocateBuffer {
+        /// The inner error
+        inner: vulkano::memory::DeviceMemoryAllocError,
+    },
     /// Could not read the given font
     #[error("Could not read font file {file:?}: {inner:?}")]
     CouldNotReadFontFile {
@@ -64,6 +149,31 @@ pub enum GuiError {
     /// Could not parse the font file
     #[error("Could not load font")]
     CouldNotLoadFont,
+    /// The animated texture does not contain any frames
+    #[error("The animation has no frames")]
+    EmptyAnimation,
+    /// Could not upload a texture to the GPU
+    #[error("Could not upload texture: {inner}")]
+    CouldNotUploadTexture {
+        /// The inner error
+        inner: UploadError,
+    },
+}
+
+/// Errors generated when changing the icon of the window
+#[derive(Error, Debug)]
+pub enum IconError {
+    /// Could not load the image at the given path
+    #[error("Could not load icon {path:?}: {inner:?}")]
+    CouldNotLoadImage {
+        /// The path where the icon was loaded from
+        path: String,
+        /// The inner error that was thrown
+        inner: image::error::ImageError,
+    },
+    /// The image could not be used as an icon
+    #[error("Invalid icon: {0}")]
+    InvalidIcon(winit::window::BadIcon),
 }
 
 /// Errors that are thrown during initialization. These are mostly internal and graphic card errors and are (hopefully) unlikely to occur.
@@ -112,4 +222,285 @@ pub enum InitError {
     /// Could not create a vulkano_win window
     #[error("Could not create a window: {0:?}")]
     CouldNotCreateWindow(vulkano_win::CreationError),
+
+    /// The window mode of the [WindowBuilder](../struct.WindowBuilder.html#method.with_window_mode) refers to a monitor or video mode that does not exist
+    #[error("Could not set the window mode: {0}")]
+    InvalidWindowMode(MonitorError),
+}
+
+/// Errors generated when changing the [WindowMode](./enum.WindowMode.html) of the window
+#[derive(Error, Debug)]
+pub enum MonitorError {
+    /// There is no monitor with the given index, see [GameState::monitors](../struct.GameState.html#method.monitors)
+    #[error("There are {count} monitors, so there is no monitor {index}")]
+    UnknownMonitor {
+        /// The index of the monitor
+        index: usize,
+        /// The amount of monitors
+        count: usize,
+    },
+    /// The monitor does not support the video mode, see [MonitorInfo::video_modes](./struct.MonitorInfo.html#structfield.video_modes)
+    #[error("Monitor {monitor} does not support video mode {video_mode:?}")]
+    UnknownVideoMode {
+        /// The index of the monitor
+        monitor: usize,
+        /// The video mode
+        video_mode: VideoModeInfo,
+    },
+}
+
+/// Errors that are thrown when saving or loading a [Scene](./struct.Scene.html)
+#[derive(Error, Debug)]
+pub enum SceneError {
+    /// Could not read the scene file
+    #[error("Could not read scene {path:?}: {inner:?}")]
+    CouldNotReadFile {
+        /// The path of the scene file
+        path: String,
+        /// The inner error that was thrown
+        inner: std::io::Error,
+    },
+    /// Could not write the scene file
+    #[error("Could not write scene {path:?}: {inner:?}")]
+    CouldNotWriteFile {
+        /// The path of the scene file
+        path: String,
+        /// The inner error that was thrown
+        inner: std::io::Error,
+    },
+    /// Could not turn the scene into RON.
+    ///
+    /// This error can only be thrown if the `serialize` feature is enabled
+    #[cfg(feature = "serialize")]
+    #[error("Could not serialize scene: {0}")]
+    Serialize(ron::Error),
+    /// The scene file is not a valid scene.
+    ///
+    /// This error can only be thrown if the `serialize` feature is enabled
+    #[cfg(feature = "serialize")]
+    #[error("Could not deserialize scene: {0}")]
+    Deserialize(ron::Error),
+    /// The scene contains a model of a format that is not enabled. This is the path of the model.
+    #[error("The format of model {0:?} is not enabled")]
+    FormatNotEnabled(String),
+    /// Could not load a model of the scene
+    #[error("Could not load model: {0:?}")]
+    Model(ModelError),
+}
+
+/// Errors generated when loading the library of a [HotReload](../struct.HotReload.html) game.
+///
+/// This error is only available if the `hot-reload` feature is enabled
+#[cfg(feature = "hot-reload")]
+#[derive(Error, Debug)]
+pub enum HotReloadError {
+    /// Could not copy the library to the temp directory, which is where it is loaded from
+    #[error("Could not copy the library: {0:?}")]
+    CouldNotCopyLibrary(std::io::Error),
+    /// Could not load the library
+    #[error("Could not load the library: {0}")]
+    CouldNotLoadLibrary(libloading::Error),
+    /// The library does not export a game. Make sure the library uses [export_game](../macro.export_game.html).
+    #[error("The library does not export a game, use `crystal_engine::export_game!`: {0}")]
+    MissingGame(libloading::Error),
+}
+
+/// Errors generated when loading an environment map
+#[derive(Error, Debug)]
+pub enum EnvironmentMapError {
+    /// Could not load the image of a face of the cubemap
+    #[error("Could not load environment map face {path:?}: {inner:?}")]
+    CouldNotLoadImage {
+        /// The path of the face
+        path: String,
+        /// The inner error that was thrown
+        inner: image::error::ImageError,
+    },
+    /// A face of the cubemap is not square, or does not have the same size as the other faces
+    #[error("Environment map face {path:?} has a size of {width}x{height}, but the faces have to be square and have the same size")]
+    InvalidFaceSize {
+        /// The path of the face
+        path: String,
+        /// The width of the face
+        width: u32,
+        /// The height of the face
+        height: u32,
+    },
+    /// Could not upload the cubemaps to the GPU
+    #[error("Could not upload environment map: {inner}")]
+    CouldNotUpload {
+        /// The inner error that was thrown
+        inner: UploadError,
+    },
+}
+
+/// Errors generated when loading a color lookup table, see [ColorLut](./struct.ColorLut.html)
+#[derive(Error, Debug)]
+pub enum ColorLutError {
+    /// Could not load the image of the lookup table
+    #[error("Could not load color lookup table {path:?}: {inner:?}")]
+    CouldNotLoadImage {
+        /// The path of the image
+        path: String,
+        /// The inner error that was thrown
+        inner: image::error::ImageError,
+    },
+    /// The image is not `N * N` pixels wide and `N` pixels high
+    #[error("Color lookup table {path:?} has a size of {width}x{height}, but it has to be N*N pixels wide and N pixels high")]
+    InvalidSize {
+        /// The path of the image
+        path: String,
+        /// The width of the image
+        width: u32,
+        /// The height of the image
+        height: u32,
+    },
+    /// Could not upload the lookup table to the GPU
+    #[error("Could not upload color lookup table: {inner}")]
+    CouldNotUpload {
+        /// The inner error that was thrown
+        inner: UploadError,
+    },
+}
+
+/// Errors generated when adding lights to a [FixedVec](./struct.FixedVec.html)
+#[derive(Error, Debug)]
+pub enum LightError {
+    /// The lights are full. The capacity depends on the GPU, see [DeviceLimits::max_directional_lights](./struct.DeviceLimits.html#structfield.max_directional_lights).
+    #[error("Can not add more than {capacity} lights")]
+    CapacityReached {
+        /// The maximum amount of lights
+        capacity: usize,
+    },
+}
+
+/// Errors generated when changing the mesh of a model with [ModelHandle::update_vertices](../struct.ModelHandle.html#method.update_vertices), [ModelHandle::update_indices](../struct.ModelHandle.html#method.update_indices) or [ModelHandle::set_morph_weight](../struct.ModelHandle.html#method.set_morph_weight)
+#[derive(Error, Debug)]
+pub enum MeshUpdateError {
+    /// The vertices of the model are stored per group, see [ModelHandle::update_group_vertices](../struct.ModelHandle.html#method.update_group_vertices)
+    #[error("The model has no vertices that are shared by its groups")]
+    NoSharedVertices,
+    /// The model does not have a group with the given index
+    #[error("The model has {count} groups, so there is no group {group}")]
+    InvalidGroup {
+        /// The index of the group
+        group: usize,
+        /// The amount of groups of the model
+        count: usize,
+    },
+    /// The group uses the vertices that are shared by the model, see [ModelHandle::update_vertices](../struct.ModelHandle.html#method.update_vertices)
+    #[error("Group {group} uses the vertices that are shared by the model")]
+    NoGroupVertices {
+        /// The index of the group
+        group: usize,
+    },
+    /// An index of a group refers to a vertex that does not exist. The change is kept, but the model is drawn with its previous mesh until every index is valid again.
+    #[error("Group {group} has index {index}, but only {vertex_count} vertices")]
+    IndexOutOfRange {
+        /// The index of the group
+        group: usize,
+        /// The invalid index
+        index: u32,
+        /// The amount of vertices that the group can use
+        vertex_count: usize,
+    },
+    /// The model does not have a morph target with the given name, see [ModelHandle::morph_target_names](../struct.ModelHandle.html#method.morph_target_names)
+    #[error("The model has no morph target named {name:?}")]
+    UnknownMorphTarget {
+        /// The name of the morph target
+        name: String,
+    },
+    /// The mesh of the model could not be copied back from the GPU, which is done the first time that the mesh is changed
+    #[error("Could not read back the mesh: {0}")]
+    CouldNotReadBack(UploadError),
+}
+
+/// Errors generated when uploading a texture or a mesh to the GPU. These are mostly caused by running out of GPU memory, or by losing the device.
+#[derive(Error, Debug)]
+pub enum UploadError {
+    /// Could not allocate the buffer that the data is uploaded from or into
+    #[error("Could not allocate buffer: {0:?}")]
+    CouldNotAllocateBuffer(vulkano::memory::DeviceMemoryAllocError),
+
+    /// Could not create the image that the texture is uploaded into
+    #[error("Could not create image: {0:?}")]
+    CouldNotCreateImage(vulkano::image::ImageCreationError),
+
+    /// Could not allocate the command buffer that copies the data
+    #[error("Could not allocate command buffer: {0:?}")]
+    CouldNotAllocateCommandBuffer(vulkano::OomError),
+
+    /// Could not record the copy of a buffer, e.g. because the buffers have different sizes
+    #[error("Could not record the copy: {0:?}")]
+    CouldNotRecordCopy(vulkano::command_buffer::CopyBufferError),
+
+    /// Could not build the command buffer that copies the data
+    #[error("Could not build command buffer: {0:?}")]
+    CouldNotBuildCommandBuffer(vulkano::command_buffer::BuildError),
+
+    /// Could not execute the command buffer that copies the data
+    #[error("Could not copy the data: {0:?}")]
+    CouldNotCopy(vulkano::command_buffer::CommandBufferExecError),
+
+    /// Could not submit the upload to the GPU, which usually means that the device was lost
+    #[error("Could not submit the upload: {0:?}")]
+    CouldNotSubmit(vulkano::sync::FlushError),
+}
+
+/// Errors generated while rendering a frame. The frame that caused the error is skipped, see [Game::on_error](../trait.Game.html#method.on_error).
+#[derive(Error, Debug)]
+pub enum RenderError {
+    /// Could not recreate the swapchain or the images that the scene is rendered into, e.g. after the window was resized or the render scale was changed
+    #[error("Could not recreate the render targets: {0}")]
+    CouldNotRecreateTargets(InitError),
+
+    /// Could not allocate the command buffer of the frame
+    #[error("Could not allocate command buffer: {0:?}")]
+    CouldNotAllocateCommandBuffer(vulkano::OomError),
+
+    /// Could not allocate a buffer that is used by the frame
+    #[error("Could not allocate buffer: {0:?}")]
+    CouldNotAllocateBuffer(vulkano::memory::DeviceMemoryAllocError),
+
+    /// Could not allocate an image that is used by the frame
+    #[error("Could not allocate image: {0:?}")]
+    CouldNotAllocateImage(vulkano::image::ImageCreationError),
+
+    /// Could not create a descriptor set, which usually means that the descriptor pool ran out of memory
+    #[error("Could not create descriptor set: {0:?}")]
+    CouldNotCreateDescriptorSet(
+        vulkano::descriptor::descriptor_set::PersistentDescriptorSetBuildError,
+    ),
+
+    /// Could not record a draw call
+    #[error("Could not draw: {0:?}")]
+    CouldNotDraw(vulkano::command_buffer::DrawError),
+
+    /// Could not record an indexed draw call
+    #[error("Could not draw: {0:?}")]
+    CouldNotDrawIndexed(vulkano::command_buffer::DrawIndexedError),
+
+    /// Could not build the command buffer of the frame
+    #[error("Could not build command buffer: {0:?}")]
+    CouldNotBuildCommandBuffer(vulkano::command_buffer::BuildError),
+
+    /// Could not execute the command buffer of the frame
+    #[error("Could not execute command buffer: {0:?}")]
+    CouldNotExecuteCommandBuffer(vulkano::command_buffer::CommandBufferExecError),
+
+    /// Could not submit the frame to the GPU
+    #[error("Could not submit frame: {0:?}")]
+    CouldNotSubmitFrame(vulkano::sync::FlushError),
+
+    /// The GPU was lost, e.g. because its driver crashed or was updated. A window creates a new device instead of passing this error to `Game::on_error`, see [Game::device_restored](../trait.Game.html#method.device_restored).
+    #[error("The device was lost")]
+    DeviceLost,
+
+    /// The device was lost, and a new device or a new swapchain could not be created
+    #[error("Could not restore the lost device: {0}")]
+    CouldNotRestoreDevice(InitError),
+
+    /// The device was lost, and a model could not be uploaded to the new device, e.g. because its texture was deleted
+    #[error("Could not upload a model to the restored device: {0}")]
+    CouldNotRestoreModel(ModelError),
 }
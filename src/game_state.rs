@@ -1,159 +1,1796 @@
 use crate::{
-    gui::{GuiElementBuilder, GuiElementRef},
+    assets::AssetPaths,
+    camera::{CameraController, CameraEffects},
+    gui::{
+        CustomCursor, DebugWidgetId, DebugWidgets, GlyphCache, GlyphRange, Gradient,
+        GuiElementBuilder, GuiElementRef, GuiFilter, GuiState, GuiTexture, Notifications, SdfFont,
+    },
+    input::InputMap,
     internal::UpdateMessage,
-    model::{loader::ParsedModel, ModelBuilder, ModelRef, SourceOrShape},
-    render::lights::LightState,
-    state::GuiError,
+    model::{
+        batch, loader::ParsedModel, GpuMemoryStats, ModelBuilder, ModelData, ModelHandle, ModelId,
+        ModelRef, ModelView, Progress, SourceOrShape, Transform,
+    },
+    presence::Presence,
+    profiler::{
+        PerfOverlay, ProfileScope, ProfilerOverlay, ProfilerStage, ProfilerState, RenderStats,
+    },
+    render::{
+        clip_plane::ClipPlane,
+        day_night::DayNightCycle,
+        debug_draw::{DebugDraw, DebugHelperId},
+        device::DeviceInfo,
+        environment::EnvironmentMap,
+        lights::{LightState, PointLightAttenuation},
+        limits::DeviceLimits,
+        monitor::{self, MonitorInfo, WindowMode},
+        occlusion::OcclusionCulling,
+        picking::Ray,
+        post_processing::{ColorLut, PostEffect, PostProcessingState},
+        ruler::Ruler,
+        texture,
+        transition::{ActiveTransition, Transition},
+        weather::WeatherState,
+    },
+    scene::Scene,
+    snapshot::DebugSnapshot,
+    state::{
+        ColorLutError, EnvironmentMapError, GuiError, IconError, ModelError, MonitorError,
+        SceneError,
+    },
+    storage::{ScreenshotRequest, Storage},
+    streaming::StreamingState,
+    timer::{TimerCallback, TimerId, Timers},
     Font,
 };
-use cgmath::{Matrix4, SquareMatrix};
+use cgmath::{Matrix4, Rad, SquareMatrix, Vector2, Vector3, Vector4, Zero};
 use std::{
     collections::{HashMap, HashSet, VecDeque},
-    sync::{mpsc::Sender, Arc},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc,
+    },
     time::{Duration, Instant},
 };
 use vulkano::{
     device::{Device, Queue},
+    format::Format,
     swapchain::Surface,
 };
 use winit::event::VirtualKeyCode;
 
+/// The near plane of the camera, in meters
+const CAMERA_NEAR_METERS: f32 = 0.01;
+/// The far plane of the camera, in meters
+pub(crate) const CAMERA_FAR_METERS: f32 = 100.0;
+
 /// Contains the game state. This struct is passed to [Game::init](trait.Game.html#tymethod.init) and [Game::update](trait.Game.html#tymethod.update).
 pub struct GameState {
     pub(crate) device: Arc<Device>,
     pub(crate) queue: Arc<Queue>,
+    /// The queue that models, textures and fonts are uploaded on. This is a dedicated transfer queue when the GPU has one, so uploads do not hold up the rendering.
+    pub(crate) transfer_queue: Arc<Queue>,
     pub(crate) model_handles: HashMap<u64, ModelRef>,
     pub(crate) internal_update_sender: Sender<UpdateMessage>,
+    internal_update_receiver: Receiver<UpdateMessage>,
     pub(crate) gui_elements: HashMap<u64, GuiElementRef>,
     pub(crate) is_running: bool,
+    pub(crate) mouse_mode: MouseMode,
+    has_focus: bool,
+    cursor_inside: bool,
+    /// The last position of the cursor in physical pixels of the window
+    cursor_position: Option<(f64, f64)>,
+    custom_cursor: Option<CustomCursor>,
+    pub(crate) target_fps: Option<u32>,
+    render_scale: f32,
+    pub(crate) power_saving: bool,
 
     /// The matrix of the camera currently in use.
     ///
-    /// It is currently not possible to change the near and far boundaries of the camera. This might be added in a later version.
+    /// The near and far boundaries of the camera are 1 centimeter and 100 meters, converted to world units with [units](#structfield.units).
     pub camera: Matrix4<f32>,
 
+    /// The vertical field of view of the camera. Defaults to 90 degrees.
+    pub camera_fov: Rad<f32>,
+
+    /// Effects that are applied on top of the [camera](#structfield.camera), like screen shakes.
+    pub camera_effects: CameraEffects,
+
+    /// A controller that moves the [camera](#structfield.camera) with the mouse and keyboard, like an orbit or a first-person camera. This is `None` by default. See [CameraController] for more information.
+    ///
+    /// [CameraController]: ./state/enum.CameraController.html
+    pub camera_controller: Option<CameraController>,
+
     /// Get the current keyboard state.
     pub keyboard: KeyboardState,
 
+    /// The bindings of named actions to inputs. Use this instead of [keyboard](#structfield.keyboard) to support rebindable controls.
+    pub input: InputMap,
+
     /// The state of the lights currently in the world.
     pub light: LightState,
 
+    /// Planes that cut every model in the world. See [ClipPlane] for more information.
+    ///
+    /// [ClipPlane]: ./state/struct.ClipPlane.html
+    pub clip_planes: Vec<ClipPlane>,
+
+    /// The settings of the final pass of the engine, like the exposure and the tonemapping operator.
+    pub post_processing: PostProcessingState,
+
+    /// The weather of the world, like rain and snow.
+    pub weather: WeatherState,
+
+    /// The day-night cycle, which animates the sun, the sky and the fog over the day. This is `None` by default.
+    pub day_night: Option<DayNightCycle>,
+
     /// The state of the time in the game. This is where you can get the `delta` time since the
     /// last frame.
     pub time: TimeState,
 
-    surface: Arc<Surface<winit::window::Window>>,
+    /// The scale of the world, in meters per world unit. This is used by the engine to pick
+    /// sensible defaults, e.g. for the near and far planes of the camera and the attenuation of
+    /// point lights.
+    pub units: WorldUnits,
+
+    /// The way models are moved between two [fixed updates](trait.Game.html#method.fixed_update). This defaults to [TransformSmoothing::Interpolate](./state/enum.TransformSmoothing.html#variant.Interpolate).
+    pub transform_smoothing: TransformSmoothing,
+
+    /// Skip models that are hidden behind other models, see [OcclusionCulling](./state/enum.OcclusionCulling.html). This defaults to [OcclusionCulling::Disabled](./state/enum.OcclusionCulling.html#variant.Disabled).
+    pub occlusion_culling: OcclusionCulling,
+
+    /// The settings of the GUI. This can be used to set a virtual resolution for all GUI elements.
+    pub gui: GuiState,
+
+    /// Debug shapes that are drawn on top of the world, like the bounding volumes of models.
+    pub debug_draw: DebugDraw,
+
+    /// The directories that assets are loaded from. Mods can add their own directories to replace the textures, models and fonts of the game.
+    pub assets: AssetPaths,
+
+    /// Save slots on disk, with optional thumbnails of the game at the moment it was saved.
+    pub storage: Storage,
+
+    /// The rich presence of the game, like the status that Steam or Discord shows to friends of the player.
+    pub presence: Presence,
+
+    /// The timings of the last frames. This can be used to find out where the time of a frame is spent.
+    pub profiler: ProfilerState,
+
+    /// Loads the models of an open world in chunks around a focus point, see [StreamingState] for more information.
+    ///
+    /// [StreamingState]: ./state/struct.StreamingState.html
+    pub streaming: StreamingState,
+
+    /// The entities of the game. Every frame after [Game::update](trait.Game.html#method.update), the [Transform](ecs/struct.Transform.html) of every entity is copied to its [ModelComponent](ecs/struct.ModelComponent.html), and the [LightComponent](ecs/enum.LightComponent.html)s are copied to the [lights](#structfield.light).
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # use crystal_engine::ecs::{ModelComponent, Transform};
+    /// # use cgmath::Vector3;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// let model = state.new_triangle_model().build().unwrap();
+    /// let entity = state.world.spawn((
+    ///     Transform::at(Vector3::new(0.0, 1.0, 0.0)),
+    ///     ModelComponent::new(model),
+    /// ));
+    /// ```
+    ///
+    /// This is only available if the `ecs` feature is enabled.
+    #[cfg(feature = "ecs")]
+    pub world: hecs::World,
+
+    pub(crate) profiler_overlay: Option<ProfilerOverlay>,
+    pub(crate) perf_overlay: Option<PerfOverlay>,
+    pub(crate) ruler: Option<Ruler>,
+    pub(crate) notifications: Notifications,
+    pub(crate) glyph_cache: GlyphCache,
+    pub(crate) debug_widgets: DebugWidgets,
+    pub(crate) transition: Option<ActiveTransition>,
+    pub(crate) timers: Timers,
+    title: WindowTitle,
+    icon_path: Option<std::path::PathBuf>,
+    output: RenderOutput,
+    /// The library that [HotReload](../struct.HotReload.html) loads, which is set by the window builder
+    #[cfg(feature = "hot-reload")]
+    pub(crate) hot_reload_library: Option<std::path::PathBuf>,
+}
+
+/// The window that the game is rendered to, or the size of the offscreen image of a headless game.
+pub(crate) enum RenderOutput {
+    Window(Arc<Surface<winit::window::Window>>),
+    Headless { width: u32, height: u32 },
+}
+
+impl RenderOutput {
+    fn window(&self) -> Option<&winit::window::Window> {
+        match self {
+            RenderOutput::Window(surface) => Some(surface.window()),
+            RenderOutput::Headless { .. } => None,
+        }
+    }
 }
 
 impl GameState {
     pub(crate) fn new(
         device: Arc<Device>,
         queue: Arc<Queue>,
-        sender: Sender<UpdateMessage>,
-        surface: Arc<Surface<winit::window::Window>>,
+        transfer_queue: Arc<Queue>,
+        output: RenderOutput,
     ) -> Self {
+        let (sender, receiver) = channel();
+        let gui = match &output {
+            RenderOutput::Window(surface) => {
+                let window = surface.window();
+                let size = window.inner_size();
+                GuiState::new((size.width, size.height), window.scale_factor())
+            }
+            RenderOutput::Headless { width, height } => GuiState::new((*width, *height), 1.0),
+        };
+        let light = LightState::new(DeviceLimits::from_device(&device).max_directional_lights);
         Self {
             device,
             queue,
+            transfer_queue,
             model_handles: HashMap::new(),
             internal_update_sender: sender,
+            internal_update_receiver: receiver,
             gui_elements: HashMap::new(),
             is_running: true,
+            mouse_mode: MouseMode::Absolute,
+            has_focus: true,
+            cursor_inside: false,
+            cursor_position: None,
+            custom_cursor: None,
+            target_fps: None,
+            render_scale: 1.0,
+            power_saving: false,
             camera: Matrix4::identity(),
-            keyboard: KeyboardState {
-                pressed: HashSet::default(),
-            },
-            light: LightState::new(),
+            camera_fov: Rad(std::f32::consts::FRAC_PI_2),
+            camera_effects: CameraEffects::default(),
+            camera_controller: None,
+            keyboard: KeyboardState::default(),
+            input: InputMap::default(),
+            light,
+            clip_planes: Vec::new(),
+            post_processing: PostProcessingState::default(),
+            weather: WeatherState::default(),
+            day_night: None,
             time: TimeState::default(),
-            surface,
+            units: WorldUnits::default(),
+            transform_smoothing: TransformSmoothing::Interpolate,
+            occlusion_culling: OcclusionCulling::default(),
+            gui,
+            debug_draw: DebugDraw::default(),
+            assets: AssetPaths::default(),
+            storage: Storage::default(),
+            presence: Presence::default(),
+            profiler: ProfilerState::default(),
+            streaming: StreamingState::default(),
+            profiler_overlay: None,
+            perf_overlay: None,
+            ruler: None,
+            notifications: Notifications::default(),
+            glyph_cache: GlyphCache::default(),
+            debug_widgets: DebugWidgets::default(),
+            transition: None,
+            timers: Timers::default(),
+            #[cfg(feature = "ecs")]
+            world: hecs::World::new(),
+            title: WindowTitle::default(),
+            icon_path: None,
+            output,
+            #[cfg(feature = "hot-reload")]
+            hot_reload_library: None,
+        }
+    }
+
+    pub(crate) fn update(&mut self) {
+        self.time.update();
+        self.camera_effects.update(self.time.delta());
+        if let Some(controller) = &mut self.camera_controller {
+            // The camera is controlled by the player, so it keeps moving in slow-motion
+            self.camera = controller.update(&self.keyboard, self.time.real_delta());
+        }
+        // A camera matrix that can not be inverted is invalid, so the weather falls around the
+        // origin of the world instead
+        let camera_position = self
+            .view_matrix()
+            .invert()
+            .map(|m| m.w.truncate())
+            .unwrap_or_else(Vector3::zero);
+        self.weather
+            .update(self.time.delta(), camera_position, &self.units);
+        if let Some(day_night) = &mut self.day_night {
+            day_night.update(self.time.delta(), &mut self.light);
+        }
+        self.gui.window_size = self.window_size();
+        if let Some(window) = self.output.window() {
+            self.gui.scale_factor = window.scale_factor();
+            self.title.refresh(window, &self.time);
+        }
+
+        if let Some(mut transition) = self.transition.take() {
+            let callback = transition.update(self.time.real_delta());
+            if !transition.is_finished() {
+                self.transition = Some(transition);
+            }
+            if let Some(callback) = callback {
+                callback(self);
+            }
+        }
+    }
+
+    /// Call the timers that are due this frame. This is called after [Game::update](trait.Game.html#method.update), so the callbacks see the changes of the game in this frame.
+    pub(crate) fn run_timers(&mut self) {
+        let delta = self.time.delta();
+        Timers::run(self, |state| &mut state.timers, delta);
+    }
+
+    /// Start a transition, e.g. to switch to a different level. The callback is called when the screen is fully covered, so the player won't see the scene change.
+    ///
+    /// If a transition is already running, it is replaced by this transition.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # use crystal_engine::state::Transition;
+    /// # use std::time::Duration;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// state.transition(Transition::FadeToBlack(Duration::from_millis(500)), |state| {
+    ///     // load the next level
+    /// });
+    /// ```
+    pub fn transition(
+        &mut self,
+        transition: Transition,
+        callback: impl FnOnce(&mut GameState) + 'static,
+    ) {
+        self.transition = Some(ActiveTransition::new(transition, Box::new(callback)));
+    }
+
+    /// Call the callback once after the given delay. The delay is in the time of the game, so it is affected by the [time scale](state/struct.TimeState.html#method.set_time_scale) and does not pass while the game is [paused](state/struct.TimeState.html#method.pause).
+    ///
+    /// The callback is called after [Game::update](trait.Game.html#method.update). The returned [TimerId] can be used to cancel the timer with [cancel_timer](#method.cancel_timer).
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # use std::time::Duration;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// state.after(Duration::from_secs(3), |state| {
+    ///     // respawn the player
+    /// });
+    /// ```
+    ///
+    /// [TimerId]: state/struct.TimerId.html
+    pub fn after(
+        &mut self,
+        delay: Duration,
+        callback: impl FnOnce(&mut GameState) + 'static,
+    ) -> TimerId {
+        self.timers
+            .add(delay, None, TimerCallback::Once(Box::new(callback)))
+    }
+
+    /// Call the callback every time the given interval passes, until the timer is cancelled with [cancel_timer](#method.cancel_timer). Like [after](#method.after), the interval is in the time of the game.
+    ///
+    /// When a frame takes longer than the interval, the callback is called multiple times in that frame. The callback can cancel its own timer.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # use std::time::Duration;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// let mut waves = 0;
+    /// state.every(Duration::from_secs(30), move |state| {
+    ///     waves += 1;
+    ///     // spawn the next wave of enemies
+    /// });
+    /// ```
+    pub fn every(
+        &mut self,
+        interval: Duration,
+        callback: impl FnMut(&mut GameState) + 'static,
+    ) -> TimerId {
+        self.timers.add(
+            interval,
+            Some(interval),
+            TimerCallback::Repeat(Box::new(callback)),
+        )
+    }
+
+    /// Stop a timer that was started with [after](#method.after) or [every](#method.every). Returns `false` if the timer was already finished or cancelled.
+    pub fn cancel_timer(&mut self, id: TimerId) -> bool {
+        self.timers.cancel(id)
+    }
+
+    /// Returns `true` if a [transition](#method.transition) is currently running.
+    pub fn is_transitioning(&self) -> bool {
+        self.transition.is_some()
+    }
+
+    /// Take a snapshot of the world, with the data of every model, the lights, the camera and the running time. This can be restored later with [restore](#method.restore) to rewind to just before a bug happens.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # use crystal_engine::event::VirtualKeyCode;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// let snapshot = state.debug_snapshot();
+    /// // ... later, e.g. when F9 is pressed
+    /// if state.keyboard.was_just_pressed(VirtualKeyCode::F9) {
+    ///     state.restore(&snapshot);
+    /// }
+    /// ```
+    pub fn debug_snapshot(&self) -> DebugSnapshot {
+        DebugSnapshot::new(self)
+    }
+
+    /// Restore a snapshot that was taken with [debug_snapshot](#method.debug_snapshot).
+    ///
+    /// Models that were dropped after the snapshot was taken can not be restored, and models that were created after the snapshot was taken are left unchanged.
+    pub fn restore(&mut self, snapshot: &DebugSnapshot) {
+        snapshot.restore(self);
+    }
+
+    /// Get the current models, lights and camera as a [Scene]. Models that are created from a [ParsedModel] are not part of the scene.
+    ///
+    /// [Scene]: ./state/struct.Scene.html
+    /// [ParsedModel]: ./models/struct.ParsedModel.html
+    pub fn scene(&self) -> Scene {
+        Scene::new(self)
+    }
+
+    /// Load the models of a [Scene] into the world, and replace the lights and the camera with those of the scene. Models that are already in the world are kept, use [unload_models](#method.unload_models) to remove them first.
+    ///
+    /// The handles of the new models are returned. Just like any other [ModelHandle], the models are removed from the world when their handles are dropped.
+    ///
+    /// [Scene]: ./state/struct.Scene.html
+    /// [ModelHandle]: ./struct.ModelHandle.html
+    pub fn spawn_scene(&mut self, scene: &Scene) -> Result<Vec<ModelHandle>, SceneError> {
+        scene.spawn(self)
+    }
+
+    /// Save the current [scene](#method.scene) to a [RON](https://github.com/ron-rs/ron) file.
+    ///
+    /// This method is only available when the `serialize` feature is enabled.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// state.save_scene("saves/level1.ron").unwrap();
+    /// // ... later
+    /// state.unload_models();
+    /// let models: Vec<ModelHandle> = state.load_scene("saves/level1.ron").unwrap();
+    /// ```
+    #[cfg(feature = "serialize")]
+    pub fn save_scene(&self, path: impl AsRef<std::path::Path>) -> Result<(), SceneError> {
+        crate::scene::save(&self.scene(), path.as_ref())
+    }
+
+    /// Load a scene from a [RON](https://github.com/ron-rs/ron) file that was saved with [save_scene](#method.save_scene). See [spawn_scene](#method.spawn_scene) for what happens with the current world.
+    ///
+    /// This method is only available when the `serialize` feature is enabled.
+    #[cfg(feature = "serialize")]
+    pub fn load_scene(
+        &mut self,
+        path: impl AsRef<std::path::Path>,
+    ) -> Result<Vec<ModelHandle>, SceneError> {
+        let scene = crate::scene::load(&self.assets.resolve(path))?;
+        self.spawn_scene(&scene)
+    }
+
+    /// Get the limits of the GPU that the game is running on. This can be used to pick the quality of the graphics, or to check how many lights can be added.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// let limits = state.device_limits();
+    /// println!("Textures can be up to {0}x{0} pixels", limits.max_image_dimension_2d);
+    /// ```
+    pub fn device_limits(&self) -> DeviceLimits {
+        DeviceLimits::from_device(&self.device)
+    }
+
+    /// Get the name and type of the GPU that the game is running on. The GPU can be picked with [WindowBuilder::with_device_preference](struct.WindowBuilder.html#method.with_device_preference).
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// let info = state.device_info();
+    /// println!("Running on {} ({:?})", info.name, info.device_type);
+    /// ```
+    pub fn device_info(&self) -> DeviceInfo {
+        DeviceInfo::from_physical(&self.device.physical_device())
+    }
+
+    /// Get the GPU memory that is used by the models in the world. This can be used to check that the models of a level are released after a level transition.
+    pub fn gpu_memory_stats(&self) -> GpuMemoryStats {
+        let meshes: Vec<_> = self.model_handles.values().map(ModelRef::mesh).collect();
+        GpuMemoryStats::from_models(meshes.iter())
+    }
+
+    /// Get the amount of draw calls, triangles, models and GUI elements that were rendered in the last frame. This can be used to track rendering regressions, e.g. in a benchmark.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// let stats = state.render_stats();
+    /// println!("{} draw calls, {} triangles", stats.draw_calls, stats.triangles);
+    /// ```
+    pub fn render_stats(&self) -> RenderStats {
+        self.profiler.render_stats
+    }
+
+    /// Remove every model from the world and release their buffers and textures, even when there are still [ModelHandle]s to them. This is useful when switching levels, where forgetting a single clone of a handle would otherwise keep its buffers alive.
+    ///
+    /// The memory is released as soon as the GPU has finished rendering the current frame. [ModelHandle]s to unloaded models can still be used, but they are no longer rendered, and neither are their clones.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// state.unload_models();
+    /// assert_eq!(0, state.gpu_memory_stats().total_bytes());
+    /// ```
+    ///
+    /// [ModelHandle]: ./struct.ModelHandle.html
+    pub fn unload_models(&mut self) {
+        self.model_handles.clear();
+    }
+
+    /// Apply the changes of the model and GUI element handles that were cloned or dropped since the last call.
+    pub(crate) fn apply_internal_updates(&mut self) {
+        while let Ok(message) = self.internal_update_receiver.try_recv() {
+            message.apply(self);
+        }
+    }
+
+    /// Replace the device after the old device was lost, and upload the models in the world again from the parsed models that they were loaded from. Double buffered canvases are drawn again with the pixels that were last written to them.
+    ///
+    /// Everything else that only exists on the old device is removed: models that were merged with [merge_static_models](#method.merge_static_models), the other GUI elements, the custom cursor, the environment map and color grading. The game can create these again in [Game::device_restored](trait.Game.html#method.device_restored).
+    pub(crate) fn restore_device(
+        &mut self,
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        transfer_queue: Arc<Queue>,
+    ) -> Result<(), ModelError> {
+        self.device = device;
+        self.queue = queue;
+        self.transfer_queue = transfer_queue;
+        self.apply_internal_updates();
+
+        let mut lost = Vec::new();
+        // The clones of a model share their mesh, so every mesh is only uploaded once
+        let mut restored = HashSet::new();
+        for (id, model_ref) in &self.model_handles {
+            let old = model_ref.mesh();
+            // Uploads that were never waited on wait for the lost device when they are dropped, so
+            // they are leaked instead
+            let pending = std::mem::replace(&mut *old.texture_future.write(), Vec::new());
+            std::mem::forget(pending);
+            let source = match &old.source {
+                Some(source) => source.clone(),
+                None => {
+                    lost.push(*id);
+                    continue;
+                }
+            };
+            if !restored.insert(Arc::as_ptr(&model_ref.model)) {
+                continue;
+            }
+            let model = source.upload(
+                &self.device,
+                &self.transfer_queue,
+                &self.assets,
+                &mut Progress::new(None),
+            )?;
+            // A mesh that was changed at runtime keeps its changes
+            if let Some(mut mesh) = old.dynamic.write().take() {
+                mesh.restore(&self.device);
+                *model.dynamic.write() = Some(mesh);
+            }
+            *model_ref.model.write() = Arc::new(model);
+        }
+        for id in lost {
+            self.model_handles.remove(&id);
+        }
+
+        // Double buffered canvases keep their pixels, the other GUI textures can not be restored
+        let queue = &self.queue;
+        self.gui_elements
+            .retain(|_, element| match &element.texture {
+                GuiTexture::DoubleBuffered(texture) => texture.write().restore(queue).is_ok(),
+                _ => {
+                    std::mem::forget(element.texture_future.take());
+                    false
+                }
+            });
+        self.custom_cursor = None;
+        self.apply_cursor_grab();
+        if let Some(environment) = self.light.environment.take() {
+            std::mem::forget(environment.take_upload_futures());
+        }
+        self.post_processing.effects.retain(|effect| match effect {
+            PostEffect::ColorGrading(grading) => {
+                std::mem::forget(grading.lut.take_upload_futures());
+                false
+            }
+            _ => true,
+        });
+        Ok(())
+    }
+
+    /// Iterate over all the models in the world, in no particular order. This can be used by debugging tools and spatial queries to inspect the world.
+    ///
+    /// Models that were created by cloning a [ModelHandle] are found from the next frame on.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// for model in state.models() {
+    ///     if model.read(|data| data.position.y < -100.0) {
+    ///         println!("{:?} fell out of the world", model.id());
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// [ModelHandle]: ./struct.ModelHandle.html
+    pub fn models(&self) -> impl Iterator<Item = ModelView<'_>> {
+        self.model_handles
+            .iter()
+            .map(|(id, model)| ModelView::new(*id, model))
+    }
+
+    /// Look up a model by its id. Returns `None` if the model is no longer in the world. See [ModelHandle::id].
+    ///
+    /// [ModelHandle::id]: ./struct.ModelHandle.html#method.id
+    pub fn model(&self, id: ModelId) -> Option<ModelView<'_>> {
+        self.model_handles
+            .get(&id.0)
+            .map(|model| ModelView::new(id.0, model))
+    }
+
+    /// Iterate over the models in the world that have the given tag, in no particular order. See [ModelData::tags] for more information.
+    ///
+    /// Models that were created by cloning a [ModelHandle] are found from the next frame on.
+    ///
+    /// [ModelData::tags]: ./models/struct.ModelData.html#structfield.tags
+    /// [ModelHandle]: ./struct.ModelHandle.html
+    pub fn models_with_tag<'a>(&'a self, tag: &'a str) -> impl Iterator<Item = ModelView<'a>> {
+        self.model_handles
+            .iter()
+            .filter(move |(_, model)| model.data.read().has_tag(tag))
+            .map(|(id, model)| ModelView::new(*id, model))
+    }
+
+    /// Find every pair of models in the world whose [colliders](./models/struct.ModelData.html#structfield.collider) touch each other. Every pair is returned once, with the lowest id first. Models without a collider are skipped.
+    ///
+    /// This tests every collider against every other collider, which is fast enough for the few dozen colliders of an arcade game. Models that were created by cloning a [ModelHandle] are found from the next frame on.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// # let player: ModelHandle = state.new_cube_model().build().unwrap();
+    /// for (a, b) in state.collisions() {
+    ///     if a == player.id() || b == player.id() {
+    ///         // the player touches something
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// [ModelHandle]: ./struct.ModelHandle.html
+    pub fn collisions(&self) -> Vec<(ModelId, ModelId)> {
+        let mut colliders = self
+            .model_handles
+            .iter()
+            .filter_map(|(id, model)| Some((ModelId(*id), model.data.read().world_collider()?)))
+            .collect::<Vec<_>>();
+        colliders.sort_by_key(|(id, _)| *id);
+        let mut collisions = Vec::new();
+        for (index, (a, a_collider)) in colliders.iter().enumerate() {
+            for (b, b_collider) in &colliders[index + 1..] {
+                if a_collider.intersects(b_collider) {
+                    collisions.push((*a, *b));
+                }
+            }
+        }
+        collisions
+    }
+
+    /// Set the position, rotation and scale of many models at once, e.g. every agent of a crowd simulation. This is faster than calling [ModelHandle::modify] for every model, as every model is looked up and locked only once, and the handles do not have to be kept around.
+    ///
+    /// Ids of models that are no longer in the world are ignored.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # use crystal_engine::models::Transform;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// # let agents: Vec<(ModelId, cgmath::Vector3<f32>)> = Vec::new();
+    /// state.update_models(agents.iter().map(|(id, position)| {
+    ///     let transform = Transform {
+    ///         position: *position,
+    ///         ..Transform::default()
+    ///     };
+    ///     (*id, transform)
+    /// }));
+    /// ```
+    ///
+    /// [ModelHandle::modify]: ./struct.ModelHandle.html#method.modify
+    pub fn update_models(&self, updates: impl IntoIterator<Item = (ModelId, Transform)>) {
+        for (id, transform) in updates {
+            if let Some(model) = self.model_handles.get(&id.0) {
+                model.data.write().set_transform(transform);
+            }
+        }
+    }
+
+    /// Merge models that never move, e.g. props and rocks, into a single static model. Every model is normally drawn separately, with its own uniforms. The static model draws all parts that share a texture and material at once, which is a lot faster for many small models.
+    ///
+    /// The position, rotation, scale and [texture region](./models/struct.ModelData.html#structfield.texture_region) of every model is baked into the static model, so moving the given handles afterwards has no effect. The other [ModelData](./models/struct.ModelData.html) of the models is not copied, and the levels of detail of the models are not used. Models only share a texture when they were created from the same texture, e.g. by cloning a [ModelHandle].
+    ///
+    /// The given models are removed from the world, just like with [unload_models](#method.unload_models). The static model is removed from the world when the returned handle is dropped. It is not part of a [scene](#method.scene).
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// let rock: ModelHandle = state
+    ///     .new_obj_model("assets/rock.obj")
+    ///     .with_texture_from_file("assets/rock.png")
+    ///     .build()
+    ///     .unwrap();
+    /// let rocks: Vec<ModelHandle> = (0..100)
+    ///     .map(|i| {
+    ///         let rock = rock.clone();
+    ///         rock.modify(|data| data.position.x = i as f32 * 2.0);
+    ///         rock
+    ///     })
+    ///     .collect();
+    /// let static_rocks: ModelHandle = state.merge_static_models(&rocks).unwrap();
+    /// ```
+    ///
+    /// Returns [ModelError::InvalidModelVertexBuffer] when none of the models have any triangles.
+    ///
+    /// [ModelHandle]: ./struct.ModelHandle.html
+    /// [ModelError::InvalidModelVertexBuffer]: ./state/enum.ModelError.html#variant.InvalidModelVertexBuffer
+    pub fn merge_static_models(
+        &mut self,
+        handles: &[ModelHandle],
+    ) -> Result<ModelHandle, ModelError> {
+        // Handles that were cloned this frame have to be added to the world first
+        self.apply_internal_updates();
+        let models = handles
+            .iter()
+            .filter_map(|handle| self.model_handles.get(&handle.id().0));
+        let (model, statistics) = batch::merge_models(&self.transfer_queue, models)?;
+        for handle in handles {
+            self.model_handles.remove(&handle.id().0);
+        }
+
+        let (id, model_ref, model_handle) = ModelRef::new(
+            Arc::new(model),
+            statistics,
+            None,
+            self.internal_update_sender.clone(),
+            ModelData::default(),
+        );
+        self.model_handles.insert(id, model_ref);
+        Ok(model_handle)
+    }
+
+    /// The view matrix that is used to render the world. This is the [camera](#structfield.camera) with the [camera_effects](#structfield.camera_effects) applied, e.g. with the shake of the camera.
+    pub fn view_matrix(&self) -> Matrix4<f32> {
+        self.camera_effects.matrix() * self.camera
+    }
+
+    /// The projection matrix that is used to render the world, based on the [camera_fov](#structfield.camera_fov), the [units](#structfield.units) of the world and the size of the window.
+    ///
+    /// Together with the [view_matrix](#method.view_matrix), this can be used to draw custom geometry that lines up with the world, or to do custom math with the camera. See [world_to_screen](#method.world_to_screen) and [screen_to_world_ray](#method.screen_to_world_ray) for the most common conversions.
+    pub fn projection_matrix(&self) -> Matrix4<f32> {
+        let (width, height) = self.window_size();
+        // A minimized window has no size, so any aspect ratio is as good as the other
+        let aspect_ratio = if width == 0 || height == 0 {
+            1.0
+        } else {
+            width as f32 / height as f32
+        };
+        self.perspective_matrix(aspect_ratio)
+    }
+
+    /// The projection matrix of the camera for a frame with the given aspect ratio.
+    pub(crate) fn perspective_matrix(&self, aspect_ratio: f32) -> Matrix4<f32> {
+        cgmath::perspective(
+            self.camera_fov,
+            aspect_ratio,
+            self.units.meters_to_units(CAMERA_NEAR_METERS),
+            self.units.meters_to_units(CAMERA_FAR_METERS),
+        )
+    }
+
+    /// Find the point in the world under a position in the window. The position is in physical pixels with the origin in the top-left, like the `CursorMoved` event.
+    ///
+    /// Models are hit by their [bounding box](./struct.ModelHandle.html#method.world_bounding_box), and the ground is the plane at `y = 0`. Models that contain the camera are ignored. Returns `None` if nothing is under the position, e.g. when looking at the sky.
+    pub fn pick(&self, position: (f64, f64)) -> Option<Vector3<f32>> {
+        let ray = self.screen_ray(position)?;
+        self.model_handles
+            .values()
+            .filter_map(|model| ray.intersect_box(model.world_bounding_box()))
+            .filter(|&distance| distance > 0.0)
+            .chain(ray.intersect_ground(0.0))
+            .fold(None, |closest: Option<f32>, distance| {
+                Some(closest.map_or(distance, |closest| closest.min(distance)))
+            })
+            .map(|distance| ray.at(distance))
+    }
+
+    /// Get the ray that goes from the camera through a position in the window, as `(origin, direction)`. The position is in physical pixels with the origin in the top-left, like the `CursorMoved` event. The origin is on the near plane of the camera, and the direction is normalized.
+    ///
+    /// This can be used for click-to-move, by intersecting the ray with the ground or with a [collider](./state/enum.Collider.html). Returns `None` if the window has no size, e.g. when it is minimized.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// # let cursor_position = (400.0, 300.0);
+    /// // Find the point on the ground at y = 0 under the cursor
+    /// if let Some((origin, direction)) = state.screen_to_world_ray(cursor_position) {
+    ///     if direction.y < 0.0 {
+    ///         let target = origin + direction * (-origin.y / direction.y);
+    ///     }
+    /// }
+    /// ```
+    pub fn screen_to_world_ray(
+        &self,
+        position: (f64, f64),
+    ) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        self.screen_ray(position)
+            .map(|ray| (ray.origin, ray.direction))
+    }
+
+    fn screen_ray(&self, position: (f64, f64)) -> Option<Ray> {
+        let (width, height) = self.window_size();
+        if width == 0 || height == 0 {
+            return None;
+        }
+        let (width, height) = (width as f32, height as f32);
+        let screen_position = Vector2::new(
+            position.0 as f32 / width * 2.0 - 1.0,
+            1.0 - position.1 as f32 / height * 2.0,
+        );
+        Ray::from_screen(
+            self.perspective_matrix(width / height) * self.view_matrix(),
+            screen_position,
+        )
+    }
+
+    /// Convert a point in the world to a position in the window, in physical pixels with the origin in the top-left. Returns `None` if the point is behind the camera, or if the window has no size.
+    ///
+    /// The position can be outside of the window, when the point is in front of the camera but outside of its view. See [world_to_gui](#method.world_to_gui) to place GUI elements over a point in the world.
+    pub fn world_to_screen(&self, point: Vector3<f32>) -> Option<(f32, f32)> {
+        let (width, height) = self.window_size();
+        if width == 0 || height == 0 {
+            return None;
+        }
+        let (width, height) = (width as f32, height as f32);
+        let view_projection = self.perspective_matrix(width / height) * self.view_matrix();
+        project(view_projection, point, (width, height))
+    }
+
+    /// Convert a point in the world to a position in GUI coordinates, e.g. to show a health bar or a name above a character. Returns `None` if the point is behind the camera.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # use cgmath::Vector3;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// # let enemy: ModelHandle = state.new_cube_model().build().unwrap();
+    /// # let marker: GuiElement = state.new_gui_element((0, 0, 16, 16)).canvas().build().unwrap();
+    /// let above_enemy = enemy.position() + Vector3::new(0.0, 2.0, 0.0);
+    /// if let Some((x, y)) = state.world_to_gui(above_enemy) {
+    ///     marker.modify(|data| data.dimensions = (x as i32 - 8, y as i32, 16, 16));
+    /// }
+    /// ```
+    pub fn world_to_gui(&self, point: Vector3<f32>) -> Option<(f32, f32)> {
+        let (x, y) = self.world_to_screen(point)?;
+        Some(self.gui.window_to_gui((x as f64, y as f64)))
+    }
+
+    /// The color of the fog and the density of the fog per world unit. The fog of the weather is tinted by the day-night cycle.
+    pub(crate) fn fog(&self) -> ([f32; 3], f32) {
+        let (color, density) = self.weather.fog(&self.units);
+        match &self.day_night {
+            Some(day_night) => {
+                let tint = day_night.fog_tint();
+                (
+                    [color[0] * tint.x, color[1] * tint.y, color[2] * tint.z],
+                    density,
+                )
+            }
+            None => (color, density),
+        }
+    }
+
+    /// The color of the background. This is the sky of the day-night cycle, or `default` if there is no cycle, faded into the fog.
+    pub(crate) fn background_color(&self, default: [f32; 4]) -> [f32; 4] {
+        let sky = self
+            .day_night
+            .as_ref()
+            .map(DayNightCycle::sky)
+            .unwrap_or(default);
+        let (fog_color, _) = self.fog();
+        let fog = self.weather.background_fog();
+        [
+            sky[0] + (fog_color[0] - sky[0]) * fog,
+            sky[1] + (fog_color[1] - sky[1]) * fog,
+            sky[2] + (fog_color[2] - sky[2]) * fog,
+            sky[3],
+        ]
+    }
+
+    /// Load a font from the given relative path. This function will panic if the font does not exist.
+    ///
+    /// The font is not stored internally, and must be stored by the developer.
+    pub fn load_font(&mut self, font: impl AsRef<std::path::Path>) -> Result<Font, GuiError> {
+        use std::{fs::File, io::Read};
+        let font = font.as_ref();
+        let font_str = font.to_str().unwrap_or("unknown");
+
+        let mut file =
+            File::open(self.assets.resolve(font)).map_err(|e| GuiError::CouldNotReadFontFile {
+                file: font_str.to_string(),
+                inner: e,
+            })?;
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)
+            .map_err(|e| GuiError::CouldNotReadFontFile {
+                file: font_str.to_string(),
+                inner: e,
+            })?;
+
+        match rusttype::Font::try_from_vec(content) {
+            Some(font) => Ok(Arc::new(font)),
+            None => Err(GuiError::CouldNotLoadFont),
+        }
+    }
+
+    /// Load an environment cubemap for image based lighting. The faces are in the order +X, -X, +Y, -Y, +Z, -Z, and have to be square images of the same size.
+    ///
+    /// The lighting and the reflections of the environment are calculated when it is loaded, which can take a moment for large cubemaps. The environment is used once it is set as [LightState::environment].
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// let sky = state
+    ///     .load_environment_map([
+    ///         "sky/right.png",
+    ///         "sky/left.png",
+    ///         "sky/top.png",
+    ///         "sky/bottom.png",
+    ///         "sky/front.png",
+    ///         "sky/back.png",
+    ///     ])
+    ///     .unwrap();
+    /// state.light.environment = Some(sky);
+    /// ```
+    ///
+    /// [LightState::environment]: ./state/struct.LightState.html#structfield.environment
+    pub fn load_environment_map<P: AsRef<std::path::Path>>(
+        &mut self,
+        faces: [P; 6],
+    ) -> Result<EnvironmentMap, EnvironmentMapError> {
+        let start = Instant::now();
+        let paths: [std::path::PathBuf; 6] =
+            array_init::array_init(|i| self.assets.resolve(&faces[i]));
+        let environment = EnvironmentMap::load(
+            self.transfer_queue.clone(),
+            array_init::array_init(|i| paths[i].as_path()),
+        )?;
+        self.profiler
+            .record_nested(ProfilerStage::AssetUpload, start.elapsed());
+        Ok(environment)
+    }
+
+    /// Load a color lookup table for [PostEffect::ColorGrading]. See [ColorLut] for the layout of the image.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # use crystal_engine::state::{ColorGrading, PostEffect};
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// let lut = state.load_color_lut("luts/sunset.png").unwrap();
+    /// state.post_processing.effects.push(PostEffect::ColorGrading(ColorGrading {
+    ///     lut,
+    ///     intensity: 1.0,
+    /// }));
+    /// ```
+    ///
+    /// [PostEffect::ColorGrading]: ./state/enum.PostEffect.html#variant.ColorGrading
+    /// [ColorLut]: ./state/struct.ColorLut.html
+    pub fn load_color_lut(&mut self, path: &str) -> Result<ColorLut, ColorLutError> {
+        let start = Instant::now();
+        let image = image::open(self.assets.resolve(path))
+            .map_err(|inner| ColorLutError::CouldNotLoadImage {
+                path: path.to_owned(),
+                inner,
+            })?
+            .into_rgba();
+        let (width, height) = image.dimensions();
+        let size = ColorLut::validate(path, width, height)?;
+        let (texture, future) = texture::from_rgba(
+            self.transfer_queue.clone(),
+            width,
+            height,
+            image.into_raw(),
+            Format::R8G8B8A8Srgb,
+        )
+        .map_err(|inner| ColorLutError::CouldNotUpload { inner })?;
+        self.profiler
+            .record_nested(ProfilerStage::AssetUpload, start.elapsed());
+        Ok(ColorLut::new(texture, size, future))
+    }
+
+    /// Rasterize the glyphs of a font ahead of time, so text with these characters does not cause a hitch the first time it is shown.
+    ///
+    /// Rasterized glyphs are cached per font and font size, so this should be called with every font size that the game uses.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # use crystal_engine::state::GlyphRange;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// let font = state.load_font("assets/roboto.ttf").unwrap();
+    /// state.prewarm_glyphs(&font, &[16, 24], &[GlyphRange::Latin1, GlyphRange::Range('а', 'я')]);
+    /// ```
+    pub fn prewarm_glyphs(&mut self, font: &Font, sizes: &[u16], ranges: &[GlyphRange]) {
+        for range in ranges {
+            self.glyph_cache.prewarm(font, sizes, range);
+        }
+    }
+
+    /// Create a signed distance field atlas with the glyphs in the given ranges. Text that is rendered with this atlas stays crisp at any size, see [GuiElementBuilder::sdf_text].
+    ///
+    /// Creating the atlas can take a moment, so this should be done while loading the game. Characters that are not in the ranges are not rendered.
+    ///
+    /// [GuiElementBuilder::sdf_text]: ./state/struct.GuiElementBuilder.html#method.sdf_text
+    pub fn create_sdf_font(
+        &mut self,
+        font: &Font,
+        ranges: &[GlyphRange],
+    ) -> Result<SdfFont, GuiError> {
+        let start = Instant::now();
+        let font = SdfFont::new(self.transfer_queue.clone(), font, ranges)?;
+        self.profiler
+            .record_nested(ProfilerStage::AssetUpload, start.elapsed());
+        Ok(font)
+    }
+
+    /// Remove all rasterized glyphs from the cache. They will be rasterized again the next time they are used.
+    ///
+    /// Glyphs of fonts that are dropped are removed automatically.
+    pub fn clear_glyph_cache(&mut self) {
+        self.glyph_cache.clear();
+    }
+
+    /// Add a color picker to the screen, with a saturation/value square, a hue bar and an alpha bar. The callback is called with the new color every time the player picks a color.
+    ///
+    /// This is intended for tuning values in a running game, e.g. the color of a light.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// state
+    ///     .add_color_picker((10, 10, 200, 150), color::WHITE, |state, color| {
+    ///         if let Some(light) = state.light.point.get_mut(0) {
+    ///             light.color.diffuse = [
+    ///                 color[0] as f32 / 255.0,
+    ///                 color[1] as f32 / 255.0,
+    ///                 color[2] as f32 / 255.0,
+    ///             ]
+    ///             .into();
+    ///         }
+    ///     })
+    ///     .unwrap();
+    /// ```
+    pub fn add_color_picker(
+        &mut self,
+        dimensions: (i32, i32, u32, u32),
+        color: [u8; 4],
+        callback: impl FnMut(&mut GameState, [u8; 4]) + 'static,
+    ) -> Result<DebugWidgetId, GuiError> {
+        DebugWidgets::add_color_picker(self, dimensions, color, callback)
+    }
+
+    /// Add a gradient editor to the screen. The top of the editor shows the gradient. Clicking the gradient adds a new stop, stops can be dragged to move them and right-clicked to remove them. The bottom of the editor is a color picker for the selected stop.
+    ///
+    /// The callback is called with the new gradient every time the gradient is changed.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # use crystal_engine::state::Gradient;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// let gradient = Gradient::new(vec![(0.0, color::BLACK), (1.0, color::WHITE)]);
+    /// state
+    ///     .add_gradient_editor((10, 10, 200, 200), gradient, |_state, gradient| {
+    ///         println!("Fog color at 50%: {:?}", gradient.sample(0.5));
+    ///     })
+    ///     .unwrap();
+    /// ```
+    pub fn add_gradient_editor(
+        &mut self,
+        dimensions: (i32, i32, u32, u32),
+        gradient: Gradient,
+        callback: impl FnMut(&mut GameState, &Gradient) + 'static,
+    ) -> Result<DebugWidgetId, GuiError> {
+        DebugWidgets::add_gradient_editor(self, dimensions, gradient, callback)
+    }
+
+    /// Remove a color picker or gradient editor from the screen.
+    pub fn remove_debug_widget(&mut self, id: DebugWidgetId) {
+        self.debug_widgets.remove(id);
+    }
+
+    /// Add a grid on the XZ plane to the world, centered on the origin. The grid is `size` world units wide and deep, with a line every `spacing` world units. This makes the scale of the world visible while blocking out levels.
+    ///
+    /// The grid is drawn by [debug_draw](#structfield.debug_draw) until it is removed, and can be toggled at runtime:
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// let grid = state.new_grid(100.0, 1.0);
+    /// state.new_axes(5.0);
+    ///
+    /// // Hide only the grid
+    /// state.debug_draw.set_helper_visible(grid, false);
+    /// // Hide all helpers
+    /// state.debug_draw.show_helpers = false;
+    /// ```
+    pub fn new_grid(&mut self, size: f32, spacing: f32) -> DebugHelperId {
+        self.debug_draw.add_grid(size, spacing)
+    }
+
+    /// Add the axes of the world, starting at the origin and `length` world units long. The x-axis is red, the y-axis is green and the z-axis is blue.
+    ///
+    /// The axes are drawn by [debug_draw](#structfield.debug_draw) until they are removed, see [new_grid](#method.new_grid).
+    pub fn new_axes(&mut self, length: f32) -> DebugHelperId {
+        self.debug_draw.add_axes(length)
+    }
+
+    /// Pass a window event to the debug widgets. Returns `true` if the event was used by one of the widgets.
+    pub(crate) fn handle_debug_widget_event(&mut self, event: &winit::event::WindowEvent) -> bool {
+        let mut widgets = std::mem::take(&mut self.debug_widgets);
+        let mut consumed = widgets.handle_event(self, event);
+        self.debug_widgets = widgets;
+        if !consumed {
+            if let Some(mut ruler) = self.ruler.take() {
+                consumed = ruler.handle_event(self, event);
+                self.ruler = Some(ruler);
+            }
+        }
+        consumed
+    }
+
+    /// Show the timings of the [profiler](#structfield.profiler) in the top-left corner of the screen.
+    /// The overlay is refreshed a few times per second, and shows the average timings of the last 60 frames. When a frame took a lot longer than the average, the overlay also shows the stage that caused this spike.
+    ///
+    /// When the game uses [profile scopes](#method.profile_scope), the slowest scopes of the last frame are shown on a second line.
+    ///
+    /// The overlay can be hidden again by calling [hide_profiler_overlay](#method.hide_profiler_overlay).
+    pub fn show_profiler_overlay(&mut self, font: Font) -> Result<(), GuiError> {
+        let overlay = ProfilerOverlay::new(self, font)?;
+        self.profiler_overlay = Some(overlay);
+        Ok(())
+    }
+
+    /// Hide the overlay that was shown with [show_profiler_overlay](#method.show_profiler_overlay).
+    pub fn hide_profiler_overlay(&mut self) {
+        self.profiler_overlay = None;
+    }
+
+    /// Show or hide an overlay with the fps, a graph of the frame times of the last 60 frames, the amount of [draw calls](./state/struct.ProfilerState.html#method.draw_calls) and the amount of visible models. The overlay is placed in the top-left corner of the screen, below the [profiler overlay](#method.show_profiler_overlay).
+    ///
+    /// Frames in the graph that are slower than 60 fps are yellow, frames that are slower than 30 fps are red. The top of the graph is 50 milliseconds.
+    ///
+    /// Note that [GuiTheme::font] has to be set for the text of the overlay to be shown.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// state.gui.theme.font = Some(state.load_font("assets/roboto.ttf").unwrap());
+    /// state.show_perf_overlay(true);
+    /// ```
+    ///
+    /// [GuiTheme::font]: ./state/struct.GuiTheme.html#structfield.font
+    pub fn show_perf_overlay(&mut self, show: bool) {
+        if !show {
+            self.perf_overlay = None;
+        } else if self.perf_overlay.is_none() {
+            self.perf_overlay = Some(PerfOverlay::default());
+        }
+    }
+
+    /// Measure the time spent in a part of the game, until the returned [ProfileScope] is dropped. Scopes can be nested, and the scopes with the same name are added together for every frame.
+    ///
+    /// The timings of the last frame can be read with [ProfilerState::scopes], and are shown in the [profiler overlay](#method.show_profiler_overlay) and in the traces of [ProfilerState::start_trace].
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// # fn find_path() {}
+    /// let _scope = state.profile_scope("ai");
+    /// for _ in 0..10 {
+    ///     let _scope = state.profile_scope("pathfinding");
+    ///     find_path();
+    /// }
+    /// ```
+    ///
+    /// [ProfileScope]: ./state/struct.ProfileScope.html
+    /// [ProfilerState::scopes]: ./state/struct.ProfilerState.html#method.scopes
+    /// [ProfilerState::start_trace]: ./state/struct.ProfilerState.html#method.start_trace
+    pub fn profile_scope(&self, name: &'static str) -> ProfileScope {
+        self.profiler.scope(name)
+    }
+
+    /// Show a ruler that measures the distance between two points in the world. This can be used to tune the size of gameplay spaces to the scale of the assets.
+    ///
+    /// Left click two points in the world to measure the distance between them. The points are found with [pick](#method.pick). The distance and the difference between the points, in world units, are shown in a label in the middle of the line. A third click starts a new measurement, and a right click removes the measurement.
+    ///
+    /// While the ruler is shown, mouse clicks are not passed to [Game::event](./trait.Game.html#method.event). The ruler can be hidden again by calling [hide_ruler](#method.hide_ruler).
+    pub fn show_ruler(&mut self, font: Font) {
+        self.ruler = Some(Ruler::new(font));
+    }
+
+    /// Hide the ruler that was shown with [show_ruler](#method.show_ruler).
+    pub fn hide_ruler(&mut self) {
+        self.ruler = None;
+    }
+
+    pub(crate) fn refresh_ruler(&mut self) {
+        if let Some(mut ruler) = self.ruler.take() {
+            ruler.refresh(self);
+            self.ruler = Some(ruler);
+        }
+    }
+
+    pub(crate) fn refresh_profiler_overlay(&mut self) {
+        if let Some(mut overlay) = self.profiler_overlay.take() {
+            overlay.refresh(self);
+            self.profiler_overlay = Some(overlay);
+        }
+        if let Some(mut overlay) = self.perf_overlay.take() {
+            overlay.refresh(self);
+            self.perf_overlay = Some(overlay);
+        }
+    }
+
+    /// Show a short notification in the top-right corner of the screen for the given duration.
+    ///
+    /// Notifications are queued, so if a notification is already visible, this notification will be shown after the previous notifications have disappeared.
+    /// The notification is styled with [GuiState::theme], and placed inside of the [safe area].
+    ///
+    /// Note that [GuiTheme::font] has to be set, or the notification will not be shown.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # use std::time::Duration;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// state.gui.theme.font = Some(state.load_font("assets/roboto.ttf").unwrap());
+    /// state.notify("Checkpoint reached", Duration::from_secs(3));
+    /// ```
+    ///
+    /// [GuiState::theme]: ./state/struct.GuiState.html#structfield.theme
+    /// [GuiTheme::font]: ./state/struct.GuiTheme.html#structfield.font
+    /// [safe area]: ./state/struct.GuiState.html#method.safe_area
+    pub fn notify(&mut self, text: impl std::fmt::Display, duration: Duration) {
+        self.notifications.push_toast(text.to_string(), duration);
+    }
+
+    /// Show a subtitle at the bottom of the screen for the given duration.
+    ///
+    /// Subtitles are queued separately from [notifications](#method.notify), and are shown one after another.
+    pub fn show_subtitle(&mut self, text: impl std::fmt::Display, duration: Duration) {
+        self.notifications.push_subtitle(text.to_string(), duration);
+    }
+
+    /// Remove all notifications and subtitles from the screen, including the ones that are queued.
+    pub fn clear_notifications(&mut self) {
+        self.notifications.clear();
+    }
+
+    pub(crate) fn refresh_notifications(&mut self) {
+        let mut notifications = std::mem::take(&mut self.notifications);
+        notifications.refresh(self);
+        self.notifications = notifications;
+    }
+
+    /// Upload the models of the chunks that were parsed by the [streaming](#structfield.streaming) state.
+    pub(crate) fn update_streaming(&mut self) {
+        let uploads = self.streaming.poll(&self.assets);
+        for upload in uploads {
+            let builder = self.new_model(upload.parsed);
+            let result = crate::scene::build_model(builder, &upload.model);
+            self.streaming
+                .finish_upload(upload.chunk, upload.load_id, result);
+        }
+    }
+
+    /// Save the next frame that is rendered to an image file. The format of the image is based on the extension of the path, e.g. `.png`.
+    ///
+    /// The screenshot is saved after the frame is rendered. Errors while saving the screenshot are printed to stderr.
+    pub fn save_screenshot(&mut self, path: impl Into<std::path::PathBuf>) {
+        self.storage.screenshots.push(ScreenshotRequest {
+            path: path.into(),
+            max_width: None,
+        });
+    }
+
+    /// Get a reference to the winit window. This can be used to set the title with `set_title`, grap the cursor with `set_cursor_grab` and `set_cursor_visible`, and more.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the game is [headless](#method.is_headless), because there is no window.
+    pub fn window(&self) -> &winit::window::Window {
+        self.output
+            .window()
+            .expect("A headless game does not have a window")
+    }
+
+    /// Check if the game is rendered into an offscreen image instead of a window. See [HeadlessWindow] for more information.
+    ///
+    /// Methods that change the window, like [set_title](#method.set_title) and [set_mouse_mode](#method.set_mouse_mode), do nothing in headless games.
+    ///
+    /// [HeadlessWindow]: ./struct.HeadlessWindow.html
+    pub fn is_headless(&self) -> bool {
+        matches!(self.output, RenderOutput::Headless { .. })
+    }
+
+    /// Set the title of the window. This is cached, so it is cheap to call this every frame.
+    ///
+    /// The engine does not change the title of the window until this is called. After that, titles that are set directly on the [window](#method.window) are overwritten when the fps suffix is updated, see [set_title_fps_suffix](#method.set_title_fps_suffix).
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// # let level = 1;
+    /// state.set_title(format_args!("My game - level {}", level));
+    /// ```
+    pub fn set_title(&mut self, title: impl std::fmt::Display) {
+        let title = title.to_string();
+        if self.title.title.as_ref() != Some(&title) {
+            self.title.title = Some(title);
+            if let Some(window) = self.output.window() {
+                self.title.refresh(window, &self.time);
+            }
+        }
+    }
+
+    /// Enable or disable the fps counter at the end of the window title. This is disabled by default.
+    ///
+    /// The counter is added to the title that is set with [set_title](#method.set_title), so it is only shown once the title is set.
+    pub fn set_title_fps_suffix(&mut self, enabled: bool) {
+        self.title.fps_suffix = enabled;
+        if let Some(window) = self.output.window() {
+            self.title.refresh(window, &self.time);
+        }
+    }
+
+    /// Change the icon of the window to the image at the given path. Loading the same icon again does nothing, so this can be called every frame.
+    ///
+    /// # Platform-specific
+    ///
+    /// This only has an effect on Windows and X11.
+    pub fn set_window_icon(&mut self, path: impl AsRef<std::path::Path>) -> Result<(), IconError> {
+        let path = path.as_ref();
+        if self.icon_path.as_deref() == Some(path) {
+            return Ok(());
+        }
+        let image = image::open(self.assets.resolve(path))
+            .map_err(|inner| IconError::CouldNotLoadImage {
+                path: path.display().to_string(),
+                inner,
+            })?
+            .into_rgba();
+        let (width, height) = image.dimensions();
+        let icon = winit::window::Icon::from_rgba(image.into_raw(), width, height)
+            .map_err(IconError::InvalidIcon)?;
+        if let Some(window) = self.output.window() {
+            window.set_window_icon(Some(icon));
+        }
+        self.icon_path = Some(path.to_owned());
+        Ok(())
+    }
+
+    /// Remove the icon of the window, and go back to the default icon of the platform.
+    pub fn clear_window_icon(&mut self) {
+        if let Some(window) = self.output.window() {
+            window.set_window_icon(None);
+        }
+        self.icon_path = None;
+    }
+
+    /// Set the cursor position. This is short for:
+    ///
+    /// ```rust
+    /// state.window()
+    ///      .set_cursor_position(winit::dpi::PhysicalPosition::new(position.0, position.1))
+    ///      .unwrap();
+    /// ```
+    ///
+    /// # Platform-specific
+    ///
+    /// On iOS this always returns an error, so this function is empty
+    #[cfg(target = "ios")]
+    pub fn set_cursor_position<P: winit::dpi::Pixel>(&self, _position: (P, P)) {}
+
+    /// Set the cursor position. This is short for:
+    ///
+    /// ```rust, no_run
+    /// # use crystal_engine::*;
+    /// # let state: GameState = unsafe { std::mem::zeroed() };
+    /// state.window()
+    ///      .set_cursor_position(winit::dpi::PhysicalPosition::new(0u32, 0u32))
+    ///      .unwrap();
+    /// ```
+    ///
+    /// # Platform-specific
+    ///
+    /// On iOS this always returns an error, so this function is empty
+    #[cfg(not(target = "ios"))]
+    pub fn set_cursor_position<P: winit::dpi::Pixel>(&self, position: (P, P)) {
+        if let Some(window) = self.output.window() {
+            window
+                .set_cursor_position(winit::dpi::PhysicalPosition::new(position.0, position.1))
+                .unwrap(); // we assume this always succeeds
+        }
+    }
+
+    /// Change the way the mouse behaves. See [MouseMode] for more information.
+    ///
+    /// A relative mouse is released while the window does not have [focus](#method.has_focus), and grabbed again when the window gets focus back.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # use crystal_engine::state::MouseMode;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// // First person camera, receive the mouse movement in `Game::mouse_moved`
+    /// state.set_mouse_mode(MouseMode::Relative);
+    /// ```
+    ///
+    /// [MouseMode]: ./state/enum.MouseMode.html
+    pub fn set_mouse_mode(&mut self, mode: MouseMode) {
+        self.mouse_mode = mode;
+        self.apply_cursor_grab();
+    }
+
+    /// Grab and hide the cursor when the mouse is relative and the window has focus, so the cursor is released when the player switches to another window.
+    fn apply_cursor_grab(&self) {
+        let grab = self.mouse_mode == MouseMode::Relative && self.has_focus;
+        if let Some(window) = self.output.window() {
+            // Grabbing the cursor is not supported on every platform. On those platforms the
+            // cursor will only be hidden, which is the best we can do.
+            let _ = window.set_cursor_grab(grab);
+            // A custom cursor is drawn by the engine, so the cursor of the OS is always hidden
+            window.set_cursor_visible(!grab && self.custom_cursor.is_none());
+        }
+    }
+
+    /// Returns `true` if the window has the keyboard focus. This is `false` when the player switches to another window, which is a good moment to pause the game, see [Game::focus_changed](trait.Game.html#method.focus_changed).
+    ///
+    /// A [headless](struct.HeadlessWindow.html) game always has focus.
+    pub fn has_focus(&self) -> bool {
+        self.has_focus
+    }
+
+    /// Returns `true` if the cursor is inside of the window.
+    pub fn is_cursor_inside(&self) -> bool {
+        self.cursor_inside
+    }
+
+    /// Change the focus of the window. The cursor is released while the window does not have focus.
+    pub(crate) fn set_focus(&mut self, focused: bool) {
+        self.has_focus = focused;
+        self.apply_cursor_grab();
+    }
+
+    pub(crate) fn set_cursor_inside(&mut self, inside: bool) {
+        self.cursor_inside = inside;
+    }
+
+    pub(crate) fn set_cursor_position(&mut self, position: (f64, f64)) {
+        self.cursor_position = Some(position);
+    }
+
+    /// Change the cursor of the OS that is shown when the mouse is over the window, e.g. to a hand when the mouse is over a button. This removes the cursor that was set with [set_custom_cursor](#method.set_custom_cursor).
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// state.set_cursor(winit::window::CursorIcon::Hand);
+    /// ```
+    pub fn set_cursor(&mut self, icon: winit::window::CursorIcon) {
+        self.custom_cursor = None;
+        if let Some(window) = self.output.window() {
+            window.set_cursor_icon(icon);
+        }
+        self.apply_cursor_grab();
+    }
+
+    /// Replace the cursor with an image, e.g. a sword for a fantasy game. The `hotspot` is the pixel of the image that points at the position of the mouse, counted from the top-left of the image.
+    ///
+    /// The cursor of the OS is hidden, and the image is drawn on top of all GUI elements at the position of the mouse. The image is drawn at its original size in pixels, independent of the [virtual resolution](./state/struct.GuiState.html#structfield.virtual_resolution). The image is hidden while the [mouse mode](#method.set_mouse_mode) is relative, or when the mouse is outside of the window.
+    ///
+    /// The cursor of the OS can be shown again with [set_cursor](#method.set_cursor).
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// // The tip of the sword is in the top-left corner of the image
+    /// state.set_custom_cursor("assets/sword.png", (0, 0)).unwrap();
+    /// ```
+    pub fn set_custom_cursor(&mut self, path: &str, hotspot: (u32, u32)) -> Result<(), GuiError> {
+        let element = self
+            .new_gui_element((0, 0, 0, 0))
+            .with_texture(path)
+            // The image is drawn at its original size, so it does not need to be blended
+            .with_filter(GuiFilter::Nearest)
+            .build()?;
+        let image_size = self
+            .gui_elements
+            .get(&element.id)
+            .and_then(|element| match &element.texture {
+                GuiTexture::Immutable(texture) => {
+                    let dimensions = texture.dimensions();
+                    Some((dimensions.width(), dimensions.height()))
+                }
+                _ => None,
+            })
+            // The element was just added, and a texture element is always immutable
+            .expect("Cursor element has no texture");
+        let cursor = CustomCursor::new(element, image_size, hotspot);
+        self.custom_cursor = Some(cursor);
+        self.apply_cursor_grab();
+        self.refresh_custom_cursor();
+        Ok(())
+    }
+
+    /// Move the image of the [custom cursor](#method.set_custom_cursor) to the position of the mouse.
+    pub(crate) fn refresh_custom_cursor(&mut self) {
+        if let Some(cursor) = &self.custom_cursor {
+            let visible = self.cursor_inside && self.mouse_mode != MouseMode::Relative;
+            let position = self.cursor_position.filter(|_| visible);
+            cursor.refresh(&self.gui, position);
+        }
+    }
+
+    /// Get the current [MouseMode](./state/enum.MouseMode.html).
+    pub fn mouse_mode(&self) -> MouseMode {
+        self.mouse_mode
+    }
+
+    /// Limit the amount of frames that are rendered per second. `None` renders frames as fast as possible, which is limited by the refresh rate of the monitor. This defaults to `None`.
+    ///
+    /// The engine sleeps between frames, so a lower frame rate saves power, e.g. in menus.
+    pub fn set_target_fps(&mut self, fps: Option<u32>) {
+        self.target_fps = fps.filter(|fps| *fps > 0);
+    }
+
+    /// Get the frame rate that was set with [set_target_fps](#method.set_target_fps).
+    pub fn target_fps(&self) -> Option<u32> {
+        self.target_fps
+    }
+
+    /// Render the world at a lower resolution than the window to make the game faster on slow GPUs, or at a higher resolution to smooth the edges of models. The image of the world is scaled to the size of the window before the GUI is drawn on top of it, so the GUI stays sharp. This defaults to `1.0`.
+    ///
+    /// The scale is clamped between `0.25` and `2.0`. A render scale of `0.5` renders a quarter of the pixels. Changing the scale recreates the images that the world is rendered into, so avoid changing it every frame.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// // Render at 75% of the resolution of the window
+    /// state.set_render_scale(0.75);
+    /// ```
+    pub fn set_render_scale(&mut self, scale: f32) {
+        if scale.is_finite() {
+            self.render_scale = scale.max(0.25).min(2.0);
         }
     }
 
-    pub(crate) fn update(&mut self) {
-        self.time.update();
+    /// Get the render scale that was set with [set_render_scale](#method.set_render_scale).
+    pub fn render_scale(&self) -> f32 {
+        self.render_scale
     }
 
-    /// Load a font from the given relative path. This function will panic if the font does not exist.
+    /// Call [Game::fixed_update](trait.Game.html#method.fixed_update) the given amount of times per second, independent of the frame rate. `None` disables the fixed update. This defaults to `None`.
     ///
-    /// The font is not stored internally, and must be stored by the developer.
-    pub fn load_font(&mut self, font: impl AsRef<std::path::Path>) -> Result<Font, GuiError> {
-        use std::{fs::File, io::Read};
-        let font = font.as_ref();
-        let font_str = font.to_str().unwrap_or("unknown");
+    /// Models that are moved in the fixed update are smoothly moved between the updates when they are rendered, see [transform_smoothing](#structfield.transform_smoothing).
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// // Run the physics at 30 Hz, while rendering as fast as the monitor allows
+    /// state.set_fixed_update_rate(Some(30));
+    /// ```
+    pub fn set_fixed_update_rate(&mut self, rate: Option<u32>) {
+        self.time.set_fixed_delta(
+            rate.filter(|rate| *rate > 0)
+                .map(|rate| Duration::from_secs_f64(1.0 / rate as f64)),
+        );
+    }
 
-        let mut file = File::open(font).map_err(|e| GuiError::CouldNotReadFontFile {
-            file: font_str.to_string(),
-            inner: e,
-        })?;
-        let mut content = Vec::new();
-        file.read_to_end(&mut content)
-            .map_err(|e| GuiError::CouldNotReadFontFile {
-                file: font_str.to_string(),
-                inner: e,
-            })?;
+    /// Remember the transform of every model before a fixed update.
+    pub(crate) fn begin_fixed_update(&mut self) {
+        for model in self.model_handles.values_mut() {
+            let transform = model.data.read().transform();
+            model.fixed_transforms = Some((transform, transform));
+        }
+    }
 
-        match rusttype::Font::try_from_vec(content) {
-            Some(font) => Ok(Arc::new(font)),
-            None => Err(GuiError::CouldNotLoadFont),
+    /// Remember the transform of every model after a fixed update, so the models can be moved smoothly until the next fixed update.
+    pub(crate) fn end_fixed_update(&mut self) {
+        for model in self.model_handles.values_mut() {
+            if let Some((_, current)) = &mut model.fixed_transforms {
+                *current = model.data.read().transform();
+            }
         }
     }
 
-    /// Get a reference to the winit window. This can be used to set the title with `set_title`, grap the cursor with `set_cursor_grab` and `set_cursor_visible`, and more.
-    pub fn window(&self) -> &winit::window::Window {
-        self.surface.window()
+    /// The matrix that a model is rendered with, with the [transform_smoothing](#structfield.transform_smoothing) applied.
+    pub(crate) fn model_matrix(&self, model: &ModelRef, data: &ModelData) -> Matrix4<f32> {
+        let amount = match self.transform_smoothing {
+            TransformSmoothing::None => return data.matrix(),
+            TransformSmoothing::Interpolate => self.time.fixed_alpha(),
+            TransformSmoothing::Extrapolate => 1.0 + self.time.fixed_alpha(),
+        };
+        match model.fixed_transforms {
+            // Models that were moved outside of the fixed update are rendered where they are
+            Some((previous, current))
+                if self.time.fixed_delta().is_some() && current == data.transform() =>
+            {
+                previous.blended_matrix(&current, amount)
+            }
+            _ => data.matrix(),
+        }
     }
 
-    /// Set the cursor position. This is short for:
+    /// Only update and render a new frame when an event happens, e.g. when the mouse moves or a key is pressed. This saves a lot of power in applications that only have a GUI, but [Game::update](trait.Game.html#tymethod.update) is no longer called every frame, so animations and timers stop while nothing happens. This defaults to `false`.
     ///
-    /// ```rust
-    /// state.window()
-    ///      .set_cursor_position(winit::dpi::PhysicalPosition::new(position.0, position.1))
-    ///      .unwrap();
-    /// ```
+    /// When a [target fps](#method.set_target_fps) is set, a frame is still rendered at that rate, and events in between are handled without rendering a frame. Power saving only waits for events when no target fps is set.
+    pub fn set_power_saving(&mut self, enabled: bool) {
+        self.power_saving = enabled;
+    }
+
+    /// Check if [power saving](#method.set_power_saving) is enabled.
+    pub fn is_power_saving(&self) -> bool {
+        self.power_saving
+    }
+
+    /// Exit the game. Once this function is called, it cannot be cancelled. This does not confirm with [Game::can_shutdown](trait.Game.html#method.can_shutdown).
+    pub fn terminate_game(&mut self) {
+        self.is_running = false;
+    }
+
+    /// Get the width and height of the window, excluding the menu bar and borders. This is the renderable surface.
     ///
-    /// # Platform-specific
+    /// This method is short for `window().inner_size()`. In [headless](#method.is_headless) games this is the size of the offscreen image.
+    pub fn window_size(&self) -> (u32, u32) {
+        match &self.output {
+            RenderOutput::Window(surface) => {
+                let size = surface.window().inner_size();
+                (size.width, size.height)
+            }
+            RenderOutput::Headless { width, height } => (*width, *height),
+        }
+    }
+
+    /// Get the scale factor of the monitor that the window is on. This is the amount of physical pixels per logical pixel, e.g. `2.0` on most HiDPI screens. In [headless](#method.is_headless) games this is always `1.0`.
     ///
-    /// On iOS this always returns an error, so this function is empty
-    #[cfg(target = "ios")]
-    pub fn set_cursor_position<P: winit::dpi::Pixel>(&self, _position: (P, P)) {}
+    /// [Game::resized] is called when this changes. See [GuiState::logical_pixels] to place GUI elements in logical pixels.
+    ///
+    /// [Game::resized]: ./trait.Game.html#method.resized
+    /// [GuiState::logical_pixels]: ./state/struct.GuiState.html#structfield.logical_pixels
+    pub fn scale_factor(&self) -> f64 {
+        match &self.output {
+            RenderOutput::Window(surface) => surface.window().scale_factor(),
+            RenderOutput::Headless { .. } => 1.0,
+        }
+    }
 
-    /// Set the cursor position. This is short for:
+    /// Get the monitors that are connected to the computer, e.g. to show them in a display settings menu. The index of a monitor is its position in this list. In [headless](#method.is_headless) games there are no monitors.
     ///
-    /// ```rust, no_run
+    /// ```no_run
     /// # use crystal_engine::*;
-    /// # let state: GameState = unsafe { std::mem::zeroed() };
-    /// state.window()
-    ///      .set_cursor_position(winit::dpi::PhysicalPosition::new(0u32, 0u32))
-    ///      .unwrap();
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// for monitor in state.monitors() {
+    ///     println!(
+    ///         "{}: {}x{} at {:?} Hz",
+    ///         monitor.name, monitor.resolution.0, monitor.resolution.1, monitor.refresh_rate
+    ///     );
+    /// }
     /// ```
+    pub fn monitors(&self) -> Vec<MonitorInfo> {
+        let window = match self.output.window() {
+            Some(window) => window,
+            None => return Vec::new(),
+        };
+        let primary = window.primary_monitor();
+        window
+            .available_monitors()
+            .enumerate()
+            .map(|(index, monitor)| MonitorInfo::new(index, &monitor, &primary))
+            .collect()
+    }
+
+    /// Get the monitor that the window is on. When the window is on multiple monitors, this is the monitor with the largest part of the window. In [headless](#method.is_headless) games this is always `None`.
+    pub fn current_monitor(&self) -> Option<MonitorInfo> {
+        let window = self.output.window()?;
+        let current = window.current_monitor();
+        let index = window
+            .available_monitors()
+            .position(|monitor| monitor == current)
+            .unwrap_or_default();
+        Some(MonitorInfo::new(index, &current, &window.primary_monitor()))
+    }
+
+    /// Get the current mode of the window. In [headless](#method.is_headless) games this is always [WindowMode::Windowed].
     ///
-    /// # Platform-specific
-    ///
-    /// On iOS this always returns an error, so this function is empty
-    #[cfg(not(target = "ios"))]
-    pub fn set_cursor_position<P: winit::dpi::Pixel>(&self, position: (P, P)) {
-        self.window()
-            .set_cursor_position(winit::dpi::PhysicalPosition::new(position.0, position.1))
-            .unwrap(); // we assume this always succeeds
+    /// [WindowMode::Windowed]: ./state/enum.WindowMode.html#variant.Windowed
+    pub fn window_mode(&self) -> WindowMode {
+        match self.output.window() {
+            Some(window) => WindowMode::from_fullscreen(
+                window.fullscreen(),
+                &window.available_monitors().collect::<Vec<_>>(),
+            ),
+            None => WindowMode::Windowed,
+        }
     }
 
-    /// Exit the game. Once this function is called, it cannot be cancelled. This does not confirm with [Game::can_shutdown](trait.Game.html#method.can_shutdown).
-    pub fn terminate_game(&mut self) {
-        self.is_running = false;
+    /// Change the window to a regular window, borderless fullscreen or exclusive fullscreen, on one of the [monitors](#method.monitors). [Game::resized] is called when the size of the window changes.
+    ///
+    /// Returns an error if the monitor does not exist, or if the monitor does not support the video mode. This does nothing in [headless](#method.is_headless) games.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # use crystal_engine::state::WindowMode;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// // Use the highest resolution and refresh rate of the monitor that the window is on
+    /// if let Some(monitor) = state.current_monitor() {
+    ///     if let Some(&video_mode) = monitor.video_modes.first() {
+    ///         state
+    ///             .set_window_mode(WindowMode::Exclusive {
+    ///                 monitor: monitor.index,
+    ///                 video_mode,
+    ///             })
+    ///             .unwrap();
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// [Game::resized]: ./trait.Game.html#method.resized
+    pub fn set_window_mode(&mut self, mode: WindowMode) -> Result<(), MonitorError> {
+        if let Some(window) = self.output.window() {
+            let monitors = window.available_monitors().collect::<Vec<_>>();
+            window.set_fullscreen(mode.to_fullscreen(&monitors)?);
+        }
+        Ok(())
     }
 
-    /// Get the width and height of the window, excluding the menu bar and borders. This is the renderable surface.
+    /// Move the window to the center of one of the [monitors](#method.monitors). A fullscreen window becomes a borderless fullscreen window on the new monitor, because the video modes of monitors are different.
     ///
-    /// This method is short for `window().inner_size()`
-    pub fn window_size(&self) -> (u32, u32) {
-        let size = self.window().inner_size();
-        (size.width, size.height)
+    /// Returns an error if the monitor does not exist. This does nothing in [headless](#method.is_headless) games.
+    pub fn move_window_to_monitor(&mut self, monitor: usize) -> Result<(), MonitorError> {
+        if self.window_mode() != WindowMode::Windowed {
+            return self.set_window_mode(WindowMode::Borderless { monitor });
+        }
+        let window = match self.output.window() {
+            Some(window) => window,
+            None => return Ok(()),
+        };
+        let monitors = window.available_monitors().collect::<Vec<_>>();
+        let handle = monitors.get(monitor).ok_or(MonitorError::UnknownMonitor {
+            index: monitor,
+            count: monitors.len(),
+        })?;
+        window.set_outer_position(monitor::centered_position(handle, window.outer_size()));
+        Ok(())
     }
 
     /// Create a new GUI element.
     /// The element will be placed at `dimensions.0 / dimensions.1` from the bottom-left of the window, with a size of `dimensions.2 x dimensions.3` scaling towards the top-right.
     /// The element will ignore window size, it is up to the developer to make sure elements are rendered inside of the window.
     ///
+    /// If [GuiState::virtual_resolution] is set, the dimensions are in the virtual resolution instead of in pixels.
+    ///
     /// The returned builder can either be turned into a [GuiElementTextureBuilder] by calling `.with_texture(path)`, or into a [GuiElementCanvasBuilder] by calling `.with_canvas(color)`.
     /// See the respective structs for more options.
     ///
@@ -177,6 +1814,7 @@ impl GameState {
     /// [GuiElementTextureBuilder]: ./state/struct.GuiElementTextureBuilder.html
     /// [GuiElementCanvasBuilder]: ./state/struct.GuiElementCanvasBuilder.html
     /// [GuiElement]: ./struct.GuiElement.html
+    /// [GuiState::virtual_resolution]: ./state/struct.GuiState.html#structfield.virtual_resolution
     pub fn new_gui_element(&mut self, dimensions: (i32, i32, u32, u32)) -> GuiElementBuilder {
         GuiElementBuilder::new(self, dimensions)
     }
@@ -224,6 +1862,73 @@ impl GameState {
         ModelBuilder::new(self, SourceOrShape::Rectangle)
     }
 
+    /// Create a new cube of 1x1x1 at the origin of the world. Every face of the cube has the full texture.
+    ///
+    /// See [ModelHandle] for information on how to move, rotate and clone the cube.
+    ///
+    /// Note: you *must* store the handle somewhere. When the handle is dropped, the cube is removed from your world and resources are unloaded.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut game_state: GameState = unsafe { std::mem::zeroed() };
+    /// let crate_model: ModelHandle = game_state.new_cube_model()
+    ///     .with_texture_from_file("assets/crate.png")
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    ///
+    /// [ModelHandle]: ./struct.ModelHandle.html
+    pub fn new_cube_model(&mut self) -> ModelBuilder {
+        ModelBuilder::new(self, SourceOrShape::Cube)
+    }
+
+    /// Create a new sphere with a diameter of 1 at the origin of the world. The sphere is made of `segments` slices around the Y axis and `rings` stacks from top to bottom. The texture is wrapped around the sphere like a map of the world.
+    ///
+    /// See [ModelHandle] for information on how to move, rotate and clone the sphere.
+    ///
+    /// [ModelHandle]: ./struct.ModelHandle.html
+    pub fn new_uv_sphere_model(&mut self, segments: u32, rings: u32) -> ModelBuilder {
+        ModelBuilder::new(self, SourceOrShape::UvSphere { segments, rings })
+    }
+
+    /// Create a new cylinder along the Y axis with a diameter and height of 1 at the origin of the world. The cylinder is made of `segments` slices around the Y axis.
+    ///
+    /// See [ModelHandle] for information on how to move, rotate and clone the cylinder.
+    ///
+    /// [ModelHandle]: ./struct.ModelHandle.html
+    pub fn new_cylinder_model(&mut self, segments: u32) -> ModelBuilder {
+        ModelBuilder::new(self, SourceOrShape::Cylinder { segments })
+    }
+
+    /// Create a new flat square of 1x1 on the XZ plane at the origin of the world, facing up. The square is divided in `subdivisions` by `subdivisions` quads, which is useful for e.g. terrain.
+    ///
+    /// See [ModelHandle] for information on how to move, rotate and clone the plane.
+    ///
+    /// [ModelHandle]: ./struct.ModelHandle.html
+    pub fn new_plane_model(&mut self, subdivisions: u32) -> ModelBuilder {
+        ModelBuilder::new(self, SourceOrShape::Plane { subdivisions })
+    }
+
+    /// Create a new capsule along the Y axis with a diameter of 1 at the origin of the world. The cylinder in the middle of the capsule is `height` high, so the capsule is `height + 1` high in total.
+    ///
+    /// The capsule is made of `segments` slices around the Y axis, and each half sphere has `rings` stacks.
+    ///
+    /// See [ModelHandle] for information on how to move, rotate and clone the capsule.
+    ///
+    /// [ModelHandle]: ./struct.ModelHandle.html
+    pub fn new_capsule_model(&mut self, segments: u32, rings: u32, height: f32) -> ModelBuilder {
+        ModelBuilder::new(
+            self,
+            SourceOrShape::Capsule {
+                segments,
+                rings,
+                height,
+            },
+        )
+    }
+
     /// Load a model externally. This allows you to define your own model loading, with more customization options.
     pub fn new_model(&mut self, parsed_model: ParsedModel) -> ModelBuilder {
         ModelBuilder::new(self, SourceOrShape::Custom(parsed_model))
@@ -258,8 +1963,11 @@ impl GameState {
 ///
 /// [GameState]: ../struct.GameState.html
 /// [Game]: ../trait.Game.html
+#[derive(Default)]
 pub struct KeyboardState {
-    pub(crate) pressed: HashSet<VirtualKeyCode>,
+    pressed: HashSet<VirtualKeyCode>,
+    just_pressed: HashSet<VirtualKeyCode>,
+    just_released: HashSet<VirtualKeyCode>,
 }
 
 impl KeyboardState {
@@ -267,6 +1975,133 @@ impl KeyboardState {
     pub fn is_pressed(&self, key: VirtualKeyCode) -> bool {
         self.pressed.contains(&key)
     }
+
+    /// Check if the given key was pressed since the last frame. Unlike [is_pressed](#method.is_pressed), this is only `true` for a single frame, and is not affected by key repeat.
+    pub fn was_just_pressed(&self, key: VirtualKeyCode) -> bool {
+        self.just_pressed.contains(&key)
+    }
+
+    /// Check if the given key was released since the last frame. This is only `true` for a single frame.
+    pub fn was_just_released(&self, key: VirtualKeyCode) -> bool {
+        self.just_released.contains(&key)
+    }
+
+    pub(crate) fn press(&mut self, key: VirtualKeyCode) {
+        // Key repeat sends a new press event for a key that is already pressed
+        if self.pressed.insert(key) {
+            self.just_pressed.insert(key);
+        }
+    }
+
+    pub(crate) fn release(&mut self, key: VirtualKeyCode) {
+        if self.pressed.remove(&key) {
+            self.just_released.insert(key);
+        }
+    }
+
+    /// Clear the keys that were pressed or released this frame. This is called after [Game::update].
+    ///
+    /// [Game::update]: ../trait.Game.html#tymethod.update
+    pub(crate) fn update(&mut self) {
+        self.just_pressed.clear();
+        self.just_released.clear();
+    }
+}
+
+/// The way the mouse behaves in the game. This can be changed with [GameState::set_mouse_mode].
+///
+/// [GameState::set_mouse_mode]: ../struct.GameState.html#method.set_mouse_mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseMode {
+    /// The cursor is visible and can move freely in and out of the window. This is the default.
+    Absolute,
+    /// The cursor is hidden and grabbed by the window, and the raw motion of the mouse is passed to [Game::mouse_moved].
+    /// The motion is reported even when the cursor would have hit the edge of the window. This is useful for e.g. first-person cameras.
+    ///
+    /// [Game::mouse_moved]: ../trait.Game.html#method.mouse_moved
+    Relative,
+}
+
+/// The way models are moved between two [fixed updates](../trait.Game.html#method.fixed_update), see [GameState::transform_smoothing].
+///
+/// Only the position, rotation and scale of models that are changed in the fixed update are smoothed. Models that are changed outside of the fixed update, e.g. in [Game::update], are always rendered where they are.
+///
+/// [GameState::transform_smoothing]: ../struct.GameState.html#structfield.transform_smoothing
+/// [Game::update]: ../trait.Game.html#tymethod.update
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransformSmoothing {
+    /// Models are rendered exactly where the last fixed update left them. Movement looks choppy when the fixed update rate is lower than the frame rate.
+    None,
+    /// Models are moved from where they were before the last fixed update to where they are now. This always looks smooth, but models are rendered up to one fixed update behind. This is the default.
+    Interpolate,
+    /// Models keep moving in the direction of the last fixed update, until the next fixed update. This has no delay, but models overshoot when they suddenly stop or turn.
+    Extrapolate,
+}
+
+/// The scale of the world. By default a single world unit is a single meter.
+///
+/// If your assets are authored at a different scale (e.g. centimeters) you can change
+/// the [meters per unit](#method.set_meters_per_unit) so that the engine defaults behave the same
+/// as they would for assets authored in meters.
+///
+/// ```no_run
+/// # use crystal_engine::*;
+/// # let mut state: GameState = unsafe { std::mem::zeroed() };
+/// // Our models are authored in centimeters
+/// state.units.set_meters_per_unit(0.01);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WorldUnits {
+    /// The amount of meters a single world unit represents. This is always positive and finite, because distances in meters are divided by it.
+    meters_per_unit: f32,
+}
+
+impl Default for WorldUnits {
+    fn default() -> Self {
+        Self {
+            meters_per_unit: 1.0,
+        }
+    }
+}
+
+impl WorldUnits {
+    /// Set the amount of meters a single world unit represents. This defaults to `1.0`.
+    ///
+    /// Values that are zero, negative, infinite or NaN are ignored, because they can not be converted back to meters.
+    pub fn set_meters_per_unit(&mut self, meters_per_unit: f32) {
+        if meters_per_unit.is_finite() && meters_per_unit > 0.0 {
+            self.meters_per_unit = meters_per_unit;
+        }
+    }
+
+    /// Get the amount of meters a single world unit represents, see [set_meters_per_unit](#method.set_meters_per_unit).
+    pub fn meters_per_unit(&self) -> f32 {
+        self.meters_per_unit
+    }
+
+    /// Convert a distance in world units to meters.
+    pub fn units_to_meters(&self, units: f32) -> f32 {
+        units * self.meters_per_unit
+    }
+
+    /// Convert a distance in meters to world units.
+    pub fn meters_to_units(&self, meters: f32) -> f32 {
+        meters / self.meters_per_unit
+    }
+
+    /// The default attenuation of a point light, scaled to the current world units.
+    ///
+    /// [PointLightAttenuation::default] assumes that a world unit is a meter. This will return the same falloff, but measured in world units.
+    ///
+    /// [PointLightAttenuation::default]: ./struct.PointLightAttenuation.html
+    pub fn default_attenuation(&self) -> PointLightAttenuation {
+        let attenuation = PointLightAttenuation::default();
+        PointLightAttenuation {
+            constant: attenuation.constant,
+            linear: attenuation.linear * self.meters_per_unit,
+            quadratic: attenuation.quadratic * self.meters_per_unit * self.meters_per_unit,
+        }
+    }
 }
 
 /// The time state of the game. This contains all time-based values of the engine, like the `delta`
@@ -277,9 +2112,18 @@ pub struct TimeState {
     last_frame_instant: Instant,
     next_frame_instant: Instant,
     frame_times: VecDeque<Duration>,
+    fixed_delta: Option<Duration>,
+    /// The time that has passed since the last fixed update
+    fixed_accumulator: Duration,
+    time_scale: f32,
+    paused: bool,
 }
 
 const FRAME_TIME_COUNT: usize = 10;
+/// The maximum amount of fixed updates per frame. When the game can not keep up, e.g. after hitting a breakpoint, the time that is left is dropped instead of running more and more fixed updates every frame.
+const MAX_FIXED_UPDATES_PER_FRAME: u32 = 8;
+/// The fastest the time of the game can pass. Larger time scales, including infinity, are clamped to this, so the scaled delta time always fits in a `Duration`.
+const MAX_TIME_SCALE: f32 = 100.0;
 
 impl Default for TimeState {
     fn default() -> Self {
@@ -289,6 +2133,10 @@ impl Default for TimeState {
             last_frame_instant: instant,
             next_frame_instant: instant,
             frame_times: VecDeque::with_capacity(FRAME_TIME_COUNT),
+            fixed_delta: None,
+            fixed_accumulator: Duration::from_secs(0),
+            time_scale: 1.0,
+            paused: false,
         }
     }
 }
@@ -301,15 +2149,105 @@ impl TimeState {
         if self.frame_times.len() == FRAME_TIME_COUNT {
             self.frame_times.pop_front();
         }
-        self.frame_times.push_back(self.delta());
+        self.frame_times.push_back(self.real_delta());
+        self.accumulate_fixed(self.delta());
+    }
+
+    fn accumulate_fixed(&mut self, delta: Duration) {
+        if let Some(fixed_delta) = self.fixed_delta {
+            self.fixed_accumulator =
+                (self.fixed_accumulator + delta).min(fixed_delta * MAX_FIXED_UPDATES_PER_FRAME);
+        }
+    }
+
+    /// Returns `true` and advances the fixed time if a fixed update is due.
+    pub(crate) fn next_fixed_update(&mut self) -> bool {
+        match self.fixed_delta {
+            Some(fixed_delta) if self.fixed_accumulator >= fixed_delta => {
+                self.fixed_accumulator -= fixed_delta;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub(crate) fn set_fixed_delta(&mut self, fixed_delta: Option<Duration>) {
+        self.fixed_delta = fixed_delta;
+        self.fixed_accumulator = Duration::from_secs(0);
+    }
+
+    /// Get the time between two calls to [Game::fixed_update](../trait.Game.html#method.fixed_update). This is `None` when the fixed update is disabled, see [GameState::set_fixed_update_rate](../struct.GameState.html#method.set_fixed_update_rate).
+    pub fn fixed_delta(&self) -> Option<Duration> {
+        self.fixed_delta
+    }
+
+    /// Get how far the time is between the last fixed update and the next one, from `0.0` right after a fixed update to `1.0` right before the next one. This is `0.0` when the fixed update is disabled.
+    pub fn fixed_alpha(&self) -> f32 {
+        match self.fixed_delta {
+            Some(fixed_delta) => {
+                (self.fixed_accumulator.as_secs_f32() / fixed_delta.as_secs_f32()).min(1.0)
+            }
+            None => 0.0,
+        }
     }
 
     /// Get the delta time since the last frame. This is used for consistent updates throughout the
     /// game where different screen refresh rates won't make objects move faster or slower.
+    ///
+    /// This is scaled by the [time scale](#method.set_time_scale), and is zero while the game is [paused](#method.pause). The fixed updates, weather, day/night cycle and camera effects use this time as well.
     pub fn delta(&self) -> Duration {
+        if self.paused {
+            Duration::from_secs(0)
+        } else {
+            self.real_delta().mul_f32(self.time_scale)
+        }
+    }
+
+    /// Get the real delta time since the last frame, which is not affected by the [time scale](#method.set_time_scale) or by [pausing](#method.pause) the game. Use this for things that should keep moving in a pause menu, like GUI animations.
+    pub fn real_delta(&self) -> Duration {
         self.next_frame_instant - self.last_frame_instant
     }
 
+    /// Set how fast the time of the game passes, e.g. `0.5` for slow-motion. This defaults to `1.0`.
+    ///
+    /// Negative values and NaN are treated as `0.0`, and values above `100.0` are treated as `100.0`.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// state.time.set_time_scale(0.25);
+    /// ```
+    pub fn set_time_scale(&mut self, time_scale: f32) {
+        // `max` and `min` return the other value when one of them is NaN
+        self.time_scale = time_scale.max(0.0).min(MAX_TIME_SCALE);
+    }
+
+    /// Get how fast the time of the game passes, see [set_time_scale](#method.set_time_scale).
+    pub fn time_scale(&self) -> f32 {
+        self.time_scale
+    }
+
+    /// Pause the game. [delta](#method.delta) is zero and [Game::fixed_update](../trait.Game.html#method.fixed_update) is not called until the game is [resumed](#method.resume). [Game::update](../trait.Game.html#method.update) is still called every frame, so the game can show a pause menu.
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    /// Resume the game after it was [paused](#method.pause).
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    /// Returns `true` if the game is [paused](#method.pause).
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Change the running time of the game, e.g. when restoring a snapshot.
+    pub(crate) fn set_running(&mut self, running: Duration) {
+        let now = Instant::now();
+        self.start_instant = now.checked_sub(running).unwrap_or(now);
+    }
+
     /// Get the total running time of the game. This is the time since the [GameState] has been
     /// created.
     pub fn running(&self) -> Duration {
@@ -329,6 +2267,48 @@ impl TimeState {
     }
 }
 
+/// The title of the window. The title is only sent to the window when it changes.
+#[derive(Default)]
+struct WindowTitle {
+    /// The title that was set by the game. The title of the window is left alone until this is set.
+    title: Option<String>,
+    fps_suffix: bool,
+    fps: u32,
+    last_fps_refresh: Option<Instant>,
+    applied: Option<String>,
+}
+
+/// How often the fps in the title of the window is updated
+const TITLE_FPS_REFRESH_INTERVAL: Duration = Duration::from_millis(500);
+
+impl WindowTitle {
+    fn refresh(&mut self, window: &winit::window::Window, time: &TimeState) {
+        let title = match &self.title {
+            Some(title) => title,
+            None => return,
+        };
+        if self.fps_suffix
+            && self
+                .last_fps_refresh
+                .map(|i| i.elapsed() >= TITLE_FPS_REFRESH_INTERVAL)
+                .unwrap_or(true)
+        {
+            self.fps = time.fps().round() as u32;
+            self.last_fps_refresh = Some(Instant::now());
+        }
+
+        let title = if self.fps_suffix {
+            format!("{} ({} fps)", title, self.fps)
+        } else {
+            title.clone()
+        };
+        if self.applied.as_ref() != Some(&title) {
+            window.set_title(&title);
+            self.applied = Some(title);
+        }
+    }
+}
+
 #[test]
 fn test_timestate_never_resize() {
     let mut state = TimeState::default();
@@ -339,3 +2319,116 @@ fn test_timestate_never_resize() {
     }
     assert_eq!(FRAME_TIME_COUNT, state.frame_times.len());
 }
+
+/// Project a point in the world to a position in a window of the given size, with the origin in the top-left. Returns `None` if the point is behind the camera.
+fn project(
+    view_projection: Matrix4<f32>,
+    point: Vector3<f32>,
+    (width, height): (f32, f32),
+) -> Option<(f32, f32)> {
+    let clip = view_projection * Vector4::new(point.x, point.y, point.z, 1.0);
+    if clip.w <= 0.0 {
+        return None;
+    }
+    Some((
+        (clip.x / clip.w + 1.0) / 2.0 * width,
+        (1.0 - clip.y / clip.w) / 2.0 * height,
+    ))
+}
+
+#[test]
+fn test_fixed_updates() {
+    let mut time = TimeState::default();
+    time.accumulate_fixed(Duration::from_millis(100));
+    assert!(!time.next_fixed_update());
+
+    time.set_fixed_delta(Some(Duration::from_millis(20)));
+    time.accumulate_fixed(Duration::from_millis(50));
+    assert!(time.next_fixed_update());
+    assert!(time.next_fixed_update());
+    assert!(!time.next_fixed_update());
+    assert!((time.fixed_alpha() - 0.5).abs() < 0.001);
+
+    // A long frame is capped, so the fixed updates can catch up
+    time.accumulate_fixed(Duration::from_secs(10));
+    let updates = std::iter::from_fn(|| Some(time.next_fixed_update()))
+        .take_while(|due| *due)
+        .count();
+    assert_eq!(MAX_FIXED_UPDATES_PER_FRAME as usize, updates);
+}
+
+#[test]
+fn test_world_units() {
+    let mut units = WorldUnits::default();
+    units.set_meters_per_unit(0.01);
+    assert_eq!(0.01, units.meters_per_unit());
+    assert!((units.meters_to_units(1.0) - 100.0).abs() < 1e-3);
+
+    // These would make meters_to_units divide by zero or flip the world
+    for &invalid in &[0.0, -1.0, f32::INFINITY, f32::NAN] {
+        units.set_meters_per_unit(invalid);
+        assert_eq!(0.01, units.meters_per_unit());
+    }
+}
+
+#[test]
+fn test_time_scale() {
+    let mut time = TimeState::default();
+    time.next_frame_instant = time.last_frame_instant + Duration::from_secs(2);
+    assert_eq!(Duration::from_secs(2), time.delta());
+
+    time.set_time_scale(0.5);
+    assert_eq!(Duration::from_secs(1), time.delta());
+    assert_eq!(Duration::from_secs(2), time.real_delta());
+
+    time.pause();
+    assert!(time.is_paused());
+    assert_eq!(Duration::from_secs(0), time.delta());
+    assert_eq!(Duration::from_secs(2), time.real_delta());
+
+    time.resume();
+    time.set_time_scale(-1.0);
+    assert_eq!(Duration::from_secs(0), time.delta());
+
+    // These would make `delta` overflow the duration and panic
+    time.set_time_scale(f32::INFINITY);
+    assert_eq!(MAX_TIME_SCALE, time.time_scale());
+    assert_eq!(Duration::from_secs(200), time.delta());
+    time.set_time_scale(f32::MAX);
+    assert_eq!(MAX_TIME_SCALE, time.time_scale());
+    time.set_time_scale(f32::NAN);
+    assert_eq!(0.0, time.time_scale());
+    assert_eq!(Duration::from_secs(0), time.delta());
+}
+
+#[test]
+fn test_project_and_screen_ray() {
+    use cgmath::InnerSpace;
+
+    let view = Matrix4::look_at(
+        cgmath::Point3::new(0.0, 2.0, 5.0),
+        cgmath::Point3::new(0.0, 0.0, 0.0),
+        Vector3::unit_y(),
+    );
+    let view_projection = cgmath::perspective(Rad(1.0), 2.0, 0.1, 100.0) * view;
+    let point = Vector3::new(1.0, 0.5, -2.0);
+    let (x, y) = project(view_projection, point, (800.0, 400.0)).unwrap();
+
+    let ray = Ray::from_screen(
+        view_projection,
+        Vector2::new(x / 800.0 * 2.0 - 1.0, 1.0 - y / 400.0 * 2.0),
+    )
+    .unwrap();
+    let along = (point - ray.origin).dot(ray.direction);
+    assert!((ray.at(along) - point).magnitude() < 0.001);
+
+    // A point behind the camera is not on the screen
+    assert_eq!(
+        None,
+        project(
+            view_projection,
+            Vector3::new(0.0, 2.0, 10.0),
+            (800.0, 400.0)
+        )
+    );
+}
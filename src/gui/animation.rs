@@ -0,0 +1,221 @@
+use super::{element::GuiTexture, GuiElement};
+use crate::{
+    error::GuiError,
+    profiler::ProfilerStage,
+    render::texture::{self, Texture},
+    GameState,
+};
+use image::{error::ImageError, gif::GifDecoder, AnimationDecoder};
+use std::{
+    fs::File,
+    io::BufReader,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use vulkano::{
+    format::Format,
+    sync::{now, GpuFuture},
+};
+
+/// Browsers show frames of a GIF without a delay for this long, so GIFs are made with this in mind.
+const DEFAULT_GIF_FRAME_DELAY: Duration = Duration::from_millis(100);
+
+/// The frames of an animated GUI element. The visible frame is picked based on the running time of the game, see [TimeState::running](../state/struct.TimeState.html#method.running).
+pub struct AnimatedTexture {
+    frames: Vec<Texture>,
+    durations: Vec<Duration>,
+    looping: bool,
+    /// The running time of the game when the animation was created
+    start: Duration,
+}
+
+impl AnimatedTexture {
+    /// The frame that is visible at the given running time of the game.
+    pub fn frame_at(&self, running: Duration) -> &Texture {
+        let elapsed = running.checked_sub(self.start).unwrap_or_default();
+        &self.frames[frame_index(&self.durations, elapsed, self.looping)]
+    }
+}
+
+/// The index of the frame that is visible after `elapsed`. Animations that do not loop stay on their last frame.
+fn frame_index(durations: &[Duration], elapsed: Duration, looping: bool) -> usize {
+    let total: Duration = durations.iter().sum();
+    if total == Duration::from_secs(0) {
+        return 0;
+    }
+    let mut elapsed = if looping {
+        Duration::from_nanos((elapsed.as_nanos() % total.as_nanos()) as u64)
+    } else {
+        elapsed
+    };
+    for (index, duration) in durations.iter().enumerate() {
+        if elapsed < *duration {
+            return index;
+        }
+        elapsed -= *duration;
+    }
+    durations.len() - 1
+}
+
+enum AnimationSource<'b> {
+    Gif(&'b str),
+    Sequence(&'b [&'b str], Duration),
+}
+
+/// A struct that is used to create a [GuiElement] with an animated texture. This is created by calling `GameState::new_gui_element(..).with_animated_texture("..")` or `with_image_sequence`.
+pub struct GuiElementAnimationBuilder<'a, 'b> {
+    game_state: &'a mut GameState,
+    dimensions: (i32, i32, u32, u32),
+    source: AnimationSource<'b>,
+    looping: bool,
+}
+
+impl<'a, 'b> GuiElementAnimationBuilder<'a, 'b> {
+    pub(crate) fn gif(
+        game_state: &'a mut GameState,
+        dimensions: (i32, i32, u32, u32),
+        path: &'b str,
+    ) -> Self {
+        Self {
+            game_state,
+            dimensions,
+            source: AnimationSource::Gif(path),
+            looping: true,
+        }
+    }
+
+    pub(crate) fn sequence(
+        game_state: &'a mut GameState,
+        dimensions: (i32, i32, u32, u32),
+        paths: &'b [&'b str],
+        frame_duration: Duration,
+    ) -> Self {
+        Self {
+            game_state,
+            dimensions,
+            source: AnimationSource::Sequence(paths, frame_duration),
+            looping: true,
+        }
+    }
+
+    /// Start the animation over when it reaches the end. When this is `false`, the last frame stays visible. This defaults to `true`.
+    pub fn with_looping(mut self, looping: bool) -> Self {
+        self.looping = looping;
+        self
+    }
+
+    /// Finish building the element and return it.
+    /// The returned [GuiElement] has to be stored somewhere, as it will be removed from the engine when dropped.
+    /// Starting next frame, the returned GuiElement will be rendered on the screen.
+    pub fn build(self) -> Result<GuiElement, GuiError> {
+        let start = Instant::now();
+        let queue = self.game_state.transfer_queue.clone();
+        let mut frames = Vec::new();
+        let mut durations = Vec::new();
+        let mut futures: Vec<Box<dyn GpuFuture>> = Vec::new();
+
+        match self.source {
+            AnimationSource::Gif(path) => {
+                let resolved = self.game_state.assets.resolve(path);
+                let decoded = File::open(&resolved)
+                    .map_err(ImageError::IoError)
+                    .and_then(|file| GifDecoder::new(BufReader::new(file)))
+                    .and_then(|decoder| decoder.into_frames().collect_frames())
+                    .map_err(|inner| GuiError::CouldNotLoadTexture {
+                        path: path.to_owned(),
+                        inner,
+                    })?;
+                for frame in decoded {
+                    let (numerator, denominator) = frame.delay().numer_denom_ms();
+                    let delay = Duration::from_secs_f64(
+                        numerator as f64 / denominator.max(1) as f64 / 1000.0,
+                    );
+                    durations.push(if delay == Duration::from_secs(0) {
+                        DEFAULT_GIF_FRAME_DELAY
+                    } else {
+                        delay
+                    });
+                    let buffer = frame.into_buffer();
+                    let (width, height) = buffer.dimensions();
+                    let (texture, future) = texture::from_rgba(
+                        queue.clone(),
+                        width,
+                        height,
+                        buffer.into_raw(),
+                        Format::R8G8B8A8Srgb,
+                    )
+                    .map_err(|inner| GuiError::CouldNotUploadTexture { inner })?;
+                    frames.push(texture);
+                    futures.push(future);
+                }
+            }
+            AnimationSource::Sequence(paths, frame_duration) => {
+                for path in paths {
+                    let resolved = self.game_state.assets.resolve(path);
+                    let (texture, future) =
+                        texture::load(queue.clone(), &resolved).map_err(|inner| {
+                            GuiError::CouldNotLoadTexture {
+                                path: (*path).to_owned(),
+                                inner,
+                            }
+                        })?;
+                    frames.push(texture);
+                    durations.push(frame_duration);
+                    futures.push(future);
+                }
+            }
+        }
+        if frames.is_empty() {
+            return Err(GuiError::EmptyAnimation);
+        }
+
+        let device = queue.device().clone();
+        let texture_future = futures
+            .into_iter()
+            .fold(now(device).boxed(), |joined, future| {
+                joined.join(future).boxed()
+            });
+        let animation = AnimatedTexture {
+            frames,
+            durations,
+            looping: self.looping,
+            start: self.game_state.time.running(),
+        };
+        let (id, element_ref, element) = GuiElement::from_texture(
+            self.dimensions,
+            GuiTexture::Animated(Arc::new(animation)),
+            Some(texture_future),
+            self.game_state.internal_update_sender.clone(),
+            None,
+        );
+        self.game_state.gui_elements.insert(id, element_ref);
+        self.game_state
+            .profiler
+            .record_nested(ProfilerStage::AssetUpload, start.elapsed());
+
+        Ok(element)
+    }
+}
+
+#[test]
+fn test_animation_frame_index() {
+    let durations = [
+        Duration::from_millis(100),
+        Duration::from_millis(50),
+        Duration::from_millis(100),
+    ];
+    let at = |millis: u64, looping: bool| {
+        frame_index(&durations, Duration::from_millis(millis), looping)
+    };
+    assert_eq!(0, at(0, true));
+    assert_eq!(0, at(99, true));
+    assert_eq!(1, at(100, true));
+    assert_eq!(2, at(150, true));
+    assert_eq!(0, at(250, true));
+    assert_eq!(1, at(370, true));
+    assert_eq!(2, at(370, false));
+    assert_eq!(
+        0,
+        frame_index(&[Duration::from_secs(0)], Duration::from_secs(5), true)
+    );
+}
@@ -1,6 +1,16 @@
-use super::GuiElement;
-use crate::{error::GuiError, Font, GameState};
+use super::{
+    animation::GuiElementAnimationBuilder,
+    element::{CanvasConfig, GuiFilter, GuiTexture},
+    glyph_cache::GlyphCache,
+    sdf::{GuiElementSdfTextBuilder, SdfFont},
+    GuiElement,
+};
+use crate::{error::GuiError, profiler::ProfilerStage, render::texture, Font, GameState};
 use image::Pixel;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 /// A struct that is used to create a [GuiElement]. It is constructed by calling `GameState::add_new_element()`
 ///
@@ -27,9 +37,84 @@ impl<'a> GuiElementBuilder<'a> {
             game_state: self.game_state,
             dimensions: self.dimensions,
             texture_path,
+            tint: crate::color::WHITE,
+            uv_region: (0.0, 0.0, 1.0, 1.0),
+            flip: (false, false),
+            filter: GuiFilter::Linear,
         }
     }
 
+    /// Create a gui element with an animated GIF. The frames are shown for the delays that are stored in the GIF, see [GuiElementAnimationBuilder] for more options.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// let spinner: GuiElement = state
+    ///     .new_gui_element((10, 10, 64, 64))
+    ///     .with_animated_texture("assets/spinner.gif")
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    ///
+    /// [GuiElementAnimationBuilder]: ./state/struct.GuiElementAnimationBuilder.html
+    pub fn with_animated_texture<'b>(
+        self,
+        gif_path: &'b str,
+    ) -> GuiElementAnimationBuilder<'a, 'b> {
+        GuiElementAnimationBuilder::gif(self.game_state, self.dimensions, gif_path)
+    }
+
+    /// Create a gui element that shows a sequence of images, every image for `frame_duration`.
+    pub fn with_image_sequence<'b>(
+        self,
+        texture_paths: &'b [&'b str],
+        frame_duration: Duration,
+    ) -> GuiElementAnimationBuilder<'a, 'b> {
+        GuiElementAnimationBuilder::sequence(
+            self.game_state,
+            self.dimensions,
+            texture_paths,
+            frame_duration,
+        )
+    }
+
+    /// Create a gui element with a text that is rendered with a signed distance field. The text stays crisp when the element is scaled, and can have an outline and a shadow, see [GuiElementSdfTextBuilder].
+    ///
+    /// The font size is in pixels at the given dimensions of the element. When the element is resized, the text is scaled with it.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # use crystal_engine::state::GlyphRange;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// let font = state.load_font("assets/roboto.ttf").unwrap();
+    /// let font = state.create_sdf_font(&font, &[GlyphRange::Ascii]).unwrap();
+    /// let title: GuiElement = state
+    ///     .new_gui_element((0, 500, 800, 100))
+    ///     .sdf_text(&font, 64.0, "Crystal", color::WHITE)
+    ///     .with_outline(2.0, color::BLACK)
+    ///     .with_shadow((3.0, 3.0), [0, 0, 0, 128])
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    ///
+    /// [GuiElementSdfTextBuilder]: ./state/struct.GuiElementSdfTextBuilder.html
+    pub fn sdf_text(
+        self,
+        font: &SdfFont,
+        font_size: f32,
+        text: impl std::fmt::Display,
+        color: [u8; 4],
+    ) -> GuiElementSdfTextBuilder<'a> {
+        GuiElementSdfTextBuilder::new(
+            self.game_state,
+            self.dimensions,
+            font,
+            font_size,
+            text.to_string(),
+            color,
+        )
+    }
+
     /// Create a gui element with a custom canvas. The returned [GuiElementCanvasBuilder] can be further changed to include background color, text and borders.
     ///
     /// The element will be completely transparent by default. Make sure to update e.g. the background color.
@@ -40,37 +125,97 @@ impl<'a> GuiElementBuilder<'a> {
             color: crate::color::TRANSPARENT,
             text: None,
             border: None,
+            double_buffered: false,
         }
     }
 }
 
-/// A struct that is used to create a [GuiElement] with a texture. This is created by calling `GameState::create_gui_element().texture("..")`. Currently nothing can be manipulated in this struct.
+/// A struct that is used to create a [GuiElement] with a texture. This is created by calling `GameState::new_gui_element(..).with_texture("..")`.
+///
+/// The texture can be tinted, flipped, and cut out of a texture atlas. These settings can be changed later with [GuiElement::modify](../struct.GuiElement.html#method.modify).
+///
+/// ```no_run
+/// # use crystal_engine::*;
+/// # use crystal_engine::state::GuiFilter;
+/// # let mut state: GameState = unsafe { std::mem::zeroed() };
+/// // The third icon of the top row of an atlas with 4x4 icons
+/// let icon: GuiElement = state
+///     .new_gui_element((10, 10, 64, 64))
+///     .with_texture("assets/icons.png")
+///     .with_uv_region(0.5, 0.0, 0.25, 0.25)
+///     .with_tint([255, 200, 200, 255])
+///     .with_filter(GuiFilter::Nearest)
+///     .build()
+///     .unwrap();
+/// ```
 pub struct GuiElementTextureBuilder<'a, 'b> {
     game_state: &'a mut GameState,
     dimensions: (i32, i32, u32, u32),
     texture_path: &'b str,
+    tint: [u8; 4],
+    uv_region: (f32, f32, f32, f32),
+    flip: (bool, bool),
+    filter: GuiFilter,
 }
 impl<'a, 'b> GuiElementTextureBuilder<'a, 'b> {
+    /// Multiply the texture with a color. The default is [WHITE](../color/constant.WHITE.html), which shows the texture as it is.
+    pub fn with_tint(mut self, tint: [u8; 4]) -> Self {
+        self.tint = tint;
+        self
+    }
+
+    /// Only show a part of the texture, e.g. a single image of a texture atlas. The region is in texture coordinates, where the top-left of the texture is `(0.0, 0.0)` and the bottom-right is `(1.0, 1.0)`.
+    pub fn with_uv_region(mut self, x: f32, y: f32, width: f32, height: f32) -> Self {
+        self.uv_region = (x, y, width, height);
+        self
+    }
+
+    /// Mirror the texture from left to right, from top to bottom, or both.
+    pub fn with_flip(mut self, horizontal: bool, vertical: bool) -> Self {
+        self.flip = (horizontal, vertical);
+        self
+    }
+
+    /// Set the way the texture is sampled when it is drawn at a different size than the image. Use [GuiFilter::Nearest] for pixel art. The default is [GuiFilter::Linear].
+    ///
+    /// [GuiFilter::Nearest]: ../state/enum.GuiFilter.html#variant.Nearest
+    /// [GuiFilter::Linear]: ../state/enum.GuiFilter.html#variant.Linear
+    pub fn with_filter(mut self, filter: GuiFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
     /// Finish building the element and return it.
     /// The returned [GuiElement] has to be stored somewhere, as it will be removed from the engine when dropped.
     /// Starting next frame, the returned GuiElement will be rendered on the screen.
     pub fn build(self) -> Result<GuiElement, GuiError> {
-        let queue = self.game_state.queue.clone();
-        let image = image::open(self.texture_path)
-            .map_err(|e| GuiError::CouldNotLoadTexture {
+        let start = Instant::now();
+        let queue = self.game_state.transfer_queue.clone();
+        let path = self.game_state.assets.resolve(self.texture_path);
+        let (texture, texture_future) =
+            texture::load(queue, &path).map_err(|e| GuiError::CouldNotLoadTexture {
                 path: self.texture_path.to_owned(),
                 inner: e,
-            })?
-            .to_rgba();
+            })?;
 
-        let (id, element_ref, element) = GuiElement::new(
-            queue,
+        let (id, element_ref, element) = GuiElement::from_texture(
             self.dimensions,
-            (image.width(), image.height(), image.into_raw()),
+            GuiTexture::Immutable(texture),
+            Some(texture_future),
             self.game_state.internal_update_sender.clone(),
             None,
-        )?;
+        );
+        element.modify(|data| {
+            data.tint = self.tint;
+            data.uv_region = self.uv_region;
+            data.flip_horizontal = self.flip.0;
+            data.flip_vertical = self.flip.1;
+            data.filter = self.filter;
+        });
         self.game_state.gui_elements.insert(id, element_ref);
+        self.game_state
+            .profiler
+            .record_nested(ProfilerStage::AssetUpload, start.elapsed());
 
         Ok(element)
     }
@@ -83,6 +228,7 @@ pub struct GuiElementCanvasBuilder<'a> {
     color: [u8; 4],
     text: Option<TextRequest>,
     border: Option<(u16, [u8; 4])>,
+    double_buffered: bool,
 }
 
 #[derive(Clone)]
@@ -91,6 +237,11 @@ pub(crate) struct TextRequest {
     pub font_size: u16,
     pub text: String,
     pub color: [u8; 4],
+    /// If `true`, the text contains markup, see [GuiElementCanvasBuilder::with_rich_text]
+    pub markup: bool,
+    pub horizontal_alignment: HorizontalAlignment,
+    pub vertical_alignment: VerticalAlignment,
+    pub wrap: bool,
 }
 
 impl<'a> GuiElementCanvasBuilder<'a> {
@@ -115,9 +266,14 @@ impl<'a> GuiElementCanvasBuilder<'a> {
         self
     }
 
-    /// Add a text to the GUI element. This text will be rendered in the center of the element, and does not respect newlines.
+    /// Add a text to the GUI element. By default this text is rendered in the center of the element, and wrapped when a line is wider than the element. Newlines start a new line.
+    ///
+    /// The alignment and wrapping can be changed with [with_text_alignment] and [with_text_wrapping].
     ///
     /// An instance of [Font](rusttype::Font) can be obtained by calling `GameState::load_font`.
+    ///
+    /// [with_text_alignment]: #method.with_text_alignment
+    /// [with_text_wrapping]: #method.with_text_wrapping
     pub fn with_text(
         mut self,
         font: Font,
@@ -130,10 +286,82 @@ impl<'a> GuiElementCanvasBuilder<'a> {
             font_size,
             text: text.to_string(),
             color,
+            markup: false,
+            horizontal_alignment: HorizontalAlignment::default(),
+            vertical_alignment: VerticalAlignment::default(),
+            wrap: true,
         });
         self
     }
 
+    /// Add a text with markup to the GUI element. This works the same as [with_text], but parts of the text can have a different color or size:
+    /// - `[color=ff0000]red[/color]` renders a text in a different color. The color is written as `rrggbb` or `rrggbbaa`.
+    /// - `[size=32]large[/size]` renders a text in a different font size.
+    /// - `[[` renders a single `[`.
+    ///
+    /// Tags can be nested, and have to be closed in the reverse order they are opened. Tags that can not be parsed are rendered as text.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # use crystal_engine::state::{HorizontalAlignment, VerticalAlignment};
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// # let font = state.load_font("").unwrap();
+    /// let element: GuiElement = state
+    ///     .new_gui_element((10, 10, 300, 100))
+    ///     .canvas()
+    ///     .with_rich_text(
+    ///         font,
+    ///         16,
+    ///         "You found [color=ffd700][size=24]a golden key[/size][/color]!\nUse it wisely.",
+    ///         color::WHITE,
+    ///     )
+    ///     .with_text_alignment(HorizontalAlignment::Left, VerticalAlignment::Top)
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    ///
+    /// [with_text]: #method.with_text
+    pub fn with_rich_text(
+        self,
+        font: Font,
+        font_size: u16,
+        markup: impl std::fmt::Display,
+        color: [u8; 4],
+    ) -> Self {
+        let mut builder = self.with_text(font, font_size, markup, color);
+        // `with_text` was just called, so this should never fail
+        builder.text.as_mut().unwrap().markup = true;
+        builder
+    }
+
+    /// Change where the text is placed in the element. This has to be called *after* `with_text` is called, or this method will panic.
+    ///
+    /// When the element has a border, the text is aligned to the inside of the border.
+    pub fn with_text_alignment(
+        mut self,
+        horizontal: HorizontalAlignment,
+        vertical: VerticalAlignment,
+    ) -> Self {
+        let text = self.text.as_mut().unwrap();
+        text.horizontal_alignment = horizontal;
+        text.vertical_alignment = vertical;
+        self
+    }
+
+    /// Enable or disable wrapping the text when a line is wider than the element. This has to be called *after* `with_text` is called, or this method will panic.
+    ///
+    /// Lines are only wrapped between words. A word that is wider than the element is placed on its own line. This defaults to `true`.
+    pub fn with_text_wrapping(mut self, wrap: bool) -> Self {
+        self.text.as_mut().unwrap().wrap = wrap;
+        self
+    }
+
+    /// Set all the text settings at once. This is used to restore the settings of an existing element.
+    pub(crate) fn with_text_request(mut self, request: TextRequest) -> Self {
+        self.text = Some(request);
+        self
+    }
+
     /// Update the text of an element. This has to be called *after* `with_text` is called, or this method will panic. This is mostly useful when calling `GuiElement::update_canvas`.
     pub fn with_text_content(mut self, text: impl std::fmt::Display) -> Self {
         self.text.as_mut().unwrap().text = text.to_string();
@@ -146,12 +374,74 @@ impl<'a> GuiElementCanvasBuilder<'a> {
         self
     }
 
+    /// Keep two images for this element, so [GuiElement::update_canvas] can write into one image while the other one is being rendered.
+    ///
+    /// Without double buffering, every update creates a new image, which can cause hitches when a canvas is updated every frame. Double buffering uses twice the memory.
+    ///
+    /// When the dimensions of the element change, new images are created.
+    ///
+    /// [GuiElement::update_canvas]: ../struct.GuiElement.html#method.update_canvas
+    pub fn with_double_buffering(mut self, double_buffered: bool) -> Self {
+        self.double_buffered = double_buffered;
+        self
+    }
+
     /// Finish building the element and return it.
     /// The returned [GuiElement] has to be stored somewhere, as it will be removed from the engine when dropped.
     /// Starting next frame, the returned GuiElement will be rendered on the screen.
-    pub fn build(self) -> Result<GuiElement, GuiError> {
-        let queue = self.game_state.queue.clone();
+    pub fn build(mut self) -> Result<GuiElement, GuiError> {
+        let image = self.rasterize();
+        self.create_element(image)
+    }
 
+    /// Update an existing element. If both the element and this builder are double buffered, and the size did not change, the new image is written into the existing texture.
+    pub(crate) fn update(mut self, element: &mut GuiElement) -> Result<(), GuiError> {
+        let image = self.rasterize();
+        if self.double_buffered {
+            if let Some(GuiTexture::DoubleBuffered(texture)) = self
+                .game_state
+                .gui_elements
+                .get(&element.id)
+                .map(|e| &e.texture)
+            {
+                let mut texture = texture.write();
+                if texture.dimensions() == image.dimensions() {
+                    texture.write(image.into_raw())?;
+                    element.data.write().dimensions = self.dimensions;
+                    element.canvas_config = Some(self.canvas_config());
+                    return Ok(());
+                }
+            }
+        }
+        let container = element.data.read().container.clone();
+        *element = self.create_element(image)?;
+        element.data.write().container = container;
+        Ok(())
+    }
+
+    fn canvas_config(&self) -> CanvasConfig {
+        CanvasConfig {
+            background: self.color,
+            border: self.border,
+            text: self.text.clone(),
+            double_buffered: self.double_buffered,
+        }
+    }
+
+    fn create_element(self, image: image::RgbaImage) -> Result<GuiElement, GuiError> {
+        let (id, element_ref, element) = GuiElement::new(
+            self.game_state.queue.clone(),
+            self.dimensions,
+            (image.width(), image.height(), image.into_raw()),
+            self.game_state.internal_update_sender.clone(),
+            Some(self.canvas_config()),
+        )?;
+        self.game_state.gui_elements.insert(id, element_ref);
+
+        Ok(element)
+    }
+
+    fn rasterize(&mut self) -> image::RgbaImage {
         let width = self.dimensions.2;
         let height = self.dimensions.3;
 
@@ -178,93 +468,403 @@ impl<'a> GuiElementCanvasBuilder<'a> {
         }
 
         if let Some(request) = &self.text {
-            let scale = rusttype::Scale::uniform(request.font_size as f32);
-            let v_metrics = request.font.v_metrics(scale);
-            let glyphs: Vec<_> = request
-                .font
-                .layout(
-                    request.text.trim(),
-                    scale,
-                    rusttype::point(0.0, v_metrics.ascent),
-                )
-                .collect();
-
-            if !glyphs.is_empty() {
-                let total_bounding_box = calc_text_bounding_box(glyphs.iter());
-
-                let text_width = total_bounding_box.max.x - total_bounding_box.min.x;
-                let text_height = total_bounding_box.max.y - total_bounding_box.min.y;
-                let position = (
-                    (width as i32 - text_width) / 2,
-                    (height as i32 - text_height) / 2,
-                );
-                let color = request.color;
-
-                for glyph in glyphs {
-                    if let Some(bounding_box) = glyph.pixel_bounding_box() {
-                        glyph.draw(|x, y, v| {
-                            let x = position.0 + x as i32 + bounding_box.min.x;
-                            let y = position.1 + y as i32 + bounding_box.min.y;
-                            if x < 0
-                                || y < 0
-                                || x >= image.width() as i32
-                                || y >= image.height() as i32
-                            {
-                                return;
-                            }
-                            image.get_pixel_mut(x as u32, y as u32).blend(&image::Rgba([
-                                color[0],
-                                color[1],
-                                color[2],
-                                (v * 255.) as u8,
-                            ]));
+            let text = request.text.trim();
+            let spans = if request.markup {
+                parse_markup(text, request.color, request.font_size)
+            } else {
+                vec![TextSpan {
+                    text: text.to_owned(),
+                    color: request.color,
+                    font_size: request.font_size,
+                }]
+            };
+            let border = self.border.map(|(width, _)| width as i32).unwrap_or(0);
+            let area = (
+                border,
+                border,
+                (width as i32 - border * 2).max(0),
+                (height as i32 - border * 2).max(0),
+            );
+            let max_width = if request.wrap {
+                Some(area.2 as f32)
+            } else {
+                None
+            };
+            draw_text(
+                &mut image,
+                &mut self.game_state.glyph_cache,
+                request,
+                &spans,
+                area,
+                max_width,
+            );
+        }
+
+        image
+    }
+}
+
+/// Where the text of a canvas is placed horizontally. See [GuiElementCanvasBuilder::with_text_alignment].
+///
+/// [GuiElementCanvasBuilder::with_text_alignment]: ./struct.GuiElementCanvasBuilder.html#method.with_text_alignment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HorizontalAlignment {
+    /// Every line starts at the left side of the element
+    Left,
+    /// Every line is centered in the element
+    Center,
+    /// Every line ends at the right side of the element
+    Right,
+}
+
+impl Default for HorizontalAlignment {
+    fn default() -> Self {
+        HorizontalAlignment::Center
+    }
+}
+
+/// Where the text of a canvas is placed vertically. See [GuiElementCanvasBuilder::with_text_alignment].
+///
+/// [GuiElementCanvasBuilder::with_text_alignment]: ./struct.GuiElementCanvasBuilder.html#method.with_text_alignment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerticalAlignment {
+    /// The first line starts at the top of the element
+    Top,
+    /// The lines are centered in the element
+    Center,
+    /// The last line ends at the bottom of the element
+    Bottom,
+}
+
+impl Default for VerticalAlignment {
+    fn default() -> Self {
+        VerticalAlignment::Center
+    }
+}
+
+/// A part of a text that is rendered with the same color and font size.
+#[derive(Debug, Clone, PartialEq)]
+struct TextSpan {
+    text: String,
+    color: [u8; 4],
+    font_size: u16,
+}
+
+/// Split a text with markup in spans. See [GuiElementCanvasBuilder::with_rich_text] for the supported tags.
+///
+/// Tags that can not be parsed are rendered as text.
+fn parse_markup(markup: &str, color: [u8; 4], font_size: u16) -> Vec<TextSpan> {
+    let mut spans = Vec::new();
+    let mut styles = vec![(color, font_size)];
+    let mut current = String::new();
+    let mut rest = markup;
+
+    while let Some(index) = rest.find('[') {
+        current.push_str(&rest[..index]);
+        rest = &rest[index..];
+        if let Some(escaped) = rest.strip_prefix("[[") {
+            current.push('[');
+            rest = escaped;
+            continue;
+        }
+        let end = match rest.find(']') {
+            Some(end) => end,
+            None => break,
+        };
+        // The stack always contains the base style, so this should never fail
+        let &(color, font_size) = styles.last().unwrap();
+        let tag = &rest[1..end];
+        let style = if tag == "/color" || tag == "/size" {
+            if styles.len() > 1 {
+                Some(None)
+            } else {
+                None
+            }
+        } else if let Some(value) = tag.strip_prefix("color=") {
+            parse_color(value).map(|color| Some((color, font_size)))
+        } else if let Some(value) = tag.strip_prefix("size=") {
+            value
+                .parse()
+                .ok()
+                .filter(|&size| size > 0)
+                .map(|size| Some((color, size)))
+        } else {
+            None
+        };
+        let style = match style {
+            Some(style) => style,
+            None => {
+                current.push_str(&rest[..=end]);
+                rest = &rest[end + 1..];
+                continue;
+            }
+        };
+
+        if !current.is_empty() {
+            spans.push(TextSpan {
+                text: std::mem::replace(&mut current, String::new()),
+                color,
+                font_size,
+            });
+        }
+        match style {
+            Some(style) => styles.push(style),
+            None => {
+                styles.pop();
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    current.push_str(rest);
+    if !current.is_empty() {
+        // The stack always contains the base style, so this should never fail
+        let &(color, font_size) = styles.last().unwrap();
+        spans.push(TextSpan {
+            text: current,
+            color,
+            font_size,
+        });
+    }
+    spans
+}
+
+/// Parse a color in the form of `rrggbb` or `rrggbbaa`, optionally starting with a `#`.
+fn parse_color(value: &str) -> Option<[u8; 4]> {
+    let value = value.trim_start_matches('#');
+    if (value.len() != 6 && value.len() != 8) || !value.is_ascii() {
+        return None;
+    }
+    let mut color = [255; 4];
+    for (index, component) in color.iter_mut().enumerate().take(value.len() / 2) {
+        *component = u8::from_str_radix(&value[index * 2..index * 2 + 2], 16).ok()?;
+    }
+    Some(color)
+}
+
+/// A character of a text, with the glyph that is used to render it.
+struct TextChar {
+    character: char,
+    /// The index of the [TextSpan] this character belongs to
+    span: usize,
+    glyph: rusttype::GlyphId,
+    /// The kerning between the previous character and this one
+    kerning: f32,
+    advance: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TokenKind {
+    Word,
+    Space,
+    Newline,
+}
+
+/// A range of characters that is never split over multiple lines.
+#[derive(Debug, Clone, PartialEq)]
+struct TextToken {
+    kind: TokenKind,
+    chars: std::ops::Range<usize>,
+    width: f32,
+}
+
+/// A line of text, as a range of tokens.
+#[derive(Debug, Clone, PartialEq)]
+struct TextLine {
+    tokens: std::ops::Range<usize>,
+    /// The width of the line, without the spaces at the end
+    width: f32,
+}
+
+/// Split the tokens in lines. Lines end at every newline, and before a word that would make the line wider than `max_width`.
+///
+/// A word that is wider than `max_width` by itself is placed on its own line.
+fn break_lines(tokens: &[TextToken], max_width: Option<f32>) -> Vec<TextLine> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    let mut width = 0.0;
+    let mut line_width = 0.0;
+    let mut has_word = false;
+
+    for (index, token) in tokens.iter().enumerate() {
+        match token.kind {
+            TokenKind::Newline => {
+                lines.push(TextLine {
+                    tokens: start..index,
+                    width: line_width,
+                });
+                start = index + 1;
+                width = 0.0;
+                line_width = 0.0;
+                has_word = false;
+            }
+            TokenKind::Space => width += token.width,
+            TokenKind::Word => {
+                if let Some(max_width) = max_width {
+                    if has_word && width + token.width > max_width {
+                        lines.push(TextLine {
+                            tokens: start..index,
+                            width: line_width,
                         });
+                        start = index;
+                        width = 0.0;
                     }
                 }
+                width += token.width;
+                line_width = width;
+                has_word = true;
             }
         }
+    }
+    lines.push(TextLine {
+        tokens: start..tokens.len(),
+        width: line_width,
+    });
+    lines
+}
 
-        let (id, element_ref, element) = GuiElement::new(
-            queue,
-            self.dimensions,
-            (width, height, image.into_raw()),
-            self.game_state.internal_update_sender.clone(),
-            Some(super::element::CanvasConfig {
-                background: self.color,
-                border: self.border,
-                text: self.text,
-            }),
-        )?;
-        self.game_state.gui_elements.insert(id, element_ref);
+/// Lay out the spans in the given `(x, y, width, height)` area of the image, and draw them.
+fn draw_text(
+    image: &mut image::RgbaImage,
+    glyph_cache: &mut GlyphCache,
+    request: &TextRequest,
+    spans: &[TextSpan],
+    area: (i32, i32, i32, i32),
+    max_width: Option<f32>,
+) {
+    let font = &request.font;
+    let metrics: Vec<_> = spans
+        .iter()
+        .map(|span| font.v_metrics(rusttype::Scale::uniform(span.font_size as f32)))
+        .collect();
 
-        Ok(element)
+    let mut chars: Vec<TextChar> = Vec::new();
+    for (span_index, span) in spans.iter().enumerate() {
+        let scale = rusttype::Scale::uniform(span.font_size as f32);
+        let mut previous = None;
+        for character in span.text.chars() {
+            let glyph = font.glyph(character).scaled(scale);
+            let id = glyph.id();
+            let kerning = previous
+                .map(|previous| font.pair_kerning(scale, previous, id))
+                .unwrap_or(0.0);
+            chars.push(TextChar {
+                character,
+                span: span_index,
+                glyph: id,
+                kerning,
+                advance: glyph.h_metrics().advance_width,
+            });
+            previous = Some(id);
+        }
     }
-}
 
-fn calc_text_bounding_box<'a>(
-    glyphs: impl Iterator<Item = &'a rusttype::PositionedGlyph<'a>>,
-) -> rusttype::Rect<i32> {
-    let mut total_bounding_box = rusttype::Rect {
-        min: rusttype::Point {
-            x: i32::max_value(),
-            y: i32::max_value(),
-        },
-        max: rusttype::Point {
-            x: i32::min_value(),
-            y: i32::min_value(),
-        },
-    };
+    let mut tokens: Vec<TextToken> = Vec::new();
+    for (index, c) in chars.iter().enumerate() {
+        let kind = match c.character {
+            '\n' => TokenKind::Newline,
+            c if c.is_whitespace() => TokenKind::Space,
+            _ => TokenKind::Word,
+        };
+        let width = if kind == TokenKind::Newline {
+            0.0
+        } else {
+            c.kerning + c.advance
+        };
+        match tokens.last_mut() {
+            Some(token) if token.kind == kind && kind != TokenKind::Newline => {
+                token.chars.end = index + 1;
+                token.width += width;
+            }
+            _ => tokens.push(TextToken {
+                kind,
+                chars: index..index + 1,
+                width,
+            }),
+        }
+    }
+
+    let lines = break_lines(&tokens, max_width);
+    let base_metrics = font.v_metrics(rusttype::Scale::uniform(request.font_size as f32));
+    // The ascent, descent and line gap of every line, based on the largest font size in that line
+    let line_metrics: Vec<(f32, f32, f32)> = lines
+        .iter()
+        .map(|line| {
+            let used_spans = tokens[line.tokens.clone()]
+                .iter()
+                .flat_map(|token| chars[token.chars.clone()].iter())
+                .map(|c| &metrics[c.span]);
+            let mut line_metrics = None;
+            for metrics in used_spans {
+                let (ascent, descent, line_gap) =
+                    line_metrics.get_or_insert((metrics.ascent, metrics.descent, metrics.line_gap));
+                *ascent = metrics.ascent.max(*ascent);
+                *descent = metrics.descent.min(*descent);
+                *line_gap = metrics.line_gap.max(*line_gap);
+            }
+            line_metrics.unwrap_or((
+                base_metrics.ascent,
+                base_metrics.descent,
+                base_metrics.line_gap,
+            ))
+        })
+        .collect();
 
-    for glyph in glyphs {
-        if let Some(bounding_box) = glyph.pixel_bounding_box() {
-            total_bounding_box.min.x = total_bounding_box.min.x.min(bounding_box.min.x);
-            total_bounding_box.min.y = total_bounding_box.min.y.min(bounding_box.min.y);
+    let text_height: f32 = line_metrics
+        .iter()
+        .map(|(ascent, descent, _)| ascent - descent)
+        .sum::<f32>()
+        + line_metrics
+            .iter()
+            .skip(1)
+            .map(|(_, _, line_gap)| line_gap)
+            .sum::<f32>();
+    let (x, y, width, height) = area;
+    let mut top = y as f32
+        + match request.vertical_alignment {
+            VerticalAlignment::Top => 0.0,
+            VerticalAlignment::Center => (height as f32 - text_height) / 2.0,
+            VerticalAlignment::Bottom => height as f32 - text_height,
+        };
 
-            total_bounding_box.max.x = total_bounding_box.max.x.max(bounding_box.max.x);
-            total_bounding_box.max.y = total_bounding_box.min.y.max(bounding_box.max.y);
+    for (line, (ascent, descent, line_gap)) in lines.iter().zip(line_metrics) {
+        let baseline = (top + ascent).round() as i32;
+        let mut position = x as f32
+            + match request.horizontal_alignment {
+                HorizontalAlignment::Left => 0.0,
+                HorizontalAlignment::Center => (width as f32 - line.width) / 2.0,
+                HorizontalAlignment::Right => width as f32 - line.width,
+            };
+        let line_chars = tokens[line.tokens.clone()]
+            .iter()
+            .flat_map(|token| chars[token.chars.clone()].iter());
+        for (index, c) in line_chars.enumerate() {
+            // Kerning with the last character of the previous line does not make sense
+            if index > 0 {
+                position += c.kerning;
+            }
+            if !c.character.is_whitespace() {
+                let span = &spans[c.span];
+                let glyph = glyph_cache.get(font, span.font_size, c.glyph);
+                glyph.draw((position.round() as i32, baseline), |x, y, v| {
+                    blend_pixel(image, x, y, span.color, v)
+                });
+            }
+            position += c.advance;
         }
+        top += ascent - descent + line_gap;
+    }
+}
+
+fn blend_pixel(image: &mut image::RgbaImage, x: i32, y: i32, color: [u8; 4], coverage: f32) {
+    if x < 0 || y < 0 || x >= image.width() as i32 || y >= image.height() as i32 {
+        return;
     }
-    total_bounding_box
+    image.get_pixel_mut(x as u32, y as u32).blend(&image::Rgba([
+        color[0],
+        color[1],
+        color[2],
+        (coverage * color[3] as f32) as u8,
+    ]));
 }
 
 fn is_border(
@@ -286,3 +886,71 @@ fn is_border(
     }
     None
 }
+
+#[test]
+fn test_parse_markup() {
+    let span = |text: &str, color: [u8; 4], font_size: u16| TextSpan {
+        text: text.to_owned(),
+        color,
+        font_size,
+    };
+    let white = [255; 4];
+    assert_eq!(
+        vec![
+            span("a ", white, 16),
+            span("red", [255, 0, 0, 255], 16),
+            span(" and ", white, 16),
+            span("big", [0, 0, 255, 128], 32),
+            span(" [text] [b]", white, 16),
+        ],
+        parse_markup(
+            "a [color=ff0000]red[/color] and [size=32][color=#0000ff80]big[/color][/size] [[text] [b]",
+            white,
+            16
+        )
+    );
+    // Closing tags without an opened tag and invalid values are rendered as text
+    assert_eq!(
+        vec![span("[/color][size=0]x", white, 16)],
+        parse_markup("[/color][size=0]x", white, 16)
+    );
+}
+
+#[test]
+fn test_break_lines() {
+    let mut chars = 0;
+    let mut token = |kind: TokenKind, width: f32| {
+        chars += 1;
+        TextToken {
+            kind,
+            chars: chars - 1..chars,
+            width,
+        }
+    };
+    let tokens = vec![
+        token(TokenKind::Word, 40.0),
+        token(TokenKind::Space, 5.0),
+        token(TokenKind::Word, 40.0),
+        token(TokenKind::Space, 5.0),
+        token(TokenKind::Word, 120.0),
+        token(TokenKind::Newline, 0.0),
+        token(TokenKind::Newline, 0.0),
+        token(TokenKind::Space, 5.0),
+        token(TokenKind::Word, 10.0),
+    ];
+    let line = |tokens: std::ops::Range<usize>, width: f32| TextLine { tokens, width };
+
+    assert_eq!(
+        vec![line(0..5, 210.0), line(6..6, 0.0), line(7..9, 15.0)],
+        break_lines(&tokens, None)
+    );
+    assert_eq!(
+        vec![
+            line(0..4, 85.0),
+            line(4..5, 120.0),
+            line(6..6, 0.0),
+            line(7..9, 15.0)
+        ],
+        break_lines(&tokens, Some(100.0))
+    );
+}
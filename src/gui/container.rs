@@ -0,0 +1,130 @@
+use parking_lot::RwLock;
+use std::sync::Arc;
+
+/// An area on the screen that clips GUI elements to its bounds, and scrolls their content. This can be used for lists, chat boxes and inventories that are bigger than the space they have on the screen.
+///
+/// Elements are placed in a container by setting [GuiElementData::container]. The dimensions of these elements are relative to the bottom-left corner of the content of the container, and anything outside of the container is not rendered.
+///
+/// This reference can be [Clone]d to place multiple elements in the same container.
+///
+/// ```no_run
+/// # use crystal_engine::*;
+/// # let mut state: GameState = unsafe { std::mem::zeroed() };
+/// # let font = state.load_font("").unwrap();
+/// let chat = GuiContainer::new((20, 20, 400, 200));
+/// let mut lines = Vec::new();
+/// for (i, message) in ["Hello", "How are you?", "Fine"].iter().enumerate() {
+///     // The newest message is at the bottom of the content
+///     let line: GuiElement = state
+///         .new_gui_element((0, 30 * (2 - i as i32), 400, 30))
+///         .canvas()
+///         .with_text(font.clone(), 24, message, color::WHITE)
+///         .build()
+///         .unwrap();
+///     line.modify(|data| data.container = Some(chat.clone()));
+///     lines.push(line);
+/// }
+/// chat.modify(|data| data.content_size = Some((400, 30 * lines.len() as u32)));
+///
+/// // Scroll up to older messages, e.g. when the mouse wheel is used
+/// chat.scroll_by(0, 30);
+/// ```
+///
+/// [GuiElementData::container]: ./state/struct.GuiElementData.html#structfield.container
+#[derive(Clone)]
+pub struct GuiContainer {
+    pub(crate) data: Arc<RwLock<GuiContainerData>>,
+}
+
+/// The data of a [GuiContainer]. This can be used to move, resize or scroll the container.
+///
+/// [GuiContainer]: ../struct.GuiContainer.html
+pub struct GuiContainerData {
+    /// The visible area of the container on the screen as `(x, y, width, height)`, in the same coordinates as the dimensions of a [GuiElement](../struct.GuiElement.html).
+    pub dimensions: (i32, i32, u32, u32),
+
+    /// The position of the visible area in the content of the container. Elements in the container are moved by `-scroll`, so `(0, 0)` shows the bottom-left of the content.
+    pub scroll: (i32, i32),
+
+    /// The size of the content of the container. When this is set, [GuiContainer::scroll_by](../struct.GuiContainer.html#method.scroll_by) stops scrolling at the edges of the content.
+    pub content_size: Option<(u32, u32)>,
+}
+
+impl GuiContainer {
+    /// Create a new container with the given `(x, y, width, height)` that is not scrolled.
+    pub fn new(dimensions: (i32, i32, u32, u32)) -> Self {
+        Self {
+            data: Arc::new(RwLock::new(GuiContainerData {
+                dimensions,
+                scroll: (0, 0),
+                content_size: None,
+            })),
+        }
+    }
+
+    /// Modify the current container. This affects every element in the container.
+    pub fn modify(&self, cb: impl FnOnce(&mut GuiContainerData)) {
+        let mut lock = self.data.write();
+        cb(&mut *lock);
+    }
+
+    /// The visible area of the container on the screen as `(x, y, width, height)`.
+    pub fn dimensions(&self) -> (i32, i32, u32, u32) {
+        self.data.read().dimensions
+    }
+
+    /// The current scroll offset of the content.
+    pub fn scroll(&self) -> (i32, i32) {
+        self.data.read().scroll
+    }
+
+    /// Scroll the content by the given amount of GUI pixels. If the [content_size](./state/struct.GuiContainerData.html#structfield.content_size) is set, the scroll offset is clamped so the visible area stays within the content.
+    pub fn scroll_by(&self, x: i32, y: i32) {
+        let mut data = self.data.write();
+        let mut scroll = (data.scroll.0 + x, data.scroll.1 + y);
+        if let Some((width, height)) = data.content_size {
+            let max_x = width.saturating_sub(data.dimensions.2) as i32;
+            let max_y = height.saturating_sub(data.dimensions.3) as i32;
+            scroll = (scroll.0.max(0).min(max_x), scroll.1.max(0).min(max_y));
+        }
+        data.scroll = scroll;
+    }
+
+    /// Returns `true` if the given position in GUI coordinates is inside the visible area of the container. This can be used to only scroll the container when the cursor is over it, see [GuiState::window_to_gui](./state/struct.GuiState.html#method.window_to_gui).
+    pub fn contains(&self, position: (f32, f32)) -> bool {
+        let (x, y, width, height) = self.dimensions();
+        position.0 >= x as f32
+            && position.1 >= y as f32
+            && position.0 < x as f32 + width as f32
+            && position.1 < y as f32 + height as f32
+    }
+
+    /// Get the position of an element with the given position in this container on the screen, and the area of the screen that the element is clipped to.
+    pub(crate) fn place(&self, position: (i32, i32)) -> ((i32, i32), (i32, i32, u32, u32)) {
+        let data = self.data.read();
+        let (x, y, _, _) = data.dimensions;
+        (
+            (
+                x + position.0 - data.scroll.0,
+                y + position.1 - data.scroll.1,
+            ),
+            data.dimensions,
+        )
+    }
+}
+
+#[test]
+fn test_container_scroll() {
+    let container = GuiContainer::new((10, 20, 100, 50));
+    container.scroll_by(0, 30);
+    assert_eq!(((15, -5), (10, 20, 100, 50)), container.place((5, 5)));
+
+    container.modify(|data| data.content_size = Some((100, 200)));
+    container.scroll_by(0, 500);
+    assert_eq!((0, 150), container.scroll());
+    container.scroll_by(-20, -200);
+    assert_eq!((0, 0), container.scroll());
+
+    assert!(container.contains((10.0, 69.0)));
+    assert!(!container.contains((110.0, 30.0)));
+}
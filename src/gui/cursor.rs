@@ -0,0 +1,68 @@
+use super::{GuiElement, GuiState};
+
+/// A cursor image that is drawn as a GUI element on top of all other elements, see [GameState::set_custom_cursor](../struct.GameState.html#method.set_custom_cursor).
+pub(crate) struct CustomCursor {
+    element: GuiElement,
+    /// The size of the image in pixels
+    image_size: (u32, u32),
+    /// The pixel of the image that is at the position of the mouse, from the top-left of the image
+    hotspot: (u32, u32),
+}
+
+impl CustomCursor {
+    pub fn new(element: GuiElement, image_size: (u32, u32), hotspot: (u32, u32)) -> Self {
+        element.modify(|data| data.z_index = u32::MAX);
+        Self {
+            element,
+            image_size,
+            hotspot,
+        }
+    }
+
+    /// Move the image to the position of the mouse, in physical pixels of the window. The image is hidden when the position is `None`.
+    pub fn refresh(&self, gui: &GuiState, position: Option<(f64, f64)>) {
+        let dimensions = match position {
+            Some(position) => cursor_rect(gui, position, self.image_size, self.hotspot),
+            // An element without a size is not visible
+            None => (0, 0, 0, 0),
+        };
+        self.element.modify(|data| data.dimensions = dimensions);
+    }
+}
+
+/// Get the dimensions of the cursor element in GUI coordinates. The image is drawn with one pixel of the image for every pixel of the window, so the cursor has the same size at every virtual resolution.
+fn cursor_rect(
+    gui: &GuiState,
+    position: (f64, f64),
+    (width, height): (u32, u32),
+    (hotspot_x, hotspot_y): (u32, u32),
+) -> (i32, i32, u32, u32) {
+    let left = position.0 - hotspot_x as f64;
+    let top = position.1 - hotspot_y as f64;
+    // The window has its origin in the top-left, but the GUI has its origin in the bottom-left
+    let (x, gui_top) = gui.window_to_gui((left, top));
+    let (gui_right, y) = gui.window_to_gui((left + width as f64, top + height as f64));
+    (
+        x.round() as i32,
+        y.round() as i32,
+        (gui_right - x).round() as u32,
+        (gui_top - y).round() as u32,
+    )
+}
+
+#[test]
+fn test_cursor_rect() {
+    let mut gui = GuiState::new((800, 600), 1.0);
+    assert_eq!(
+        (95, 468, 32, 32),
+        cursor_rect(&gui, (100.0, 100.0), (32, 32), (5, 0))
+    );
+
+    // With a virtual resolution of twice the window size, every pixel of the image is two GUI
+    // pixels
+    gui.virtual_resolution = Some((1600, 1200));
+    assert_eq!(
+        (190, 936, 64, 64),
+        cursor_rect(&gui, (100.0, 100.0), (32, 32), (5, 0))
+    );
+}
@@ -1,5 +1,5 @@
-use super::builder::TextRequest;
-use crate::{error::GuiError, internal::UpdateMessage};
+use super::{animation::AnimatedTexture, builder::TextRequest, sdf::SdfText, GuiContainer};
+use crate::{error::GuiError, internal::UpdateMessage, render::texture::Texture};
 use parking_lot::RwLock;
 use std::sync::{
     atomic::{AtomicU32, AtomicU64, Ordering},
@@ -7,18 +7,124 @@ use std::sync::{
     Arc,
 };
 use vulkano::{
-    device::Queue,
-    format::R8G8B8A8Srgb,
-    image::{Dimensions, ImmutableImage},
+    buffer::{BufferUsage, CpuAccessibleBuffer},
+    command_buffer::AutoCommandBufferBuilder,
+    device::{Device, Queue},
+    format::{Format, R8G8B8A8Srgb},
+    image::{Dimensions, ImageUsage, ImmutableImage, StorageImage},
     sync::GpuFuture,
 };
 
 pub struct GuiElementRef {
     pub data: Arc<RwLock<GuiElementData>>,
-    pub texture: Arc<ImmutableImage<R8G8B8A8Srgb>>,
+    pub texture: GuiTexture,
     pub texture_future: Option<Box<dyn GpuFuture>>,
 }
 
+#[derive(Clone)]
+pub enum GuiTexture {
+    Immutable(Texture),
+    DoubleBuffered(Arc<RwLock<DoubleBufferedTexture>>),
+    Animated(Arc<AnimatedTexture>),
+    SdfText(Arc<SdfText>),
+}
+
+/// A texture that can be updated without creating a new image. New pixels are written into the back image, while the front image is being rendered.
+pub struct DoubleBufferedTexture {
+    device: Arc<Device>,
+    images: [Arc<StorageImage<R8G8B8A8Srgb>>; 2],
+    front: usize,
+    dimensions: (u32, u32),
+    pending: Option<Arc<CpuAccessibleBuffer<[u8]>>>,
+    /// The last pixels that were written, which are uploaded again when the device is lost
+    pixels: Vec<u8>,
+}
+
+impl DoubleBufferedTexture {
+    fn new(queue: &Queue, dimensions: (u32, u32), data: Vec<u8>) -> Result<Self, GuiError> {
+        let mut texture = Self {
+            images: Self::create_images(queue, dimensions)?,
+            device: queue.device().clone(),
+            front: 0,
+            dimensions,
+            pending: None,
+            pixels: Vec::new(),
+        };
+        texture.write(data)?;
+        Ok(texture)
+    }
+
+    fn create_images(
+        queue: &Queue,
+        dimensions: (u32, u32),
+    ) -> Result<[Arc<StorageImage<R8G8B8A8Srgb>>; 2], GuiError> {
+        let device = queue.device();
+        let create = || {
+            StorageImage::with_usage(
+                device.clone(),
+                Dimensions::Dim2d {
+                    width: dimensions.0,
+                    height: dimensions.1,
+                },
+                R8G8B8A8Srgb,
+                ImageUsage {
+                    transfer_destination: true,
+                    sampled: true,
+                    ..ImageUsage::none()
+                },
+                Some(queue.family()),
+            )
+            .map_err(|inner| GuiError::CouldNotCreateTexture { inner })
+        };
+        Ok([create()?, create()?])
+    }
+
+    /// Create the images again on a new device, after the device was lost. The last pixels that were written are uploaded at the start of the next frame.
+    pub fn restore(&mut self, queue: &Queue) -> Result<(), GuiError> {
+        self.images = Self::create_images(queue, self.dimensions)?;
+        self.device = queue.device().clone();
+        self.front = 0;
+        let pixels = std::mem::replace(&mut self.pixels, Vec::new());
+        self.write(pixels)
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.dimensions
+    }
+
+    /// Write new pixels to the texture. These will be uploaded to the back image at the start of the next frame, after which the images are swapped.
+    pub fn write(&mut self, data: Vec<u8>) -> Result<(), GuiError> {
+        let buffer = CpuAccessibleBuffer::from_iter(
+            self.device.clone(),
+            BufferUsage::transfer_source(),
+            false,
+            data.iter().copied(),
+        )
+        .map_err(|inner| GuiError::CouldNotAllocateBuffer { inner })?;
+        self.pending = Some(buffer);
+        self.pixels = data;
+        Ok(())
+    }
+
+    /// Record the upload of the pending pixels, if any. This must be called outside of a render pass.
+    pub fn flush(&mut self, command_buffer_builder: &mut AutoCommandBufferBuilder) {
+        if let Some(buffer) = self.pending.take() {
+            let back = 1 - self.front;
+            command_buffer_builder
+                .copy_buffer_to_image(buffer, self.images[back].clone())
+                // The buffer is created with the same size as the image, so this should never
+                // fail
+                .unwrap();
+            self.front = back;
+        }
+    }
+
+    /// The image that should be rendered.
+    pub fn front(&self) -> Arc<StorageImage<R8G8B8A8Srgb>> {
+        self.images[self.front].clone()
+    }
+}
+
 static NEXT_Z_INDEX: AtomicU32 = AtomicU32::new(1);
 
 impl GuiElementRef {
@@ -43,6 +149,74 @@ pub struct GuiElementData {
     /// The format of this field is `(x, y, width, height)`.
     /// This means that the right edge would be `dimensions.0 + dimensions.2` and the bottom edge would be `dimensions.1 + dimensions.3`.
     pub dimensions: (i32, i32, u32, u32),
+
+    /// The [GuiContainer] that this element is placed in. When this is set, the [dimensions](#structfield.dimensions) are relative to the content of the container, and the element is clipped to the bounds of the container.
+    ///
+    /// [GuiContainer]: ../struct.GuiContainer.html
+    pub container: Option<GuiContainer>,
+
+    /// The color that the texture of the element is multiplied with, e.g. to fade out an icon or to color a white icon. Defaults to [WHITE](../color/constant.WHITE.html), which shows the texture as it is.
+    ///
+    /// This is not used by elements that are created with `sdf_text`, as these already have a color.
+    pub tint: [u8; 4],
+
+    /// The part of the texture that is shown, as `(x, y, width, height)` in texture coordinates. The top-left of the texture is `(0.0, 0.0)` and the bottom-right is `(1.0, 1.0)`. This is useful to show a single image of a texture atlas or a sprite sheet. Defaults to `(0.0, 0.0, 1.0, 1.0)`, which is the whole texture.
+    pub uv_region: (f32, f32, f32, f32),
+
+    /// Mirror the texture from left to right.
+    pub flip_horizontal: bool,
+
+    /// Mirror the texture from top to bottom.
+    pub flip_vertical: bool,
+
+    /// The way the texture is sampled when it is drawn at a different size than the image. Defaults to [GuiFilter::Linear].
+    ///
+    /// [GuiFilter::Linear]: ../state/enum.GuiFilter.html#variant.Linear
+    pub filter: GuiFilter,
+}
+
+/// The way the texture of a GUI element is sampled, see [GuiElementData::filter](../struct.GuiElementData.html#structfield.filter).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GuiFilter {
+    /// Blend the nearest pixels of the texture, which makes a scaled texture look smooth.
+    Linear,
+    /// Use the nearest pixel of the texture, which keeps the edges of pixel art sharp when it is scaled up.
+    Nearest,
+}
+
+impl Default for GuiFilter {
+    fn default() -> Self {
+        GuiFilter::Linear
+    }
+}
+
+impl GuiElementData {
+    fn new(dimensions: (i32, i32, u32, u32), z_index: u32) -> Self {
+        Self {
+            z_index,
+            dimensions,
+            container: None,
+            tint: crate::color::WHITE,
+            uv_region: (0.0, 0.0, 1.0, 1.0),
+            flip_horizontal: false,
+            flip_vertical: false,
+            filter: GuiFilter::default(),
+        }
+    }
+
+    /// The texture coordinates of the top-left and the bottom-right corner of the element, with the [uv_region](#structfield.uv_region) and the flips applied.
+    pub(crate) fn uv_rect(&self) -> [f32; 4] {
+        let (x, y, width, height) = self.uv_region;
+        let (mut left, mut right) = (x, x + width);
+        let (mut top, mut bottom) = (y, y + height);
+        if self.flip_horizontal {
+            std::mem::swap(&mut left, &mut right);
+        }
+        if self.flip_vertical {
+            std::mem::swap(&mut top, &mut bottom);
+        }
+        [left, top, right, bottom]
+    }
 }
 
 /// A reference to a GUI element on the screen.
@@ -51,10 +225,10 @@ pub struct GuiElementData {
 ///
 /// This reference can be modified with the [modify](#method.modify) method.
 pub struct GuiElement {
-    id: u64,
-    data: Arc<RwLock<GuiElementData>>,
+    pub(crate) id: u64,
+    pub(crate) data: Arc<RwLock<GuiElementData>>,
     internal_update: Sender<UpdateMessage>,
-    canvas_config: Option<CanvasConfig>,
+    pub(crate) canvas_config: Option<CanvasConfig>,
 }
 
 #[derive(Clone)]
@@ -62,6 +236,7 @@ pub(crate) struct CanvasConfig {
     pub background: [u8; 4],
     pub border: Option<(u16, [u8; 4])>,
     pub text: Option<TextRequest>,
+    pub double_buffered: bool,
 }
 
 static ID: AtomicU64 = AtomicU64::new(0);
@@ -74,6 +249,12 @@ impl Clone for GuiElement {
         let data = Arc::new(RwLock::new(GuiElementData {
             dimensions: data.dimensions,
             z_index: data.z_index,
+            container: data.container.clone(),
+            tint: data.tint,
+            uv_region: data.uv_region,
+            flip_horizontal: data.flip_horizontal,
+            flip_vertical: data.flip_vertical,
+            filter: data.filter,
         }));
 
         let _ = self.internal_update.send(UpdateMessage::NewGuiElement {
@@ -106,28 +287,57 @@ impl GuiElement {
         internal_update: Sender<UpdateMessage>,
         canvas_config: Option<CanvasConfig>,
     ) -> Result<(u64, GuiElementRef, GuiElement), GuiError> {
-        let id = ID.fetch_add(1, Ordering::Relaxed);
-
         let (width, height, data) = image_data;
-        let (texture, texture_future) = ImmutableImage::from_iter(
-            data.into_iter(),
-            Dimensions::Dim2d { width, height },
-            R8G8B8A8Srgb,
-            queue,
-        )
-        .map_err(|inner| GuiError::CouldNotCreateTexture { inner })?;
+        let double_buffered = canvas_config
+            .as_ref()
+            .map(|c| c.double_buffered)
+            .unwrap_or(false);
+        let (texture, texture_future) = if double_buffered {
+            let texture = DoubleBufferedTexture::new(&queue, (width, height), data)?;
+            (
+                GuiTexture::DoubleBuffered(Arc::new(RwLock::new(texture))),
+                None,
+            )
+        } else {
+            let (texture, texture_future) = ImmutableImage::from_iter(
+                data.into_iter(),
+                Dimensions::Dim2d { width, height },
+                Format::R8G8B8A8Srgb,
+                queue,
+            )
+            .map_err(|inner| GuiError::CouldNotCreateTexture { inner })?;
+            (GuiTexture::Immutable(texture), Some(texture_future.boxed()))
+        };
 
-        let data = Arc::new(RwLock::new(GuiElementData {
+        Ok(Self::from_texture(
             dimensions,
-            z_index: NEXT_Z_INDEX.fetch_add(1, Ordering::Relaxed),
-        }));
+            texture,
+            texture_future,
+            internal_update,
+            canvas_config,
+        ))
+    }
 
-        Ok((
+    /// Create an element that renders a texture that is already uploaded.
+    pub(crate) fn from_texture(
+        dimensions: (i32, i32, u32, u32),
+        texture: GuiTexture,
+        texture_future: Option<Box<dyn GpuFuture>>,
+        internal_update: Sender<UpdateMessage>,
+        canvas_config: Option<CanvasConfig>,
+    ) -> (u64, GuiElementRef, GuiElement) {
+        let id = ID.fetch_add(1, Ordering::Relaxed);
+        let data = Arc::new(RwLock::new(GuiElementData::new(
+            dimensions,
+            NEXT_Z_INDEX.fetch_add(1, Ordering::Relaxed),
+        )));
+
+        (
             id,
             GuiElementRef {
                 data: Arc::clone(&data),
                 texture,
-                texture_future: Some(texture_future.boxed()),
+                texture_future,
             },
             GuiElement {
                 id,
@@ -135,11 +345,13 @@ impl GuiElement {
                 internal_update,
                 canvas_config,
             },
-        ))
+        )
     }
 
     /// Update the canvas. This will have the exact same settings as before, you can overwrite this by calling one of the helper methods on [GuiElementCanvasBuilder].
     ///
+    /// By default this creates a new texture every time, which can cause hitches when the canvas is updated often. See [GuiElementCanvasBuilder::with_double_buffering] for a faster alternative.
+    ///
     /// This method will panic if the current GuiElement is created as a texture
     ///
     /// ```rust,no_run
@@ -155,6 +367,7 @@ impl GuiElement {
     /// ```
     ///
     /// [GuiElementCanvasBuilder]: ../GuiElementCanvasBuilder.html
+    /// [GuiElementCanvasBuilder::with_double_buffering]: ../GuiElementCanvasBuilder.html#method.with_double_buffering
     pub fn update_canvas(
         &mut self,
         game_state: &mut crate::GameState,
@@ -167,17 +380,26 @@ impl GuiElement {
         if let Some(border) = canvas_config.border {
             builder = builder.with_border(border.0, border.1);
         }
-        if let Some(TextRequest {
-            font,
-            font_size,
-            text,
-            color,
-        }) = canvas_config.text
+        if let Some(text) = canvas_config.text {
+            builder = builder.with_text_request(text);
+        }
+        let builder = cb(builder.with_double_buffering(canvas_config.double_buffered));
+        builder.update(self)
+    }
+
+    /// Replace the pixels of a double buffered canvas. The image must have the same size as the element.
+    pub(crate) fn write_pixels(
+        &self,
+        game_state: &crate::GameState,
+        image: image::RgbaImage,
+    ) -> Result<(), GuiError> {
+        if let Some(GuiTexture::DoubleBuffered(texture)) =
+            game_state.gui_elements.get(&self.id).map(|e| &e.texture)
         {
-            builder = builder.with_text(font, font_size, text, color);
+            let mut texture = texture.write();
+            debug_assert_eq!(texture.dimensions(), image.dimensions());
+            texture.write(image.into_raw())?;
         }
-        let builder = cb(builder);
-        *self = builder.build()?;
         Ok(())
     }
 
@@ -187,3 +409,18 @@ impl GuiElement {
         cb(&mut *lock);
     }
 }
+
+#[test]
+fn test_uv_rect() {
+    let mut data = GuiElementData::new((0, 0, 10, 10), 0);
+    assert_eq!([0.0, 0.0, 1.0, 1.0], data.uv_rect());
+
+    data.uv_region = (0.25, 0.5, 0.25, 0.5);
+    assert_eq!([0.25, 0.5, 0.5, 1.0], data.uv_rect());
+
+    data.flip_horizontal = true;
+    assert_eq!([0.5, 0.5, 0.25, 1.0], data.uv_rect());
+
+    data.flip_vertical = true;
+    assert_eq!([0.5, 1.0, 0.25, 0.5], data.uv_rect());
+}
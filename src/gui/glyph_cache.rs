@@ -0,0 +1,140 @@
+use crate::Font;
+use rusttype::{GlyphId, Point, Rect, Scale};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Weak},
+};
+
+/// A set of characters that can be rasterized ahead of time with [GameState::prewarm_glyphs].
+///
+/// [GameState::prewarm_glyphs]: ../struct.GameState.html#method.prewarm_glyphs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GlyphRange {
+    /// The printable ASCII characters, `' '` to `'~'`.
+    Ascii,
+    /// The printable characters of Latin-1, which includes ASCII and most accented characters used in western European languages.
+    Latin1,
+    /// A custom range of characters, e.g. `'а'..='я'` for lowercase Cyrillic.
+    Range(char, char),
+    /// A custom set of characters, e.g. the characters of a player name.
+    Custom(Vec<char>),
+}
+
+impl GlyphRange {
+    pub(crate) fn chars(&self) -> Vec<char> {
+        match self {
+            GlyphRange::Ascii => (' '..='~').collect(),
+            GlyphRange::Latin1 => (' '..='~').chain('\u{a0}'..='\u{ff}').collect(),
+            GlyphRange::Range(start, end) => (*start..=*end).collect(),
+            GlyphRange::Custom(chars) => chars.clone(),
+        }
+    }
+}
+
+/// A glyph that has been rasterized at a given font size.
+pub(crate) struct CachedGlyph {
+    /// The bounding box of the glyph, relative to its origin. This is `None` for glyphs without an outline, like spaces.
+    pub bounding_box: Option<Rect<i32>>,
+    /// The coverage of every pixel in the bounding box, row by row.
+    pub coverage: Vec<f32>,
+}
+
+impl CachedGlyph {
+    fn rasterize(font: &Font, size: u16, id: GlyphId) -> Self {
+        let glyph = font
+            .glyph(id)
+            .scaled(Scale::uniform(size as f32))
+            .positioned(Point { x: 0.0, y: 0.0 });
+        let bounding_box = glyph.pixel_bounding_box();
+        let mut coverage = Vec::new();
+        if let Some(bounding_box) = bounding_box {
+            let width = bounding_box.width() as usize;
+            coverage = vec![0.0; width * bounding_box.height() as usize];
+            glyph.draw(|x, y, v| coverage[y as usize * width + x as usize] = v);
+        }
+        Self {
+            bounding_box,
+            coverage,
+        }
+    }
+
+    /// Call `f` with the position and coverage of every pixel of this glyph, when its origin is placed at `origin`.
+    pub fn draw(&self, origin: (i32, i32), mut f: impl FnMut(i32, i32, f32)) {
+        if let Some(bounding_box) = self.bounding_box {
+            let width = bounding_box.width() as usize;
+            for (index, v) in self.coverage.iter().enumerate() {
+                let x = (index % width) as i32 + bounding_box.min.x + origin.0;
+                let y = (index / width) as i32 + bounding_box.min.y + origin.1;
+                f(x, y, *v);
+            }
+        }
+    }
+}
+
+struct FontGlyphs {
+    font: Weak<rusttype::Font<'static>>,
+    glyphs: HashMap<(u16, GlyphId), Arc<CachedGlyph>>,
+}
+
+/// A cache of rasterized glyphs, so text that is rendered often does not have to be rasterized every time.
+///
+/// Glyphs are snapped to whole pixels, so a glyph can be reused at every position.
+#[derive(Default)]
+pub(crate) struct GlyphCache {
+    fonts: Vec<FontGlyphs>,
+}
+
+impl GlyphCache {
+    fn glyphs_of(&mut self, font: &Font) -> &mut HashMap<(u16, GlyphId), Arc<CachedGlyph>> {
+        // Remove the glyphs of fonts that are no longer used
+        self.fonts.retain(|f| f.font.strong_count() > 0);
+
+        let index = match self
+            .fonts
+            .iter()
+            .position(|f| f.font.upgrade().map_or(false, |f| Arc::ptr_eq(&f, font)))
+        {
+            Some(index) => index,
+            None => {
+                self.fonts.push(FontGlyphs {
+                    font: Arc::downgrade(font),
+                    glyphs: HashMap::new(),
+                });
+                self.fonts.len() - 1
+            }
+        };
+        &mut self.fonts[index].glyphs
+    }
+
+    /// Get a rasterized glyph, rasterizing it if it is not in the cache yet.
+    pub fn get(&mut self, font: &Font, size: u16, id: GlyphId) -> Arc<CachedGlyph> {
+        self.glyphs_of(font)
+            .entry((size, id))
+            .or_insert_with(|| Arc::new(CachedGlyph::rasterize(font, size, id)))
+            .clone()
+    }
+
+    pub fn prewarm(&mut self, font: &Font, sizes: &[u16], range: &GlyphRange) {
+        let chars = range.chars();
+        let glyphs = self.glyphs_of(font);
+        for &size in sizes {
+            for &c in &chars {
+                let id = font.glyph(c).id();
+                glyphs
+                    .entry((size, id))
+                    .or_insert_with(|| Arc::new(CachedGlyph::rasterize(font, size, id)));
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.fonts.clear();
+    }
+}
+
+#[test]
+fn test_glyph_ranges() {
+    assert_eq!(95, GlyphRange::Ascii.chars().len());
+    assert_eq!(95 + 96, GlyphRange::Latin1.chars().len());
+    assert_eq!(vec!['a', 'b', 'c'], GlyphRange::Range('a', 'c').chars());
+}
@@ -1,11 +1,32 @@
+mod animation;
 mod builder;
+mod container;
+mod cursor;
 mod element;
+mod glyph_cache;
+mod notifications;
 mod pipeline;
+mod sdf;
+mod state;
+mod widgets;
 
 pub use self::{
-    builder::{GuiElementBuilder, GuiElementCanvasBuilder, GuiElementTextureBuilder},
-    element::{GuiElement, GuiElementData, GuiElementRef},
+    animation::GuiElementAnimationBuilder,
+    builder::{
+        GuiElementBuilder, GuiElementCanvasBuilder, GuiElementTextureBuilder, HorizontalAlignment,
+        VerticalAlignment,
+    },
+    container::{GuiContainer, GuiContainerData},
+    element::{GuiElement, GuiElementData, GuiElementRef, GuiFilter, GuiTexture},
+    glyph_cache::GlyphRange,
     pipeline::Pipeline,
+    sdf::{GuiElementSdfTextBuilder, SdfFont},
+    state::{GuiState, GuiTheme},
+    widgets::{DebugWidgetId, Gradient},
+};
+pub(crate) use self::{
+    cursor::CustomCursor, glyph_cache::GlyphCache, notifications::Notifications,
+    widgets::DebugWidgets,
 };
 
 #[derive(Default, Copy, Clone)]
@@ -25,6 +46,8 @@ layout(location = 2) in vec2 tex_coord;
 layout(location = 0) out vec2 fragment_tex_coord;
 
 layout(set = 0, binding = 0) uniform Data {
+    vec4 tint;
+    vec4 uv_rect;
     vec2 screen_size;
     vec2 position;
     vec2 size;
@@ -37,7 +60,8 @@ void main() {
         (uniforms.position / half_screen_size - vec2(1.0, 1.0)) +
         (offset * uniforms.size / half_screen_size),
         0.0, 1.0);
-    fragment_tex_coord = tex_coord;
+    // The uv rect contains the texture coordinates of the top-left and the bottom-right corner
+    fragment_tex_coord = mix(uniforms.uv_rect.xy, uniforms.uv_rect.zw, tex_coord);
 }
 "
     }
@@ -53,6 +77,8 @@ layout(location = 0) in vec2 fragment_tex_coord;
 layout(location = 0) out vec4 f_color;
 
 layout(set = 0, binding = 0) uniform Data {
+    vec4 tint;
+    vec4 uv_rect;
     vec2 screen_size;
     vec2 position;
     vec2 size;
@@ -60,7 +86,7 @@ layout(set = 0, binding = 0) uniform Data {
 layout(set = 0, binding = 1) uniform sampler2D tex;
 
 void main() {
-    f_color = texture(tex, fragment_tex_coord);
+    f_color = texture(tex, fragment_tex_coord) * uniforms.tint;
 }
 "
     }
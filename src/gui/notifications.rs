@@ -0,0 +1,109 @@
+use crate::{GameState, GuiElement};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+#[derive(Default)]
+pub(crate) struct Notifications {
+    toasts: NotificationQueue,
+    subtitles: NotificationQueue,
+}
+
+#[derive(Default)]
+struct NotificationQueue {
+    queue: VecDeque<(String, Duration)>,
+    active: Option<(GuiElement, Instant)>,
+}
+
+#[derive(Clone, Copy)]
+enum Placement {
+    TopRight,
+    BottomCenter,
+}
+
+impl Notifications {
+    pub fn push_toast(&mut self, text: String, duration: Duration) {
+        self.toasts.queue.push_back((text, duration));
+    }
+
+    pub fn push_subtitle(&mut self, text: String, duration: Duration) {
+        self.subtitles.queue.push_back((text, duration));
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn refresh(&mut self, game_state: &mut GameState) {
+        self.toasts.refresh(game_state, Placement::TopRight);
+        self.subtitles.refresh(game_state, Placement::BottomCenter);
+    }
+}
+
+impl NotificationQueue {
+    fn refresh(&mut self, game_state: &mut GameState, placement: Placement) {
+        if let Some((_, until)) = &self.active {
+            if Instant::now() < *until {
+                return;
+            }
+            self.active = None;
+        }
+        let (text, duration) = match self.queue.pop_front() {
+            Some(next) => next,
+            None => return,
+        };
+
+        let theme = game_state.gui.theme.clone();
+        let font = match theme.font {
+            Some(font) => font,
+            None => {
+                eprintln!(
+                    "Could not show notification {:?}: no font is set in `GameState::gui.theme`",
+                    text
+                );
+                return;
+            }
+        };
+
+        let dimensions = dimensions(game_state, placement, theme.font_size);
+        let mut builder = game_state
+            .new_gui_element(dimensions)
+            .canvas()
+            .with_background_color(theme.background_color)
+            .with_text(font, theme.font_size, text, theme.text_color);
+        if let Some((width, color)) = theme.border {
+            builder = builder.with_border(width, color);
+        }
+        match builder.build() {
+            Ok(element) => self.active = Some((element, Instant::now() + duration)),
+            Err(e) => eprintln!("Could not show notification: {:?}", e),
+        }
+    }
+}
+
+fn dimensions(
+    game_state: &GameState,
+    placement: Placement,
+    font_size: u16,
+) -> (i32, i32, u32, u32) {
+    let (x, y, width, height) = game_state.gui.safe_area();
+    let element_height = font_size as f32 * 2.0;
+    let (element_x, element_y, element_width) = match placement {
+        Placement::TopRight => {
+            let element_width = width * 0.3;
+            (
+                x + width - element_width,
+                y + height - element_height,
+                element_width,
+            )
+        }
+        Placement::BottomCenter => {
+            let element_width = width * 0.8;
+            (x + (width - element_width) / 2.0, y, element_width)
+        }
+    };
+    game_state
+        .gui
+        .rect(element_x, element_y, element_width, element_height)
+}
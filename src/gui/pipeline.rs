@@ -1,12 +1,19 @@
-use super::{fs, vs, GuiElementRef, Vertex};
-use std::sync::Arc;
+use super::{
+    fs,
+    sdf::{sdf_fs, sdf_vs, to_linear_color, SdfText, SHADOW_SOFTNESS},
+    vs, GuiElementRef, GuiFilter, GuiTexture, Vertex,
+};
+use crate::error::RenderError;
+use std::{sync::Arc, time::Duration};
 use vulkano::{
     buffer::{BufferUsage, CpuAccessibleBuffer, CpuBufferPool},
     command_buffer::{AutoCommandBufferBuilder, DynamicState},
     descriptor::descriptor_set::{PersistentDescriptorSet, StdDescriptorPool},
-    device::Device,
+    device::{Device, Queue},
+    format::R8G8B8A8Srgb,
     framebuffer::{RenderPassAbstract, Subpass},
-    pipeline::{GraphicsPipeline, GraphicsPipelineAbstract},
+    image::{Dimensions, ImageViewAccess, ImmutableImage},
+    pipeline::{viewport::Scissor, GraphicsPipeline, GraphicsPipelineAbstract},
     sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
     sync::{now, GpuFuture},
 };
@@ -17,13 +24,19 @@ pub struct Pipeline {
     rect_index: Arc<CpuAccessibleBuffer<[u16]>>,
     pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
     uniform_buffer: CpuBufferPool<vs::ty::Data>,
+    sdf_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    sdf_uniform_buffer: CpuBufferPool<sdf_vs::ty::Data>,
     sampler: Arc<Sampler>,
+    nearest_sampler: Arc<Sampler>,
+    white_texture: Arc<ImmutableImage<R8G8B8A8Srgb>>,
+    white_texture_future: Option<Box<dyn GpuFuture>>,
 }
 
 impl Pipeline {
     pub fn create(
         device: Arc<Device>,
-        render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        queue: Arc<Queue>,
+        subpass: Subpass<Arc<dyn RenderPassAbstract + Send + Sync>>,
     ) -> Self {
         // These should never fail, as the shaders are hard-coded and the device is assumed to be
         // valid.
@@ -34,19 +47,39 @@ impl Pipeline {
             GraphicsPipeline::start()
                 .vertex_input_single_buffer::<Vertex>()
                 .vertex_shader(vs.main_entry_point(), ())
-                .viewports_dynamic_scissors_irrelevant(1)
+                // Elements in a container are clipped to the container with a scissor
+                .viewports_scissors_dynamic(1)
                 .fragment_shader(fs.main_entry_point(), ())
                 .cull_mode_front()
                 .blend_alpha_blending()
-                .depth_stencil_simple_depth()
-                // This should never fail because the render_pass is hard-coded
-                .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+                // Elements are drawn in the order of their z-index, so no depth test is needed.
+                // Every element is drawn at the same depth, so with a depth test an element would
+                // hide the elements that are drawn on top of it, including the transition overlay.
+                // The present pass has no depth attachment either.
+                .render_pass(subpass.clone())
                 .build(device.clone())
                 // This should never fail because all arguments are hard-coded
                 .unwrap(),
         );
         let uniform_buffer = CpuBufferPool::<vs::ty::Data>::uniform_buffer(device.clone());
 
+        let sdf_vs = sdf_vs::Shader::load(device.clone()).expect("failed to create shader module");
+        let sdf_fs = sdf_fs::Shader::load(device.clone()).expect("failed to create shader module");
+        let sdf_pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input_single_buffer::<Vertex>()
+                .vertex_shader(sdf_vs.main_entry_point(), ())
+                .viewports_scissors_dynamic(1)
+                .fragment_shader(sdf_fs.main_entry_point(), ())
+                .cull_mode_front()
+                .blend_alpha_blending()
+                .render_pass(subpass)
+                .build(device.clone())
+                // This should never fail because all arguments are hard-coded
+                .unwrap(),
+        );
+        let sdf_uniform_buffer = CpuBufferPool::<sdf_vs::ty::Data>::uniform_buffer(device.clone());
+
         let rect_vertex = CpuAccessibleBuffer::from_iter(
             device.clone(),
             BufferUsage::all(),
@@ -64,18 +97,34 @@ impl Pipeline {
         // This should never fail because the arguments are hard-coded
         .unwrap();
 
-        let sampler = Sampler::new(
-            device.clone(),
-            Filter::Linear,
-            Filter::Linear,
-            MipmapMode::Nearest,
-            SamplerAddressMode::Repeat,
-            SamplerAddressMode::Repeat,
-            SamplerAddressMode::Repeat,
-            0.0,
-            1.0,
-            0.0,
-            0.0,
+        let create_sampler = |filter| {
+            Sampler::new(
+                device.clone(),
+                filter,
+                filter,
+                MipmapMode::Nearest,
+                SamplerAddressMode::Repeat,
+                SamplerAddressMode::Repeat,
+                SamplerAddressMode::Repeat,
+                0.0,
+                1.0,
+                0.0,
+                0.0,
+            )
+            // This should never fail because the arguments are hard-coded
+            .unwrap()
+        };
+        let sampler = create_sampler(Filter::Linear);
+        let nearest_sampler = create_sampler(Filter::Nearest);
+
+        let (white_texture, white_texture_future) = ImmutableImage::from_iter(
+            crate::color::WHITE.iter().cloned(),
+            Dimensions::Dim2d {
+                width: 1,
+                height: 1,
+            },
+            R8G8B8A8Srgb,
+            queue,
         )
         // This should never fail because the arguments are hard-coded
         .unwrap();
@@ -84,38 +133,267 @@ impl Pipeline {
             device,
             pipeline,
             uniform_buffer,
+            sdf_pipeline,
+            sdf_uniform_buffer,
             rect_vertex,
             rect_index,
             sampler,
+            nearest_sampler,
+            white_texture,
+            white_texture_future: Some(white_texture_future.boxed()),
+        }
+    }
+    /// Upload the new content of double buffered elements. This must be called before the render pass is started.
+    pub fn upload_element(
+        &self,
+        element: &GuiElementRef,
+        command_buffer_builder: &mut AutoCommandBufferBuilder,
+    ) {
+        if let GuiTexture::DoubleBuffered(texture) = &element.texture {
+            texture.write().flush(command_buffer_builder);
         }
     }
+
+    /// Render a single element. Returns `Ok(false)` if the element is not rendered because its container is outside of the window.
+    #[allow(clippy::too_many_arguments)]
     pub fn render_element(
         &self,
         element: &mut GuiElementRef,
         command_buffer_builder: &mut AutoCommandBufferBuilder,
         future: &mut Box<dyn GpuFuture>,
-        screen_size: [f32; 2],
+        (screen_size, offset): ([f32; 2], [f32; 2]),
+        framebuffer_size: [f32; 2],
+        running: Duration,
         dynamic_state: &DynamicState,
         descriptor_pool: &mut Arc<StdDescriptorPool>,
-    ) {
+    ) -> Result<bool, RenderError> {
         if let Some(fut) = element.texture_future.take() {
             let tmp = std::mem::replace(future, now(self.device.clone()).boxed());
             *future = tmp.join(fut).boxed();
         }
         let element_data = element.data.read();
+        let (x, y, width, height) = element_data.dimensions;
+        let appearance = Appearance {
+            tint: to_linear_color(element_data.tint),
+            uv_rect: element_data.uv_rect(),
+            filter: element_data.filter,
+        };
+        let ((x, y), scissor) = match &element_data.container {
+            Some(container) => {
+                let (position, clip) = container.place((x, y));
+                match clip_rect(clip, (screen_size, offset), framebuffer_size) {
+                    Some(scissor) => (position, scissor),
+                    // The container is not on the screen, so nothing in it is visible
+                    None => return Ok(false),
+                }
+            }
+            None => ((x, y), Scissor::irrelevant()),
+        };
+        let position = [x as f32 + offset[0], y as f32 + offset[1]];
+        let size = [width as f32, height as f32];
+        let dynamic_state = DynamicState {
+            scissors: Some(vec![scissor]),
+            ..dynamic_state.clone()
+        };
+        let texture: Arc<dyn ImageViewAccess + Send + Sync> = match &element.texture {
+            GuiTexture::Immutable(texture) => texture.clone(),
+            GuiTexture::DoubleBuffered(texture) => texture.read().front(),
+            GuiTexture::Animated(animation) => animation.frame_at(running).clone(),
+            GuiTexture::SdfText(text) => {
+                self.draw_sdf_text(
+                    text,
+                    position,
+                    size,
+                    screen_size,
+                    command_buffer_builder,
+                    &dynamic_state,
+                    descriptor_pool,
+                )?;
+                return Ok(true);
+            }
+        };
+        self.draw_quad(
+            texture,
+            position,
+            size,
+            appearance,
+            screen_size,
+            command_buffer_builder,
+            &dynamic_state,
+            descriptor_pool,
+        )?;
+        Ok(true)
+    }
+
+    /// Render the glyphs of a text with a signed distance field. The shadow is drawn first, as the same glyphs moved by the shadow offset.
+    #[allow(clippy::too_many_arguments)]
+    fn draw_sdf_text(
+        &self,
+        text: &SdfText,
+        position: [f32; 2],
+        size: [f32; 2],
+        screen_size: [f32; 2],
+        command_buffer_builder: &mut AutoCommandBufferBuilder,
+        dynamic_state: &DynamicState,
+        descriptor_pool: &mut Arc<StdDescriptorPool>,
+    ) -> Result<(), RenderError> {
+        let vertices = match &text.vertices {
+            Some(vertices) => vertices,
+            None => return Ok(()),
+        };
+        // Without an outline, the edge of the text is blended with the color of the text itself
+        let (outline_width, outline_color) = text.outline.unwrap_or((0.0, text.color));
+
+        let mut passes = Vec::with_capacity(2);
+        if let Some((shadow_offset, shadow_color)) = text.shadow {
+            passes.push(sdf_vs::ty::Data {
+                color: shadow_color,
+                outline_color: shadow_color,
+                screen_size,
+                // The GUI has its origin in the bottom-left, so a shadow that moves down has to
+                // move towards the origin
+                position: [
+                    position[0] + shadow_offset[0],
+                    position[1] - shadow_offset[1],
+                ],
+                size,
+                outline_width,
+                softness: SHADOW_SOFTNESS,
+            });
+        }
+        passes.push(sdf_vs::ty::Data {
+            color: text.color,
+            outline_color,
+            screen_size,
+            position,
+            size,
+            outline_width,
+            softness: 0.0,
+        });
+
+        for data in passes {
+            let data = self
+                .sdf_uniform_buffer
+                .next(data)
+                .map_err(RenderError::CouldNotAllocateBuffer)?;
+            // Should never fail because the pipeline and index are hard-coded
+            let layout = self.sdf_pipeline.descriptor_set_layout(0).unwrap();
+            let set = Arc::new(
+                PersistentDescriptorSet::start(layout.clone())
+                    .add_buffer(data)
+                    // Should never fail because the layout and data are hard-coded
+                    .unwrap()
+                    .add_sampled_image(text.atlas.clone(), self.sampler.clone())
+                    // Should never fail because the atlas is a valid texture and the sampler is
+                    // hard-coded
+                    .unwrap()
+                    .build_with_pool(descriptor_pool)
+                    .map_err(RenderError::CouldNotCreateDescriptorSet)?,
+            );
+            command_buffer_builder
+                .draw(
+                    self.sdf_pipeline.clone(),
+                    dynamic_state,
+                    vec![vertices.clone()],
+                    set,
+                    (),
+                )
+                .map_err(RenderError::CouldNotDraw)?;
+        }
+        Ok(())
+    }
+
+    /// Render a rectangle with a single color. The position and size are in pixels.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_color(
+        &mut self,
+        color: [f32; 4],
+        position: [f32; 2],
+        size: [f32; 2],
+        screen_size: [f32; 2],
+        command_buffer_builder: &mut AutoCommandBufferBuilder,
+        future: &mut Box<dyn GpuFuture>,
+        dynamic_state: &DynamicState,
+        descriptor_pool: &mut Arc<StdDescriptorPool>,
+    ) -> Result<(), RenderError> {
+        if let Some(fut) = self.white_texture_future.take() {
+            let tmp = std::mem::replace(future, now(self.device.clone()).boxed());
+            *future = tmp.join(fut).boxed();
+        }
+        self.render_quad(
+            self.white_texture.clone(),
+            position,
+            size,
+            color,
+            screen_size,
+            command_buffer_builder,
+            dynamic_state,
+            descriptor_pool,
+        )
+    }
+
+    /// Render a texture on the screen, multiplied by the given tint. The position and size are in pixels.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_quad<T>(
+        &self,
+        texture: T,
+        position: [f32; 2],
+        size: [f32; 2],
+        tint: [f32; 4],
+        screen_size: [f32; 2],
+        command_buffer_builder: &mut AutoCommandBufferBuilder,
+        dynamic_state: &DynamicState,
+        descriptor_pool: &mut Arc<StdDescriptorPool>,
+    ) -> Result<(), RenderError>
+    where
+        T: ImageViewAccess + Send + Sync + 'static,
+    {
+        self.draw_quad(
+            texture,
+            position,
+            size,
+            Appearance::tinted(tint),
+            screen_size,
+            command_buffer_builder,
+            &DynamicState {
+                scissors: Some(vec![Scissor::irrelevant()]),
+                ..dynamic_state.clone()
+            },
+            descriptor_pool,
+        )
+    }
+
+    /// Render a texture with a dynamic state that includes the scissor of the quad.
+    #[allow(clippy::too_many_arguments)]
+    fn draw_quad<T>(
+        &self,
+        texture: T,
+        position: [f32; 2],
+        size: [f32; 2],
+        appearance: Appearance,
+        screen_size: [f32; 2],
+        command_buffer_builder: &mut AutoCommandBufferBuilder,
+        dynamic_state: &DynamicState,
+        descriptor_pool: &mut Arc<StdDescriptorPool>,
+    ) -> Result<(), RenderError>
+    where
+        T: ImageViewAccess + Send + Sync + 'static,
+    {
         let data = vs::ty::Data {
+            tint: appearance.tint,
+            uv_rect: appearance.uv_rect,
             screen_size,
-            position: [
-                element_data.dimensions.0 as f32,
-                element_data.dimensions.1 as f32,
-            ],
-            size: [
-                element_data.dimensions.2 as f32,
-                element_data.dimensions.3 as f32,
-            ],
+            position,
+            size,
+        };
+        let data = self
+            .uniform_buffer
+            .next(data)
+            .map_err(RenderError::CouldNotAllocateBuffer)?;
+        let sampler = match appearance.filter {
+            GuiFilter::Linear => self.sampler.clone(),
+            GuiFilter::Nearest => self.nearest_sampler.clone(),
         };
-        // Should never fail if we have a valid uniform buffer
-        let data = self.uniform_buffer.next(data).unwrap();
 
         // Should never fail because the pipeline and index are hard-coded
         let layout = self.pipeline.descriptor_set_layout(0).unwrap();
@@ -124,13 +402,12 @@ impl Pipeline {
                 .add_buffer(data)
                 // Should never fail because the layout and data are hard-coded
                 .unwrap()
-                .add_sampled_image(element.texture.clone(), self.sampler.clone())
+                .add_sampled_image(texture, sampler)
                 // Should never fail because the texture should be valid and the sampler is
                 // hard-coded
                 .unwrap()
                 .build_with_pool(descriptor_pool)
-                // Should never fail because if we have a valid descriptor_pool
-                .unwrap(),
+                .map_err(RenderError::CouldNotCreateDescriptorSet)?,
         );
         command_buffer_builder
             .draw_indexed(
@@ -141,10 +418,58 @@ impl Pipeline {
                 set,
                 (),
             )
-            // Should never fail because we assume the command buffer is valid, the vertices and
-            // indices are hard-coded, and the rest of the parameters are also valid
-            .unwrap();
+            .map_err(RenderError::CouldNotDrawIndexed)?;
+        Ok(())
+    }
+}
+
+/// The way a quad is drawn: the color that the texture is multiplied with, the part of the texture that is shown, and how the texture is sampled.
+struct Appearance {
+    tint: [f32; 4],
+    /// The texture coordinates of the top-left and the bottom-right corner
+    uv_rect: [f32; 4],
+    filter: GuiFilter,
+}
+
+impl Appearance {
+    /// Draw the whole texture, multiplied by the given linear color.
+    fn tinted(tint: [f32; 4]) -> Self {
+        Self {
+            tint,
+            uv_rect: [0.0, 0.0, 1.0, 1.0],
+            filter: GuiFilter::Linear,
+        }
+    }
+}
+
+/// Convert a rectangle in GUI coordinates to a scissor in pixels of the framebuffer. Returns `None` if no part of the rectangle is on the screen.
+fn clip_rect(
+    (x, y, width, height): (i32, i32, u32, u32),
+    (screen_size, offset): ([f32; 2], [f32; 2]),
+    framebuffer_size: [f32; 2],
+) -> Option<Scissor> {
+    let scale = [
+        framebuffer_size[0] / screen_size[0],
+        framebuffer_size[1] / screen_size[1],
+    ];
+    let left = ((x as f32 + offset[0]) * scale[0]).round().max(0.0);
+    let right = ((x as f32 + width as f32 + offset[0]) * scale[0])
+        .round()
+        .min(framebuffer_size[0]);
+    // The GUI has its origin in the bottom-left, but scissors have their origin in the top-left
+    let top = (framebuffer_size[1] - (y as f32 + height as f32 + offset[1]) * scale[1])
+        .round()
+        .max(0.0);
+    let bottom = (framebuffer_size[1] - (y as f32 + offset[1]) * scale[1])
+        .round()
+        .min(framebuffer_size[1]);
+    if right <= left || bottom <= top {
+        return None;
     }
+    Some(Scissor {
+        origin: [left as i32, top as i32],
+        dimensions: [(right - left) as u32, (bottom - top) as u32],
+    })
 }
 
 const VERTICES: &[Vertex] = &[
@@ -0,0 +1,618 @@
+use super::{
+    builder::{HorizontalAlignment, VerticalAlignment},
+    element::GuiTexture,
+    glyph_cache::GlyphRange,
+    GuiElement, Vertex,
+};
+use crate::{
+    error::GuiError,
+    profiler::ProfilerStage,
+    render::texture::{submit_upload, Texture},
+    Font, GameState,
+};
+use parking_lot::RwLock;
+use rusttype::{point, Scale};
+use std::{collections::HashMap, sync::Arc, time::Instant};
+use vulkano::{
+    buffer::{BufferUsage, CpuAccessibleBuffer},
+    device::Queue,
+    format::Format,
+    image::{Dimensions, ImmutableImage},
+    sync::GpuFuture,
+};
+
+/// The font size that the glyphs are rasterized at, in pixels
+const BASE_SIZE: f32 = 48.0;
+/// The distance from the edge of a glyph at which the distance field is saturated, in pixels of the base size. This is also the padding around every glyph in the atlas.
+const SPREAD: u32 = 6;
+/// The width of the atlas image. The height depends on the amount of glyphs.
+const ATLAS_WIDTH: u32 = 512;
+/// The extra smoothing of the edge of a shadow, in distance field units
+pub(crate) const SHADOW_SOFTNESS: f32 = 0.08;
+/// Distances in the field are stored in a byte, and 0.5 is the edge of the glyph. This leaves a little room for outlines.
+const MAX_OUTLINE_WIDTH: f32 = 0.45;
+
+/// A glyph in the atlas of an [SdfFont].
+struct SdfGlyph {
+    /// The area of the glyph in the atlas as `(x, y, width, height)`, in texture coordinates. This is `None` for glyphs without an outline, like spaces.
+    region: Option<[f32; 4]>,
+    /// The area of the glyph relative to its origin as `(x, y, width, height)`, in pixels of the base size with the y-axis pointing down.
+    bounds: [f32; 4],
+    glyph: rusttype::GlyphId,
+    /// The horizontal advance, in pixels of the base size
+    advance: f32,
+}
+
+/// A font with a signed distance field atlas of its glyphs. Text that is rendered with this font stays crisp at any size, and can have an outline and a shadow.
+///
+/// This is created by calling [GameState::create_sdf_font], and used by [GuiElementBuilder::sdf_text].
+///
+/// Cloning this font is cheap, the atlas is shared.
+///
+/// [GameState::create_sdf_font]: ./struct.GameState.html#method.create_sdf_font
+/// [GuiElementBuilder::sdf_text]: ./state/struct.GuiElementBuilder.html#method.sdf_text
+#[derive(Clone)]
+pub struct SdfFont {
+    inner: Arc<SdfAtlas>,
+}
+
+struct SdfAtlas {
+    font: Font,
+    texture: Texture,
+    glyphs: HashMap<char, SdfGlyph>,
+    /// The upload of the atlas. This is waited on by the first element that is rendered with this font.
+    upload: RwLock<Vec<Box<dyn GpuFuture>>>,
+}
+
+impl SdfFont {
+    /// Rasterize the glyphs in the given ranges, and upload them as a single atlas.
+    pub(crate) fn new(
+        queue: Arc<Queue>,
+        font: &Font,
+        ranges: &[GlyphRange],
+    ) -> Result<Self, GuiError> {
+        let scale = Scale::uniform(BASE_SIZE);
+        let mut fields = Vec::new();
+        let mut glyphs = HashMap::new();
+        for c in ranges.iter().flat_map(|range| range.chars()) {
+            if glyphs.contains_key(&c) {
+                continue;
+            }
+            let scaled = font.glyph(c).scaled(scale);
+            let advance = scaled.h_metrics().advance_width;
+            let glyph = scaled.positioned(point(0.0, 0.0));
+            let mut bounds = [0.0; 4];
+            if let Some(bounding_box) = glyph.pixel_bounding_box() {
+                let (width, height) = (
+                    bounding_box.width() as u32 + SPREAD * 2,
+                    bounding_box.height() as u32 + SPREAD * 2,
+                );
+                let mut coverage = vec![0.0; width as usize * height as usize];
+                glyph.draw(|x, y, v| {
+                    coverage[(y + SPREAD) as usize * width as usize + (x + SPREAD) as usize] = v
+                });
+                bounds = [
+                    (bounding_box.min.x - SPREAD as i32) as f32,
+                    (bounding_box.min.y - SPREAD as i32) as f32,
+                    width as f32,
+                    height as f32,
+                ];
+                fields.push((c, width, height, distance_field(&coverage, width, height)));
+            }
+            glyphs.insert(
+                c,
+                SdfGlyph {
+                    region: None,
+                    bounds,
+                    glyph: glyph.id(),
+                    advance,
+                },
+            );
+        }
+
+        // Place the glyphs in rows, from the tallest to the smallest glyph
+        fields.sort_by_key(|(_, _, height, _)| std::cmp::Reverse(*height));
+        let mut positions = Vec::with_capacity(fields.len());
+        let (mut x, mut y, mut row_height) = (0, 0, 0);
+        for (_, width, height, _) in &fields {
+            if x + width > ATLAS_WIDTH {
+                x = 0;
+                y += row_height;
+                row_height = 0;
+            }
+            positions.push((x, y));
+            x += width;
+            row_height = row_height.max(*height);
+        }
+        let atlas_height = (y + row_height).max(1).next_power_of_two();
+
+        let mut pixels = vec![0u8; (ATLAS_WIDTH * atlas_height) as usize];
+        for ((c, width, height, field), (x, y)) in fields.iter().zip(positions) {
+            for row in 0..*height {
+                let target = ((y + row) * ATLAS_WIDTH + x) as usize;
+                let source = (row * width) as usize;
+                pixels[target..target + *width as usize]
+                    .copy_from_slice(&field[source..source + *width as usize]);
+            }
+            if let Some(glyph) = glyphs.get_mut(c) {
+                glyph.region = Some([
+                    x as f32 / ATLAS_WIDTH as f32,
+                    y as f32 / atlas_height as f32,
+                    *width as f32 / ATLAS_WIDTH as f32,
+                    *height as f32 / atlas_height as f32,
+                ]);
+            }
+        }
+
+        let (texture, future) = ImmutableImage::from_iter(
+            pixels.into_iter(),
+            Dimensions::Dim2d {
+                width: ATLAS_WIDTH,
+                height: atlas_height,
+            },
+            Format::R8Unorm,
+            queue.clone(),
+        )
+        .map_err(|inner| GuiError::CouldNotCreateTexture { inner })?;
+        let upload = submit_upload(&queue, future)
+            .map_err(|inner| GuiError::CouldNotUploadTexture { inner })?;
+
+        Ok(Self {
+            inner: Arc::new(SdfAtlas {
+                font: font.clone(),
+                texture,
+                glyphs,
+                upload: RwLock::new(vec![upload]),
+            }),
+        })
+    }
+
+    /// The font that this atlas was created from.
+    pub fn font(&self) -> &Font {
+        &self.inner.font
+    }
+
+    /// Lay out the text at the given font size, and return the vertices of the glyphs. The vertices are relative to an element of the given size.
+    ///
+    /// Characters that are not in the atlas are skipped.
+    fn layout(
+        &self,
+        text: &str,
+        font_size: f32,
+        alignment: (HorizontalAlignment, VerticalAlignment),
+        (width, height): (f32, f32),
+    ) -> Vec<Vertex> {
+        let atlas = &self.inner;
+        let scale = font_size / BASE_SIZE;
+        let metrics = atlas.font.v_metrics(Scale::uniform(font_size));
+        let line_height = metrics.ascent - metrics.descent + metrics.line_gap;
+        let lines: Vec<&str> = text.lines().collect();
+        let text_height = line_height * lines.len() as f32
+            - if lines.is_empty() {
+                0.0
+            } else {
+                metrics.line_gap
+            };
+        let top = match alignment.1 {
+            VerticalAlignment::Top => 0.0,
+            VerticalAlignment::Center => (height - text_height) / 2.0,
+            VerticalAlignment::Bottom => height - text_height,
+        };
+
+        let mut vertices = Vec::new();
+        for (index, line) in lines.iter().enumerate() {
+            let baseline = top + index as f32 * line_height + metrics.ascent;
+            let mut glyphs = Vec::new();
+            let mut position = 0.0;
+            let mut previous = None;
+            for glyph in line.chars().filter_map(|c| atlas.glyphs.get(&c)) {
+                if let Some(previous) = previous {
+                    position +=
+                        atlas
+                            .font
+                            .pair_kerning(Scale::uniform(font_size), previous, glyph.glyph);
+                }
+                glyphs.push((position, glyph));
+                position += glyph.advance * scale;
+                previous = Some(glyph.glyph);
+            }
+            let left = match alignment.0 {
+                HorizontalAlignment::Left => 0.0,
+                HorizontalAlignment::Center => (width - position) / 2.0,
+                HorizontalAlignment::Right => width - position,
+            };
+
+            for (position, glyph) in glyphs {
+                let [u, v, region_width, region_height] = match glyph.region {
+                    Some(region) => region,
+                    None => continue,
+                };
+                let x = left + position + glyph.bounds[0] * scale;
+                let y = baseline + glyph.bounds[1] * scale;
+                // The GUI has its origin in the bottom-left, the layout in the top-left
+                let (x0, x1) = (x / width, (x + glyph.bounds[2] * scale) / width);
+                let (y0, y1) = (
+                    1.0 - (y + glyph.bounds[3] * scale) / height,
+                    1.0 - y / height,
+                );
+                let (u0, u1, v0, v1) = (u, u + region_width, v + region_height, v);
+                let vertex = |offset, tex_coord| Vertex { offset, tex_coord };
+                // The same winding as the quad of the other elements
+                vertices.extend_from_slice(&[
+                    vertex([x0, y0], [u0, v0]),
+                    vertex([x0, y1], [u0, v1]),
+                    vertex([x1, y0], [u1, v0]),
+                    vertex([x1, y0], [u1, v0]),
+                    vertex([x0, y1], [u0, v1]),
+                    vertex([x1, y1], [u1, v1]),
+                ]);
+            }
+        }
+        vertices
+    }
+}
+
+/// The glyphs of a text element that is rendered with a signed distance field. The glyphs are placed relative to the size of the element, so the text scales with the element.
+pub struct SdfText {
+    pub(crate) atlas: Texture,
+    pub(crate) vertices: Option<Arc<CpuAccessibleBuffer<[Vertex]>>>,
+    pub(crate) color: [f32; 4],
+    pub(crate) outline: Option<(f32, [f32; 4])>,
+    pub(crate) shadow: Option<([f32; 2], [f32; 4])>,
+}
+
+/// A struct that is used to create a [GuiElement] with a text that is rendered with a signed distance field. This is created by calling `GameState::new_gui_element(..).sdf_text(..)`.
+///
+/// Unlike the text of a canvas, this text stays crisp when the element is resized, and can have an outline and a shadow.
+pub struct GuiElementSdfTextBuilder<'a> {
+    game_state: &'a mut GameState,
+    dimensions: (i32, i32, u32, u32),
+    font: SdfFont,
+    font_size: f32,
+    text: String,
+    color: [u8; 4],
+    alignment: (HorizontalAlignment, VerticalAlignment),
+    outline: Option<(f32, [u8; 4])>,
+    shadow: Option<((f32, f32), [u8; 4])>,
+}
+
+impl<'a> GuiElementSdfTextBuilder<'a> {
+    pub(crate) fn new(
+        game_state: &'a mut GameState,
+        dimensions: (i32, i32, u32, u32),
+        font: &SdfFont,
+        font_size: f32,
+        text: String,
+        color: [u8; 4],
+    ) -> Self {
+        Self {
+            game_state,
+            dimensions,
+            font: font.clone(),
+            font_size,
+            text,
+            color,
+            alignment: (HorizontalAlignment::default(), VerticalAlignment::default()),
+            outline: None,
+            shadow: None,
+        }
+    }
+
+    /// Change where the text is placed in the element. This defaults to the center of the element. Newlines in the text start a new line, the text is not wrapped.
+    pub fn with_alignment(
+        mut self,
+        horizontal: HorizontalAlignment,
+        vertical: VerticalAlignment,
+    ) -> Self {
+        self.alignment = (horizontal, vertical);
+        self
+    }
+
+    /// Draw an outline around the text. The width is in pixels at the given font size, and can be at most a few pixels.
+    pub fn with_outline(mut self, width: f32, color: [u8; 4]) -> Self {
+        self.outline = Some((width, color));
+        self
+    }
+
+    /// Draw a soft shadow behind the text. The offset is in pixels, a positive offset moves the shadow to the right and down.
+    pub fn with_shadow(mut self, offset: (f32, f32), color: [u8; 4]) -> Self {
+        self.shadow = Some((offset, color));
+        self
+    }
+
+    /// Finish building the element and return it.
+    /// The returned [GuiElement] has to be stored somewhere, as it will be removed from the engine when dropped.
+    /// Starting next frame, the returned GuiElement will be rendered on the screen.
+    pub fn build(self) -> Result<GuiElement, GuiError> {
+        let start = Instant::now();
+        let (_, _, width, height) = self.dimensions;
+        let vertices = self.font.layout(
+            self.text.trim(),
+            self.font_size,
+            self.alignment,
+            (width.max(1) as f32, height.max(1) as f32),
+        );
+        let vertices = if vertices.is_empty() {
+            None
+        } else {
+            // We assume that the device is valid, so this should never fail
+            Some(
+                CpuAccessibleBuffer::from_iter(
+                    self.game_state.device.clone(),
+                    BufferUsage::all(),
+                    false,
+                    vertices.into_iter(),
+                )
+                .unwrap(),
+            )
+        };
+        // The distance field is stored at the base size, so the outline has to be scaled down
+        let distance_per_pixel = BASE_SIZE / self.font_size / (SPREAD * 2) as f32;
+        let outline = self.outline.map(|(width, color)| {
+            (
+                (width * distance_per_pixel).min(MAX_OUTLINE_WIDTH),
+                to_linear_color(color),
+            )
+        });
+        let text = SdfText {
+            atlas: self.font.inner.texture.clone(),
+            vertices,
+            color: to_linear_color(self.color),
+            outline,
+            shadow: self
+                .shadow
+                .map(|((x, y), color)| ([x, y], to_linear_color(color))),
+        };
+
+        let upload = std::mem::replace(&mut *self.font.inner.upload.write(), Vec::new());
+        let texture_future =
+            upload
+                .into_iter()
+                .fold(None, |joined: Option<Box<dyn GpuFuture>>, future| {
+                    Some(match joined {
+                        Some(joined) => joined.join(future).boxed(),
+                        None => future,
+                    })
+                });
+        let (id, element_ref, element) = GuiElement::from_texture(
+            self.dimensions,
+            GuiTexture::SdfText(Arc::new(text)),
+            texture_future,
+            self.game_state.internal_update_sender.clone(),
+            None,
+        );
+        self.game_state.gui_elements.insert(id, element_ref);
+        self.game_state
+            .profiler
+            .record_nested(ProfilerStage::AssetUpload, start.elapsed());
+        Ok(element)
+    }
+}
+
+/// Convert an sRGB color to the linear color that the shaders expect.
+pub(crate) fn to_linear_color(color: [u8; 4]) -> [f32; 4] {
+    let linear = |c: u8| {
+        let c = c as f32 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    [
+        linear(color[0]),
+        linear(color[1]),
+        linear(color[2]),
+        color[3] as f32 / 255.0,
+    ]
+}
+
+/// Calculate the signed distance field of a glyph, based on the coverage of every pixel. Pixels on the edge of the glyph have a value of 128, pixels `SPREAD` pixels outside the glyph are 0 and pixels `SPREAD` pixels inside the glyph are 255.
+///
+/// The coverage of the edge pixels is used to place the edge between pixels, so the field is smoother than a field of a thresholded image.
+fn distance_field(coverage: &[f32], width: u32, height: u32) -> Vec<u8> {
+    const INF: f32 = 1e20;
+    // The squared distance to the nearest pixel outside and inside the glyph
+    let mut outer: Vec<f32> = coverage
+        .iter()
+        .map(|&a| {
+            if a >= 1.0 {
+                0.0
+            } else if a <= 0.0 {
+                INF
+            } else {
+                (0.5 - a).max(0.0).powi(2)
+            }
+        })
+        .collect();
+    let mut inner: Vec<f32> = coverage
+        .iter()
+        .map(|&a| {
+            if a >= 1.0 {
+                INF
+            } else if a <= 0.0 {
+                0.0
+            } else {
+                (a - 0.5).max(0.0).powi(2)
+            }
+        })
+        .collect();
+    distance_transform(&mut outer, width as usize, height as usize);
+    distance_transform(&mut inner, width as usize, height as usize);
+
+    outer
+        .iter()
+        .zip(&inner)
+        .map(|(outer, inner)| {
+            let distance = outer.sqrt() - inner.sqrt();
+            let value = 0.5 - distance / (SPREAD * 2) as f32;
+            (value.max(0.0).min(1.0) * 255.0).round() as u8
+        })
+        .collect()
+}
+
+/// Replace every value of the grid with the squared euclidean distance to the nearest zero, using the algorithm of Felzenszwalb and Huttenlocher.
+fn distance_transform(grid: &mut [f32], width: usize, height: usize) {
+    let size = width.max(height);
+    let mut f = vec![0.0; size];
+    let mut d = vec![0.0; size];
+    let mut v = vec![0; size];
+    let mut z = vec![0.0; size + 1];
+    for x in 0..width {
+        for (y, value) in f.iter_mut().enumerate().take(height) {
+            *value = grid[y * width + x];
+        }
+        distance_transform_1d(&f[..height], &mut d, &mut v, &mut z);
+        for (y, value) in d.iter().enumerate().take(height) {
+            grid[y * width + x] = *value;
+        }
+    }
+    for y in 0..height {
+        f[..width].copy_from_slice(&grid[y * width..(y + 1) * width]);
+        distance_transform_1d(&f[..width], &mut d, &mut v, &mut z);
+        grid[y * width..(y + 1) * width].copy_from_slice(&d[..width]);
+    }
+}
+
+/// The one dimensional distance transform of `f`, written into `d`. `v` and `z` are used as scratch space, and have to be at least as long as `f`, and one longer respectively.
+fn distance_transform_1d(f: &[f32], d: &mut [f32], v: &mut [usize], z: &mut [f32]) {
+    let n = f.len();
+    if n == 0 {
+        return;
+    }
+    let intersection = |q: usize, r: usize| {
+        let (qf, rf) = (q as f32, r as f32);
+        ((f[q] + qf * qf) - (f[r] + rf * rf)) / (2.0 * qf - 2.0 * rf)
+    };
+    let mut k = 0;
+    v[0] = 0;
+    z[0] = std::f32::NEG_INFINITY;
+    z[1] = std::f32::INFINITY;
+    for q in 1..n {
+        let mut s = intersection(q, v[k]);
+        while s <= z[k] {
+            k -= 1;
+            s = intersection(q, v[k]);
+        }
+        k += 1;
+        v[k] = q;
+        z[k] = s;
+        z[k + 1] = std::f32::INFINITY;
+    }
+    k = 0;
+    for (q, d) in d.iter_mut().enumerate().take(n) {
+        while z[k + 1] < q as f32 {
+            k += 1;
+        }
+        let r = v[k] as f32;
+        *d = (q as f32 - r).powi(2) + f[v[k]];
+    }
+}
+
+pub mod sdf_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "#version 450
+layout(location = 0) in vec2 offset;
+layout(location = 2) in vec2 tex_coord;
+
+layout(location = 0) out vec2 fragment_tex_coord;
+
+layout(set = 0, binding = 0) uniform Data {
+    vec4 color;
+    vec4 outline_color;
+    vec2 screen_size;
+    vec2 position;
+    vec2 size;
+    float outline_width;
+    float softness;
+} uniforms;
+
+void main() {
+    vec2 half_screen_size = uniforms.screen_size / 2;
+
+    gl_Position = vec4(
+        (uniforms.position / half_screen_size - vec2(1.0, 1.0)) +
+        (offset * uniforms.size / half_screen_size),
+        0.0, 1.0);
+    fragment_tex_coord = tex_coord;
+}
+"
+    }
+}
+
+pub mod sdf_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "#version 450
+
+layout(location = 0) in vec2 fragment_tex_coord;
+
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform Data {
+    vec4 color;
+    vec4 outline_color;
+    vec2 screen_size;
+    vec2 position;
+    vec2 size;
+    float outline_width;
+    float softness;
+} uniforms;
+layout(set = 0, binding = 1) uniform sampler2D atlas;
+
+void main() {
+    float distance = texture(atlas, fragment_tex_coord).r;
+    // Keep the edge about a pixel wide at every scale
+    float smoothing = max(fwidth(distance) * 0.7, uniforms.softness);
+
+    float fill = smoothstep(0.5 - smoothing, 0.5 + smoothing, distance);
+    float edge = 0.5 - uniforms.outline_width;
+    float outline = smoothstep(edge - smoothing, edge + smoothing, distance);
+
+    vec4 color = mix(uniforms.outline_color, uniforms.color, fill);
+    f_color = vec4(color.rgb, color.a * outline);
+}
+"
+    }
+}
+
+#[test]
+fn test_distance_field() {
+    let mut grid = vec![
+        1e20, 1e20, 1e20, 1e20, 1e20, //
+        1e20, 0.0, 1e20, 1e20, 1e20, //
+        1e20, 1e20, 1e20, 1e20, 0.0,
+    ];
+    distance_transform(&mut grid, 5, 3);
+    assert_eq!(
+        vec![
+            2.0, 1.0, 2.0, 5.0, 4.0, //
+            1.0, 0.0, 1.0, 2.0, 1.0, //
+            2.0, 1.0, 2.0, 1.0, 0.0,
+        ],
+        grid
+    );
+
+    // A filled square in the middle of an empty field
+    let (width, height) = (SPREAD * 4, SPREAD * 4);
+    let coverage: Vec<f32> = (0..width * height)
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            let inside = |c: u32| c >= SPREAD && c < SPREAD * 3;
+            if inside(x) && inside(y) {
+                1.0
+            } else {
+                0.0
+            }
+        })
+        .collect();
+    let field = distance_field(&coverage, width, height);
+    let at = |x: u32, y: u32| field[(y * width + x) as usize];
+    assert_eq!(255, at(SPREAD * 2, SPREAD * 2));
+    assert_eq!(0, at(0, 0));
+    // The values increase towards the inside, and cross the middle at the edge
+    assert!(at(SPREAD - 1, SPREAD * 2) < 128);
+    assert!(at(SPREAD, SPREAD * 2) > 128);
+    assert!(at(SPREAD - 2, SPREAD * 2) < at(SPREAD - 1, SPREAD * 2));
+}
@@ -0,0 +1,235 @@
+use crate::Font;
+
+/// The style of GUI elements that are created by the engine, like notifications and subtitles.
+#[derive(Clone)]
+pub struct GuiTheme {
+    /// The font that is used for text. This must be set before the engine can render any text, e.g. by calling:
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// state.gui.theme.font = Some(state.load_font("assets/roboto.ttf").unwrap());
+    /// ```
+    pub font: Option<Font>,
+    /// The size of the font. Defaults to `24`.
+    pub font_size: u16,
+    /// The color of the text. Defaults to white.
+    pub text_color: [u8; 4],
+    /// The background color of elements. Defaults to a transparent black.
+    pub background_color: [u8; 4],
+    /// The border of elements as `(width, color)`. Defaults to no border.
+    pub border: Option<(u16, [u8; 4])>,
+}
+
+impl Default for GuiTheme {
+    fn default() -> Self {
+        Self {
+            font: None,
+            font_size: 24,
+            text_color: crate::color::WHITE,
+            background_color: [0, 0, 0, 180],
+            border: None,
+        }
+    }
+}
+
+/// Settings of the GUI. This can be used to make GUI layouts that work on any resolution.
+///
+/// By default, GUI elements are placed in physical pixels of the window. When a
+/// [virtual_resolution](#structfield.virtual_resolution) is set, the dimensions of all GUI elements
+/// are in the virtual resolution instead, and will be scaled to fit the window. With
+/// [logical_pixels](#structfield.logical_pixels), they are in logical pixels of the window.
+///
+/// ```no_run
+/// # use crystal_engine::*;
+/// # let mut state: GameState = unsafe { std::mem::zeroed() };
+/// // Design the GUI for a 1920x1080 screen
+/// state.gui.virtual_resolution = Some((1920, 1080));
+///
+/// // This element will always be in the bottom-left quarter of the screen
+/// let (x, y, width, height) = (0, 0, 960, 540);
+/// ```
+pub struct GuiState {
+    /// The default style of GUI elements that are created by the engine, like notifications.
+    pub theme: GuiTheme,
+
+    /// The margin around the edge of the screen that the engine keeps clear when placing elements
+    /// like notifications, as a fraction of the GUI size. Defaults to `0.05`, which is 5% on every side.
+    pub safe_area_margin: f32,
+
+    /// The resolution that GUI elements are designed for. When this is set, the dimensions of all
+    /// GUI elements are in this resolution, and the GUI is scaled uniformly to fit the window.
+    ///
+    /// If the aspect ratio of the window does not match the aspect ratio of the virtual
+    /// resolution, the GUI will be centered in the window.
+    ///
+    /// Note that canvas elements are rasterized at their virtual size, so text might look blurry
+    /// when the window is a lot bigger than the virtual resolution.
+    pub virtual_resolution: Option<(u32, u32)>,
+
+    /// When this is `true`, the dimensions of GUI elements are in logical pixels instead of physical pixels, so the GUI has the same size on HiDPI monitors as on regular monitors. A logical pixel is [GameState::scale_factor] physical pixels. This is ignored when the [virtual_resolution](#structfield.virtual_resolution) is set. Defaults to `false`.
+    ///
+    /// Like with the virtual resolution, canvas elements are rasterized at their size in logical pixels, so text might look blurry on HiDPI monitors.
+    ///
+    /// [GameState::scale_factor]: ../struct.GameState.html#method.scale_factor
+    pub logical_pixels: bool,
+
+    pub(crate) window_size: (u32, u32),
+    pub(crate) scale_factor: f64,
+}
+
+impl GuiState {
+    pub(crate) fn new(window_size: (u32, u32), scale_factor: f64) -> Self {
+        Self {
+            theme: GuiTheme::default(),
+            safe_area_margin: 0.05,
+            virtual_resolution: None,
+            logical_pixels: false,
+            window_size,
+            scale_factor,
+        }
+    }
+
+    /// Get the size of the GUI space. This is the virtual resolution if it is set, and the size of the window otherwise. With [logical_pixels](#structfield.logical_pixels), the size of the window is in logical pixels.
+    pub fn size(&self) -> (f32, f32) {
+        match self.virtual_resolution {
+            Some((width, height)) => (width as f32, height as f32),
+            None => {
+                let scale = self.pixel_scale();
+                (
+                    self.window_size.0 as f32 / scale,
+                    self.window_size.1 as f32 / scale,
+                )
+            }
+        }
+    }
+
+    /// Convert DPI-independent points to GUI pixels.
+    ///
+    /// Without a virtual resolution, this multiplies the points with the scale factor of the monitor the window is on.
+    /// With a virtual resolution or [logical_pixels](#structfield.logical_pixels), GUI pixels are already independent of the DPI, so `points` is returned as-is.
+    pub fn pt(&self, points: f32) -> f32 {
+        if self.virtual_resolution.is_some() || self.logical_pixels {
+            points
+        } else {
+            points * self.scale_factor as f32
+        }
+    }
+
+    /// The amount of physical pixels per GUI pixel when there is no virtual resolution.
+    fn pixel_scale(&self) -> f32 {
+        if self.logical_pixels && self.scale_factor > 0.0 {
+            self.scale_factor as f32
+        } else {
+            1.0
+        }
+    }
+
+    /// Get a percentage of the width of the GUI space. `vw(50.0)` is half the width of the GUI.
+    pub fn vw(&self, percentage: f32) -> f32 {
+        self.size().0 * percentage / 100.0
+    }
+
+    /// Get a percentage of the height of the GUI space. `vh(50.0)` is half the height of the GUI.
+    pub fn vh(&self, percentage: f32) -> f32 {
+        self.size().1 * percentage / 100.0
+    }
+
+    /// Turn a floating point `(x, y, width, height)` into the dimensions that are used by [GameState::new_gui_element](../struct.GameState.html#method.new_gui_element).
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// let gui = &state.gui;
+    /// let dimensions = gui.rect(gui.vw(10.0), gui.vh(10.0), gui.pt(200.0), gui.pt(40.0));
+    /// ```
+    pub fn rect(&self, x: f32, y: f32, width: f32, height: f32) -> (i32, i32, u32, u32) {
+        (
+            x.round() as i32,
+            y.round() as i32,
+            width.max(0.0).round() as u32,
+            height.max(0.0).round() as u32,
+        )
+    }
+
+    /// Get the safe area of the GUI as `(x, y, width, height)`. This is the GUI space with the [safe_area_margin](#structfield.safe_area_margin) removed on every side.
+    pub fn safe_area(&self) -> (f32, f32, f32, f32) {
+        let (width, height) = self.size();
+        let margin_x = width * self.safe_area_margin;
+        let margin_y = height * self.safe_area_margin;
+        (
+            margin_x,
+            margin_y,
+            width - 2.0 * margin_x,
+            height - 2.0 * margin_y,
+        )
+    }
+
+    /// Get the screen size and offset that the GUI pipeline should use to render the elements.
+    pub(crate) fn projection(&self, dimensions: [f32; 2]) -> ([f32; 2], [f32; 2]) {
+        match self.virtual_resolution {
+            None => {
+                let scale = self.pixel_scale();
+                ([dimensions[0] / scale, dimensions[1] / scale], [0.0, 0.0])
+            }
+            Some((width, height)) => {
+                let (width, height) = (width as f32, height as f32);
+                let scale = (dimensions[0] / width).min(dimensions[1] / height);
+                let screen_size = [dimensions[0] / scale, dimensions[1] / scale];
+                let offset = [
+                    (screen_size[0] - width) / 2.0,
+                    (screen_size[1] - height) / 2.0,
+                ];
+                (screen_size, offset)
+            }
+        }
+    }
+
+    /// Convert a position in physical pixels of the window, with the origin at the top-left like the `CursorMoved` event, to a position in GUI coordinates.
+    pub fn window_to_gui(&self, position: (f64, f64)) -> (f32, f32) {
+        let dimensions = [self.window_size.0 as f32, self.window_size.1 as f32];
+        let (screen_size, offset) = self.projection(dimensions);
+        let x = position.0 as f32 * screen_size[0] / dimensions[0] - offset[0];
+        let y = (dimensions[1] - position.1 as f32) * screen_size[1] / dimensions[1] - offset[1];
+        (x, y)
+    }
+}
+
+#[test]
+fn test_virtual_resolution_projection() {
+    let mut state = GuiState::new((800, 600), 1.0);
+    assert_eq!(
+        ([800.0, 600.0], [0.0, 0.0]),
+        state.projection([800.0, 600.0])
+    );
+
+    state.virtual_resolution = Some((1920, 1080));
+    let (screen_size, offset) = state.projection([960.0, 1080.0]);
+    assert_eq!([1920.0, 2160.0], screen_size);
+    assert_eq!([0.0, 540.0], offset);
+}
+
+#[test]
+fn test_window_to_gui() {
+    let mut state = GuiState::new((800, 600), 1.0);
+    assert_eq!((100.0, 500.0), state.window_to_gui((100.0, 100.0)));
+
+    state.virtual_resolution = Some((1600, 1200));
+    assert_eq!((200.0, 1000.0), state.window_to_gui((100.0, 100.0)));
+}
+
+#[test]
+fn test_logical_pixels() {
+    let mut state = GuiState::new((1600, 1200), 2.0);
+    assert_eq!((1600.0, 1200.0), state.size());
+    assert_eq!(40.0, state.pt(20.0));
+
+    state.logical_pixels = true;
+    assert_eq!((800.0, 600.0), state.size());
+    assert_eq!(20.0, state.pt(20.0));
+    assert_eq!(
+        ([800.0, 600.0], [0.0, 0.0]),
+        state.projection([1600.0, 1200.0])
+    );
+    assert_eq!((50.0, 550.0), state.window_to_gui((100.0, 100.0)));
+}
@@ -0,0 +1,649 @@
+use super::GuiElement;
+use crate::{error::GuiError, GameState};
+use winit::event::{ElementState, MouseButton, WindowEvent};
+
+/// The width of the hue and alpha bars, as a fraction of the width of the color picker
+const BAR_WIDTH: f32 = 0.12;
+/// The height of the gradient strip, as a fraction of the height of the gradient editor
+const GRADIENT_HEIGHT: f32 = 0.25;
+/// How close the cursor has to be to a gradient stop to select it, in pixels
+const STOP_SELECT_DISTANCE: i32 = 6;
+/// The width of the markers of the gradient stops, in pixels
+const STOP_MARKER_WIDTH: i32 = 5;
+/// The size of the squares of the checkerboard that is drawn behind transparent colors, in pixels
+const CHECKERBOARD_SIZE: u32 = 6;
+
+/// A list of colors at positions between `0.0` and `1.0`. This is edited by the gradient editor, see [GameState::add_gradient_editor].
+///
+/// [GameState::add_gradient_editor]: ../struct.GameState.html#method.add_gradient_editor
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gradient {
+    /// The stops of the gradient as `(position, color)`, ordered by their position.
+    pub stops: Vec<(f32, [u8; 4])>,
+}
+
+impl Gradient {
+    /// Create a new gradient. The stops will be sorted by their position.
+    pub fn new(stops: impl IntoIterator<Item = (f32, [u8; 4])>) -> Self {
+        let mut gradient = Self {
+            stops: stops.into_iter().collect(),
+        };
+        gradient.sort();
+        gradient
+    }
+
+    /// Get the color of the gradient at the given position. Positions before the first stop or after the last stop get the color of that stop.
+    ///
+    /// This returns a transparent color if the gradient has no stops.
+    pub fn sample(&self, position: f32) -> [u8; 4] {
+        let (first, last) = match (self.stops.first(), self.stops.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return crate::color::TRANSPARENT,
+        };
+        if position <= first.0 {
+            return first.1;
+        }
+        for pair in self.stops.windows(2) {
+            let (start, end) = (pair[0], pair[1]);
+            if position <= end.0 {
+                let factor = if end.0 > start.0 {
+                    (position - start.0) / (end.0 - start.0)
+                } else {
+                    0.0
+                };
+                return lerp_color(start.1, end.1, factor);
+            }
+        }
+        last.1
+    }
+
+    fn sort(&mut self) {
+        self.stops
+            .sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+    }
+}
+
+fn lerp_color(a: [u8; 4], b: [u8; 4], factor: f32) -> [u8; 4] {
+    let mut result = [0; 4];
+    for (result, (&a, &b)) in result.iter_mut().zip(a.iter().zip(&b)) {
+        *result = (a as f32 + (b as f32 - a as f32) * factor).round() as u8;
+    }
+    result
+}
+
+/// The id of a debug widget. This can be used to remove the widget with [GameState::remove_debug_widget].
+///
+/// [GameState::remove_debug_widget]: ../struct.GameState.html#method.remove_debug_widget
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DebugWidgetId(u64);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Hsva {
+    hue: f32,
+    saturation: f32,
+    value: f32,
+    alpha: f32,
+}
+
+impl Hsva {
+    fn from_rgba(color: [u8; 4]) -> Self {
+        let [r, g, b, a] = [
+            color[0] as f32 / 255.0,
+            color[1] as f32 / 255.0,
+            color[2] as f32 / 255.0,
+            color[3] as f32 / 255.0,
+        ];
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let delta = max - min;
+        let hue = if delta == 0.0 {
+            0.0
+        } else if max == r {
+            ((g - b) / delta).rem_euclid(6.0) / 6.0
+        } else if max == g {
+            ((b - r) / delta + 2.0) / 6.0
+        } else {
+            ((r - g) / delta + 4.0) / 6.0
+        };
+        Self {
+            hue,
+            saturation: if max == 0.0 { 0.0 } else { delta / max },
+            value: max,
+            alpha: a,
+        }
+    }
+
+    fn to_rgba(self) -> [u8; 4] {
+        let [r, g, b] = hsv_to_rgb(self.hue, self.saturation, self.value);
+        [r, g, b, (self.alpha * 255.0).round() as u8]
+    }
+}
+
+fn hsv_to_rgb(hue: f32, saturation: f32, value: f32) -> [u8; 3] {
+    let h = (hue.rem_euclid(1.0)) * 6.0;
+    let c = value * saturation;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let m = value - c;
+    [
+        ((r + m) * 255.0).round() as u8,
+        ((g + m) * 255.0).round() as u8,
+        ((b + m) * 255.0).round() as u8,
+    ]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PickerArea {
+    SaturationValue,
+    Hue,
+    Alpha,
+}
+
+/// A color picker that is drawn into a region of a widget. The region has a saturation/value square, a hue bar and an alpha bar.
+struct Picker {
+    color: Hsva,
+    drag: Option<PickerArea>,
+}
+
+impl Picker {
+    fn new(color: [u8; 4]) -> Self {
+        Self {
+            color: Hsva::from_rgba(color),
+            drag: None,
+        }
+    }
+
+    fn layout(size: (u32, u32)) -> (u32, u32) {
+        let bar = ((size.0 as f32 * BAR_WIDTH) as u32).max(1);
+        let square = size.0.saturating_sub(2 * bar);
+        (square, bar)
+    }
+
+    fn area_at(position: (i32, i32), size: (u32, u32)) -> Option<PickerArea> {
+        let (square, bar) = Self::layout(size);
+        let (x, y) = position;
+        if x < 0 || y < 0 || y >= size.1 as i32 {
+            None
+        } else if x < square as i32 {
+            Some(PickerArea::SaturationValue)
+        } else if x < (square + bar) as i32 {
+            Some(PickerArea::Hue)
+        } else if x < size.0 as i32 {
+            Some(PickerArea::Alpha)
+        } else {
+            None
+        }
+    }
+
+    /// Update the color, based on a position in the given area. The position is clamped to the area.
+    fn update(&mut self, area: PickerArea, position: (i32, i32), size: (u32, u32)) {
+        let (square, _) = Self::layout(size);
+        let vertical = fraction(position.1, size.1);
+        match area {
+            PickerArea::SaturationValue => {
+                self.color.saturation = fraction(position.0, square);
+                self.color.value = 1.0 - vertical;
+            }
+            PickerArea::Hue => self.color.hue = vertical,
+            PickerArea::Alpha => self.color.alpha = 1.0 - vertical,
+        }
+    }
+
+    fn draw(&self, image: &mut image::RgbaImage, offset: (u32, u32), size: (u32, u32)) {
+        let (square, bar) = Self::layout(size);
+        let marker_y = |fraction: f32| (fraction * (size.1.max(1) - 1) as f32).round() as u32;
+        let sv_marker = (
+            (self.color.saturation * (square.max(1) - 1) as f32).round() as i32,
+            marker_y(1.0 - self.color.value) as i32,
+        );
+        let hue_marker = marker_y(self.color.hue);
+        let alpha_marker = marker_y(1.0 - self.color.alpha);
+        let [r, g, b, _] = self.color.to_rgba();
+
+        for y in 0..size.1 {
+            let vertical = fraction(y as i32, size.1);
+            for x in 0..size.0 {
+                let pixel = if x < square {
+                    let distance_squared =
+                        (x as i32 - sv_marker.0).pow(2) + (y as i32 - sv_marker.1).pow(2);
+                    if distance_squared >= 9 && distance_squared <= 16 {
+                        marker_color(self.color.value)
+                    } else {
+                        let [r, g, b] =
+                            hsv_to_rgb(self.color.hue, fraction(x as i32, square), 1.0 - vertical);
+                        [r, g, b, 255]
+                    }
+                } else if x < square + bar {
+                    if y == hue_marker {
+                        marker_color(0.0)
+                    } else {
+                        let [r, g, b] = hsv_to_rgb(vertical, 1.0, 1.0);
+                        [r, g, b, 255]
+                    }
+                } else if y == alpha_marker {
+                    marker_color(0.0)
+                } else {
+                    blend_checkerboard([r, g, b, ((1.0 - vertical) * 255.0) as u8], x, y)
+                };
+                image.put_pixel(offset.0 + x, offset.1 + y, image::Rgba(pixel));
+            }
+        }
+    }
+}
+
+/// Get the relative position of `value` in `0..size`, clamped to `0.0..=1.0`.
+fn fraction(value: i32, size: u32) -> f32 {
+    if size <= 1 {
+        0.0
+    } else {
+        (value as f32 / (size - 1) as f32).max(0.0).min(1.0)
+    }
+}
+
+/// A color for markers that is visible on a background with the given brightness.
+fn marker_color(background_value: f32) -> [u8; 4] {
+    if background_value > 0.5 {
+        crate::color::BLACK
+    } else {
+        crate::color::WHITE
+    }
+}
+
+fn blend_checkerboard(color: [u8; 4], x: u32, y: u32) -> [u8; 4] {
+    let background = if (x / CHECKERBOARD_SIZE + y / CHECKERBOARD_SIZE) % 2 == 0 {
+        [200, 200, 200, 255]
+    } else {
+        [120, 120, 120, 255]
+    };
+    let mut result = lerp_color(background, color, color[3] as f32 / 255.0);
+    result[3] = 255;
+    result
+}
+
+type ColorCallback = Box<dyn FnMut(&mut GameState, [u8; 4])>;
+type GradientCallback = Box<dyn FnMut(&mut GameState, &Gradient)>;
+
+enum WidgetKind {
+    ColorPicker {
+        picker: Picker,
+        callback: ColorCallback,
+    },
+    GradientEditor {
+        gradient: Gradient,
+        selected: usize,
+        dragging_stop: bool,
+        picker: Picker,
+        callback: GradientCallback,
+    },
+}
+
+struct Widget {
+    id: u64,
+    element: GuiElement,
+    kind: WidgetKind,
+}
+
+impl Widget {
+    fn size(&self) -> (u32, u32) {
+        let dimensions = self.element.data.read().dimensions;
+        (dimensions.2, dimensions.3)
+    }
+
+    /// Convert a position in GUI coordinates to a position in the image of this widget, with the origin at the top-left.
+    fn local_position(&self, position: (f32, f32)) -> (i32, i32) {
+        let (x, y, _, height) = self.element.data.read().dimensions;
+        (
+            (position.0 - x as f32).floor() as i32,
+            (y as f32 + height as f32 - position.1).floor() as i32,
+        )
+    }
+
+    fn contains(&self, position: (f32, f32)) -> bool {
+        let (x, y) = self.local_position(position);
+        let (width, height) = self.size();
+        x >= 0 && y >= 0 && x < width as i32 && y < height as i32
+    }
+
+    fn is_dragging(&self) -> bool {
+        match &self.kind {
+            WidgetKind::ColorPicker { picker, .. } => picker.drag.is_some(),
+            WidgetKind::GradientEditor {
+                picker,
+                dragging_stop,
+                ..
+            } => picker.drag.is_some() || *dragging_stop,
+        }
+    }
+
+    fn stop_dragging(&mut self) {
+        match &mut self.kind {
+            WidgetKind::ColorPicker { picker, .. } => picker.drag = None,
+            WidgetKind::GradientEditor {
+                picker,
+                dragging_stop,
+                ..
+            } => {
+                picker.drag = None;
+                *dragging_stop = false;
+            }
+        }
+    }
+
+    fn press(&mut self, game_state: &mut GameState, position: (i32, i32), button: MouseButton) {
+        let size = self.size();
+        match &mut self.kind {
+            WidgetKind::ColorPicker { picker, .. } => {
+                if button == MouseButton::Left {
+                    picker.drag = Picker::area_at(position, size);
+                }
+            }
+            WidgetKind::GradientEditor {
+                gradient,
+                selected,
+                dragging_stop,
+                picker,
+                ..
+            } => {
+                let strip_height = gradient_strip_height(size);
+                if position.1 < strip_height as i32 {
+                    let width = size.0.max(2) - 1;
+                    let nearest = gradient
+                        .stops
+                        .iter()
+                        .enumerate()
+                        .map(|(i, (t, _))| (i, ((t * width as f32) as i32 - position.0).abs()))
+                        .min_by_key(|(_, distance)| *distance)
+                        .filter(|(_, distance)| *distance <= STOP_SELECT_DISTANCE)
+                        .map(|(i, _)| i);
+                    match (nearest, button) {
+                        (Some(index), MouseButton::Right) if gradient.stops.len() > 1 => {
+                            gradient.stops.remove(index);
+                            *selected = (*selected).min(gradient.stops.len() - 1);
+                        }
+                        (Some(index), MouseButton::Left) => {
+                            *selected = index;
+                            *dragging_stop = true;
+                        }
+                        (None, MouseButton::Left) => {
+                            let t = fraction(position.0, size.0);
+                            let color = gradient.sample(t);
+                            gradient.stops.push((t, color));
+                            gradient.sort();
+                            *selected = gradient
+                                .stops
+                                .iter()
+                                .position(|(stop, _)| *stop == t)
+                                .unwrap_or(0);
+                            *dragging_stop = true;
+                        }
+                        _ => return,
+                    }
+                    if let Some((_, color)) = gradient.stops.get(*selected) {
+                        *picker = Picker::new(*color);
+                    }
+                } else if button == MouseButton::Left {
+                    let picker_position = (position.0, position.1 - strip_height as i32);
+                    picker.drag = Picker::area_at(picker_position, (size.0, size.1 - strip_height));
+                }
+            }
+        }
+        self.drag(game_state, position);
+    }
+
+    fn drag(&mut self, game_state: &mut GameState, position: (i32, i32)) {
+        let size = self.size();
+        match &mut self.kind {
+            WidgetKind::ColorPicker { picker, callback } => {
+                if let Some(area) = picker.drag {
+                    picker.update(area, position, size);
+                    callback(game_state, picker.color.to_rgba());
+                }
+            }
+            WidgetKind::GradientEditor {
+                gradient,
+                selected,
+                dragging_stop,
+                picker,
+                callback,
+            } => {
+                let strip_height = gradient_strip_height(size);
+                if *dragging_stop {
+                    let stop = (fraction(position.0, size.0), gradient.stops[*selected].1);
+                    gradient.stops[*selected] = stop;
+                    gradient.sort();
+                    *selected = gradient.stops.iter().position(|s| *s == stop).unwrap_or(0);
+                } else if let Some(area) = picker.drag {
+                    let picker_position = (position.0, position.1 - strip_height as i32);
+                    picker.update(area, picker_position, (size.0, size.1 - strip_height));
+                    gradient.stops[*selected].1 = picker.color.to_rgba();
+                }
+                callback(game_state, gradient);
+            }
+        }
+        self.redraw(game_state);
+    }
+
+    fn redraw(&self, game_state: &GameState) {
+        let size = self.size();
+        let mut image = image::RgbaImage::new(size.0, size.1);
+        match &self.kind {
+            WidgetKind::ColorPicker { picker, .. } => picker.draw(&mut image, (0, 0), size),
+            WidgetKind::GradientEditor {
+                gradient,
+                selected,
+                picker,
+                ..
+            } => {
+                let strip_height = gradient_strip_height(size);
+                let marker_height = strip_height / 3;
+                let width = size.0.max(2) - 1;
+                for y in 0..strip_height {
+                    for x in 0..size.0 {
+                        let color = if y < strip_height - marker_height {
+                            blend_checkerboard(gradient.sample(fraction(x as i32, size.0)), x, y)
+                        } else {
+                            [40, 40, 40, 255]
+                        };
+                        image.put_pixel(x, y, image::Rgba(color));
+                    }
+                }
+                for (index, (t, color)) in gradient.stops.iter().enumerate() {
+                    let center = (t * width as f32) as i32;
+                    let border = if index == *selected {
+                        crate::color::WHITE
+                    } else {
+                        crate::color::BLACK
+                    };
+                    for x in (center - STOP_MARKER_WIDTH / 2)..=(center + STOP_MARKER_WIDTH / 2) {
+                        if x < 0 || x >= size.0 as i32 {
+                            continue;
+                        }
+                        for y in (strip_height - marker_height)..strip_height {
+                            let is_edge = (x - center).abs() == STOP_MARKER_WIDTH / 2
+                                || y == strip_height - marker_height
+                                || y == strip_height - 1;
+                            let pixel = if is_edge {
+                                border
+                            } else {
+                                blend_checkerboard(*color, x as u32, y)
+                            };
+                            image.put_pixel(x as u32, y, image::Rgba(pixel));
+                        }
+                    }
+                }
+                picker.draw(
+                    &mut image,
+                    (0, strip_height),
+                    (size.0, size.1 - strip_height),
+                );
+            }
+        }
+        if let Err(e) = self.element.write_pixels(game_state, image) {
+            eprintln!("Could not update debug widget: {:?}", e);
+        }
+    }
+}
+
+fn gradient_strip_height(size: (u32, u32)) -> u32 {
+    ((size.1 as f32 * GRADIENT_HEIGHT) as u32)
+        .max(3)
+        .min(size.1)
+}
+
+/// The color pickers and gradient editors that are on the screen.
+#[derive(Default)]
+pub(crate) struct DebugWidgets {
+    widgets: Vec<Widget>,
+    /// The last known position of the cursor, in physical pixels of the window
+    cursor: (f64, f64),
+    next_id: u64,
+    /// Widgets that were removed while the widgets were taken out of the game state
+    removed: Vec<u64>,
+}
+
+impl DebugWidgets {
+    fn add(
+        game_state: &mut GameState,
+        dimensions: (i32, i32, u32, u32),
+        kind: WidgetKind,
+    ) -> Result<DebugWidgetId, GuiError> {
+        let element = game_state
+            .new_gui_element(dimensions)
+            .canvas()
+            .with_double_buffering(true)
+            .build()?;
+        let id = game_state.debug_widgets.next_id;
+        game_state.debug_widgets.next_id += 1;
+        let widget = Widget { id, element, kind };
+        widget.redraw(game_state);
+        game_state.debug_widgets.widgets.push(widget);
+        Ok(DebugWidgetId(id))
+    }
+
+    pub fn add_color_picker(
+        game_state: &mut GameState,
+        dimensions: (i32, i32, u32, u32),
+        color: [u8; 4],
+        callback: impl FnMut(&mut GameState, [u8; 4]) + 'static,
+    ) -> Result<DebugWidgetId, GuiError> {
+        let kind = WidgetKind::ColorPicker {
+            picker: Picker::new(color),
+            callback: Box::new(callback),
+        };
+        Self::add(game_state, dimensions, kind)
+    }
+
+    pub fn add_gradient_editor(
+        game_state: &mut GameState,
+        dimensions: (i32, i32, u32, u32),
+        mut gradient: Gradient,
+        callback: impl FnMut(&mut GameState, &Gradient) + 'static,
+    ) -> Result<DebugWidgetId, GuiError> {
+        if gradient.stops.is_empty() {
+            gradient.stops.push((0.0, crate::color::WHITE));
+        }
+        gradient.sort();
+        let kind = WidgetKind::GradientEditor {
+            picker: Picker::new(gradient.stops[0].1),
+            gradient,
+            selected: 0,
+            dragging_stop: false,
+            callback: Box::new(callback),
+        };
+        Self::add(game_state, dimensions, kind)
+    }
+
+    pub fn remove(&mut self, id: DebugWidgetId) {
+        let count = self.widgets.len();
+        self.widgets.retain(|w| w.id != id.0);
+        if count == self.widgets.len() {
+            self.removed.push(id.0);
+        }
+    }
+
+    /// Handle a window event. Returns `true` if the event was used by a widget.
+    pub fn handle_event(&mut self, game_state: &mut GameState, event: &WindowEvent) -> bool {
+        let consumed = match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor = (position.x, position.y);
+                let position = game_state.gui.window_to_gui(self.cursor);
+                let mut consumed = false;
+                for widget in self.widgets.iter_mut().filter(|w| w.is_dragging()) {
+                    let local = widget.local_position(position);
+                    widget.drag(game_state, local);
+                    consumed = true;
+                }
+                consumed
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button,
+                ..
+            } => {
+                let position = game_state.gui.window_to_gui(self.cursor);
+                // Elements that are created later are drawn on top, so the last widget under the
+                // cursor receives the click
+                match self.widgets.iter_mut().rev().find(|w| w.contains(position)) {
+                    Some(widget) => {
+                        let local = widget.local_position(position);
+                        widget.press(game_state, local, *button);
+                        true
+                    }
+                    None => false,
+                }
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Released,
+                ..
+            } => {
+                let mut consumed = false;
+                for widget in self.widgets.iter_mut().filter(|w| w.is_dragging()) {
+                    widget.stop_dragging();
+                    consumed = true;
+                }
+                consumed
+            }
+            _ => false,
+        };
+
+        // Apply the changes that the callbacks made to the widgets
+        let mut added = std::mem::take(&mut game_state.debug_widgets);
+        self.widgets.append(&mut added.widgets);
+        self.next_id = self.next_id.max(added.next_id);
+        let removed = std::mem::take(&mut self.removed)
+            .into_iter()
+            .chain(added.removed)
+            .collect::<Vec<_>>();
+        self.widgets.retain(|w| !removed.contains(&w.id));
+
+        consumed
+    }
+}
+
+#[test]
+fn test_gradient_sample() {
+    let gradient = Gradient::new(vec![(1.0, [255, 255, 255, 255]), (0.0, [0, 0, 0, 255])]);
+    assert_eq!(0.0, gradient.stops[0].0);
+    assert_eq!([0, 0, 0, 255], gradient.sample(-1.0));
+    assert_eq!([128, 128, 128, 255], gradient.sample(0.5));
+    assert_eq!([255, 255, 255, 255], gradient.sample(2.0));
+}
+
+#[test]
+fn test_hsva_roundtrip() {
+    for color in &[
+        [255, 0, 0, 255],
+        [12, 200, 99, 128],
+        [0, 0, 0, 0],
+        [80, 80, 80, 255],
+    ] {
+        assert_eq!(*color, Hsva::from_rgba(*color).to_rgba());
+    }
+}
@@ -0,0 +1,350 @@
+use crate::{
+    event::{DeviceEvent, VirtualKeyCode, WindowEvent},
+    state::{HotReloadError, RenderContext, RenderError},
+    Game, GameState,
+};
+use libloading::Library;
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// The name of the function that [export_game] adds to the game library.
+///
+/// [export_game]: ./macro.export_game.html
+const INIT_SYMBOL: &[u8] = b"crystal_engine_init_game\0";
+
+/// The time that the library has to be unchanged before it is reloaded. The compiler writes the library in multiple steps, and loading it halfway through would fail.
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// A [Game] that is loaded from a dynamic library, and reloaded when the library is rebuilt. This is meant for development, the window, the GPU resources and the [GameState] stay alive while the game logic is reloaded.
+///
+/// The game is compiled as a separate crate with `crate-type = ["cdylib"]`, which exports the [Game] implementation with [export_game]. A small binary then runs the library:
+///
+/// ```no_run
+/// # use crystal_engine::*;
+/// let window = Window::<HotReload>::builder(800., 600.)
+///     .with_game_library("target/debug/libmy_game.so")
+///     .build()
+///     .unwrap();
+/// window.run();
+/// ```
+///
+/// When the library changes, the old instance of the game is dropped and [Game::init] of the new library is called with the existing [GameState]. Models and GUI elements that are owned by the game are dropped with it, so `init` has to create them again. The camera, the lights and every other setting of the [GameState] are kept.
+///
+/// If the new library can not be loaded, the error is printed to stderr and the old library keeps running.
+///
+/// # Limitations
+///
+/// The library and the binary have to be built by the same compiler, with the same version and features of this crate. The [GameState] must not contain code of the library when it is reloaded, e.g. a pending callback of [GameState::transition], because this code is unloaded.
+///
+/// [export_game]: ./macro.export_game.html
+/// [GameState::transition]: ./struct.GameState.html#method.transition
+pub struct HotReload {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    loaded: Option<LoadedGame>,
+    /// The amount of times the library has been loaded, which is used to give every copy of the library a unique name
+    generation: usize,
+}
+
+struct LoadedGame {
+    game: Box<dyn DynGame>,
+    library: Library,
+    copy_path: PathBuf,
+}
+
+impl LoadedGame {
+    /// Drop the game before its library, because the code of the game lives in the library.
+    fn unload(self) {
+        let LoadedGame {
+            game,
+            library,
+            copy_path,
+        } = self;
+        drop(game);
+        drop(library);
+        let _ = std::fs::remove_file(copy_path);
+    }
+}
+
+impl HotReload {
+    /// The path of the library that the game is loaded from.
+    pub fn library_path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reload the library, even if it did not change. On success the old game is dropped, and a new game is created.
+    pub fn reload(&mut self, state: &mut GameState) -> Result<(), HotReloadError> {
+        self.modified = modified_time(&self.path);
+        self.generation += 1;
+
+        // The library is copied first, so the compiler can overwrite the original file while the
+        // copy is loaded. This also makes sure the OS doesn't hand out the old library again.
+        let copy_path = copy_path(&self.path, self.generation);
+        std::fs::copy(&self.path, &copy_path).map_err(HotReloadError::CouldNotCopyLibrary)?;
+        let library = match Library::new(&copy_path) {
+            Ok(library) => library,
+            Err(e) => {
+                let _ = std::fs::remove_file(&copy_path);
+                return Err(HotReloadError::CouldNotLoadLibrary(e));
+            }
+        };
+        let init = unsafe {
+            // The signature is enforced by `export_game!`
+            library.get::<fn(&mut GameState) -> Box<dyn DynGame>>(INIT_SYMBOL)
+        };
+        let init = match init {
+            Ok(init) => *init,
+            Err(e) => {
+                drop(library);
+                let _ = std::fs::remove_file(&copy_path);
+                return Err(HotReloadError::MissingGame(e));
+            }
+        };
+
+        // The old game is dropped first, so its models and GUI elements are gone before the new
+        // game creates them again
+        if let Some(loaded) = self.loaded.take() {
+            loaded.unload();
+        }
+        self.loaded = Some(LoadedGame {
+            game: init(state),
+            library,
+            copy_path,
+        });
+        Ok(())
+    }
+
+    fn reload_if_changed(&mut self, state: &mut GameState) {
+        let modified = match modified_time(&self.path) {
+            Some(modified) if Some(modified) != self.modified => modified,
+            _ => return,
+        };
+        let settled = modified
+            .elapsed()
+            .map(|elapsed| elapsed >= SETTLE_TIME)
+            .unwrap_or(false);
+        if settled {
+            match self.reload(state) {
+                Ok(()) => println!("Reloaded {:?}", self.path),
+                Err(e) => eprintln!("Could not reload {:?}: {}", self.path, e),
+            }
+        }
+    }
+
+    fn game(&mut self) -> &mut dyn DynGame {
+        // The game is only unloaded when `HotReload` is dropped, so this should never fail
+        self.loaded.as_mut().unwrap().game.as_mut()
+    }
+}
+
+impl Drop for HotReload {
+    fn drop(&mut self) {
+        if let Some(loaded) = self.loaded.take() {
+            loaded.unload();
+        }
+    }
+}
+
+impl Game for HotReload {
+    /// Load the library that was set with [WindowBuilder::with_game_library].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the library was not set, or if it can not be loaded.
+    ///
+    /// [WindowBuilder::with_game_library]: ./struct.WindowBuilder.html#method.with_game_library
+    fn init(state: &mut GameState) -> Self {
+        let path = state
+            .hot_reload_library
+            .take()
+            .expect("The game library is not set, use `WindowBuilder::with_game_library`");
+        let mut game = HotReload {
+            path,
+            modified: None,
+            loaded: None,
+            generation: 0,
+        };
+        if let Err(e) = game.reload(state) {
+            panic!("Could not load {:?}: {}", game.path, e);
+        }
+        game
+    }
+
+    fn update(&mut self, state: &mut GameState) {
+        self.reload_if_changed(state);
+        self.game().update(state);
+    }
+
+    fn fixed_update(&mut self, state: &mut GameState) {
+        self.game().fixed_update(state);
+    }
+
+    fn can_shutdown(&mut self, state: &mut GameState) -> bool {
+        self.game().can_shutdown(state)
+    }
+
+    fn event(&mut self, state: &mut GameState, event: &WindowEvent) {
+        self.game().event(state, event);
+    }
+
+    fn keydown(&mut self, state: &mut GameState, key: VirtualKeyCode) {
+        self.game().keydown(state, key);
+    }
+
+    fn keyup(&mut self, state: &mut GameState, key: VirtualKeyCode) {
+        self.game().keyup(state, key);
+    }
+
+    fn mouse_moved(&mut self, state: &mut GameState, delta: (f64, f64)) {
+        self.game().mouse_moved(state, delta);
+    }
+
+    fn device_event(&mut self, state: &mut GameState, event: &DeviceEvent) {
+        self.game().device_event(state, event);
+    }
+
+    fn focus_changed(&mut self, state: &mut GameState, focused: bool) {
+        self.game().focus_changed(state, focused);
+    }
+
+    fn cursor_entered(&mut self, state: &mut GameState) {
+        self.game().cursor_entered(state);
+    }
+
+    fn cursor_left(&mut self, state: &mut GameState) {
+        self.game().cursor_left(state);
+    }
+
+    fn resized(&mut self, state: &mut GameState, new_size: (u32, u32)) {
+        self.game().resized(state, new_size);
+    }
+
+    fn custom_render(&mut self, context: &mut RenderContext) {
+        self.game().custom_render(context);
+    }
+
+    fn on_error(&mut self, state: &mut GameState, error: &RenderError) -> bool {
+        self.game().on_error(state, error)
+    }
+
+    fn device_restored(&mut self, state: &mut GameState) {
+        self.game().device_restored(state)
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn copy_path(path: &Path, generation: usize) -> PathBuf {
+    let mut file_name = path.file_stem().unwrap_or_default().to_owned();
+    file_name.push(format!("-reload-{}-{}", std::process::id(), generation));
+    if let Some(extension) = path.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+    std::env::temp_dir().join(file_name)
+}
+
+/// An object safe version of [Game], which is what the game library exports.
+#[doc(hidden)]
+pub trait DynGame {
+    fn update(&mut self, state: &mut GameState);
+    fn fixed_update(&mut self, state: &mut GameState);
+    fn can_shutdown(&mut self, state: &mut GameState) -> bool;
+    fn event(&mut self, state: &mut GameState, event: &WindowEvent);
+    fn keydown(&mut self, state: &mut GameState, key: VirtualKeyCode);
+    fn keyup(&mut self, state: &mut GameState, key: VirtualKeyCode);
+    fn mouse_moved(&mut self, state: &mut GameState, delta: (f64, f64));
+    fn device_event(&mut self, state: &mut GameState, event: &DeviceEvent);
+    fn focus_changed(&mut self, state: &mut GameState, focused: bool);
+    fn cursor_entered(&mut self, state: &mut GameState);
+    fn cursor_left(&mut self, state: &mut GameState);
+    fn resized(&mut self, state: &mut GameState, new_size: (u32, u32));
+    fn custom_render(&mut self, context: &mut RenderContext);
+    fn on_error(&mut self, state: &mut GameState, error: &RenderError) -> bool;
+    fn device_restored(&mut self, state: &mut GameState);
+}
+
+impl<T: Game> DynGame for T {
+    fn update(&mut self, state: &mut GameState) {
+        Game::update(self, state)
+    }
+    fn fixed_update(&mut self, state: &mut GameState) {
+        Game::fixed_update(self, state)
+    }
+    fn can_shutdown(&mut self, state: &mut GameState) -> bool {
+        Game::can_shutdown(self, state)
+    }
+    fn event(&mut self, state: &mut GameState, event: &WindowEvent) {
+        Game::event(self, state, event)
+    }
+    fn keydown(&mut self, state: &mut GameState, key: VirtualKeyCode) {
+        Game::keydown(self, state, key)
+    }
+    fn keyup(&mut self, state: &mut GameState, key: VirtualKeyCode) {
+        Game::keyup(self, state, key)
+    }
+    fn mouse_moved(&mut self, state: &mut GameState, delta: (f64, f64)) {
+        Game::mouse_moved(self, state, delta)
+    }
+    fn device_event(&mut self, state: &mut GameState, event: &DeviceEvent) {
+        Game::device_event(self, state, event)
+    }
+    fn focus_changed(&mut self, state: &mut GameState, focused: bool) {
+        Game::focus_changed(self, state, focused)
+    }
+    fn cursor_entered(&mut self, state: &mut GameState) {
+        Game::cursor_entered(self, state)
+    }
+    fn cursor_left(&mut self, state: &mut GameState) {
+        Game::cursor_left(self, state)
+    }
+    fn resized(&mut self, state: &mut GameState, new_size: (u32, u32)) {
+        Game::resized(self, state, new_size)
+    }
+    fn custom_render(&mut self, context: &mut RenderContext) {
+        Game::custom_render(self, context)
+    }
+    fn on_error(&mut self, state: &mut GameState, error: &RenderError) -> bool {
+        Game::on_error(self, state, error)
+    }
+    fn device_restored(&mut self, state: &mut GameState) {
+        Game::device_restored(self, state)
+    }
+}
+
+/// Export a [Game] from a library, so it can be loaded by [HotReload]. The library has to be built with `crate-type = ["cdylib"]`.
+///
+/// ```ignore
+/// struct MyGame;
+///
+/// impl crystal_engine::Game for MyGame {
+///     fn init(state: &mut GameState) -> Self { MyGame }
+///     fn update(&mut self, state: &mut GameState) {}
+/// }
+///
+/// crystal_engine::export_game!(MyGame);
+/// ```
+///
+/// [Game]: ./trait.Game.html
+/// [HotReload]: ./struct.HotReload.html
+#[macro_export]
+macro_rules! export_game {
+    ($game:ty) => {
+        #[no_mangle]
+        pub fn crystal_engine_init_game(state: &mut $crate::GameState) -> Box<dyn $crate::DynGame> {
+            Box::new(<$game as $crate::Game>::init(state))
+        }
+    };
+}
+
+#[test]
+fn test_copy_path() {
+    let path = copy_path(Path::new("target/debug/libgame.so"), 3);
+    let name = path.file_name().unwrap().to_str().unwrap();
+    assert_eq!(format!("libgame-reload-{}-3.so", std::process::id()), name);
+    assert_eq!(std::env::temp_dir(), path.parent().unwrap());
+}
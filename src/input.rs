@@ -0,0 +1,261 @@
+use std::collections::{HashMap, HashSet};
+use winit::event::{
+    DeviceEvent, DeviceId, ElementState, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent,
+};
+
+/// The absolute value at which an action counts as pressed.
+const PRESSED_THRESHOLD: f32 = 0.5;
+
+/// An input that can be bound to an action in the [InputMap].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum InputBinding {
+    /// A key on the keyboard. This has a value of `1.0` while the key is pressed.
+    Key(VirtualKeyCode),
+    /// A mouse button. This has a value of `1.0` while the button is pressed.
+    MouseButton(MouseButton),
+    /// The motion of the mouse along an axis. The value is the distance the mouse moved since the last [Game::update](../trait.Game.html#tymethod.update), and is `0.0` when the mouse does not move. The distance is reported by the platform, and is usually in pixels, so use the scale of the binding to convert it to the range of an axis.
+    MouseAxis(MouseAxis),
+    /// A button of a gamepad or joystick. This has a value of `1.0` while the button is pressed.
+    ///
+    /// The id of the button is reported by the platform and can differ between controllers. Listen to `DeviceEvent::Button` to find out which id a button has.
+    GamepadButton(u32),
+    /// An analog axis of a gamepad or joystick. The value is the last value reported by the platform.
+    ///
+    /// The id of the axis is reported by the platform and can differ between controllers. Listen to `DeviceEvent::Motion` to find out which id an axis has. The axes of mice are not included, use [MouseAxis](#variant.MouseAxis) for those.
+    GamepadAxis(u32),
+}
+
+/// An axis of the motion of the mouse, see [InputBinding::MouseAxis].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum MouseAxis {
+    /// The horizontal motion. Moving the mouse to the right is positive.
+    X,
+    /// The vertical motion. Moving the mouse down is positive.
+    Y,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+struct Binding {
+    input: InputBinding,
+    scale: f32,
+}
+
+/// Maps named actions (e.g. `"jump"` or `"move_forward"`) to inputs, so the controls of the game can be rebound by the player.
+///
+/// With the `serialize` feature enabled, this implements `serde::Serialize` and `serde::Deserialize`, so the bindings can be stored in a config file. The current state of the inputs is not serialized.
+///
+/// ```no_run
+/// # use crystal_engine::*;
+/// # use crystal_engine::state::InputBinding;
+/// # use crystal_engine::event::VirtualKeyCode;
+/// # let mut state: GameState = unsafe { std::mem::zeroed() };
+/// state.input.bind("jump", InputBinding::Key(VirtualKeyCode::Space));
+/// state.input.bind_axis("move_forward", InputBinding::Key(VirtualKeyCode::W), 1.0);
+/// state.input.bind_axis("move_forward", InputBinding::Key(VirtualKeyCode::S), -1.0);
+///
+/// // in Game::update
+/// if state.input.is_action_pressed("jump") {
+///     // jump
+/// }
+/// let forward = state.input.action_axis("move_forward");
+/// ```
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct InputMap {
+    actions: HashMap<String, Vec<Binding>>,
+    #[cfg_attr(feature = "serialize", serde(skip))]
+    values: HashMap<InputBinding, f32>,
+    /// The devices that reported mouse motion. Mice also report their motion as `DeviceEvent::Motion`, which is not a gamepad axis.
+    #[cfg_attr(feature = "serialize", serde(skip))]
+    mice: HashSet<DeviceId>,
+    /// The device that last reported each gamepad axis, so the axes of a device can be cleared once it turns out to be a mouse.
+    #[cfg_attr(feature = "serialize", serde(skip))]
+    axis_devices: HashMap<u32, DeviceId>,
+}
+
+impl InputMap {
+    /// Bind an input to an action. This is the same as calling [bind_axis](#method.bind_axis) with a scale of `1.0`.
+    ///
+    /// An action can have multiple bindings, e.g. both a key and a gamepad button.
+    pub fn bind(&mut self, action: impl Into<String>, input: InputBinding) {
+        self.bind_axis(action, input, 1.0);
+    }
+
+    /// Bind an input to an action, with a scale that its value is multiplied with. This can be used to bind two keys to opposite directions of an axis.
+    ///
+    /// If the input is already bound to this action, its scale is replaced.
+    pub fn bind_axis(&mut self, action: impl Into<String>, input: InputBinding, scale: f32) {
+        let bindings = self.actions.entry(action.into()).or_default();
+        match bindings.iter_mut().find(|b| b.input == input) {
+            Some(binding) => binding.scale = scale,
+            None => bindings.push(Binding { input, scale }),
+        }
+    }
+
+    /// Remove an input from an action.
+    pub fn unbind(&mut self, action: &str, input: InputBinding) {
+        if let Some(bindings) = self.actions.get_mut(action) {
+            bindings.retain(|b| b.input != input);
+        }
+    }
+
+    /// Remove all inputs of an action.
+    pub fn clear_action(&mut self, action: &str) {
+        self.actions.remove(action);
+    }
+
+    /// Get the inputs that are bound to an action, with their scale.
+    pub fn bindings<'a>(&'a self, action: &str) -> impl Iterator<Item = (InputBinding, f32)> + 'a {
+        self.actions
+            .get(action)
+            .into_iter()
+            .flatten()
+            .map(|b| (b.input, b.scale))
+    }
+
+    /// Get the names of all actions that have bindings.
+    pub fn actions(&self) -> impl Iterator<Item = &str> {
+        self.actions.keys().map(String::as_str)
+    }
+
+    /// Check if any of the inputs of an action is pressed. Analog axes count as pressed when they are more than halfway.
+    pub fn is_action_pressed(&self, action: &str) -> bool {
+        self.bindings(action)
+            .any(|(input, scale)| (self.value(input) * scale).abs() >= PRESSED_THRESHOLD)
+    }
+
+    /// Get the value of an action, which is the sum of the values of its inputs multiplied by their scale. The result is clamped between `-1.0` and `1.0`.
+    ///
+    /// Returns `0.0` if the action has no bindings or none of its inputs are pressed.
+    pub fn action_axis(&self, action: &str) -> f32 {
+        self.bindings(action)
+            .map(|(input, scale)| self.value(input) * scale)
+            .sum::<f32>()
+            .max(-1.0)
+            .min(1.0)
+    }
+
+    fn value(&self, input: InputBinding) -> f32 {
+        self.values.get(&input).copied().unwrap_or(0.0)
+    }
+
+    fn set_value(&mut self, input: InputBinding, value: f32) {
+        if value == 0.0 {
+            self.values.remove(&input);
+        } else {
+            self.values.insert(input, value);
+        }
+    }
+
+    pub(crate) fn handle_window_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } => self.set_value(InputBinding::Key(*key), pressed_value(*state)),
+            WindowEvent::MouseInput { state, button, .. } => {
+                self.set_value(InputBinding::MouseButton(*button), pressed_value(*state))
+            }
+            // Keys that are released while the window is not focused are never reported
+            WindowEvent::Focused(false) => self.values.clear(),
+            _ => {}
+        }
+    }
+
+    pub(crate) fn handle_device_event(&mut self, device_id: DeviceId, event: &DeviceEvent) {
+        match event {
+            DeviceEvent::Button { button, state } => {
+                self.set_value(InputBinding::GamepadButton(*button), pressed_value(*state))
+            }
+            DeviceEvent::MouseMotion { delta } => {
+                if self.mice.insert(device_id) {
+                    // The motion of this mouse may already have been reported as a gamepad axis
+                    let axes = self
+                        .axis_devices
+                        .iter()
+                        .filter(|(_, device)| **device == device_id)
+                        .map(|(axis, _)| *axis)
+                        .collect::<Vec<_>>();
+                    for axis in axes {
+                        self.axis_devices.remove(&axis);
+                        self.set_value(InputBinding::GamepadAxis(axis), 0.0);
+                    }
+                }
+                self.add_mouse_motion(*delta);
+            }
+            DeviceEvent::Motion { axis, value } if !self.mice.contains(&device_id) => {
+                self.axis_devices.insert(*axis, device_id);
+                self.set_value(InputBinding::GamepadAxis(*axis), *value as f32)
+            }
+            _ => {}
+        }
+    }
+
+    fn add_mouse_motion(&mut self, (x, y): (f64, f64)) {
+        let x_value = self.value(InputBinding::MouseAxis(MouseAxis::X)) + x as f32;
+        let y_value = self.value(InputBinding::MouseAxis(MouseAxis::Y)) + y as f32;
+        self.set_value(InputBinding::MouseAxis(MouseAxis::X), x_value);
+        self.set_value(InputBinding::MouseAxis(MouseAxis::Y), y_value);
+    }
+
+    /// Reset the motion of the mouse, so it is only counted in a single frame. This is called after [Game::update].
+    ///
+    /// [Game::update]: ../trait.Game.html#tymethod.update
+    pub(crate) fn update(&mut self) {
+        self.set_value(InputBinding::MouseAxis(MouseAxis::X), 0.0);
+        self.set_value(InputBinding::MouseAxis(MouseAxis::Y), 0.0);
+    }
+}
+
+fn pressed_value(state: ElementState) -> f32 {
+    match state {
+        ElementState::Pressed => 1.0,
+        ElementState::Released => 0.0,
+    }
+}
+
+#[test]
+fn test_action_axis() {
+    let mut map = InputMap::default();
+    map.bind_axis("move", InputBinding::Key(VirtualKeyCode::W), 1.0);
+    map.bind_axis("move", InputBinding::Key(VirtualKeyCode::S), -1.0);
+    map.bind_axis("move", InputBinding::GamepadAxis(1), -1.0);
+    assert_eq!(0.0, map.action_axis("move"));
+    assert!(!map.is_action_pressed("move"));
+
+    map.set_value(InputBinding::Key(VirtualKeyCode::S), 1.0);
+    assert_eq!(-1.0, map.action_axis("move"));
+    assert!(map.is_action_pressed("move"));
+
+    map.set_value(InputBinding::Key(VirtualKeyCode::W), 1.0);
+    assert_eq!(0.0, map.action_axis("move"));
+
+    map.set_value(InputBinding::GamepadAxis(1), -0.25);
+    assert_eq!(0.25, map.action_axis("move"));
+    assert!(!map.is_action_pressed("unknown"));
+}
+
+#[test]
+fn test_mouse_axis() {
+    let mut map = InputMap::default();
+    map.bind_axis("look", InputBinding::MouseAxis(MouseAxis::X), 0.1);
+    map.bind("steer", InputBinding::GamepadAxis(0));
+
+    // The motion is added up until the end of the frame
+    map.add_mouse_motion((2.0, 1.0));
+    map.add_mouse_motion((3.0, -1.0));
+    assert!((map.action_axis("look") - 0.5).abs() < 1e-6);
+    assert_eq!(0.0, map.value(InputBinding::MouseAxis(MouseAxis::Y)));
+    assert_eq!(0.0, map.action_axis("steer"));
+
+    map.update();
+    assert_eq!(0.0, map.action_axis("look"));
+}
@@ -28,9 +28,12 @@ impl UpdateMessage {
                 new_id,
                 data,
             } => {
-                let old = &game_state.model_handles[&old_id];
-                let new = old.with_new_data(data);
-                game_state.model_handles.insert(new_id, new);
+                // The old model is gone when it was unloaded with `GameState::unload_models`, in
+                // which case the clone is unloaded as well
+                if let Some(old) = game_state.model_handles.get(&old_id) {
+                    let new = old.with_new_data(data);
+                    game_state.model_handles.insert(new_id, new);
+                }
             }
             UpdateMessage::GuiElementDropped(id) => {
                 game_state.gui_elements.remove(&id);
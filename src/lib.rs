@@ -68,44 +68,107 @@
 #![warn(missing_docs)]
 #![allow(clippy::needless_doctest_main)]
 
+mod assets;
+mod camera;
+mod collision;
 mod error;
 mod game_state;
 mod gui;
+#[cfg(feature = "hot-reload")]
+mod hot_reload;
+mod input;
 mod internal;
 mod model;
+mod presence;
+mod profiler;
 mod render;
+mod scene;
+mod snapshot;
+mod storage;
+mod streaming;
+mod timer;
 
 pub mod color;
+#[cfg(feature = "ecs")]
+pub mod ecs;
 
 pub use self::{
     game_state::GameState,
-    gui::GuiElement,
-    model::{ModelBuilder, ModelHandle},
-    render::window::Window,
+    gui::{GuiContainer, GuiElement, SdfFont},
+    model::{ModelBuilder, ModelHandle, ModelId, ModelView},
+    render::window::{HeadlessWindow, RenderingPath, Window, WindowBuilder},
 };
 
+#[cfg(feature = "hot-reload")]
+#[doc(hidden)]
+pub use self::hot_reload::DynGame;
+#[cfg(feature = "hot-reload")]
+pub use self::hot_reload::HotReload;
+
 /// Reference to a Font. This is [rusttype::Font] but behind an Arc.
 pub type Font = std::sync::Arc<rusttype::Font<'static>>;
 
 /// Contains the states that are used in [GameState]. These are in a seperate module so we don't pollute the base module documentation.
 pub mod state {
     pub use crate::{
+        assets::AssetPaths,
+        camera::{CameraController, CameraEffects, Easing, FlyCamera, OrbitCamera, Timeline},
+        collision::Collider,
         error::*,
-        game_state::{KeyboardState, TimeState},
+        game_state::{KeyboardState, MouseMode, TimeState, TransformSmoothing, WorldUnits},
         gui::{
-            GuiElementBuilder, GuiElementCanvasBuilder, GuiElementData, GuiElementTextureBuilder,
+            DebugWidgetId, GlyphRange, Gradient, GuiContainerData, GuiElementAnimationBuilder,
+            GuiElementBuilder, GuiElementCanvasBuilder, GuiElementData, GuiElementSdfTextBuilder,
+            GuiElementTextureBuilder, GuiFilter, GuiState, GuiTheme, HorizontalAlignment,
+            VerticalAlignment,
+        },
+        input::{InputBinding, InputMap, MouseAxis},
+        presence::{Activity, Presence, PresenceBackend},
+        profiler::{
+            FrameSpike, FrameTimings, GpuTimings, ProfileScope, ProfilerStage, ProfilerState,
+            RenderStats, ScopeTimings,
         },
-        render::lights::{
-            DirectionalLight, FixedVec, LightColor, LightState, PointLight, PointLightAttenuation,
+        render::{
+            clip_plane::{ClipPlane, MAX_CLIP_PLANES},
+            context::RenderContext,
+            day_night::DayNightCycle,
+            debug_draw::{DebugDraw, DebugHelperId},
+            device::{DeviceInfo, DevicePreference, DeviceType},
+            environment::EnvironmentMap,
+            lights::{
+                DirectionalLight, FixedVec, HemisphereLight, LightColor, LightState, PointLight,
+                PointLightAttenuation,
+            },
+            limits::DeviceLimits,
+            monitor::{MonitorInfo, VideoModeInfo, WindowMode},
+            occlusion::OcclusionCulling,
+            outline::Outline,
+            post_processing::{
+                AutoExposure, Bloom, ColorAdjustments, ColorGrading, ColorLut, PostEffect,
+                PostProcessingState, Tonemapping, Vignette,
+            },
+            reflection::PlanarReflection,
+            transition::Transition,
+            weather::{Weather, WeatherState},
         },
+        scene::{ModelAsset, ModelSource, Scene, SceneModel},
+        snapshot::DebugSnapshot,
+        storage::{Storage, THUMBNAIL_WIDTH},
+        streaming::{ChunkId, ChunkState, StreamingState},
+        timer::TimerId,
     };
 }
 
 /// Helper structs for manual model loading
 pub mod models {
     pub use crate::model::{
-        loader::{ParsedModel, ParsedModelPart, ParsedTexture},
-        Material, ModelData, Vertex,
+        loader::{
+            ParsedModel, ParsedModelBuilder, ParsedModelPart, ParsedMorphTarget, ParsedPbrMaterial,
+            ParsedTexture,
+        },
+        CullMode, DepthBias, GpuMemoryStats, LoadingStage, Material, MeshStatistics, ModelData,
+        PbrMaterial, SpriteAnimation, TextureRegion, Transform, Vertex, MAX_ACTIVE_MORPH_TARGETS,
+        MAX_TEXTURE_LAYERS,
     };
 }
 
@@ -122,6 +185,10 @@ pub trait Game {
     fn init(state: &mut GameState) -> Self;
     /// Update the game. This will be called every frame. Use this to implement your game logic.
     fn update(&mut self, state: &mut GameState);
+    /// Update the game at a fixed rate, independent of the frame rate. This is only called when a rate is set with [GameState::set_fixed_update_rate](struct.GameState.html#method.set_fixed_update_rate), and can be called zero or more times per frame, before [update](#tymethod.update).
+    ///
+    /// Use this for logic that has to be deterministic, like physics. The time between two fixed updates is [TimeState::fixed_delta](state/struct.TimeState.html#method.fixed_delta).
+    fn fixed_update(&mut self, _state: &mut GameState) {}
     /// Checks if the game can shut down. This is called when a player tries to close the window by clicking X or pressing alt+f4
     fn can_shutdown(&mut self, _state: &mut GameState) -> bool {
         true
@@ -138,4 +205,52 @@ pub trait Game {
     /// Note that the [GameState.keyboard](struct.GameState.html#structfield.keyboard) is updated *before* this method is called.
     /// This means that `state.keyboard.is_pressed(key)` will always return `false`.
     fn keyup(&mut self, _state: &mut GameState, _key: event::VirtualKeyCode) {}
+    /// Triggered when the mouse is moved while the [MouseMode] is [Relative] and the window has focus.
+    ///
+    /// The delta is the raw motion of the mouse, in an unspecified unit that depends on the device.
+    /// This is not affected by the cursor reaching the edge of the window or the screen.
+    ///
+    /// [MouseMode]: state/enum.MouseMode.html
+    /// [Relative]: state/enum.MouseMode.html#variant.Relative
+    fn mouse_moved(&mut self, _state: &mut GameState, _delta: (f64, f64)) {}
+    /// Triggered when a raw event of an input device is received, e.g. the raw motion of the mouse or a raw key press.
+    ///
+    /// Unlike [event](#method.event), device events are not tied to the window. They are received regardless of the [MouseMode], and depending on the platform they can also be received while the window is not focused.
+    ///
+    /// [MouseMode]: state/enum.MouseMode.html
+    fn device_event(&mut self, _state: &mut GameState, _event: &event::DeviceEvent) {}
+    /// Triggered when the window gains or loses the keyboard focus, e.g. when the player alt-tabs to another window. See [GameState::has_focus](struct.GameState.html#method.has_focus).
+    ///
+    /// Use this to pause the game when the player is away.
+    fn focus_changed(&mut self, _state: &mut GameState, _focused: bool) {}
+    /// Triggered when the cursor enters the window.
+    fn cursor_entered(&mut self, _state: &mut GameState) {}
+    /// Triggered when the cursor leaves the window.
+    fn cursor_left(&mut self, _state: &mut GameState) {}
+    /// Triggered when the window is resized, or moved to a monitor with a different [scale factor](struct.GameState.html#method.scale_factor). The new size is in physical pixels, see [GameState::window_size](struct.GameState.html#method.window_size).
+    ///
+    /// Use this to move GUI elements that are positioned relative to the edges of the window.
+    fn resized(&mut self, _state: &mut GameState, _new_size: (u32, u32)) {}
+    /// Record custom vulkano draw calls into the frame. This is called every frame, after the models and the weather are drawn, and before the post-processing and the GUI.
+    ///
+    /// This is an escape hatch for advanced users, e.g. to draw effects that the engine does not support. See [RenderContext] for the state of the command buffer, and the device and camera matrices that are available.
+    ///
+    /// [RenderContext]: state/struct.RenderContext.html
+    fn custom_render(&mut self, _context: &mut state::RenderContext) {}
+    /// Triggered when a frame could not be rendered, e.g. because the GPU ran out of memory. The frame is skipped, and the game keeps running if this returns `true`.
+    ///
+    /// By default the error is printed to stderr, and the game is closed when a lost device can not be restored, because nothing can be rendered after that. Errors of assets that are loaded by the game, e.g. a [ModelError](state/enum.ModelError.html) of a texture that could not be uploaded, are returned by the method that loads them instead.
+    fn on_error(&mut self, _state: &mut GameState, error: &state::RenderError) -> bool {
+        eprintln!("Could not render the frame: {}", error);
+        !matches!(
+            error,
+            state::RenderError::DeviceLost
+                | state::RenderError::CouldNotRestoreDevice(_)
+                | state::RenderError::CouldNotRestoreModel(_)
+        )
+    }
+    /// Triggered when the GPU was lost, e.g. because its driver crashed or was updated, and the engine created a new device. The models in the world are uploaded again from the parsed meshes that are kept with every model, and keep their [ModelData](models/struct.ModelData.html).
+    ///
+    /// Resources that only exist on the old device are gone, and should be created again here: GUI elements that are not double buffered canvases, SDF fonts, merged models, the custom cursor, the [environment map](state/struct.LightState.html#structfield.environment) and [color grading](state/enum.PostEffect.html#variant.ColorGrading).
+    fn device_restored(&mut self, _state: &mut GameState) {}
 }
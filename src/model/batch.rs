@@ -0,0 +1,316 @@
+use super::{
+    handle::ModelRef,
+    upload::{upload_buffer, Readback},
+    Material, MeshStatistics, Model, ModelGroup, PbrMaps, PbrMaterial, TextureLayers, Vertex,
+};
+use crate::{error::ModelError, render::texture::Texture};
+use cgmath::{InnerSpace, Matrix, Matrix3, Matrix4, SquareMatrix, Vector3, Vector4};
+use parking_lot::RwLock;
+use std::{collections::HashMap, mem, sync::Arc};
+use vulkano::{buffer::BufferUsage, device::Queue};
+
+/// The vertices and indices of all the groups that are rendered with the same surface, in world space.
+struct Batch {
+    texture: Option<Texture>,
+    material: Option<Material>,
+    pbr_material: Option<PbrMaterial>,
+    pbr_maps: PbrMaps,
+    specular_map: Option<Texture>,
+    normal_map: Option<Texture>,
+    texture_layers: Option<TextureLayers>,
+    vertices: Vec<Vertex>,
+    index: Vec<u32>,
+}
+
+impl Batch {
+    fn new(group: &ModelGroup) -> Self {
+        Self {
+            texture: group.texture.clone(),
+            material: group.material,
+            pbr_material: group.pbr_material,
+            pbr_maps: group.pbr_maps.clone(),
+            specular_map: group.specular_map.clone(),
+            normal_map: group.normal_map.clone(),
+            texture_layers: group.texture_layers.clone(),
+            vertices: Vec::new(),
+            index: Vec::new(),
+        }
+    }
+
+    /// Check if the group is rendered exactly like the groups in this batch. Textures are compared by their pointer, so models have to share a texture to end up in the same batch.
+    fn has_surface_of(&self, group: &ModelGroup) -> bool {
+        fn same_texture(a: Option<&Texture>, b: Option<&Texture>) -> bool {
+            match (a, b) {
+                (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+                (None, None) => true,
+                _ => false,
+            }
+        }
+        let (maps, other_maps) = (&self.pbr_maps, &group.pbr_maps);
+        let same_layers = match (&self.texture_layers, &group.texture_layers) {
+            (Some(a), Some(b)) => a.same_as(b),
+            (None, None) => true,
+            _ => false,
+        };
+        same_texture(self.texture.as_ref(), group.texture.as_ref())
+            && same_texture(maps.metallic.as_ref(), other_maps.metallic.as_ref())
+            && same_texture(maps.roughness.as_ref(), other_maps.roughness.as_ref())
+            && same_texture(
+                maps.ambient_occlusion.as_ref(),
+                other_maps.ambient_occlusion.as_ref(),
+            )
+            && same_texture(self.specular_map.as_ref(), group.specular_map.as_ref())
+            && same_texture(self.normal_map.as_ref(), group.normal_map.as_ref())
+            && self.material == group.material
+            && self.pbr_material == group.pbr_material
+            && same_layers
+    }
+}
+
+/// Merge the meshes of the given models into a single model, with the position, rotation, scale and texture region of every model baked into the vertices. Groups that are rendered with the same texture and material are merged into a single group, so they are drawn at once.
+///
+/// Only the full detail mesh of the models is merged, the levels of detail are not used. The buffers of the models are in device-local memory, so they are copied back into host-visible memory first.
+pub(crate) fn merge_models<'a>(
+    queue: &Arc<Queue>,
+    models: impl Iterator<Item = &'a ModelRef>,
+) -> Result<(Model, MeshStatistics), ModelError> {
+    let mut readback = Readback::new(queue).map_err(ModelError::CouldNotUpload)?;
+    let mut uploads = Vec::new();
+    let mut sources = Vec::new();
+
+    // The meshes are cloned out of their slots, so their groups can be borrowed until the end
+    let models = models
+        .map(|model_ref| (model_ref, model_ref.mesh()))
+        .collect::<Vec<_>>();
+    for (model_ref, model) in &models {
+        let data = model_ref.data.read();
+        let base_matrix = data.matrix();
+        let region = data.texture_region.to_shader_value();
+        // The buffers and textures that are still being uploaded are waited on before the
+        // buffers are read back
+        uploads.extend(mem::replace(&mut *model.texture_future.write(), Vec::new()));
+        let dynamic = model.dynamic.read();
+
+        for (index, group) in model.groups.iter().enumerate() {
+            let group_matrix = data
+                .groups
+                .get(index)
+                .map(|g| g.matrix)
+                .unwrap_or_else(Matrix4::identity);
+            // A mesh that was changed at runtime is merged as it is now, its buffers are already
+            // in host-visible memory
+            let (vertex_buffer, index_buffer) = match dynamic.as_ref() {
+                Some(mesh) => {
+                    let (vertex_buffer, index_buffer) = mesh.buffers(index);
+                    (vertex_buffer.cloned(), index_buffer.cloned())
+                }
+                None => (
+                    group
+                        .vertex_buffer
+                        .as_ref()
+                        .or(model.vertex_buffer.as_ref())
+                        .map(|b| readback.copy(b))
+                        .transpose()
+                        .map_err(ModelError::CouldNotUpload)?,
+                    group
+                        .index
+                        .as_ref()
+                        .map(|b| readback.copy(b))
+                        .transpose()
+                        .map_err(ModelError::CouldNotUpload)?,
+                ),
+            };
+            if let Some(vertex_buffer) = vertex_buffer {
+                let matrix = base_matrix * group_matrix;
+                sources.push((group, matrix, region, vertex_buffer, index_buffer));
+            }
+        }
+    }
+    readback
+        .finish(uploads)
+        .map_err(ModelError::CouldNotUpload)?;
+
+    let mut batches: Vec<Batch> = Vec::new();
+    for (group, matrix, region, vertex_buffer, index_buffer) in sources {
+        // The copies are done and the buffers are never written by the GPU, so these should
+        // never fail
+        let vertices = vertex_buffer.read().unwrap();
+        let indices = index_buffer.as_ref().map(|index| index.read().unwrap());
+
+        let position = batches.iter().position(|b| b.has_surface_of(group));
+        let batch = match position {
+            Some(position) => &mut batches[position],
+            None => {
+                batches.push(Batch::new(group));
+                // A batch was just pushed so this should never fail
+                batches.last_mut().unwrap()
+            }
+        };
+        append_vertices(
+            &mut batch.vertices,
+            &mut batch.index,
+            &vertices,
+            indices.as_deref(),
+            matrix,
+            region,
+        );
+    }
+
+    let mut statistics = MeshStatistics {
+        bounding_box: (Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0)),
+        vertex_count: 0,
+        triangle_count: 0,
+    };
+    let mut min = Vector3::new(std::f32::MAX, std::f32::MAX, std::f32::MAX);
+    let mut max = Vector3::new(std::f32::MIN, std::f32::MIN, std::f32::MIN);
+    let mut groups = Vec::with_capacity(batches.len());
+    let mut futures = Vec::new();
+    for batch in batches {
+        if batch.index.is_empty() {
+            continue;
+        }
+        for vertex in &batch.vertices {
+            let [x, y, z] = vertex.position;
+            min = Vector3::new(min.x.min(x), min.y.min(y), min.z.min(z));
+            max = Vector3::new(max.x.max(x), max.y.max(y), max.z.max(z));
+        }
+        statistics.vertex_count += batch.vertices.len();
+        statistics.triangle_count += batch.index.len() / 3;
+
+        // The batch has indices, so it also has vertices and neither buffer is empty. The
+        // buffers are only `None` when they are empty, so the unwraps should never fail
+        let (vertex_buffer, vertex_future) =
+            upload_buffer(queue, BufferUsage::vertex_buffer(), &batch.vertices)
+                .map_err(ModelError::CouldNotUpload)?
+                .unwrap();
+        let (index, index_future) = upload_buffer(queue, BufferUsage::index_buffer(), &batch.index)
+            .map_err(ModelError::CouldNotUpload)?
+            .unwrap();
+        futures.push(vertex_future);
+        futures.push(index_future);
+        groups.push(ModelGroup {
+            vertex_buffer: Some(vertex_buffer),
+            material: batch.material,
+            pbr_material: batch.pbr_material,
+            pbr_maps: batch.pbr_maps,
+            texture: batch.texture,
+            specular_map: batch.specular_map,
+            normal_map: batch.normal_map,
+            texture_layers: batch.texture_layers,
+            index: Some(index),
+            // The merged vertices are in world space, so the offsets of the morph targets no
+            // longer fit
+            morph: None,
+        });
+    }
+    if groups.is_empty() {
+        return Err(ModelError::InvalidModelVertexBuffer);
+    }
+    statistics.bounding_box = (min, max);
+
+    let model = Model {
+        vertex_buffer: None,
+        groups,
+        texture_future: RwLock::new(futures),
+        queue: queue.clone(),
+        lods: Vec::new(),
+        dynamic: RwLock::new(None),
+        morph_targets: Vec::new().into(),
+        // The merged meshes only exist on the GPU, so they can not be uploaded again
+        source: None,
+    };
+    Ok((model, statistics))
+}
+
+/// Transform the vertices with `matrix` and the texture `region`, and append them to `target_vertices`. Only the vertices that are used by `indices` are appended. When `indices` is `None`, every vertex is used in order.
+fn append_vertices(
+    target_vertices: &mut Vec<Vertex>,
+    target_indices: &mut Vec<u32>,
+    vertices: &[Vertex],
+    indices: Option<&[u32]>,
+    matrix: Matrix4<f32>,
+    region: [f32; 4],
+) {
+    let normal_matrix = Matrix3::from_cols(
+        matrix.x.truncate(),
+        matrix.y.truncate(),
+        matrix.z.truncate(),
+    )
+    .invert()
+    .map(|m| m.transpose())
+    .unwrap_or_else(Matrix3::identity);
+
+    let mut remapped: HashMap<u32, u32> = HashMap::new();
+    let mut add_index = |index: u32| {
+        let vertex = match vertices.get(index as usize) {
+            Some(vertex) => vertex,
+            None => return,
+        };
+        let new_index = *remapped.entry(index).or_insert_with(|| {
+            let [x, y, z] = vertex.position;
+            let position = matrix * Vector4::new(x, y, z, 1.0);
+            let normal = normal_matrix * Vector3::from(vertex.normal);
+            let normal = if normal.magnitude2() > 0.0 {
+                normal.normalize()
+            } else {
+                normal
+            };
+            let [u, v] = vertex.tex_coord;
+            // Negative texture coordinates mean that the model has no texture, see the vertex shader
+            let tex_coord = if u < 0.0 && v < 0.0 {
+                [u, v]
+            } else {
+                [region[0] + u * region[2], region[1] + v * region[3]]
+            };
+            target_vertices.push(Vertex {
+                position: (position.truncate() / position.w).into(),
+                normal: normal.into(),
+                tex_coord,
+            });
+            target_vertices.len() as u32 - 1
+        });
+        target_indices.push(new_index);
+    };
+    match indices {
+        Some(indices) => indices.iter().copied().for_each(&mut add_index),
+        None => (0..vertices.len() as u32).for_each(&mut add_index),
+    }
+}
+
+#[test]
+fn test_append_vertices() {
+    let vertex = |x: f32, u: f32| Vertex {
+        position: [x, 0.0, 0.0],
+        normal: [0.0, 1.0, 0.0],
+        tex_coord: [u, 0.5],
+    };
+    let vertices = [vertex(0.0, 0.0), vertex(1.0, 1.0), vertex(2.0, -1.0)];
+    let matrix = Matrix4::from_translation(Vector3::new(0.0, 0.0, 5.0)) * Matrix4::from_scale(2.0);
+
+    let mut target_vertices = vec![vertex(9.0, 0.0)];
+    let mut target_indices = vec![0];
+    append_vertices(
+        &mut target_vertices,
+        &mut target_indices,
+        &vertices,
+        Some(&[0, 1, 1, 0][..]),
+        matrix,
+        [0.5, 0.0, 0.5, 1.0],
+    );
+    // Only the used vertices are copied, once each
+    assert_eq!(vec![0, 1, 2, 2, 1], target_indices);
+    assert_eq!(3, target_vertices.len());
+    assert_eq!([2.0, 0.0, 5.0], target_vertices[2].position);
+    assert_eq!([0.0, 1.0, 0.0], target_vertices[2].normal);
+    assert_eq!([1.0, 0.5], target_vertices[2].tex_coord);
+
+    append_vertices(
+        &mut target_vertices,
+        &mut target_indices,
+        &vertices[..1],
+        None,
+        Matrix4::identity(),
+        [0.0, 0.0, 1.0, 1.0],
+    );
+    assert_eq!(vec![0, 1, 2, 2, 1, 3], target_indices);
+}
@@ -1,17 +1,34 @@
 use super::{
-    handle::ModelRef, loader::SourceOrShape, Model, ModelDataGroup, ModelGroup, ModelHandle,
+    handle::ModelRef,
+    loader::{ParsedModel, SourceOrShape},
+    morph::morph_target_names,
+    progress::ProgressCallback,
+    upload::upload_buffer,
+    CullMode, DepthBias, LoadingStage, Model, ModelDataGroup, ModelGroup, ModelHandle, ModelLod,
+    MorphTargets, PbrMaps, PbrMaterial, Progress, TextureLayers, TextureRegion, Vertex,
+    MAX_TEXTURE_LAYERS,
+};
+use crate::{
+    assets::AssetPaths,
+    collision::Collider,
+    error::ModelError,
+    model::ModelData,
+    profiler::ProfilerStage,
+    render::{
+        clip_plane::ClipPlane,
+        reflection::PlanarReflection,
+        texture::{self, Texture},
+    },
+    scene::ModelAsset,
+    GameState,
 };
-use crate::{error::ModelError, model::ModelData, GameState};
 use cgmath::{Euler, Rad, Vector3, Zero};
 use parking_lot::RwLock;
-use std::sync::Arc;
+use std::{sync::Arc, time::Instant};
 use vulkano::{
-    buffer::{BufferUsage, CpuAccessibleBuffer},
-    command_buffer::{AutoCommandBuffer, CommandBufferExecFuture},
-    device::Queue,
-    format::R8G8B8A8Srgb,
-    image::{Dimensions, ImmutableImage},
-    sync::{GpuFuture, NowFuture},
+    buffer::{BufferUsage, DeviceLocalBuffer},
+    device::{Device, Queue},
+    sync::GpuFuture,
 };
 
 /// A builder that is used to configure a model being loaded
@@ -20,9 +37,34 @@ pub struct ModelBuilder<'a> {
     source_or_shape: SourceOrShape<'a>,
     fallback_color: Option<Vector3<f32>>,
     texture: Option<&'a str>,
+    pbr_material: Option<PbrMaterial>,
+    metallic_map: Option<&'a str>,
+    roughness_map: Option<&'a str>,
+    ambient_occlusion_map: Option<&'a str>,
+    texture_layers: Vec<&'a str>,
+    splat_map: Option<&'a str>,
+    texture_layer_scale: f32,
     position: Vector3<f32>,
     rotation: Euler<Rad<f32>>,
     scale: f32,
+    render_order: i32,
+    depth_bias: DepthBias,
+    depth_test: bool,
+    depth_write: bool,
+    cull_mode: CullMode,
+    clip_planes: Vec<ClipPlane>,
+    texture_region: TextureRegion,
+    custom_data: [f32; 16],
+    tags: Vec<String>,
+    reflection: Option<PlanarReflection>,
+    collider: Option<Collider>,
+    lods: Vec<(f32, LodSource)>,
+    progress: Option<ProgressCallback<'a>>,
+}
+
+enum LodSource {
+    Model(ParsedModel),
+    Simplified(u32),
 }
 
 impl<'a> ModelBuilder<'a> {
@@ -32,9 +74,29 @@ impl<'a> ModelBuilder<'a> {
             source_or_shape,
             fallback_color: None,
             texture: None,
+            pbr_material: None,
+            metallic_map: None,
+            roughness_map: None,
+            ambient_occlusion_map: None,
+            texture_layers: Vec::new(),
+            splat_map: None,
+            texture_layer_scale: 1.0,
             position: Vector3::zero(),
             rotation: Euler::new(Rad(0.0), Rad(0.0), Rad(0.0)),
             scale: 1.0,
+            render_order: 0,
+            depth_bias: DepthBias::default(),
+            depth_test: true,
+            depth_write: true,
+            cull_mode: CullMode::default(),
+            clip_planes: Vec::new(),
+            texture_region: TextureRegion::default(),
+            custom_data: [0.0; 16],
+            tags: Vec::new(),
+            reflection: None,
+            collider: None,
+            lods: Vec::new(),
+            progress: None,
         }
     }
 
@@ -50,6 +112,84 @@ impl<'a> ModelBuilder<'a> {
         self
     }
 
+    /// Render the model with a physically based material, instead of the Blinn-Phong material of the model file. This replaces the PBR materials of the model file as well.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # use crystal_engine::models::PbrMaterial;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// let model = state
+    ///     .new_obj_model("assets/helmet.obj")
+    ///     .with_texture_from_file("assets/helmet_albedo.png")
+    ///     .with_pbr_material(PbrMaterial {
+    ///         metallic: 1.0,
+    ///         roughness: 1.0,
+    ///         ..PbrMaterial::default()
+    ///     })
+    ///     .with_metallic_map("assets/helmet_metallic.png")
+    ///     .with_roughness_map("assets/helmet_roughness.png")
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn with_pbr_material(mut self, material: PbrMaterial) -> Self {
+        self.pbr_material = Some(material);
+        self
+    }
+
+    /// Set the texture that is multiplied with the metallic factor of the PBR material. The red channel of the texture is used.
+    ///
+    /// If the model has no PBR material, the default [PbrMaterial](./models/struct.PbrMaterial.html) is used.
+    pub fn with_metallic_map(mut self, texture_src: &'a str) -> Self {
+        self.metallic_map = Some(texture_src);
+        self
+    }
+
+    /// Set the texture that is multiplied with the roughness factor of the PBR material. The red channel of the texture is used.
+    ///
+    /// If the model has no PBR material, the default [PbrMaterial](./models/struct.PbrMaterial.html) is used.
+    pub fn with_roughness_map(mut self, texture_src: &'a str) -> Self {
+        self.roughness_map = Some(texture_src);
+        self
+    }
+
+    /// Set the texture that is multiplied with the ambient occlusion factor of the PBR material. The red channel of the texture is used.
+    ///
+    /// If the model has no PBR material, the default [PbrMaterial](./models/struct.PbrMaterial.html) is used.
+    pub fn with_ambient_occlusion_map(mut self, texture_src: &'a str) -> Self {
+        self.ambient_occlusion_map = Some(texture_src);
+        self
+    }
+
+    /// Blend multiple textures over the model, e.g. grass, sand and rock on a terrain. The channels of the splat map are the weights of the layers: red is the weight of the first layer, green of the second layer, and so on. The weights are normalized, so they do not have to add up to 1. This replaces the texture of the model.
+    ///
+    /// A model can have up to [MAX_TEXTURE_LAYERS](./models/constant.MAX_TEXTURE_LAYERS.html) layers. The splat map covers the model once, and the layers repeat over the model, see [with_texture_layer_scale](#method.with_texture_layer_scale).
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// let terrain = state
+    ///     .new_plane_model(64)
+    ///     .with_scale(100.0)
+    ///     .with_texture_layers(
+    ///         &["assets/grass.png", "assets/sand.png", "assets/rock.png"],
+    ///         "assets/terrain_splat.png",
+    ///     )
+    ///     .with_texture_layer_scale(32.0)
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn with_texture_layers(mut self, layers: &[&'a str], splat_map: &'a str) -> Self {
+        self.texture_layers = layers.to_vec();
+        self.splat_map = Some(splat_map);
+        self
+    }
+
+    /// Set how often the [texture layers](#method.with_texture_layers) repeat over the texture coordinates of the model. This defaults to `1.0`.
+    pub fn with_texture_layer_scale(mut self, scale: f32) -> Self {
+        self.texture_layer_scale = scale;
+        self
+    }
+
     /// Set the initial position of the model
     pub fn with_position(mut self, position: impl Into<Vector3<f32>>) -> Self {
         self.position = position.into();
@@ -68,63 +208,208 @@ impl<'a> ModelBuilder<'a> {
         self
     }
 
+    /// Set the initial render order of the model. See [ModelData::render_order](./models/struct.ModelData.html#structfield.render_order) for more information.
+    pub fn with_render_order(mut self, render_order: i32) -> Self {
+        self.render_order = render_order;
+        self
+    }
+
+    /// Set the initial depth bias of the model. See [DepthBias](./models/struct.DepthBias.html) for the meaning of the factors.
+    pub fn with_depth_bias(mut self, constant_factor: f32, slope_factor: f32) -> Self {
+        self.depth_bias = DepthBias {
+            constant_factor,
+            slope_factor,
+        };
+        self
+    }
+
+    /// Set if the model is hidden by the models in front of it. See [ModelData::depth_test](./models/struct.ModelData.html#structfield.depth_test) for more information.
+    pub fn with_depth_test(mut self, depth_test: bool) -> Self {
+        self.depth_test = depth_test;
+        self
+    }
+
+    /// Set if the model hides the models behind it that are rendered later. See [ModelData::depth_write](./models/struct.ModelData.html#structfield.depth_write) for more information.
+    pub fn with_depth_write(mut self, depth_write: bool) -> Self {
+        self.depth_write = depth_write;
+        self
+    }
+
+    /// Set which side of the triangles of the model is hidden. Use `CullMode::None` for models that should be visible from both sides, like leaves. See [CullMode](./models/enum.CullMode.html) for more information.
+    pub fn with_cull_mode(mut self, cull_mode: CullMode) -> Self {
+        self.cull_mode = cull_mode;
+        self
+    }
+
+    /// Add a plane that cuts the model. See [ClipPlane](./state/struct.ClipPlane.html) for more information.
+    pub fn with_clip_plane(mut self, clip_plane: ClipPlane) -> Self {
+        self.clip_planes.push(clip_plane);
+        self
+    }
+
+    /// Only show a part of the texture on the model, e.g. a single sprite of a sprite sheet. The region is given in texture coordinates, see [TextureRegion](./models/struct.TextureRegion.html) for more information.
+    pub fn with_texture_region(mut self, x: f32, y: f32, width: f32, height: f32) -> Self {
+        self.texture_region = TextureRegion {
+            x,
+            y,
+            width,
+            height,
+        };
+        self
+    }
+
+    /// Set the data that is passed to the shaders of this model as push constants. See [ModelData::custom_data](./models/struct.ModelData.html#structfield.custom_data) for more information.
+    pub fn with_custom_data(mut self, custom_data: [f32; 16]) -> Self {
+        self.custom_data = custom_data;
+        self
+    }
+
+    /// Add a tag to the model, so it can be found with [GameState::models_with_tag](./struct.GameState.html#method.models_with_tag). This can be called multiple times to add multiple tags. See [ModelData::tags](./models/struct.ModelData.html#structfield.tags) for more information.
+    pub fn with_tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+
+    /// Make the model reflect the world in its plane, like a mirror or the surface of water. See [PlanarReflection](./state/struct.PlanarReflection.html) for more information.
+    pub fn with_reflection(mut self, reflection: PlanarReflection) -> Self {
+        self.reflection = Some(reflection);
+        self
+    }
+
+    /// Attach a collider to the model, so it can be tested against other models with [ModelHandle::intersects](./struct.ModelHandle.html#method.intersects) and [GameState::collisions](./struct.GameState.html#method.collisions). See [Collider](./state/enum.Collider.html) for more information.
+    pub fn with_collider(mut self, collider: Collider) -> Self {
+        self.collider = Some(collider);
+        self
+    }
+
+    /// Add a lower level of detail. This model is rendered instead when the camera is at least `distance` world units away from the position of the model.
+    ///
+    /// Parts of the level of detail without a texture use the texture of the same part of the original model.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # use crystal_engine::models::ParsedModel;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// # let (tree, tree_low_poly): (ParsedModel, ParsedModel) = unsafe { std::mem::zeroed() };
+    /// let model = state
+    ///     .new_model(tree)
+    ///     .with_lod(20.0, tree_low_poly)
+    ///     .with_simplified_lod(50.0, 4)
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn with_lod(mut self, distance: f32, model: ParsedModel) -> Self {
+        self.lods.push((distance, LodSource::Model(model)));
+        self
+    }
+
+    /// Add a lower level of detail that is generated from the model. This is rendered instead when the camera is at least `distance` world units away from the position of the model.
+    ///
+    /// See [ParsedModel::simplified](./models/struct.ParsedModel.html#method.simplified) for the meaning of `grid_size`.
+    pub fn with_simplified_lod(mut self, distance: f32, grid_size: u32) -> Self {
+        self.lods.push((distance, LodSource::Simplified(grid_size)));
+        self
+    }
+
+    /// Report the progress of loading the model to the given callback, e.g. to show a loading bar. The callback is called with the current [LoadingStage] and how far along that stage is, as a percentage from `0.0` to `100.0`.
+    ///
+    /// The model is loaded on the calling thread, so the callback is called from within [build](#method.build).
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// let model = state
+    ///     .new_fbx_model("assets/castle.fbx")
+    ///     .with_progress(|stage, percentage| println!("{:?}: {:.0}%", stage, percentage))
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    ///
+    /// [LoadingStage]: ./models/enum.LoadingStage.html
+    pub fn with_progress(mut self, callback: impl FnMut(LoadingStage, f32) + 'a) -> Self {
+        self.progress = Some(Box::new(callback));
+        self
+    }
+
     /// Finish configuring the model and try to load it.
     pub fn build(self) -> Result<ModelHandle, ModelError> {
+        let start = Instant::now();
         let position = self.position;
         let rotation = self.rotation;
         let scale = self.scale;
+        let render_order = self.render_order;
+        let depth_bias = self.depth_bias;
+        let depth_test = self.depth_test;
+        let depth_write = self.depth_write;
+        let cull_mode = self.cull_mode;
+        let clip_planes = self.clip_planes;
+        let texture_region = self.texture_region;
+        let custom_data = self.custom_data;
+        let tags = self.tags;
+        let reflection = self.reflection;
+        let collider = self.collider;
 
-        let source = self.source_or_shape.parse()?;
-        let device = self.game_state.device.clone();
-        let queue = self.game_state.queue.clone();
-
-        let (tex, mut futures) = if let Some(texture) = self.texture {
-            let (tex, tex_future) = load_texture(self.game_state.queue.clone(), texture)?;
-            (Some(tex), vec![tex_future.boxed()])
-        } else {
-            (None, Vec::new())
-        };
-
-        let vertex_buffer = if let Some(vertices) = source.vertices {
-            CpuAccessibleBuffer::from_iter(
-                device.clone(),
-                BufferUsage::all(),
-                false,
-                vertices.iter().copied(),
-            )
-            .ok()
-        } else {
-            None
-        };
+        let asset = self
+            .source_or_shape
+            .to_model_source()
+            .map(|source| ModelAsset {
+                source,
+                texture: self.texture.map(String::from),
+                fallback_color: self.fallback_color,
+                pbr_material: self.pbr_material,
+                metallic_map: self.metallic_map.map(String::from),
+                roughness_map: self.roughness_map.map(String::from),
+                ambient_occlusion_map: self.ambient_occlusion_map.map(String::from),
+                texture_layers: self
+                    .texture_layers
+                    .iter()
+                    .copied()
+                    .map(String::from)
+                    .collect(),
+                splat_map: self.splat_map.map(String::from),
+                texture_layer_scale: self.texture_layer_scale,
+            });
+        let layer_count = self.texture_layers.len();
+        if self.splat_map.is_some() && (layer_count == 0 || layer_count > MAX_TEXTURE_LAYERS) {
+            return Err(ModelError::InvalidTextureLayerCount { count: layer_count });
+        }
+        let mut progress = Progress::new(self.progress);
+        let mesh = self
+            .source_or_shape
+            .parse(&self.game_state.assets, &mut progress)?;
+        let statistics = mesh.statistics();
 
-        let mut groups: Vec<_> = source
-            .parts
+        let lods = self
+            .lods
             .into_iter()
-            .map(|part| {
-                let (group, maybe_future) =
-                    ModelGroup::from_part(device.clone(), queue.clone(), &tex, part);
-                if let Some(fut) = maybe_future {
-                    futures.push(fut);
-                }
-                group
+            .map(|(distance, lod)| match lod {
+                LodSource::Model(model) => (distance, model),
+                LodSource::Simplified(grid_size) => (distance, mesh.simplified(grid_size)),
             })
             .collect();
-
-        if groups.is_empty() {
-            // we always need a single group, so add a dummy group
-            // TODO: Why do we always need a single group?
-            groups.push(ModelGroup::from_tex(tex));
-        }
-
-        let model = Model {
-            vertex_buffer,
-            groups,
-            texture_future: RwLock::new(futures),
+        let source = UploadSource {
+            mesh,
+            lods,
+            texture: self.texture.map(String::from),
+            pbr_material: self.pbr_material,
+            metallic_map: self.metallic_map.map(String::from),
+            roughness_map: self.roughness_map.map(String::from),
+            ambient_occlusion_map: self.ambient_occlusion_map.map(String::from),
+            texture_layers: self
+                .texture_layers
+                .iter()
+                .copied()
+                .map(String::from)
+                .collect(),
+            splat_map: self.splat_map.map(String::from),
+            texture_layer_scale: self.texture_layer_scale,
         };
-
-        if model.vertex_buffer.is_none() && model.groups.iter().all(|g| g.vertex_buffer.is_none()) {
-            return Err(ModelError::InvalidModelVertexBuffer);
-        }
+        let model = Arc::new(source).upload(
+            &self.game_state.device,
+            &self.game_state.transfer_queue,
+            &self.game_state.assets,
+            &mut progress,
+        )?;
 
         let groups = (0..model.groups.len())
             .map(|_| ModelDataGroup::default())
@@ -132,44 +417,284 @@ impl<'a> ModelBuilder<'a> {
 
         let (id, model_ref, model_handle) = ModelRef::new(
             Arc::new(model),
+            statistics,
+            asset,
             self.game_state.internal_update_sender.clone(),
             ModelData {
                 position,
                 rotation,
                 scale,
+                render_order,
+                depth_bias,
+                depth_test,
+                depth_write,
+                cull_mode,
+                clip_planes,
+                texture_region,
+                custom_data,
+                tags,
+                reflection,
+                collider,
                 groups,
+                ..ModelData::default()
             },
         );
         self.game_state.model_handles.insert(id, model_ref);
+        progress.finish();
+        self.game_state
+            .profiler
+            .record_nested(ProfilerStage::AssetUpload, start.elapsed());
 
         Ok(model_handle)
     }
 }
 
-type LoadedTexture = (
-    Arc<ImmutableImage<R8G8B8A8Srgb>>,
-    CommandBufferExecFuture<NowFuture, AutoCommandBuffer>,
-);
+/// The parsed meshes and the texture paths that a [Model] is uploaded from. These are kept with the model, so the model can be uploaded again when the device is lost.
+pub struct UploadSource {
+    mesh: ParsedModel,
+    /// The levels of detail, with the distance from which they are used
+    lods: Vec<(f32, ParsedModel)>,
+    texture: Option<String>,
+    pbr_material: Option<PbrMaterial>,
+    metallic_map: Option<String>,
+    roughness_map: Option<String>,
+    ambient_occlusion_map: Option<String>,
+    texture_layers: Vec<String>,
+    splat_map: Option<String>,
+    texture_layer_scale: f32,
+}
 
-fn load_texture(queue: Arc<Queue>, path: &str) -> Result<LoadedTexture, ModelError> {
-    let image = image::open(path)
-        .map_err(|inner| ModelError::CouldNotLoadTexture {
+impl UploadSource {
+    /// Upload the meshes and load the textures of the model. The returned model keeps this source.
+    pub(crate) fn upload(
+        self: Arc<Self>,
+        device: &Arc<Device>,
+        queue: &Arc<Queue>,
+        assets: &AssetPaths,
+        progress: &mut Progress,
+    ) -> Result<Model, ModelError> {
+        // Every texture and every part of the meshes is uploaded separately
+        let layer_count = self.texture_layers.len();
+        let layer_texture_count = self.splat_map.as_ref().map_or(0, |_| layer_count + 1);
+        let texture_count = [
+            &self.texture,
+            &self.metallic_map,
+            &self.roughness_map,
+            &self.ambient_occlusion_map,
+        ]
+        .iter()
+        .filter(|path| path.is_some())
+        .count()
+            + layer_texture_count;
+        let part_count: usize = std::iter::once(&self.mesh)
+            .chain(self.lods.iter().map(|(_, lod)| lod))
+            .map(|model| model.parts.len().max(1))
+            .sum();
+        progress.start(LoadingStage::Upload, texture_count + part_count);
+
+        let (tex, mut futures) = if let Some(texture) = &self.texture {
+            let (tex, tex_future) = load_texture(queue.clone(), assets, texture)?;
+            progress.step();
+            (Some(tex), vec![tex_future])
+        } else {
+            (None, Vec::new())
+        };
+
+        let texture_layers = match &self.splat_map {
+            Some(splat_map) => {
+                let mut layers = Vec::with_capacity(layer_count);
+                for path in &self.texture_layers {
+                    let (layer, future) = load_texture(queue.clone(), assets, path)?;
+                    futures.push(future);
+                    progress.step();
+                    layers.push(layer);
+                }
+                // The splat map contains weights instead of colors, so it is not in sRGB
+                let (splat_map, future) = load_linear_texture(queue.clone(), assets, splat_map)?;
+                futures.push(future);
+                progress.step();
+                Some(TextureLayers {
+                    layers,
+                    splat_map,
+                    scale: self.texture_layer_scale,
+                })
+            }
+            None => None,
+        };
+        let mut load_map = |path: &Option<String>| -> Result<Option<Texture>, ModelError> {
+            Ok(match path {
+                Some(path) => {
+                    let (map, future) = load_linear_texture(queue.clone(), assets, path)?;
+                    futures.push(future);
+                    progress.step();
+                    Some(map)
+                }
+                None => None,
+            })
+        };
+        let pbr_maps = PbrMaps {
+            metallic: load_map(&self.metallic_map)?,
+            roughness: load_map(&self.roughness_map)?,
+            ambient_occlusion: load_map(&self.ambient_occlusion_map)?,
+        };
+        let pbr_material = match self.pbr_material {
+            Some(material) => Some(material),
+            None if pbr_maps.iter().next().is_some() => Some(PbrMaterial::default()),
+            None => None,
+        };
+
+        let morph_targets =
+            morph_target_names(self.mesh.parts.iter().flat_map(|part| &part.morph_targets));
+        let (vertex_buffer, mut groups) = create_mesh(
+            device,
+            queue,
+            &tex,
+            &[],
+            &morph_targets,
+            &self.mesh,
+            &mut futures,
+            progress,
+        )?;
+        let mut lods = self
+            .lods
+            .iter()
+            .map(|(distance, lod)| {
+                let (vertex_buffer, groups) = create_mesh(
+                    device,
+                    queue,
+                    &tex,
+                    &groups,
+                    &morph_targets,
+                    lod,
+                    &mut futures,
+                    progress,
+                )?;
+                Ok(ModelLod {
+                    distance: *distance,
+                    vertex_buffer,
+                    groups,
+                })
+            })
+            .collect::<Result<Vec<_>, ModelError>>()?;
+        let lod_groups = lods.iter_mut().flat_map(|lod| lod.groups.iter_mut());
+        for group in groups.iter_mut().chain(lod_groups) {
+            if let Some(material) = pbr_material {
+                group.pbr_material = Some(material);
+                let mut maps = pbr_maps.clone();
+                maps.fill_from(&group.pbr_maps);
+                group.pbr_maps = maps;
+            }
+            if texture_layers.is_some() {
+                group.texture_layers = texture_layers.clone();
+            }
+        }
+        lods.sort_by(|a, b| {
+            a.distance
+                .partial_cmp(&b.distance)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        Ok(Model {
+            vertex_buffer,
+            groups,
+            texture_future: RwLock::new(futures),
+            queue: queue.clone(),
+            lods,
+            dynamic: RwLock::new(None),
+            morph_targets: morph_targets.into(),
+            source: Some(self),
+        })
+    }
+}
+
+type Mesh = (Option<Arc<DeviceLocalBuffer<[Vertex]>>>, Vec<ModelGroup>);
+
+/// Upload the vertices and the parts of a parsed model into device-local memory. Parts without a texture use the texture of the part with the same index in `fallback_groups`, or `texture` if there is no such part.
+///
+/// The morph targets of the parts are uploaded in the order of `morph_names`.
+#[allow(clippy::too_many_arguments)]
+fn create_mesh(
+    device: &Arc<Device>,
+    queue: &Arc<Queue>,
+    texture: &Option<Texture>,
+    fallback_groups: &[ModelGroup],
+    morph_names: &[String],
+    source: &ParsedModel,
+    futures: &mut Vec<Box<dyn GpuFuture>>,
+    progress: &mut Progress,
+) -> Result<Mesh, ModelError> {
+    let shared_vertex_count = source.vertices.as_ref().map_or(0, Vec::len);
+    let vertex_buffer = match &source.vertices {
+        Some(vertices) => upload_buffer(queue, BufferUsage::vertex_buffer(), vertices)
+            .map_err(ModelError::CouldNotUpload)?,
+        None => None,
+    }
+    .map(|(vertex_buffer, future)| {
+        futures.push(future);
+        vertex_buffer
+    });
+
+    let mut groups: Vec<_> = source
+        .parts
+        .iter()
+        .enumerate()
+        .map(|(index, part)| -> Result<_, ModelError> {
+            let vertex_count = part.vertices.as_ref().map_or(shared_vertex_count, Vec::len);
+            let texture = fallback_groups
+                .get(index)
+                .and_then(|g| g.texture.clone())
+                .or_else(|| texture.clone());
+            let (mut group, group_futures) = ModelGroup::from_part(queue.clone(), &texture, part)?;
+            futures.extend(group_futures);
+            if let Some(fallback) = fallback_groups.get(index) {
+                group.pbr_maps.fill_from(&fallback.pbr_maps);
+                if group.specular_map.is_none() {
+                    group.specular_map = fallback.specular_map.clone();
+                }
+                if group.normal_map.is_none() {
+                    group.normal_map = fallback.normal_map.clone();
+                }
+            }
+            group.morph = MorphTargets::new(device, morph_names, &part.morph_targets, vertex_count);
+            progress.step();
+            Ok(group)
+        })
+        .collect::<Result<_, ModelError>>()?;
+
+    if groups.is_empty() {
+        // we always need a single group, so add a dummy group
+        // TODO: Why do we always need a single group?
+        groups.push(ModelGroup::from_tex(texture.clone()));
+        progress.step();
+    }
+
+    if vertex_buffer.is_none() && groups.iter().all(|g| g.vertex_buffer.is_none()) {
+        return Err(ModelError::InvalidModelVertexBuffer);
+    }
+    Ok((vertex_buffer, groups))
+}
+
+fn load_texture(
+    queue: Arc<Queue>,
+    assets: &AssetPaths,
+    path: &str,
+) -> Result<(Texture, Box<dyn GpuFuture>), ModelError> {
+    texture::load(queue, &assets.resolve(path)).map_err(|inner| ModelError::CouldNotLoadTexture {
+        path: path.to_owned(),
+        inner,
+    })
+}
+
+/// Load a texture that contains factors instead of colors, e.g. a roughness map.
+fn load_linear_texture(
+    queue: Arc<Queue>,
+    assets: &AssetPaths,
+    path: &str,
+) -> Result<(Texture, Box<dyn GpuFuture>), ModelError> {
+    texture::load_linear(queue, &assets.resolve(path)).map_err(|inner| {
+        ModelError::CouldNotLoadTexture {
             path: path.to_owned(),
             inner,
-        })?
-        .to_rgba();
-    let dimensions = Dimensions::Dim2d {
-        width: image.width(),
-        height: image.height(),
-    };
-
-    Ok(ImmutableImage::from_iter(
-        image.into_raw().into_iter(),
-        dimensions,
-        R8G8B8A8Srgb,
-        queue,
-    )
-    // Should never fail because the image is in the correct format, the dimensions
-    // match and the queue is assumed to be valid
-    .unwrap())
+        }
+    })
 }
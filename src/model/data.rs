@@ -1,9 +1,16 @@
-use cgmath::{Euler, Matrix4, Rad, SquareMatrix, Vector3, Zero};
+use crate::{
+    collision::Collider,
+    render::{clip_plane::ClipPlane, outline::Outline, reflection::PlanarReflection},
+};
+use cgmath::{
+    Euler, InnerSpace, Matrix4, Quaternion, Rad, SquareMatrix, Vector3, VectorSpace, Zero,
+};
 
 /// Data of a model. This is behind an `Arc<RwLock<>>` so that the engine can keep a copy and check the latest values.
 ///
 /// For an example on how to use this, see the example in the root of this module. This is the value passed in `ModelHandle::modify`.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub struct ModelData {
     /// The current position in the world that this model exists at.
     pub position: Vector3<f32>,
@@ -14,6 +21,112 @@ pub struct ModelData {
     /// The scale of this model.
     pub scale: f32,
 
+    /// The order in which this model is rendered, compared to other models. Models with a higher render order are rendered later.
+    ///
+    /// When two models are at the exact same depth, e.g. a decal on a wall, the model with the highest render order is visible. This defaults to `0`.
+    pub render_order: i32,
+
+    /// Moves the depth of this model towards the camera, so that it wins the depth test against a surface at the same position. This is useful for e.g. road markings that are laid over terrain. See [DepthBias] for more information.
+    ///
+    /// Every distinct depth bias needs its own pipeline, so prefer re-using the same few values. This defaults to no bias.
+    ///
+    /// [DepthBias]: ./struct.DepthBias.html
+    pub depth_bias: DepthBias,
+
+    /// When this is `false`, this model is drawn over everything that was rendered before it, even when it is behind it. Together with a high [render_order](#structfield.render_order) this makes a model always visible, which is useful for selection outlines, gizmos and markers. This defaults to `true`.
+    ///
+    /// Every combination of `depth_test` and `depth_write` needs its own pipeline, which is created the first time a model with that combination is rendered.
+    pub depth_test: bool,
+
+    /// When this is `false`, this model does not hide the models that are rendered after it. Models that are drawn with `depth_test` disabled should usually disable this as well, so they do not cut holes in the models that are rendered later. This defaults to `true`.
+    pub depth_write: bool,
+
+    /// Which side of the triangles of this model is hidden. Models that are a single plane, like leaves and billboards, should use [CullMode::None] so they are visible from both sides. See [CullMode] for more information.
+    ///
+    /// Every cull mode needs its own pipeline, which is created the first time a model with that cull mode is rendered. This defaults to [CullMode::Back].
+    ///
+    /// [CullMode]: ./enum.CullMode.html
+    /// [CullMode::None]: ./enum.CullMode.html#variant.None
+    /// [CullMode::Back]: ./enum.CullMode.html#variant.Back
+    pub cull_mode: CullMode,
+
+    /// Planes that cut this model, on top of the [GameState::clip_planes] that apply to every model. See [ClipPlane] for more information.
+    ///
+    /// [GameState::clip_planes]: ../struct.GameState.html#structfield.clip_planes
+    /// [ClipPlane]: ../state/struct.ClipPlane.html
+    pub clip_planes: Vec<ClipPlane>,
+
+    /// The part of the texture that is shown on this model. This can be used to render a single sprite of a sprite sheet, or be updated every frame by a [SpriteAnimation]. This defaults to the whole texture.
+    ///
+    /// [SpriteAnimation]: ./struct.SpriteAnimation.html
+    pub texture_region: TextureRegion,
+
+    /// A small block of data that is passed to the shaders of this model as push constants, so it can vary per model without creating a new material. The engine does not use this data itself, it is meant for effects that are configured per model. This defaults to all zeroes.
+    ///
+    /// In GLSL, this data is available as:
+    ///
+    /// ```glsl
+    /// layout(push_constant) uniform PushConstants {
+    ///     float custom_data[16];
+    /// } push_constants;
+    /// ```
+    pub custom_data: [f32; 16],
+
+    /// A color as `[r, g, b, a]` that the color of the surface of this model is multiplied with, before it is lit. This can be used to make a model red when it takes damage, or to color variations of the same model. Models are drawn opaque, so the alpha channel currently has no visible effect. This defaults to white, which does not change the model.
+    pub tint: [f32; 4],
+
+    /// Makes this model glow in its own color, regardless of the lights in the world. At `1.0` the surface color of the model is added on top of the lit color. This can be used to highlight a selected model, or be animated for a flash effect. This defaults to `0.0`.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// # let model: ModelHandle = state.new_cube_model().build().unwrap();
+    /// // Flash red when the model is hit
+    /// model.modify(|data| {
+    ///     data.tint = [1.0, 0.3, 0.3, 1.0];
+    ///     data.highlight = 0.8;
+    /// });
+    /// ```
+    pub highlight: f32,
+
+    /// Names that group this model with other models, e.g. `"enemy"` or `"pickup"`. The engine does not use the tags itself, they can be queried with [GameState::models_with_tag]. This defaults to no tags.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// # let model: ModelHandle = state.new_cube_model().with_tag("enemy").build().unwrap();
+    /// // Make every enemy in the world glow
+    /// for enemy in state.models_with_tag("enemy") {
+    ///     enemy.modify(|data| data.highlight = 0.5);
+    /// }
+    /// ```
+    ///
+    /// [GameState::models_with_tag]: ../struct.GameState.html#method.models_with_tag
+    pub tags: Vec<String>,
+
+    /// Makes this model a mirror or the surface of water, which reflects the world in its plane. See [PlanarReflection] for more information. This defaults to `None`.
+    ///
+    /// [PlanarReflection]: ../state/struct.PlanarReflection.html
+    pub reflection: Option<PlanarReflection>,
+
+    /// Draws a solid line around the silhouette of this model, e.g. to show that it is selected. This is usually changed with [ModelHandle::set_outline]. See [Outline] for more information. This defaults to `None`.
+    ///
+    /// [ModelHandle::set_outline]: ../struct.ModelHandle.html#method.set_outline
+    /// [Outline]: ../state/struct.Outline.html
+    pub outline: Option<Outline>,
+
+    /// A simple shape in the local space of this model, which is used to test if the model touches other models, see [ModelHandle::intersects] and [GameState::collisions]. This defaults to `None`, which means the model never collides.
+    ///
+    /// [ModelHandle::intersects]: ../struct.ModelHandle.html#method.intersects
+    /// [GameState::collisions]: ../struct.GameState.html#method.collisions
+    pub collider: Option<Collider>,
+
+    /// The weights of the morph targets of this model, in the order of [ModelHandle::morph_target_names]. A weight of `1.0` moves the vertices fully to the shape of the morph target. Missing weights are `0.0`, which is the default for every morph target. This is usually changed with [ModelHandle::set_morph_weight].
+    ///
+    /// [ModelHandle::morph_target_names]: ../struct.ModelHandle.html#method.morph_target_names
+    /// [ModelHandle::set_morph_weight]: ../struct.ModelHandle.html#method.set_morph_weight
+    pub morph_weights: Vec<f32>,
+
     /// Contains the data of the groups in the model.
     /// If your 3d model has multiple parts, you can move them individually with this property.
     pub groups: Vec<ModelDataGroup>,
@@ -25,6 +138,21 @@ impl Default for ModelData {
             position: Vector3::zero(),
             rotation: Euler::new(Rad(0.0), Rad(0.0), Rad(0.0)),
             scale: 1.0,
+            render_order: 0,
+            depth_bias: DepthBias::default(),
+            depth_test: true,
+            depth_write: true,
+            cull_mode: CullMode::default(),
+            clip_planes: Vec::new(),
+            texture_region: TextureRegion::default(),
+            custom_data: [0.0; 16],
+            tint: [1.0; 4],
+            highlight: 0.0,
+            tags: Vec::new(),
+            reflection: None,
+            outline: None,
+            collider: None,
+            morph_weights: Vec::new(),
             groups: Vec::new(),
         }
     }
@@ -36,9 +164,171 @@ impl ModelData {
             * Matrix4::from(self.rotation)
             * Matrix4::from_scale(self.scale)
     }
+
+    /// Get the position, rotation and scale of this model.
+    pub fn transform(&self) -> Transform {
+        Transform {
+            position: self.position,
+            rotation: self.rotation,
+            scale: self.scale,
+        }
+    }
+
+    /// Set the position, rotation and scale of this model.
+    pub fn set_transform(&mut self, transform: Transform) {
+        self.position = transform.position;
+        self.rotation = transform.rotation;
+        self.scale = transform.scale;
+    }
+
+    /// Check if this model has the given tag. See [tags](#structfield.tags) for more information.
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    /// Get the [collider](#structfield.collider) in world space, with the position, rotation and scale of this model applied.
+    pub fn world_collider(&self) -> Option<Collider> {
+        self.collider.map(|c| c.transform(self.matrix()))
+    }
+}
+
+/// The position, rotation and scale of a model. This is used to update many models at once with [GameState::update_models](../struct.GameState.html#method.update_models).
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct Transform {
+    /// The position in the world, see [ModelData::position](./struct.ModelData.html#structfield.position).
+    pub position: Vector3<f32>,
+    /// The rotation in euler angles, see [ModelData::rotation](./struct.ModelData.html#structfield.rotation).
+    pub rotation: Euler<Rad<f32>>,
+    /// The scale, see [ModelData::scale](./struct.ModelData.html#structfield.scale).
+    pub scale: f32,
+}
+
+impl Transform {
+    /// The matrix of the transform that is `amount` of the way from `self` to `other`. An amount above `1.0` continues past `other` in the same direction.
+    pub(crate) fn blended_matrix(&self, other: &Transform, amount: f32) -> Matrix4<f32> {
+        let from = Quaternion::from(self.rotation);
+        let mut to = Quaternion::from(other.rotation);
+        // Both quaternions describe the same rotation, but only one of them takes the short way
+        if from.dot(to) < 0.0 {
+            to = -to;
+        }
+        Matrix4::from_translation(self.position.lerp(other.position, amount))
+            * Matrix4::from(from.nlerp(to, amount))
+            * Matrix4::from_scale(self.scale + (other.scale - self.scale) * amount)
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            position: Vector3::zero(),
+            rotation: Euler::new(Rad(0.0), Rad(0.0), Rad(0.0)),
+            scale: 1.0,
+        }
+    }
+}
+
+/// The side of the triangles of a model that is not rendered, see [ModelData::cull_mode].
+///
+/// Which side of a triangle is the front depends on the order of its corners, which is the same for most model formats. When the back side of a model is visible, it is lit as if its normals point towards the camera, so single-plane models look the same from both sides.
+///
+/// [ModelData::cull_mode]: ./struct.ModelData.html#structfield.cull_mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum CullMode {
+    /// Both sides of the triangles are rendered. Use this for models that are a single plane, like leaves, billboards and flags.
+    None,
+    /// The front side of the triangles is hidden. This can be used to render the inside of a model, e.g. a sky sphere, or models with a flipped winding.
+    Front,
+    /// The back side of the triangles is hidden. This is the default, and is the fastest for closed models, as their back side is never visible.
+    Back,
+}
+
+impl Default for CullMode {
+    fn default() -> Self {
+        CullMode::Back
+    }
+}
+
+/// The depth bias of a model, similar to `glPolygonOffset`. The depth of every pixel of the model is decreased by `constant_factor` times the smallest difference in depth that the depth buffer can store, plus `slope_factor` times the slope of the depth of the triangle on the screen.
+///
+/// Surfaces that are almost parallel to the camera only need a small constant bias, but surfaces that are seen at a steep angle need a larger bias, which is what `slope_factor` is for. A good starting point is `DepthBias { constant_factor: 1.0, slope_factor: 1.0 }`.
+///
+/// Negative factors are treated as `0.0`, so the bias can only move a model towards the camera.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct DepthBias {
+    /// A constant offset, in the smallest difference in depth that the depth buffer can store.
+    pub constant_factor: f32,
+    /// An offset that scales with the slope of the depth of the triangle.
+    pub slope_factor: f32,
+}
+
+impl DepthBias {
+    /// The key of the pipeline that renders this depth bias. Factors are compared by their bits, because `f32` does not implement `Hash`.
+    pub(crate) fn pipeline_key(self) -> (u32, u32) {
+        (
+            self.constant_factor.max(0.0).to_bits(),
+            self.slope_factor.max(0.0).to_bits(),
+        )
+    }
+}
+
+/// A rectangle of a texture, in texture coordinates. `(0.0, 0.0)` is the top-left corner of the texture and `(1.0, 1.0)` is the bottom-right corner.
+///
+/// The texture coordinates of a model are mapped onto this rectangle, so a [rectangle model](../struct.GameState.html#method.new_rectangle_model) with a region shows exactly that part of the texture.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct TextureRegion {
+    /// The left side of the region.
+    pub x: f32,
+    /// The top side of the region.
+    pub y: f32,
+    /// The width of the region.
+    pub width: f32,
+    /// The height of the region.
+    pub height: f32,
+}
+
+impl Default for TextureRegion {
+    fn default() -> Self {
+        Self {
+            x: 0.0,
+            y: 0.0,
+            width: 1.0,
+            height: 1.0,
+        }
+    }
+}
+
+impl TextureRegion {
+    /// Create a region from a rectangle in pixels, in a texture of `texture_width` by `texture_height` pixels.
+    pub fn from_pixels(
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        texture_width: u32,
+        texture_height: u32,
+    ) -> Self {
+        let texture_width = texture_width.max(1) as f32;
+        let texture_height = texture_height.max(1) as f32;
+        Self {
+            x: x as f32 / texture_width,
+            y: y as f32 / texture_height,
+            width: width as f32 / texture_width,
+            height: height as f32 / texture_height,
+        }
+    }
+
+    pub(crate) fn to_shader_value(&self) -> [f32; 4] {
+        [self.x, self.y, self.width, self.height]
+    }
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub struct ModelDataGroup {
     pub matrix: Matrix4<f32>,
 }
@@ -50,3 +340,49 @@ impl Default for ModelDataGroup {
         }
     }
 }
+
+#[test]
+fn test_model_tags() {
+    let mut data = ModelData::default();
+    assert!(!data.has_tag("enemy"));
+    data.tags.push(String::from("enemy"));
+    data.tags.push(String::from("flying"));
+    assert!(data.has_tag("enemy"));
+    assert!(data.has_tag("flying"));
+    assert!(!data.has_tag("enem"));
+}
+
+#[test]
+fn test_transform_blending() {
+    let from = Transform {
+        position: Vector3::new(0.0, 0.0, 0.0),
+        rotation: Euler::new(Rad(0.0), Rad(0.2), Rad(0.0)),
+        scale: 1.0,
+    };
+    let to = Transform {
+        position: Vector3::new(2.0, 0.0, 0.0),
+        rotation: Euler::new(Rad(0.0), Rad(0.6), Rad(0.0)),
+        scale: 3.0,
+    };
+    let expected = |position: f32, angle: f32, scale: f32| {
+        Matrix4::from_translation(Vector3::new(position, 0.0, 0.0))
+            * Matrix4::from(Euler::new(Rad(0.0), Rad(angle), Rad(0.0)))
+            * Matrix4::from_scale(scale)
+    };
+    let assert_close = |a: Matrix4<f32>, b: Matrix4<f32>| {
+        for column in 0..4 {
+            assert!(
+                (a[column] - b[column]).magnitude() < 0.001,
+                "{:?} != {:?}",
+                a,
+                b
+            );
+        }
+    };
+    assert_close(expected(0.0, 0.2, 1.0), from.blended_matrix(&to, 0.0));
+    assert_close(expected(2.0, 0.6, 3.0), from.blended_matrix(&to, 1.0));
+    assert_close(expected(1.0, 0.4, 2.0), from.blended_matrix(&to, 0.5));
+    // The rotation is normalized after blending, so it is only close to the extrapolated angle
+    let extrapolated = from.blended_matrix(&to, 1.5);
+    assert!((extrapolated.w.x - 3.0).abs() < 0.001);
+}
@@ -0,0 +1,326 @@
+use super::{upload::Readback, MeshStatistics, Model, Vertex};
+use crate::error::{MeshUpdateError, UploadError};
+use cgmath::Vector3;
+use std::{mem, sync::Arc};
+use vulkano::{
+    buffer::{BufferUsage, CpuAccessibleBuffer},
+    device::Device,
+};
+
+/// A copy of the vertices and indices of a model that can be changed after the model is built, see [ModelHandle::update_vertices](../struct.ModelHandle.html#method.update_vertices).
+///
+/// Every change is uploaded into new buffers, so the buffers of a frame that is still being rendered are never written to. The old buffers are released when the GPU is done with them.
+pub struct DynamicMesh {
+    device: Arc<Device>,
+    /// The vertices that are shared by the groups
+    vertices: Option<MeshBuffer<Vertex>>,
+    groups: Vec<DynamicGroup>,
+    /// The statistics of the buffers that are drawn
+    pub statistics: MeshStatistics,
+}
+
+struct DynamicGroup {
+    /// The vertices of this group, `None` if the group uses the shared vertices
+    vertices: Option<MeshBuffer<Vertex>>,
+    /// `None` if the group is drawn without indices
+    indices: Option<MeshBuffer<u32>>,
+}
+
+struct MeshBuffer<T> {
+    data: Vec<T>,
+    /// The buffer that is drawn, this is `None` when the data is empty
+    buffer: Option<Arc<CpuAccessibleBuffer<[T]>>>,
+    /// The data has changed since it was last uploaded
+    changed: bool,
+}
+
+impl<T: Copy + Send + Sync + 'static> MeshBuffer<T> {
+    fn read(buffer: &Arc<CpuAccessibleBuffer<[T]>>) -> Self {
+        Self {
+            // The copy of the buffer is done and never written by the GPU, so this should never fail
+            data: buffer.read().unwrap().to_vec(),
+            buffer: Some(buffer.clone()),
+            changed: false,
+        }
+    }
+
+    fn upload(&mut self, device: &Arc<Device>) {
+        if !self.changed {
+            return;
+        }
+        self.changed = false;
+        // Vulkan does not allow empty buffers
+        self.buffer = if self.data.is_empty() {
+            None
+        } else {
+            Some(
+                CpuAccessibleBuffer::from_iter(
+                    device.clone(),
+                    BufferUsage::all(),
+                    false,
+                    self.data.iter().copied(),
+                )
+                .unwrap(), // This can only fail if we run out of memory
+            )
+        };
+    }
+}
+
+impl DynamicMesh {
+    /// Copy the vertices and indices of a model. The levels of detail of the model are not copied, a changed mesh is drawn at every distance.
+    ///
+    /// The buffers of the model are in device-local memory, so they are copied back into host-visible memory first. This waits until the uploads of the model and the copies are done.
+    pub fn new(model: &Model, statistics: MeshStatistics) -> Result<Self, UploadError> {
+        let mut readback = Readback::new(&model.queue)?;
+        let vertices = model
+            .vertex_buffer
+            .as_ref()
+            .map(|b| readback.copy(b))
+            .transpose()?;
+        let groups = model
+            .groups
+            .iter()
+            .map(|group| {
+                Ok((
+                    group
+                        .vertex_buffer
+                        .as_ref()
+                        .map(|b| readback.copy(b))
+                        .transpose()?,
+                    group.index.as_ref().map(|b| readback.copy(b)).transpose()?,
+                ))
+            })
+            .collect::<Result<Vec<_>, UploadError>>()?;
+        readback.finish(mem::replace(&mut *model.texture_future.write(), Vec::new()))?;
+
+        Ok(Self {
+            device: model.queue.device().clone(),
+            vertices: vertices.as_ref().map(MeshBuffer::read),
+            groups: groups
+                .iter()
+                .map(|(vertices, indices)| DynamicGroup {
+                    vertices: vertices.as_ref().map(MeshBuffer::read),
+                    indices: indices.as_ref().map(MeshBuffer::read),
+                })
+                .collect(),
+            statistics,
+        })
+    }
+
+    /// Change the vertices that are shared by every group, or the vertices of a single group.
+    pub fn update_vertices<T>(
+        &mut self,
+        group: Option<usize>,
+        cb: impl FnOnce(&mut Vec<Vertex>) -> T,
+    ) -> Result<T, MeshUpdateError> {
+        let vertices = match group {
+            None => self
+                .vertices
+                .as_mut()
+                .ok_or(MeshUpdateError::NoSharedVertices)?,
+            Some(group) => {
+                let count = self.groups.len();
+                self.groups
+                    .get_mut(group)
+                    .ok_or(MeshUpdateError::InvalidGroup { group, count })?
+                    .vertices
+                    .as_mut()
+                    .ok_or(MeshUpdateError::NoGroupVertices { group })?
+            }
+        };
+        let result = cb(&mut vertices.data);
+        vertices.changed = true;
+        self.upload()?;
+        Ok(result)
+    }
+
+    /// Change the indices of a group. A group that was drawn without indices is drawn with the indices from now on.
+    pub fn update_indices<T>(
+        &mut self,
+        group: usize,
+        cb: impl FnOnce(&mut Vec<u32>) -> T,
+    ) -> Result<T, MeshUpdateError> {
+        let count = self.groups.len();
+        let indices = self
+            .groups
+            .get_mut(group)
+            .ok_or(MeshUpdateError::InvalidGroup { group, count })?
+            .indices
+            .get_or_insert_with(|| MeshBuffer {
+                data: Vec::new(),
+                buffer: None,
+                changed: true,
+            });
+        let result = cb(&mut indices.data);
+        indices.changed = true;
+        self.upload()?;
+        Ok(result)
+    }
+
+    /// Upload every buffer again on a new device, after the device that the buffers were created on was lost.
+    pub fn restore(&mut self, device: &Arc<Device>) {
+        self.device = device.clone();
+        let groups = self.groups.iter_mut();
+        for vertices in self
+            .vertices
+            .iter_mut()
+            .chain(groups.flat_map(|g| g.vertices.as_mut()))
+        {
+            vertices.changed = true;
+        }
+        for indices in self.groups.iter_mut().flat_map(|g| g.indices.as_mut()) {
+            indices.changed = true;
+        }
+        if self.upload().is_err() {
+            // The last change had an invalid index and was never uploaded. The old buffers can
+            // not be drawn on the new device, so nothing is drawn until the mesh is changed again.
+            let groups = self.groups.iter_mut();
+            for vertices in self
+                .vertices
+                .iter_mut()
+                .chain(groups.flat_map(|g| g.vertices.as_mut()))
+            {
+                vertices.buffer = None;
+            }
+            for indices in self.groups.iter_mut().flat_map(|g| g.indices.as_mut()) {
+                indices.buffer = None;
+            }
+        }
+    }
+
+    /// Get the vertex buffer and the index buffer that a group is drawn with. The vertex buffer is `None` when nothing should be drawn, e.g. when every vertex was removed.
+    pub fn buffers(
+        &self,
+        group: usize,
+    ) -> (
+        Option<&Arc<CpuAccessibleBuffer<[Vertex]>>>,
+        Option<&Arc<CpuAccessibleBuffer<[u32]>>>,
+    ) {
+        let group = match self.groups.get(group) {
+            Some(group) => group,
+            None => return (None, None),
+        };
+        let vertices = group.vertices.as_ref().or_else(|| self.vertices.as_ref());
+        let vertex_buffer = vertices.and_then(|v| v.buffer.as_ref());
+        match &group.indices {
+            // A group without any indices draws nothing
+            Some(MeshBuffer { buffer: None, .. }) => (None, None),
+            Some(indices) => (vertex_buffer, indices.buffer.as_ref()),
+            None => (vertex_buffer, None),
+        }
+    }
+
+    /// Check that every index refers to an existing vertex, and upload the buffers that have changed. The buffers are not uploaded when an index is invalid, so the mesh is drawn as it was before.
+    fn upload(&mut self) -> Result<(), MeshUpdateError> {
+        let shared_count = self.vertices.as_ref().map_or(0, |v| v.data.len());
+        for (index, group) in self.groups.iter().enumerate() {
+            let vertex_count = group
+                .vertices
+                .as_ref()
+                .map_or(shared_count, |v| v.data.len());
+            let indices = group.indices.iter().flat_map(|i| i.data.iter());
+            if let Some(invalid) = indices.copied().find(|&i| i as usize >= vertex_count) {
+                return Err(MeshUpdateError::IndexOutOfRange {
+                    group: index,
+                    index: invalid,
+                    vertex_count,
+                });
+            }
+        }
+
+        let device = &self.device;
+        let groups = self.groups.iter_mut();
+        for vertices in self
+            .vertices
+            .iter_mut()
+            .chain(groups.flat_map(|g| g.vertices.as_mut()))
+        {
+            vertices.upload(device);
+        }
+        for indices in self.groups.iter_mut().flat_map(|g| g.indices.as_mut()) {
+            indices.upload(device);
+        }
+        self.statistics = self.calculate_statistics();
+        Ok(())
+    }
+
+    fn calculate_statistics(&self) -> MeshStatistics {
+        let groups = self
+            .groups
+            .iter()
+            .map(|g| {
+                (
+                    g.vertices.as_ref().map(|v| &v.data[..]),
+                    g.indices.as_ref().map(|i| &i.data[..]),
+                )
+            })
+            .collect::<Vec<_>>();
+        mesh_statistics(self.vertices.as_ref().map(|v| &v.data[..]), &groups)
+    }
+}
+
+/// Calculate the statistics of the shared vertices and the `(vertices, indices)` of every group.
+fn mesh_statistics(
+    shared: Option<&[Vertex]>,
+    groups: &[(Option<&[Vertex]>, Option<&[u32]>)],
+) -> MeshStatistics {
+    let mut min = Vector3::new(std::f32::MAX, std::f32::MAX, std::f32::MAX);
+    let mut max = Vector3::new(std::f32::MIN, std::f32::MIN, std::f32::MIN);
+    let mut vertex_count = 0;
+    let shared = shared.unwrap_or(&[]);
+    let own = groups.iter().filter_map(|(vertices, _)| *vertices);
+    for vertices in std::iter::once(shared).chain(own) {
+        vertex_count += vertices.len();
+        for vertex in vertices {
+            let [x, y, z] = vertex.position;
+            min = Vector3::new(min.x.min(x), min.y.min(y), min.z.min(z));
+            max = Vector3::new(max.x.max(x), max.y.max(y), max.z.max(z));
+        }
+    }
+    let triangle_count = groups
+        .iter()
+        .map(|group| match group {
+            (_, Some(indices)) => indices.len() / 3,
+            (Some(vertices), None) => vertices.len() / 3,
+            (None, None) => shared.len() / 3,
+        })
+        .sum();
+    if vertex_count == 0 {
+        // An empty mesh has an empty bounding box at the origin
+        min = Vector3::new(0.0, 0.0, 0.0);
+        max = min;
+    }
+    MeshStatistics {
+        bounding_box: (min, max),
+        vertex_count,
+        triangle_count,
+    }
+}
+
+#[test]
+fn test_mesh_statistics() {
+    let vertex = |x: f32, y: f32| Vertex {
+        position: [x, y, 0.0],
+        normal: [0.0, 0.0, 1.0],
+        tex_coord: [0.0, 0.0],
+    };
+    let shared = [vertex(-1.0, 0.0), vertex(1.0, 0.0), vertex(0.0, 2.0)];
+    let own = [vertex(0.0, 0.0), vertex(0.0, 0.0), vertex(0.0, -3.0)];
+    let statistics = mesh_statistics(
+        Some(&shared),
+        &[(None, Some(&[0, 1, 2, 2, 1, 0])), (Some(&own), None)],
+    );
+    assert_eq!(6, statistics.vertex_count);
+    assert_eq!(3, statistics.triangle_count);
+    assert_eq!(
+        (Vector3::new(-1.0, -3.0, 0.0), Vector3::new(1.0, 2.0, 0.0)),
+        statistics.bounding_box
+    );
+
+    let empty = mesh_statistics(Some(&[]), &[(None, None)]);
+    assert_eq!(0, empty.triangle_count);
+    assert_eq!(
+        (Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0)),
+        empty.bounding_box
+    );
+}
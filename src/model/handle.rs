@@ -1,6 +1,12 @@
-use super::{Model, ModelData, ModelDataGroup};
-use crate::internal::UpdateMessage;
-use cgmath::{Euler, Rad, Vector3};
+use super::{DynamicMesh, MeshStatistics, Model, ModelData, ModelDataGroup, Transform, Vertex};
+use crate::{
+    collision::Collider,
+    internal::UpdateMessage,
+    render::{frustum::Frustum, outline::Outline},
+    scene::ModelAsset,
+    state::MeshUpdateError,
+};
+use cgmath::{Euler, Rad, SquareMatrix, Vector3};
 use parking_lot::RwLock;
 use std::sync::{
     atomic::{AtomicU64, Ordering},
@@ -10,6 +16,16 @@ use std::sync::{
 
 static ID: AtomicU64 = AtomicU64::new(1);
 
+/// The mesh of a model, which is shared by the handles and the clones of the model. The mesh is replaced when it is uploaded again on a new device, see `GameState::restore_device`.
+pub type SharedModel = Arc<RwLock<Arc<Model>>>;
+
+/// The id of a model in the world, returned by [ModelHandle::id]. Every model has a different id, including models that are created by cloning a [ModelHandle].
+///
+/// [ModelHandle::id]: ./struct.ModelHandle.html#method.id
+/// [ModelHandle]: ./struct.ModelHandle.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ModelId(pub(crate) u64);
+
 /// A handle to the model that was loaded. This can be used to move the model around in the world.
 ///
 /// When this handle is dropped, the model will disappear from the world on the next tick.
@@ -19,9 +35,19 @@ pub struct ModelHandle {
     id: u64,
     message_handle: Sender<UpdateMessage>,
     data: Arc<RwLock<ModelData>>,
+    /// The mesh of the model, which is shared with the clones of this handle
+    model: SharedModel,
+    /// The names of the morph targets of the mesh, these are the same on every device
+    morph_targets: Arc<[String]>,
+    statistics: MeshStatistics,
 }
 
 impl ModelHandle {
+    /// Get the id of the model. This can be used to update many models at once with [GameState::update_models](./struct.GameState.html#method.update_models).
+    pub fn id(&self) -> ModelId {
+        ModelId(self.id)
+    }
+
     // TODO: Helper functions for:
     // - translate
     // - rotate_to
@@ -42,6 +68,189 @@ impl ModelHandle {
         self.read(|d| d.scale)
     }
 
+    /// Get the axis-aligned bounding box of the model as `(min, max)`, in the local space of the model.
+    /// This does not take the position, rotation and scale of the model into account, see [world_bounding_box](#method.world_bounding_box) for that.
+    pub fn bounding_box(&self) -> (Vector3<f32>, Vector3<f32>) {
+        self.statistics().bounding_box
+    }
+
+    /// Get the axis-aligned bounding box of the model as `(min, max)` in world space, with the current position, rotation and scale of the model applied.
+    ///
+    /// Note that this box can be bigger than the model, as the rotated local bounding box is wrapped in a new axis-aligned box.
+    pub fn world_bounding_box(&self) -> (Vector3<f32>, Vector3<f32>) {
+        let matrix = self.read(|d| d.matrix());
+        self.statistics().world_bounding_box(matrix)
+    }
+
+    /// Get the [collider](./models/struct.ModelData.html#structfield.collider) of the model in world space, with the current position, rotation and scale of the model applied. Returns `None` if the model has no collider.
+    pub fn collider(&self) -> Option<Collider> {
+        self.read(|d| d.world_collider())
+    }
+
+    /// Check if the collider of this model touches the collider of another model. Returns `false` if one of the models has no collider.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// # let ball: ModelHandle = state.new_cube_model().build().unwrap();
+    /// # let paddle: ModelHandle = state.new_cube_model().build().unwrap();
+    /// if ball.intersects(&paddle) {
+    ///     // bounce the ball back
+    /// }
+    /// ```
+    pub fn intersects(&self, other: &ModelHandle) -> bool {
+        match (self.collider(), other.collider()) {
+            (Some(a), Some(b)) => a.intersects(&b),
+            _ => false,
+        }
+    }
+
+    /// Get the amount of vertices of the model.
+    pub fn vertex_count(&self) -> usize {
+        self.statistics().vertex_count
+    }
+
+    /// Get the amount of triangles of the model.
+    pub fn triangle_count(&self) -> usize {
+        self.statistics().triangle_count
+    }
+
+    fn statistics(&self) -> MeshStatistics {
+        model_statistics(&self.model.read(), self.statistics)
+    }
+
+    /// Change the vertices of the model at runtime, e.g. to animate the surface of water or to deform terrain. The vertices are shared by every group of the model, which is the case for the built-in shapes and for models that are built from a list of vertices.
+    ///
+    /// The changed vertices are uploaded into a new buffer, so a frame that is still being rendered is not affected. This also updates the [bounding box](#method.bounding_box) of the model.
+    ///
+    /// The mesh is shared by the clones of this handle, and the model is no longer rendered with its levels of detail after the first change. The first change copies the mesh back from GPU memory, which waits for the GPU. Returns an error if the groups of the model have their own vertices, see [update_group_vertices](#method.update_group_vertices), or if an index refers to a vertex that no longer exists.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// let water = state.new_plane_model(32).build().unwrap();
+    /// let time = state.time.running().as_secs_f32();
+    /// water
+    ///     .update_vertices(|vertices| {
+    ///         for vertex in vertices {
+    ///             vertex.position[1] = (vertex.position[0] * 4.0 + time).sin() * 0.05;
+    ///         }
+    ///     })
+    ///     .unwrap();
+    /// ```
+    pub fn update_vertices<T>(
+        &self,
+        cb: impl FnOnce(&mut Vec<Vertex>) -> T,
+    ) -> Result<T, MeshUpdateError> {
+        self.modify_mesh(|mesh| mesh.update_vertices(None, cb))
+    }
+
+    /// Change the vertices of a single group of the model at runtime. This is the same as [update_vertices](#method.update_vertices), for models where every group has its own vertices.
+    pub fn update_group_vertices<T>(
+        &self,
+        group: usize,
+        cb: impl FnOnce(&mut Vec<Vertex>) -> T,
+    ) -> Result<T, MeshUpdateError> {
+        self.modify_mesh(|mesh| mesh.update_vertices(Some(group), cb))
+    }
+
+    /// Change the indices of a group of the model at runtime. A group without indices is drawn with the new indices from now on, and a group whose indices are all removed is not drawn.
+    ///
+    /// Returns an error if the group does not exist, or if an index refers to a vertex that does not exist. In that case the change is kept, but the model is rendered with its previous mesh until the indices are valid again.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// # let terrain: ModelHandle = state.new_plane_model(32).build().unwrap();
+    /// // Dig a hole in the terrain by removing its first triangle
+    /// terrain
+    ///     .update_indices(0, |indices| {
+    ///         indices.drain(0..3);
+    ///     })
+    ///     .unwrap();
+    /// ```
+    pub fn update_indices<T>(
+        &self,
+        group: usize,
+        cb: impl FnOnce(&mut Vec<u32>) -> T,
+    ) -> Result<T, MeshUpdateError> {
+        self.modify_mesh(|mesh| mesh.update_indices(group, cb))
+    }
+
+    /// Get the names of the morph targets (blend shapes) of the model, e.g. `"smile"` or `"blink"`. Models that are loaded from FBX files have a morph target for every blend shape channel. Other models have no morph targets.
+    pub fn morph_target_names(&self) -> &[String] {
+        &self.morph_targets
+    }
+
+    /// Get the weight of a morph target, or `None` if the model has no morph target with that name.
+    pub fn morph_weight(&self, name: &str) -> Option<f32> {
+        let index = self.morph_target_index(name)?;
+        Some(self.read(|d| d.morph_weights.get(index).copied().unwrap_or(0.0)))
+    }
+
+    /// Set the weight of a morph target. At `0.0` the model has its original shape, and at `1.0` the vertices are moved fully to the shape of the morph target. The positions and normals of the vertices are blended on the GPU, so this is cheap to change every frame.
+    ///
+    /// At most [MAX_ACTIVE_MORPH_TARGETS](./models/constant.MAX_ACTIVE_MORPH_TARGETS.html) morph targets are applied at once, the ones with the largest weights. Morph targets are not applied to models whose mesh was changed with [update_vertices](#method.update_vertices).
+    ///
+    /// Returns an error if the model has no morph target with that name.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// let face = state.new_fbx_model("assets/face.fbx").build().unwrap();
+    /// let time = state.time.running().as_secs_f32();
+    /// face.set_morph_weight("smile", (time.sin() + 1.0) / 2.0)
+    ///     .unwrap();
+    /// ```
+    pub fn set_morph_weight(&self, name: &str, weight: f32) -> Result<(), MeshUpdateError> {
+        let index =
+            self.morph_target_index(name)
+                .ok_or_else(|| MeshUpdateError::UnknownMorphTarget {
+                    name: name.to_owned(),
+                })?;
+        self.modify(|d| {
+            if d.morph_weights.len() <= index {
+                d.morph_weights.resize(index + 1, 0.0);
+            }
+            d.morph_weights[index] = weight;
+        });
+        Ok(())
+    }
+
+    /// Draw a solid line around the silhouette of the model, or remove it with `None`. This is short for `self.modify(|d| d.outline = outline)`, see [Outline](./state/struct.Outline.html) for more information.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # use crystal_engine::state::Outline;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// # let unit: ModelHandle = state.new_cube_model().build().unwrap();
+    /// unit.set_outline(Some(Outline::new([1.0, 1.0, 0.0, 1.0], 2.0)));
+    /// ```
+    pub fn set_outline(&self, outline: Option<Outline>) {
+        self.modify(|d| d.outline = outline);
+    }
+
+    fn morph_target_index(&self, name: &str) -> Option<usize> {
+        self.morph_targets.iter().position(|n| n == name)
+    }
+
+    fn modify_mesh<T>(
+        &self,
+        cb: impl FnOnce(&mut DynamicMesh) -> Result<T, MeshUpdateError>,
+    ) -> Result<T, MeshUpdateError> {
+        let model = self.model.read();
+        let mut dynamic = model.dynamic.write();
+        let mesh = match dynamic.as_mut() {
+            Some(mesh) => mesh,
+            None => {
+                let mesh = DynamicMesh::new(&model, self.statistics)
+                    .map_err(MeshUpdateError::CouldNotReadBack)?;
+                dynamic.get_or_insert(mesh)
+            }
+        };
+        cb(mesh)
+    }
+
     /// Read the data of the model. Optionally returning a value.
     ///
     /// ```no_run
@@ -74,12 +283,7 @@ impl Clone for ModelHandle {
         let new_id = ID.fetch_add(1, Ordering::Relaxed);
         let message_handle = self.message_handle.clone();
         let data = self.data.read();
-        let data = Arc::new(RwLock::new(ModelData {
-            position: data.position,
-            rotation: data.rotation,
-            scale: data.scale,
-            groups: data.groups.clone(),
-        }));
+        let data = Arc::new(RwLock::new(data.clone()));
 
         // This sender only errors when the receiver is dropped
         // which should only happen when the game is shutting down
@@ -94,6 +298,9 @@ impl Clone for ModelHandle {
             id: new_id,
             message_handle,
             data,
+            model: self.model.clone(),
+            morph_targets: self.morph_targets.clone(),
+            statistics: self.statistics,
         }
     }
 }
@@ -109,14 +316,63 @@ impl Drop for ModelHandle {
     }
 }
 
+/// A model in the world that is borrowed from the [GameState](../struct.GameState.html), e.g. by [GameState::models_with_tag](../struct.GameState.html#method.models_with_tag).
+///
+/// Unlike a [ModelHandle], the model is not removed from the world when this view is dropped, and cloning it does not create a new model.
+#[derive(Clone, Copy)]
+pub struct ModelView<'a> {
+    id: ModelId,
+    model: &'a ModelRef,
+}
+
+impl<'a> ModelView<'a> {
+    pub(crate) fn new(id: u64, model: &'a ModelRef) -> Self {
+        Self {
+            id: ModelId(id),
+            model,
+        }
+    }
+
+    /// Get the id of the model. This is the same id as the [ModelHandle::id](./struct.ModelHandle.html#method.id) of the model.
+    pub fn id(&self) -> ModelId {
+        self.id
+    }
+
+    /// Get the axis-aligned bounding box of the model as `(min, max)` in world space. See [ModelHandle::world_bounding_box](./struct.ModelHandle.html#method.world_bounding_box) for more information.
+    pub fn world_bounding_box(&self) -> (Vector3<f32>, Vector3<f32>) {
+        self.model.world_bounding_box()
+    }
+
+    /// Read the data of the model. Optionally returning a value.
+    pub fn read<T>(&self, cb: impl FnOnce(&ModelData) -> T) -> T {
+        let data = self.model.data.read();
+        cb(&data)
+    }
+
+    /// Update the data of the model. Optionally returning a value.
+    ///
+    /// The data is shared with the [ModelHandle] of the model, so the change is visible through both.
+    pub fn modify<T>(&self, cb: impl FnOnce(&mut ModelData) -> T) -> T {
+        let mut data = self.model.data.write();
+        cb(&mut data)
+    }
+}
+
 pub struct ModelRef {
-    pub model: Arc<Model>,
+    pub model: SharedModel,
     pub data: Arc<RwLock<ModelData>>,
+    pub statistics: MeshStatistics,
+    /// The assets that this model was loaded from, if it can be loaded again as part of a scene.
+    pub asset: Option<Arc<ModelAsset>>,
+    /// The transform of the model before and after the last fixed update, see `GameState::transform_smoothing`.
+    pub fixed_transforms: Option<(Transform, Transform)>,
 }
 
 impl ModelRef {
     pub fn new(
         model: Arc<Model>,
+        statistics: MeshStatistics,
+        asset: Option<ModelAsset>,
         message_handle: Sender<UpdateMessage>,
         mut data: ModelData,
     ) -> (u64, ModelRef, ModelHandle) {
@@ -127,16 +383,24 @@ impl ModelRef {
 
         data.groups = groups;
         let data = Arc::new(RwLock::new(data));
+        let morph_targets = model.morph_targets.clone();
+        let model = Arc::new(RwLock::new(model));
         (
             id,
             ModelRef {
-                model,
+                model: model.clone(),
                 data: data.clone(),
+                statistics,
+                asset: asset.map(Arc::new),
+                fixed_transforms: None,
             },
             ModelHandle {
                 id,
                 data,
                 message_handle,
+                model,
+                morph_targets,
+                statistics,
             },
         )
     }
@@ -144,6 +408,42 @@ impl ModelRef {
         ModelRef {
             model: self.model.clone(),
             data,
+            statistics: self.statistics,
+            asset: self.asset.clone(),
+            fixed_transforms: None,
         }
     }
+
+    /// Get the mesh of the model. This is cloned out of the shared slot, so the slot is not locked while the mesh is rendered.
+    pub fn mesh(&self) -> Arc<Model> {
+        self.model.read().clone()
+    }
+
+    /// Get the bounding box of the model in world space.
+    pub fn world_bounding_box(&self) -> (Vector3<f32>, Vector3<f32>) {
+        let matrix = self.data.read().matrix();
+        model_statistics(&self.model.read(), self.statistics).world_bounding_box(matrix)
+    }
+
+    /// Check if the model is inside of the frustum of the camera.
+    pub fn is_visible(&self, frustum: &Frustum) -> bool {
+        // The bounding box is calculated without the matrices of the groups, so groups that are
+        // moved could end up outside of the box. These models are never culled.
+        let groups_moved = self
+            .data
+            .read()
+            .groups
+            .iter()
+            .any(|g| !g.matrix.is_identity());
+        groups_moved || frustum.intersects_box(self.world_bounding_box())
+    }
+}
+
+/// Get the statistics of the mesh of a model, which change when the mesh is changed at runtime.
+fn model_statistics(model: &Model, loaded: MeshStatistics) -> MeshStatistics {
+    model
+        .dynamic
+        .read()
+        .as_ref()
+        .map_or(loaded, |mesh| mesh.statistics)
 }
@@ -0,0 +1,236 @@
+//! A builder for models that are generated in code.
+
+use super::{ParsedModel, ParsedModelPart, ParsedMorphTarget, ParsedPbrMaterial, ParsedTexture};
+use crate::{
+    model::{Material, Vertex},
+    state::ParsedModelError,
+};
+
+/// Builds a [ParsedModel] in code, e.g. for a procedurally generated mesh. Parts are added with [add_part](#method.add_part) or [add_shared_part](#method.add_shared_part), and the other methods change the part that was added last.
+///
+/// The model is validated by [build](#method.build), so mistakes like an index that is out of range are reported with the part that they are in, instead of when the model is uploaded.
+///
+/// ```no_run
+/// # use crystal_engine::*;
+/// # use crystal_engine::models::*;
+/// # let mut state: GameState = unsafe { std::mem::zeroed() };
+/// let vertex = |x: f32, z: f32| Vertex {
+///     position: [x, 0.0, z],
+///     normal: [0.0, 1.0, 0.0],
+///     tex_coord: [x, z],
+/// };
+/// let floor = ParsedModelBuilder::new()
+///     .add_part(
+///         vec![vertex(0.0, 0.0), vertex(0.0, 1.0), vertex(1.0, 1.0), vertex(1.0, 0.0)],
+///         vec![0, 1, 2, 0, 2, 3],
+///     )
+///     .with_material(Material {
+///         ambient: [0.2; 3],
+///         diffuse: [0.8, 0.7, 0.6],
+///         specular: [0.1; 3],
+///         shininess: 8.0,
+///     })
+///     .build()
+///     .unwrap();
+/// let model = state.new_model(floor).build().unwrap();
+/// ```
+#[derive(Default)]
+pub struct ParsedModelBuilder {
+    vertices: Option<Vec<Vertex>>,
+    parts: Vec<ParsedModelPart>,
+    /// The first method that was called before a part was added, which is reported by `build`
+    misplaced_call: Option<&'static str>,
+}
+
+impl ParsedModelBuilder {
+    /// Create a builder for a model without any parts.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the vertices that are shared by the parts that are added with [add_shared_part](#method.add_shared_part). A model without parts draws these vertices as a list of triangles.
+    pub fn with_shared_vertices(mut self, vertices: Vec<Vertex>) -> Self {
+        self.vertices = Some(vertices);
+        self
+    }
+
+    /// Add a part with its own vertices. Every 3 indices are a triangle, wound counter-clockwise when seen from the front. A part without indices draws every 3 vertices as a triangle.
+    pub fn add_part(mut self, vertices: Vec<Vertex>, index: Vec<u32>) -> Self {
+        self.parts.push(ParsedModelPart {
+            vertices: Some(vertices),
+            index,
+            ..ParsedModelPart::default()
+        });
+        self
+    }
+
+    /// Add a part that uses the [shared vertices](#method.with_shared_vertices) of the model.
+    pub fn add_shared_part(mut self, index: Vec<u32>) -> Self {
+        self.parts.push(index.into());
+        self
+    }
+
+    /// Set the material of the last part, see [ParsedModelPart::material].
+    pub fn with_material(mut self, material: Material) -> Self {
+        if let Some(part) = self.last_part("with_material") {
+            part.material = Some(material);
+        }
+        self
+    }
+
+    /// Set the PBR material of the last part, see [ParsedModelPart::pbr].
+    pub fn with_pbr_material(mut self, material: ParsedPbrMaterial) -> Self {
+        if let Some(part) = self.last_part("with_pbr_material") {
+            part.pbr = Some(material);
+        }
+        self
+    }
+
+    /// Set the texture of the last part, see [ParsedModelPart::texture].
+    pub fn with_texture(mut self, texture: ParsedTexture) -> Self {
+        if let Some(part) = self.last_part("with_texture") {
+            part.texture = Some(texture);
+        }
+        self
+    }
+
+    /// Set the specular map of the last part, see [ParsedModelPart::specular_map].
+    pub fn with_specular_map(mut self, texture: ParsedTexture) -> Self {
+        if let Some(part) = self.last_part("with_specular_map") {
+            part.specular_map = Some(texture);
+        }
+        self
+    }
+
+    /// Set the normal map of the last part, see [ParsedModelPart::normal_map].
+    pub fn with_normal_map(mut self, texture: ParsedTexture) -> Self {
+        if let Some(part) = self.last_part("with_normal_map") {
+            part.normal_map = Some(texture);
+        }
+        self
+    }
+
+    /// Add a morph target to the last part, see [ParsedModelPart::morph_targets].
+    pub fn with_morph_target(mut self, target: ParsedMorphTarget) -> Self {
+        if let Some(part) = self.last_part("with_morph_target") {
+            part.morph_targets.push(target);
+        }
+        self
+    }
+
+    /// Validate the model with [ParsedModel::validate], and return it if it is valid.
+    pub fn build(self) -> Result<ParsedModel, ParsedModelError> {
+        if let Some(method) = self.misplaced_call {
+            return Err(ParsedModelError::NoPart { method });
+        }
+        let model = ParsedModel {
+            vertices: self.vertices,
+            parts: self.parts,
+        };
+        model.validate()?;
+        Ok(model)
+    }
+
+    /// Get the part that was added last. If there is no part yet, the call to `method` is remembered so it can be reported by `build`.
+    fn last_part(&mut self, method: &'static str) -> Option<&mut ParsedModelPart> {
+        if self.parts.is_empty() && self.misplaced_call.is_none() {
+            self.misplaced_call = Some(method);
+        }
+        self.parts.last_mut()
+    }
+}
+
+#[test]
+fn test_parsed_model_validation() {
+    let vertex = |x: f32, y: f32| Vertex {
+        position: [x, y, 0.0],
+        normal: [0.0, 0.0, 1.0],
+        tex_coord: [x, y],
+    };
+    let triangle = || vec![vertex(0.0, 0.0), vertex(1.0, 0.0), vertex(0.0, 1.0)];
+    let texture = |width: u32, height: u32, len: usize| ParsedTexture {
+        width,
+        height,
+        rgba_data: vec![255; len],
+    };
+
+    let model = ParsedModelBuilder::new()
+        .with_shared_vertices(triangle())
+        .add_part(triangle(), vec![0, 1, 2, 2, 1, 0])
+        .with_material(Material::default())
+        .with_texture(texture(2, 1, 8))
+        .add_shared_part(Vec::new())
+        .with_morph_target(ParsedMorphTarget {
+            name: "bulge".to_owned(),
+            positions: vec![[0.0, 0.0, 1.0]; 3],
+            normals: Vec::new(),
+        })
+        .build()
+        .unwrap();
+    assert_eq!(2, model.parts.len());
+    assert!(model.parts[0].material.is_some());
+    assert!(model.parts[1].vertices.is_none());
+    assert_eq!(1, model.parts[1].morph_targets.len());
+
+    // The shapes of the engine are valid as well
+    assert!(super::shapes::cube().validate().is_ok());
+    assert!(super::shapes::capsule(8, 4, 1.0).validate().is_ok());
+
+    let error =
+        |builder: ParsedModelBuilder| builder.build().err().expect("The model should be invalid");
+    assert!(matches!(
+        error(ParsedModelBuilder::new().with_material(Material::default())),
+        ParsedModelError::NoPart {
+            method: "with_material"
+        }
+    ));
+    assert!(matches!(
+        error(ParsedModelBuilder::new().add_shared_part(vec![0, 1, 2])),
+        ParsedModelError::NoVertices { part: 0 }
+    ));
+    assert!(matches!(
+        error(
+            ParsedModelBuilder::new()
+                .add_part(triangle(), vec![0, 1, 2])
+                .add_part(triangle(), vec![0, 1, 3])
+        ),
+        ParsedModelError::IndexOutOfRange {
+            part: 1,
+            index: 3,
+            vertex_count: 3
+        }
+    ));
+    assert!(matches!(
+        error(ParsedModelBuilder::new().add_part(triangle(), vec![0, 1])),
+        ParsedModelError::IncompleteTriangle { part: 0, count: 2 }
+    ));
+    assert!(matches!(
+        error(ParsedModelBuilder::new().with_shared_vertices(triangle()[..2].to_vec())),
+        ParsedModelError::IncompleteTriangle { part: 0, count: 2 }
+    ));
+    assert!(matches!(
+        error(
+            ParsedModelBuilder::new()
+                .add_part(triangle(), Vec::new())
+                .with_morph_target(ParsedMorphTarget {
+                    name: "bulge".to_owned(),
+                    positions: vec![[0.0; 3]; 3],
+                    normals: vec![[0.0; 3]; 2],
+                })
+        ),
+        ParsedModelError::InvalidMorphTarget { part: 0, .. }
+    ));
+    assert!(matches!(
+        error(
+            ParsedModelBuilder::new()
+                .add_part(triangle(), Vec::new())
+                .with_normal_map(texture(2, 2, 12))
+        ),
+        ParsedModelError::InvalidTextureSize {
+            part: 0,
+            width: 2,
+            height: 2,
+            len: 12
+        }
+    ));
+}
@@ -15,4 +15,17 @@ pub struct GeometryMesh {
     pub uv: Vec<Point2<f32>>,
     /// Indices per materials.
     pub indices_per_material: Vec<Vec<u32>>,
+    /// Morph targets, with an offset for every vertex.
+    pub morph_targets: Vec<MorphTarget>,
+}
+
+/// Morph target (blend shape).
+#[derive(Debug, Clone)]
+pub struct MorphTarget {
+    /// Name.
+    pub name: String,
+    /// Position offsets.
+    pub positions: Vec<Vector3<f32>>,
+    /// Normal offsets.
+    pub normals: Vec<Vector3<f32>>,
 }
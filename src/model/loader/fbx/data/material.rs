@@ -9,8 +9,14 @@ pub struct Material {
     pub name: Option<String>,
     /// Texture index.
     pub diffuse_texture: Option<TextureIndex>,
+    /// Specular color texture index.
+    pub specular_texture: Option<TextureIndex>,
+    /// Normal map texture index.
+    pub normal_texture: Option<TextureIndex>,
     /// Shading parameters.
     pub data: ShadingData,
+    /// PBR metallic-roughness parameters, if the material has them.
+    pub pbr: Option<PbrData>,
 }
 
 /// Shading data.
@@ -18,6 +24,8 @@ pub struct Material {
 pub enum ShadingData {
     /// Lambert material.
     Lambert(LambertData),
+    /// Phong material.
+    Phong(PhongData),
 }
 
 /// Lambert data.
@@ -31,6 +39,45 @@ pub struct LambertData {
     pub emissive: [f32; 3],
 }
 
+/// Phong data, which extends the Lambert data with a specular highlight.
+#[derive(Debug, Clone, Copy)]
+pub struct PhongData {
+    /// Lambert data.
+    pub lambert: LambertData,
+    /// Specular.
+    pub specular: [f32; 3],
+    /// Shininess exponent.
+    pub shininess: f32,
+}
+
+/// PBR metallic-roughness data.
+#[derive(Debug, Clone, Copy)]
+pub struct PbrData {
+    /// Base color.
+    pub base_color: [f32; 3],
+    /// Metallic factor.
+    pub metallic: f32,
+    /// Roughness factor.
+    pub roughness: f32,
+    /// Metallic texture index.
+    pub metallic_texture: Option<TextureIndex>,
+    /// Roughness texture index.
+    pub roughness_texture: Option<TextureIndex>,
+    /// Ambient occlusion texture index.
+    pub ambient_occlusion_texture: Option<TextureIndex>,
+}
+
+impl Into<crate::model::PbrMaterial> for PbrData {
+    fn into(self) -> crate::model::PbrMaterial {
+        crate::model::PbrMaterial {
+            albedo: self.base_color,
+            metallic: self.metallic,
+            roughness: self.roughness,
+            ambient_occlusion: 1.0,
+        }
+    }
+}
+
 impl Into<crate::model::Material> for Material {
     fn into(self) -> crate::model::Material {
         match self.data {
@@ -40,6 +87,12 @@ impl Into<crate::model::Material> for Material {
                 specular: lambert.emissive,
                 shininess: 0.0,
             },
+            ShadingData::Phong(phong) => crate::model::Material {
+                ambient: phong.lambert.ambient,
+                diffuse: phong.lambert.diffuse,
+                specular: phong.specular,
+                shininess: phong.shininess,
+            },
         }
     }
 }
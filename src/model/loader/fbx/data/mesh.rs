@@ -1,6 +1,7 @@
 //! Mesh.
 
 use crate::model::loader::fbx::data::{GeometryMeshIndex, MaterialIndex};
+use cgmath::{InnerSpace, Matrix, Matrix3, Matrix4, Point3, SquareMatrix, Transform, Vector3};
 
 /// Mesh.
 #[derive(Debug, Clone)]
@@ -11,4 +12,65 @@ pub struct Mesh {
     pub geometry_mesh_index: GeometryMeshIndex,
     /// Materials.
     pub materials: Vec<MaterialIndex>,
+    /// Transform from the space of the geometry to the space of the scene, with the transforms
+    /// of the parent nodes and the geometric transform applied.
+    pub transform: Matrix4<f64>,
+}
+
+/// Applies the transform of a mesh to its geometry.
+pub(crate) struct MeshTransform {
+    /// Transform of the positions.
+    matrix: Matrix4<f32>,
+    /// Transform of the normals, without scaling.
+    normal_matrix: Matrix3<f32>,
+    /// True if the transform mirrors the geometry, which flips the winding of its triangles.
+    pub mirrored: bool,
+}
+
+impl MeshTransform {
+    /// Creates a new `MeshTransform`.
+    pub fn new(transform: Matrix4<f64>) -> Self {
+        let matrix: Matrix4<f32> = transform.cast().unwrap_or_else(Matrix4::identity);
+        let linear = Matrix3::from_cols(
+            matrix.x.truncate(),
+            matrix.y.truncate(),
+            matrix.z.truncate(),
+        );
+        // Normals are transformed with the inverse transpose, so they stay perpendicular to
+        // the surface when the geometry is scaled unevenly
+        let normal_matrix = linear
+            .invert()
+            .map(|inverse| inverse.transpose() / inverse.determinant().abs().cbrt())
+            .unwrap_or_else(Matrix3::identity);
+        Self {
+            matrix,
+            normal_matrix,
+            mirrored: linear.determinant() < 0.0,
+        }
+    }
+
+    /// Transforms a position.
+    pub fn position(&self, position: Point3<f32>) -> [f32; 3] {
+        self.matrix.transform_point(position).into()
+    }
+
+    /// Transforms the offset of a position.
+    pub fn position_offset(&self, offset: Vector3<f32>) -> [f32; 3] {
+        self.matrix.transform_vector(offset).into()
+    }
+
+    /// Transforms a normal.
+    pub fn normal(&self, normal: Vector3<f32>) -> [f32; 3] {
+        let normal = self.normal_matrix * normal;
+        if normal.magnitude2() > 0.0 {
+            normal.normalize().into()
+        } else {
+            normal.into()
+        }
+    }
+
+    /// Transforms the offset of a normal.
+    pub fn normal_offset(&self, offset: Vector3<f32>) -> [f32; 3] {
+        (self.normal_matrix * offset).into()
+    }
 }
@@ -1,9 +1,9 @@
 //! 3D content data.
 
 pub use self::{
-    geometry::GeometryMesh,
-    material::{LambertData, Material, ShadingData},
-    mesh::Mesh,
+    geometry::{GeometryMesh, MorphTarget},
+    material::{LambertData, Material, PbrData, PhongData, ShadingData},
+    mesh::{Mesh, MeshTransform},
     scene::{GeometryMeshIndex, MaterialIndex, MeshIndex, Scene, TextureIndex},
     texture::{Texture, WrapMode},
 };
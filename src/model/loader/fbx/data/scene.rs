@@ -2,8 +2,8 @@
 
 use crate::model::{
     loader::{
-        fbx::data::{GeometryMesh, Material, Mesh, Texture},
-        ParsedModel, ParsedModelPart, ParsedTexture,
+        fbx::data::{GeometryMesh, Material, Mesh, MeshTransform, Texture},
+        ParsedModel, ParsedModelPart, ParsedMorphTarget, ParsedPbrMaterial, ParsedTexture,
     },
     Vertex,
 };
@@ -26,7 +26,7 @@ pub struct Scene {
 impl Scene {
     /// Add a geometry mesh.
     pub(crate) fn add_geometry_mesh(&mut self, mesh: GeometryMesh) -> GeometryMeshIndex {
-        let index = GeometryMeshIndex::new(self.meshes.len());
+        let index = GeometryMeshIndex::new(self.geometry_meshes.len());
         self.geometry_meshes.push(mesh);
         index
     }
@@ -141,6 +141,9 @@ impl Into<ParsedModel> for Scene {
         for mesh in &self.meshes {
             // we assume the mesh exists, else the parser should have returned an error
             let geometry = self.geometry_mesh(mesh.geometry_mesh_index).unwrap();
+            // The transform of the node is baked into the vertices, so the meshes of the scene
+            // keep their place relative to each other
+            let transform = MeshTransform::new(mesh.transform);
             for (i, indices) in geometry.indices_per_material.iter().enumerate() {
                 let material = self.material(mesh.materials[i]);
 
@@ -149,23 +152,69 @@ impl Into<ParsedModel> for Scene {
                     .and_then(|i| self.texture(i))
                     .map(|texture| texture.clone().into());
 
+                let load_map = |index: Option<TextureIndex>| -> Option<ParsedTexture> {
+                    index
+                        .and_then(|i| self.texture(i))
+                        .map(|texture| texture.clone().into())
+                };
+                let specular_map = load_map(material.and_then(|m| m.specular_texture));
+                let normal_map = load_map(material.and_then(|m| m.normal_texture));
+                let pbr = material.and_then(|m| m.pbr).map(|pbr| ParsedPbrMaterial {
+                    material: pbr.into(),
+                    metallic_map: load_map(pbr.metallic_texture),
+                    roughness_map: load_map(pbr.roughness_texture),
+                    ambient_occlusion_map: load_map(pbr.ambient_occlusion_texture),
+                });
+
                 let vertices = geometry
                     .positions
                     .iter()
                     .zip(geometry.normals.iter())
                     .zip(geometry.uv.iter())
-                    .map(|((position, normal), uv)| Vertex {
-                        position: position.clone().into(),
-                        normal: normal.clone().into(),
+                    .map(|((&position, &normal), uv)| Vertex {
+                        position: transform.position(position),
+                        normal: transform.normal(normal),
                         tex_coord: uv.clone().into(),
                     })
                     .collect();
+                let morph_targets = geometry
+                    .morph_targets
+                    .iter()
+                    .map(|target| ParsedMorphTarget {
+                        name: target.name.clone(),
+                        positions: target
+                            .positions
+                            .iter()
+                            .map(|&p| transform.position_offset(p))
+                            .collect(),
+                        normals: target
+                            .normals
+                            .iter()
+                            .map(|&n| transform.normal_offset(n))
+                            .collect(),
+                    })
+                    .collect();
+
+                let index = if transform.mirrored {
+                    // Mirroring turns the triangles inside out, reversing them keeps them facing
+                    // outwards
+                    indices
+                        .chunks(3)
+                        .flat_map(|triangle| triangle.iter().rev().copied())
+                        .collect()
+                } else {
+                    indices.clone()
+                };
 
                 parts.push(ParsedModelPart {
-                    index: indices.clone(),
+                    index,
                     material: material.cloned().map(Into::into),
                     vertices: Some(vertices),
                     texture,
+                    specular_map,
+                    normal_map,
+                    pbr,
+                    morph_targets,
                 });
             }
         }
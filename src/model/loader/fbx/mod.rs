@@ -2,7 +2,10 @@
 mod data;
 mod v7400;
 
-use crate::error::ModelError;
+use crate::{
+    error::ModelError,
+    model::{LoadingStage, Progress},
+};
 use data::Scene;
 use fbxcel_dom::{any::AnyDocument, fbxcel::low::FbxVersion, v7400::data::material::ShadingModel};
 use std::path::Path;
@@ -114,15 +117,16 @@ pub enum Error {
     },
 }
 
-/// Loads FBX data.
-pub fn load(path: impl AsRef<Path>) -> Result<Scene, ModelError> {
-    load_impl(path.as_ref()).map_err(ModelError::Fbx)
+/// Loads FBX data, and reports the progress of parsing, triangulating and decoding the textures.
+pub fn load(path: impl AsRef<Path>, progress: &mut Progress) -> Result<Scene, ModelError> {
+    load_impl(path.as_ref(), progress).map_err(ModelError::Fbx)
 }
 
 static SUPPORTED_VERSIONS: &[FbxVersion] = &[FbxVersion::V7_4];
 
 /// Loads FBX data.
-fn load_impl(path: &Path) -> Result<Scene, Error> {
+fn load_impl(path: &Path, progress: &mut Progress) -> Result<Scene, Error> {
+    progress.start(LoadingStage::Parse, 1);
     let file_name = path.to_str().unwrap_or("unknown");
     let file = std::io::BufReader::new(std::fs::File::open(path).map_err(|e| {
         Error::CouldNotOpenFile {
@@ -131,7 +135,10 @@ fn load_impl(path: &Path) -> Result<Scene, Error> {
         }
     })?);
     match AnyDocument::from_seekable_reader(file).map_err(Error::CouldNotParseDocument)? {
-        AnyDocument::V7400(_ver, doc) => v7400::from_doc(doc),
+        AnyDocument::V7400(_ver, doc) => {
+            progress.finish();
+            v7400::from_doc(doc, progress)
+        }
         x => Err(Error::UnsupportedFormat {
             version: x.fbx_version(),
             supported: SUPPORTED_VERSIONS,
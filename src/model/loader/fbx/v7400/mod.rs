@@ -1,37 +1,74 @@
 //! FBX v7400 support.
 
-use self::triangulator::triangulator;
+use self::{transform::NodeTransform, triangulator::triangulator};
 use super::{
     data::{
         GeometryMesh, GeometryMeshIndex, LambertData, Material, MaterialIndex, Mesh, MeshIndex,
-        Scene, ShadingData, Texture, TextureIndex, WrapMode,
+        MorphTarget, PbrData, PhongData, Scene, ShadingData, Texture, TextureIndex, WrapMode,
     },
     Error,
 };
-use cgmath::{Point2, Point3, Vector3};
-use fbxcel_dom::v7400::{
-    data::{
-        material::ShadingModel, mesh::layer::TypedLayerElementHandle,
-        texture::WrapMode as RawWrapMode,
+use crate::model::{LoadingStage, Progress};
+use cgmath::{Matrix4, Point2, Point3, SquareMatrix, Vector3, Zero};
+use fbxcel_dom::{
+    fbxcel::tree::v7400::NodeHandle,
+    v7400::{
+        data::{
+            material::ShadingModel, mesh::layer::TypedLayerElementHandle,
+            texture::WrapMode as RawWrapMode,
+        },
+        object::{
+            self,
+            model::{ModelHandle, TypedModelHandle},
+            property::loaders::{F64Arr3Loader, PrimitiveLoader},
+            ObjectId, TypedObjectHandle,
+        },
+        Document,
     },
-    object::{self, model::TypedModelHandle, ObjectId, TypedObjectHandle},
-    Document,
 };
 use std::{collections::HashMap, path::Path};
 
+mod transform;
 mod triangulator;
 
 type Result<T = ()> = std::result::Result<T, Error>;
 
+/// The property names of the base color, metallic and roughness values of the PBR materials we
+/// understand: Stingray PBS, Arnold standard surface and the 3ds Max physical material.
+const PBR_PROPERTIES: &[(&str, &str, &str)] = &[
+    ("Maya|base_color", "Maya|metallic", "Maya|roughness"),
+    ("Maya|baseColor", "Maya|metalness", "Maya|specularRoughness"),
+    (
+        "3dsMax|Parameters|base_color",
+        "3dsMax|Parameters|metalness",
+        "3dsMax|Parameters|roughness",
+    ),
+];
+
+/// The connection labels of the base color, metallic, roughness and ambient occlusion textures
+/// of a Stingray PBS material.
+const PBR_TEXTURES: (&str, &str, &str, &str) = (
+    "Maya|TEX_color_map",
+    "Maya|TEX_metallic_map",
+    "Maya|TEX_roughness_map",
+    "Maya|TEX_ao_map",
+);
+
+/// The connection labels of the specular color and normal map textures of Lambert and Phong
+/// materials.
+const SURFACE_TEXTURES: (&str, &str) = ("SpecularColor", "NormalMap");
+
 /// Loads the data from the document.
-pub fn from_doc(doc: Box<Document>) -> Result<Scene> {
-    Loader::new(&doc).load()
+pub fn from_doc(doc: Box<Document>, progress: &mut Progress) -> Result<Scene> {
+    Loader::new(&doc, progress).load()
 }
 
 /// FBX data loader.
-pub struct Loader<'a> {
+pub struct Loader<'a, 'p> {
     /// Document.
     doc: &'a Document,
+    /// Progress of the stage that is being loaded.
+    progress: &'a mut Progress<'p>,
     /// Scene.
     scene: Scene,
     /// Geometry mesh indices.
@@ -40,30 +77,60 @@ pub struct Loader<'a> {
     material_indices: HashMap<ObjectId, MaterialIndex>,
     /// Mesh indices.
     mesh_indices: HashMap<ObjectId, MeshIndex>,
+    /// Transforms of the model nodes, relative to the scene.
+    node_matrices: HashMap<ObjectId, Matrix4<f64>>,
     /// Texture indices.
     texture_indices: HashMap<ObjectId, TextureIndex>,
 }
 
-impl<'a> Loader<'a> {
+impl<'a, 'p> Loader<'a, 'p> {
     /// Creates a new `Loader`.
-    fn new(doc: &'a Document) -> Self {
+    fn new(doc: &'a Document, progress: &'a mut Progress<'p>) -> Self {
         Self {
             doc,
+            progress,
             scene: Default::default(),
             geometry_mesh_indices: Default::default(),
             material_indices: Default::default(),
             mesh_indices: Default::default(),
+            node_matrices: Default::default(),
             texture_indices: Default::default(),
         }
     }
 
     /// Loads the document.
     fn load(mut self) -> Result<Scene> {
-        for obj in self.doc.objects() {
-            if let TypedObjectHandle::Model(TypedModelHandle::Mesh(mesh)) = obj.get_typed() {
-                self.load_mesh(mesh)?;
-            }
+        let meshes = self
+            .doc
+            .objects()
+            .filter_map(|obj| match obj.get_typed() {
+                TypedObjectHandle::Model(TypedModelHandle::Mesh(mesh)) => Some(mesh),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        // The geometry is loaded first, so the progress of triangulating the meshes and decoding
+        // the textures can be reported separately. The geometry is cached for `load_mesh`.
+        self.progress.start(LoadingStage::Triangulate, meshes.len());
+        for mesh in &meshes {
+            let geometry_obj = mesh.geometry().map_err(Error::CouldNotLoadGeometry)?;
+            self.load_geometry_mesh(geometry_obj, mesh.materials().count())
+                .map_err(|e| Error::CouldNotLoadGeometryMesh(Box::new(e)))?;
+            self.progress.step();
         }
+        self.progress.finish();
+
+        let texture_count = self
+            .doc
+            .objects()
+            .filter(|obj| matches!(obj.get_typed(), TypedObjectHandle::Texture(_)))
+            .count();
+        self.progress
+            .start(LoadingStage::DecodeTextures, texture_count);
+        for mesh in meshes {
+            self.load_mesh(mesh)?;
+        }
+        self.progress.finish();
 
         Ok(self.scene)
     }
@@ -74,7 +141,8 @@ impl<'a> Loader<'a> {
         mesh_obj: object::geometry::MeshHandle<'a>,
         num_materials: usize,
     ) -> Result<GeometryMeshIndex> {
-        if let Some(index) = self.geometry_mesh_indices.get(&mesh_obj.object_id()) {
+        let id = mesh_obj.object_id();
+        if let Some(index) = self.geometry_mesh_indices.get(&id) {
             return Ok(*index);
         }
 
@@ -85,10 +153,13 @@ impl<'a> Loader<'a> {
             .triangulate_each(triangulator)
             .map_err(Error::CouldNotTriangulate)?;
 
-        let positions = triangle_pvi_indices
+        let control_points = triangle_pvi_indices
             .iter_control_point_indices()
             .filter_map(|cpi| cpi)
-            .filter_map(|cpi| polygon_vertices.control_point(cpi).map(Point3::from))
+            .collect::<Vec<_>>();
+        let positions = control_points
+            .iter()
+            .filter_map(|&cpi| polygon_vertices.control_point(cpi).map(Point3::from))
             .filter_map(|p| p.cast())
             .collect::<Vec<_>>();
 
@@ -164,15 +235,24 @@ impl<'a> Loader<'a> {
             });
         }
 
+        let control_points = control_points
+            .iter()
+            .map(|cpi| cpi.to_u32())
+            .collect::<Vec<_>>();
+        let morph_targets = load_morph_targets(mesh_obj, &control_points);
+
         let mesh = GeometryMesh {
             name: mesh_obj.name().map(Into::into),
             positions,
             normals,
             uv,
             indices_per_material,
+            morph_targets,
         };
 
-        Ok(self.scene.add_geometry_mesh(mesh))
+        let index = self.scene.add_geometry_mesh(mesh);
+        self.geometry_mesh_indices.insert(id, index);
+        Ok(index)
     }
 
     /// Loads the material.
@@ -180,7 +260,8 @@ impl<'a> Loader<'a> {
         &mut self,
         material_obj: object::material::MaterialHandle<'a>,
     ) -> Result<MaterialIndex> {
-        if let Some(index) = self.material_indices.get(&material_obj.object_id()) {
+        let id = material_obj.object_id();
+        if let Some(index) = self.material_indices.get(&id) {
             return Ok(*index);
         }
 
@@ -188,26 +269,55 @@ impl<'a> Loader<'a> {
             .transparent_texture()
             .map(|v| (true, v))
             .or_else(|| material_obj.diffuse_texture().map(|v| (false, v)))
+            .or_else(|| texture_by_label(material_obj, PBR_TEXTURES.0).map(|v| (false, v)))
             .and_then(|(transparent, texture_obj)| {
                 self.load_texture(texture_obj, transparent).ok()
             });
+        let mut load_map = |label: &str| {
+            texture_by_label(material_obj, label)
+                .and_then(|texture_obj| self.load_texture(texture_obj, false).ok())
+        };
+        let specular_texture = load_map(SURFACE_TEXTURES.0);
+        let normal_texture = load_map(SURFACE_TEXTURES.1);
+        let pbr = self.load_pbr(material_obj);
 
         let properties = material_obj.properties();
+        let ambient_color = properties.ambient_color_or_default().unwrap_or_default();
+        let ambient_factor = properties.ambient_factor_or_default().unwrap_or_default();
+        let ambient = ambient_color * ambient_factor;
+        let diffuse_color = properties.diffuse_color_or_default().unwrap_or_default();
+        let diffuse_factor = properties.diffuse_factor_or_default().unwrap_or_default();
+        let diffuse = diffuse_color * diffuse_factor;
+        let emissive_color = properties.emissive_color_or_default().unwrap_or_default();
+        let emissive_factor = properties.emissive_factor_or_default().unwrap_or_default();
+        let emissive = emissive_color * emissive_factor;
+        let lambert = LambertData {
+            ambient: [ambient.r as f32, ambient.g as f32, ambient.b as f32],
+            diffuse: [diffuse.r as f32, diffuse.g as f32, diffuse.b as f32],
+            emissive: [emissive.r as f32, emissive.g as f32, emissive.b as f32],
+        };
         let shading_data = match properties.shading_model_or_default() {
-            Ok(ShadingModel::Lambert) | Ok(ShadingModel::Phong) => {
-                let ambient_color = properties.ambient_color_or_default().unwrap_or_default();
-                let ambient_factor = properties.ambient_factor_or_default().unwrap_or_default();
-                let ambient = ambient_color * ambient_factor;
-                let diffuse_color = properties.diffuse_color_or_default().unwrap_or_default();
-                let diffuse_factor = properties.diffuse_factor_or_default().unwrap_or_default();
-                let diffuse = diffuse_color * diffuse_factor;
-                let emissive_color = properties.emissive_color_or_default().unwrap_or_default();
-                let emissive_factor = properties.emissive_factor_or_default().unwrap_or_default();
-                let emissive = emissive_color * emissive_factor;
+            Ok(ShadingModel::Lambert) => ShadingData::Lambert(lambert),
+            Ok(ShadingModel::Phong) => {
+                let specular_color = properties.specular_or_default().unwrap_or_default();
+                let specular_factor = properties.specular_factor_or_default().unwrap_or_default();
+                let specular = specular_color * specular_factor;
+                let shininess = properties.shininess_or_default().unwrap_or_default();
+                ShadingData::Phong(PhongData {
+                    lambert,
+                    specular: [specular.r as f32, specular.g as f32, specular.b as f32],
+                    shininess: shininess as f32,
+                })
+            }
+            // PBR materials usually have a custom shading model, fall back to a lambert
+            // material with the same base color for the non-PBR properties.
+            _ if pbr.is_some() => {
+                // should never fail because we just checked that `pbr` is `Some`
+                let base_color = pbr.unwrap().base_color;
                 ShadingData::Lambert(LambertData {
-                    ambient: [ambient.r as f32, ambient.g as f32, ambient.b as f32],
-                    diffuse: [diffuse.r as f32, diffuse.g as f32, diffuse.b as f32],
-                    emissive: [emissive.r as f32, emissive.g as f32, emissive.b as f32],
+                    ambient: [0.0; 3],
+                    diffuse: base_color,
+                    emissive: [0.0; 3],
                 })
             }
             v => return Err(Error::UnknownShadingModel(v)),
@@ -216,15 +326,61 @@ impl<'a> Loader<'a> {
         let material = Material {
             name: material_obj.name().map(Into::into),
             diffuse_texture,
+            specular_texture,
+            normal_texture,
             data: shading_data,
+            pbr,
         };
 
-        Ok(self.scene.add_material(material))
+        let index = self.scene.add_material(material);
+        self.material_indices.insert(id, index);
+        Ok(index)
+    }
+
+    /// Loads the PBR metallic-roughness properties of the material, if it has any.
+    fn load_pbr(&mut self, material_obj: object::material::MaterialHandle<'a>) -> Option<PbrData> {
+        let properties = material_obj.properties();
+        let (base_color, metallic, roughness) =
+            PBR_PROPERTIES
+                .iter()
+                .find_map(|(base_color, metallic, roughness)| {
+                    let base_color = properties
+                        .get_property(base_color)?
+                        .load_value(F64Arr3Loader::new())
+                        .ok()?;
+                    let metallic = properties
+                        .get_property(metallic)?
+                        .load_value(PrimitiveLoader::<f64>::new())
+                        .ok()?;
+                    let roughness = properties
+                        .get_property(roughness)?
+                        .load_value(PrimitiveLoader::<f64>::new())
+                        .ok()?;
+                    Some((base_color, metallic, roughness))
+                })?;
+
+        let mut load_map = |label: &str| {
+            texture_by_label(material_obj, label)
+                .and_then(|texture_obj| self.load_texture(texture_obj, false).ok())
+        };
+        Some(PbrData {
+            base_color: [
+                base_color[0] as f32,
+                base_color[1] as f32,
+                base_color[2] as f32,
+            ],
+            metallic: metallic as f32,
+            roughness: roughness as f32,
+            metallic_texture: load_map(PBR_TEXTURES.1),
+            roughness_texture: load_map(PBR_TEXTURES.2),
+            ambient_occlusion_texture: load_map(PBR_TEXTURES.3),
+        })
     }
 
     /// Loads the mesh.
     fn load_mesh(&mut self, mesh_obj: object::model::MeshHandle<'a>) -> Result<MeshIndex> {
-        if let Some(index) = self.mesh_indices.get(&mesh_obj.object_id()) {
+        let id = mesh_obj.object_id();
+        if let Some(index) = self.mesh_indices.get(&id) {
             return Ok(*index);
         }
 
@@ -240,13 +396,38 @@ impl<'a> Loader<'a> {
             .load_geometry_mesh(geometry_obj, materials.len())
             .map_err(|e| Error::CouldNotLoadGeometryMesh(Box::new(e)))?;
 
+        // The geometric transform only applies to the geometry of this node, not to its children
+        let transform =
+            self.node_matrix(*mesh_obj) * NodeTransform::load(*mesh_obj).geometric_matrix();
+
         let mesh = Mesh {
             name: mesh_obj.name().map(Into::into),
             geometry_mesh_index: geometry_index,
             materials,
+            transform,
         };
 
-        Ok(self.scene.add_mesh(mesh))
+        let index = self.scene.add_mesh(mesh);
+        self.mesh_indices.insert(id, index);
+        Ok(index)
+    }
+
+    /// Returns the transform from the space of the model node to the space of the scene, with the
+    /// transforms of its parents applied.
+    fn node_matrix(&mut self, model_obj: ModelHandle<'a>) -> Matrix4<f64> {
+        let id = model_obj.object_id();
+        if let Some(matrix) = self.node_matrices.get(&id) {
+            return *matrix;
+        }
+        // A broken file could make a node its own ancestor, the loop is cut off at this node
+        self.node_matrices.insert(id, Matrix4::identity());
+        let parent = model_obj
+            .parent_model()
+            .map(|parent_obj| self.node_matrix(*parent_obj))
+            .unwrap_or_else(Matrix4::identity);
+        let matrix = parent * NodeTransform::load(model_obj).local_matrix();
+        self.node_matrices.insert(id, matrix);
+        matrix
     }
 
     /// Loads the texture.
@@ -255,9 +436,11 @@ impl<'a> Loader<'a> {
         texture_obj: object::texture::TextureHandle<'a>,
         transparent: bool,
     ) -> Result<TextureIndex> {
-        if let Some(index) = self.texture_indices.get(&texture_obj.object_id()) {
+        let id = texture_obj.object_id();
+        if let Some(index) = self.texture_indices.get(&id) {
             return Ok(*index);
         }
+        self.progress.step();
 
         let properties = texture_obj.properties();
         let wrap_mode_u = {
@@ -291,7 +474,9 @@ impl<'a> Loader<'a> {
             wrap_mode_v,
         };
 
-        Ok(self.scene.add_texture(texture))
+        let index = self.scene.add_texture(texture);
+        self.texture_indices.insert(id, index);
+        Ok(index)
     }
 
     /// Loads the texture image.
@@ -316,3 +501,95 @@ impl<'a> Loader<'a> {
         Ok(image)
     }
 }
+
+/// Loads the first shape of every blendshape channel of the mesh, with an offset for the control
+/// point of every vertex. Shapes without valid data are skipped.
+fn load_morph_targets(
+    mesh_obj: object::geometry::MeshHandle<'_>,
+    control_points: &[u32],
+) -> Vec<MorphTarget> {
+    let mut targets = Vec::new();
+    for channel in mesh_obj
+        .blendshapes()
+        .flat_map(|blendshape| blendshape.blendshape_channels())
+    {
+        let shape = match channel.shapes().next() {
+            Some(shape) => shape,
+            None => continue,
+        };
+        let node = shape.node();
+        let (indices, vertices) = match (i32_array(node, "Indexes"), f64_array(node, "Vertices")) {
+            (Some(indices), Some(vertices)) => (indices, vertices),
+            _ => continue,
+        };
+        // Shapes without normals only move the positions
+        let normals = f64_array(node, "Normals").unwrap_or(&[]);
+
+        // The offsets are stored for the control points that the shape moves
+        let mut offsets = HashMap::new();
+        for (i, &index) in indices.iter().enumerate() {
+            let vector = |data: &[f64]| {
+                data.get(i * 3..i * 3 + 3)
+                    .map(|v| Vector3::new(v[0] as f32, v[1] as f32, v[2] as f32))
+                    .unwrap_or_else(Vector3::zero)
+            };
+            offsets.insert(index as u32, (vector(vertices), vector(normals)));
+        }
+        let (positions, normals) = control_points
+            .iter()
+            .map(|cpi| {
+                offsets
+                    .get(cpi)
+                    .copied()
+                    .unwrap_or_else(|| (Vector3::zero(), Vector3::zero()))
+            })
+            .unzip();
+
+        let name = channel
+            .name()
+            .or_else(|| shape.name())
+            .map(String::from)
+            .unwrap_or_else(|| format!("morph{}", targets.len()));
+        targets.push(MorphTarget {
+            name,
+            positions,
+            normals,
+        });
+    }
+    targets
+}
+
+/// Returns the `[i32]` attribute of the child node with the given name, if available.
+fn i32_array<'a>(node: NodeHandle<'a>, name: &str) -> Option<&'a [i32]> {
+    node.children_by_name(name)
+        .next()?
+        .attributes()
+        .get(0)?
+        .get_arr_i32_or_type()
+        .ok()
+}
+
+/// Returns the `[f64]` attribute of the child node with the given name, if available.
+fn f64_array<'a>(node: NodeHandle<'a>, name: &str) -> Option<&'a [f64]> {
+    node.children_by_name(name)
+        .next()?
+        .attributes()
+        .get(0)?
+        .get_arr_f64_or_type()
+        .ok()
+}
+
+/// Returns the texture connected to the material with the given label, if available.
+fn texture_by_label<'a>(
+    material_obj: object::material::MaterialHandle<'a>,
+    label: &str,
+) -> Option<object::texture::TextureHandle<'a>> {
+    material_obj
+        .source_objects()
+        .filter(|obj| obj.label() == Some(label))
+        .filter_map(|obj| obj.object_handle())
+        .find_map(|obj| match obj.get_typed() {
+            TypedObjectHandle::Texture(texture_obj) => Some(texture_obj),
+            _ => None,
+        })
+}
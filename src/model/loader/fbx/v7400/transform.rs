@@ -0,0 +1,264 @@
+//! Node transforms.
+
+use cgmath::{Deg, Matrix4, Vector3};
+use fbxcel_dom::v7400::object::{
+    model::ModelHandle,
+    property::{
+        loaders::{F64Arr3Loader, PrimitiveLoader},
+        ObjectProperties,
+    },
+};
+
+/// The order in which the euler angles of a rotation are applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RotationOrder {
+    /// X, then Y, then Z. This is the default.
+    Xyz,
+    /// X, then Z, then Y.
+    Xzy,
+    /// Y, then Z, then X.
+    Yzx,
+    /// Y, then X, then Z.
+    Yxz,
+    /// Z, then X, then Y.
+    Zxy,
+    /// Z, then Y, then X.
+    Zyx,
+}
+
+impl RotationOrder {
+    /// Returns the order of the `RotationOrder` property value.
+    fn from_raw(value: i32) -> Self {
+        match value {
+            1 => RotationOrder::Xzy,
+            2 => RotationOrder::Yzx,
+            3 => RotationOrder::Yxz,
+            4 => RotationOrder::Zxy,
+            5 => RotationOrder::Zyx,
+            _ => RotationOrder::Xyz,
+        }
+    }
+}
+
+/// The transform properties of a model node. Angles are in degrees.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NodeTransform {
+    /// Translation.
+    pub translation: Vector3<f64>,
+    /// Rotation.
+    pub rotation: Vector3<f64>,
+    /// Rotation order of `rotation`.
+    pub rotation_order: RotationOrder,
+    /// Rotation that is applied before `rotation`.
+    pub pre_rotation: Vector3<f64>,
+    /// Rotation that is undone after `rotation`.
+    pub post_rotation: Vector3<f64>,
+    /// Rotation offset.
+    pub rotation_offset: Vector3<f64>,
+    /// The point that the node is rotated around.
+    pub rotation_pivot: Vector3<f64>,
+    /// Scaling.
+    pub scaling: Vector3<f64>,
+    /// Scaling offset.
+    pub scaling_offset: Vector3<f64>,
+    /// The point that the node is scaled around.
+    pub scaling_pivot: Vector3<f64>,
+    /// Translation of the geometry, which is not inherited by the children.
+    pub geometric_translation: Vector3<f64>,
+    /// Rotation of the geometry, which is not inherited by the children.
+    pub geometric_rotation: Vector3<f64>,
+    /// Scaling of the geometry, which is not inherited by the children.
+    pub geometric_scaling: Vector3<f64>,
+}
+
+impl Default for NodeTransform {
+    fn default() -> Self {
+        let zero = Vector3::new(0.0, 0.0, 0.0);
+        let one = Vector3::new(1.0, 1.0, 1.0);
+        Self {
+            translation: zero,
+            rotation: zero,
+            rotation_order: RotationOrder::Xyz,
+            pre_rotation: zero,
+            post_rotation: zero,
+            rotation_offset: zero,
+            rotation_pivot: zero,
+            scaling: one,
+            scaling_offset: zero,
+            scaling_pivot: zero,
+            geometric_translation: zero,
+            geometric_rotation: zero,
+            geometric_scaling: one,
+        }
+    }
+}
+
+impl NodeTransform {
+    /// Loads the transform of the model. Missing properties keep their default value.
+    pub fn load(model_obj: ModelHandle<'_>) -> Self {
+        let properties = model_obj.properties_by_native_typename("FbxNode");
+        let default = Self::default();
+        let vector =
+            |name: &str, default: Vector3<f64>| load_vector(&properties, name).unwrap_or(default);
+        let rotation_order = properties
+            .get_property("RotationOrder")
+            .and_then(|p| p.load_value(PrimitiveLoader::<i32>::new()).ok())
+            .map(RotationOrder::from_raw)
+            .unwrap_or(default.rotation_order);
+        // Pre- and post-rotation are only applied when the rotation is active
+        let rotation_active = properties
+            .get_property("RotationActive")
+            .and_then(|p| p.load_value(PrimitiveLoader::<bool>::new()).ok())
+            .unwrap_or(false);
+        let (pre_rotation, post_rotation) = if rotation_active {
+            (
+                vector("PreRotation", default.pre_rotation),
+                vector("PostRotation", default.post_rotation),
+            )
+        } else {
+            (default.pre_rotation, default.post_rotation)
+        };
+
+        Self {
+            translation: vector("Lcl Translation", default.translation),
+            rotation: vector("Lcl Rotation", default.rotation),
+            rotation_order,
+            pre_rotation,
+            post_rotation,
+            rotation_offset: vector("RotationOffset", default.rotation_offset),
+            rotation_pivot: vector("RotationPivot", default.rotation_pivot),
+            scaling: vector("Lcl Scaling", default.scaling),
+            scaling_offset: vector("ScalingOffset", default.scaling_offset),
+            scaling_pivot: vector("ScalingPivot", default.scaling_pivot),
+            geometric_translation: vector("GeometricTranslation", default.geometric_translation),
+            geometric_rotation: vector("GeometricRotation", default.geometric_rotation),
+            geometric_scaling: vector("GeometricScaling", default.geometric_scaling),
+        }
+    }
+
+    /// Returns the transform of the node relative to its parent. The pivots and offsets are
+    /// applied in the same order as the FBX SDK:
+    ///
+    /// `T * Roff * Rp * Rpre * R * Rpost^-1 * Rp^-1 * Soff * Sp * S * Sp^-1`
+    pub fn local_matrix(&self) -> Matrix4<f64> {
+        let translation = Matrix4::from_translation;
+        let scaling = |s: Vector3<f64>| Matrix4::from_nonuniform_scale(s.x, s.y, s.z);
+        translation(self.translation)
+            * translation(self.rotation_offset)
+            * translation(self.rotation_pivot)
+            * rotation_matrix(self.pre_rotation, RotationOrder::Xyz)
+            * rotation_matrix(self.rotation, self.rotation_order)
+            * rotation_matrix(-self.post_rotation, RotationOrder::Zyx)
+            * translation(-self.rotation_pivot)
+            * translation(self.scaling_offset)
+            * translation(self.scaling_pivot)
+            * scaling(self.scaling)
+            * translation(-self.scaling_pivot)
+    }
+
+    /// Returns the transform of the geometry of the node, relative to the node.
+    pub fn geometric_matrix(&self) -> Matrix4<f64> {
+        let s = self.geometric_scaling;
+        Matrix4::from_translation(self.geometric_translation)
+            * rotation_matrix(self.geometric_rotation, RotationOrder::Xyz)
+            * Matrix4::from_nonuniform_scale(s.x, s.y, s.z)
+    }
+}
+
+/// Returns the rotation of the euler angles in degrees, applied in the given order.
+fn rotation_matrix(angles: Vector3<f64>, order: RotationOrder) -> Matrix4<f64> {
+    let x = Matrix4::from_angle_x(Deg(angles.x));
+    let y = Matrix4::from_angle_y(Deg(angles.y));
+    let z = Matrix4::from_angle_z(Deg(angles.z));
+    // The first rotation is applied to the vector first, so it is the last in the product
+    match order {
+        RotationOrder::Xyz => z * y * x,
+        RotationOrder::Xzy => y * z * x,
+        RotationOrder::Yzx => x * z * y,
+        RotationOrder::Yxz => z * x * y,
+        RotationOrder::Zxy => y * x * z,
+        RotationOrder::Zyx => x * y * z,
+    }
+}
+
+/// Returns the `[f64; 3]` property with the given name, if available.
+fn load_vector(properties: &ObjectProperties<'_>, name: &str) -> Option<Vector3<f64>> {
+    properties
+        .get_property(name)?
+        .load_value(F64Arr3Loader::new())
+        .ok()
+        .map(Vector3::from)
+}
+
+#[test]
+fn test_node_transform() {
+    use cgmath::{InnerSpace, Point3, Transform};
+
+    let assert_close = |expected: Point3<f64>, actual: Point3<f64>| {
+        assert!(
+            (expected - actual).magnitude() < 1e-9,
+            "{:?} != {:?}",
+            expected,
+            actual
+        );
+    };
+
+    // Rotating around the pivot keeps the pivot in place
+    let transform = NodeTransform {
+        translation: Vector3::new(10.0, 0.0, 0.0),
+        rotation: Vector3::new(0.0, 0.0, 90.0),
+        rotation_pivot: Vector3::new(1.0, 0.0, 0.0),
+        ..Default::default()
+    };
+    let matrix = transform.local_matrix();
+    assert_close(
+        Point3::new(11.0, 0.0, 0.0),
+        matrix.transform_point(Point3::new(1.0, 0.0, 0.0)),
+    );
+    assert_close(
+        Point3::new(11.0, 1.0, 0.0),
+        matrix.transform_point(Point3::new(2.0, 0.0, 0.0)),
+    );
+
+    // X is applied first, so the Y axis is rotated onto Z before it is rotated around Y
+    let transform = NodeTransform {
+        rotation: Vector3::new(90.0, 90.0, 0.0),
+        ..Default::default()
+    };
+    assert_close(
+        Point3::new(1.0, 0.0, 0.0),
+        transform
+            .local_matrix()
+            .transform_point(Point3::new(0.0, 1.0, 0.0)),
+    );
+    let transform = NodeTransform {
+        rotation_order: RotationOrder::Yxz,
+        ..transform
+    };
+    assert_close(
+        Point3::new(0.0, 0.0, 1.0),
+        transform
+            .local_matrix()
+            .transform_point(Point3::new(0.0, 1.0, 0.0)),
+    );
+
+    // The post-rotation is undone, so it cancels out the same pre-rotation
+    let transform = NodeTransform {
+        pre_rotation: Vector3::new(30.0, 40.0, 50.0),
+        post_rotation: Vector3::new(30.0, 40.0, 50.0),
+        geometric_scaling: Vector3::new(2.0, 2.0, 2.0),
+        ..Default::default()
+    };
+    assert_close(
+        Point3::new(1.0, 2.0, 3.0),
+        transform
+            .local_matrix()
+            .transform_point(Point3::new(1.0, 2.0, 3.0)),
+    );
+    assert_close(
+        Point3::new(2.0, 4.0, 6.0),
+        transform
+            .geometric_matrix()
+            .transform_point(Point3::new(1.0, 2.0, 3.0)),
+    );
+}
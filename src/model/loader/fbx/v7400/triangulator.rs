@@ -1,9 +1,7 @@
 //! Triangulator.
 
-use std::f64;
-
 use anyhow::{anyhow, bail};
-use cgmath::{InnerSpace, Point3, Vector2, Vector3};
+use cgmath::{Point3, Vector2, Vector3, Zero};
 use fbxcel_dom::v7400::data::mesh::{PolygonVertexIndex, PolygonVertices};
 
 /// Triangulator.
@@ -12,12 +10,6 @@ pub fn triangulator(
     poly_pvis: &[PolygonVertexIndex],
     results: &mut Vec<[PolygonVertexIndex; 3]>,
 ) -> anyhow::Result<()> {
-    macro_rules! get_vec {
-        ($pvii:expr) => {
-            get_vec(pvs, poly_pvis[$pvii])
-        };
-    }
-
     match poly_pvis.len() {
         n @ 0..=2 => {
             // Not a polygon.
@@ -29,110 +21,19 @@ pub fn triangulator(
             results.push([poly_pvis[0], poly_pvis[1], poly_pvis[2]]);
             Ok(())
         }
-        4 => {
-            // p0, p1, p2, p3: vertices of the quadrangle (angle{0..3}).
-            let p0 = get_vec!(0)?;
-            let p1 = get_vec!(1)?;
-            let p2 = get_vec!(2)?;
-            let p3 = get_vec!(3)?;
-            // n1: Normal vector calculated with two edges of the angle1.
-            // n3: Normal vector calculated with two edges of the angle3.
-            let n1 = (p0 - p1).cross(p1 - p2);
-            let n3 = (p2 - p3).cross(p3 - p0);
-            // If both angle1 and angle3 are concave, vectors n1 and n3 are
-            // oriented in the same direction and `n1.dot(n3)` will be positive.
-            // If either angle1 or angle3 is concave, vector n1 and n3 are
-            // oriented in the opposite directions and `n1.dot(n3)` will be
-            // negative.
-            // It does not matter when the vertices of quadrangle is not on the
-            // same plane, because whichever diagonal you choose, the cut will
-            // be inaccurate.
-            if n1.dot(n3) >= 0.0 {
-                // Both angle1 and angle3 are concave.
-                // This means that either angle0 or angle2 can be convex.
-                // Cut from p0 to p2.
-                results.extend_from_slice(&[
-                    [poly_pvis[0], poly_pvis[1], poly_pvis[2]],
-                    [poly_pvis[2], poly_pvis[3], poly_pvis[0]],
-                ]);
-            } else {
-                // Either angle1 or angle3 is convex.
-                // Cut from p1 to p3.
-                results.extend_from_slice(&[
-                    [poly_pvis[0], poly_pvis[1], poly_pvis[3]],
-                    [poly_pvis[3], poly_pvis[1], poly_pvis[2]],
-                ]);
-            }
+        _ => {
+            let points = poly_pvis
+                .iter()
+                .map(|&pvi| get_vec(pvs, pvi))
+                .collect::<Result<Vec<_>, _>>()?;
+            let triangles = ear_clipping(&project(&points));
+            results.extend(
+                triangles
+                    .into_iter()
+                    .map(|[a, b, c]| [poly_pvis[a], poly_pvis[b], poly_pvis[c]]),
+            );
             Ok(())
         }
-        n => {
-            let points = (0..n).map(|i| get_vec!(i)).collect::<Result<Vec<_>, _>>()?;
-            let points_2d: Vec<_> = {
-                // Reduce dimensions for faster computation.
-                // This helps treat points which are not on a single plane.
-                let (min, max) =
-                    bounding_box(&points).expect("Should never happen: there are 5 or more points");
-                let width = max - min;
-                match smallest_direction(&width) {
-                    Axis::X => points
-                        .into_iter()
-                        .map(|v| Vector2::new(v[1], v[2]))
-                        .collect(),
-                    Axis::Y => points
-                        .into_iter()
-                        .map(|v| Vector2::new(v[0], v[2]))
-                        .collect(),
-                    Axis::Z => points
-                        .into_iter()
-                        .map(|v| Vector2::new(v[0], v[1]))
-                        .collect(),
-                }
-            };
-            // Normal directions.
-            let normal_directions = {
-                // 0 ... n-1
-                let iter_cur = points_2d.iter();
-                // n-1, 0, ... n-2
-                let iter_prev = points_2d.iter().cycle().skip(n - 1);
-                // 1, ... n-1, 0
-                let iter_next = points_2d.iter().cycle().skip(1);
-                iter_cur
-                    .zip(iter_prev)
-                    .zip(iter_next)
-                    .map(|((cur, prev), next)| {
-                        let prev_cur = prev - cur;
-                        let cur_next = cur - next;
-                        prev_cur.perp_dot(cur_next) > 0.0
-                    })
-                    .collect::<Vec<_>>()
-            };
-            assert_eq!(normal_directions.len(), n);
-
-            let dirs_true_count = normal_directions.iter().filter(|&&v| v).count();
-            if dirs_true_count <= 1 || dirs_true_count >= n - 1 {
-                // Zero or one angles are concave.
-                let minor_sign = dirs_true_count <= 1;
-                // If there are no concave angles, use 0 as center.
-                let convex_index = normal_directions
-                    .iter()
-                    .position(|&sign| sign == minor_sign)
-                    .unwrap_or(0);
-
-                let convex_pvi = poly_pvis[convex_index];
-                let iter1 = (0..n)
-                    .cycle()
-                    .skip(convex_index + 1)
-                    .take(n - 2)
-                    .map(|i| poly_pvis[i]);
-                let iter2 = (0..n).cycle().skip(convex_index + 2).map(|i| poly_pvis[i]);
-                for (pvi1, pvi2) in iter1.zip(iter2) {
-                    results.push([convex_pvi, pvi1, pvi2]);
-                }
-                Ok(())
-            } else {
-                bail!("Unsupported polygon: {}-gon with two or more concave angles");
-            }
-        }
     }
 }
 
@@ -143,29 +44,100 @@ fn get_vec(pvs: &PolygonVertices<'_>, pvi: PolygonVertexIndex) -> anyhow::Result
         .ok_or_else(|| anyhow!("Index out of range: {:?}", pvi))
 }
 
-/// Returns bounding box as `(min, max)`.
-fn bounding_box<'a>(
-    points: impl IntoIterator<Item = &'a Point3<f64>>,
-) -> Option<(Point3<f64>, Point3<f64>)> {
-    points.into_iter().fold(None, |minmax, point| {
-        minmax.map_or_else(
-            || Some((*point, *point)),
-            |(min, max)| {
-                Some((
-                    Point3 {
-                        x: min.x.min(point.x),
-                        y: min.y.min(point.y),
-                        z: min.z.min(point.z),
-                    },
-                    Point3 {
-                        x: max.x.max(point.x),
-                        y: max.y.max(point.y),
-                        z: max.z.max(point.z),
-                    },
-                ))
-            },
-        )
-    })
+/// Projects the points of a polygon onto the axis-aligned plane that the polygon faces the most.
+/// This also works for polygons whose points are not on a single plane.
+fn project(points: &[Point3<f64>]) -> Vec<Vector2<f64>> {
+    // Newell's method, which gives the normal of concave polygons as well
+    let normal = points.iter().zip(points.iter().cycle().skip(1)).fold(
+        Vector3::zero(),
+        |normal, (cur, next)| {
+            normal
+                + Vector3::new(
+                    (cur.y - next.y) * (cur.z + next.z),
+                    (cur.z - next.z) * (cur.x + next.x),
+                    (cur.x - next.x) * (cur.y + next.y),
+                )
+        },
+    );
+    let axis = largest_direction(&normal.map(f64::abs));
+    points
+        .iter()
+        .map(|p| match axis {
+            Axis::X => Vector2::new(p.y, p.z),
+            Axis::Y => Vector2::new(p.x, p.z),
+            Axis::Z => Vector2::new(p.x, p.y),
+        })
+        .collect()
+}
+
+/// Triangulates a polygon by cutting off its ears one by one. An ear is a convex corner whose
+/// triangle contains no other corner of the polygon. Every simple polygon has at least two ears,
+/// no matter how many concave corners it has.
+///
+/// Returns the indices of the corners of the triangles, in the winding order of the polygon.
+/// Polygons that are not simple, e.g. because their edges cross, can run out of ears. Their
+/// convex corners are then cut off regardless, so every polygon is triangulated.
+fn ear_clipping(points: &[Vector2<f64>]) -> Vec<[usize; 3]> {
+    // The corners of an ear turn in the same direction as the polygon itself
+    let orientation = signed_area(points).signum();
+    let mut remaining = (0..points.len()).collect::<Vec<_>>();
+    let mut triangles = Vec::with_capacity(points.len().saturating_sub(2));
+    while remaining.len() > 3 {
+        let count = remaining.len();
+        let corner = |i: usize| {
+            [
+                remaining[(i + count - 1) % count],
+                remaining[i],
+                remaining[(i + 1) % count],
+            ]
+        };
+        let is_convex = |[prev, cur, next]: [usize; 3]| {
+            (points[cur] - points[prev]).perp_dot(points[next] - points[cur]) * orientation > 0.0
+        };
+        let is_ear = |i: usize| {
+            let triangle = corner(i);
+            let corners = [
+                points[triangle[0]],
+                points[triangle[1]],
+                points[triangle[2]],
+            ];
+            is_convex(triangle)
+                && !remaining.iter().any(|&other| {
+                    // Polygons can touch themselves, so a point at the same position as a corner
+                    // does not block the ear
+                    !corners.contains(&points[other]) && in_triangle(points[other], corners)
+                })
+        };
+        let ear = (0..count)
+            .find(|&i| is_ear(i))
+            .or_else(|| (0..count).find(|&i| is_convex(corner(i))))
+            .unwrap_or(0);
+        triangles.push(corner(ear));
+        remaining.remove(ear);
+    }
+    if let [a, b, c] = remaining[..] {
+        triangles.push([a, b, c]);
+    }
+    triangles
+}
+
+/// Returns twice the signed area of a polygon, which is positive for counter-clockwise polygons.
+fn signed_area(points: &[Vector2<f64>]) -> f64 {
+    points
+        .iter()
+        .zip(points.iter().cycle().skip(1))
+        .map(|(cur, next)| cur.perp_dot(*next))
+        .sum()
+}
+
+/// Returns true if the point is inside the triangle or on one of its edges.
+fn in_triangle(point: Vector2<f64>, [a, b, c]: [Vector2<f64>; 3]) -> bool {
+    let sides = [
+        (b - a).perp_dot(point - a),
+        (c - b).perp_dot(point - b),
+        (a - c).perp_dot(point - c),
+    ];
+    sides.iter().all(|&side| side >= 0.0) || sides.iter().all(|&side| side <= 0.0)
 }
 
 /// Axis.
@@ -179,17 +151,54 @@ enum Axis {
     Z,
 }
 
-/// Returns smallest direction.
-fn smallest_direction(v: &Vector3<f64>) -> Axis {
-    if v.x < v.y {
-        if v.z < v.x {
+/// Returns largest direction.
+fn largest_direction(v: &Vector3<f64>) -> Axis {
+    if v.x > v.y {
+        if v.z > v.x {
             Axis::Z
         } else {
             Axis::X
         }
-    } else if v.z < v.y {
+    } else if v.z > v.y {
         Axis::Z
     } else {
         Axis::Y
     }
 }
+
+#[test]
+fn test_ear_clipping() {
+    // A comb with three teeth, which has four concave corners
+    let comb = [
+        (0.0, 0.0),
+        (5.0, 0.0),
+        (5.0, 3.0),
+        (4.0, 3.0),
+        (4.0, 1.0),
+        (3.0, 1.0),
+        (3.0, 3.0),
+        (2.0, 3.0),
+        (2.0, 1.0),
+        (1.0, 1.0),
+        (1.0, 3.0),
+        (0.0, 3.0),
+    ]
+    .iter()
+    .map(|&(x, y)| Vector2::new(x, y))
+    .collect::<Vec<_>>();
+    let clockwise = comb.iter().rev().copied().collect::<Vec<_>>();
+
+    for points in &[comb, clockwise] {
+        let area = signed_area(points);
+        let triangles = ear_clipping(points);
+        assert_eq!(points.len() - 2, triangles.len());
+        // Every triangle has the winding of the polygon, so they can only cover the polygon
+        // exactly if they do not overlap
+        let triangle_areas = triangles
+            .iter()
+            .map(|triangle| signed_area(&triangle.iter().map(|&i| points[i]).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        assert!(triangle_areas.iter().all(|a| a * area > 0.0));
+        assert!((triangle_areas.iter().sum::<f64>() - area).abs() < 1e-9);
+    }
+}
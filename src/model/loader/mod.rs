@@ -1,12 +1,20 @@
 use crate::{
-    model::{Material, Vertex},
-    state::ModelError,
+    assets::AssetPaths,
+    model::{Material, MeshStatistics, PbrMaterial, Progress, Vertex},
+    scene::ModelSource,
+    state::{ModelError, ParsedModelError},
 };
+use cgmath::Vector3;
 
+mod builder;
 #[cfg(feature = "format-fbx")]
 pub mod fbx;
 #[cfg(feature = "format-obj")]
 pub mod obj;
+pub(crate) mod shapes;
+mod simplify;
+
+pub use builder::ParsedModelBuilder;
 
 pub enum SourceOrShape<'a> {
     #[cfg(feature = "format-obj")]
@@ -15,6 +23,22 @@ pub enum SourceOrShape<'a> {
     Fbx(&'a str),
     Triangle,
     Rectangle,
+    Cube,
+    UvSphere {
+        segments: u32,
+        rings: u32,
+    },
+    Cylinder {
+        segments: u32,
+    },
+    Plane {
+        subdivisions: u32,
+    },
+    Capsule {
+        segments: u32,
+        rings: u32,
+        height: f32,
+    },
     Custom(ParsedModel),
 
     // This dummy is needed to prevent compile issues when no formats are enabled because of the unused lifetime 'a
@@ -24,19 +48,71 @@ pub enum SourceOrShape<'a> {
 }
 
 impl SourceOrShape<'_> {
-    pub fn parse(self) -> Result<ParsedModel, ModelError> {
+    /// Load the model. Files are looked up in the directories of `assets`. The progress of the stages before the model is uploaded is reported to `progress`.
+    pub fn parse(
+        self,
+        assets: &AssetPaths,
+        progress: &mut Progress,
+    ) -> Result<ParsedModel, ModelError> {
         match self {
             #[cfg(feature = "format-obj")]
-            SourceOrShape::Obj(src) => obj::load(src).map_err(ModelError::Obj),
+            SourceOrShape::Obj(src) => {
+                obj::load(&assets.resolve(src), progress).map_err(ModelError::Obj)
+            }
 
             #[cfg(feature = "format-fbx")]
-            SourceOrShape::Fbx(src) => fbx::load(src).map(Into::into),
+            SourceOrShape::Fbx(src) => fbx::load(assets.resolve(src), progress).map(Into::into),
             SourceOrShape::Rectangle => Ok(RECTANGLE.into()),
             SourceOrShape::Triangle => Ok(TRIANGLE.into()),
-            SourceOrShape::Custom(model) => Ok(model),
+            SourceOrShape::Cube => Ok(shapes::cube()),
+            SourceOrShape::UvSphere { segments, rings } => Ok(shapes::uv_sphere(segments, rings)),
+            SourceOrShape::Cylinder { segments } => Ok(shapes::cylinder(segments)),
+            SourceOrShape::Plane { subdivisions } => Ok(shapes::plane(subdivisions)),
+            SourceOrShape::Capsule {
+                segments,
+                rings,
+                height,
+            } => Ok(shapes::capsule(segments, rings, height)),
+            SourceOrShape::Custom(model) => model
+                .validate()
+                .map(|()| model)
+                .map_err(ModelError::InvalidParsedModel),
             SourceOrShape::Dummy(_) => unimplemented!(),
         }
     }
+
+    /// Get the source that this model can be loaded from again, e.g. when a scene is loaded. Models that are created from a [ParsedModel] have no such source.
+    pub fn to_model_source(&self) -> Option<ModelSource> {
+        Some(match self {
+            #[cfg(feature = "format-obj")]
+            SourceOrShape::Obj(src) => ModelSource::Obj(src.to_string()),
+            #[cfg(feature = "format-fbx")]
+            SourceOrShape::Fbx(src) => ModelSource::Fbx(src.to_string()),
+            SourceOrShape::Triangle => ModelSource::Triangle,
+            SourceOrShape::Rectangle => ModelSource::Rectangle,
+            SourceOrShape::Cube => ModelSource::Cube,
+            SourceOrShape::UvSphere { segments, rings } => ModelSource::UvSphere {
+                segments: *segments,
+                rings: *rings,
+            },
+            SourceOrShape::Cylinder { segments } => ModelSource::Cylinder {
+                segments: *segments,
+            },
+            SourceOrShape::Plane { subdivisions } => ModelSource::Plane {
+                subdivisions: *subdivisions,
+            },
+            SourceOrShape::Capsule {
+                segments,
+                rings,
+                height,
+            } => ModelSource::Capsule {
+                segments: *segments,
+                rings: *rings,
+                height: *height,
+            },
+            SourceOrShape::Custom(_) | SourceOrShape::Dummy(_) => return None,
+        })
+    }
 }
 
 /// A parsed model, ready to be imported into the engine.
@@ -47,6 +123,71 @@ pub struct ParsedModel {
     pub parts: Vec<ParsedModelPart>,
 }
 
+impl ParsedModel {
+    /// Check that the model can be uploaded: every part has vertices, its indices refer to existing vertices and form whole triangles, and its morph targets and textures have the right size. This is done by [GameState::new_model](../struct.GameState.html#method.new_model) as well, and by [ParsedModelBuilder::build].
+    pub fn validate(&self) -> Result<(), ParsedModelError> {
+        if self.parts.is_empty() {
+            // The vertices of the model are drawn as a single part
+            return ParsedModelPart::default().validate(0, self.vertices.as_deref());
+        }
+        for (index, part) in self.parts.iter().enumerate() {
+            part.validate(index, self.vertices.as_deref())?;
+        }
+        Ok(())
+    }
+
+    /// Calculate the bounding box, vertex count and triangle count of this model.
+    pub fn statistics(&self) -> MeshStatistics {
+        let mut vertex_count = self.vertices.as_ref().map(Vec::len).unwrap_or(0);
+        let mut triangle_count = 0;
+        let mut min = Vector3::new(std::f32::MAX, std::f32::MAX, std::f32::MAX);
+        let mut max = Vector3::new(std::f32::MIN, std::f32::MIN, std::f32::MIN);
+
+        let mut add_vertices = |vertices: &[Vertex]| {
+            for vertex in vertices {
+                let [x, y, z] = vertex.position;
+                min = Vector3::new(min.x.min(x), min.y.min(y), min.z.min(z));
+                max = Vector3::new(max.x.max(x), max.y.max(y), max.z.max(z));
+            }
+        };
+
+        if let Some(vertices) = &self.vertices {
+            add_vertices(vertices);
+        }
+        for part in &self.parts {
+            if let Some(vertices) = &part.vertices {
+                vertex_count += vertices.len();
+                add_vertices(vertices);
+            }
+            triangle_count += if part.index.is_empty() {
+                part.vertices
+                    .as_ref()
+                    .or_else(|| self.vertices.as_ref())
+                    .map(Vec::len)
+                    .unwrap_or(0)
+                    / 3
+            } else {
+                part.index.len() / 3
+            };
+        }
+        if self.parts.is_empty() {
+            triangle_count = vertex_count / 3;
+        }
+
+        let bounding_box = if vertex_count == 0 {
+            (Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0))
+        } else {
+            (min, max)
+        };
+
+        MeshStatistics {
+            bounding_box,
+            vertex_count,
+            triangle_count,
+        }
+    }
+}
+
 /// A part of the parsed model. Each part is a sub-model, e.g. the wheels of a car that can rotate independently, but still belong to the car model.
 #[derive(Default)]
 pub struct ParsedModelPart {
@@ -56,11 +197,113 @@ pub struct ParsedModelPart {
     pub index: Vec<u32>,
     /// The material of this part
     pub material: Option<Material>,
+    /// The PBR material of this part. When this is set, the part is rendered with this material instead of [material](#structfield.material).
+    pub pbr: Option<ParsedPbrMaterial>,
     /// The texture of this part
     pub texture: Option<ParsedTexture>,
+    /// The texture that is multiplied with the specular color of the [material](#structfield.material), in sRGB like [texture](#structfield.texture).
+    pub specular_map: Option<ParsedTexture>,
+    /// The normal map of this part, with the normals in tangent space. The red channel points in the direction in which the U texture coordinate increases, the green channel in the direction in which V increases. This is used with both the [material](#structfield.material) and the [PBR material](#structfield.pbr).
+    pub normal_map: Option<ParsedTexture>,
+    /// The morph targets of this part, also known as blend shapes. See [ModelHandle::set_morph_weight](../struct.ModelHandle.html#method.set_morph_weight).
+    pub morph_targets: Vec<ParsedMorphTarget>,
+}
+
+impl ParsedModelPart {
+    /// Validate this part, which is part `index` of a model with the given shared vertices. See [ParsedModel::validate].
+    fn validate(
+        &self,
+        index: usize,
+        shared_vertices: Option<&[Vertex]>,
+    ) -> Result<(), ParsedModelError> {
+        let vertex_count = self
+            .vertices
+            .as_deref()
+            .or(shared_vertices)
+            .ok_or(ParsedModelError::NoVertices { part: index })?
+            .len();
+        let count = if self.index.is_empty() {
+            vertex_count
+        } else {
+            self.index.len()
+        };
+        if count % 3 != 0 {
+            return Err(ParsedModelError::IncompleteTriangle { part: index, count });
+        }
+        if let Some(&invalid) = self.index.iter().find(|&&i| i as usize >= vertex_count) {
+            return Err(ParsedModelError::IndexOutOfRange {
+                part: index,
+                index: invalid,
+                vertex_count,
+            });
+        }
+        for target in &self.morph_targets {
+            let normals = target.normals.len();
+            if target.positions.len() != vertex_count || (normals != 0 && normals != vertex_count) {
+                return Err(ParsedModelError::InvalidMorphTarget {
+                    part: index,
+                    name: target.name.clone(),
+                    positions: target.positions.len(),
+                    normals,
+                    vertex_count,
+                });
+            }
+        }
+        let pbr_maps = self.pbr.iter().flat_map(|pbr| {
+            pbr.metallic_map
+                .iter()
+                .chain(&pbr.roughness_map)
+                .chain(&pbr.ambient_occlusion_map)
+        });
+        let textures = self
+            .texture
+            .iter()
+            .chain(&self.specular_map)
+            .chain(&self.normal_map)
+            .chain(pbr_maps);
+        for texture in textures {
+            let pixels = texture.width as usize * texture.height as usize;
+            if pixels == 0 || texture.rgba_data.len() != pixels * 4 {
+                return Err(ParsedModelError::InvalidTextureSize {
+                    part: index,
+                    width: texture.width,
+                    height: texture.height,
+                    len: texture.rgba_data.len(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A morph target of a parsed model part, which moves the vertices of the part when its weight is set with [ModelHandle::set_morph_weight](../struct.ModelHandle.html#method.set_morph_weight). The offsets are added to the vertices, multiplied with the weight.
+///
+/// There is an offset for every vertex of the part, or for every vertex of the model if the part uses the vertices of the model.
+#[derive(Clone, Default)]
+pub struct ParsedMorphTarget {
+    /// The name of the morph target, which is used to set its weight
+    pub name: String,
+    /// The offset of the position of every vertex
+    pub positions: Vec<[f32; 3]>,
+    /// The offset of the normal of every vertex
+    pub normals: Vec<[f32; 3]>,
+}
+
+/// The PBR material of a parsed model part, with its textures. The maps contain factors instead of colors, so they are not in sRGB. The red channel of every map is used.
+#[derive(Default)]
+pub struct ParsedPbrMaterial {
+    /// The factors of the material
+    pub material: PbrMaterial,
+    /// The texture that is multiplied with the metallic factor
+    pub metallic_map: Option<ParsedTexture>,
+    /// The texture that is multiplied with the roughness factor
+    pub roughness_map: Option<ParsedTexture>,
+    /// The texture that is multiplied with the ambient occlusion factor
+    pub ambient_occlusion_map: Option<ParsedTexture>,
 }
 
 /// The texture of a parsed model part
+#[derive(Clone)]
 pub struct ParsedTexture {
     /// The width of the parsed texture
     pub width: u32,
@@ -158,3 +401,13 @@ static TRIANGLE: &[Vertex] = &[
         tex_coord: [1.0, 1.0],
     },
 ];
+
+#[test]
+fn test_rectangle_statistics() {
+    let rectangle: ParsedModel = RECTANGLE.into();
+    let statistics = rectangle.statistics();
+    assert_eq!(4, statistics.vertex_count);
+    assert_eq!(2, statistics.triangle_count);
+    assert_eq!(Vector3::new(-0.5, -0.5, 0.0), statistics.bounding_box.0);
+    assert_eq!(Vector3::new(0.5, 0.5, 0.0), statistics.bounding_box.1);
+}
@@ -1,8 +1,13 @@
-use super::{ParsedModel, ParsedModelPart};
-use crate::model::{Material, Vertex};
+use super::{ParsedModel, ParsedModelPart, ParsedTexture};
+use crate::model::{LoadingStage, Material, Progress, Vertex};
+use cgmath::{InnerSpace, Vector3, Zero};
 use genmesh::EmitTriangles;
-use obj::ObjMaterial;
-use std::sync::Arc;
+use obj::{IndexTuple, ObjMaterial};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 /// Errors that can occur when loading an .obj file
 #[derive(Debug, thiserror::Error)]
@@ -13,11 +18,37 @@ pub enum Error {
     /// Could not load the .obj's materials
     #[error("Could not load materials: {0:?}")]
     CouldNotLoadMaterials(obj::MtlLibsLoadError),
+    /// Could not load the diffuse texture (`map_Kd`) of a material
+    #[error("Could not load texture {path:?}: {inner:?}")]
+    CouldNotLoadTexture {
+        /// The path of the texture
+        path: PathBuf,
+        /// The inner error that was thrown
+        inner: image::ImageError,
+    },
 }
 
-pub fn load(src: &str) -> Result<ParsedModel, Error> {
-    let mut obj = obj::Obj::load(std::path::Path::new(src)).map_err(Error::CouldNotLoadObj)?;
+pub fn load(src: &Path, progress: &mut Progress) -> Result<ParsedModel, Error> {
+    progress.start(LoadingStage::Parse, 1);
+    let mut obj = obj::Obj::load(src).map_err(Error::CouldNotLoadObj)?;
     obj.load_mtls().map_err(Error::CouldNotLoadMaterials)?;
+    let needs_normals = obj
+        .data
+        .objects
+        .iter()
+        .flat_map(|object| &object.groups)
+        .flat_map(|group| &group.polys)
+        .any(|poly| poly.0.iter().any(|corner| corner.2.is_none()));
+    // The smoothing groups are only needed to generate normals, so the file is not read twice when
+    // every face has normals
+    let smoothing_groups = if needs_normals {
+        std::fs::read_to_string(src)
+            .map(|text| smoothing_groups(&text))
+            .map_err(|e| Error::CouldNotLoadObj(obj::ObjError::Io(e)))?
+    } else {
+        Vec::new()
+    };
+    progress.finish();
     let obj::ObjData {
         position,
         texture,
@@ -26,30 +57,29 @@ pub fn load(src: &str) -> Result<ParsedModel, Error> {
         material_libs,
     } = obj.data;
 
-    let vertices: Vec<_> = position
-        .into_iter()
-        .enumerate()
-        .map(|(index, position)| Vertex {
-            position,
-            normal: normal.get(index).cloned().unwrap_or([0.0, 0.0, 0.0]),
-            tex_coord: texture.get(index).cloned().unwrap_or([-1.0, -1.0]),
-        })
-        .collect();
-
-    let mut result: ParsedModel = vertices.into();
-    result
-        .parts
-        .reserve(objects.iter().map(|o| o.groups.len()).sum());
+    let mut vertices = VertexBuilder::new(&position, &texture, &normal);
+    let mut parts = Vec::with_capacity(objects.iter().map(|o| o.groups.len()).sum());
+    // The texture of every part, which is decoded after every part is triangulated
+    let mut texture_paths = Vec::with_capacity(parts.capacity());
+    let mut face_index = 0;
 
+    progress.start(LoadingStage::Triangulate, parts.capacity());
     for object in objects {
         for group in object.groups {
             let mut index_group = Vec::new();
             for poly in group.polys {
+                let key = match smoothing_groups.get(face_index) {
+                    Some(&group) if group > 0 => NormalKey::Smooth(group),
+                    _ => NormalKey::Flat(face_index),
+                };
                 poly.into_genmesh().emit_triangles(|triangle| {
-                    index_group.push(triangle.x.0 as u32);
-                    index_group.push(triangle.y.0 as u32);
-                    index_group.push(triangle.z.0 as u32);
+                    vertices.add_triangle(
+                        [triangle.x, triangle.y, triangle.z],
+                        key,
+                        &mut index_group,
+                    );
                 });
+                face_index += 1;
             }
 
             let mut part: ParsedModelPart = index_group.into();
@@ -61,7 +91,7 @@ pub fn load(src: &str) -> Result<ParsedModel, Error> {
                     .find(|m| m.name == name)
                     .map(|m| Arc::clone(m)),
             });
-            if let Some(material) = material {
+            if let Some(material) = &material {
                 part.material = Some(Material {
                     ambient: material.ka.unwrap_or([1.0, 0.0, 0.0]),
                     diffuse: material.kd.unwrap_or([1.0, 0.0, 0.0]),
@@ -69,9 +99,353 @@ pub fn load(src: &str) -> Result<ParsedModel, Error> {
                     shininess: material.km.unwrap_or(0.0),
                 });
             }
-            result.parts.push(part);
+            texture_paths.push(
+                material
+                    .as_ref()
+                    .and_then(|m| m.map_kd.as_ref())
+                    .map(|map| texture_path(src, map)),
+            );
+            parts.push(part);
+            progress.step();
+        }
+    }
+    progress.finish();
+
+    // Parts that use the same texture share the decoded image
+    let mut textures: HashMap<&Path, ParsedTexture> = HashMap::new();
+    let texture_count = texture_paths.iter().flatten().collect::<HashSet<_>>().len();
+    progress.start(LoadingStage::DecodeTextures, texture_count);
+    for (part, path) in parts.iter_mut().zip(&texture_paths) {
+        let path = match path {
+            Some(path) => path.as_path(),
+            None => continue,
+        };
+        if !textures.contains_key(path) {
+            let image = image::open(path)
+                .map_err(|inner| Error::CouldNotLoadTexture {
+                    path: path.to_owned(),
+                    inner,
+                })?
+                .into_rgba();
+            let (width, height) = image.dimensions();
+            textures.insert(
+                path,
+                ParsedTexture {
+                    width,
+                    height,
+                    rgba_data: image.into_raw(),
+                },
+            );
+            progress.step();
         }
+        part.texture = textures.get(path).cloned();
     }
+    progress.finish();
 
+    let mut result: ParsedModel = vertices.finish().into();
+    result.parts = parts;
     Ok(result)
 }
+
+/// Get the path of the texture of a material map, relative to the directory of the OBJ file at `src`.
+///
+/// Maps can have options before the file name, e.g. `map_Kd -s 2 2 1 bricks.png`, so only the last word is used when the map starts with an option.
+fn texture_path(src: &Path, map: &str) -> PathBuf {
+    let file = if map.starts_with('-') {
+        map.split_whitespace().last().unwrap_or(map)
+    } else {
+        map.trim()
+    };
+    src.parent().unwrap_or_else(|| Path::new("")).join(file)
+}
+
+/// Get the smoothing group of every face in the file, in the order of the faces. Faces that are not smoothed are in group `0`.
+///
+/// The obj crate skips the `s` command, so this reads the file itself.
+fn smoothing_groups(text: &str) -> Vec<u32> {
+    let mut current = 0;
+    let mut result = Vec::new();
+    for line in text.lines() {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            // `s off` is the same as `s 0`
+            Some("s") => current = words.next().and_then(|w| w.parse().ok()).unwrap_or(0),
+            Some("f") => result.push(current),
+            _ => {}
+        }
+    }
+    result
+}
+
+/// The corners of faces without normals that share a generated normal.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum NormalKey {
+    /// The corners of the faces in this smoothing group share a vertex, so the faces look smooth
+    Smooth(u32),
+    /// The face with this index is not smoothed, so its corners get their own vertices
+    Flat(usize),
+}
+
+/// The normal of a corner of a face.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum CornerNormal {
+    /// The index of the normal in the file
+    Index(usize),
+    /// The corner has no normal in the file, so it gets the average normal of the faces around its position that share the same [NormalKey]
+    Generated(NormalKey),
+}
+
+/// Builds the vertices of the model. The corners of the faces in an OBJ file index the positions, texture coordinates and normals separately, so every distinct combination of the three becomes a vertex.
+struct VertexBuilder<'a> {
+    positions: &'a [[f32; 3]],
+    tex_coords: &'a [[f32; 2]],
+    normals: &'a [[f32; 3]],
+    vertices: Vec<Vertex>,
+    lookup: HashMap<(usize, Option<usize>, CornerNormal), u32>,
+    /// The sum of the normals of the faces around a position that share a [NormalKey]. This is keyed by the position instead of the vertex, so vertices that only differ in their texture coordinates get the same normal.
+    generated_normals: HashMap<(usize, NormalKey), Vector3<f32>>,
+    /// The vertices with a generated normal, with the key of their normal in `generated_normals`
+    generated: Vec<(usize, (usize, NormalKey))>,
+}
+
+impl<'a> VertexBuilder<'a> {
+    fn new(positions: &'a [[f32; 3]], tex_coords: &'a [[f32; 2]], normals: &'a [[f32; 3]]) -> Self {
+        Self {
+            positions,
+            tex_coords,
+            normals,
+            vertices: Vec::new(),
+            lookup: HashMap::new(),
+            generated_normals: HashMap::new(),
+            generated: Vec::new(),
+        }
+    }
+
+    /// Add a triangle, and push the indices of its vertices to `index`. Triangles with a position that does not exist are skipped. `key` is used for the corners that have no normal.
+    fn add_triangle(&mut self, corners: [IndexTuple; 3], key: NormalKey, index: &mut Vec<u32>) {
+        let positions = self.positions;
+        let position = |corner: IndexTuple| positions.get(corner.0).copied().map(Vector3::from);
+        let (a, b, c) = match (
+            position(corners[0]),
+            position(corners[1]),
+            position(corners[2]),
+        ) {
+            (Some(a), Some(b), Some(c)) => (a, b, c),
+            _ => return,
+        };
+        // This is not normalized, so larger faces have more influence on the smoothed normal
+        let face_normal = (b - a).cross(c - a);
+
+        for (&corner, &position) in corners.iter().zip(&[a, b, c]) {
+            let IndexTuple(position_index, tex_coord_index, normal_index) = corner;
+            let normal = match normal_index {
+                Some(normal_index) if normal_index < self.normals.len() => {
+                    CornerNormal::Index(normal_index)
+                }
+                _ => CornerNormal::Generated(key),
+            };
+            let Self {
+                tex_coords,
+                normals,
+                vertices,
+                lookup,
+                generated_normals,
+                generated,
+                ..
+            } = self;
+            let vertex_index = *lookup
+                .entry((position_index, tex_coord_index, normal))
+                .or_insert_with(|| {
+                    let vertex_index = vertices.len();
+                    let normal = match normal {
+                        CornerNormal::Index(normal_index) => normals[normal_index],
+                        CornerNormal::Generated(key) => {
+                            generated.push((vertex_index, (position_index, key)));
+                            [0.0, 0.0, 0.0]
+                        }
+                    };
+                    vertices.push(Vertex {
+                        position: position.into(),
+                        normal,
+                        // Negative texture coordinates mean that the model has no texture
+                        tex_coord: tex_coord_index
+                            .and_then(|index| tex_coords.get(index))
+                            .copied()
+                            .unwrap_or([-1.0, -1.0]),
+                    });
+                    vertex_index as u32
+                });
+            if let CornerNormal::Generated(key) = normal {
+                *generated_normals
+                    .entry((position_index, key))
+                    .or_insert_with(Vector3::zero) += face_normal;
+            }
+            index.push(vertex_index);
+        }
+    }
+
+    /// Get the vertices, with their generated normals.
+    fn finish(self) -> Vec<Vertex> {
+        let mut vertices = self.vertices;
+        for (index, key) in &self.generated {
+            let normal = self.generated_normals[key];
+            if normal.magnitude2() > 0.0 {
+                vertices[*index].normal = normal.normalize().into();
+            }
+        }
+        vertices
+    }
+}
+
+#[test]
+fn test_generate_normals() {
+    let text = "v 0 0 0\nf 1 2 3\ns 1\nf 1 2 3\nf 1 2 3\ns off\nf 1 2 3\n";
+    assert_eq!(vec![0, 1, 1, 0], smoothing_groups(text));
+
+    // Two triangles that meet at a right angle along the edge from (0, 0, 0) to (1, 0, 0)
+    let positions = [
+        [0.0, 0.0, 0.0],
+        [1.0, 0.0, 0.0],
+        [0.0, 0.0, -1.0],
+        [0.0, 1.0, 0.0],
+    ];
+    let corners = |indices: [usize; 3]| {
+        let corner = |index: usize| IndexTuple(index, None, None);
+        [corner(indices[0]), corner(indices[1]), corner(indices[2])]
+    };
+    let floor = corners([0, 1, 2]);
+    let wall = corners([0, 1, 3]);
+
+    let mut flat = VertexBuilder::new(&positions, &[], &[]);
+    let mut index = Vec::new();
+    flat.add_triangle(floor, NormalKey::Flat(0), &mut index);
+    flat.add_triangle(wall, NormalKey::Flat(1), &mut index);
+    let vertices = flat.finish();
+    assert_eq!(vec![0, 1, 2, 3, 4, 5], index);
+    assert_eq!([0.0, 1.0, 0.0], vertices[0].normal);
+    assert_eq!([0.0, 0.0, 1.0], vertices[3].normal);
+    assert_eq!([-1.0, -1.0], vertices[0].tex_coord);
+
+    let mut smooth = VertexBuilder::new(&positions, &[], &[]);
+    let mut index = Vec::new();
+    smooth.add_triangle(floor, NormalKey::Smooth(1), &mut index);
+    smooth.add_triangle(wall, NormalKey::Smooth(1), &mut index);
+    let vertices = smooth.finish();
+    // The corners on the shared edge are shared, and point halfway between the faces
+    assert_eq!(vec![0, 1, 2, 0, 1, 3], index);
+    let half = std::f32::consts::FRAC_1_SQRT_2;
+    for (&actual, expected) in vertices[0].normal.iter().zip(&[0.0, half, half]) {
+        assert!((actual - expected).abs() < 1e-5);
+    }
+    assert_eq!([0.0, 1.0, 0.0], vertices[2].normal);
+
+    // The same faces with a texture seam along the shared edge, so the corners on the edge are not
+    // shared. They still get the same smoothed normal, otherwise the seam would be visible.
+    let tex_coords = [[0.0, 0.0], [1.0, 0.0], [0.0, 1.0], [0.5, 0.5], [0.5, 1.0]];
+    let corner = |index: usize, tex_coord: usize| IndexTuple(index, Some(tex_coord), None);
+    let mut seam = VertexBuilder::new(&positions, &tex_coords, &[]);
+    let mut index = Vec::new();
+    let floor = [corner(0, 0), corner(1, 1), corner(2, 2)];
+    let wall = [corner(0, 3), corner(1, 4), corner(3, 2)];
+    seam.add_triangle(floor, NormalKey::Smooth(1), &mut index);
+    seam.add_triangle(wall, NormalKey::Smooth(1), &mut index);
+    let vertices = seam.finish();
+    assert_eq!(vec![0, 1, 2, 3, 4, 5], index);
+    for &vertex in &[0, 1, 3, 4] {
+        for (&actual, expected) in vertices[vertex].normal.iter().zip(&[0.0, half, half]) {
+            assert!((actual - expected).abs() < 1e-5);
+        }
+    }
+    assert_eq!([0.0, 1.0, 0.0], vertices[2].normal);
+    assert_eq!([0.0, 0.0, 1.0], vertices[5].normal);
+}
+
+#[test]
+fn test_load_obj_index_triplets() {
+    // Every face of this cube has its own normal and texture coordinates, so the 8 corners of the
+    // cube are used by 24 distinct combinations of position, texture coordinate and normal
+    let path = Path::new("examples/pong/assets/paddle.obj");
+    let model = load(path, &mut Progress::new(None)).unwrap();
+    let vertices = model.vertices.as_ref().unwrap();
+    assert_eq!(24, vertices.len());
+    assert_eq!(12, model.statistics().triangle_count);
+
+    let text = std::fs::read_to_string(path).unwrap();
+    let values = |command: &str| -> Vec<Vec<f32>> {
+        text.lines()
+            .filter(|line| line.split_whitespace().next() == Some(command))
+            .map(|line| {
+                line.split_whitespace()
+                    .skip(1)
+                    .map(|v| v.parse().unwrap())
+                    .collect()
+            })
+            .collect()
+    };
+    let (positions, tex_coords, normals) = (values("v"), values("vt"), values("vn"));
+    // The corners of every face, as zero-based position/uv/normal indices
+    let faces: Vec<Vec<Vec<usize>>> = text
+        .lines()
+        .filter(|line| line.starts_with("f "))
+        .map(|line| {
+            line.split_whitespace()
+                .skip(1)
+                .map(|corner| {
+                    corner
+                        .split('/')
+                        .map(|i| i.parse::<usize>().unwrap() - 1)
+                        .collect()
+                })
+                .collect()
+        })
+        .collect();
+    // The position and uv indices of the corners differ, so a vertex that indexes the texture
+    // coordinates with its position index is caught
+    assert!(faces.iter().flatten().any(|corner| corner[0] != corner[1]));
+
+    // The faces are triangulated in order, into `corners - 2` triangles each
+    let face_of_triangle = faces
+        .iter()
+        .flat_map(|face| (2..face.len()).map(move |_| face));
+    let triangles = model.parts.iter().flat_map(|p| p.index.chunks_exact(3));
+    for (face, triangle) in face_of_triangle.zip(triangles) {
+        let [a, b, c] = [
+            vertices[triangle[0] as usize],
+            vertices[triangle[1] as usize],
+            vertices[triangle[2] as usize],
+        ];
+        // Every corner has the exact texture coordinate and normal that the corner of the face
+        // with its position references
+        for vertex in &[a, b, c] {
+            let corner = face
+                .iter()
+                .find(|corner| positions[corner[0]] == vertex.position.to_vec())
+                .expect("The vertex is not a corner of its face");
+            assert_eq!(tex_coords[corner[1]], vertex.tex_coord.to_vec());
+            assert_eq!(normals[corner[2]], vertex.normal.to_vec());
+        }
+        // Every corner of a face has the normal of that face
+        let position = |v: Vertex| Vector3::from(v.position);
+        let face_normal = (position(b) - position(a)).cross(position(c) - position(a));
+        for normal in &[a.normal, b.normal, c.normal] {
+            assert!(face_normal.normalize().dot(Vector3::from(*normal)) > 0.99);
+        }
+    }
+}
+
+#[test]
+fn test_texture_path() {
+    let src = Path::new("assets/models/crate.obj");
+    assert_eq!(
+        Path::new("assets/models/textures/wood.png"),
+        texture_path(src, "textures/wood.png")
+    );
+    assert_eq!(
+        Path::new("assets/models/wood.png"),
+        texture_path(src, "-s 2 2 1 -clamp on wood.png")
+    );
+    assert_eq!(
+        Path::new("wood.png"),
+        texture_path(Path::new("crate.obj"), "wood.png")
+    );
+}
@@ -0,0 +1,261 @@
+//! Procedural shapes. Every shape is centered on the origin and fits in a cube of 1x1x1, and its triangles are wound counter-clockwise when seen from the outside.
+
+use super::ParsedModel;
+use crate::model::Vertex;
+use cgmath::{InnerSpace, Vector3};
+use std::f32::consts::PI;
+
+/// Rows with a smaller radius than this are considered to be a single point. `sin(PI)` is not exactly `0.0`, so this can not be compared to zero.
+const POLE_RADIUS: f32 = 1e-6;
+
+/// A cube of 1x1x1. Every face has the full texture.
+pub fn cube() -> ParsedModel {
+    // The normal of every face, with two axes along the face so that `u.cross(v) == normal`
+    let faces = [
+        (Vector3::unit_x(), -Vector3::unit_z(), Vector3::unit_y()),
+        (-Vector3::unit_x(), Vector3::unit_z(), Vector3::unit_y()),
+        (Vector3::unit_y(), Vector3::unit_x(), -Vector3::unit_z()),
+        (-Vector3::unit_y(), Vector3::unit_x(), Vector3::unit_z()),
+        (Vector3::unit_z(), Vector3::unit_x(), Vector3::unit_y()),
+        (-Vector3::unit_z(), -Vector3::unit_x(), Vector3::unit_y()),
+    ];
+    let mut vertices = Vec::with_capacity(24);
+    let mut index = Vec::with_capacity(36);
+    for &(normal, u, v) in faces.iter() {
+        let start = vertices.len() as u32;
+        for &(x, y) in &[(0.0, 1.0), (1.0, 1.0), (1.0, 0.0), (0.0, 0.0)] {
+            let position = normal * 0.5 + u * (x - 0.5) + v * (0.5 - y);
+            vertices.push(Vertex {
+                position: position.into(),
+                normal: normal.into(),
+                tex_coord: [x, y],
+            });
+        }
+        index.extend(quad(start, start + 1, start + 2, start + 3));
+    }
+    indexed(vertices, index)
+}
+
+/// A flat square on the XZ plane, facing up. The square is divided in `subdivisions` by `subdivisions` quads.
+pub fn plane(subdivisions: u32) -> ParsedModel {
+    let count = subdivisions.max(1);
+    let mut vertices = Vec::new();
+    let mut index = Vec::new();
+    for row in 0..=count {
+        for column in 0..=count {
+            let x = column as f32 / count as f32;
+            let z = row as f32 / count as f32;
+            vertices.push(Vertex {
+                position: [x - 0.5, 0.0, z - 0.5],
+                normal: [0.0, 1.0, 0.0],
+                tex_coord: [x, z],
+            });
+        }
+    }
+    for row in 0..count {
+        for column in 0..count {
+            let top_left = row * (count + 1) + column;
+            let bottom_left = top_left + count + 1;
+            index.extend(quad(bottom_left, bottom_left + 1, top_left + 1, top_left));
+        }
+    }
+    indexed(vertices, index)
+}
+
+/// A sphere with a diameter of 1, made of `segments` slices around the Y axis and `rings` stacks from the top to the bottom.
+pub fn uv_sphere(segments: u32, rings: u32) -> ParsedModel {
+    let rings = rings.max(2);
+    let rows = (0..=rings)
+        .map(|ring| {
+            let angle = PI * ring as f32 / rings as f32;
+            Row {
+                y: angle.cos() * 0.5,
+                radius: angle.sin() * 0.5,
+                normal_angle: angle,
+                v: ring as f32 / rings as f32,
+            }
+        })
+        .collect::<Vec<_>>();
+    let (vertices, index) = revolve(&rows, segments);
+    indexed(vertices, index)
+}
+
+/// A cylinder along the Y axis with a diameter and height of 1, made of `segments` slices around the Y axis.
+pub fn cylinder(segments: u32) -> ParsedModel {
+    let segments = segments.max(3);
+    let rows = [
+        Row {
+            y: 0.5,
+            radius: 0.5,
+            normal_angle: PI / 2.0,
+            v: 0.0,
+        },
+        Row {
+            y: -0.5,
+            radius: 0.5,
+            normal_angle: PI / 2.0,
+            v: 1.0,
+        },
+    ];
+    let (mut vertices, mut index) = revolve(&rows, segments);
+
+    // The caps of the cylinder
+    for &(y, normal) in &[(0.5, 1.0), (-0.5, -1.0)] {
+        let center = vertices.len() as u32;
+        vertices.push(Vertex {
+            position: [0.0, y, 0.0],
+            normal: [0.0, normal, 0.0],
+            tex_coord: [0.5, 0.5],
+        });
+        for segment in 0..=segments {
+            let angle = 2.0 * PI * segment as f32 / segments as f32;
+            vertices.push(Vertex {
+                position: [angle.cos() * 0.5, y, angle.sin() * 0.5],
+                normal: [0.0, normal, 0.0],
+                tex_coord: [0.5 + angle.cos() * 0.5, 0.5 + angle.sin() * 0.5],
+            });
+        }
+        for segment in 0..segments {
+            let current = center + 1 + segment;
+            if normal > 0.0 {
+                index.extend_from_slice(&[center, current + 1, current]);
+            } else {
+                index.extend_from_slice(&[center, current, current + 1]);
+            }
+        }
+    }
+    indexed(vertices, index)
+}
+
+/// A capsule along the Y axis with a diameter of 1. The cylinder in the middle is `height` high, so the total height of the capsule is `height + 1`.
+///
+/// The capsule is made of `segments` slices around the Y axis, and each half sphere has `rings` stacks.
+pub fn capsule(segments: u32, rings: u32, height: f32) -> ParsedModel {
+    let rings = rings.max(1);
+    let total_height = height + 1.0;
+    let half_sphere = |offset: f32, angles: Vec<f32>| {
+        angles.into_iter().map(move |angle| {
+            let y = angle.cos() * 0.5 + offset;
+            Row {
+                y,
+                radius: angle.sin() * 0.5,
+                normal_angle: angle,
+                v: (total_height / 2.0 - y) / total_height,
+            }
+        })
+    };
+    let top = (0..=rings)
+        .map(|ring| PI / 2.0 * ring as f32 / rings as f32)
+        .collect();
+    let bottom = (0..=rings)
+        .map(|ring| PI / 2.0 + PI / 2.0 * ring as f32 / rings as f32)
+        .collect();
+    let rows = half_sphere(height / 2.0, top)
+        .chain(half_sphere(-height / 2.0, bottom))
+        .collect::<Vec<_>>();
+    let (vertices, index) = revolve(&rows, segments);
+    indexed(vertices, index)
+}
+
+/// A ring of vertices of a shape that is rotated around the Y axis.
+struct Row {
+    y: f32,
+    radius: f32,
+    /// The angle between the normal of the vertices and the Y axis
+    normal_angle: f32,
+    /// The texture coordinate of the vertices along the Y axis
+    v: f32,
+}
+
+/// Rotate the rows around the Y axis, and connect every row to the next row.
+fn revolve(rows: &[Row], segments: u32) -> (Vec<Vertex>, Vec<u32>) {
+    let segments = segments.max(3);
+    let mut vertices = Vec::new();
+    for row in rows {
+        // The first and last vertex of a row are at the same position, but have different
+        // texture coordinates
+        for segment in 0..=segments {
+            let angle = 2.0 * PI * segment as f32 / segments as f32;
+            let (sin, cos) = angle.sin_cos();
+            vertices.push(Vertex {
+                position: [cos * row.radius, row.y, sin * row.radius],
+                normal: [
+                    row.normal_angle.sin() * cos,
+                    row.normal_angle.cos(),
+                    row.normal_angle.sin() * sin,
+                ],
+                tex_coord: [segment as f32 / segments as f32, row.v],
+            });
+        }
+    }
+
+    let mut index = Vec::new();
+    let row_length = segments + 1;
+    for (row_index, rows) in rows.windows(2).enumerate() {
+        let top = row_index as u32 * row_length;
+        let bottom = top + row_length;
+        for segment in 0..segments {
+            let (a, b) = (top + segment, top + segment + 1);
+            let (c, d) = (bottom + segment + 1, bottom + segment);
+            // Skip the triangles that collapse into a line at the poles
+            if rows[0].radius > POLE_RADIUS {
+                index.extend_from_slice(&[a, b, c]);
+            }
+            if rows[1].radius > POLE_RADIUS {
+                index.extend_from_slice(&[a, c, d]);
+            }
+        }
+    }
+    (vertices, index)
+}
+
+/// The indices of the two triangles of a quad, with the corners given counter-clockwise.
+fn quad(a: u32, b: u32, c: u32, d: u32) -> Vec<u32> {
+    vec![a, b, c, a, c, d]
+}
+
+fn indexed(vertices: Vec<Vertex>, index: Vec<u32>) -> ParsedModel {
+    ParsedModel {
+        vertices: Some(vertices),
+        parts: vec![index.into()],
+    }
+}
+
+#[test]
+fn test_shapes_face_outwards() {
+    let shapes = vec![
+        ("cube", cube()),
+        ("plane", plane(4)),
+        ("uv_sphere", uv_sphere(16, 8)),
+        ("cylinder", cylinder(16)),
+        ("capsule", capsule(16, 4, 1.0)),
+    ];
+    for (name, shape) in shapes {
+        let vertices = shape.vertices.as_ref().unwrap();
+        let index = &shape.parts[0].index;
+        assert!(!index.is_empty(), "{} has no triangles", name);
+        for triangle in index.chunks_exact(3) {
+            let [a, b, c] = [
+                vertices[triangle[0] as usize],
+                vertices[triangle[1] as usize],
+                vertices[triangle[2] as usize],
+            ];
+            let position = |v: Vertex| Vector3::from(v.position);
+            let face_normal = (position(b) - position(a)).cross(position(c) - position(a));
+            let vertex_normal =
+                Vector3::from(a.normal) + Vector3::from(b.normal) + Vector3::from(c.normal);
+            assert!(
+                face_normal.dot(vertex_normal) > 0.0,
+                "{} has a triangle that faces inwards",
+                name
+            );
+        }
+        let (min, max) = shape.statistics().bounding_box;
+        assert!(
+            min.x >= -0.5 - 1e-5 && max.x <= 0.5 + 1e-5,
+            "{} is too wide",
+            name
+        );
+    }
+    assert_eq!(12, cube().statistics().triangle_count);
+}
@@ -0,0 +1,176 @@
+use super::{ParsedModel, ParsedModelPart, ParsedPbrMaterial};
+use crate::model::Vertex;
+use cgmath::{InnerSpace, Vector3, Zero};
+use std::collections::HashMap;
+
+impl ParsedModel {
+    /// Create a simplified version of this model, e.g. to use as a lower level of detail.
+    ///
+    /// The model is divided in a grid of cubes, with `grid_size` cubes along the longest side of its bounding box. All vertices within the same cube are merged into a single vertex, and triangles that collapse are removed. A lower `grid_size` results in a simpler model.
+    ///
+    /// The textures and PBR maps of the parts are not copied. [ModelBuilder::with_lod](../struct.ModelBuilder.html#method.with_lod) will use the textures of the original model for parts without a texture.
+    pub fn simplified(&self, grid_size: u32) -> ParsedModel {
+        let (min, max) = self.statistics().bounding_box;
+        let extent = max - min;
+        let longest = extent.x.max(extent.y).max(extent.z);
+        let cell_size = if longest > 0.0 {
+            longest / grid_size.max(1) as f32
+        } else {
+            1.0
+        };
+
+        let whole_model = ParsedModelPart::default();
+        let parts: Vec<&ParsedModelPart> = if self.parts.is_empty() {
+            vec![&whole_model]
+        } else {
+            self.parts.iter().collect()
+        };
+
+        let parts = parts
+            .into_iter()
+            .map(|part| {
+                let vertices = match part.vertices.as_ref().or_else(|| self.vertices.as_ref()) {
+                    Some(vertices) => vertices,
+                    None => return ParsedModelPart::default(),
+                };
+                let (vertices, index) = if part.index.is_empty() {
+                    let index = (0..vertices.len() as u32).collect::<Vec<_>>();
+                    simplify(vertices, &index, min, cell_size)
+                } else {
+                    simplify(vertices, &part.index, min, cell_size)
+                };
+                ParsedModelPart {
+                    vertices: Some(vertices),
+                    index,
+                    material: part.material,
+                    // Textures are shared with the original model, see `ModelBuilder::with_lod`
+                    pbr: part.pbr.as_ref().map(|pbr| ParsedPbrMaterial {
+                        material: pbr.material,
+                        ..ParsedPbrMaterial::default()
+                    }),
+                    texture: None,
+                    specular_map: None,
+                    normal_map: None,
+                    // The simplified mesh has other vertices, so the morph targets do not fit
+                    morph_targets: Vec::new(),
+                }
+            })
+            .collect();
+
+        ParsedModel {
+            vertices: None,
+            parts,
+        }
+    }
+}
+
+/// Vertices that are merged into a single vertex
+struct Cluster {
+    position: Vector3<f32>,
+    normal: Vector3<f32>,
+    tex_coord: [f32; 2],
+    count: u32,
+}
+
+fn simplify(
+    vertices: &[Vertex],
+    index: &[u32],
+    origin: Vector3<f32>,
+    cell_size: f32,
+) -> (Vec<Vertex>, Vec<u32>) {
+    let mut cells = HashMap::<(i32, i32, i32), u32>::new();
+    let mut clusters = Vec::<Cluster>::new();
+    let remap = vertices
+        .iter()
+        .map(|vertex| {
+            let position = Vector3::from(vertex.position);
+            let cell = (position - origin) / cell_size;
+            let cell = (
+                cell.x.floor() as i32,
+                cell.y.floor() as i32,
+                cell.z.floor() as i32,
+            );
+            let cluster_index = *cells.entry(cell).or_insert_with(|| {
+                clusters.push(Cluster {
+                    position: Vector3::zero(),
+                    normal: Vector3::zero(),
+                    // Texture coordinates can not be averaged, because they wrap around at seams
+                    tex_coord: vertex.tex_coord,
+                    count: 0,
+                });
+                clusters.len() as u32 - 1
+            });
+            let cluster = &mut clusters[cluster_index as usize];
+            cluster.position += position;
+            cluster.normal += Vector3::from(vertex.normal);
+            cluster.count += 1;
+            cluster_index
+        })
+        .collect::<Vec<_>>();
+
+    let vertices = clusters
+        .iter()
+        .map(|cluster| {
+            let normal = if cluster.normal.magnitude2() > 0.0 {
+                cluster.normal.normalize()
+            } else {
+                cluster.normal
+            };
+            Vertex {
+                position: (cluster.position / cluster.count as f32).into(),
+                normal: normal.into(),
+                tex_coord: cluster.tex_coord,
+            }
+        })
+        .collect();
+
+    let index = index
+        .chunks_exact(3)
+        .map(|triangle| {
+            [
+                remap[triangle[0] as usize],
+                remap[triangle[1] as usize],
+                remap[triangle[2] as usize],
+            ]
+        })
+        // Triangles with two vertices in the same cell have collapsed into a line or a point
+        .filter(|[a, b, c]| a != b && b != c && a != c)
+        .flat_map(|triangle| triangle.to_vec())
+        .collect();
+
+    (vertices, index)
+}
+
+#[test]
+fn test_simplified() {
+    // A flat grid of 10x10 quads
+    let mut vertices = Vec::new();
+    let mut index = Vec::new();
+    for y in 0..=10 {
+        for x in 0..=10 {
+            vertices.push(Vertex {
+                position: [x as f32, y as f32, 0.0],
+                normal: [0.0, 0.0, 1.0],
+                tex_coord: [x as f32 / 10.0, y as f32 / 10.0],
+            });
+        }
+    }
+    for y in 0..10 {
+        for x in 0..10 {
+            let i = y * 11 + x;
+            index.extend_from_slice(&[i, i + 1, i + 12, i, i + 12, i + 11]);
+        }
+    }
+    let model = ParsedModel::from((&vertices[..], &index[..]));
+    assert_eq!(200, model.statistics().triangle_count);
+
+    let simplified = model.simplified(2);
+    let statistics = simplified.statistics();
+    assert!(statistics.triangle_count > 0);
+    assert!(statistics.triangle_count < 200);
+    assert!(statistics.vertex_count < vertices.len());
+    assert_eq!(
+        [0.0, 0.0, 1.0],
+        simplified.parts[0].vertices.as_ref().unwrap()[0].normal
+    );
+}
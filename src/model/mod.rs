@@ -1,15 +1,25 @@
+pub(crate) mod batch;
 mod builder;
 mod data;
+mod dynamic;
 mod handle;
 pub mod loader;
+mod morph;
 mod pipeline;
+mod progress;
+mod sprite;
+mod upload;
 
 pub use self::{
-    builder::ModelBuilder,
-    data::{ModelData, ModelDataGroup},
-    handle::{ModelHandle, ModelRef},
+    builder::{ModelBuilder, UploadSource},
+    data::{CullMode, DepthBias, ModelData, ModelDataGroup, TextureRegion, Transform},
+    dynamic::DynamicMesh,
+    handle::{ModelHandle, ModelId, ModelRef, ModelView},
     loader::SourceOrShape,
-    pipeline::{vs, Pipeline},
+    morph::{MorphOffset, MorphTargets, MAX_ACTIVE_MORPH_TARGETS},
+    pipeline::{fs, Pipeline, RenderView},
+    progress::{LoadingStage, Progress},
+    sprite::SpriteAnimation,
 };
 
 #[cfg(feature = "format-fbx")]
@@ -18,87 +28,295 @@ pub use self::loader::fbx::Error as FbxError;
 #[cfg(feature = "format-obj")]
 pub use self::loader::obj::Error as ObjError;
 
-use loader::{ParsedModelPart, ParsedTexture};
+use crate::{
+    render::texture::{self, Texture},
+    state::ModelError,
+};
+use cgmath::{Matrix4, Transform, Vector3};
+use loader::{ParsedModelPart, ParsedPbrMaterial, ParsedTexture};
 use parking_lot::RwLock;
-use std::sync::Arc;
+use std::{collections::HashSet, mem, sync::Arc};
 use vulkano::{
-    buffer::{BufferUsage, CpuAccessibleBuffer},
-    device::{Device, Queue},
-    format::R8G8B8A8Srgb,
-    image::{Dimensions, ImmutableImage},
+    buffer::{BufferAccess, BufferUsage, DeviceLocalBuffer, TypedBufferAccess},
+    device::Queue,
+    format::Format,
     sync::GpuFuture,
 };
 
 // TODO: Make it so that developers can create their own models/vertices?
 pub struct Model {
-    pub vertex_buffer: Option<Arc<CpuAccessibleBuffer<[Vertex]>>>,
+    pub vertex_buffer: Option<Arc<DeviceLocalBuffer<[Vertex]>>>,
     pub groups: Vec<ModelGroup>,
+    /// The uploads of the textures and buffers of this model, which are waited on before the model is drawn for the first time.
     pub texture_future: RwLock<Vec<Box<dyn GpuFuture>>>,
+    /// The queue that the buffers were uploaded on. The buffers are in device-local memory, so they are copied back on this queue when the mesh is changed.
+    pub queue: Arc<Queue>,
+    /// The lower levels of detail of this model, ordered by the distance from which they are used.
+    pub lods: Vec<ModelLod>,
+    /// The mesh that replaces the buffers above, once it is changed with `ModelHandle::update_vertices` or `ModelHandle::update_indices`.
+    pub dynamic: RwLock<Option<DynamicMesh>>,
+    /// The names of the morph targets of the groups, the weights in `ModelData::morph_weights` are in this order.
+    pub morph_targets: Arc<[String]>,
+    /// The parsed model that this model was uploaded from, which is used to upload the model again when the device is lost. This is `None` for models that are merged at runtime.
+    pub source: Option<Arc<UploadSource>>,
+}
+
+/// A level of detail of a [Model], which is rendered instead of the model when the camera is further away than `distance`.
+pub struct ModelLod {
+    pub distance: f32,
+    pub vertex_buffer: Option<Arc<DeviceLocalBuffer<[Vertex]>>>,
+    pub groups: Vec<ModelGroup>,
+}
+
+impl Model {
+    /// Get the vertex buffer and the groups that should be rendered when the camera is at the given distance.
+    pub fn mesh_at(
+        &self,
+        distance: f32,
+    ) -> (Option<&Arc<DeviceLocalBuffer<[Vertex]>>>, &[ModelGroup]) {
+        match self.lods.iter().rev().find(|lod| distance >= lod.distance) {
+            Some(lod) => (lod.vertex_buffer.as_ref(), &lod.groups),
+            None => (self.vertex_buffer.as_ref(), &self.groups),
+        }
+    }
+
+    /// Get the groups that should be rendered when the camera is at the given distance, with the buffers that every group is drawn with. A mesh that was changed at runtime is drawn at every distance.
+    pub fn draw_groups(&self, distance: f32) -> Vec<DrawGroup> {
+        if let Some(mesh) = self.dynamic.read().as_ref() {
+            return self
+                .groups
+                .iter()
+                .enumerate()
+                .map(|(index, group)| {
+                    let (vertex_buffer, index) = mesh.buffers(index);
+                    DrawGroup {
+                        group,
+                        vertex_buffer: vertex_buffer.map(|b| b.clone() as _),
+                        index: index.map(|b| b.clone() as _),
+                    }
+                })
+                .collect();
+        }
+        let (vertex_buffer, groups) = self.mesh_at(distance);
+        groups
+            .iter()
+            .map(|group| DrawGroup {
+                group,
+                vertex_buffer: group
+                    .vertex_buffer
+                    .as_ref()
+                    .or(vertex_buffer)
+                    .map(|b| b.clone() as _),
+                index: group.index.as_ref().map(|b| b.clone() as _),
+            })
+            .collect()
+    }
+}
+
+/// A group of a [Model] with the buffers that it is drawn with, see [Model::draw_groups]. Static meshes are drawn from device-local buffers and dynamic meshes from host-visible buffers, so the buffers are type-erased.
+pub struct DrawGroup<'a> {
+    pub group: &'a ModelGroup,
+    /// When this is `None`, the group should not be drawn
+    pub vertex_buffer: Option<Arc<dyn BufferAccess + Send + Sync>>,
+    pub index: Option<Arc<dyn TypedBufferAccess<Content = [u32]> + Send + Sync>>,
+}
+
+impl DrawGroup<'_> {
+    /// The amount of triangles that are drawn for this group.
+    pub fn triangle_count(&self) -> u32 {
+        match (&self.index, &self.vertex_buffer) {
+            (Some(index), _) => index.len() as u32 / 3,
+            (None, Some(vertices)) => (vertices.size() / mem::size_of::<Vertex>()) as u32 / 3,
+            (None, None) => 0,
+        }
+    }
 }
 
 pub struct ModelGroup {
-    pub vertex_buffer: Option<Arc<CpuAccessibleBuffer<[Vertex]>>>,
+    pub vertex_buffer: Option<Arc<DeviceLocalBuffer<[Vertex]>>>,
     pub material: Option<Material>,
-    pub texture: Option<Arc<ImmutableImage<R8G8B8A8Srgb>>>,
-    pub index: Option<Arc<CpuAccessibleBuffer<[u32]>>>,
+    /// When this is set, the group is rendered with the PBR material instead of `material`
+    pub pbr_material: Option<PbrMaterial>,
+    pub pbr_maps: PbrMaps,
+    pub texture: Option<Texture>,
+    /// Multiplied with the specular color of `material`
+    pub specular_map: Option<Texture>,
+    /// A normal map in tangent space, see [ParsedModelPart::normal_map]
+    pub normal_map: Option<Texture>,
+    /// When this is set, the group is rendered with these layers instead of `texture`
+    pub texture_layers: Option<TextureLayers>,
+    pub index: Option<Arc<DeviceLocalBuffer<[u32]>>>,
+    /// The morph targets of the vertices that this group is drawn with
+    pub morph: Option<MorphTargets>,
+}
+
+/// The maximum amount of texture layers of a model, see [ModelBuilder::with_texture_layers](../struct.ModelBuilder.html#method.with_texture_layers). Every channel of the splat map is the weight of a single layer.
+pub const MAX_TEXTURE_LAYERS: usize = 4;
+
+/// Textures that are blended together with the weights of a splat map, see [ModelBuilder::with_texture_layers](../struct.ModelBuilder.html#method.with_texture_layers).
+#[derive(Clone)]
+pub struct TextureLayers {
+    /// Between 1 and [MAX_TEXTURE_LAYERS] textures. Layer `i` is weighted by channel `i` of the splat map.
+    pub layers: Vec<Texture>,
+    pub splat_map: Texture,
+    /// How often the layers repeat over the texture coordinates of the model. The splat map covers the model once.
+    pub scale: f32,
+}
+
+impl TextureLayers {
+    /// Check if both layers use the same textures. Textures are compared by their pointer.
+    pub fn same_as(&self, other: &TextureLayers) -> bool {
+        Arc::ptr_eq(&self.splat_map, &other.splat_map)
+            && self.layers.len() == other.layers.len()
+            && self
+                .layers
+                .iter()
+                .zip(&other.layers)
+                .all(|(a, b)| Arc::ptr_eq(a, b))
+            && self.scale == other.scale
+    }
+}
+
+/// The textures of a [PbrMaterial]. The red channel of each texture is multiplied with the matching factor of the material.
+#[derive(Clone, Default)]
+pub struct PbrMaps {
+    pub metallic: Option<Texture>,
+    pub roughness: Option<Texture>,
+    pub ambient_occlusion: Option<Texture>,
+}
+
+impl PbrMaps {
+    /// Use the maps of `other` for every map that is not set.
+    pub fn fill_from(&mut self, other: &PbrMaps) {
+        if self.metallic.is_none() {
+            self.metallic = other.metallic.clone();
+        }
+        if self.roughness.is_none() {
+            self.roughness = other.roughness.clone();
+        }
+        if self.ambient_occlusion.is_none() {
+            self.ambient_occlusion = other.ambient_occlusion.clone();
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Texture> {
+        self.metallic
+            .iter()
+            .chain(self.roughness.iter())
+            .chain(self.ambient_occlusion.iter())
+    }
 }
 
 impl ModelGroup {
-    pub fn from_tex(texture: Option<Arc<ImmutableImage<R8G8B8A8Srgb>>>) -> Self {
+    pub fn from_tex(texture: Option<Texture>) -> Self {
         Self {
             vertex_buffer: None,
             material: None,
+            pbr_material: None,
+            pbr_maps: PbrMaps::default(),
             texture,
+            specular_map: None,
+            normal_map: None,
+            texture_layers: None,
             index: None,
+            morph: None,
         }
     }
 
     pub fn from_part(
-        device: Arc<Device>,
         queue: Arc<Queue>,
-        texture: &Option<Arc<ImmutableImage<R8G8B8A8Srgb>>>,
-        part: ParsedModelPart,
-    ) -> (Self, Option<Box<dyn GpuFuture>>) {
-        let index = CpuAccessibleBuffer::from_iter(
-            device.clone(),
-            BufferUsage::all(),
-            false,
-            part.index.iter().copied(),
-        )
-        .ok();
-
-        let vertex_buffer = part.vertices.map(|v| {
-            CpuAccessibleBuffer::from_iter(device, BufferUsage::all(), false, v.iter().copied())
-                .unwrap() // We assume that device and v are valid, so this should never fail
+        texture: &Option<Texture>,
+        part: &ParsedModelPart,
+    ) -> Result<(Self, Vec<Box<dyn GpuFuture>>), ModelError> {
+        let mut futures = Vec::new();
+        // A part without indices is drawn without an index buffer
+        let index = upload::upload_buffer(&queue, BufferUsage::index_buffer(), &part.index)
+            .map_err(ModelError::CouldNotUpload)?
+            .map(|(index, future)| {
+                futures.push(future);
+                index
+            });
+        // A part without vertices uses the vertices of the model
+        let vertex_buffer = match &part.vertices {
+            Some(v) => upload::upload_buffer(&queue, BufferUsage::vertex_buffer(), v)
+                .map_err(ModelError::CouldNotUpload)?,
+            None => None,
+        }
+        .map(|(vertex_buffer, future)| {
+            futures.push(future);
+            vertex_buffer
         });
 
-        let (texture, future) = if let Some(texture_to_load) = part.texture {
-            let ParsedTexture {
-                width,
-                height,
-                rgba_data,
-            } = texture_to_load;
-            let (tex, fut) = ImmutableImage::from_iter(
-                rgba_data.into_iter(),
-                Dimensions::Dim2d { width, height },
-                R8G8B8A8Srgb,
-                queue,
+        let mut upload = |parsed: &ParsedTexture, format: Format| -> Result<Texture, ModelError> {
+            let (tex, fut) = texture::from_rgba(
+                queue.clone(),
+                parsed.width,
+                parsed.height,
+                parsed.rgba_data.clone(),
+                format,
             )
-            .unwrap(); // We assume that queue, rgba_data and width/height are valid, so this should never fail
-            (Some(tex), Some(Box::new(fut) as Box<dyn GpuFuture>))
-        } else {
-            (texture.clone(), None)
+            .map_err(ModelError::CouldNotUpload)?;
+            futures.push(fut);
+            Ok(tex)
         };
 
-        (
+        let texture = match &part.texture {
+            Some(parsed) => Some(upload(parsed, Format::R8G8B8A8Srgb)?),
+            None => texture.clone(),
+        };
+        let specular_map = part
+            .specular_map
+            .as_ref()
+            .map(|m| upload(m, Format::R8G8B8A8Srgb))
+            .transpose()?;
+        // The normal map contains directions instead of colors, so it is not in sRGB either
+        let normal_map = part
+            .normal_map
+            .as_ref()
+            .map(|m| upload(m, Format::R8G8B8A8Unorm))
+            .transpose()?;
+        // The PBR maps contain factors instead of colors, so they are not in sRGB
+        let (pbr_material, pbr_maps) = match &part.pbr {
+            Some(ParsedPbrMaterial {
+                material,
+                metallic_map,
+                roughness_map,
+                ambient_occlusion_map,
+            }) => (
+                Some(*material),
+                PbrMaps {
+                    metallic: metallic_map
+                        .as_ref()
+                        .map(|m| upload(m, Format::R8G8B8A8Unorm))
+                        .transpose()?,
+                    roughness: roughness_map
+                        .as_ref()
+                        .map(|m| upload(m, Format::R8G8B8A8Unorm))
+                        .transpose()?,
+                    ambient_occlusion: ambient_occlusion_map
+                        .as_ref()
+                        .map(|m| upload(m, Format::R8G8B8A8Unorm))
+                        .transpose()?,
+                },
+            ),
+            None => (None, PbrMaps::default()),
+        };
+
+        Ok((
             Self {
                 vertex_buffer,
-                material: None,
+                material: part.material,
+                pbr_material,
+                pbr_maps,
                 texture,
+                specular_map,
+                normal_map,
+                texture_layers: None,
                 index,
+                morph: None,
             },
-            future,
-        )
+            futures,
+        ))
     }
 }
 
@@ -116,7 +334,128 @@ pub struct Vertex {
 }
 vulkano::impl_vertex!(Vertex, position, normal, tex_coord);
 
-#[derive(Copy, Clone, Debug)]
+/// Statistics of the geometry of a model. These are calculated when the model is loaded.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MeshStatistics {
+    /// The axis-aligned bounding box of the model, as `(min, max)`. This is in the local space of the model, so the position, rotation and scale of the model are not applied.
+    pub bounding_box: (Vector3<f32>, Vector3<f32>),
+    /// The amount of vertices in the model.
+    pub vertex_count: usize,
+    /// The amount of triangles in the model.
+    pub triangle_count: usize,
+}
+
+impl MeshStatistics {
+    /// Get the bounding box with the given model matrix applied. The rotated bounding box is wrapped in a new axis-aligned box.
+    pub(crate) fn world_bounding_box(&self, matrix: Matrix4<f32>) -> (Vector3<f32>, Vector3<f32>) {
+        transform_box(self.bounding_box, matrix)
+    }
+}
+
+/// Transform the corners of a box with a matrix, and wrap them in a new axis-aligned box.
+pub(crate) fn transform_box(
+    (min, max): (Vector3<f32>, Vector3<f32>),
+    matrix: Matrix4<f32>,
+) -> (Vector3<f32>, Vector3<f32>) {
+    let corners = (0..8).map(|i| {
+        let corner = Vector3::new(
+            if i & 1 == 0 { min.x } else { max.x },
+            if i & 2 == 0 { min.y } else { max.y },
+            if i & 4 == 0 { min.z } else { max.z },
+        );
+        matrix.transform_vector(corner) + matrix.w.truncate()
+    });
+    corners.fold(
+        (
+            Vector3::new(std::f32::MAX, std::f32::MAX, std::f32::MAX),
+            Vector3::new(std::f32::MIN, std::f32::MIN, std::f32::MIN),
+        ),
+        |(min, max), c| {
+            (
+                Vector3::new(min.x.min(c.x), min.y.min(c.y), min.z.min(c.z)),
+                Vector3::new(max.x.max(c.x), max.y.max(c.y), max.z.max(c.z)),
+            )
+        },
+    )
+}
+
+/// The GPU memory that is used by the models in the world, returned by [GameState::gpu_memory_stats].
+///
+/// Models that are cloned from the same [ModelHandle] share their buffers and textures, so these are only counted once.
+///
+/// [GameState::gpu_memory_stats]: ../struct.GameState.html#method.gpu_memory_stats
+/// [ModelHandle]: ../struct.ModelHandle.html
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct GpuMemoryStats {
+    /// The amount of models in the world.
+    pub model_count: usize,
+    /// The amount of distinct meshes that the models use.
+    pub mesh_count: usize,
+    /// The size of all vertex buffers, in bytes. This includes the levels of detail of the models.
+    pub vertex_bytes: usize,
+    /// The size of all index buffers, in bytes. This includes the levels of detail of the models.
+    pub index_bytes: usize,
+    /// The size of all textures, in bytes.
+    pub texture_bytes: usize,
+}
+
+impl GpuMemoryStats {
+    /// The total amount of bytes that is used by the models.
+    pub fn total_bytes(&self) -> usize {
+        self.vertex_bytes + self.index_bytes + self.texture_bytes
+    }
+
+    pub(crate) fn from_models<'a>(models: impl Iterator<Item = &'a Arc<Model>>) -> Self {
+        let mut stats = Self::default();
+        let mut meshes = HashSet::new();
+        let mut buffers = HashSet::new();
+        let mut textures = HashSet::new();
+        for model in models {
+            stats.model_count += 1;
+            if !meshes.insert(Arc::as_ptr(model)) {
+                continue;
+            }
+            let lods = model
+                .lods
+                .iter()
+                .map(|lod| (lod.vertex_buffer.as_ref(), &lod.groups));
+            for (vertex_buffer, groups) in
+                std::iter::once((model.vertex_buffer.as_ref(), &model.groups)).chain(lods)
+            {
+                let vertex_buffers = groups
+                    .iter()
+                    .filter_map(|g| g.vertex_buffer.as_ref())
+                    .chain(vertex_buffer);
+                for buffer in vertex_buffers {
+                    if buffers.insert(Arc::as_ptr(buffer) as *const ()) {
+                        stats.vertex_bytes += buffer.size();
+                    }
+                }
+                for buffer in groups.iter().filter_map(|g| g.index.as_ref()) {
+                    if buffers.insert(Arc::as_ptr(buffer) as *const ()) {
+                        stats.index_bytes += buffer.size();
+                    }
+                }
+                let group_textures = groups.iter().flat_map(|g| {
+                    g.texture
+                        .iter()
+                        .chain(g.pbr_maps.iter())
+                        .chain(g.specular_map.iter())
+                        .chain(g.normal_map.iter())
+                });
+                for texture in group_textures {
+                    if textures.insert(Arc::as_ptr(texture)) {
+                        stats.texture_bytes += texture::size_in_bytes(texture);
+                    }
+                }
+            }
+        }
+        stats.mesh_count = meshes.len();
+        stats
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 /// The material of a model part. See the lights module for more information
 pub struct Material {
     /// The ambient color multiplier of this material
@@ -139,3 +478,35 @@ impl Default for Material {
         }
     }
 }
+
+/// A physically based material, using the metallic-roughness workflow. This is an alternative to the Blinn-Phong [Material], and can be set on a model with [ModelBuilder::with_pbr_material].
+///
+/// Every factor can be combined with a texture, e.g. [ModelBuilder::with_roughness_map]. The red channel of the texture is multiplied with the factor.
+///
+/// With a PBR material, the diffuse color of a light is used as its radiance, and the specular color is ignored. The ambient color of a light is multiplied with the albedo and the ambient occlusion.
+///
+/// [ModelBuilder::with_pbr_material]: ../struct.ModelBuilder.html#method.with_pbr_material
+/// [ModelBuilder::with_roughness_map]: ../struct.ModelBuilder.html#method.with_roughness_map
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct PbrMaterial {
+    /// The base color of the material. This is multiplied with the texture of the model.
+    pub albedo: [f32; 3],
+    /// How metallic the material is, from `0.0` for non-metals to `1.0` for metals.
+    pub metallic: f32,
+    /// How rough the surface is, from `0.0` for a perfect mirror to `1.0` for a completely diffuse surface.
+    pub roughness: f32,
+    /// The amount of ambient light that reaches the surface, from `0.0` for none to `1.0` for all of it.
+    pub ambient_occlusion: f32,
+}
+
+impl Default for PbrMaterial {
+    fn default() -> Self {
+        Self {
+            albedo: [1.0, 1.0, 1.0],
+            metallic: 0.0,
+            roughness: 0.5,
+            ambient_occlusion: 1.0,
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use super::loader::ParsedMorphTarget;
+use std::sync::Arc;
+use vulkano::{
+    buffer::{BufferUsage, CpuAccessibleBuffer},
+    device::Device,
+};
+
+/// The maximum amount of morph targets that are applied to a model at once. When more morph targets have a weight, the ones with the largest weights are applied.
+pub const MAX_ACTIVE_MORPH_TARGETS: usize = 8;
+
+/// The offset of a single vertex in a morph target, as it is stored in the storage buffer of the vertex shader.
+#[derive(Default, Copy, Clone)]
+#[repr(C)]
+pub struct MorphOffset {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+}
+
+/// The morph targets of a [ModelGroup](../struct.ModelGroup.html). The offsets of every morph target of the model are stored after each other, so the offset of vertex `v` in target `t` is at `t * vertex_count + v`. Targets that the group does not have contain no offsets.
+pub struct MorphTargets {
+    pub offsets: Arc<CpuAccessibleBuffer<[MorphOffset]>>,
+    pub vertex_count: u32,
+}
+
+impl MorphTargets {
+    /// Upload the morph targets of a group, in the order of the `names` of the morph targets of the model. Returns `None` if the group has no morph targets.
+    pub fn new(
+        device: &Arc<Device>,
+        names: &[String],
+        targets: &[ParsedMorphTarget],
+        vertex_count: usize,
+    ) -> Option<Self> {
+        if targets.is_empty() || vertex_count == 0 {
+            return None;
+        }
+        let offsets = morph_offsets(names, targets, vertex_count);
+        // We assume that the device is valid, so this should never fail
+        let offsets = CpuAccessibleBuffer::from_iter(
+            device.clone(),
+            BufferUsage::storage_buffer(),
+            false,
+            offsets.into_iter(),
+        )
+        .unwrap();
+        Some(Self {
+            offsets,
+            vertex_count: vertex_count as u32,
+        })
+    }
+}
+
+/// Get the names of the morph targets of every part, without duplicates. Parts that have the same morph target, e.g. the parts of a single mesh with multiple materials, share its weight.
+pub fn morph_target_names<'a>(targets: impl Iterator<Item = &'a ParsedMorphTarget>) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for target in targets {
+        if !names.contains(&target.name) {
+            names.push(target.name.clone());
+        }
+    }
+    names
+}
+
+/// Lay out the offsets of the targets in the order of `names`. Missing offsets are zero.
+fn morph_offsets(
+    names: &[String],
+    targets: &[ParsedMorphTarget],
+    vertex_count: usize,
+) -> Vec<MorphOffset> {
+    let mut offsets = vec![MorphOffset::default(); names.len() * vertex_count];
+    for target in targets {
+        let index = match names.iter().position(|name| *name == target.name) {
+            Some(index) => index,
+            None => continue,
+        };
+        let start = index * vertex_count;
+        for (i, offset) in offsets[start..start + vertex_count].iter_mut().enumerate() {
+            offset.position = target.positions.get(i).copied().unwrap_or_default();
+            offset.normal = target.normals.get(i).copied().unwrap_or_default();
+        }
+    }
+    offsets
+}
+
+/// Get the `(index, weight)` of the morph targets that are applied, with the largest weights first. Targets without a weight are skipped, and at most [MAX_ACTIVE_MORPH_TARGETS] are returned.
+pub fn active_morph_targets(weights: &[f32]) -> Vec<(usize, f32)> {
+    let mut active = weights
+        .iter()
+        .copied()
+        .enumerate()
+        .filter(|(_, weight)| *weight != 0.0)
+        .collect::<Vec<_>>();
+    active.sort_by(|a, b| {
+        b.1.abs()
+            .partial_cmp(&a.1.abs())
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    active.truncate(MAX_ACTIVE_MORPH_TARGETS);
+    active
+}
+
+#[test]
+fn test_morph_targets() {
+    let target = |name: &str, x: f32| ParsedMorphTarget {
+        name: name.to_owned(),
+        positions: vec![[x, 0.0, 0.0], [0.0, x, 0.0]],
+        normals: Vec::new(),
+    };
+    let part_a = [target("smile", 1.0), target("blink", 2.0)];
+    let part_b = [target("blink", 3.0)];
+    let names = morph_target_names(part_a.iter().chain(&part_b));
+    assert_eq!(vec!["smile".to_owned(), "blink".to_owned()], names);
+
+    // The second part has no offsets for "smile"
+    let offsets = morph_offsets(&names, &part_b, 2);
+    assert_eq!(4, offsets.len());
+    assert_eq!([0.0; 3], offsets[1].position);
+    assert_eq!([0.0, 3.0, 0.0], offsets[3].position);
+
+    let mut weights = vec![0.0, 0.5, -0.9, 0.1];
+    weights.extend(std::iter::repeat(0.2).take(10));
+    let active = active_morph_targets(&weights);
+    assert_eq!(MAX_ACTIVE_MORPH_TARGETS, active.len());
+    assert_eq!((2, -0.9), active[0]);
+    assert_eq!((1, 0.5), active[1]);
+    assert!(active.iter().all(|(index, _)| *index != 0 && *index != 3));
+}
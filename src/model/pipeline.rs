@@ -1,26 +1,141 @@
-use super::{Material, Vertex};
-use crate::GameState;
-use cgmath::{Matrix4, Rad, Zero};
-use std::{mem, sync::Arc};
+use super::{
+    morph::active_morph_targets, CullMode, ModelData, ModelGroup, MorphOffset, MorphTargets,
+    TextureRegion, Vertex,
+};
+use crate::{
+    profiler::RenderStats,
+    render::{
+        clip_plane::{ClipPlane, MAX_CLIP_PLANES},
+        environment::{self, EnvironmentMap},
+        frustum::Frustum,
+        lights::DirectionalLight,
+        occlusion::OcclusionResults,
+        texture::{self, Texture},
+    },
+    state::RenderError,
+    GameState, RenderingPath,
+};
+use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector3, Zero};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    hash::Hash,
+    mem,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use vulkano::{
-    buffer::CpuBufferPool,
+    buffer::{BufferAccess, BufferUsage, CpuAccessibleBuffer, CpuBufferPool, TypedBufferAccess},
     command_buffer::{AutoCommandBufferBuilder, DynamicState},
-    descriptor::descriptor_set::{PersistentDescriptorSet, StdDescriptorPool},
+    descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet, StdDescriptorPool},
     device::{Device, Queue},
-    format::R8G8B8A8Srgb,
+    format::Format,
     framebuffer::{RenderPassAbstract, Subpass},
-    image::{Dimensions, ImmutableImage},
-    pipeline::{GraphicsPipeline, GraphicsPipelineAbstract},
+    pipeline::{
+        depth_stencil::{Compare, DepthStencil},
+        GraphicsPipeline, GraphicsPipelineAbstract,
+    },
     sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
     sync::{now, GpuFuture},
 };
 
-pub struct Pipeline {
+/// The settings of a model that need a different pipeline to render.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct PipelineKey {
+    /// The factors of the depth bias, by their bits
+    depth_bias: (u32, u32),
+    cull_mode: CullMode,
+    depth_test: bool,
+    depth_write: bool,
+}
+
+impl PipelineKey {
+    fn of(model_data: &ModelData) -> Self {
+        Self {
+            depth_bias: model_data.depth_bias.pipeline_key(),
+            cull_mode: model_data.cull_mode,
+            depth_test: model_data.depth_test,
+            depth_write: model_data.depth_write,
+        }
+    }
+}
+
+/// The point of view that the models are rendered from. The scene is rendered from the camera, a [PlanarReflection] is rendered from the camera mirrored in its plane.
+///
+/// [PlanarReflection]: ../state/struct.PlanarReflection.html
+pub struct RenderView<'a> {
+    /// The view matrix, which moves the world in front of the camera
+    pub view: Matrix4<f32>,
+    /// A plane that cuts every model, before the clip planes of the game and the model
+    pub clip_plane: Option<ClipPlane>,
+    /// A model that is not rendered, e.g. the model of a reflection, which can not reflect itself
+    pub hidden_model: Option<u64>,
+    /// The models that are hidden behind other models, see [OcclusionCulling]. This is only set for the view of the camera, because the tests are rendered from the camera.
+    ///
+    /// [OcclusionCulling]: ../state/enum.OcclusionCulling.html
+    pub occlusion: Option<&'a OcclusionResults>,
+}
+
+impl<'a> RenderView<'a> {
+    /// The view of the camera of the game
+    pub fn camera(game_state: &GameState) -> Self {
+        Self {
+            view: game_state.view_matrix(),
+            clip_plane: None,
+            hidden_model: None,
+            occlusion: None,
+        }
+    }
+}
+
+/// Cached descriptor sets that are not used for this many calls to [Pipeline::render] are released, so the textures and buffers of removed models can be freed.
+const DESCRIPTOR_CACHE_FRAMES: u64 = 60;
+
+/// A descriptor set that is reused between frames.
+struct CachedSet {
+    set: Arc<dyn DescriptorSet + Send + Sync>,
+    /// The frame in which the set was last used
+    last_used: u64,
+}
+
+/// A group that is drawn once the data of every group of the frame is uploaded.
+struct Draw {
     pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
-    uniform_buffer: CpuBufferPool<vs::ty::Data>,
+    vertex_buffer: Arc<dyn BufferAccess + Send + Sync>,
+    index: Option<Arc<dyn TypedBufferAccess<Content = [u32]> + Send + Sync>>,
+    morph_set: Arc<dyn DescriptorSet + Send + Sync>,
+    texture_set: Arc<dyn DescriptorSet + Send + Sync>,
+    push_constants: vs::ty::PushConstants,
+}
+
+pub struct Pipeline {
+    /// The pipelines that render models, by the settings of the models that change the pipeline. The default pipeline is created immediately, the others are created the first time a model with those settings is rendered.
+    pipelines: HashMap<PipelineKey, Arc<dyn GraphicsPipelineAbstract + Send + Sync>>,
+    subpass: Subpass<Arc<dyn RenderPassAbstract + Send + Sync>>,
+    rendering_path: RenderingPath,
+    /// True if the models are rendered mirrored, which turns the triangles around
+    mirrored: bool,
+    /// The data of every group that is drawn, which is uploaded once per frame. The group that is drawn picks its data with the `draw_index` push constant.
+    draw_buffer: CpuBufferPool<vs::ty::Data>,
+    /// The directional lights of the forward rendering path. These are in a storage buffer, so there is no fixed limit on the amount of lights.
+    directional_buffer: CpuBufferPool<fs::ty::DirectionalLight>,
     device: Arc<Device>,
-    empty_texture: Arc<ImmutableImage<R8G8B8A8Srgb>>,
+    empty_texture: Texture,
+    /// The texture that is used for PBR maps that are not set, so the factors of the material are used as-is
+    white_texture: Texture,
+    /// The morph buffer of groups without morph targets. A buffer can not be empty, so this has a single unused offset.
+    empty_morph_buffer: Arc<CpuAccessibleBuffer<[MorphOffset]>>,
     sampler: Arc<Sampler>,
+    environment_sampler: Arc<Sampler>,
+    /// The descriptor sets with the textures of the groups, by the pointers of the textures. The textures of a group rarely change, so these are reused between frames. A cached set keeps its textures alive, so their pointers can not be reused by other textures.
+    texture_sets: HashMap<[usize; 11], CachedSet>,
+    /// The descriptor sets with the morph targets of the groups, by the pointer of the morph buffer
+    morph_sets: HashMap<usize, CachedSet>,
+    /// The amount of calls to `render`, which is used to release cached descriptor sets
+    frame: u64,
+    /// The time spent creating descriptor sets since the last call to `take_descriptor_creation_time`
+    descriptor_creation_time: Duration,
+    /// The draw calls, triangles and visible models since the last call to `take_draw_statistics`
+    statistics: RenderStats,
     next_frame_futures: Vec<Box<dyn GpuFuture>>,
 }
 
@@ -28,30 +143,24 @@ impl Pipeline {
     pub fn create(
         device: Arc<Device>,
         queue: Arc<Queue>,
-        render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        subpass: Subpass<Arc<dyn RenderPassAbstract + Send + Sync>>,
+        rendering_path: RenderingPath,
+        mirrored: bool,
     ) -> Self {
-        // The shaders are hard-coded and the device is assumed to be valid, so this should never
-        // fail
-        let vs = vs::Shader::load(device.clone()).expect("failed to create shader module");
-        let fs = fs::Shader::load(device.clone()).expect("failed to create shader module");
-
-        let pipeline = Arc::new(
-            GraphicsPipeline::start()
-                .vertex_input_single_buffer::<Vertex>()
-                .vertex_shader(vs.main_entry_point(), ())
-                .viewports_dynamic_scissors_irrelevant(1)
-                .fragment_shader(fs.main_entry_point(), ())
-                .cull_mode_back()
-                .blend_alpha_blending()
-                .depth_stencil_simple_depth()
-                // The render pass is hard-coded so this is assumed to never fail
-                .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
-                .build(device.clone())
-                // The arguments are hard-coded so this is assumed to never fail
-                .unwrap(),
+        let mut pipelines = HashMap::new();
+        let default_data = ModelData::default();
+        pipelines.insert(
+            PipelineKey::of(&default_data),
+            create_pipeline(
+                device.clone(),
+                subpass.clone(),
+                rendering_path,
+                mirrored,
+                &default_data,
+            ),
         );
-        let uniform_buffer = CpuBufferPool::<vs::ty::Data>::uniform_buffer(device.clone());
-        let (empty_texture, fut) = generate_empty_texture(queue, [255, 0, 0, 255]);
+        let (empty_texture, fut) = generate_empty_texture(queue.clone(), [255, 0, 0, 255]);
+        let (white_texture, white_fut) = generate_empty_texture(queue, [255, 255, 255, 255]);
 
         let sampler = Sampler::new(
             device.clone(),
@@ -69,46 +178,151 @@ impl Pipeline {
         // The arguments are hard-coded so this is assumed to never fail
         .unwrap();
 
+        // The device is assumed to be valid so this should never fail
+        let empty_morph_buffer = CpuAccessibleBuffer::from_iter(
+            device.clone(),
+            BufferUsage::storage_buffer(),
+            false,
+            std::iter::once(MorphOffset::default()),
+        )
+        .unwrap();
+
         Self {
-            pipeline,
-            uniform_buffer,
+            pipelines,
+            subpass,
+            rendering_path,
+            mirrored,
+            draw_buffer: CpuBufferPool::new(device.clone(), BufferUsage::storage_buffer()),
+            directional_buffer: CpuBufferPool::new(device.clone(), BufferUsage::storage_buffer()),
+            environment_sampler: environment::sampler(device.clone()),
+            texture_sets: HashMap::new(),
+            morph_sets: HashMap::new(),
+            frame: 0,
+            descriptor_creation_time: Duration::default(),
+            statistics: RenderStats::default(),
             device,
             empty_texture,
+            white_texture,
+            empty_morph_buffer,
             sampler,
-            next_frame_futures: vec![fut],
+            next_frame_futures: vec![fut, white_fut],
         }
     }
 
+    /// Get the time that was spent creating descriptor sets since the last call to this function.
+    pub fn take_descriptor_creation_time(&mut self) -> Duration {
+        mem::take(&mut self.descriptor_creation_time)
+    }
+
+    /// Get the amount of draw calls, triangles and visible models since the last call to this function.
+    pub fn take_draw_statistics(&mut self) -> RenderStats {
+        mem::take(&mut self.statistics)
+    }
+
+    /// Render every visible model from the given view. `environment` is only used by the forward rendering path, the deferred rendering path applies it in the lighting pass.
+    #[allow(clippy::too_many_arguments)]
     pub fn render(
         &mut self,
         future: &mut Box<dyn GpuFuture>,
         command_buffer_builder: &mut AutoCommandBufferBuilder,
         dimensions: [f32; 2],
         game_state: &GameState,
+        view: &RenderView,
+        environment: &EnvironmentMap,
         dynamic_state: &DynamicState,
         descriptor_pool: &mut Arc<StdDescriptorPool>,
-    ) {
+    ) -> Result<(), RenderError> {
         for fut in self.next_frame_futures.drain(..) {
             let tmp = std::mem::replace(future, now(self.device.clone()).boxed());
             *future = tmp.join(fut).boxed();
         }
-        let proj = cgmath::perspective(
-            Rad(std::f32::consts::FRAC_PI_2),
-            dimensions[0] / dimensions[1],
-            0.01,
-            100.0,
-        );
+        let proj = game_state.perspective_matrix(dimensions[0] / dimensions[1]);
+        let frustum = Frustum::from_matrix(proj * view.view);
+        // The levels of detail are picked based on the distance to the camera. A camera matrix that
+        // can not be inverted is invalid, so fall back to the origin of the world.
+        let camera_position = view
+            .view
+            .invert()
+            .map(|m| m.w.truncate())
+            .unwrap_or_else(Vector3::zero);
 
-        let mut data = default_uniform(
-            game_state.camera,
-            proj,
-            game_state.light.directional.to_shader_value(),
-        );
+        let weather = &game_state.weather;
+        let directional = &game_state.light.directional;
+        let mut data = default_uniform(view.view, proj, directional.len() as i32);
+        // The directional lights are only used by the forward rendering path, the deferred rendering
+        // path applies them in the lighting pass. A buffer can not be empty, so a single unused light
+        // is uploaded when there are no lights.
+        let directional_lights = match self.rendering_path {
+            RenderingPath::Forward if directional.is_empty() => {
+                Some(self.directional_buffer.chunk(std::iter::once(
+                    DirectionalLight::default().to_shader_value(1.0),
+                )))
+            }
+            RenderingPath::Forward => Some(
+                self.directional_buffer
+                    .chunk(directional.to_shader_value(weather.light_factor())),
+            ),
+            RenderingPath::Deferred => None,
+        }
+        .transpose()
+        .map_err(RenderError::CouldNotAllocateBuffer)?
+        .map(Arc::new);
+        if game_state.light.environment.is_some() {
+            data.environment_intensity =
+                game_state.light.environment_intensity * weather.light_factor();
+        }
+        let (sky, ground) = game_state
+            .light
+            .hemisphere
+            .to_shader_value(weather.light_factor());
+        data.hemisphere_sky_r = sky.x;
+        data.hemisphere_sky_g = sky.y;
+        data.hemisphere_sky_b = sky.z;
+        data.hemisphere_ground_r = ground.x;
+        data.hemisphere_ground_g = ground.y;
+        data.hemisphere_ground_b = ground.z;
+        let (fog_color, fog_density) = game_state.fog();
+        data.fog_r = fog_color[0];
+        data.fog_g = fog_color[1];
+        data.fog_b = fog_color[2];
+        data.fog_density = fog_density;
+        data.wetness = weather.surface_wetness();
 
-        for model in game_state.model_handles.values() {
+        // Models are sorted by their render order. The id is used to keep the order of models with
+        // the same render order stable between frames.
+        let mut models = game_state
+            .model_handles
+            .iter()
+            .filter(|(id, model)| Some(**id) != view.hidden_model && model.is_visible(&frustum))
+            .map(|(id, model)| (model.data.read().render_order, *id, model))
+            .collect::<Vec<_>>();
+        if let Some(occlusion) = view.occlusion {
+            let count = models.len();
+            models.retain(|(_, id, _)| !occlusion.is_occluded(*id));
+            self.statistics.occluded_models += (count - models.len()) as u32;
+        }
+        models.sort_unstable_by_key(|(render_order, id, _)| (*render_order, *id));
+        self.statistics.visible_models += models.len() as u32;
+
+        self.frame += 1;
+        let mut draws = Vec::new();
+        let mut draw_data = Vec::new();
+        for (_, _, model) in models {
             let model_data = model.data.read();
-            let model = &model.model;
-            let base_matrix = model_data.matrix();
+            let pipeline = self.pipeline(&model_data);
+            update_uniform_clip_planes(
+                &mut data,
+                view.clip_plane
+                    .iter()
+                    .chain(&game_state.clip_planes)
+                    .chain(&model_data.clip_planes),
+            );
+            data.texture_region = model_data.texture_region.to_shader_value();
+            data.tint = model_data.tint;
+            data.highlight = model_data.highlight.max(0.0);
+            let base_matrix = game_state.model_matrix(model, &model_data);
+            let model = model.mesh();
+            let groups = model.draw_groups((model_data.position - camera_position).magnitude());
 
             if !model.texture_future.read().is_empty() {
                 let texture_futures = mem::replace(&mut *model.texture_future.write(), Vec::new());
@@ -117,86 +331,383 @@ impl Pipeline {
                     *future = tmp.join(fut).boxed();
                 }
             }
-            // The pipeline and the layout index are hard-coded so this is assumed to never fail
-            let layout = self.pipeline.descriptor_set_layout(0).unwrap();
-
-            for (group, group_data) in model.groups.iter().zip(model_data.groups.iter()) {
-                let texture = group
-                    .texture
-                    .as_ref()
-                    .unwrap_or(&self.empty_texture)
-                    .clone();
-
-                data.world = (base_matrix * group_data.matrix).into();
-                update_uniform_material(&mut data, group.material.as_ref());
-
-                // The uniform_buffer is assumed to be valid so this should never fail
-                let uniform_buffer_subbuffer = self.uniform_buffer.next(data).unwrap();
-
-                let set = Arc::new(
-                    PersistentDescriptorSet::start(layout.clone())
-                        .add_buffer(uniform_buffer_subbuffer)
-                        // The uniform subbuffer is assumed to be valid so this should never fail
+            // The vertices of a mesh that was changed at runtime no longer match the morph targets
+            let morph_enabled = model.dynamic.read().is_none();
+
+            for (index, draw_group) in groups.into_iter().enumerate() {
+                let group = draw_group.group;
+                let triangles = draw_group.triangle_count();
+                let vertex_buffer = match draw_group.vertex_buffer {
+                    Some(vertex_buffer) => vertex_buffer,
+                    // A mesh that was changed at runtime can be empty
+                    None => continue,
+                };
+                // Levels of detail can have more groups than the model itself
+                let group_matrix = model_data
+                    .groups
+                    .get(index)
+                    .map(|g| g.matrix)
+                    .unwrap_or_else(Matrix4::identity);
+
+                data.world = (base_matrix * group_matrix).into();
+                update_uniform_material(&mut data, group);
+                let layers = group.texture_layers.as_ref();
+                data.texture_layer_count = layers.map_or(0, |l| l.layers.len() as i32);
+                data.texture_layer_scale = layers.map_or(1.0, |l| l.scale);
+                let morph = group.morph.as_ref().filter(|_| morph_enabled);
+                update_uniform_morph_targets(&mut data, morph, &model_data.morph_weights);
+
+                let descriptor_start = Instant::now();
+                let morph_set = self.morph_set(&pipeline, morph, descriptor_pool)?;
+                let texture_set = self.texture_set(&pipeline, group, descriptor_pool)?;
+                self.descriptor_creation_time += descriptor_start.elapsed();
+
+                self.statistics.draw_calls += 1;
+                self.statistics.triangles += triangles;
+                draws.push(Draw {
+                    morph_set,
+                    texture_set,
+                    pipeline: pipeline.clone(),
+                    vertex_buffer,
+                    index: draw_group.index,
+                    push_constants: vs::ty::PushConstants {
+                        custom_data: model_data.custom_data,
+                        draw_index: draw_data.len() as u32,
+                    },
+                });
+                draw_data.push(data);
+            }
+        }
+        self.release_unused_sets();
+
+        let first_pipeline = match draws.first() {
+            Some(draw) => draw.pipeline.clone(),
+            None => return Ok(()),
+        };
+        // The data of every group is uploaded at once, so only a single descriptor set is created
+        // for the buffer, the lights and the environment map.
+        let descriptor_start = Instant::now();
+        let draw_buffer = self
+            .draw_buffer
+            .chunk(draw_data)
+            .map_err(RenderError::CouldNotAllocateBuffer)?;
+        // The pipeline and the layout index are hard-coded so this is assumed to never fail
+        let layout = first_pipeline.descriptor_set_layout(0).unwrap();
+        let set = PersistentDescriptorSet::start(layout.clone())
+            .add_buffer(draw_buffer)
+            // The buffer is assumed to be valid so this should never fail
+            .unwrap();
+        // The textures, samplers and lights are assumed to be valid so adding them should never
+        // fail
+        let frame_set: Arc<dyn DescriptorSet + Send + Sync> = match self.rendering_path {
+            RenderingPath::Forward => {
+                let sampler = &self.environment_sampler;
+                Arc::new(
+                    set.add_sampled_image(environment.irradiance.clone(), sampler.clone())
                         .unwrap()
-                        .add_sampled_image(texture, self.sampler.clone())
-                        // The texture and sampler are assumed to be valid so this should never fail
+                        .add_sampled_image(environment.specular[0].clone(), sampler.clone())
+                        .unwrap()
+                        .add_sampled_image(environment.specular[1].clone(), sampler.clone())
+                        .unwrap()
+                        .add_sampled_image(environment.specular[2].clone(), sampler.clone())
+                        .unwrap()
+                        .add_sampled_image(environment.specular[3].clone(), sampler.clone())
+                        .unwrap()
+                        // The lights are always uploaded for the forward rendering path
+                        .add_buffer(directional_lights.unwrap())
                         .unwrap()
                         .build_with_pool(descriptor_pool)
-                        // The pool is assumed to be valid so this should never fail
-                        .unwrap(),
-                );
-
-                let vertex_buffer = group
-                    .vertex_buffer
-                    .as_ref()
-                    .or_else(|| model.vertex_buffer.as_ref())
-                    // This is already validated in ModelBuilder::build so this should never fail
-                    .expect("Model has no valid vertex buffer");
-
-                if let Some(index) = group.index.as_ref() {
-                    command_buffer_builder
-                        .draw_indexed(
-                            self.pipeline.clone(),
-                            dynamic_state,
-                            vec![vertex_buffer.clone()],
-                            index.clone(),
-                            set.clone(),
-                            (),
-                        )
-                        // the builder and arguments are assumed to be valid so this should never
-                        // fail
-                        .unwrap();
-                } else {
-                    command_buffer_builder
-                        .draw(
-                            self.pipeline.clone(),
-                            dynamic_state,
-                            vec![vertex_buffer.clone()],
-                            set,
-                            (),
-                        )
-                        // the builder and arguments are assumed to be valid so this should never
-                        // fail
-                        .unwrap();
-                }
+                        .map_err(RenderError::CouldNotCreateDescriptorSet)?,
+                )
             }
+            RenderingPath::Deferred => Arc::new(
+                set.build_with_pool(descriptor_pool)
+                    .map_err(RenderError::CouldNotCreateDescriptorSet)?,
+            ),
+        };
+        self.descriptor_creation_time += descriptor_start.elapsed();
+
+        for draw in draws {
+            let sets = (frame_set.clone(), draw.morph_set, draw.texture_set);
+            if let Some(index) = draw.index {
+                command_buffer_builder
+                    .draw_indexed(
+                        draw.pipeline,
+                        dynamic_state,
+                        vec![draw.vertex_buffer],
+                        index,
+                        sets,
+                        draw.push_constants,
+                    )
+                    .map_err(RenderError::CouldNotDrawIndexed)?;
+            } else {
+                command_buffer_builder
+                    .draw(
+                        draw.pipeline,
+                        dynamic_state,
+                        vec![draw.vertex_buffer],
+                        sets,
+                        draw.push_constants,
+                    )
+                    .map_err(RenderError::CouldNotDraw)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the descriptor set with the textures of a group, and create it if it is not cached yet. Textures that the group does not have are replaced by the empty and white textures.
+    fn texture_set(
+        &mut self,
+        pipeline: &Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+        group: &ModelGroup,
+        descriptor_pool: &mut Arc<StdDescriptorPool>,
+    ) -> Result<Arc<dyn DescriptorSet + Send + Sync>, RenderError> {
+        let white = &self.white_texture;
+        let maps = &group.pbr_maps;
+        let layers = group.texture_layers.as_ref();
+        // Layers that are not used are never sampled, so they can be any texture
+        let layer = |i: usize| layers.and_then(|l| l.layers.get(i)).unwrap_or(white);
+        let textures = [
+            group.texture.as_ref().unwrap_or(&self.empty_texture),
+            maps.metallic.as_ref().unwrap_or(white),
+            maps.roughness.as_ref().unwrap_or(white),
+            maps.ambient_occlusion.as_ref().unwrap_or(white),
+            layers.map_or(white, |l| &l.splat_map),
+            layer(0),
+            layer(1),
+            layer(2),
+            layer(3),
+            group.specular_map.as_ref().unwrap_or(white),
+            group.normal_map.as_ref().unwrap_or(white),
+        ];
+        let mut key = [0; 11];
+        for (key, texture) in key.iter_mut().zip(&textures) {
+            *key = Arc::as_ptr(texture) as usize;
         }
+        let sampler = &self.sampler;
+        cached_set(&mut self.texture_sets, key, self.frame, || {
+            // The pipeline and the layout index are hard-coded, and the textures and sampler are
+            // assumed to be valid, so these should never fail
+            let layout = pipeline.descriptor_set_layout(2).unwrap();
+            let image = |i: usize| textures[i].clone();
+            let set = PersistentDescriptorSet::start(layout.clone())
+                .add_sampled_image(image(0), sampler.clone())
+                .unwrap()
+                .add_sampled_image(image(1), sampler.clone())
+                .unwrap()
+                .add_sampled_image(image(2), sampler.clone())
+                .unwrap()
+                .add_sampled_image(image(3), sampler.clone())
+                .unwrap()
+                .add_sampled_image(image(4), sampler.clone())
+                .unwrap()
+                .add_sampled_image(image(5), sampler.clone())
+                .unwrap()
+                .add_sampled_image(image(6), sampler.clone())
+                .unwrap()
+                .add_sampled_image(image(7), sampler.clone())
+                .unwrap()
+                .add_sampled_image(image(8), sampler.clone())
+                .unwrap()
+                .add_sampled_image(image(9), sampler.clone())
+                .unwrap()
+                .add_sampled_image(image(10), sampler.clone())
+                .unwrap()
+                .build_with_pool(descriptor_pool)
+                .map_err(RenderError::CouldNotCreateDescriptorSet)?;
+            Ok(Arc::new(set))
+        })
+    }
+
+    /// Get the descriptor set with the morph targets of a group, and create it if it is not cached yet. Groups without morph targets use an empty buffer.
+    fn morph_set(
+        &mut self,
+        pipeline: &Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+        morph: Option<&MorphTargets>,
+        descriptor_pool: &mut Arc<StdDescriptorPool>,
+    ) -> Result<Arc<dyn DescriptorSet + Send + Sync>, RenderError> {
+        let buffer = morph.map_or(&self.empty_morph_buffer, |m| &m.offsets);
+        let key = Arc::as_ptr(buffer) as usize;
+        cached_set(&mut self.morph_sets, key, self.frame, || {
+            // The pipeline and the layout index are hard-coded, and the buffer is assumed to be
+            // valid, so these should never fail
+            let layout = pipeline.descriptor_set_layout(1).unwrap();
+            let set = PersistentDescriptorSet::start(layout.clone())
+                .add_buffer(buffer.clone())
+                .unwrap()
+                .build_with_pool(descriptor_pool)
+                .map_err(RenderError::CouldNotCreateDescriptorSet)?;
+            Ok(Arc::new(set))
+        })
+    }
+
+    /// Release the cached descriptor sets that were not used in the last [DESCRIPTOR_CACHE_FRAMES] frames.
+    fn release_unused_sets(&mut self) {
+        let frame = self.frame;
+        let used = |cached: &CachedSet| frame - cached.last_used <= DESCRIPTOR_CACHE_FRAMES;
+        self.texture_sets.retain(|_, cached| used(cached));
+        self.morph_sets.retain(|_, cached| used(cached));
+    }
+}
+
+/// Get a descriptor set from the cache, or create it with `create` if it is not cached yet. The set is marked as used in `frame`. Sets that could not be created are not cached.
+fn cached_set<K: Eq + Hash>(
+    cache: &mut HashMap<K, CachedSet>,
+    key: K,
+    frame: u64,
+    create: impl FnOnce() -> Result<Arc<dyn DescriptorSet + Send + Sync>, RenderError>,
+) -> Result<Arc<dyn DescriptorSet + Send + Sync>, RenderError> {
+    let cached = match cache.entry(key) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => entry.insert(CachedSet {
+            set: create()?,
+            last_used: frame,
+        }),
+    };
+    cached.last_used = frame;
+    Ok(cached.set.clone())
+}
+
+impl Pipeline {
+    /// Get the pipeline that renders the given model, and create it if it does not exist yet.
+    fn pipeline(
+        &mut self,
+        model_data: &ModelData,
+    ) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
+        let device = &self.device;
+        let subpass = &self.subpass;
+        let rendering_path = self.rendering_path;
+        let mirrored = self.mirrored;
+        self.pipelines
+            .entry(PipelineKey::of(model_data))
+            .or_insert_with(|| {
+                create_pipeline(
+                    device.clone(),
+                    subpass.clone(),
+                    rendering_path,
+                    mirrored,
+                    model_data,
+                )
+            })
+            .clone()
     }
 }
 
-fn default_uniform(
-    camera: Matrix4<f32>,
-    proj: Matrix4<f32>,
-    directional_lights: (i32, [vs::ty::DirectionalLight; 100]),
-) -> vs::ty::Data {
+fn create_pipeline(
+    device: Arc<Device>,
+    subpass: Subpass<Arc<dyn RenderPassAbstract + Send + Sync>>,
+    rendering_path: RenderingPath,
+    mirrored: bool,
+    model_data: &ModelData,
+) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
+    let depth_bias_constant = model_data.depth_bias.constant_factor.max(0.0);
+    let depth_bias_slope = model_data.depth_bias.slope_factor.max(0.0);
+    // Devices without clip distances can not load `vs_clip`, so they fall back to `vs` and ignore
+    // the clip planes
+    let clip_distance = device.enabled_features().shader_clip_distance;
+
+    // Every shader module has its own types, so the pipeline is built by a macro instead of a
+    // function
+    macro_rules! build_pipeline {
+        ($vs:expr, $fs:expr, $fs_constants:expr, $blend:ident) => {{
+            let builder = GraphicsPipeline::start()
+                .vertex_input_single_buffer::<Vertex>()
+                .vertex_shader($vs.main_entry_point(), ())
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader($fs.main_entry_point(), $fs_constants);
+            // Mirroring the world turns the triangles around, so the other side is the front
+            let builder = if mirrored {
+                builder.front_face_clockwise()
+            } else {
+                builder.front_face_counter_clockwise()
+            };
+            let builder = match model_data.cull_mode {
+                CullMode::None => builder.cull_mode_disabled(),
+                CullMode::Front => builder.cull_mode_front(),
+                CullMode::Back => builder.cull_mode_back(),
+            };
+            let pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync> = Arc::new(
+                builder
+                    .$blend()
+                    .depth_stencil(depth_stencil(model_data.depth_test, model_data.depth_write))
+                    .render_pass(subpass)
+                    .build(device.clone())
+                    // The arguments are hard-coded so this is assumed to never fail
+                    .unwrap(),
+            );
+            pipeline
+        }};
+    }
+
+    // The shaders are hard-coded and the device is assumed to be valid, so these should never
+    // fail
+    match rendering_path {
+        RenderingPath::Forward => {
+            let fs = fs::Shader::load(device.clone()).expect("failed to create shader module");
+            let constants = fs::SpecializationConstants {
+                depth_bias_constant,
+                depth_bias_slope,
+            };
+            if clip_distance {
+                let vs =
+                    vs_clip::Shader::load(device.clone()).expect("failed to create shader module");
+                build_pipeline!(vs, fs, constants, blend_alpha_blending)
+            } else {
+                let vs = vs::Shader::load(device.clone()).expect("failed to create shader module");
+                build_pipeline!(vs, fs, constants, blend_alpha_blending)
+            }
+        }
+        RenderingPath::Deferred => {
+            let fs =
+                fs_deferred::Shader::load(device.clone()).expect("failed to create shader module");
+            let constants = fs_deferred::SpecializationConstants {
+                depth_bias_constant,
+                depth_bias_slope,
+            };
+            // The G-buffer contains material data instead of colors, so it can not be blended.
+            // This means transparent models are not supported.
+            if clip_distance {
+                let vs =
+                    vs_clip::Shader::load(device.clone()).expect("failed to create shader module");
+                build_pipeline!(vs, fs, constants, blend_pass_through)
+            } else {
+                let vs = vs::Shader::load(device.clone()).expect("failed to create shader module");
+                build_pipeline!(vs, fs, constants, blend_pass_through)
+            }
+        }
+    }
+}
+
+/// A depth test that lets models that are rendered later win when they have the same depth as an
+/// earlier model. Together with the render order of models this resolves z-fighting.
+///
+/// Models without a depth test always pass it, so they are drawn over everything that was rendered
+/// before them.
+fn depth_stencil(depth_test: bool, depth_write: bool) -> DepthStencil {
+    DepthStencil {
+        depth_compare: if depth_test {
+            Compare::LessOrEqual
+        } else {
+            Compare::Always
+        },
+        depth_write,
+        ..DepthStencil::simple_depth_test()
+    }
+}
+
+fn default_uniform(camera: Matrix4<f32>, proj: Matrix4<f32>, light_count: i32) -> vs::ty::Data {
     let camera_pos = -camera.z.truncate();
 
     vs::ty::Data {
         world: Matrix4::zero().into(),
         view: camera.into(),
         proj: proj.into(),
-        lights: directional_lights.1,
-        lightCount: directional_lights.0,
+        clip_planes: [[0.0; 4]; MAX_CLIP_PLANES],
+        texture_region: TextureRegion::default().to_shader_value(),
+        tint: [1.0; 4],
+        morph_offsets: [[0; 4]; 2],
+        morph_weights: [[0.0; 4]; 2],
+        lightCount: light_count,
 
         camera_x: camera_pos.x,
         camera_y: camera_pos.y,
@@ -211,10 +722,52 @@ fn default_uniform(
         material_specular_g: 0.0,
         material_specular_b: 0.0,
         material_shininess: 0.0,
+        clip_plane_count: 0,
+        environment_intensity: 0.0,
+        material_pbr: 0,
+        material_metallic: 0.0,
+        material_roughness: 0.0,
+        material_ambient_occlusion: 0.0,
+        fog_r: 0.0,
+        fog_g: 0.0,
+        fog_b: 0.0,
+        fog_density: 0.0,
+        wetness: 0.0,
+        highlight: 0.0,
+        hemisphere_sky_r: 0.0,
+        hemisphere_sky_g: 0.0,
+        hemisphere_sky_b: 0.0,
+        hemisphere_ground_r: 0.0,
+        hemisphere_ground_g: 0.0,
+        hemisphere_ground_b: 0.0,
+        texture_layer_count: 0,
+        texture_layer_scale: 1.0,
+        morph_target_count: 0,
+        normal_mapped: 0,
     }
 }
-pub(crate) fn update_uniform_material(data: &mut vs::ty::Data, material: Option<&Material>) {
-    let material = material.cloned().unwrap_or_default();
+/// Set the clip planes of a model. Planes beyond [MAX_CLIP_PLANES] are ignored.
+fn update_uniform_clip_planes<'a>(
+    data: &mut vs::ty::Data,
+    clip_planes: impl Iterator<Item = &'a ClipPlane>,
+) {
+    let mut count = 0;
+    for (target, plane) in data.clip_planes.iter_mut().zip(clip_planes) {
+        *target = plane.to_shader_value();
+        count += 1;
+    }
+    data.clip_plane_count = count;
+}
+
+/// The flags of `material_pbr` in the uniform data. The flags of the maps must match the ones that are checked by the fragment shaders.
+const PBR_MATERIAL: i32 = 1;
+const PBR_METALLIC_MAP: i32 = 2;
+const PBR_ROUGHNESS_MAP: i32 = 4;
+const PBR_AMBIENT_OCCLUSION_MAP: i32 = 8;
+
+/// Set the material of a model group. Groups with a PBR material pass the albedo as the diffuse color.
+pub(crate) fn update_uniform_material(data: &mut vs::ty::Data, group: &ModelGroup) {
+    let material = group.material.unwrap_or_default();
     data.material_ambient_r = material.ambient[0];
     data.material_ambient_g = material.ambient[1];
     data.material_ambient_b = material.ambient[2];
@@ -225,6 +778,43 @@ pub(crate) fn update_uniform_material(data: &mut vs::ty::Data, material: Option<
     data.material_diffuse_g = material.diffuse[1];
     data.material_diffuse_b = material.diffuse[2];
     data.material_shininess = material.shininess;
+
+    data.normal_mapped = group.normal_map.is_some() as i32;
+    data.material_pbr = 0;
+    if let Some(pbr) = group.pbr_material {
+        let maps = &group.pbr_maps;
+        let flag = |map: &Option<Texture>, flag: i32| if map.is_some() { flag } else { 0 };
+        data.material_pbr = PBR_MATERIAL
+            | flag(&maps.metallic, PBR_METALLIC_MAP)
+            | flag(&maps.roughness, PBR_ROUGHNESS_MAP)
+            | flag(&maps.ambient_occlusion, PBR_AMBIENT_OCCLUSION_MAP);
+        data.material_diffuse_r = pbr.albedo[0];
+        data.material_diffuse_g = pbr.albedo[1];
+        data.material_diffuse_b = pbr.albedo[2];
+        data.material_metallic = pbr.metallic;
+        data.material_roughness = pbr.roughness;
+        data.material_ambient_occlusion = pbr.ambient_occlusion;
+    }
+}
+
+/// Set the morph targets that are applied to a model group. Weights of morph targets that the group does not have are ignored.
+fn update_uniform_morph_targets(
+    data: &mut vs::ty::Data,
+    morph: Option<&MorphTargets>,
+    weights: &[f32],
+) {
+    data.morph_target_count = 0;
+    let morph = match morph {
+        Some(morph) => morph,
+        None => return,
+    };
+    let target_count = morph.offsets.len() / morph.vertex_count as usize;
+    let weights = &weights[..weights.len().min(target_count)];
+    for (i, (target, weight)) in active_morph_targets(weights).into_iter().enumerate() {
+        data.morph_offsets[i / 4][i % 4] = (target * morph.vertex_count as usize) as i32;
+        data.morph_weights[i / 4][i % 4] = weight;
+        data.morph_target_count += 1;
+    }
 }
 
 pub mod vs {
@@ -238,27 +828,151 @@ layout(location = 2) in vec2 tex_coord;
 
 layout(location = 0) out vec2 fragment_tex_coord;
 layout(location = 1) out vec3 fragment_normal;
+layout(location = 2) out vec3 fragment_view_position;
 
-struct DirectionalLight {
-    float direction_x;
-    float direction_y;
-    float direction_z;
-    float color_ambient_r;
-    float color_ambient_g;
-    float color_ambient_b;
-    float color_diffuse_r;
-    float color_diffuse_g;
-    float color_diffuse_b;
-    float color_specular_r;
-    float color_specular_g;
-    float color_specular_b;
+struct Data {
+    mat4 world;
+    mat4 view;
+    mat4 proj;
+    vec4 clip_planes[4];
+    vec4 texture_region;
+    vec4 tint;
+    // The morph targets that are applied, see ModelHandle::set_morph_weight. Every element
+    // holds the start of 4 morph targets in the morph buffer and their weights
+    ivec4 morph_offsets[2];
+    vec4 morph_weights[2];
+    int lightCount;
+
+    float camera_x;
+    float camera_y;
+    float camera_z;
+
+    float material_ambient_r;
+    float material_ambient_g;
+    float material_ambient_b;
+    float material_diffuse_r;
+    float material_diffuse_g;
+    float material_diffuse_b;
+    float material_specular_r;
+    float material_specular_g;
+    float material_specular_b;
+    float material_shininess;
+    int clip_plane_count;
+    float environment_intensity;
+
+    // PBR_MATERIAL if the group has a PBR material, combined with a flag for every PBR map that
+    // is set, see update_uniform_material
+    int material_pbr;
+    float material_metallic;
+    float material_roughness;
+    float material_ambient_occlusion;
+
+    float fog_r;
+    float fog_g;
+    float fog_b;
+    float fog_density;
+    float wetness;
+    float highlight;
+
+    // The colors of the hemisphere light, multiplied with its intensity
+    float hemisphere_sky_r;
+    float hemisphere_sky_g;
+    float hemisphere_sky_b;
+    float hemisphere_ground_r;
+    float hemisphere_ground_g;
+    float hemisphere_ground_b;
+
+    // The texture layers of the model, see ModelBuilder::with_texture_layers
+    int texture_layer_count;
+    float texture_layer_scale;
+    int morph_target_count;
+    // 1 if the group has a normal map. This also pads the struct to a multiple of 16 bytes, so
+    // the stride of the array matches ty::Data
+    int normal_mapped;
+};
+
+// The data of every group that is drawn by a call to Pipeline::render
+layout(set = 0, binding = 0) readonly buffer Draws {
+    Data data[];
+} draws;
+
+// The custom data of the model, see `ModelData::custom_data`, and the index of the group in
+// `draws`
+layout(push_constant) uniform PushConstants {
+    float custom_data[16];
+    uint draw_index;
+} push_constants;
+
+#define uniforms draws.data[push_constants.draw_index]
+
+struct MorphOffset {
+    float position_x;
+    float position_y;
+    float position_z;
+    float normal_x;
+    float normal_y;
+    float normal_z;
 };
 
-layout(set = 0, binding = 0) uniform Data {
+// The offsets of the vertices of every morph target of the group, see MorphTargets
+layout(set = 1, binding = 0) readonly buffer MorphTargets {
+    MorphOffset offsets[];
+} morph;
+
+void apply_morph_targets(inout vec3 position, inout vec3 normal) {
+    for(int i = 0; i < uniforms.morph_target_count; i++) {
+        float weight = uniforms.morph_weights[i / 4][i % 4];
+        MorphOffset offset = morph.offsets[uniforms.morph_offsets[i / 4][i % 4] + gl_VertexIndex];
+        position += weight * vec3(offset.position_x, offset.position_y, offset.position_z);
+        normal += weight * vec3(offset.normal_x, offset.normal_y, offset.normal_z);
+    }
+}
+
+void main() {
+    vec3 morphed_position = position;
+    vec3 morphed_normal = normal;
+    apply_morph_targets(morphed_position, morphed_normal);
+
+    mat4 worldview = uniforms.view * uniforms.world;
+    gl_Position = uniforms.proj * worldview * vec4(morphed_position, 1.0);
+    // Negative texture coordinates mean that the model has no texture, so they are kept as-is
+    fragment_tex_coord = tex_coord.x < 0.0 && tex_coord.y < 0.0
+        ? tex_coord
+        : uniforms.texture_region.xy + tex_coord * uniforms.texture_region.zw;
+
+    fragment_normal = transpose(inverse(mat3(worldview))) * morphed_normal;
+    fragment_view_position = (worldview * vec4(morphed_position, 1.0)).xyz;
+}
+"
+    }
+}
+
+/// The vertex shader that is used when the device supports clip distances. This is the same as [vs], with the clip planes applied.
+pub mod vs_clip {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "#version 450
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec2 tex_coord;
+
+layout(location = 0) out vec2 fragment_tex_coord;
+layout(location = 1) out vec3 fragment_normal;
+layout(location = 2) out vec3 fragment_view_position;
+out float gl_ClipDistance[4];
+
+struct Data {
     mat4 world;
     mat4 view;
     mat4 proj;
-    DirectionalLight[100] lights;
+    vec4 clip_planes[4];
+    vec4 texture_region;
+    vec4 tint;
+    // The morph targets that are applied, see ModelHandle::set_morph_weight. Every element
+    // holds the start of 4 morph targets in the morph buffer and their weights
+    ivec4 morph_offsets[2];
+    vec4 morph_weights[2];
     int lightCount;
 
     float camera_x;
@@ -275,14 +989,96 @@ layout(set = 0, binding = 0) uniform Data {
     float material_specular_g;
     float material_specular_b;
     float material_shininess;
-} uniforms;
+    int clip_plane_count;
+    float environment_intensity;
+
+    // PBR_MATERIAL if the group has a PBR material, combined with a flag for every PBR map that
+    // is set, see update_uniform_material
+    int material_pbr;
+    float material_metallic;
+    float material_roughness;
+    float material_ambient_occlusion;
+
+    float fog_r;
+    float fog_g;
+    float fog_b;
+    float fog_density;
+    float wetness;
+    float highlight;
+
+    // The colors of the hemisphere light, multiplied with its intensity
+    float hemisphere_sky_r;
+    float hemisphere_sky_g;
+    float hemisphere_sky_b;
+    float hemisphere_ground_r;
+    float hemisphere_ground_g;
+    float hemisphere_ground_b;
+
+    // The texture layers of the model, see ModelBuilder::with_texture_layers
+    int texture_layer_count;
+    float texture_layer_scale;
+    int morph_target_count;
+    // 1 if the group has a normal map. This also pads the struct to a multiple of 16 bytes, so
+    // the stride of the array matches ty::Data
+    int normal_mapped;
+};
+
+// The data of every group that is drawn by a call to Pipeline::render
+layout(set = 0, binding = 0) readonly buffer Draws {
+    Data data[];
+} draws;
+
+// The custom data of the model, see `ModelData::custom_data`, and the index of the group in
+// `draws`
+layout(push_constant) uniform PushConstants {
+    float custom_data[16];
+    uint draw_index;
+} push_constants;
+
+#define uniforms draws.data[push_constants.draw_index]
+
+struct MorphOffset {
+    float position_x;
+    float position_y;
+    float position_z;
+    float normal_x;
+    float normal_y;
+    float normal_z;
+};
+
+// The offsets of the vertices of every morph target of the group, see MorphTargets
+layout(set = 1, binding = 0) readonly buffer MorphTargets {
+    MorphOffset offsets[];
+} morph;
+
+void apply_morph_targets(inout vec3 position, inout vec3 normal) {
+    for(int i = 0; i < uniforms.morph_target_count; i++) {
+        float weight = uniforms.morph_weights[i / 4][i % 4];
+        MorphOffset offset = morph.offsets[uniforms.morph_offsets[i / 4][i % 4] + gl_VertexIndex];
+        position += weight * vec3(offset.position_x, offset.position_y, offset.position_z);
+        normal += weight * vec3(offset.normal_x, offset.normal_y, offset.normal_z);
+    }
+}
 
 void main() {
+    vec3 morphed_position = position;
+    vec3 morphed_normal = normal;
+    apply_morph_targets(morphed_position, morphed_normal);
+
     mat4 worldview = uniforms.view * uniforms.world;
-    gl_Position = uniforms.proj * worldview * vec4(position, 1.0);
-    fragment_tex_coord = tex_coord;
+    gl_Position = uniforms.proj * worldview * vec4(morphed_position, 1.0);
+    // Negative texture coordinates mean that the model has no texture, so they are kept as-is
+    fragment_tex_coord = tex_coord.x < 0.0 && tex_coord.y < 0.0
+        ? tex_coord
+        : uniforms.texture_region.xy + tex_coord * uniforms.texture_region.zw;
+
+    fragment_normal = transpose(inverse(mat3(worldview))) * morphed_normal;
+    fragment_view_position = (worldview * vec4(morphed_position, 1.0)).xyz;
 
-    fragment_normal = transpose(inverse(mat3(worldview))) * normal;
+    vec4 world_position = uniforms.world * vec4(morphed_position, 1.0);
+    for(int i = 0; i < 4; i++) {
+        gl_ClipDistance[i] = i < uniforms.clip_plane_count ? dot(uniforms.clip_planes[i], world_position) : 1.0;
+    }
 }
 "
     }
@@ -295,6 +1091,7 @@ pub mod fs {
 
 layout(location = 0) in vec2 fragment_tex_coord;
 layout(location = 1) in vec3 fragment_normal;
+layout(location = 2) in vec3 fragment_view_position;
 
 layout(location = 0) out vec4 f_color;
 
@@ -313,12 +1110,54 @@ struct DirectionalLight {
     float color_specular_b;
 };
 
-layout(set = 0, binding = 1) uniform sampler2D tex;
-layout(set = 0, binding = 0) uniform Data {
+// The depth bias of the pipeline, see DepthBias. The depth buffer has 16 bits, so the constant
+// factor is in steps of 1 / 65535.
+layout(constant_id = 0) const float depth_bias_constant = 0.0;
+layout(constant_id = 1) const float depth_bias_slope = 0.0;
+// The bias only moves fragments towards the camera, so the depth test can still happen before
+// the fragment shader runs
+layout(depth_less) out float gl_FragDepth;
+
+// The environment map, see render/environment.rs. Specular map `i` is prefiltered for a shininess
+// of 256 / 4^i.
+layout(set = 0, binding = 1) uniform samplerCube environment_irradiance;
+layout(set = 0, binding = 2) uniform samplerCube environment_specular0;
+layout(set = 0, binding = 3) uniform samplerCube environment_specular1;
+layout(set = 0, binding = 4) uniform samplerCube environment_specular2;
+layout(set = 0, binding = 5) uniform samplerCube environment_specular3;
+// The directional lights, the amount of lights is in `uniforms.lightCount`
+layout(set = 0, binding = 6) readonly buffer DirectionalLights {
+    DirectionalLight lights[];
+} directional;
+
+// The textures of the group, which only change when the textures of the group change
+layout(set = 2, binding = 0) uniform sampler2D tex;
+// The maps of the PBR material, only the red channel is used
+layout(set = 2, binding = 1) uniform sampler2D metallic_map;
+layout(set = 2, binding = 2) uniform sampler2D roughness_map;
+layout(set = 2, binding = 3) uniform sampler2D ambient_occlusion_map;
+// The texture layers, layer `i` is weighted by channel `i` of the splat map. See
+// ModelBuilder::with_texture_layers.
+layout(set = 2, binding = 4) uniform sampler2D splat_map;
+layout(set = 2, binding = 5) uniform sampler2D texture_layer0;
+layout(set = 2, binding = 6) uniform sampler2D texture_layer1;
+layout(set = 2, binding = 7) uniform sampler2D texture_layer2;
+layout(set = 2, binding = 8) uniform sampler2D texture_layer3;
+// Multiplied with the specular color of the Blinn-Phong material
+layout(set = 2, binding = 9) uniform sampler2D specular_map;
+// The normal map in tangent space, which is only sampled when `uniforms.normal_mapped` is set
+layout(set = 2, binding = 10) uniform sampler2D normal_map;
+struct Data {
     mat4 world;
     mat4 view;
     mat4 proj;
-    DirectionalLight[100] lights;
+    vec4 clip_planes[4];
+    vec4 texture_region;
+    vec4 tint;
+    // The morph targets that are applied, see ModelHandle::set_morph_weight. Every element
+    // holds the start of 4 morph targets in the morph buffer and their weights
+    ivec4 morph_offsets[2];
+    vec4 morph_weights[2];
     int lightCount;
 
     float camera_x;
@@ -335,7 +1174,53 @@ layout(set = 0, binding = 0) uniform Data {
     float material_specular_g;
     float material_specular_b;
     float material_shininess;
-} uniforms;
+    int clip_plane_count;
+    float environment_intensity;
+
+    // PBR_MATERIAL if the group has a PBR material, combined with a flag for every PBR map that
+    // is set, see update_uniform_material
+    int material_pbr;
+    float material_metallic;
+    float material_roughness;
+    float material_ambient_occlusion;
+
+    float fog_r;
+    float fog_g;
+    float fog_b;
+    float fog_density;
+    float wetness;
+    float highlight;
+
+    // The colors of the hemisphere light, multiplied with its intensity
+    float hemisphere_sky_r;
+    float hemisphere_sky_g;
+    float hemisphere_sky_b;
+    float hemisphere_ground_r;
+    float hemisphere_ground_g;
+    float hemisphere_ground_b;
+
+    // The texture layers of the model, see ModelBuilder::with_texture_layers
+    int texture_layer_count;
+    float texture_layer_scale;
+    int morph_target_count;
+    // 1 if the group has a normal map. This also pads the struct to a multiple of 16 bytes, so
+    // the stride of the array matches ty::Data
+    int normal_mapped;
+};
+
+// The data of every group that is drawn by a call to Pipeline::render
+layout(set = 0, binding = 0) readonly buffer Draws {
+    Data data[];
+} draws;
+
+// The custom data of the model, see `ModelData::custom_data`, and the index of the group in
+// `draws`
+layout(push_constant) uniform PushConstants {
+    float custom_data[16];
+    uint draw_index;
+} push_constants;
+
+#define uniforms draws.data[push_constants.draw_index]
 
 vec3 max_member(vec3 lhs, vec3 rhs) {
     return vec3(
@@ -345,15 +1230,6 @@ vec3 max_member(vec3 lhs, vec3 rhs) {
     );
 }
 
-vec4 min_member(vec4 lhs, vec4 rhs) {
-    return vec4(
-        min(lhs.x, rhs.x),
-        min(lhs.y, rhs.y),
-        min(lhs.z, rhs.z),
-        min(lhs.w, rhs.w)
-    );
-}
-
 vec4 CalcDirLight(DirectionalLight light, vec4 tex_color, vec3 normal, vec3 viewDir)
 {
     vec3 direction = vec3(light.direction_x, light.direction_y, light.direction_z);
@@ -363,7 +1239,8 @@ vec4 CalcDirLight(DirectionalLight light, vec4 tex_color, vec3 normal, vec3 view
 
     vec3 material_ambient = vec3(uniforms.material_ambient_r, uniforms.material_ambient_g, uniforms.material_ambient_b);
     vec3 material_diffuse = vec3(uniforms.material_diffuse_r, uniforms.material_diffuse_g, uniforms.material_diffuse_b);
-    vec3 material_specular = vec3(uniforms.material_specular_r, uniforms.material_specular_g, uniforms.material_specular_b);
+    vec3 material_specular = vec3(uniforms.material_specular_r, uniforms.material_specular_g, uniforms.material_specular_b)
+        * texture(specular_map, fragment_tex_coord).rgb;
 
     vec3 lightDir = normalize(-direction);
     // diffuse shading
@@ -375,47 +1252,499 @@ vec4 CalcDirLight(DirectionalLight light, vec4 tex_color, vec3 normal, vec3 view
     ambient  = ambient  * material_ambient;
     diffuse  = diffuse  * diff * material_diffuse;
     specular = specular * spec * material_specular;
-    return tex_color * min_member(vec4(ambient + diffuse + specular, 1.0), vec4(1.0, 1.0, 1.0, 1.0));
+    // The scene is rendered into a HDR buffer, so the light is not clamped here. Colors that are
+    // too bright are handled by the tonemapping pass.
+    return tex_color * vec4(ambient + diffuse + specular, 1.0);
 } 
 
+// The light of the environment map. The normal and the position are in view space, the cubemaps
+// are in world space.
+vec3 environment_light(vec3 diffuse, vec3 specular, float shininess, vec3 normal, vec3 position) {
+    mat3 view_to_world = transpose(mat3(uniforms.view));
+    vec3 world_normal = view_to_world * normal;
+    vec3 reflected = view_to_world * reflect(normalize(position), normal);
 
-void main() {
-    if(fragment_tex_coord.x < 0.0 && fragment_tex_coord.y < 0.0) {
-        f_color = vec4(uniforms.material_ambient_r, uniforms.material_ambient_g, uniforms.material_ambient_b, 1);
+    // Blend between the two specular maps that are closest to the shininess
+    float level = clamp(4.0 - log2(max(shininess, 1.0)) / 2.0, 0.0, 3.0);
+    vec3 reflection =
+        texture(environment_specular0, reflected).rgb * max(1.0 - abs(level - 0.0), 0.0) +
+        texture(environment_specular1, reflected).rgb * max(1.0 - abs(level - 1.0), 0.0) +
+        texture(environment_specular2, reflected).rgb * max(1.0 - abs(level - 2.0), 0.0) +
+        texture(environment_specular3, reflected).rgb * max(1.0 - abs(level - 3.0), 0.0);
+
+    vec3 irradiance = texture(environment_irradiance, world_normal).rgb;
+    return (diffuse * irradiance + specular * reflection) * uniforms.environment_intensity;
+}
+
+// The light of the hemisphere light for a normal in view space. Surfaces that face up get the sky
+// color, surfaces that face down get the ground color.
+vec3 hemisphere_light(vec3 normal) {
+    float up = (transpose(mat3(uniforms.view)) * normal).y;
+    vec3 sky = vec3(uniforms.hemisphere_sky_r, uniforms.hemisphere_sky_g, uniforms.hemisphere_sky_b);
+    vec3 ground = vec3(uniforms.hemisphere_ground_r, uniforms.hemisphere_ground_g, uniforms.hemisphere_ground_b);
+    return mix(ground, sky, up * 0.5 + 0.5);
+}
+
+const float PI = 3.14159265359;
+
+// The Cook-Torrance BRDF, with the GGX normal distribution, the Smith-Schlick geometry function
+// and Schlick's approximation of the fresnel factor. All vectors are in view space.
+vec3 pbr_light(vec3 albedo, float metallic, float roughness, vec3 normal, vec3 view_dir, vec3 light_dir, vec3 radiance) {
+    vec3 halfway = normalize(view_dir + light_dir);
+    float n_dot_l = max(dot(normal, light_dir), 0.0);
+    float n_dot_v = max(dot(normal, view_dir), 0.0);
+    float n_dot_h = max(dot(normal, halfway), 0.0);
+
+    float alpha = roughness * roughness;
+    float alpha2 = alpha * alpha;
+    float denominator = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    float distribution = alpha2 / (PI * denominator * denominator);
+
+    float k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    float geometry = n_dot_v / (n_dot_v * (1.0 - k) + k) * n_dot_l / (n_dot_l * (1.0 - k) + k);
+
+    vec3 f0 = mix(vec3(0.04), albedo, metallic);
+    vec3 fresnel = f0 + (1.0 - f0) * pow(1.0 - max(dot(halfway, view_dir), 0.0), 5.0);
+
+    vec3 specular = distribution * geometry * fresnel / max(4.0 * n_dot_v * n_dot_l, 0.001);
+    vec3 diffuse = (vec3(1.0) - fresnel) * (1.0 - metallic) * albedo / PI;
+    return (diffuse + specular) * radiance * n_dot_l;
+}
+
+// The Blinn-Phong shininess that matches a roughness, which is used to pick the prefiltered
+// environment map
+float roughness_to_shininess(float roughness) {
+    float alpha = max(roughness * roughness, 0.001);
+    return 2.0 / (alpha * alpha) - 2.0;
+}
+
+// The color of the texture of the model. A model with texture layers blends the layers with the
+// weights in the channels of the splat map instead.
+vec4 surface_texture() {
+    if(uniforms.texture_layer_count == 0) {
+        return texture(tex, fragment_tex_coord);
+    }
+    // Channels without a layer are ignored
+    vec4 weights = texture(splat_map, fragment_tex_coord) * vec4(
+        1.0,
+        uniforms.texture_layer_count > 1 ? 1.0 : 0.0,
+        uniforms.texture_layer_count > 2 ? 1.0 : 0.0,
+        uniforms.texture_layer_count > 3 ? 1.0 : 0.0
+    );
+    float total = dot(weights, vec4(1.0));
+    if(total <= 0.0) {
+        // Spots without any weight show the first layer
+        weights = vec4(1.0, 0.0, 0.0, 0.0);
+        total = 1.0;
+    }
+    // The layers repeat over the model, the splat map covers it once
+    vec2 coord = fragment_tex_coord * uniforms.texture_layer_scale;
+    vec4 color =
+        texture(texture_layer0, coord) * weights.r +
+        texture(texture_layer1, coord) * weights.g +
+        texture(texture_layer2, coord) * weights.b +
+        texture(texture_layer3, coord) * weights.a;
+    return color / total;
+}
+
+// The normal of the side of the triangle that is visible, with the normal map of the group applied.
+// The back side of a model is only visible when the model is not culled, see CullMode.
+vec3 surface_normal() {
+    vec3 normal = normalize(fragment_normal);
+    normal = gl_FrontFacing ? normal : -normal;
+    if(uniforms.normal_mapped == 0) {
+        return normal;
+    }
+    // The tangent and bitangent follow from the derivatives of the position and the texture
+    // coordinates, so the vertices do not need to store them
+    vec3 dp1 = dFdx(fragment_view_position);
+    vec3 dp2 = dFdy(fragment_view_position);
+    vec2 duv1 = dFdx(fragment_tex_coord);
+    vec2 duv2 = dFdy(fragment_tex_coord);
+    vec3 dp2_perp = cross(dp2, normal);
+    vec3 dp1_perp = cross(normal, dp1);
+    vec3 tangent = dp2_perp * duv1.x + dp1_perp * duv2.x;
+    vec3 bitangent = dp2_perp * duv1.y + dp1_perp * duv2.y;
+    float length2 = max(dot(tangent, tangent), dot(bitangent, bitangent));
+    if(length2 <= 0.0) {
+        // The texture coordinates do not change over the triangle
+        return normal;
+    }
+    mat3 tangent_to_view = mat3(tangent * inversesqrt(length2), bitangent * inversesqrt(length2), normal);
+    vec3 mapped = texture(normal_map, fragment_tex_coord).xyz * 2.0 - 1.0;
+    return normalize(tangent_to_view * mapped);
+}
+
+vec4 pbr_color(bool textured) {
+    vec3 albedo = vec3(uniforms.material_diffuse_r, uniforms.material_diffuse_g, uniforms.material_diffuse_b);
+    float metallic = uniforms.material_metallic;
+    float roughness = uniforms.material_roughness;
+    float ambient_occlusion = uniforms.material_ambient_occlusion;
+    float alpha = 1.0;
+    if(textured) {
+        vec4 tex_color = surface_texture();
+        albedo *= tex_color.rgb;
+        alpha = tex_color.a;
+    }
+    // The maps don't depend on the base texture, a group can have PBR maps without one
+    if((uniforms.material_pbr & 2) != 0) {
+        metallic *= texture(metallic_map, fragment_tex_coord).r;
+    }
+    if((uniforms.material_pbr & 4) != 0) {
+        roughness *= texture(roughness_map, fragment_tex_coord).r;
+    }
+    if((uniforms.material_pbr & 8) != 0) {
+        ambient_occlusion *= texture(ambient_occlusion_map, fragment_tex_coord).r;
+    }
+    albedo *= uniforms.tint.rgb;
+    alpha *= uniforms.tint.a;
+    // A roughness of 0 would make the highlights infinitely small
+    roughness = clamp(roughness, 0.04, 1.0);
+
+    vec3 normal = surface_normal();
+    vec3 view_dir = normalize(-fragment_view_position);
+    mat3 view = mat3(uniforms.view);
+    vec3 result = vec3(0.0);
+    for(int i = 0; i < uniforms.lightCount; i++) {
+        DirectionalLight light = directional.lights[i];
+        vec3 light_dir = normalize(-(view * vec3(light.direction_x, light.direction_y, light.direction_z)));
+        // Scaled by PI, so a light is as bright on a rough PBR material as on a Blinn-Phong material
+        vec3 radiance = vec3(light.color_diffuse_r, light.color_diffuse_g, light.color_diffuse_b) * PI;
+        result += vec3(light.color_ambient_r, light.color_ambient_g, light.color_ambient_b) * albedo * ambient_occlusion;
+        result += pbr_light(albedo, metallic, roughness, normal, view_dir, light_dir, radiance);
+    }
+    result += hemisphere_light(normal) * albedo * ambient_occlusion;
+
+    if(uniforms.environment_intensity > 0.0) {
+        result += environment_light(
+            albedo * (1.0 - metallic) * ambient_occlusion,
+            mix(vec3(0.04), albedo, metallic) * ambient_occlusion,
+            roughness_to_shininess(roughness),
+            normal,
+            fragment_view_position
+        );
+    }
+    // The model glows in its own color, see `ModelData::highlight`
+    result += albedo * uniforms.highlight;
+    return vec4(result, alpha);
+}
+
+vec4 phong_color(bool textured) {
+    vec4 color;
+    if(!textured) {
+        color = vec4(uniforms.material_ambient_r, uniforms.material_ambient_g, uniforms.material_ambient_b, 1);
     } else {
-        f_color = texture(tex, fragment_tex_coord);
+        color = surface_texture();
     }
+    color *= uniforms.tint;
+    vec3 albedo = color.rgb;
 
     vec3 camera_pos = vec3(uniforms.camera_x, uniforms.camera_y, uniforms.camera_z);
     
     for(int i = 0; i < uniforms.lightCount; i++) {
-        f_color = CalcDirLight(
-            uniforms.lights[i],
-            f_color,
-            fragment_normal,
+        color = CalcDirLight(
+            directional.lights[i],
+            color,
+            surface_normal(),
             camera_pos
         );
     }
+    vec3 material_ambient = vec3(uniforms.material_ambient_r, uniforms.material_ambient_g, uniforms.material_ambient_b);
+    color.rgb += hemisphere_light(surface_normal()) * albedo * material_ambient;
+
+    if(uniforms.environment_intensity > 0.0) {
+        vec3 material_diffuse = vec3(uniforms.material_diffuse_r, uniforms.material_diffuse_g, uniforms.material_diffuse_b);
+        vec3 material_specular = vec3(uniforms.material_specular_r, uniforms.material_specular_g, uniforms.material_specular_b)
+            * texture(specular_map, fragment_tex_coord).rgb;
+        color.rgb += environment_light(
+            albedo * material_diffuse,
+            albedo * material_specular,
+            uniforms.material_shininess,
+            surface_normal(),
+            fragment_view_position
+        );
+    }
+    // The model glows in its own color, see `ModelData::highlight`
+    vec3 material_diffuse = vec3(uniforms.material_diffuse_r, uniforms.material_diffuse_g, uniforms.material_diffuse_b);
+    color.rgb += albedo * material_diffuse * uniforms.highlight;
+    return color;
+}
+
+// Darken the surfaces that are wet from the rain, and give them a glossy highlight. Only surfaces
+// that face up get wet. See render/weather.rs.
+vec3 wet_surface(vec3 color, vec3 normal, vec3 view_dir) {
+    mat3 view = mat3(uniforms.view);
+    float wetness = uniforms.wetness * clamp((transpose(view) * normal).y, 0.0, 1.0);
+    if(wetness <= 0.0) {
+        return color;
+    }
+    vec3 highlight = vec3(0.0);
+    for(int i = 0; i < uniforms.lightCount; i++) {
+        DirectionalLight light = directional.lights[i];
+        vec3 light_dir = normalize(-(view * vec3(light.direction_x, light.direction_y, light.direction_z)));
+        vec3 halfway = normalize(view_dir + light_dir);
+        vec3 diffuse = vec3(light.color_diffuse_r, light.color_diffuse_g, light.color_diffuse_b);
+        highlight += diffuse * pow(max(dot(normal, halfway), 0.0), 128.0);
+    }
+    return color * (1.0 - 0.4 * wetness) + highlight * wetness;
+}
+
+// Fade the color into the fog of the weather, based on the distance to the camera
+vec3 apply_fog(vec3 color, vec3 position) {
+    vec3 fog_color = vec3(uniforms.fog_r, uniforms.fog_g, uniforms.fog_b);
+    return mix(fog_color, color, exp(-uniforms.fog_density * length(position)));
+}
+
+float biased_depth() {
+    float slope = max(abs(dFdx(gl_FragCoord.z)), abs(dFdy(gl_FragCoord.z)));
+    return max(gl_FragCoord.z - depth_bias_constant / 65535.0 - depth_bias_slope * slope, 0.0);
+}
+
+void main() {
+    gl_FragDepth = biased_depth();
+    bool textured = fragment_tex_coord.x >= 0.0 || fragment_tex_coord.y >= 0.0;
+    if(uniforms.material_pbr != 0) {
+        f_color = pbr_color(textured);
+    } else {
+        f_color = phong_color(textured);
+    }
+    vec3 normal = surface_normal();
+    vec3 color = wet_surface(f_color.rgb, normal, normalize(-fragment_view_position));
+    f_color.rgb = apply_fog(color, fragment_view_position);
 }
 "
     }
 }
 
-fn generate_empty_texture(
-    queue: Arc<Queue>,
-    color: [u8; 4],
-) -> (Arc<ImmutableImage<R8G8B8A8Srgb>>, Box<dyn GpuFuture>) {
-    let (img, fut) = ImmutableImage::from_iter(
-        color.iter().cloned(),
-        Dimensions::Dim2d {
-            width: 1,
-            height: 1,
-        },
-        R8G8B8A8Srgb,
-        queue,
-    )
-    // The format, dimensions are valid, and the queue is assumed to be valid, so this should
+pub mod fs_deferred {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "#version 450
+
+layout(location = 0) in vec2 fragment_tex_coord;
+layout(location = 1) in vec3 fragment_normal;
+layout(location = 2) in vec3 fragment_view_position;
+
+layout(location = 0) out vec4 g_ambient;
+layout(location = 1) out vec4 g_diffuse;
+layout(location = 2) out vec4 g_specular;
+layout(location = 3) out vec4 g_normal;
+
+// The depth bias of the pipeline, see DepthBias. The depth buffer has 16 bits, so the constant
+// factor is in steps of 1 / 65535.
+layout(constant_id = 0) const float depth_bias_constant = 0.0;
+layout(constant_id = 1) const float depth_bias_slope = 0.0;
+// The bias only moves fragments towards the camera, so the depth test can still happen before
+// the fragment shader runs
+layout(depth_less) out float gl_FragDepth;
+
+// The textures of the group, see the forward shader
+layout(set = 2, binding = 0) uniform sampler2D tex;
+// The maps of the PBR material, only the red channel is used
+layout(set = 2, binding = 1) uniform sampler2D metallic_map;
+layout(set = 2, binding = 2) uniform sampler2D roughness_map;
+layout(set = 2, binding = 3) uniform sampler2D ambient_occlusion_map;
+// The texture layers, see the forward shader
+layout(set = 2, binding = 4) uniform sampler2D splat_map;
+layout(set = 2, binding = 5) uniform sampler2D texture_layer0;
+layout(set = 2, binding = 6) uniform sampler2D texture_layer1;
+layout(set = 2, binding = 7) uniform sampler2D texture_layer2;
+layout(set = 2, binding = 8) uniform sampler2D texture_layer3;
+// The specular and normal maps, see the forward shader
+layout(set = 2, binding = 9) uniform sampler2D specular_map;
+layout(set = 2, binding = 10) uniform sampler2D normal_map;
+struct Data {
+    mat4 world;
+    mat4 view;
+    mat4 proj;
+    vec4 clip_planes[4];
+    vec4 texture_region;
+    vec4 tint;
+    // The morph targets that are applied, see ModelHandle::set_morph_weight. Every element
+    // holds the start of 4 morph targets in the morph buffer and their weights
+    ivec4 morph_offsets[2];
+    vec4 morph_weights[2];
+    int lightCount;
+
+    float camera_x;
+    float camera_y;
+    float camera_z;
+
+    float material_ambient_r;
+    float material_ambient_g;
+    float material_ambient_b;
+    float material_diffuse_r;
+    float material_diffuse_g;
+    float material_diffuse_b;
+    float material_specular_r;
+    float material_specular_g;
+    float material_specular_b;
+    float material_shininess;
+    int clip_plane_count;
+    float environment_intensity;
+
+    // PBR_MATERIAL if the group has a PBR material, combined with a flag for every PBR map that
+    // is set, see update_uniform_material
+    int material_pbr;
+    float material_metallic;
+    float material_roughness;
+    float material_ambient_occlusion;
+
+    float fog_r;
+    float fog_g;
+    float fog_b;
+    float fog_density;
+    float wetness;
+    float highlight;
+
+    // The colors of the hemisphere light, multiplied with its intensity
+    float hemisphere_sky_r;
+    float hemisphere_sky_g;
+    float hemisphere_sky_b;
+    float hemisphere_ground_r;
+    float hemisphere_ground_g;
+    float hemisphere_ground_b;
+
+    // The texture layers of the model, see ModelBuilder::with_texture_layers
+    int texture_layer_count;
+    float texture_layer_scale;
+    int morph_target_count;
+    // 1 if the group has a normal map. This also pads the struct to a multiple of 16 bytes, so
+    // the stride of the array matches ty::Data
+    int normal_mapped;
+};
+
+// The data of every group that is drawn by a call to Pipeline::render
+layout(set = 0, binding = 0) readonly buffer Draws {
+    Data data[];
+} draws;
+
+// The custom data of the model, see `ModelData::custom_data`, and the index of the group in
+// `draws`
+layout(push_constant) uniform PushConstants {
+    float custom_data[16];
+    uint draw_index;
+} push_constants;
+
+#define uniforms draws.data[push_constants.draw_index]
+
+float biased_depth() {
+    float slope = max(abs(dFdx(gl_FragCoord.z)), abs(dFdy(gl_FragCoord.z)));
+    return max(gl_FragCoord.z - depth_bias_constant / 65535.0 - depth_bias_slope * slope, 0.0);
+}
+
+// The normal of the side of the triangle that is visible, with the normal map of the group applied.
+// This is the same as the forward shader.
+vec3 surface_normal() {
+    vec3 normal = normalize(fragment_normal);
+    normal = gl_FrontFacing ? normal : -normal;
+    if(uniforms.normal_mapped == 0) {
+        return normal;
+    }
+    vec3 dp1 = dFdx(fragment_view_position);
+    vec3 dp2 = dFdy(fragment_view_position);
+    vec2 duv1 = dFdx(fragment_tex_coord);
+    vec2 duv2 = dFdy(fragment_tex_coord);
+    vec3 dp2_perp = cross(dp2, normal);
+    vec3 dp1_perp = cross(normal, dp1);
+    vec3 tangent = dp2_perp * duv1.x + dp1_perp * duv2.x;
+    vec3 bitangent = dp2_perp * duv1.y + dp1_perp * duv2.y;
+    float length2 = max(dot(tangent, tangent), dot(bitangent, bitangent));
+    if(length2 <= 0.0) {
+        return normal;
+    }
+    mat3 tangent_to_view = mat3(tangent * inversesqrt(length2), bitangent * inversesqrt(length2), normal);
+    vec3 mapped = texture(normal_map, fragment_tex_coord).xyz * 2.0 - 1.0;
+    return normalize(tangent_to_view * mapped);
+}
+
+// The color of the texture of the model, this is the same as the forward shader
+vec4 surface_texture() {
+    if(uniforms.texture_layer_count == 0) {
+        return texture(tex, fragment_tex_coord);
+    }
+    // Channels without a layer are ignored
+    vec4 weights = texture(splat_map, fragment_tex_coord) * vec4(
+        1.0,
+        uniforms.texture_layer_count > 1 ? 1.0 : 0.0,
+        uniforms.texture_layer_count > 2 ? 1.0 : 0.0,
+        uniforms.texture_layer_count > 3 ? 1.0 : 0.0
+    );
+    float total = dot(weights, vec4(1.0));
+    if(total <= 0.0) {
+        // Spots without any weight show the first layer
+        weights = vec4(1.0, 0.0, 0.0, 0.0);
+        total = 1.0;
+    }
+    // The layers repeat over the model, the splat map covers it once
+    vec2 coord = fragment_tex_coord * uniforms.texture_layer_scale;
+    vec4 color =
+        texture(texture_layer0, coord) * weights.r +
+        texture(texture_layer1, coord) * weights.g +
+        texture(texture_layer2, coord) * weights.b +
+        texture(texture_layer3, coord) * weights.a;
+    return color / total;
+}
+
+// Write a PBR material into the G-buffer. The lighting pass recognizes these fragments by the alpha
+// channel of the normal, see render/deferred.rs.
+void write_pbr(bool textured) {
+    vec3 albedo = vec3(uniforms.material_diffuse_r, uniforms.material_diffuse_g, uniforms.material_diffuse_b);
+    float metallic = uniforms.material_metallic;
+    float roughness = uniforms.material_roughness;
+    float ambient_occlusion = uniforms.material_ambient_occlusion;
+    if(textured) {
+        albedo *= surface_texture().rgb;
+    }
+    // The maps don't depend on the base texture, a group can have PBR maps without one
+    if((uniforms.material_pbr & 2) != 0) {
+        metallic *= texture(metallic_map, fragment_tex_coord).r;
+    }
+    if((uniforms.material_pbr & 4) != 0) {
+        roughness *= texture(roughness_map, fragment_tex_coord).r;
+    }
+    if((uniforms.material_pbr & 8) != 0) {
+        ambient_occlusion *= texture(ambient_occlusion_map, fragment_tex_coord).r;
+    }
+    albedo *= uniforms.tint.rgb;
+
+    g_ambient = vec4(albedo * ambient_occlusion, metallic);
+    g_diffuse = vec4(albedo, 1.0 + uniforms.highlight);
+    g_specular = vec4(ambient_occlusion, 0.0, 0.0, clamp(roughness, 0.04, 1.0));
+    g_normal = vec4(surface_normal(), 1.0);
+}
+
+void main() {
+    gl_FragDepth = biased_depth();
+    bool textured = fragment_tex_coord.x >= 0.0 || fragment_tex_coord.y >= 0.0;
+    if(uniforms.material_pbr != 0) {
+        write_pbr(textured);
+        return;
+    }
+    vec4 color;
+    if(!textured) {
+        color = vec4(uniforms.material_ambient_r, uniforms.material_ambient_g, uniforms.material_ambient_b, 1);
+    } else {
+        color = surface_texture();
+    }
+    color *= uniforms.tint;
+
+    vec3 material_ambient = vec3(uniforms.material_ambient_r, uniforms.material_ambient_g, uniforms.material_ambient_b);
+    vec3 material_diffuse = vec3(uniforms.material_diffuse_r, uniforms.material_diffuse_g, uniforms.material_diffuse_b);
+    vec3 material_specular = vec3(uniforms.material_specular_r, uniforms.material_specular_g, uniforms.material_specular_b)
+        * texture(specular_map, fragment_tex_coord).rgb;
+
+    g_ambient = vec4(color.rgb * material_ambient, 1.0);
+    g_diffuse = vec4(color.rgb * material_diffuse, 1.0 + uniforms.highlight);
+    g_specular = vec4(color.rgb * material_specular, uniforms.material_shininess);
+    g_normal = vec4(surface_normal(), 0.0);
+}
+"
+    }
+}
+
+fn generate_empty_texture(queue: Arc<Queue>, color: [u8; 4]) -> (Texture, Box<dyn GpuFuture>) {
+    // The texture is a single pixel that is uploaded when the pipeline is created, so this should
     // never fail
-    .unwrap();
-    (img, fut.boxed())
+    texture::from_rgba(queue, 1, 1, color.to_vec(), Format::R8G8B8A8Srgb).unwrap()
 }
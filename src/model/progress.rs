@@ -0,0 +1,100 @@
+/// A stage of loading a model, which is reported to the callback of [ModelBuilder::with_progress](../struct.ModelBuilder.html#method.with_progress).
+///
+/// The stages are reported in the order they are declared in. Stages that do not apply to a model are skipped, e.g. shapes like `GameState::new_cube_model` only report [Upload](#variant.Upload).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadingStage {
+    /// The model file is read and parsed
+    Parse,
+    /// The polygons of the model are split into triangles
+    Triangulate,
+    /// The textures that are embedded in the model file, or that are referenced by its materials, are decoded
+    DecodeTextures,
+    /// The vertices, indices and textures are uploaded to the GPU. This includes loading the textures that are set on the [ModelBuilder](../struct.ModelBuilder.html).
+    Upload,
+}
+
+/// The callback of [ModelBuilder::with_progress](../struct.ModelBuilder.html#method.with_progress)
+pub(crate) type ProgressCallback<'a> = Box<dyn FnMut(LoadingStage, f32) + 'a>;
+
+/// Keeps track of the progress of the current stage of loading a model, and reports it to the callback of the builder.
+pub struct Progress<'a> {
+    callback: Option<ProgressCallback<'a>>,
+    stage: LoadingStage,
+    steps: usize,
+    done: usize,
+    /// The last reported percentage, so the callback is not called twice with the same value
+    last: Option<(LoadingStage, f32)>,
+}
+
+impl<'a> Progress<'a> {
+    pub(crate) fn new(callback: Option<ProgressCallback<'a>>) -> Self {
+        Self {
+            callback,
+            stage: LoadingStage::Parse,
+            steps: 0,
+            done: 0,
+            last: None,
+        }
+    }
+
+    /// Start a new stage that takes the given amount of steps.
+    pub fn start(&mut self, stage: LoadingStage, steps: usize) {
+        self.stage = stage;
+        self.steps = steps;
+        self.done = 0;
+        self.report(0.0);
+    }
+
+    /// Finish a single step of the current stage.
+    pub fn step(&mut self) {
+        self.done = (self.done + 1).min(self.steps);
+        if self.steps == 0 {
+            self.report(100.0);
+        } else {
+            self.report(self.done as f32 * 100.0 / self.steps as f32);
+        }
+    }
+
+    /// Finish the current stage, including the steps that were not needed.
+    pub fn finish(&mut self) {
+        self.done = self.steps;
+        self.report(100.0);
+    }
+
+    fn report(&mut self, percentage: f32) {
+        if let Some(callback) = self.callback.as_mut() {
+            if self.last != Some((self.stage, percentage)) {
+                self.last = Some((self.stage, percentage));
+                callback(self.stage, percentage);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_progress_reports() {
+    let mut reports = Vec::new();
+    let mut progress = Progress::new(Some(Box::new(|stage, percentage| {
+        reports.push((stage, percentage))
+    })));
+    progress.start(LoadingStage::Triangulate, 4);
+    progress.step();
+    progress.step();
+    progress.finish();
+    progress.finish();
+    progress.start(LoadingStage::Upload, 0);
+    progress.finish();
+    drop(progress);
+
+    assert_eq!(
+        vec![
+            (LoadingStage::Triangulate, 0.0),
+            (LoadingStage::Triangulate, 25.0),
+            (LoadingStage::Triangulate, 50.0),
+            (LoadingStage::Triangulate, 100.0),
+            (LoadingStage::Upload, 0.0),
+            (LoadingStage::Upload, 100.0),
+        ],
+        reports
+    );
+}
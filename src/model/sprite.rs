@@ -0,0 +1,132 @@
+use super::TextureRegion;
+use std::time::Duration;
+
+/// Flips through the frames of a sprite sheet. The sprite sheet is a grid of `columns` by `rows` frames of the same size, which are numbered from left to right and from top to bottom.
+///
+/// ```no_run
+/// # use crystal_engine::*;
+/// # use crystal_engine::models::SpriteAnimation;
+/// # let mut state: GameState = unsafe { std::mem::zeroed() };
+/// // A sprite sheet with 8 columns and 4 rows, where the second row is the walk cycle
+/// let mut walk = SpriteAnimation::new(8, 4, 12.0).with_frames(8, 8);
+/// let player = state
+///     .new_rectangle_model()
+///     .with_texture_from_file("assets/player.png")
+///     .build()
+///     .unwrap();
+///
+/// // In Game::update
+/// walk.update(state.time.delta());
+/// player.modify(|data| data.texture_region = walk.region());
+/// ```
+#[derive(Debug, Clone)]
+pub struct SpriteAnimation {
+    columns: u32,
+    rows: u32,
+    first_frame: u32,
+    frame_count: u32,
+    frame_duration: Duration,
+    looping: bool,
+    elapsed: Duration,
+}
+
+impl SpriteAnimation {
+    /// Create an animation that plays every frame of a sprite sheet of `columns` by `rows` frames, at `frames_per_second` frames per second. The animation loops by default.
+    pub fn new(columns: u32, rows: u32, frames_per_second: f32) -> Self {
+        let columns = columns.max(1);
+        let rows = rows.max(1);
+        Self {
+            columns,
+            rows,
+            first_frame: 0,
+            frame_count: columns * rows,
+            frame_duration: Duration::from_secs_f32(1.0 / frames_per_second.max(f32::EPSILON)),
+            looping: true,
+            elapsed: Duration::from_secs(0),
+        }
+    }
+
+    /// Only play `count` frames, starting at frame `first`. This is useful when a sprite sheet contains multiple animations.
+    pub fn with_frames(mut self, first: u32, count: u32) -> Self {
+        let total = self.columns * self.rows;
+        self.first_frame = first.min(total - 1);
+        self.frame_count = count.max(1).min(total - self.first_frame);
+        self
+    }
+
+    /// Set whether the animation starts over after the last frame. An animation that does not loop stays on its last frame.
+    pub fn with_looping(mut self, looping: bool) -> Self {
+        self.looping = looping;
+        self
+    }
+
+    /// Advance the animation by `delta`, which is usually [TimeState::delta](../state/struct.TimeState.html#method.delta).
+    pub fn update(&mut self, delta: Duration) {
+        self.elapsed += delta;
+        let length = self.frame_duration * self.frame_count;
+        if self.looping {
+            // Keep the elapsed time small, so that it does not lose precision in long-running games
+            while self.elapsed >= length {
+                self.elapsed -= length;
+            }
+        } else if self.elapsed > length {
+            self.elapsed = length;
+        }
+    }
+
+    /// Start the animation from its first frame again.
+    pub fn restart(&mut self) {
+        self.elapsed = Duration::from_secs(0);
+    }
+
+    /// Returns `true` if the animation does not loop and has reached its last frame.
+    pub fn is_finished(&self) -> bool {
+        !self.looping && self.elapsed >= self.frame_duration * self.frame_count
+    }
+
+    /// The index of the current frame in the sprite sheet.
+    pub fn frame(&self) -> u32 {
+        let index = (self.elapsed.as_secs_f64() / self.frame_duration.as_secs_f64()) as u32;
+        self.first_frame + index.min(self.frame_count - 1)
+    }
+
+    /// The region of the sprite sheet of the current frame. This can be set as the [ModelData::texture_region](./struct.ModelData.html#structfield.texture_region) of a model.
+    pub fn region(&self) -> TextureRegion {
+        let frame = self.frame();
+        let width = 1.0 / self.columns as f32;
+        let height = 1.0 / self.rows as f32;
+        TextureRegion {
+            x: (frame % self.columns) as f32 * width,
+            y: (frame / self.columns) as f32 * height,
+            width,
+            height,
+        }
+    }
+}
+
+#[test]
+fn test_sprite_animation_frames() {
+    let mut animation = SpriteAnimation::new(4, 2, 10.0).with_frames(2, 3);
+    assert_eq!(2, animation.frame());
+    animation.update(Duration::from_millis(150));
+    assert_eq!(3, animation.frame());
+    animation.update(Duration::from_millis(100));
+    assert_eq!(4, animation.frame());
+    assert_eq!(
+        TextureRegion {
+            x: 0.0,
+            y: 0.5,
+            width: 0.25,
+            height: 0.5
+        },
+        animation.region()
+    );
+    // Loops back to the first frame of the animation
+    animation.update(Duration::from_millis(100));
+    assert_eq!(2, animation.frame());
+
+    let mut animation = animation.with_looping(false);
+    animation.update(Duration::from_secs(10));
+    assert_eq!(4, animation.frame());
+    assert!(animation.is_finished());
+}
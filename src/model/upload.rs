@@ -0,0 +1,122 @@
+use crate::{render::texture, state::UploadError};
+use std::sync::Arc;
+use vulkano::{
+    buffer::{BufferUsage, CpuAccessibleBuffer, DeviceLocalBuffer, TypedBufferAccess},
+    command_buffer::AutoCommandBufferBuilder,
+    device::Queue,
+    sync::{self, GpuFuture},
+};
+
+/// Upload the data of a static mesh into a buffer in device-local memory, through a staging buffer in host-visible memory. Returns `None` when the data is empty, because Vulkan does not allow empty buffers.
+///
+/// The returned future has to be waited on before the buffer is used, like the futures of textures.
+pub(crate) fn upload_buffer<T>(
+    queue: &Arc<Queue>,
+    usage: BufferUsage,
+    data: &[T],
+) -> Result<Option<(Arc<DeviceLocalBuffer<[T]>>, Box<dyn GpuFuture>)>, UploadError>
+where
+    T: Copy + Send + Sync + 'static,
+{
+    if data.is_empty() {
+        return Ok(None);
+    }
+    let device = queue.device();
+    let staging = CpuAccessibleBuffer::from_iter(
+        device.clone(),
+        BufferUsage::transfer_source(),
+        false,
+        data.iter().copied(),
+    )
+    .map_err(UploadError::CouldNotAllocateBuffer)?;
+    // The buffer is a transfer source as well, so it can be read back when the mesh is changed
+    let buffer = DeviceLocalBuffer::array(
+        device.clone(),
+        data.len(),
+        BufferUsage {
+            transfer_source: true,
+            transfer_destination: true,
+            ..usage
+        },
+        device.active_queue_families(),
+    )
+    .map_err(UploadError::CouldNotAllocateBuffer)?;
+
+    let mut builder =
+        AutoCommandBufferBuilder::primary_one_time_submit(device.clone(), queue.family())
+            .map_err(UploadError::CouldNotAllocateCommandBuffer)?;
+    builder
+        .copy_buffer(staging, buffer.clone())
+        .map_err(UploadError::CouldNotRecordCopy)?;
+    let command_buffer = builder
+        .build()
+        .map_err(UploadError::CouldNotBuildCommandBuffer)?;
+    let future = sync::now(device.clone())
+        .then_execute(queue.clone(), command_buffer)
+        .map_err(UploadError::CouldNotCopy)?;
+    Ok(Some((buffer, texture::submit_upload(queue, future)?)))
+}
+
+/// Copies buffers in device-local memory back into host-visible memory, so their contents can be read on the CPU. Every copy is recorded into a single command buffer, which is executed by [Readback::finish].
+pub(crate) struct Readback {
+    queue: Arc<Queue>,
+    builder: AutoCommandBufferBuilder,
+}
+
+impl Readback {
+    pub fn new(queue: &Arc<Queue>) -> Result<Self, UploadError> {
+        let builder = AutoCommandBufferBuilder::primary_one_time_submit(
+            queue.device().clone(),
+            queue.family(),
+        )
+        .map_err(UploadError::CouldNotAllocateCommandBuffer)?;
+        Ok(Self {
+            queue: queue.clone(),
+            builder,
+        })
+    }
+
+    /// Record a copy of the buffer. The returned buffer can be read once [Readback::finish] has returned.
+    pub fn copy<T, B>(
+        &mut self,
+        buffer: &Arc<B>,
+    ) -> Result<Arc<CpuAccessibleBuffer<[T]>>, UploadError>
+    where
+        T: Copy + Send + Sync + 'static,
+        B: TypedBufferAccess<Content = [T]> + Send + Sync + 'static,
+    {
+        // The copy is also a regular vertex or index buffer, so a dynamic mesh can draw it as is
+        let target = unsafe {
+            CpuAccessibleBuffer::uninitialized_array(
+                self.queue.device().clone(),
+                buffer.len(),
+                BufferUsage::all(),
+                false,
+            )
+        }
+        .map_err(UploadError::CouldNotAllocateBuffer)?;
+        self.builder
+            .copy_buffer(buffer.clone(), target.clone())
+            .map_err(UploadError::CouldNotRecordCopy)?;
+        Ok(target)
+    }
+
+    /// Execute the copies after the `uploads` that are still pending, and wait until they are done.
+    pub fn finish(self, uploads: Vec<Box<dyn GpuFuture>>) -> Result<(), UploadError> {
+        let command_buffer = self
+            .builder
+            .build()
+            .map_err(UploadError::CouldNotBuildCommandBuffer)?;
+        let mut future = sync::now(self.queue.device().clone()).boxed();
+        for upload in uploads {
+            future = Box::new(future.join(upload));
+        }
+        future
+            .then_execute(self.queue, command_buffer)
+            .map_err(UploadError::CouldNotCopy)?
+            .then_signal_fence_and_flush()
+            .map_err(UploadError::CouldNotSubmit)?
+            .wait(None)
+            .map_err(UploadError::CouldNotSubmit)
+    }
+}
@@ -0,0 +1,148 @@
+use std::time::SystemTime;
+
+/// A platform that can show what the player is doing, like Steam or Discord rich presence.
+///
+/// The engine does not depend on any platform SDK. Implement this trait for the SDK crate of your platform, and pass it to [Presence::set_backend].
+///
+/// ```no_run
+/// # use crystal_engine::*;
+/// # use crystal_engine::state::{Activity, PresenceBackend};
+/// struct PrintBackend;
+///
+/// impl PresenceBackend for PrintBackend {
+///     fn update(&mut self, activity: Option<&Activity>) {
+///         println!("Presence: {:?}", activity.map(|a| &a.status));
+///     }
+/// }
+///
+/// # let mut state: GameState = unsafe { std::mem::zeroed() };
+/// state.presence.set_backend(PrintBackend);
+/// state.presence.set("In level 3");
+/// ```
+///
+/// [Presence::set_backend]: ./struct.Presence.html#method.set_backend
+pub trait PresenceBackend {
+    /// Show the given activity, or clear the presence if `activity` is `None`.
+    ///
+    /// This is called at most once per frame, after [Game::update](../trait.Game.html#tymethod.update), and only when the activity has changed.
+    fn update(&mut self, activity: Option<&Activity>);
+
+    /// Called every frame. Most SDKs need to run their callbacks regularly, which can be done here.
+    fn tick(&mut self) {}
+}
+
+/// What the player is currently doing, shown by a [PresenceBackend].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Activity {
+    /// The main status, e.g. "In level 3".
+    pub status: String,
+    /// Additional details, e.g. "Fighting the final boss".
+    pub details: Option<String>,
+    /// The time that the player started this activity. This is reset when the status changes, but not when the details change.
+    pub started: SystemTime,
+}
+
+/// The rich presence of the game, like the status that Steam and Discord show to friends of the player. See [PresenceBackend] for how to connect this to a platform.
+///
+/// Without a backend, the presence is only stored.
+///
+/// [PresenceBackend]: ./trait.PresenceBackend.html
+#[derive(Default)]
+pub struct Presence {
+    backend: Option<Box<dyn PresenceBackend>>,
+    activity: Option<Activity>,
+    changed: bool,
+}
+
+impl Presence {
+    /// Set the platform that the presence is shown on. The current activity is sent to the backend on the next frame.
+    pub fn set_backend(&mut self, backend: impl PresenceBackend + 'static) {
+        self.backend = Some(Box::new(backend));
+        self.changed = true;
+    }
+
+    /// Remove the backend that was set with [set_backend](#method.set_backend).
+    pub fn remove_backend(&mut self) {
+        self.backend = None;
+    }
+
+    /// Set the main status of the player, e.g. "In level 3". This clears the [details](#method.set_details) when the status changes.
+    pub fn set(&mut self, status: impl Into<String>) {
+        let status = status.into();
+        if self.activity.as_ref().map(|a| &a.status) == Some(&status) {
+            return;
+        }
+        self.activity = Some(Activity {
+            status,
+            details: None,
+            started: SystemTime::now(),
+        });
+        self.changed = true;
+    }
+
+    /// Set the additional details of the current activity. This does nothing when no status is [set](#method.set).
+    pub fn set_details(&mut self, details: Option<String>) {
+        if let Some(activity) = self.activity.as_mut() {
+            if activity.details != details {
+                activity.details = details;
+                self.changed = true;
+            }
+        }
+    }
+
+    /// Clear the presence.
+    pub fn clear(&mut self) {
+        if self.activity.take().is_some() {
+            self.changed = true;
+        }
+    }
+
+    /// Get the current activity, if a status is [set](#method.set).
+    pub fn activity(&self) -> Option<&Activity> {
+        self.activity.as_ref()
+    }
+
+    /// Send the activity to the backend if it has changed. This is called once per frame.
+    pub(crate) fn update(&mut self) {
+        if let Some(backend) = self.backend.as_mut() {
+            if self.changed {
+                backend.update(self.activity.as_ref());
+                self.changed = false;
+            }
+            backend.tick();
+        }
+    }
+}
+
+#[test]
+fn test_presence_updates_backend_once_per_change() {
+    use std::{cell::RefCell, rc::Rc};
+
+    struct Backend(Rc<RefCell<Vec<Option<String>>>>);
+    impl PresenceBackend for Backend {
+        fn update(&mut self, activity: Option<&Activity>) {
+            self.0.borrow_mut().push(activity.map(|a| a.status.clone()));
+        }
+    }
+
+    let updates = Rc::new(RefCell::new(Vec::new()));
+    let mut presence = Presence::default();
+    presence.set("Main menu");
+    presence.set_backend(Backend(updates.clone()));
+    presence.update();
+    presence.update();
+    presence.set("In level 3");
+    presence.set_details(Some("Fighting the final boss".to_owned()));
+    presence.update();
+    presence.clear();
+    presence.update();
+
+    assert_eq!(
+        vec![
+            Some("Main menu".to_owned()),
+            Some("In level 3".to_owned()),
+            None
+        ],
+        *updates.borrow()
+    );
+}
@@ -0,0 +1,1027 @@
+use crate::{error::GuiError, Font, GameState, GuiElement};
+use parking_lot::Mutex;
+use std::{
+    collections::VecDeque,
+    fmt,
+    fmt::Write,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+const FRAME_HISTORY_COUNT: usize = 60;
+const OVERLAY_REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+const OVERLAY_SIZE: (u32, u32) = (960, 24);
+/// The most scopes that are shown in the profiler overlay, so the text fits on a single line
+const OVERLAY_SCOPE_COUNT: usize = 8;
+/// The size of the text of the performance overlay, which is placed below the profiler overlay
+const PERF_TEXT_SIZE: (u32, u32) = (240, 40);
+/// The size of the frame time graph of the performance overlay, which is placed below its text
+const PERF_GRAPH_SIZE: (u32, u32) = (240, 80);
+/// The frame time at the top of the frame time graph
+const PERF_GRAPH_MAX: Duration = Duration::from_millis(50);
+
+/// The amount of spikes that are remembered by the profiler.
+const SPIKE_HISTORY_COUNT: usize = 10;
+/// The amount of frames that have to be recorded before spikes are detected. The first frames upload a lot of assets, and the average is not reliable yet.
+const SPIKE_MIN_HISTORY: usize = 10;
+/// Frames that are faster than this are never considered a spike, even if they are a lot slower than the average.
+const SPIKE_MIN_DURATION: Duration = Duration::from_millis(5);
+
+/// The timings of a single frame. Each field contains the time that was spent in a single stage of the engine.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct FrameTimings {
+    /// The time spent in [Game::update](../trait.Game.html#tymethod.update).
+    pub update: Duration,
+    /// The time spent handling window events, including [Game::event], [Game::keydown] and [Game::keyup].
+    ///
+    /// [Game::event]: ../trait.Game.html#method.event
+    /// [Game::keydown]: ../trait.Game.html#method.keydown
+    /// [Game::keyup]: ../trait.Game.html#method.keyup
+    pub event_handling: Duration,
+    /// The time spent building the command buffers of the models and the GUI. This does not include the [descriptor_creation](#structfield.descriptor_creation) and the [swapchain_recreation](#structfield.swapchain_recreation).
+    pub command_buffer_building: Duration,
+    /// The time spent loading assets and uploading them to the GPU, e.g. models, textures and environment maps.
+    ///
+    /// Assets that are loaded in [Game::update](../trait.Game.html#tymethod.update) are counted here instead of in [update](#structfield.update).
+    pub asset_upload: Duration,
+    /// The time spent creating the descriptor sets of the models.
+    pub descriptor_creation: Duration,
+    /// The time spent recreating the swapchain and the framebuffers, e.g. after the window was resized.
+    pub swapchain_recreation: Duration,
+    /// The time the CPU was waiting on the GPU to finish rendering an earlier frame. The CPU prepares the next frame while the GPU renders the previous one, so this is only the time that the GPU is behind the CPU, not the time the GPU spent rendering. That is measured by [ProfilerState::gpu_timings].
+    ///
+    /// [ProfilerState::gpu_timings]: ./struct.ProfilerState.html#method.gpu_timings
+    pub gpu_wait: Duration,
+}
+
+impl FrameTimings {
+    /// The total time of the frame. This is the sum of all stages.
+    pub fn total(&self) -> Duration {
+        ProfilerStage::ALL
+            .iter()
+            .map(|&stage| self.get(stage))
+            .sum()
+    }
+
+    /// Get the time that was spent in the given stage.
+    pub fn get(&self, stage: ProfilerStage) -> Duration {
+        match stage {
+            ProfilerStage::Update => self.update,
+            ProfilerStage::EventHandling => self.event_handling,
+            ProfilerStage::CommandBufferBuilding => self.command_buffer_building,
+            ProfilerStage::AssetUpload => self.asset_upload,
+            ProfilerStage::DescriptorCreation => self.descriptor_creation,
+            ProfilerStage::SwapchainRecreation => self.swapchain_recreation,
+            ProfilerStage::GpuWait => self.gpu_wait,
+        }
+    }
+
+    fn get_mut(&mut self, stage: ProfilerStage) -> &mut Duration {
+        match stage {
+            ProfilerStage::Update => &mut self.update,
+            ProfilerStage::EventHandling => &mut self.event_handling,
+            ProfilerStage::CommandBufferBuilding => &mut self.command_buffer_building,
+            ProfilerStage::AssetUpload => &mut self.asset_upload,
+            ProfilerStage::DescriptorCreation => &mut self.descriptor_creation,
+            ProfilerStage::SwapchainRecreation => &mut self.swapchain_recreation,
+            ProfilerStage::GpuWait => &mut self.gpu_wait,
+        }
+    }
+}
+
+/// The time the GPU spent rendering the passes of a frame, see [ProfilerState::gpu_timings].
+///
+/// [ProfilerState::gpu_timings]: ./struct.ProfilerState.html#method.gpu_timings
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct GpuTimings {
+    /// The time spent rendering the scene, including the reflections and the assets that were uploaded before it.
+    pub scene: Duration,
+    /// The time spent applying the [post-processing effects](../render/post_processing/index.html) and the auto exposure.
+    pub post_processing: Duration,
+    /// The time spent tonemapping the frame to the window and drawing the GUI on top of it.
+    pub present: Duration,
+}
+
+impl GpuTimings {
+    /// The total time the GPU spent rendering the frame. This is the sum of all passes.
+    pub fn total(&self) -> Duration {
+        self.scene + self.post_processing + self.present
+    }
+}
+
+/// A frame that took a lot longer than the frames before it, see [ProfilerState::spikes].
+///
+/// [ProfilerState::spikes]: ./struct.ProfilerState.html#method.spikes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameSpike {
+    /// The timings of the frame.
+    pub timings: FrameTimings,
+    /// The average timings of the frames before the spike.
+    pub average: FrameTimings,
+    /// The stage that took the most extra time compared to the average. Use [ProfilerStage::is_game_code] to check if the spike was caused by the game or by the engine.
+    ///
+    /// [ProfilerStage::is_game_code]: ./enum.ProfilerStage.html#method.is_game_code
+    pub cause: ProfilerStage,
+}
+
+impl FrameSpike {
+    /// The extra time that was spent in the [cause](#structfield.cause) of the spike, compared to the average.
+    pub fn extra_time(&self) -> Duration {
+        self.timings
+            .get(self.cause)
+            .checked_sub(self.average.get(self.cause))
+            .unwrap_or_default()
+    }
+}
+
+impl fmt::Display for FrameSpike {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:.2}ms (average {:.2}ms), {} took {:.2}ms longer ({})",
+            ms(self.timings.total()),
+            ms(self.average.total()),
+            self.cause,
+            ms(self.extra_time()),
+            if self.cause.is_game_code() {
+                "game"
+            } else {
+                "engine"
+            }
+        )
+    }
+}
+
+/// Frame timings of the engine. This can be used to find out where the time of a frame is spent.
+///
+/// ```no_run
+/// # use crystal_engine::*;
+/// # let state: GameState = unsafe { std::mem::zeroed() };
+/// let timings = state.profiler.last_frame();
+/// println!("Update took {:?}", timings.update);
+/// ```
+///
+/// The profiler also detects frames that take a lot longer than the average frame, and finds the stage that caused it. These spikes are printed to stderr, shown in the [profiler overlay], and can be read with [spikes](#method.spikes).
+///
+/// The code of the game can be measured with [GameState::profile_scope]. These scopes are shown in the profiler overlay, can be read with [scopes](#method.scopes), and are included in the traces of [start_trace](#method.start_trace).
+///
+/// [profiler overlay]: ../struct.GameState.html#method.show_profiler_overlay
+/// [GameState::profile_scope]: ../struct.GameState.html#method.profile_scope
+pub struct ProfilerState {
+    current: FrameTimings,
+    /// Shared with the [ProfileScope]s, so a scope does not have to borrow the game state
+    recorder: Arc<Mutex<ScopeRecorder>>,
+    history: VecDeque<FrameTimings>,
+    spikes: VecDeque<FrameSpike>,
+    /// The total time of the stages that were recorded with `record_nested`. This is excluded from the stage that was measured around it.
+    nested: Duration,
+    /// A frame is a spike when it takes this many times longer than the average of the last 60 frames. Defaults to `2.0`.
+    pub spike_factor: f32,
+    /// Print every spike to stderr. Defaults to `true` in debug builds and `false` in release builds.
+    pub log_spikes: bool,
+    pub(crate) render_stats: RenderStats,
+    gpu_timings: Option<GpuTimings>,
+}
+
+impl Default for ProfilerState {
+    fn default() -> Self {
+        Self {
+            current: FrameTimings::default(),
+            recorder: Arc::default(),
+            history: VecDeque::with_capacity(FRAME_HISTORY_COUNT),
+            spikes: VecDeque::with_capacity(SPIKE_HISTORY_COUNT),
+            nested: Duration::default(),
+            spike_factor: 2.0,
+            log_spikes: cfg!(debug_assertions),
+            render_stats: RenderStats::default(),
+            gpu_timings: None,
+        }
+    }
+}
+
+/// What was rendered in the last frame, see [GameState::render_stats](../struct.GameState.html#method.render_stats).
+///
+/// The models that are rendered into a [planar reflection](./struct.PlanarReflection.html) are counted as well, so a model that is visible in a mirror counts twice.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RenderStats {
+    /// The amount of draw calls that rendered models. Every group of a model is a separate draw call.
+    pub draw_calls: u32,
+    /// The amount of triangles of the rendered models, at the level of detail that they were rendered with.
+    pub triangles: u32,
+    /// The amount of models that were rendered. Models that are outside of the view of the camera are not rendered.
+    pub visible_models: u32,
+    /// The amount of models in the view of the camera that were not rendered, because they were hidden behind other models. This is always `0` when [occlusion culling](./enum.OcclusionCulling.html) is disabled.
+    pub occluded_models: u32,
+    /// The amount of GUI elements that were rendered. Elements in a container that is outside of the window are not rendered.
+    pub gui_elements: u32,
+}
+
+/// The start of a stage that is measured with [ProfilerState::start] and [ProfilerState::record].
+#[derive(Clone, Copy)]
+pub(crate) struct ProfilerStart {
+    instant: Instant,
+    nested: Duration,
+}
+
+impl ProfilerStart {
+    pub(crate) fn instant(&self) -> Instant {
+        self.instant
+    }
+}
+
+impl ProfilerState {
+    /// Start measuring a stage. Stages that are recorded with `record_nested` before the stage is recorded are not counted in this stage.
+    pub(crate) fn start(&self) -> ProfilerStart {
+        ProfilerStart {
+            instant: Instant::now(),
+            nested: self.nested,
+        }
+    }
+
+    pub(crate) fn record(&mut self, stage: ProfilerStage, start: ProfilerStart) {
+        let nested = self.nested - start.nested;
+        let elapsed = start.instant.elapsed();
+        *self.current.get_mut(stage) += elapsed.checked_sub(nested).unwrap_or_default();
+        self.recorder
+            .lock()
+            .trace_stage(stage, start.instant, elapsed);
+    }
+
+    /// Record a stage that runs inside of another stage, e.g. a model that is loaded in [Game::update](../trait.Game.html#tymethod.update).
+    pub(crate) fn record_nested(&mut self, stage: ProfilerStage, duration: Duration) {
+        *self.current.get_mut(stage) += duration;
+        self.nested += duration;
+        // The stage has just finished, so it started `duration` ago
+        let now = Instant::now();
+        let start = now.checked_sub(duration).unwrap_or(now);
+        self.recorder.lock().trace_stage(stage, start, duration);
+    }
+
+    /// Get the amount of draw calls that rendered models in the last frame. Every group of a model is a separate draw call.
+    pub fn draw_calls(&self) -> u32 {
+        self.render_stats.draw_calls
+    }
+
+    /// Get the amount of models that were rendered in the last frame. Models that are outside of the view of the camera are not rendered.
+    pub fn visible_models(&self) -> u32 {
+        self.render_stats.visible_models
+    }
+
+    /// Get the time the GPU spent rendering the passes of a frame. This is measured with timestamp queries, which are read when the GPU is done with the frame, so the timings are a few frames older than [last_frame](#method.last_frame).
+    ///
+    /// Returns `None` until the first frame was measured, and always on GPUs that do not support timestamps.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let state: GameState = unsafe { std::mem::zeroed() };
+    /// if let Some(timings) = state.profiler.gpu_timings() {
+    ///     println!("The GPU took {:?} to render the scene", timings.scene);
+    /// }
+    /// ```
+    pub fn gpu_timings(&self) -> Option<GpuTimings> {
+        self.gpu_timings
+    }
+
+    pub(crate) fn record_gpu_timings(&mut self, timings: GpuTimings) {
+        self.gpu_timings = Some(timings);
+    }
+
+    /// Start measuring a scope of the game, see [GameState::profile_scope](../struct.GameState.html#method.profile_scope).
+    pub(crate) fn scope(&self, name: &'static str) -> ProfileScope {
+        let (index, frame) = self.recorder.lock().open(name);
+        ProfileScope {
+            recorder: self.recorder.clone(),
+            index,
+            frame,
+        }
+    }
+
+    /// Get the timings of the [profile scopes](../struct.GameState.html#method.profile_scope) of the last frame. Scopes with the same name and the same parent are added together. The scopes are ordered as a tree: every scope is followed by the scopes that are nested in it.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let state: GameState = unsafe { std::mem::zeroed() };
+    /// for scope in state.profiler.scopes() {
+    ///     let indent = "  ".repeat(scope.depth);
+    ///     println!("{}{}: {:?} ({} calls)", indent, scope.name, scope.duration, scope.count);
+    /// }
+    /// ```
+    pub fn scopes(&self) -> Vec<ScopeTimings> {
+        self.recorder.lock().last_frame.clone()
+    }
+
+    /// Start recording a trace of every stage of the engine and every profile scope, until [finish_trace](#method.finish_trace) is called. If a trace is already being recorded, it is restarted.
+    ///
+    /// Traces can become large, so they should only be recorded for a few seconds.
+    pub fn start_trace(&mut self) {
+        self.recorder.lock().trace = Some(Trace {
+            start: Instant::now(),
+            events: Vec::new(),
+        });
+    }
+
+    /// Stop recording the trace that was started with [start_trace](#method.start_trace), and return it in the JSON format of `chrome://tracing`. Returns `None` if no trace was being recorded.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let mut state: GameState = unsafe { std::mem::zeroed() };
+    /// state.profiler.start_trace();
+    /// // ... a few frames later
+    /// if let Some(trace) = state.profiler.finish_trace() {
+    ///     std::fs::write("trace.json", trace).unwrap();
+    /// }
+    /// ```
+    pub fn finish_trace(&mut self) -> Option<String> {
+        self.recorder
+            .lock()
+            .trace
+            .take()
+            .map(|trace| trace.to_json())
+    }
+
+    pub(crate) fn finish_frame(&mut self) {
+        self.recorder.lock().finish_frame();
+        let frame = std::mem::take(&mut self.current);
+        if let Some(spike) = self.detect_spike(&frame) {
+            if self.log_spikes {
+                eprintln!("Frame spike: {}", spike);
+            }
+            if self.spikes.len() == SPIKE_HISTORY_COUNT {
+                self.spikes.pop_front();
+            }
+            self.spikes.push_back(spike);
+        }
+
+        if self.history.len() == FRAME_HISTORY_COUNT {
+            self.history.pop_front();
+        }
+        self.history.push_back(frame);
+    }
+
+    fn detect_spike(&self, frame: &FrameTimings) -> Option<FrameSpike> {
+        if self.history.len() < SPIKE_MIN_HISTORY {
+            return None;
+        }
+        let total = frame.total();
+        let average = self.average();
+        if total < SPIKE_MIN_DURATION
+            || total.as_secs_f32() < average.total().as_secs_f32() * self.spike_factor
+        {
+            return None;
+        }
+        let extra_time = |stage| {
+            frame
+                .get(stage)
+                .checked_sub(average.get(stage))
+                .unwrap_or_default()
+        };
+        let cause = ProfilerStage::ALL
+            .iter()
+            .copied()
+            .max_by_key(|&stage| extra_time(stage))?;
+        Some(FrameSpike {
+            timings: *frame,
+            average,
+            cause,
+        })
+    }
+
+    /// Get the last 10 frames that were a spike, ordered from oldest to newest.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # let state: GameState = unsafe { std::mem::zeroed() };
+    /// for spike in state.profiler.spikes() {
+    ///     if !spike.cause.is_game_code() {
+    ///         println!("The engine caused a spike: {}", spike);
+    ///     }
+    /// }
+    /// ```
+    pub fn spikes(&self) -> impl Iterator<Item = &FrameSpike> {
+        self.spikes.iter()
+    }
+
+    /// Get the last frame that was a spike.
+    pub fn last_spike(&self) -> Option<&FrameSpike> {
+        self.spikes.back()
+    }
+
+    /// Get the timings of the last frame that was rendered. This will be all zeroes if no frames have been rendered yet.
+    pub fn last_frame(&self) -> FrameTimings {
+        self.history.back().copied().unwrap_or_default()
+    }
+
+    /// Get the timings of the last 60 frames, ordered from oldest to newest.
+    pub fn history(&self) -> impl Iterator<Item = &FrameTimings> {
+        self.history.iter()
+    }
+
+    /// Get the average timings of the last 60 frames. This will be all zeroes if no frames have been rendered yet.
+    pub fn average(&self) -> FrameTimings {
+        if self.history.is_empty() {
+            return FrameTimings::default();
+        }
+        let count = self.history.len() as u32;
+        let mut result = FrameTimings::default();
+        for timings in &self.history {
+            for &stage in &ProfilerStage::ALL {
+                *result.get_mut(stage) += timings.get(stage);
+            }
+        }
+        for &stage in &ProfilerStage::ALL {
+            *result.get_mut(stage) /= count;
+        }
+        result
+    }
+}
+
+pub(crate) struct ProfilerOverlay {
+    element: GuiElement,
+    /// The line below the timings, with the profile scopes of the game. This only exists when the game uses profile scopes.
+    scope_element: Option<GuiElement>,
+    font: Font,
+    last_refresh: Instant,
+}
+
+impl ProfilerOverlay {
+    pub fn new(game_state: &mut GameState, font: Font) -> Result<Self, GuiError> {
+        let dimensions = overlay_dimensions(game_state);
+        let text = overlay_text(game_state);
+        let element = game_state
+            .new_gui_element(dimensions)
+            .canvas()
+            .with_background_color([0, 0, 0, 160])
+            .with_text(font.clone(), 16, text, crate::color::WHITE)
+            .build()?;
+        Ok(Self {
+            element,
+            scope_element: None,
+            font,
+            last_refresh: Instant::now(),
+        })
+    }
+
+    pub fn refresh(&mut self, game_state: &mut GameState) {
+        if self.last_refresh.elapsed() < OVERLAY_REFRESH_INTERVAL {
+            return;
+        }
+        self.last_refresh = Instant::now();
+
+        let dimensions = overlay_dimensions(game_state);
+        let text = overlay_text(game_state);
+        if let Err(e) = self.element.update_canvas(game_state, |b| {
+            b.with_dimensions(dimensions).with_text_content(text)
+        }) {
+            eprintln!("Could not update the profiler overlay: {:?}", e);
+        }
+
+        let scopes = game_state.profiler.scopes();
+        if scopes.is_empty() {
+            self.scope_element = None;
+            return;
+        }
+        let (x, y, width, height) = dimensions;
+        let dimensions = (x, y - height as i32, width, height);
+        let text = overlay_scope_text(&scopes);
+        let result = match &mut self.scope_element {
+            Some(element) => element.update_canvas(game_state, |b| {
+                b.with_dimensions(dimensions).with_text_content(text)
+            }),
+            None => game_state
+                .new_gui_element(dimensions)
+                .canvas()
+                .with_background_color([0, 0, 0, 160])
+                .with_text(self.font.clone(), 16, text, crate::color::WHITE)
+                .build()
+                .map(|element| self.scope_element = Some(element)),
+        };
+        if let Err(e) = result {
+            eprintln!("Could not update the profiler overlay: {:?}", e);
+        }
+    }
+}
+
+fn overlay_dimensions(game_state: &GameState) -> (i32, i32, u32, u32) {
+    let (_, height) = game_state.gui.size();
+    (
+        0,
+        height as i32 - OVERLAY_SIZE.1 as i32,
+        OVERLAY_SIZE.0,
+        OVERLAY_SIZE.1,
+    )
+}
+
+fn overlay_text(game_state: &GameState) -> String {
+    let timings = game_state.profiler.average();
+    let mut text = format!(
+        "{:.1} fps | update {:.2}ms | events {:.2}ms | commands {:.2}ms | assets {:.2}ms | gpu wait {:.2}ms",
+        game_state.time.fps(),
+        ms(timings.update),
+        ms(timings.event_handling),
+        ms(timings.command_buffer_building + timings.descriptor_creation + timings.swapchain_recreation),
+        ms(timings.asset_upload),
+        ms(timings.gpu_wait),
+    );
+    if let Some(gpu) = game_state.profiler.gpu_timings() {
+        text += &format!(" | gpu {:.2}ms", ms(gpu.total()));
+    }
+    if let Some(spike) = game_state.profiler.last_spike() {
+        text += &format!(
+            " | spike {:.1}ms: {}",
+            ms(spike.timings.total()),
+            spike.cause
+        );
+    }
+    text
+}
+
+/// The slowest scopes of the last frame, with the names of their parents
+fn overlay_scope_text(scopes: &[ScopeTimings]) -> String {
+    let mut scopes = scopes.iter().collect::<Vec<_>>();
+    scopes.sort_by(|a, b| b.duration.cmp(&a.duration));
+    scopes
+        .iter()
+        .take(OVERLAY_SCOPE_COUNT)
+        .map(|scope| format!("{} {:.2}ms", scope.path, ms(scope.duration)))
+        .collect::<Vec<_>>()
+        .join(" | ")
+}
+
+/// The FPS counter and frame time graph that are shown by [GameState::show_perf_overlay](../struct.GameState.html#method.show_perf_overlay). The elements are created on the first refresh.
+#[derive(Default)]
+pub(crate) struct PerfOverlay {
+    text: Option<GuiElement>,
+    graph: Option<GuiElement>,
+    last_refresh: Option<Instant>,
+}
+
+impl PerfOverlay {
+    pub fn refresh(&mut self, game_state: &mut GameState) {
+        let (text_dimensions, graph_dimensions) = perf_overlay_dimensions(game_state);
+        let image = draw_frame_time_graph(game_state.profiler.history(), PERF_GRAPH_SIZE);
+        let result = match &self.graph {
+            Some(graph) => {
+                graph.modify(|d| d.dimensions = graph_dimensions);
+                graph.write_pixels(game_state, image)
+            }
+            None => game_state
+                .new_gui_element(graph_dimensions)
+                .canvas()
+                .with_double_buffering(true)
+                .build()
+                .and_then(|graph| {
+                    graph.write_pixels(game_state, image)?;
+                    self.graph = Some(graph);
+                    Ok(())
+                }),
+        };
+        if let Err(e) = result {
+            eprintln!("Could not update the performance overlay: {:?}", e);
+        }
+
+        if matches!(self.last_refresh, Some(last) if last.elapsed() < OVERLAY_REFRESH_INTERVAL) {
+            return;
+        }
+        self.last_refresh = Some(Instant::now());
+        let text = perf_overlay_text(game_state);
+        let result = match &mut self.text {
+            Some(element) => element.update_canvas(game_state, |b| {
+                b.with_dimensions(text_dimensions).with_text_content(text)
+            }),
+            None => {
+                // The text is shown once the game sets a font
+                let font = match game_state.gui.theme.font.clone() {
+                    Some(font) => font,
+                    None => return,
+                };
+                game_state
+                    .new_gui_element(text_dimensions)
+                    .canvas()
+                    .with_background_color([0, 0, 0, 160])
+                    .with_text(font, 16, text, crate::color::WHITE)
+                    .build()
+                    .map(|element| self.text = Some(element))
+            }
+        };
+        if let Err(e) = result {
+            eprintln!("Could not update the performance overlay: {:?}", e);
+        }
+    }
+}
+
+/// The dimensions of the text and the graph of the performance overlay, in the top-left corner below the profiler overlay
+fn perf_overlay_dimensions(game_state: &GameState) -> ((i32, i32, u32, u32), (i32, i32, u32, u32)) {
+    let (_, height) = game_state.gui.size();
+    let text_y = height as i32 - OVERLAY_SIZE.1 as i32 - PERF_TEXT_SIZE.1 as i32;
+    (
+        (0, text_y, PERF_TEXT_SIZE.0, PERF_TEXT_SIZE.1),
+        (
+            0,
+            text_y - PERF_GRAPH_SIZE.1 as i32,
+            PERF_GRAPH_SIZE.0,
+            PERF_GRAPH_SIZE.1,
+        ),
+    )
+}
+
+fn perf_overlay_text(game_state: &GameState) -> String {
+    let profiler = &game_state.profiler;
+    format!(
+        "{:.1} fps | {:.2}ms\n{} draw calls | {}/{} models",
+        game_state.time.fps(),
+        ms(profiler.average().total()),
+        profiler.draw_calls(),
+        profiler.visible_models(),
+        game_state.model_handles.len(),
+    )
+}
+
+/// Draw a bar for the total time of every frame, with the newest frame on the right. Frames that are slower than 60 fps are yellow, frames that are slower than 30 fps are red.
+fn draw_frame_time_graph<'a>(
+    history: impl Iterator<Item = &'a FrameTimings>,
+    size: (u32, u32),
+) -> image::RgbaImage {
+    let mut image = image::RgbaImage::from_pixel(size.0, size.1, image::Rgba([0, 0, 0, 160]));
+    let bar_width = (size.0 / FRAME_HISTORY_COUNT as u32).max(1);
+    let to_height = |duration: Duration| {
+        let factor = duration.as_secs_f32() / PERF_GRAPH_MAX.as_secs_f32();
+        ((factor * size.1 as f32).round() as u32).min(size.1)
+    };
+    let history = history.collect::<Vec<_>>();
+    let offset = FRAME_HISTORY_COUNT.saturating_sub(history.len()) as u32;
+    for (index, timings) in history.iter().enumerate() {
+        let total = timings.total();
+        let color = if total > Duration::from_micros(33_333) {
+            [230, 60, 60, 255]
+        } else if total > Duration::from_micros(16_667) {
+            [230, 200, 60, 255]
+        } else {
+            [60, 200, 90, 255]
+        };
+        let x_start = (offset + index as u32) * bar_width;
+        for x in x_start..(x_start + bar_width).min(size.0) {
+            // The first row of the image is the top of the graph
+            for y in (size.1 - to_height(total))..size.1 {
+                image.put_pixel(x, y, image::Rgba(color));
+            }
+        }
+    }
+    for target in &[Duration::from_micros(16_667), Duration::from_micros(33_333)] {
+        let height = to_height(*target);
+        if height > 0 {
+            for x in 0..size.0 {
+                image.put_pixel(x, size.1 - height, image::Rgba([255, 255, 255, 120]));
+            }
+        }
+    }
+    image
+}
+
+fn ms(duration: Duration) -> f32 {
+    duration.as_secs_f32() * 1000.0
+}
+
+/// A scope of the game that is measured until it is dropped. This is created by [GameState::profile_scope].
+///
+/// [GameState::profile_scope]: ../struct.GameState.html#method.profile_scope
+#[must_use = "the scope is measured until it is dropped"]
+pub struct ProfileScope {
+    recorder: Arc<Mutex<ScopeRecorder>>,
+    index: usize,
+    /// The frame that the scope was opened in. Scopes that are still open at the end of a frame are closed by the profiler.
+    frame: u64,
+}
+
+impl Drop for ProfileScope {
+    fn drop(&mut self) {
+        self.recorder.lock().close(self.index, self.frame);
+    }
+}
+
+/// The time spent in a [profile scope](../struct.GameState.html#method.profile_scope) during a single frame, see [ProfilerState::scopes].
+///
+/// [ProfilerState::scopes]: ./struct.ProfilerState.html#method.scopes
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScopeTimings {
+    /// The name of the scope.
+    pub name: &'static str,
+    /// The names of the parents of the scope and the name of the scope, separated by `/`, e.g. `"ai/pathfinding"`.
+    pub path: String,
+    /// The amount of scopes this scope is nested in. Scopes that are not nested have a depth of `0`.
+    pub depth: usize,
+    /// The total time spent in the scope.
+    pub duration: Duration,
+    /// The amount of times the scope was entered.
+    pub count: u32,
+}
+
+/// Records the profile scopes of the current frame, and the trace if one is being recorded.
+#[derive(Default)]
+struct ScopeRecorder {
+    frame: u64,
+    spans: Vec<ScopeSpan>,
+    /// The indices of the spans that are open, from the outermost to the innermost
+    open: Vec<usize>,
+    last_frame: Vec<ScopeTimings>,
+    trace: Option<Trace>,
+}
+
+struct ScopeSpan {
+    name: &'static str,
+    parent: Option<usize>,
+    start: Instant,
+    duration: Option<Duration>,
+}
+
+impl ScopeRecorder {
+    fn open(&mut self, name: &'static str) -> (usize, u64) {
+        let index = self.spans.len();
+        self.spans.push(ScopeSpan {
+            name,
+            parent: self.open.last().copied(),
+            start: Instant::now(),
+            duration: None,
+        });
+        self.open.push(index);
+        (index, self.frame)
+    }
+
+    fn close(&mut self, index: usize, frame: u64) {
+        if frame != self.frame {
+            return;
+        }
+        // Scopes that are dropped out of order also close the scopes that are nested in them
+        if let Some(position) = self.open.iter().rposition(|&open| open == index) {
+            self.open.truncate(position);
+        }
+        let span = &mut self.spans[index];
+        if span.duration.is_none() {
+            let duration = span.start.elapsed();
+            span.duration = Some(duration);
+            if let Some(trace) = &mut self.trace {
+                trace.add(span.name, "game", span.start, duration);
+            }
+        }
+    }
+
+    fn trace_stage(&mut self, stage: ProfilerStage, start: Instant, duration: Duration) {
+        if let Some(trace) = &mut self.trace {
+            trace.add(&stage.to_string(), "engine", start, duration);
+        }
+    }
+
+    fn finish_frame(&mut self) {
+        for index in std::mem::take(&mut self.open) {
+            self.close(index, self.frame);
+        }
+        self.last_frame = aggregate_spans(&self.spans);
+        self.spans.clear();
+        self.frame += 1;
+    }
+}
+
+/// Add the spans with the same name and parent together, and order them as a tree.
+fn aggregate_spans(spans: &[ScopeSpan]) -> Vec<ScopeTimings> {
+    // The timings with the index of their parent
+    let mut timings: Vec<(Option<usize>, ScopeTimings)> = Vec::new();
+    // The index in `timings` of every span
+    let mut span_timings = Vec::with_capacity(spans.len());
+    for span in spans {
+        let parent = span.parent.map(|parent| span_timings[parent]);
+        let index = match timings
+            .iter()
+            .position(|(p, t)| t.name == span.name && *p == parent)
+        {
+            Some(index) => index,
+            None => {
+                let (path, depth) = match parent {
+                    Some(parent) => (
+                        format!("{}/{}", timings[parent].1.path, span.name),
+                        timings[parent].1.depth + 1,
+                    ),
+                    None => (span.name.to_owned(), 0),
+                };
+                let timing = ScopeTimings {
+                    name: span.name,
+                    path,
+                    depth,
+                    duration: Duration::default(),
+                    count: 0,
+                };
+                timings.push((parent, timing));
+                timings.len() - 1
+            }
+        };
+        // Every span is closed at the end of the frame
+        timings[index].1.duration += span.duration.unwrap_or_default();
+        timings[index].1.count += 1;
+        span_timings.push(index);
+    }
+
+    // Scopes that were first entered later in the frame can be children of earlier scopes, so
+    // the tree is walked to put every scope after its parent
+    fn visit(
+        timings: &[(Option<usize>, ScopeTimings)],
+        parent: Option<usize>,
+        result: &mut Vec<ScopeTimings>,
+    ) {
+        for (index, (p, timing)) in timings.iter().enumerate() {
+            if *p == parent {
+                result.push(timing.clone());
+                visit(timings, Some(index), result);
+            }
+        }
+    }
+    let mut result = Vec::with_capacity(timings.len());
+    visit(&timings, None, &mut result);
+    result
+}
+
+/// A trace in the format of `chrome://tracing`, see [ProfilerState::start_trace].
+struct Trace {
+    start: Instant,
+    events: Vec<String>,
+}
+
+impl Trace {
+    fn add(&mut self, name: &str, category: &str, start: Instant, duration: Duration) {
+        let micros = |duration: Duration| duration.as_secs_f64() * 1_000_000.0;
+        let start = start.checked_duration_since(self.start).unwrap_or_default();
+        let mut escaped = String::with_capacity(name.len());
+        for c in name.chars() {
+            match c {
+                '"' => escaped += "\\\"",
+                '\\' => escaped += "\\\\",
+                c if c.is_control() => {
+                    // Writing to a String can not fail
+                    let _ = write!(escaped, "\\u{:04x}", c as u32);
+                }
+                c => escaped.push(c),
+            }
+        }
+        self.events.push(format!(
+            "{{\"name\":\"{}\",\"cat\":\"{}\",\"ph\":\"X\",\"ts\":{:.3},\"dur\":{:.3},\"pid\":1,\"tid\":1}}",
+            escaped,
+            category,
+            micros(start),
+            micros(duration)
+        ));
+    }
+
+    fn to_json(&self) -> String {
+        format!("{{\"traceEvents\":[{}]}}", self.events.join(","))
+    }
+}
+
+/// A stage of a frame that is measured by the [ProfilerState].
+///
+/// [ProfilerState]: ./struct.ProfilerState.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProfilerStage {
+    /// [Game::update](../trait.Game.html#tymethod.update) and [Game::fixed_update](../trait.Game.html#method.fixed_update)
+    Update,
+    /// The window events, including the event handlers of the game
+    EventHandling,
+    /// Building the command buffers of the models and the GUI
+    CommandBufferBuilding,
+    /// Loading assets and uploading them to the GPU
+    AssetUpload,
+    /// Creating the descriptor sets of the models
+    DescriptorCreation,
+    /// Recreating the swapchain and the framebuffers
+    SwapchainRecreation,
+    /// Waiting on the GPU to finish rendering an earlier frame
+    GpuWait,
+}
+
+impl ProfilerStage {
+    /// All stages, in the order in which they are shown by the profiler.
+    pub const ALL: [ProfilerStage; 7] = [
+        ProfilerStage::Update,
+        ProfilerStage::EventHandling,
+        ProfilerStage::CommandBufferBuilding,
+        ProfilerStage::AssetUpload,
+        ProfilerStage::DescriptorCreation,
+        ProfilerStage::SwapchainRecreation,
+        ProfilerStage::GpuWait,
+    ];
+
+    /// Returns `true` if the time of this stage is mostly spent in the code of the game, instead of in the engine.
+    pub fn is_game_code(self) -> bool {
+        matches!(self, ProfilerStage::Update | ProfilerStage::EventHandling)
+    }
+}
+
+impl fmt::Display for ProfilerStage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ProfilerStage::Update => "update",
+            ProfilerStage::EventHandling => "event handling",
+            ProfilerStage::CommandBufferBuilding => "command buffer building",
+            ProfilerStage::AssetUpload => "asset upload",
+            ProfilerStage::DescriptorCreation => "descriptor creation",
+            ProfilerStage::SwapchainRecreation => "swapchain recreation",
+            ProfilerStage::GpuWait => "gpu wait",
+        })
+    }
+}
+
+#[test]
+fn test_profiler_history_never_resize() {
+    let mut state = ProfilerState::default();
+    let cap = state.history.capacity();
+    for _ in 0..cap * 2 {
+        state.record_nested(ProfilerStage::Update, Duration::from_millis(2));
+        state.finish_frame();
+        assert_eq!(cap, state.history.capacity());
+    }
+    assert_eq!(Duration::from_millis(2), state.average().update);
+}
+
+#[test]
+fn test_profiler_spike_attribution() {
+    let mut state = ProfilerState::default();
+    state.log_spikes = false;
+    for _ in 0..SPIKE_MIN_HISTORY {
+        state.record_nested(ProfilerStage::Update, Duration::from_millis(4));
+        state.record_nested(ProfilerStage::GpuWait, Duration::from_millis(4));
+        state.finish_frame();
+    }
+    assert!(state.last_spike().is_none());
+
+    state.record_nested(ProfilerStage::Update, Duration::from_millis(5));
+    state.record_nested(ProfilerStage::AssetUpload, Duration::from_millis(30));
+    state.record_nested(ProfilerStage::GpuWait, Duration::from_millis(4));
+    state.finish_frame();
+    let spike = state.last_spike().unwrap();
+    assert_eq!(ProfilerStage::AssetUpload, spike.cause);
+    assert!(!spike.cause.is_game_code());
+    assert_eq!(Duration::from_millis(30), spike.extra_time());
+}
+
+#[test]
+fn test_profiler_scopes() {
+    let mut state = ProfilerState::default();
+    state.start_trace();
+    {
+        let _ai = state.scope("ai");
+        for _ in 0..3 {
+            let _path = state.scope("pathfinding");
+        }
+    }
+    let _physics = state.scope("physics");
+    // The scopes are only aggregated at the end of the frame
+    assert!(state.scopes().is_empty());
+    state.finish_frame();
+    drop(_physics);
+
+    let scopes = state.scopes();
+    let paths = scopes.iter().map(|s| s.path.as_str()).collect::<Vec<_>>();
+    assert_eq!(vec!["ai", "ai/pathfinding", "physics"], paths);
+    assert_eq!(3, scopes[1].count);
+    assert_eq!(1, scopes[1].depth);
+    assert!(scopes[0].duration >= scopes[1].duration);
+
+    let trace = state.finish_trace().unwrap();
+    assert!(trace.starts_with("{\"traceEvents\":[{\"name\":\"pathfinding\",\"cat\":\"game\""));
+    assert_eq!(5, trace.matches("\"ph\":\"X\"").count());
+    assert!(state.finish_trace().is_none());
+}
+
+#[test]
+fn test_frame_time_graph() {
+    let frame = |millis| FrameTimings {
+        gpu_wait: Duration::from_millis(millis),
+        ..FrameTimings::default()
+    };
+    let history = [frame(10), frame(25), frame(100)];
+    let image = draw_frame_time_graph(history.iter(), (240, 100));
+    let bar_width = 240 / FRAME_HISTORY_COUNT as u32;
+    let bar = |index: usize| 240 - (3 - index as u32) * bar_width;
+
+    // The newest frame is on the right, and is clamped to the top of the graph
+    assert_eq!(&[230, 60, 60, 255], &image.get_pixel(bar(2), 0).0);
+    // A frame of 10ms is a fifth of the height of the graph
+    assert_eq!(&[60, 200, 90, 255], &image.get_pixel(bar(0), 80).0);
+    assert_eq!(&[0, 0, 0, 160], &image.get_pixel(bar(0), 79).0);
+    assert_eq!(&[230, 200, 60, 255], &image.get_pixel(bar(1), 99).0);
+    assert_eq!(&[0, 0, 0, 160], &image.get_pixel(0, 99).0);
+}
+
+#[test]
+fn test_gpu_timings() {
+    let mut state = ProfilerState::default();
+    assert_eq!(None, state.gpu_timings());
+
+    state.record_gpu_timings(GpuTimings {
+        scene: Duration::from_millis(4),
+        post_processing: Duration::from_millis(1),
+        present: Duration::from_millis(2),
+    });
+    let timings = state.gpu_timings().unwrap();
+    assert_eq!(Duration::from_millis(7), timings.total());
+    // The GPU timings are measured separately, so they are not part of the CPU stages
+    state.finish_frame();
+    assert_eq!(Duration::default(), state.last_frame().total());
+    assert_eq!(Some(timings), state.gpu_timings());
+}
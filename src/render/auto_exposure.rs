@@ -0,0 +1,300 @@
+use super::post_processing::AutoExposure;
+use std::{sync::Arc, time::Duration};
+use vulkano::{
+    command_buffer::AutoCommandBufferBuilder,
+    descriptor::descriptor_set::{PersistentDescriptorSet, StdDescriptorPool},
+    device::Queue,
+    format::{ClearValue, R32Sfloat, R32Uint},
+    image::{attachment::AttachmentImage, Dimensions, ImageUsage, StorageImage},
+    pipeline::{ComputePipeline, ComputePipelineAbstract},
+    sampler::Sampler,
+};
+
+/// The amount of bins in the luminance histogram. This is also the size of the work group of the adaptation shader.
+const HISTOGRAM_BINS: u32 = 256;
+/// The size of the work groups of the histogram shader, in pixels
+const HISTOGRAM_GROUP_SIZE: u32 = 16;
+/// The lowest luminance that is counted in the histogram, as `log2(luminance)`
+const MIN_LOG_LUMINANCE: f32 = -10.0;
+/// The range of luminances that is counted in the histogram, as `log2(luminance)`
+const LOG_LUMINANCE_RANGE: f32 = 22.0;
+
+/// Measures the brightness of the HDR image of the scene, and adapts the exposure that the tonemap pipeline uses.
+///
+/// This runs between the scene render pass and the present render pass, so the exposure is adapted before the frame is tonemapped. The exposure never leaves the GPU.
+pub struct Pipeline {
+    histogram_pipeline: Arc<dyn ComputePipelineAbstract + Send + Sync>,
+    adapt_pipeline: Arc<dyn ComputePipelineAbstract + Send + Sync>,
+    sampler: Arc<Sampler>,
+    /// The amount of pixels in each luminance bin. This is reset to zero by the adaptation shader.
+    histogram: Arc<StorageImage<R32Uint>>,
+    /// A single pixel with the current exposure, which is read by the tonemap pipeline
+    exposure: Arc<StorageImage<R32Sfloat>>,
+    /// The images have to be cleared before they are used, and when the auto exposure is turned back on
+    needs_clear: bool,
+}
+
+impl Pipeline {
+    pub fn create(queue: Arc<Queue>) -> Self {
+        let device = queue.device().clone();
+        // These should never fail, as the shaders are hard-coded and the device is assumed to be
+        // valid.
+        let histogram_cs =
+            histogram_cs::Shader::load(device.clone()).expect("failed to create shader module");
+        let adapt_cs =
+            adapt_cs::Shader::load(device.clone()).expect("failed to create shader module");
+
+        let histogram_pipeline = Arc::new(
+            ComputePipeline::new(device.clone(), &histogram_cs.main_entry_point(), &())
+                // This should never fail because all arguments are hard-coded
+                .unwrap(),
+        );
+        let adapt_pipeline = Arc::new(
+            ComputePipeline::new(device.clone(), &adapt_cs.main_entry_point(), &())
+                // This should never fail because all arguments are hard-coded
+                .unwrap(),
+        );
+
+        let usage = ImageUsage {
+            storage: true,
+            transfer_destination: true,
+            ..ImageUsage::none()
+        };
+        let histogram = StorageImage::with_usage(
+            device.clone(),
+            Dimensions::Dim2d {
+                width: HISTOGRAM_BINS,
+                height: 1,
+            },
+            R32Uint,
+            usage,
+            Some(queue.family()),
+        )
+        .unwrap(); // This can only fail if we run out of memory
+        let exposure = StorageImage::with_usage(
+            device.clone(),
+            Dimensions::Dim2d {
+                width: 1,
+                height: 1,
+            },
+            R32Sfloat,
+            usage,
+            Some(queue.family()),
+        )
+        .unwrap(); // This can only fail if we run out of memory
+
+        Self {
+            histogram_pipeline,
+            adapt_pipeline,
+            sampler: Sampler::simple_repeat_linear_no_mipmap(device),
+            histogram,
+            exposure,
+            needs_clear: true,
+        }
+    }
+
+    /// The image that contains the current exposure. This should only be read when the auto exposure is enabled.
+    pub fn exposure(&self) -> Arc<StorageImage<R32Sfloat>> {
+        self.exposure.clone()
+    }
+
+    /// Clear the histogram and the exposure if needed. This has to be called before the scene render pass starts.
+    pub fn prepare(
+        &mut self,
+        settings: Option<&AutoExposure>,
+        command_buffer_builder: &mut AutoCommandBufferBuilder,
+    ) {
+        if settings.is_none() {
+            // Start from scratch when the auto exposure is turned on again
+            self.needs_clear = true;
+            return;
+        }
+        if !self.needs_clear {
+            return;
+        }
+        command_buffer_builder
+            .clear_color_image(self.histogram.clone(), ClearValue::Uint([0; 4]))
+            // The image is created with transfer_destination so this should never fail
+            .unwrap()
+            // An exposure of 0 makes the adaptation shader start at the target exposure
+            .clear_color_image(self.exposure.clone(), ClearValue::Float([0.0; 4]))
+            // The image is created with transfer_destination so this should never fail
+            .unwrap();
+        self.needs_clear = false;
+    }
+
+    /// Measure the brightness of the HDR image, and adapt the exposure. This has to be called after the scene render pass has ended.
+    pub fn adapt(
+        &self,
+        settings: Option<&AutoExposure>,
+        delta: Duration,
+        hdr_buffer: Arc<AttachmentImage>,
+        command_buffer_builder: &mut AutoCommandBufferBuilder,
+        descriptor_pool: &mut Arc<StdDescriptorPool>,
+    ) {
+        let settings = match settings {
+            Some(settings) => settings,
+            None => return,
+        };
+        let [width, height] = hdr_buffer.dimensions();
+
+        // The pipeline and the layout index are hard-coded so this is assumed to never fail
+        let layout = self.histogram_pipeline.descriptor_set_layout(0).unwrap();
+        let set = Arc::new(
+            PersistentDescriptorSet::start(layout.clone())
+                .add_sampled_image(hdr_buffer, self.sampler.clone())
+                // The HDR buffer is created as a sampled image so this should never fail
+                .unwrap()
+                .add_image(self.histogram.clone())
+                // The histogram is created as a storage image so this should never fail
+                .unwrap()
+                .build_with_pool(descriptor_pool)
+                // The pool is assumed to be valid so this should never fail
+                .unwrap(),
+        );
+        let push_constants = histogram_cs::ty::PushConstants {
+            min_log_luminance: MIN_LOG_LUMINANCE,
+            inverse_log_luminance_range: 1.0 / LOG_LUMINANCE_RANGE,
+        };
+        command_buffer_builder
+            .dispatch(
+                [
+                    (width + HISTOGRAM_GROUP_SIZE - 1) / HISTOGRAM_GROUP_SIZE,
+                    (height + HISTOGRAM_GROUP_SIZE - 1) / HISTOGRAM_GROUP_SIZE,
+                    1,
+                ],
+                self.histogram_pipeline.clone(),
+                set,
+                push_constants,
+            )
+            // the builder and arguments are assumed to be valid so this should never fail
+            .unwrap();
+
+        // The pipeline and the layout index are hard-coded so this is assumed to never fail
+        let layout = self.adapt_pipeline.descriptor_set_layout(0).unwrap();
+        let set = Arc::new(
+            PersistentDescriptorSet::start(layout.clone())
+                .add_image(self.histogram.clone())
+                // The histogram is created as a storage image so this should never fail
+                .unwrap()
+                .add_image(self.exposure.clone())
+                // The exposure is created as a storage image so this should never fail
+                .unwrap()
+                .build_with_pool(descriptor_pool)
+                // The pool is assumed to be valid so this should never fail
+                .unwrap(),
+        );
+        let push_constants = adapt_cs::ty::PushConstants {
+            min_log_luminance: MIN_LOG_LUMINANCE,
+            log_luminance_range: LOG_LUMINANCE_RANGE,
+            pixel_count: (width * height) as f32,
+            adaptation: settings.adaptation(delta),
+            key: settings.key,
+            min_exposure: settings.min_exposure,
+            max_exposure: settings.max_exposure,
+        };
+        command_buffer_builder
+            .dispatch([1, 1, 1], self.adapt_pipeline.clone(), set, push_constants)
+            // the builder and arguments are assumed to be valid so this should never fail
+            .unwrap();
+    }
+}
+
+mod histogram_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src: "#version 450
+
+layout(local_size_x = 16, local_size_y = 16) in;
+
+layout(set = 0, binding = 0) uniform sampler2D hdr;
+layout(set = 0, binding = 1, r32ui) uniform uimage2D histogram;
+
+layout(push_constant) uniform PushConstants {
+    float min_log_luminance;
+    float inverse_log_luminance_range;
+} push_constants;
+
+shared uint bins[256];
+
+void main() {
+    bins[gl_LocalInvocationIndex] = 0;
+    barrier();
+
+    ivec2 size = textureSize(hdr, 0);
+    ivec2 coord = ivec2(gl_GlobalInvocationID.xy);
+    if (coord.x < size.x && coord.y < size.y) {
+        vec3 color = texelFetch(hdr, coord, 0).rgb;
+        float luminance = dot(color, vec3(0.2126, 0.7152, 0.0722));
+
+        // Black pixels go into the first bin, which is not counted in the average
+        uint bin = 0;
+        if (luminance > 0.0001) {
+            float position = (log2(luminance) - push_constants.min_log_luminance) * push_constants.inverse_log_luminance_range;
+            bin = uint(clamp(position, 0.0, 1.0) * 254.0 + 1.0);
+        }
+        atomicAdd(bins[bin], 1);
+    }
+    barrier();
+
+    imageAtomicAdd(histogram, ivec2(gl_LocalInvocationIndex, 0), bins[gl_LocalInvocationIndex]);
+}
+"
+    }
+}
+
+mod adapt_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        src: "#version 450
+
+layout(local_size_x = 256) in;
+
+layout(set = 0, binding = 0, r32ui) uniform uimage2D histogram;
+layout(set = 0, binding = 1, r32f) uniform image2D exposure;
+
+layout(push_constant) uniform PushConstants {
+    float min_log_luminance;
+    float log_luminance_range;
+    float pixel_count;
+    float adaptation;
+    float key;
+    float min_exposure;
+    float max_exposure;
+} push_constants;
+
+shared float weighted_bins[256];
+
+void main() {
+    uint index = gl_LocalInvocationIndex;
+    uint count = imageLoad(histogram, ivec2(index, 0)).r;
+    weighted_bins[index] = float(count) * float(index);
+    // Reset the histogram for the next frame
+    imageStore(histogram, ivec2(index, 0), uvec4(0));
+    barrier();
+
+    for (uint stride = 128; stride > 0; stride >>= 1) {
+        if (index < stride) {
+            weighted_bins[index] += weighted_bins[index + stride];
+        }
+        barrier();
+    }
+
+    if (index == 0) {
+        // `count` is the amount of black pixels here, which are not part of the average
+        float lit_pixels = max(push_constants.pixel_count - float(count), 1.0);
+        float average_bin = weighted_bins[0] / lit_pixels;
+        float log_luminance = (average_bin - 1.0) / 254.0 * push_constants.log_luminance_range + push_constants.min_log_luminance;
+        float target = clamp(push_constants.key / exp2(log_luminance), push_constants.min_exposure, push_constants.max_exposure);
+
+        float previous = imageLoad(exposure, ivec2(0, 0)).r;
+        if (previous <= 0.0) {
+            // The exposure was just cleared
+            previous = target;
+        }
+        imageStore(exposure, ivec2(0, 0), vec4(mix(previous, target, push_constants.adaptation)));
+    }
+}
+"
+    }
+}
@@ -0,0 +1,60 @@
+use cgmath::{InnerSpace, Vector3};
+
+/// The maximum amount of clip planes that is applied to a single model. This includes both the planes in [GameState::clip_planes] and the planes of the model itself.
+///
+/// [GameState::clip_planes]: ../struct.GameState.html#structfield.clip_planes
+pub const MAX_CLIP_PLANES: usize = 4;
+
+/// A plane that cuts models in two. The part of a model on the side that the normal points to is rendered, the part on the other side is clipped.
+///
+/// Clip planes can be set for the whole world with [GameState::clip_planes], or for a single model with [ModelData::clip_planes]. This is useful for e.g. planar reflections, showing a cross-section of a model, or hiding everything below the surface of water.
+///
+/// ```no_run
+/// # use crystal_engine::*;
+/// # use crystal_engine::state::ClipPlane;
+/// # use cgmath::Vector3;
+/// # let mut state: GameState = unsafe { std::mem::zeroed() };
+/// // Only render what is above the water
+/// state.clip_planes.push(ClipPlane::new(Vector3::new(0.0, -1.0, 0.0), Vector3::unit_y()));
+/// ```
+///
+/// Note: clip planes are ignored when the GPU does not support the `shader_clip_distance` feature.
+///
+/// [GameState::clip_planes]: ../struct.GameState.html#structfield.clip_planes
+/// [ModelData::clip_planes]: ../models/struct.ModelData.html#structfield.clip_planes
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClipPlane {
+    /// The normal of the plane, pointing to the side that is rendered. This should be normalized.
+    pub normal: Vector3<f32>,
+    /// The distance of the plane along its normal from the origin of the world, negated. This is the `d` in `ax + by + cz + d = 0`.
+    pub distance: f32,
+}
+
+impl ClipPlane {
+    /// Create a plane that goes through `point`, with the side that `normal` points to being rendered.
+    pub fn new(point: Vector3<f32>, normal: Vector3<f32>) -> Self {
+        let normal = normal.normalize();
+        Self {
+            normal,
+            distance: -normal.dot(point),
+        }
+    }
+
+    /// Get the distance of the point to the plane. This is positive when the point is rendered, and negative when the point is clipped.
+    pub fn signed_distance(&self, point: Vector3<f32>) -> f32 {
+        self.normal.dot(point) + self.distance
+    }
+
+    pub(crate) fn to_shader_value(&self) -> [f32; 4] {
+        [self.normal.x, self.normal.y, self.normal.z, self.distance]
+    }
+}
+
+#[test]
+fn test_clip_plane_signed_distance() {
+    let plane = ClipPlane::new(Vector3::new(0.0, 2.0, 0.0), Vector3::new(0.0, 3.0, 0.0));
+    assert_eq!(Vector3::unit_y(), plane.normal);
+    assert_eq!(1.0, plane.signed_distance(Vector3::new(5.0, 3.0, 5.0)));
+    assert_eq!(-2.0, plane.signed_distance(Vector3::new(0.0, 0.0, 0.0)));
+}
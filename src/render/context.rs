@@ -0,0 +1,97 @@
+use super::window::RenderingPath;
+use cgmath::Matrix4;
+use std::sync::Arc;
+use vulkano::{
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    device::{Device, Queue},
+    framebuffer::{FramebufferAbstract, RenderPassAbstract, Subpass},
+    image::attachment::AttachmentImage,
+    sync::GpuFuture,
+};
+
+/// The state of the frame that is being rendered, which is passed to [Game::custom_render](../trait.Game.html#method.custom_render). This is an escape hatch for advanced users that want to record their own vulkano draw calls.
+///
+/// The command buffer is inside the last subpass of the scene render pass, after the models, the lighting, the reflections and the weather are drawn. Everything that is drawn here is tonemapped and post-processed like the rest of the scene, and the GUI is drawn on top of it.
+///
+/// The color attachment of the subpass is an HDR image with the format `R16G16B16A16Sfloat`. With the [forward](../enum.RenderingPath.html#variant.Forward) rendering path the subpass has a depth attachment, with the [deferred](../enum.RenderingPath.html#variant.Deferred) rendering path the depth can only be read as input attachment `4`.
+///
+/// The command buffer has to be left in the same subpass. Ending the render pass or going to the next subpass will make the engine panic.
+pub struct RenderContext<'a> {
+    pub(crate) device: Arc<Device>,
+    pub(crate) queue: Arc<Queue>,
+    pub(crate) subpass: Subpass<Arc<dyn RenderPassAbstract + Send + Sync>>,
+    pub(crate) framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    pub(crate) depth: Arc<AttachmentImage>,
+    pub(crate) command_buffer_builder: &'a mut AutoCommandBufferBuilder,
+    pub(crate) dynamic_state: &'a DynamicState,
+    pub(crate) future: &'a mut Box<dyn GpuFuture>,
+    pub(crate) rendering_path: RenderingPath,
+    pub(crate) dimensions: [f32; 2],
+    pub(crate) view: Matrix4<f32>,
+    pub(crate) projection: Matrix4<f32>,
+}
+
+impl<'a> RenderContext<'a> {
+    /// The device that the game is rendered with.
+    pub fn device(&self) -> &Arc<Device> {
+        &self.device
+    }
+
+    /// The graphics queue that the frame is submitted to. This can also be used to upload buffers and images.
+    pub fn queue(&self) -> &Arc<Queue> {
+        &self.queue
+    }
+
+    /// The subpass that the command buffer is in. Graphics pipelines that are used in [command_buffer_builder](#method.command_buffer_builder) have to be built for this subpass.
+    ///
+    /// The subpass is the same every frame, as long as the rendering path does not change. Pipelines can be built once and be kept in the game.
+    pub fn subpass(&self) -> &Subpass<Arc<dyn RenderPassAbstract + Send + Sync>> {
+        &self.subpass
+    }
+
+    /// The framebuffer of the scene render pass. This changes when the window is resized.
+    pub fn framebuffer(&self) -> &Arc<dyn FramebufferAbstract + Send + Sync> {
+        &self.framebuffer
+    }
+
+    /// The depth buffer of the scene. With the deferred rendering path, this can be bound as input attachment, e.g. to do a manual depth test.
+    pub fn depth(&self) -> &Arc<AttachmentImage> {
+        &self.depth
+    }
+
+    /// The command buffer of the frame, to record draw calls into.
+    pub fn command_buffer_builder(&mut self) -> &mut AutoCommandBufferBuilder {
+        self.command_buffer_builder
+    }
+
+    /// The dynamic state with the viewport of the frame. Pipelines that are built with `viewports_dynamic_scissors_irrelevant` can be drawn with this.
+    pub fn dynamic_state(&self) -> &DynamicState {
+        self.dynamic_state
+    }
+
+    /// Make the frame wait for the given future, e.g. for the upload of an `ImmutableBuffer` that is used in this frame.
+    pub fn wait_for(&mut self, future: impl GpuFuture + 'static) {
+        let start = std::mem::replace(self.future, vulkano::sync::now(self.device.clone()).boxed());
+        *self.future = start.join(future).boxed();
+    }
+
+    /// The rendering path that the scene is rendered with, see the documentation of [RenderContext] for the differences.
+    pub fn rendering_path(&self) -> RenderingPath {
+        self.rendering_path
+    }
+
+    /// The size of the images of the scene in pixels. This is the size of the window multiplied with the [render scale](../struct.GameState.html#method.render_scale).
+    pub fn dimensions(&self) -> [f32; 2] {
+        self.dimensions
+    }
+
+    /// The view matrix of the [camera](../struct.GameState.html#structfield.camera), with the [camera_effects](../struct.GameState.html#structfield.camera_effects) applied.
+    pub fn view_matrix(&self) -> Matrix4<f32> {
+        self.view
+    }
+
+    /// The projection matrix of the camera, based on the [camera_fov](../struct.GameState.html#structfield.camera_fov) and the size of the frame.
+    pub fn projection_matrix(&self) -> Matrix4<f32> {
+        self.projection
+    }
+}
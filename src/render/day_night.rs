@@ -0,0 +1,249 @@
+use super::lights::{DirectionalLight, LightColor, LightState};
+use crate::gui::Gradient;
+use cgmath::{Rad, Vector3};
+use std::time::Duration;
+
+/// The amount of hours in a day
+const HOURS_PER_DAY: f32 = 24.0;
+
+/// Animates the sun, the ambient light, the sky and the fog over the day. Set [GameState::day_night] to enable the cycle.
+///
+/// The time of day is measured in hours, from `0.0` at midnight to `24.0` at the next midnight. The sun rises in the east (positive x) at 6:00, is at its highest point at 12:00 and sets in the west (negative x) at 18:00. At night the sun light becomes the light of the moon, which is on the opposite side of the sky.
+///
+/// The colors are [Gradient]s over the day, where `0.0` is midnight, `0.5` is noon and `1.0` is the next midnight. The colors are in sRGB.
+///
+/// ```no_run
+/// # use crystal_engine::*;
+/// # use crystal_engine::state::DayNightCycle;
+/// # use std::time::Duration;
+/// # let mut state: GameState = unsafe { std::mem::zeroed() };
+/// let mut cycle = DayNightCycle::new(Duration::from_secs(20 * 60));
+/// cycle.set_time_of_day(8.0);
+/// state.day_night = Some(cycle);
+///
+/// // In Game::update
+/// if let Some(cycle) = &state.day_night {
+///     if cycle.has_passed(19.0) {
+///         // turn on the street lights
+///     }
+/// }
+/// ```
+///
+/// [GameState::day_night]: ../struct.GameState.html#structfield.day_night
+/// [Gradient]: ./struct.Gradient.html
+#[derive(Debug, Clone)]
+pub struct DayNightCycle {
+    /// How long a full day of 24 hours takes in real time. Defaults to 10 minutes.
+    pub day_length: Duration,
+    /// Stop the time of day from advancing. The lights are still updated, so [set_time_of_day](#method.set_time_of_day) can be used to pick a fixed time.
+    pub paused: bool,
+    /// The index of the directional light in [LightState::directional] that is animated as the sun. Lights are added if there are not enough lights. Defaults to `0`.
+    ///
+    /// [LightState::directional]: ./struct.LightState.html#structfield.directional
+    pub sun_light: usize,
+    /// How far the path of the sun is tilted towards the south (negative z), e.g. `Rad(0.0)` has the sun straight above at noon. Defaults to 30 degrees.
+    pub sun_tilt: Rad<f32>,
+    /// The diffuse and specular color of the sun, and of the moon at night.
+    pub sun_color: Gradient,
+    /// The ambient color of the sun light.
+    pub ambient_color: Gradient,
+    /// The color of the sky, which is the background of the world.
+    pub sky_color: Gradient,
+    /// The tint of the fog of the [WeatherState], so the fog gets dark at night.
+    ///
+    /// [WeatherState]: ./struct.WeatherState.html
+    pub fog_color: Gradient,
+    /// The time of day as a fraction of the day
+    time: f32,
+    /// The time of day before the last update, used by [has_passed](#method.has_passed)
+    previous_time: f32,
+    day: u32,
+}
+
+impl Default for DayNightCycle {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(10 * 60))
+    }
+}
+
+impl DayNightCycle {
+    /// Create a new cycle with the given length of a day. The cycle starts at noon.
+    pub fn new(day_length: Duration) -> Self {
+        Self {
+            day_length,
+            paused: false,
+            sun_light: 0,
+            sun_tilt: Rad(std::f32::consts::PI / 6.0),
+            sun_color: Gradient::new(vec![
+                (0.0, [50, 60, 90, 255]),
+                (0.23, [30, 35, 60, 255]),
+                (0.25, [0, 0, 0, 255]),
+                (0.28, [255, 150, 80, 255]),
+                (0.35, [255, 240, 220, 255]),
+                (0.5, [255, 255, 245, 255]),
+                (0.65, [255, 240, 220, 255]),
+                (0.72, [255, 150, 80, 255]),
+                (0.75, [0, 0, 0, 255]),
+                (0.77, [30, 35, 60, 255]),
+                (1.0, [50, 60, 90, 255]),
+            ]),
+            ambient_color: Gradient::new(vec![
+                (0.0, [15, 18, 30, 255]),
+                (0.25, [60, 50, 60, 255]),
+                (0.35, [90, 90, 100, 255]),
+                (0.65, [90, 90, 100, 255]),
+                (0.75, [60, 50, 60, 255]),
+                (1.0, [15, 18, 30, 255]),
+            ]),
+            sky_color: Gradient::new(vec![
+                (0.0, [5, 8, 20, 255]),
+                (0.22, [20, 25, 50, 255]),
+                (0.27, [250, 150, 100, 255]),
+                (0.35, [188, 188, 255, 255]),
+                (0.65, [188, 188, 255, 255]),
+                (0.73, [250, 140, 90, 255]),
+                (0.78, [20, 25, 50, 255]),
+                (1.0, [5, 8, 20, 255]),
+            ]),
+            fog_color: Gradient::new(vec![
+                (0.0, [40, 45, 60, 255]),
+                (0.25, [150, 130, 130, 255]),
+                (0.35, [255, 255, 255, 255]),
+                (0.65, [255, 255, 255, 255]),
+                (0.75, [150, 130, 130, 255]),
+                (1.0, [40, 45, 60, 255]),
+            ]),
+            time: 0.5,
+            previous_time: 0.5,
+            day: 0,
+        }
+    }
+
+    /// The time of day in hours, from `0.0` to `24.0`.
+    pub fn time_of_day(&self) -> f32 {
+        self.time * HOURS_PER_DAY
+    }
+
+    /// Change the time of day to the given hour. Hours outside of `0.0..24.0` wrap around to the previous or next day.
+    ///
+    /// This does not trigger [has_passed](#method.has_passed) for the hours that were skipped.
+    pub fn set_time_of_day(&mut self, hours: f32) {
+        self.time = (hours / HOURS_PER_DAY).rem_euclid(1.0);
+        self.previous_time = self.time;
+    }
+
+    /// The amount of days that have passed since the cycle was created.
+    pub fn day(&self) -> u32 {
+        self.day
+    }
+
+    /// Returns `true` if the sun is above the horizon.
+    pub fn is_day(&self) -> bool {
+        self.time > 0.25 && self.time < 0.75
+    }
+
+    /// Returns `true` if the time of day passed the given hour in the last frame. This can be used to trigger events at a certain time, e.g. to turn on the street lights at 19:00.
+    pub fn has_passed(&self, hours: f32) -> bool {
+        let time = (hours / HOURS_PER_DAY).rem_euclid(1.0);
+        if self.previous_time <= self.time {
+            self.previous_time < time && time <= self.time
+        } else {
+            // The day ended in the last frame
+            self.previous_time < time || time <= self.time
+        }
+    }
+
+    /// The direction from the world to the sun.
+    pub fn sun_direction(&self) -> Vector3<f32> {
+        let angle = (self.time - 0.25) * std::f32::consts::PI * 2.0;
+        let tilt = self.sun_tilt.0;
+        Vector3::new(
+            angle.cos(),
+            angle.sin() * tilt.cos(),
+            -angle.sin() * tilt.sin(),
+        )
+    }
+
+    /// The light of the sun at the current time of day. At night this is the light of the moon.
+    pub fn sun(&self) -> DirectionalLight {
+        let sun = self.sun_direction();
+        let color = srgb_to_linear(self.sun_color.sample(self.time));
+        DirectionalLight {
+            // The light shines away from the sun or the moon, whichever is above the horizon
+            direction: if self.is_day() { -sun } else { sun },
+            color: LightColor {
+                ambient: srgb_to_linear(self.ambient_color.sample(self.time)),
+                diffuse: color,
+                specular: color,
+                intensity: 1.0,
+            },
+            enabled: true,
+        }
+    }
+
+    /// The color of the sky at the current time of day, in linear space.
+    pub(crate) fn sky(&self) -> [f32; 4] {
+        let color = srgb_to_linear(self.sky_color.sample(self.time));
+        [color.x, color.y, color.z, 1.0]
+    }
+
+    /// The tint of the fog at the current time of day, in linear space.
+    pub(crate) fn fog_tint(&self) -> Vector3<f32> {
+        srgb_to_linear(self.fog_color.sample(self.time))
+    }
+
+    /// Advance the time of day, and update the sun light.
+    pub(crate) fn update(&mut self, delta: Duration, light: &mut LightState) {
+        self.previous_time = self.time;
+        if !self.paused && self.day_length > Duration::from_secs(0) {
+            self.time += delta.as_secs_f32() / self.day_length.as_secs_f32();
+            while self.time >= 1.0 {
+                self.time -= 1.0;
+                self.day += 1;
+            }
+        }
+
+        while light.directional.len() <= self.sun_light {
+            // The sun can not be shown when its index is past the capacity of the lights
+            if light.directional.push(DirectionalLight::default()).is_err() {
+                return;
+            }
+        }
+        light.directional[self.sun_light] = self.sun();
+    }
+}
+
+/// Convert an sRGB color to a linear color, ignoring the alpha channel
+fn srgb_to_linear(color: [u8; 4]) -> Vector3<f32> {
+    let channel = |c: u8| (c as f32 / 255.0).powf(2.2);
+    Vector3::new(channel(color[0]), channel(color[1]), channel(color[2]))
+}
+
+#[test]
+fn test_day_night_cycle() {
+    let mut light = LightState::new();
+    // An hour passes every second
+    let mut cycle = DayNightCycle::new(Duration::from_secs(24));
+    cycle.set_time_of_day(5.5);
+    cycle.update(Duration::from_secs(1), &mut light);
+
+    assert!((cycle.time_of_day() - 6.5).abs() < 0.001);
+    assert!(cycle.is_day());
+    assert!(cycle.has_passed(6.0));
+    assert!(!cycle.has_passed(5.0));
+    assert!(!cycle.has_passed(7.0));
+    assert_eq!(light.directional.len(), 1);
+    // The morning sun is low in the east, so it shines down towards the west
+    let direction = light.directional[0].direction;
+    assert!(direction.y < 0.0 && direction.x < 0.0);
+
+    cycle.set_time_of_day(23.5);
+    cycle.update(Duration::from_secs(1), &mut light);
+    assert_eq!(cycle.day(), 1);
+    assert!(!cycle.is_day());
+    assert!(cycle.has_passed(0.0));
+    assert!(cycle.has_passed(23.75));
+    assert!(!cycle.has_passed(12.0));
+    // The moon is up, so the light still shines down
+    assert!(light.directional[0].direction.y < 0.0);
+}
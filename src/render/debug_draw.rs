@@ -0,0 +1,437 @@
+use super::frustum::Frustum;
+use crate::{collision::Collider, GameState};
+use cgmath::{InnerSpace, Matrix4, Vector3};
+use std::sync::Arc;
+use vulkano::{
+    buffer::CpuBufferPool,
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    descriptor::descriptor_set::{PersistentDescriptorSet, StdDescriptorPool},
+    device::Device,
+    framebuffer::{RenderPassAbstract, Subpass},
+    pipeline::{GraphicsPipeline, GraphicsPipelineAbstract},
+};
+
+/// The color of the bounding volumes of models that are rendered
+const VISIBLE_COLOR: [u8; 4] = [0, 255, 0, 255];
+/// The color of the bounding volumes of models that are culled
+const CULLED_COLOR: [u8; 4] = [255, 0, 0, 255];
+/// The amount of line segments that is used to draw a circle
+const CIRCLE_SEGMENTS: usize = 32;
+/// The color of the colliders of models
+const COLLIDER_COLOR: [u8; 4] = [255, 255, 0, 255];
+/// The color of the lines of a grid helper
+const GRID_COLOR: [u8; 4] = [128, 128, 128, 255];
+
+/// Debug shapes that are drawn on top of the world. This can be used to visualize values that are otherwise invisible, like bounding volumes.
+///
+/// Shapes are drawn without depth testing, so they are visible through models. Shapes that are added with the `draw_*` methods are drawn for a single frame, so they should be added in every [Game::update].
+///
+/// ```no_run
+/// # use crystal_engine::*;
+/// # use cgmath::Vector3;
+/// # let mut state: GameState = unsafe { std::mem::zeroed() };
+/// state.debug_draw.show_bounding_volumes = true;
+/// state.debug_draw.draw_line(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0), color::WHITE);
+/// ```
+///
+/// [Game::update]: ../trait.Game.html#tymethod.update
+pub struct DebugDraw {
+    /// Draw the bounding box and bounding sphere of every model. The volumes of models that are rendered are green, and the volumes of models that are culled because they are outside of the view of the camera are red.
+    pub show_bounding_volumes: bool,
+    /// Draw the [collider](../models/struct.ModelData.html#structfield.collider) of every model in yellow.
+    pub show_colliders: bool,
+    /// Draw the helpers that are added with [GameState::new_grid] and [GameState::new_axes]. Defaults to `true`.
+    ///
+    /// [GameState::new_grid]: ../struct.GameState.html#method.new_grid
+    /// [GameState::new_axes]: ../struct.GameState.html#method.new_axes
+    pub show_helpers: bool,
+    lines: Vec<(Vector3<f32>, [u8; 4])>,
+    helpers: Vec<DebugHelper>,
+    next_helper_id: u64,
+}
+
+/// The id of a grid or axis helper. This can be used to hide or remove the helper, see [DebugDraw::set_helper_visible] and [DebugDraw::remove_helper].
+///
+/// [DebugDraw::set_helper_visible]: ./struct.DebugDraw.html#method.set_helper_visible
+/// [DebugDraw::remove_helper]: ./struct.DebugDraw.html#method.remove_helper
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DebugHelperId(u64);
+
+/// Lines that are drawn every frame until they are removed.
+struct DebugHelper {
+    id: DebugHelperId,
+    visible: bool,
+    lines: Vec<(Vector3<f32>, [u8; 4])>,
+}
+
+impl Default for DebugDraw {
+    fn default() -> Self {
+        Self {
+            show_bounding_volumes: false,
+            show_colliders: false,
+            show_helpers: true,
+            lines: Vec::new(),
+            helpers: Vec::new(),
+            next_helper_id: 0,
+        }
+    }
+}
+
+impl DebugDraw {
+    /// Draw a line between two points in the world.
+    pub fn draw_line(&mut self, from: Vector3<f32>, to: Vector3<f32>, color: [u8; 4]) {
+        self.lines.push((from, color));
+        self.lines.push((to, color));
+    }
+
+    /// Draw the edges of an axis-aligned box, given as `(min, max)`.
+    pub fn draw_box(&mut self, (min, max): (Vector3<f32>, Vector3<f32>), color: [u8; 4]) {
+        let corner = |i: usize| {
+            Vector3::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            )
+        };
+        for i in 0..8 {
+            // Connect every corner to the corners that differ in a single axis
+            for axis in &[1, 2, 4] {
+                if i & axis == 0 {
+                    self.draw_line(corner(i), corner(i | axis), color);
+                }
+            }
+        }
+    }
+
+    /// Draw a sphere as three circles, one around every axis.
+    pub fn draw_sphere(&mut self, center: Vector3<f32>, radius: f32, color: [u8; 4]) {
+        let axes = [
+            (Vector3::unit_x(), Vector3::unit_y()),
+            (Vector3::unit_y(), Vector3::unit_z()),
+            (Vector3::unit_z(), Vector3::unit_x()),
+        ];
+        for &(u, v) in axes.iter() {
+            let point = |i: usize| {
+                let angle = i as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::PI * 2.0;
+                center + (u * angle.cos() + v * angle.sin()) * radius
+            };
+            for i in 0..CIRCLE_SEGMENTS {
+                self.draw_line(point(i), point(i + 1), color);
+            }
+        }
+    }
+
+    /// Draw a capsule as the spheres around its ends, connected by four lines along its sides.
+    pub fn draw_capsule(
+        &mut self,
+        start: Vector3<f32>,
+        end: Vector3<f32>,
+        radius: f32,
+        color: [u8; 4],
+    ) {
+        self.draw_sphere(start, radius, color);
+        let direction = end - start;
+        if direction.magnitude2() <= std::f32::EPSILON {
+            return;
+        }
+        self.draw_sphere(end, radius, color);
+        // Any axis that is not parallel to the capsule can be used to find the sides
+        let axis = if direction.x.abs() < direction.y.abs() {
+            Vector3::unit_x()
+        } else {
+            Vector3::unit_y()
+        };
+        let side = direction.cross(axis).normalize() * radius;
+        let other_side = direction.cross(side).normalize() * radius;
+        for &offset in &[side, -side, other_side, -other_side] {
+            self.draw_line(start + offset, end + offset, color);
+        }
+    }
+
+    /// Draw the outline of a collider, see [Collider](../state/enum.Collider.html).
+    pub fn draw_collider(&mut self, collider: &Collider, color: [u8; 4]) {
+        match *collider {
+            Collider::Sphere { center, radius } => self.draw_sphere(center, radius, color),
+            Collider::Box { min, max } => self.draw_box((min, max), color),
+            Collider::Capsule { start, end, radius } => {
+                self.draw_capsule(start, end, radius, color)
+            }
+        }
+    }
+
+    /// Add a grid on the XZ plane, centered on the origin. The grid is `size` world units wide and deep, with a line every `spacing` world units.
+    pub fn add_grid(&mut self, size: f32, spacing: f32) -> DebugHelperId {
+        let mut lines = Vec::new();
+        if spacing > 0.0 {
+            let half_size = size / 2.0;
+            let count = (half_size / spacing).floor() as i32;
+            for i in -count..=count {
+                let offset = i as f32 * spacing;
+                lines.push((Vector3::new(offset, 0.0, -half_size), GRID_COLOR));
+                lines.push((Vector3::new(offset, 0.0, half_size), GRID_COLOR));
+                lines.push((Vector3::new(-half_size, 0.0, offset), GRID_COLOR));
+                lines.push((Vector3::new(half_size, 0.0, offset), GRID_COLOR));
+            }
+        }
+        self.add_helper(lines)
+    }
+
+    /// Add the axes of the world, starting at the origin and `length` world units long. The x-axis is red, the y-axis is green and the z-axis is blue.
+    pub fn add_axes(&mut self, length: f32) -> DebugHelperId {
+        let origin = Vector3::new(0.0, 0.0, 0.0);
+        let axes = [
+            (Vector3::unit_x(), [255, 0, 0, 255]),
+            (Vector3::unit_y(), [0, 255, 0, 255]),
+            (Vector3::unit_z(), [0, 0, 255, 255]),
+        ];
+        let lines = axes
+            .iter()
+            .flat_map(|&(axis, color)| vec![(origin, color), (axis * length, color)])
+            .collect();
+        self.add_helper(lines)
+    }
+
+    /// Show or hide a grid or axis helper.
+    pub fn set_helper_visible(&mut self, id: DebugHelperId, visible: bool) {
+        if let Some(helper) = self.helpers.iter_mut().find(|h| h.id == id) {
+            helper.visible = visible;
+        }
+    }
+
+    /// Remove a grid or axis helper.
+    pub fn remove_helper(&mut self, id: DebugHelperId) {
+        self.helpers.retain(|h| h.id != id);
+    }
+
+    fn add_helper(&mut self, lines: Vec<(Vector3<f32>, [u8; 4])>) -> DebugHelperId {
+        let id = DebugHelperId(self.next_helper_id);
+        self.next_helper_id += 1;
+        self.helpers.push(DebugHelper {
+            id,
+            visible: true,
+            lines,
+        });
+        id
+    }
+
+    fn add_helper_lines(&mut self) {
+        for helper in &self.helpers {
+            if helper.visible {
+                self.lines.extend_from_slice(&helper.lines);
+            }
+        }
+    }
+
+    fn add_bounding_volumes(&mut self, game_state: &GameState, frustum: &Frustum) {
+        for model in game_state.model_handles.values() {
+            let color = if model.is_visible(frustum) {
+                VISIBLE_COLOR
+            } else {
+                CULLED_COLOR
+            };
+            let (min, max) = model.world_bounding_box();
+            self.draw_box((min, max), color);
+            self.draw_sphere((min + max) / 2.0, (max - min).magnitude() / 2.0, color);
+        }
+    }
+}
+
+/// The pipeline that draws the lines of [DebugDraw].
+pub struct Pipeline {
+    pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    vertex_buffer: CpuBufferPool<Vertex>,
+    uniform_buffer: CpuBufferPool<vs::ty::Data>,
+    gamma_correct: bool,
+}
+
+impl Pipeline {
+    /// Create the pipeline. If `gamma_correct` is true, the colors are written as-is, because the swapchain does not have an sRGB format.
+    pub fn create(
+        device: Arc<Device>,
+        subpass: Subpass<Arc<dyn RenderPassAbstract + Send + Sync>>,
+        gamma_correct: bool,
+    ) -> Self {
+        // These should never fail, as the shaders are hard-coded and the device is assumed to be
+        // valid.
+        let vs = vs::Shader::load(device.clone()).expect("failed to create shader module");
+        let fs = fs::Shader::load(device.clone()).expect("failed to create shader module");
+
+        let pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input_single_buffer::<Vertex>()
+                .vertex_shader(vs.main_entry_point(), ())
+                .line_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(fs.main_entry_point(), ())
+                .blend_alpha_blending()
+                .render_pass(subpass)
+                .build(device.clone())
+                // This should never fail because all arguments are hard-coded
+                .unwrap(),
+        );
+
+        Self {
+            pipeline,
+            vertex_buffer: CpuBufferPool::vertex_buffer(device.clone()),
+            uniform_buffer: CpuBufferPool::uniform_buffer(device),
+            gamma_correct,
+        }
+    }
+
+    /// Draw the lines that were added to [GameState::debug_draw] this frame, and clear them.
+    pub fn render(
+        &self,
+        game_state: &mut GameState,
+        view_projection: Matrix4<f32>,
+        command_buffer_builder: &mut AutoCommandBufferBuilder,
+        dynamic_state: &DynamicState,
+        descriptor_pool: &mut Arc<StdDescriptorPool>,
+    ) {
+        let mut debug_draw = std::mem::take(&mut game_state.debug_draw);
+        if debug_draw.show_bounding_volumes {
+            let frustum = Frustum::from_matrix(view_projection);
+            debug_draw.add_bounding_volumes(game_state, &frustum);
+        }
+        if debug_draw.show_colliders {
+            for model in game_state.model_handles.values() {
+                if let Some(collider) = model.data.read().world_collider() {
+                    debug_draw.draw_collider(&collider, COLLIDER_COLOR);
+                }
+            }
+        }
+        if debug_draw.show_helpers {
+            debug_draw.add_helper_lines();
+        }
+        let lines = std::mem::take(&mut debug_draw.lines);
+        // The next frame will most likely draw about the same amount of lines
+        game_state.debug_draw = DebugDraw {
+            lines: Vec::with_capacity(lines.len()),
+            ..debug_draw
+        };
+        if lines.is_empty() {
+            return;
+        }
+
+        let gamma_correct = self.gamma_correct;
+        let vertices = lines.into_iter().map(|(position, color)| Vertex {
+            position: position.into(),
+            color: to_shader_color(color, gamma_correct),
+        });
+        // The vertex_buffer is assumed to be valid so this should never fail
+        let vertex_buffer = self.vertex_buffer.chunk(vertices).unwrap();
+
+        let data = vs::ty::Data {
+            view_projection: view_projection.into(),
+        };
+        // The uniform_buffer is assumed to be valid so this should never fail
+        let uniform_buffer_subbuffer = self.uniform_buffer.next(data).unwrap();
+
+        // The pipeline and the layout index are hard-coded so this is assumed to never fail
+        let layout = self.pipeline.descriptor_set_layout(0).unwrap();
+        let set = Arc::new(
+            PersistentDescriptorSet::start(layout.clone())
+                .add_buffer(uniform_buffer_subbuffer)
+                // The uniform subbuffer is assumed to be valid so this should never fail
+                .unwrap()
+                .build_with_pool(descriptor_pool)
+                // The pool is assumed to be valid so this should never fail
+                .unwrap(),
+        );
+
+        command_buffer_builder
+            .draw(
+                self.pipeline.clone(),
+                dynamic_state,
+                vec![Arc::new(vertex_buffer)],
+                set,
+                (),
+            )
+            // the builder and arguments are assumed to be valid so this should never fail
+            .unwrap();
+    }
+}
+
+#[test]
+fn test_helpers() {
+    let mut debug_draw = DebugDraw::default();
+    let grid = debug_draw.add_grid(10.0, 1.0);
+    let axes = debug_draw.add_axes(2.0);
+    debug_draw.add_helper_lines();
+    // 11 lines along both the x-axis and the z-axis, and 3 axes
+    assert_eq!((22 + 3) * 2, debug_draw.lines.len());
+    assert!(debug_draw
+        .lines
+        .contains(&(Vector3::new(0.0, 2.0, 0.0), [0, 255, 0, 255])));
+
+    debug_draw.lines.clear();
+    debug_draw.set_helper_visible(grid, false);
+    debug_draw.add_helper_lines();
+    assert_eq!(3 * 2, debug_draw.lines.len());
+
+    debug_draw.lines.clear();
+    debug_draw.remove_helper(axes);
+    debug_draw.set_helper_visible(grid, true);
+    debug_draw.add_helper_lines();
+    assert_eq!(22 * 2, debug_draw.lines.len());
+}
+
+/// Convert an sRGB color to the color that the shader should output. An sRGB swapchain expects linear colors, and converts them back to sRGB itself.
+fn to_shader_color(color: [u8; 4], gamma_correct: bool) -> [f32; 4] {
+    let channel = |c: u8| {
+        let c = c as f32 / 255.0;
+        if gamma_correct {
+            c
+        } else {
+            c.powf(2.2)
+        }
+    };
+    [
+        channel(color[0]),
+        channel(color[1]),
+        channel(color[2]),
+        color[3] as f32 / 255.0,
+    ]
+}
+
+#[derive(Default, Copy, Clone)]
+pub struct Vertex {
+    pub position: [f32; 3],
+    pub color: [f32; 4],
+}
+vulkano::impl_vertex!(Vertex, position, color);
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "#version 450
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec4 color;
+
+layout(location = 0) out vec4 fragment_color;
+
+layout(set = 0, binding = 0) uniform Data {
+    mat4 view_projection;
+} uniforms;
+
+void main() {
+    gl_Position = uniforms.view_projection * vec4(position, 1.0);
+    fragment_color = color;
+}
+"
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "#version 450
+layout(location = 0) in vec4 fragment_color;
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+    f_color = fragment_color;
+}
+"
+    }
+}
@@ -0,0 +1,597 @@
+use super::{
+    environment::{self, EnvironmentMap},
+    lights::{DirectionalLight, PointLight},
+};
+use crate::GameState;
+use cgmath::{Matrix4, SquareMatrix};
+use std::sync::Arc;
+use vulkano::{
+    buffer::{BufferUsage, CpuAccessibleBuffer, CpuBufferPool},
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    descriptor::descriptor_set::{PersistentDescriptorSet, StdDescriptorPool},
+    device::Device,
+    format::Format,
+    framebuffer::{RenderPassAbstract, Subpass},
+    image::attachment::AttachmentImage,
+    pipeline::{GraphicsPipeline, GraphicsPipelineAbstract},
+    sampler::Sampler,
+};
+
+/// The format of the attachments of the G-buffer
+pub const GBUFFER_FORMAT: Format = Format::R16G16B16A16Sfloat;
+
+/// The attachments that the models are rendered into by the deferred rendering path.
+///
+/// The albedo of the model is pre-multiplied with the ambient, diffuse and specular factors of
+/// the material, so the lighting pass does not need to know about textures or materials.
+///
+/// Models with a PBR material are marked with an alpha of `1.0` in the normal buffer. For these
+/// the ambient buffer contains the albedo multiplied with the ambient occlusion and the metallic
+/// factor in the alpha channel, the diffuse buffer contains the albedo, and the specular buffer
+/// contains the ambient occlusion in the red channel and the roughness in the alpha channel.
+pub struct GBuffer {
+    /// The ambient color of the fragment. Where no model was drawn, this contains the clear color.
+    pub ambient: Arc<AttachmentImage>,
+    /// The diffuse color of the fragment. The alpha channel is `1.0` plus the highlight of the model where a model was drawn, and `0.0` everywhere else.
+    pub diffuse: Arc<AttachmentImage>,
+    /// The specular color of the fragment, with the shininess in the alpha channel.
+    pub specular: Arc<AttachmentImage>,
+    /// The normal of the fragment in view space. The alpha channel is `1.0` for PBR materials.
+    pub normal: Arc<AttachmentImage>,
+}
+
+impl GBuffer {
+    pub fn new(device: Arc<Device>, dimensions: [u32; 2]) -> Self {
+        let create = || {
+            AttachmentImage::transient_input_attachment(device.clone(), dimensions, GBUFFER_FORMAT)
+                .unwrap() // this should always be valid as long as the device is valid
+        };
+        Self {
+            ambient: create(),
+            diffuse: create(),
+            specular: create(),
+            normal: create(),
+        }
+    }
+}
+
+/// The lighting pass of the deferred rendering path. This reads the [GBuffer] and the depth buffer, and writes the lit scene to the HDR buffer.
+///
+/// The lights are passed to the shader in storage buffers, so there is no limit on the amount of lights.
+pub struct Pipeline {
+    pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    vertex_buffer: Arc<CpuAccessibleBuffer<[Vertex]>>,
+    uniform_buffer: CpuBufferPool<fs::ty::Data>,
+    directional_buffer: CpuBufferPool<fs::ty::DirectionalLight>,
+    point_buffer: CpuBufferPool<fs::ty::PointLight>,
+    environment_sampler: Arc<Sampler>,
+}
+
+impl Pipeline {
+    pub fn create(
+        device: Arc<Device>,
+        subpass: Subpass<Arc<dyn RenderPassAbstract + Send + Sync>>,
+    ) -> Self {
+        // These should never fail, as the shaders are hard-coded and the device is assumed to be
+        // valid.
+        let vs = vs::Shader::load(device.clone()).expect("failed to create shader module");
+        let fs = fs::Shader::load(device.clone()).expect("failed to create shader module");
+
+        let pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input_single_buffer::<Vertex>()
+                .vertex_shader(vs.main_entry_point(), ())
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(fs.main_entry_point(), ())
+                .render_pass(subpass)
+                .build(device.clone())
+                // This should never fail because all arguments are hard-coded
+                .unwrap(),
+        );
+
+        // A single triangle that covers the entire screen
+        let vertex_buffer = CpuAccessibleBuffer::from_iter(
+            device.clone(),
+            BufferUsage::all(),
+            false,
+            [
+                Vertex {
+                    position: [-1.0, -1.0],
+                },
+                Vertex {
+                    position: [3.0, -1.0],
+                },
+                Vertex {
+                    position: [-1.0, 3.0],
+                },
+            ]
+            .iter()
+            .cloned(),
+        )
+        // This should never fail because the arguments are hard-coded
+        .unwrap();
+
+        Self {
+            pipeline,
+            vertex_buffer,
+            uniform_buffer: CpuBufferPool::uniform_buffer(device.clone()),
+            directional_buffer: CpuBufferPool::new(device.clone(), BufferUsage::storage_buffer()),
+            point_buffer: CpuBufferPool::new(device.clone(), BufferUsage::storage_buffer()),
+            environment_sampler: environment::sampler(device),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn render(
+        &self,
+        gbuffer: &GBuffer,
+        depth: Arc<AttachmentImage>,
+        dimensions: [f32; 2],
+        game_state: &GameState,
+        environment: &EnvironmentMap,
+        command_buffer_builder: &mut AutoCommandBufferBuilder,
+        dynamic_state: &DynamicState,
+        descriptor_pool: &mut Arc<StdDescriptorPool>,
+    ) {
+        let view = game_state.view_matrix();
+        let inverse_projection = game_state
+            .perspective_matrix(dimensions[0] / dimensions[1])
+            .invert()
+            // A perspective matrix can always be inverted
+            .unwrap_or_else(Matrix4::identity);
+
+        let directional = game_state.light.directional.as_slice();
+        let point = game_state.light.point.as_slice();
+        let weather = &game_state.weather;
+        let brightness = weather.light_factor();
+        let (fog_color, fog_density) = game_state.fog();
+        let (sky, ground) = game_state.light.hemisphere.to_shader_value(brightness);
+        let data = fs::ty::Data {
+            inverse_projection: inverse_projection.into(),
+            view: view.into(),
+            screen_size: dimensions,
+            directional_count: directional.len() as i32,
+            point_count: point.len() as i32,
+            environment_intensity: if game_state.light.environment.is_some() {
+                game_state.light.environment_intensity * brightness
+            } else {
+                0.0
+            },
+            fog_r: fog_color[0],
+            fog_g: fog_color[1],
+            fog_b: fog_color[2],
+            fog_density,
+            wetness: weather.surface_wetness(),
+            hemisphere_sky_r: sky.x,
+            hemisphere_sky_g: sky.y,
+            hemisphere_sky_b: sky.z,
+            hemisphere_ground_r: ground.x,
+            hemisphere_ground_g: ground.y,
+            hemisphere_ground_b: ground.z,
+        };
+
+        // A buffer can not be empty, so a single unused light is uploaded when there are no lights
+        let directional_lights = if directional.is_empty() {
+            self.directional_buffer
+                .chunk(std::iter::once(directional_light(
+                    &DirectionalLight::default(),
+                    1.0,
+                )))
+        } else {
+            self.directional_buffer.chunk(
+                directional
+                    .iter()
+                    .map(|light| directional_light(light, brightness)),
+            )
+        }
+        // The buffer pool is assumed to be valid so this should never fail
+        .unwrap();
+        let point_lights = if point.is_empty() {
+            self.point_buffer
+                .chunk(std::iter::once(point_light(&PointLight::default())))
+        } else {
+            self.point_buffer.chunk(point.iter().map(point_light))
+        }
+        // The buffer pool is assumed to be valid so this should never fail
+        .unwrap();
+
+        // The pipeline and the layout index are hard-coded so this is assumed to never fail
+        let layout = self.pipeline.descriptor_set_layout(0).unwrap();
+        let sampler = &self.environment_sampler;
+        let set = Arc::new(
+            PersistentDescriptorSet::start(layout.clone())
+                // The G-buffer is created as input attachments so these should never fail
+                .add_image(gbuffer.ambient.clone())
+                .unwrap()
+                .add_image(gbuffer.diffuse.clone())
+                .unwrap()
+                .add_image(gbuffer.specular.clone())
+                .unwrap()
+                .add_image(gbuffer.normal.clone())
+                .unwrap()
+                .add_image(depth)
+                .unwrap()
+                // The buffers are assumed to be valid so these should never fail
+                .add_buffer(self.uniform_buffer.next(data).unwrap())
+                .unwrap()
+                .add_buffer(directional_lights)
+                .unwrap()
+                .add_buffer(point_lights)
+                .unwrap()
+                // The environment maps and the sampler are assumed to be valid so these should
+                // never fail
+                .add_sampled_image(environment.irradiance.clone(), sampler.clone())
+                .unwrap()
+                .add_sampled_image(environment.specular[0].clone(), sampler.clone())
+                .unwrap()
+                .add_sampled_image(environment.specular[1].clone(), sampler.clone())
+                .unwrap()
+                .add_sampled_image(environment.specular[2].clone(), sampler.clone())
+                .unwrap()
+                .add_sampled_image(environment.specular[3].clone(), sampler.clone())
+                .unwrap()
+                .build_with_pool(descriptor_pool)
+                // The pool is assumed to be valid so this should never fail
+                .unwrap(),
+        );
+
+        command_buffer_builder
+            .draw(
+                self.pipeline.clone(),
+                dynamic_state,
+                vec![self.vertex_buffer.clone()],
+                set,
+                (),
+            )
+            // the builder and arguments are assumed to be valid so this should never fail
+            .unwrap();
+    }
+}
+
+/// Convert a directional light to the value of the shader, with its colors multiplied with `brightness`.
+fn directional_light(light: &DirectionalLight, brightness: f32) -> fs::ty::DirectionalLight {
+    let (ambient, diffuse, specular) = light.color.to_shader_value(light.enabled, brightness);
+    fs::ty::DirectionalLight {
+        direction_x: light.direction.x,
+        direction_y: light.direction.y,
+        direction_z: light.direction.z,
+        color_ambient_r: ambient.x,
+        color_ambient_g: ambient.y,
+        color_ambient_b: ambient.z,
+        color_diffuse_r: diffuse.x,
+        color_diffuse_g: diffuse.y,
+        color_diffuse_b: diffuse.z,
+        color_specular_r: specular.x,
+        color_specular_g: specular.y,
+        color_specular_b: specular.z,
+    }
+}
+
+fn point_light(light: &PointLight) -> fs::ty::PointLight {
+    let (ambient, diffuse, specular) = light.color.to_shader_value(light.enabled, 1.0);
+    fs::ty::PointLight {
+        position_x: light.position.x,
+        position_y: light.position.y,
+        position_z: light.position.z,
+        color_ambient_r: ambient.x,
+        color_ambient_g: ambient.y,
+        color_ambient_b: ambient.z,
+        color_diffuse_r: diffuse.x,
+        color_diffuse_g: diffuse.y,
+        color_diffuse_b: diffuse.z,
+        color_specular_r: specular.x,
+        color_specular_g: specular.y,
+        color_specular_b: specular.z,
+        attenuation_constant: light.attenuation.constant,
+        attenuation_linear: light.attenuation.linear,
+        attenuation_quadratic: light.attenuation.quadratic,
+    }
+}
+
+#[derive(Default, Debug, Clone)]
+struct Vertex {
+    position: [f32; 2],
+}
+
+vulkano::impl_vertex!(Vertex, position);
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "#version 450
+
+layout(location = 0) in vec2 position;
+
+void main() {
+    gl_Position = vec4(position, 0.0, 1.0);
+}
+"
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "#version 450
+
+layout(input_attachment_index = 0, set = 0, binding = 0) uniform subpassInput g_ambient;
+layout(input_attachment_index = 1, set = 0, binding = 1) uniform subpassInput g_diffuse;
+layout(input_attachment_index = 2, set = 0, binding = 2) uniform subpassInput g_specular;
+layout(input_attachment_index = 3, set = 0, binding = 3) uniform subpassInput g_normal;
+layout(input_attachment_index = 4, set = 0, binding = 4) uniform subpassInput g_depth;
+
+layout(set = 0, binding = 5) uniform Data {
+    mat4 inverse_projection;
+    mat4 view;
+    vec2 screen_size;
+    int directional_count;
+    int point_count;
+    float environment_intensity;
+    float fog_r;
+    float fog_g;
+    float fog_b;
+    float fog_density;
+    float wetness;
+    float hemisphere_sky_r;
+    float hemisphere_sky_g;
+    float hemisphere_sky_b;
+    float hemisphere_ground_r;
+    float hemisphere_ground_g;
+    float hemisphere_ground_b;
+} uniforms;
+
+struct DirectionalLight {
+    float direction_x;
+    float direction_y;
+    float direction_z;
+    float color_ambient_r;
+    float color_ambient_g;
+    float color_ambient_b;
+    float color_diffuse_r;
+    float color_diffuse_g;
+    float color_diffuse_b;
+    float color_specular_r;
+    float color_specular_g;
+    float color_specular_b;
+};
+
+struct PointLight {
+    float position_x;
+    float position_y;
+    float position_z;
+    float color_ambient_r;
+    float color_ambient_g;
+    float color_ambient_b;
+    float color_diffuse_r;
+    float color_diffuse_g;
+    float color_diffuse_b;
+    float color_specular_r;
+    float color_specular_g;
+    float color_specular_b;
+    float attenuation_constant;
+    float attenuation_linear;
+    float attenuation_quadratic;
+};
+
+layout(set = 0, binding = 6) readonly buffer DirectionalLights {
+    DirectionalLight lights[];
+} directional;
+
+layout(set = 0, binding = 7) readonly buffer PointLights {
+    PointLight lights[];
+} point;
+
+// The environment map, see render/environment.rs
+layout(set = 0, binding = 8) uniform samplerCube environment_irradiance;
+layout(set = 0, binding = 9) uniform samplerCube environment_specular0;
+layout(set = 0, binding = 10) uniform samplerCube environment_specular1;
+layout(set = 0, binding = 11) uniform samplerCube environment_specular2;
+layout(set = 0, binding = 12) uniform samplerCube environment_specular3;
+
+layout(location = 0) out vec4 f_color;
+
+// Reconstruct the view space position of the fragment from the depth buffer
+vec3 view_position() {
+    vec2 ndc = vec2(
+        gl_FragCoord.x / uniforms.screen_size.x * 2.0 - 1.0,
+        // The viewport is flipped, so the y-axis goes up
+        1.0 - gl_FragCoord.y / uniforms.screen_size.y * 2.0
+    );
+    vec4 position = uniforms.inverse_projection * vec4(ndc, subpassLoad(g_depth).x, 1.0);
+    return position.xyz / position.w;
+}
+
+// The light of the environment map, this is the same as the forward shader in model/pipeline.rs
+vec3 environment_light(vec3 diffuse, vec3 specular, float shininess, vec3 normal, vec3 position) {
+    mat3 view_to_world = transpose(mat3(uniforms.view));
+    vec3 world_normal = view_to_world * normal;
+    vec3 reflected = view_to_world * reflect(normalize(position), normal);
+
+    float level = clamp(4.0 - log2(max(shininess, 1.0)) / 2.0, 0.0, 3.0);
+    vec3 reflection =
+        texture(environment_specular0, reflected).rgb * max(1.0 - abs(level - 0.0), 0.0) +
+        texture(environment_specular1, reflected).rgb * max(1.0 - abs(level - 1.0), 0.0) +
+        texture(environment_specular2, reflected).rgb * max(1.0 - abs(level - 2.0), 0.0) +
+        texture(environment_specular3, reflected).rgb * max(1.0 - abs(level - 3.0), 0.0);
+
+    vec3 irradiance = texture(environment_irradiance, world_normal).rgb;
+    return (diffuse * irradiance + specular * reflection) * uniforms.environment_intensity;
+}
+
+// The light of the hemisphere light, this is the same as the forward shader in model/pipeline.rs
+vec3 hemisphere_light(vec3 normal) {
+    float up = (transpose(mat3(uniforms.view)) * normal).y;
+    vec3 sky = vec3(uniforms.hemisphere_sky_r, uniforms.hemisphere_sky_g, uniforms.hemisphere_sky_b);
+    vec3 ground = vec3(uniforms.hemisphere_ground_r, uniforms.hemisphere_ground_g, uniforms.hemisphere_ground_b);
+    return mix(ground, sky, up * 0.5 + 0.5);
+}
+
+const float PI = 3.14159265359;
+
+// The Cook-Torrance BRDF, this is the same as the forward shader in model/pipeline.rs
+vec3 pbr_light(vec3 albedo, float metallic, float roughness, vec3 normal, vec3 view_dir, vec3 light_dir, vec3 radiance) {
+    vec3 halfway = normalize(view_dir + light_dir);
+    float n_dot_l = max(dot(normal, light_dir), 0.0);
+    float n_dot_v = max(dot(normal, view_dir), 0.0);
+    float n_dot_h = max(dot(normal, halfway), 0.0);
+
+    float alpha = roughness * roughness;
+    float alpha2 = alpha * alpha;
+    float denominator = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    float distribution = alpha2 / (PI * denominator * denominator);
+
+    float k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    float geometry = n_dot_v / (n_dot_v * (1.0 - k) + k) * n_dot_l / (n_dot_l * (1.0 - k) + k);
+
+    vec3 f0 = mix(vec3(0.04), albedo, metallic);
+    vec3 fresnel = f0 + (1.0 - f0) * pow(1.0 - max(dot(halfway, view_dir), 0.0), 5.0);
+
+    vec3 specular = distribution * geometry * fresnel / max(4.0 * n_dot_v * n_dot_l, 0.001);
+    vec3 diffuse = (vec3(1.0) - fresnel) * (1.0 - metallic) * albedo / PI;
+    return (diffuse + specular) * radiance * n_dot_l;
+}
+
+vec3 pbr_color(vec4 ambient, vec3 albedo, vec4 specular, vec3 normal, vec3 position) {
+    float metallic = ambient.a;
+    float ambient_occlusion = specular.r;
+    float roughness = specular.a;
+    vec3 view_dir = normalize(-position);
+    mat3 view = mat3(uniforms.view);
+
+    // The diffuse colors of the lights are scaled by PI, so a light is as bright on a rough PBR
+    // material as on a Blinn-Phong material
+    vec3 result = vec3(0.0);
+    for (int i = 0; i < uniforms.directional_count; i++) {
+        DirectionalLight light = directional.lights[i];
+        vec3 light_dir = normalize(-(view * vec3(light.direction_x, light.direction_y, light.direction_z)));
+        vec3 radiance = vec3(light.color_diffuse_r, light.color_diffuse_g, light.color_diffuse_b) * PI;
+        result += vec3(light.color_ambient_r, light.color_ambient_g, light.color_ambient_b) * ambient.rgb;
+        result += pbr_light(albedo, metallic, roughness, normal, view_dir, light_dir, radiance);
+    }
+
+    for (int i = 0; i < uniforms.point_count; i++) {
+        PointLight light = point.lights[i];
+        vec3 light_position = (uniforms.view * vec4(light.position_x, light.position_y, light.position_z, 1.0)).xyz;
+        vec3 light_dir = normalize(light_position - position);
+        float distance = length(light_position - position);
+        float attenuation = 1.0 / (
+            light.attenuation_constant +
+            light.attenuation_linear * distance +
+            light.attenuation_quadratic * distance * distance
+        );
+        vec3 radiance = vec3(light.color_diffuse_r, light.color_diffuse_g, light.color_diffuse_b) * PI * attenuation;
+        result += vec3(light.color_ambient_r, light.color_ambient_g, light.color_ambient_b) * ambient.rgb * attenuation;
+        result += pbr_light(albedo, metallic, roughness, normal, view_dir, light_dir, radiance);
+    }
+    result += hemisphere_light(normal) * ambient.rgb;
+
+    if (uniforms.environment_intensity > 0.0) {
+        // The Blinn-Phong shininess that matches the roughness
+        float alpha = max(roughness * roughness, 0.001);
+        result += environment_light(
+            albedo * (1.0 - metallic) * ambient_occlusion,
+            mix(vec3(0.04), albedo, metallic) * ambient_occlusion,
+            2.0 / (alpha * alpha) - 2.0,
+            normal,
+            position
+        );
+    }
+    return result;
+}
+
+// Darken the surfaces that are wet from the rain, this is the same as the forward shader in
+// model/pipeline.rs
+vec3 wet_surface(vec3 color, vec3 normal, vec3 view_dir) {
+    mat3 view = mat3(uniforms.view);
+    float wetness = uniforms.wetness * clamp((transpose(view) * normal).y, 0.0, 1.0);
+    if (wetness <= 0.0) {
+        return color;
+    }
+    vec3 highlight = vec3(0.0);
+    for (int i = 0; i < uniforms.directional_count; i++) {
+        DirectionalLight light = directional.lights[i];
+        vec3 light_dir = normalize(-(view * vec3(light.direction_x, light.direction_y, light.direction_z)));
+        vec3 halfway = normalize(view_dir + light_dir);
+        vec3 diffuse = vec3(light.color_diffuse_r, light.color_diffuse_g, light.color_diffuse_b);
+        highlight += diffuse * pow(max(dot(normal, halfway), 0.0), 128.0);
+    }
+    return color * (1.0 - 0.4 * wetness) + highlight * wetness;
+}
+
+// Fade the color into the fog of the weather. The background is not fogged here, the clear color
+// already contains the fog.
+vec3 apply_fog(vec3 color, vec3 position) {
+    vec3 fog_color = vec3(uniforms.fog_r, uniforms.fog_g, uniforms.fog_b);
+    return mix(fog_color, color, exp(-uniforms.fog_density * length(position)));
+}
+
+vec3 phong_color(vec4 ambient, vec4 diffuse, vec4 specular, vec3 normal, vec3 position) {
+    vec3 view_dir = normalize(-position);
+    mat3 view = mat3(uniforms.view);
+
+    vec3 result = vec3(0.0);
+    for (int i = 0; i < uniforms.directional_count; i++) {
+        DirectionalLight light = directional.lights[i];
+        vec3 light_dir = normalize(-(view * vec3(light.direction_x, light.direction_y, light.direction_z)));
+        float diff = max(dot(normal, light_dir), 0.0);
+        vec3 reflect_dir = reflect(-light_dir, normal);
+        float spec = pow(max(dot(view_dir, reflect_dir), 0.0), specular.a);
+
+        result += vec3(light.color_ambient_r, light.color_ambient_g, light.color_ambient_b) * ambient.rgb;
+        result += vec3(light.color_diffuse_r, light.color_diffuse_g, light.color_diffuse_b) * diff * diffuse.rgb;
+        result += vec3(light.color_specular_r, light.color_specular_g, light.color_specular_b) * spec * specular.rgb;
+    }
+
+    for (int i = 0; i < uniforms.point_count; i++) {
+        PointLight light = point.lights[i];
+        vec3 light_position = (uniforms.view * vec4(light.position_x, light.position_y, light.position_z, 1.0)).xyz;
+        vec3 light_dir = normalize(light_position - position);
+        float distance = length(light_position - position);
+        float attenuation = 1.0 / (
+            light.attenuation_constant +
+            light.attenuation_linear * distance +
+            light.attenuation_quadratic * distance * distance
+        );
+        float diff = max(dot(normal, light_dir), 0.0);
+        vec3 reflect_dir = reflect(-light_dir, normal);
+        float spec = pow(max(dot(view_dir, reflect_dir), 0.0), specular.a);
+
+        result += vec3(light.color_ambient_r, light.color_ambient_g, light.color_ambient_b) * ambient.rgb * attenuation;
+        result += vec3(light.color_diffuse_r, light.color_diffuse_g, light.color_diffuse_b) * diff * diffuse.rgb * attenuation;
+        result += vec3(light.color_specular_r, light.color_specular_g, light.color_specular_b) * spec * specular.rgb * attenuation;
+    }
+
+    result += hemisphere_light(normal) * ambient.rgb;
+
+    if (uniforms.environment_intensity > 0.0) {
+        result += environment_light(diffuse.rgb, specular.rgb, specular.a, normal, position);
+    }
+    return result;
+}
+
+void main() {
+    vec4 ambient = subpassLoad(g_ambient);
+    vec4 diffuse = subpassLoad(g_diffuse);
+    if (diffuse.a == 0.0) {
+        // No model was drawn here, so the ambient buffer contains the clear color
+        f_color = vec4(ambient.rgb, 1.0);
+        return;
+    }
+    vec4 specular = subpassLoad(g_specular);
+    vec4 g_normal_value = subpassLoad(g_normal);
+    vec3 normal = normalize(g_normal_value.xyz);
+    vec3 position = view_position();
+    vec3 result;
+    if (g_normal_value.a > 0.5) {
+        result = pbr_color(ambient, diffuse.rgb, specular, normal, position);
+    } else {
+        result = phong_color(ambient, diffuse, specular, normal, position);
+    }
+    // The model glows in its own color, see `ModelData::highlight`
+    result += diffuse.rgb * (diffuse.a - 1.0);
+    result = wet_surface(result, normal, normalize(-position));
+    f_color = vec4(apply_fog(result, position), 1.0);
+}
+"
+    }
+}
@@ -0,0 +1,138 @@
+use vulkano::instance::{PhysicalDevice, PhysicalDeviceType};
+
+/// Which GPU the game is rendered on, when the computer has more than one. This can be set with [WindowBuilder::with_device_preference](../struct.WindowBuilder.html#method.with_device_preference).
+///
+/// Devices without a graphics queue are never picked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DevicePreference {
+    /// Prefer a discrete GPU, then an integrated GPU, then any other device. This is the default.
+    Discrete,
+    /// Prefer an integrated GPU, then a discrete GPU, then any other device. Integrated GPUs are slower, but use less power, which makes laptops last longer on battery.
+    Integrated,
+    /// Pick the device with the given index, in the order that the devices are printed when the window is created. Creating the window fails when there is no such device.
+    Index(usize),
+    /// Pick the first device whose name contains the given text, ignoring case, e.g. `"nvidia"`. Creating the window fails when there is no such device.
+    Name(String),
+}
+
+impl Default for DevicePreference {
+    fn default() -> Self {
+        DevicePreference::Discrete
+    }
+}
+
+impl DevicePreference {
+    /// Pick one of the given devices. Returns the position of the device in `devices`.
+    pub(crate) fn pick(&self, devices: &[(usize, &str, DeviceType)]) -> Option<usize> {
+        let rank_by = |order: &[DeviceType]| {
+            devices
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, (_, _, ty))| {
+                    order.iter().position(|o| o == ty).unwrap_or(order.len())
+                })
+                .map(|(position, _)| position)
+        };
+        match self {
+            DevicePreference::Discrete => rank_by(&[
+                DeviceType::Discrete,
+                DeviceType::Integrated,
+                DeviceType::Virtual,
+            ]),
+            DevicePreference::Integrated => rank_by(&[
+                DeviceType::Integrated,
+                DeviceType::Discrete,
+                DeviceType::Virtual,
+            ]),
+            DevicePreference::Index(index) => devices.iter().position(|(i, _, _)| i == index),
+            DevicePreference::Name(name) => {
+                let name = name.to_lowercase();
+                devices
+                    .iter()
+                    .position(|(_, n, _)| n.to_lowercase().contains(&name))
+            }
+        }
+    }
+}
+
+/// The kind of GPU that the game is rendered on, see [DeviceInfo].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceType {
+    /// A GPU that is a separate card, these are usually the fastest.
+    Discrete,
+    /// A GPU that is built into the CPU.
+    Integrated,
+    /// A GPU of a virtual machine.
+    Virtual,
+    /// Vulkan running on the CPU, e.g. lavapipe or SwiftShader.
+    Cpu,
+    /// Any other kind of device.
+    Other,
+}
+
+impl From<PhysicalDeviceType> for DeviceType {
+    fn from(ty: PhysicalDeviceType) -> Self {
+        match ty {
+            PhysicalDeviceType::DiscreteGpu => DeviceType::Discrete,
+            PhysicalDeviceType::IntegratedGpu => DeviceType::Integrated,
+            PhysicalDeviceType::VirtualGpu => DeviceType::Virtual,
+            PhysicalDeviceType::Cpu => DeviceType::Cpu,
+            PhysicalDeviceType::Other => DeviceType::Other,
+        }
+    }
+}
+
+/// Information about the GPU that the game is running on, see [GameState::device_info](../struct.GameState.html#method.device_info).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    /// The index of the device, which can be used with [DevicePreference::Index] to pick the same device again.
+    pub index: usize,
+    /// The name of the device, e.g. `"GeForce GTX 1080"`.
+    pub name: String,
+    /// The kind of device.
+    pub device_type: DeviceType,
+    /// The PCI vendor ID, e.g. `0x10DE` for NVIDIA.
+    pub vendor_id: u32,
+    /// The PCI device ID.
+    pub device_id: u32,
+    /// The version of the driver. The format of this is specific to the vendor.
+    pub driver_version: u32,
+}
+
+impl DeviceInfo {
+    pub(crate) fn from_physical(physical: &PhysicalDevice) -> Self {
+        Self {
+            index: physical.index(),
+            name: physical.name().to_owned(),
+            device_type: physical.ty().into(),
+            vendor_id: physical.pci_vendor_id(),
+            device_id: physical.pci_device_id(),
+            driver_version: physical.driver_version(),
+        }
+    }
+}
+
+#[test]
+fn test_device_preference() {
+    let devices = [
+        (0, "Intel(R) UHD Graphics 630", DeviceType::Integrated),
+        (1, "llvmpipe (LLVM 10.0.0, 256 bits)", DeviceType::Cpu),
+        (2, "GeForce GTX 1080", DeviceType::Discrete),
+    ];
+    assert_eq!(Some(2), DevicePreference::Discrete.pick(&devices));
+    assert_eq!(Some(0), DevicePreference::Integrated.pick(&devices));
+    assert_eq!(Some(1), DevicePreference::Index(1).pick(&devices));
+    assert_eq!(None, DevicePreference::Index(3).pick(&devices));
+    assert_eq!(
+        Some(2),
+        DevicePreference::Name("geforce".to_owned()).pick(&devices)
+    );
+    assert_eq!(
+        None,
+        DevicePreference::Name("radeon".to_owned()).pick(&devices)
+    );
+
+    // Without a GPU, the first other device is picked
+    assert_eq!(Some(0), DevicePreference::Discrete.pick(&devices[1..2]));
+    assert_eq!(None, DevicePreference::Discrete.pick(&[]));
+}
@@ -0,0 +1,600 @@
+use super::{
+    pipeline::HDR_FORMAT,
+    post_processing::{PostEffect, PostProcessingState},
+    tonemap::Pipeline as TonemapPipeline,
+};
+use std::sync::Arc;
+use vulkano::{
+    buffer::{BufferUsage, CpuAccessibleBuffer, CpuBufferPool},
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    descriptor::descriptor_set::{PersistentDescriptorSet, StdDescriptorPool},
+    device::Device,
+    format::{ClearValue, R32Sfloat},
+    framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract, Subpass},
+    image::{attachment::AttachmentImage, ImageAccess, ImageViewAccess, StorageImage},
+    pipeline::{viewport::Viewport, GraphicsPipeline, GraphicsPipelineAbstract},
+    sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
+};
+
+/// The passes of the effect shader, see `fs`
+const PASS_COPY: i32 = 0;
+const PASS_BRIGHT: i32 = 1;
+const PASS_BLUR: i32 = 2;
+const PASS_BLOOM: i32 = 3;
+const PASS_VIGNETTE: i32 = 4;
+const PASS_FXAA: i32 = 5;
+const PASS_COLOR_GRADING: i32 = 6;
+
+/// An image that an effect is rendered into
+struct Target {
+    image: Arc<AttachmentImage>,
+    framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+}
+
+/// The images that the effects are rendered into. The effects ping-pong between the two full size images, the bloom is blurred in the two half size images.
+struct Targets {
+    size: [u32; 2],
+    full: [Target; 2],
+    half: [Target; 2],
+    full_state: DynamicState,
+    half_state: DynamicState,
+}
+
+/// Applies the effects of [PostProcessingState::effects].
+///
+/// Without effects, the HDR image of the scene is tonemapped straight into the swapchain image. With effects, it is tonemapped into an offscreen image first, every effect is rendered into the next offscreen image, and the result is copied into the swapchain image.
+///
+/// [PostProcessingState::effects]: ../post_processing/struct.PostProcessingState.html#structfield.effects
+pub struct Pipeline {
+    device: Arc<Device>,
+    render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    /// The pipeline that renders into the offscreen images
+    pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    /// The pipeline that copies the result into the swapchain image
+    present_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    tonemap_pipeline: TonemapPipeline,
+    vertex_buffer: Arc<CpuAccessibleBuffer<[Vertex]>>,
+    uniform_buffer: CpuBufferPool<fs::ty::Data>,
+    sampler: Arc<Sampler>,
+    gamma_correct: bool,
+    targets: Option<Targets>,
+}
+
+impl Pipeline {
+    /// Create the pipeline. If `gamma_correct` is true, the copy into the swapchain image converts the output to sRGB. This is needed when the swapchain does not have an sRGB format.
+    pub fn create(
+        device: Arc<Device>,
+        present_subpass: Subpass<Arc<dyn RenderPassAbstract + Send + Sync>>,
+        gamma_correct: bool,
+    ) -> Self {
+        let render_pass: Arc<dyn RenderPassAbstract + Send + Sync> = Arc::new(
+            vulkano::single_pass_renderpass!(device.clone(),
+                attachments: {
+                    color: {
+                        load: DontCare,
+                        store: Store,
+                        format: HDR_FORMAT,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color],
+                    depth_stencil: {}
+                }
+            )
+            .unwrap(), // should never fail because the device should be valid and the parameters are hard-coded
+        );
+        // The subpass is hard-coded above, so this should never fail
+        let subpass = Subpass::from(render_pass.clone(), 0).unwrap();
+
+        // These should never fail, as the shaders are hard-coded and the device is assumed to be
+        // valid.
+        let vs = vs::Shader::load(device.clone()).expect("failed to create shader module");
+        let fs = fs::Shader::load(device.clone()).expect("failed to create shader module");
+        let build = |subpass: Subpass<Arc<dyn RenderPassAbstract + Send + Sync>>| -> Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
+            Arc::new(
+                GraphicsPipeline::start()
+                    .vertex_input_single_buffer::<Vertex>()
+                    .vertex_shader(vs.main_entry_point(), ())
+                    .viewports_dynamic_scissors_irrelevant(1)
+                    .fragment_shader(fs.main_entry_point(), ())
+                    .render_pass(subpass)
+                    .build(device.clone())
+                    // This should never fail because all arguments are hard-coded
+                    .unwrap(),
+            )
+        };
+        let pipeline = build(subpass.clone());
+        let present_pipeline = build(present_subpass);
+        // The offscreen images are in linear space, so only the copy into the swapchain image is
+        // gamma corrected
+        let tonemap_pipeline = TonemapPipeline::create(device.clone(), subpass, false);
+
+        // A single triangle that covers the entire screen
+        let vertex_buffer = CpuAccessibleBuffer::from_iter(
+            device.clone(),
+            BufferUsage::all(),
+            false,
+            [
+                Vertex {
+                    position: [-1.0, -1.0],
+                },
+                Vertex {
+                    position: [3.0, -1.0],
+                },
+                Vertex {
+                    position: [-1.0, 3.0],
+                },
+            ]
+            .iter()
+            .cloned(),
+        )
+        // This should never fail because the arguments are hard-coded
+        .unwrap();
+
+        // The blur and the anti-aliasing sample outside of the screen at the edges, which should
+        // repeat the edge instead of wrapping around
+        let sampler = Sampler::new(
+            device.clone(),
+            Filter::Linear,
+            Filter::Linear,
+            MipmapMode::Nearest,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            0.0,
+            1.0,
+            0.0,
+            0.0,
+        )
+        // This should never fail because the arguments are hard-coded
+        .unwrap();
+
+        Self {
+            uniform_buffer: CpuBufferPool::uniform_buffer(device.clone()),
+            device,
+            render_pass,
+            pipeline,
+            present_pipeline,
+            tonemap_pipeline,
+            vertex_buffer,
+            sampler,
+            gamma_correct,
+            targets: None,
+        }
+    }
+
+    /// Tonemap the HDR image and apply every effect. This has to be called outside of a render pass. Returns the image with the result, which is drawn with [present](#method.present).
+    pub fn apply(
+        &mut self,
+        hdr_buffer: Arc<AttachmentImage>,
+        auto_exposure: Arc<StorageImage<R32Sfloat>>,
+        settings: &PostProcessingState,
+        command_buffer_builder: &mut AutoCommandBufferBuilder,
+        descriptor_pool: &mut Arc<StdDescriptorPool>,
+    ) -> Arc<AttachmentImage> {
+        let size = hdr_buffer.dimensions().width_height();
+        if self.targets.as_ref().map(|t| t.size) != Some(size) {
+            self.targets = Some(self.create_targets(size));
+        }
+        // The targets were created above, so this should never fail
+        let targets = self.targets.as_ref().unwrap();
+
+        begin(command_buffer_builder, &targets.full[0]);
+        self.tonemap_pipeline.render(
+            hdr_buffer,
+            auto_exposure,
+            settings,
+            size,
+            command_buffer_builder,
+            &targets.full_state,
+            descriptor_pool,
+        );
+        end(command_buffer_builder);
+
+        let mut current = 0;
+        for effect in &settings.effects {
+            let source = targets.full[current].image.clone();
+            let next = &targets.full[1 - current];
+            match effect {
+                PostEffect::Bloom(bloom) => {
+                    let radius = bloom.radius.max(0.0);
+                    let half = &targets.half;
+                    let passes = [
+                        (PASS_BRIGHT, source.clone(), &half[0], [0.0, 0.0]),
+                        (PASS_BLUR, half[0].image.clone(), &half[1], [radius, 0.0]),
+                        (PASS_BLUR, half[1].image.clone(), &half[0], [0.0, radius]),
+                    ];
+                    for (pass, pass_source, target, direction) in passes.iter() {
+                        let data = fs::ty::Data {
+                            threshold: bloom.threshold.max(0.0),
+                            direction_x: direction[0],
+                            direction_y: direction[1],
+                            ..pass_data(*pass, target.image.dimensions().width_height())
+                        };
+                        begin(command_buffer_builder, target);
+                        self.draw(
+                            &self.pipeline,
+                            data,
+                            pass_source.clone(),
+                            pass_source.clone(),
+                            command_buffer_builder,
+                            &targets.half_state,
+                            descriptor_pool,
+                        );
+                        end(command_buffer_builder);
+                    }
+                    let data = fs::ty::Data {
+                        intensity: bloom.intensity.max(0.0),
+                        ..pass_data(PASS_BLOOM, size)
+                    };
+                    self.pass(
+                        next,
+                        data,
+                        source,
+                        half[0].image.clone(),
+                        command_buffer_builder,
+                        descriptor_pool,
+                    );
+                }
+                PostEffect::Vignette(vignette) => {
+                    let data = fs::ty::Data {
+                        intensity: vignette.intensity.max(0.0).min(1.0),
+                        radius: vignette.radius,
+                        smoothness: vignette.smoothness.max(0.001),
+                        ..pass_data(PASS_VIGNETTE, size)
+                    };
+                    self.pass(
+                        next,
+                        data,
+                        source.clone(),
+                        source,
+                        command_buffer_builder,
+                        descriptor_pool,
+                    );
+                }
+                PostEffect::Fxaa => {
+                    let data = pass_data(PASS_FXAA, size);
+                    self.pass(
+                        next,
+                        data,
+                        source.clone(),
+                        source,
+                        command_buffer_builder,
+                        descriptor_pool,
+                    );
+                }
+                PostEffect::ColorGrading(grading) => {
+                    let data = fs::ty::Data {
+                        intensity: grading.intensity.max(0.0).min(1.0),
+                        lut_size: grading.lut.size as f32,
+                        ..pass_data(PASS_COLOR_GRADING, size)
+                    };
+                    self.pass(
+                        next,
+                        data,
+                        source,
+                        grading.lut.texture.clone(),
+                        command_buffer_builder,
+                        descriptor_pool,
+                    );
+                }
+            }
+            current = 1 - current;
+        }
+        targets.full[current].image.clone()
+    }
+
+    /// Copy the result of [apply](#method.apply) into the swapchain image, which has the given size. This has to be called inside of the present render pass.
+    pub fn present(
+        &self,
+        image: Arc<AttachmentImage>,
+        output_size: [u32; 2],
+        command_buffer_builder: &mut AutoCommandBufferBuilder,
+        dynamic_state: &DynamicState,
+        descriptor_pool: &mut Arc<StdDescriptorPool>,
+    ) {
+        let data = fs::ty::Data {
+            gamma_correct: self.gamma_correct as i32,
+            ..pass_data(PASS_COPY, output_size)
+        };
+        self.draw(
+            &self.present_pipeline,
+            data,
+            image.clone(),
+            image,
+            command_buffer_builder,
+            dynamic_state,
+            descriptor_pool,
+        );
+    }
+
+    fn create_targets(&self, size: [u32; 2]) -> Targets {
+        let half_size = [(size[0] / 2).max(1), (size[1] / 2).max(1)];
+        let target = |size| {
+            // These should always be valid as long as the device is valid
+            let image = AttachmentImage::sampled(self.device.clone(), size, HDR_FORMAT).unwrap();
+            let framebuffer = Arc::new(
+                Framebuffer::start(self.render_pass.clone())
+                    .add(image.clone())
+                    .and_then(|f| f.build())
+                    // The image matches the render pass, so this should never fail
+                    .unwrap(),
+            );
+            Target { image, framebuffer }
+        };
+        let state = |size: [u32; 2]| {
+            let mut state = DynamicState::none();
+            state.viewports = Some(vec![Viewport {
+                origin: [0.0, 0.0],
+                dimensions: [size[0] as f32, size[1] as f32],
+                depth_range: 0.0..1.0,
+            }]);
+            state
+        };
+        Targets {
+            size,
+            full: [target(size), target(size)],
+            half: [target(half_size), target(half_size)],
+            full_state: state(size),
+            half_state: state(half_size),
+        }
+    }
+
+    /// Render a full size pass into `target`
+    fn pass(
+        &self,
+        target: &Target,
+        data: fs::ty::Data,
+        source: Arc<dyn ImageViewAccess + Send + Sync>,
+        extra: Arc<dyn ImageViewAccess + Send + Sync>,
+        command_buffer_builder: &mut AutoCommandBufferBuilder,
+        descriptor_pool: &mut Arc<StdDescriptorPool>,
+    ) {
+        // The targets are created before any pass is rendered, so this should never fail
+        let targets = self.targets.as_ref().unwrap();
+        begin(command_buffer_builder, target);
+        self.draw(
+            &self.pipeline,
+            data,
+            source,
+            extra,
+            command_buffer_builder,
+            &targets.full_state,
+            descriptor_pool,
+        );
+        end(command_buffer_builder);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn draw(
+        &self,
+        pipeline: &Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+        data: fs::ty::Data,
+        source: Arc<dyn ImageViewAccess + Send + Sync>,
+        extra: Arc<dyn ImageViewAccess + Send + Sync>,
+        command_buffer_builder: &mut AutoCommandBufferBuilder,
+        dynamic_state: &DynamicState,
+        descriptor_pool: &mut Arc<StdDescriptorPool>,
+    ) {
+        // The uniform_buffer is assumed to be valid so this should never fail
+        let uniform_buffer_subbuffer = self.uniform_buffer.next(data).unwrap();
+
+        // The pipeline and the layout index are hard-coded so this is assumed to never fail
+        let layout = pipeline.descriptor_set_layout(0).unwrap();
+        let set = Arc::new(
+            PersistentDescriptorSet::start(layout.clone())
+                .add_sampled_image(source, self.sampler.clone())
+                // The images are created as sampled images so these should never fail
+                .unwrap()
+                .add_sampled_image(extra, self.sampler.clone())
+                .unwrap()
+                .add_buffer(uniform_buffer_subbuffer)
+                // The uniform subbuffer is assumed to be valid so this should never fail
+                .unwrap()
+                .build_with_pool(descriptor_pool)
+                // The pool is assumed to be valid so this should never fail
+                .unwrap(),
+        );
+
+        command_buffer_builder
+            .draw(
+                pipeline.clone(),
+                dynamic_state,
+                vec![self.vertex_buffer.clone()],
+                set,
+                (),
+            )
+            // the builder and arguments are assumed to be valid so this should never fail
+            .unwrap();
+    }
+}
+
+/// The settings of a pass into an image of the given size, without the settings of an effect
+fn pass_data(pass: i32, size: [u32; 2]) -> fs::ty::Data {
+    fs::ty::Data {
+        pass,
+        gamma_correct: 0,
+        target_width: size[0] as f32,
+        target_height: size[1] as f32,
+        intensity: 0.0,
+        threshold: 0.0,
+        radius: 0.0,
+        smoothness: 0.0,
+        direction_x: 0.0,
+        direction_y: 0.0,
+        lut_size: 0.0,
+    }
+}
+
+fn begin(command_buffer_builder: &mut AutoCommandBufferBuilder, target: &Target) {
+    command_buffer_builder
+        .begin_render_pass(target.framebuffer.clone(), false, vec![ClearValue::None])
+        .unwrap(); // This can only error if we're in the wrong state of the command buffer, and the state is hard-coded
+}
+
+fn end(command_buffer_builder: &mut AutoCommandBufferBuilder) {
+    command_buffer_builder.end_render_pass().unwrap(); // This can only error if we're in the wrong state of the command buffer, and the state is hard-coded
+}
+
+#[derive(Default, Debug, Clone)]
+struct Vertex {
+    position: [f32; 2],
+}
+
+vulkano::impl_vertex!(Vertex, position);
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "#version 450
+
+layout(location = 0) in vec2 position;
+
+void main() {
+    gl_Position = vec4(position, 0.0, 1.0);
+}
+"
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "#version 450
+
+layout(set = 0, binding = 0) uniform sampler2D source;
+// The bloom of the bloom pass, or the lookup table of the color grading
+layout(set = 0, binding = 1) uniform sampler2D extra;
+layout(set = 0, binding = 2) uniform Data {
+    int pass;
+    int gamma_correct;
+    float target_width;
+    float target_height;
+    float intensity;
+    float threshold;
+    float radius;
+    float smoothness;
+    float direction_x;
+    float direction_y;
+    float lut_size;
+} uniforms;
+
+layout(location = 0) out vec4 f_color;
+
+// Keep the part of the color that is brighter than the threshold, with a soft knee so colors just
+// over the threshold do not pop in
+vec3 bright(vec2 uv) {
+    vec3 color = texture(source, uv).rgb;
+    float brightness = max(color.r, max(color.g, color.b));
+    float knee = uniforms.threshold * 0.5 + 0.00001;
+    float soft = clamp(brightness - uniforms.threshold + knee, 0.0, 2.0 * knee);
+    soft = soft * soft / (4.0 * knee);
+    return color * max(soft, brightness - uniforms.threshold) / max(brightness, 0.00001);
+}
+
+// A 9 tap gaussian blur in a single direction, which uses linear filtering to read two pixels
+// with every sample
+vec3 blur(vec2 uv) {
+    vec2 offset = vec2(uniforms.direction_x, uniforms.direction_y) / vec2(textureSize(source, 0));
+    vec3 color = texture(source, uv).rgb * 0.2270270270;
+    color += texture(source, uv + offset * 1.3846153846).rgb * 0.3162162162;
+    color += texture(source, uv - offset * 1.3846153846).rgb * 0.3162162162;
+    color += texture(source, uv + offset * 3.2307692308).rgb * 0.0702702703;
+    color += texture(source, uv - offset * 3.2307692308).rgb * 0.0702702703;
+    return color;
+}
+
+vec3 vignette(vec2 uv) {
+    // The distance to the center of the screen, which is 1 in the corners
+    float from_center = length(uv - 0.5) * 1.41421356;
+    float shade = 1.0 - smoothstep(uniforms.radius, uniforms.radius + uniforms.smoothness, from_center);
+    return texture(source, uv).rgb * mix(1.0, shade, uniforms.intensity);
+}
+
+// Edges are found by the perceived brightness, which is closer to sRGB than to linear colors
+float luma(vec3 color) {
+    return dot(sqrt(max(color, 0.0)), vec3(0.299, 0.587, 0.114));
+}
+
+// The console version of FXAA by Timothy Lottes, which blurs along the edges it finds
+vec3 fxaa(vec2 uv) {
+    const float REDUCE_MIN = 1.0 / 128.0;
+    const float REDUCE_MUL = 1.0 / 8.0;
+    const float SPAN_MAX = 8.0;
+    vec2 texel = 1.0 / vec2(textureSize(source, 0));
+
+    vec3 center = texture(source, uv).rgb;
+    float luma_nw = luma(texture(source, uv + vec2(-1.0, -1.0) * texel).rgb);
+    float luma_ne = luma(texture(source, uv + vec2(1.0, -1.0) * texel).rgb);
+    float luma_sw = luma(texture(source, uv + vec2(-1.0, 1.0) * texel).rgb);
+    float luma_se = luma(texture(source, uv + vec2(1.0, 1.0) * texel).rgb);
+    float luma_m = luma(center);
+    float luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    float luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    vec2 direction = vec2(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se)
+    );
+    float reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * REDUCE_MUL, REDUCE_MIN);
+    float scale = 1.0 / (min(abs(direction.x), abs(direction.y)) + reduce);
+    direction = clamp(direction * scale, vec2(-SPAN_MAX), vec2(SPAN_MAX)) * texel;
+
+    vec3 inner = 0.5 * (
+        texture(source, uv + direction * (1.0 / 3.0 - 0.5)).rgb +
+        texture(source, uv + direction * (2.0 / 3.0 - 0.5)).rgb
+    );
+    vec3 outer = inner * 0.5 + 0.25 * (
+        texture(source, uv - direction * 0.5).rgb +
+        texture(source, uv + direction * 0.5).rgb
+    );
+    float luma_outer = luma(outer);
+    return luma_outer < luma_min || luma_outer > luma_max ? inner : outer;
+}
+
+// Look up the color in the lookup table, see `ColorLut` for the layout
+vec3 color_grading(vec2 uv) {
+    vec3 color = clamp(texture(source, uv).rgb, 0.0, 1.0);
+    // The lookup table is indexed by sRGB colors, and is an sRGB texture so the result is linear
+    vec3 srgb = pow(color, vec3(1.0 / 2.2));
+    float size = uniforms.lut_size;
+    float blue = srgb.b * (size - 1.0);
+    float slice = floor(blue);
+    float next_slice = min(slice + 1.0, size - 1.0);
+    vec2 position = vec2(
+        (srgb.r * (size - 1.0) + 0.5) / (size * size),
+        (srgb.g * (size - 1.0) + 0.5) / size
+    );
+    vec3 low = texture(extra, position + vec2(slice / size, 0.0)).rgb;
+    vec3 high = texture(extra, position + vec2(next_slice / size, 0.0)).rgb;
+    return mix(color, mix(low, high, blue - slice), uniforms.intensity);
+}
+
+void main() {
+    vec2 uv = gl_FragCoord.xy / vec2(uniforms.target_width, uniforms.target_height);
+    vec3 color;
+    if (uniforms.pass == 1) {
+        color = bright(uv);
+    } else if (uniforms.pass == 2) {
+        color = blur(uv);
+    } else if (uniforms.pass == 3) {
+        color = texture(source, uv).rgb + texture(extra, uv).rgb * uniforms.intensity;
+    } else if (uniforms.pass == 4) {
+        color = vignette(uv);
+    } else if (uniforms.pass == 5) {
+        color = fxaa(uv);
+    } else if (uniforms.pass == 6) {
+        color = color_grading(uv);
+    } else {
+        color = texture(source, uv).rgb;
+    }
+
+    if (uniforms.gamma_correct != 0) {
+        color = pow(clamp(color, 0.0, 1.0), vec3(1.0 / 2.2));
+    }
+    f_color = vec4(color, 1.0);
+}
+"
+    }
+}
@@ -0,0 +1,356 @@
+//! Image based lighting. An environment cubemap is convolved into an irradiance map, which lights models from every direction, and a set of prefiltered specular maps, which are reflected by shiny models. This is done once on the CPU when the environment map is loaded.
+
+use super::texture::{submit_upload, Texture};
+use crate::state::{EnvironmentMapError, UploadError};
+use parking_lot::RwLock;
+use std::{ops::Range, path::Path, sync::Arc};
+use vulkano::{
+    device::{Device, Queue},
+    format::Format,
+    image::{Dimensions, ImmutableImage},
+    sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
+    sync::GpuFuture,
+};
+
+/// The size of the faces of the irradiance map. The irradiance changes slowly, so this can be tiny.
+const IRRADIANCE_SIZE: usize = 8;
+/// The size and the Phong exponent of the prefiltered specular maps. The first map is the environment itself, for the shiniest materials.
+///
+/// The shaders pick a map based on the shininess of the material, level `i` is used for a shininess of `256 / 4^i`.
+const SPECULAR_LEVELS: [(usize, Option<f32>); 4] = [
+    (128, None),
+    (32, Some(64.0)),
+    (16, Some(16.0)),
+    (8, Some(4.0)),
+];
+/// The format of the cubemaps. Lighting is done in linear space, so 8 bits per channel is not enough.
+const FORMAT: Format = Format::R16G16B16A16Sfloat;
+
+/// A cubemap that lights the models in the world, and is reflected by shiny models. This is created with [GameState::load_environment_map], and enabled by setting [LightState::environment].
+///
+/// This is cheap to clone, the cubemaps are shared between clones.
+///
+/// [GameState::load_environment_map]: ../struct.GameState.html#method.load_environment_map
+/// [LightState::environment]: ./struct.LightState.html#structfield.environment
+#[derive(Clone)]
+pub struct EnvironmentMap {
+    pub(crate) irradiance: Texture,
+    pub(crate) specular: [Texture; 4],
+    /// The upload of the cubemaps, which has to finish before the first frame that uses them
+    upload: Arc<RwLock<Vec<Box<dyn GpuFuture>>>>,
+}
+
+impl EnvironmentMap {
+    /// Load the six faces of an environment cubemap, in the order +X, -X, +Y, -Y, +Z, -Z. The faces have to be square and have the same size. The pixels are treated as sRGB colors.
+    pub(crate) fn load(queue: Arc<Queue>, faces: [&Path; 6]) -> Result<Self, EnvironmentMapError> {
+        let mut size = None;
+        let mut texels = Vec::with_capacity(6);
+        for path in faces.iter() {
+            let image = image::open(path)
+                .map_err(|inner| EnvironmentMapError::CouldNotLoadImage {
+                    path: path.display().to_string(),
+                    inner,
+                })?
+                .into_rgb();
+            let (width, height) = image.dimensions();
+            if width != height || width != *size.get_or_insert(width) || width == 0 {
+                return Err(EnvironmentMapError::InvalidFaceSize {
+                    path: path.display().to_string(),
+                    width,
+                    height,
+                });
+            }
+            texels.push(
+                image
+                    .pixels()
+                    .map(|p| {
+                        [
+                            srgb_to_linear(p[0]),
+                            srgb_to_linear(p[1]),
+                            srgb_to_linear(p[2]),
+                        ]
+                    })
+                    .collect(),
+            );
+        }
+        let environment = Cubemap {
+            // Every face is checked above, so this should never fail
+            size: size.unwrap() as usize,
+            faces: texels,
+        };
+        Self::from_cubemap(queue, &environment)
+            .map_err(|inner| EnvironmentMapError::CouldNotUpload { inner })
+    }
+
+    /// An environment that is completely black, which is bound when the game has no environment map.
+    pub(crate) fn empty(queue: Arc<Queue>) -> Self {
+        let black = Cubemap {
+            size: 1,
+            faces: vec![vec![[0.0; 3]]; 6],
+        };
+        // The cubemaps are a single texel, and this is created when the window is created, so this
+        // should never fail
+        Self::from_cubemap(queue, &black).unwrap()
+    }
+
+    fn from_cubemap(queue: Arc<Queue>, environment: &Cubemap) -> Result<Self, UploadError> {
+        let mut upload = Vec::new();
+        let mut create = |cubemap: &Cubemap| -> Result<Texture, UploadError> {
+            let (texture, future) = cubemap.upload(queue.clone())?;
+            upload.push(future);
+            Ok(texture)
+        };
+        let irradiance = create(&environment.convolve(IRRADIANCE_SIZE, 1.0))?;
+        let specular = array_init::try_array_init(|level| {
+            let (size, exponent) = SPECULAR_LEVELS[level];
+            let size = size.min(environment.size);
+            match exponent {
+                Some(exponent) => create(&environment.convolve(size, exponent)),
+                None => create(&environment.downsample(size)),
+            }
+        })?;
+        Ok(Self {
+            irradiance,
+            specular,
+            upload: Arc::new(RwLock::new(upload)),
+        })
+    }
+
+    /// Take the futures of the upload of the cubemaps, if they are not joined into a frame yet.
+    pub(crate) fn take_upload_futures(&self) -> Vec<Box<dyn GpuFuture>> {
+        if self.upload.read().is_empty() {
+            return Vec::new();
+        }
+        std::mem::replace(&mut *self.upload.write(), Vec::new())
+    }
+}
+
+/// The sampler of the environment cubemaps.
+pub(crate) fn sampler(device: Arc<Device>) -> Arc<Sampler> {
+    Sampler::new(
+        device,
+        Filter::Linear,
+        Filter::Linear,
+        MipmapMode::Nearest,
+        SamplerAddressMode::ClampToEdge,
+        SamplerAddressMode::ClampToEdge,
+        SamplerAddressMode::ClampToEdge,
+        0.0,
+        1.0,
+        0.0,
+        0.0,
+    )
+    // The arguments are hard-coded so this is assumed to never fail
+    .unwrap()
+}
+
+/// The linear colors of the six faces of a cubemap, in the order +X, -X, +Y, -Y, +Z, -Z. Every face is stored row by row, starting at the top.
+struct Cubemap {
+    size: usize,
+    faces: Vec<Vec<[f32; 3]>>,
+}
+
+impl Cubemap {
+    /// Shrink every face to `size`, by averaging the texels that each new texel covers.
+    fn downsample(&self, size: usize) -> Cubemap {
+        let faces = self
+            .faces
+            .iter()
+            .map(|face| {
+                let mut result = Vec::with_capacity(size * size);
+                for y in 0..size {
+                    let rows = footprint(y, self.size, size);
+                    for x in 0..size {
+                        let columns = footprint(x, self.size, size);
+                        let mut sum = [0.0; 3];
+                        for row in rows.clone() {
+                            let start = row * self.size;
+                            for texel in &face[start + columns.start..start + columns.end] {
+                                for (sum, value) in sum.iter_mut().zip(texel) {
+                                    *sum += value;
+                                }
+                            }
+                        }
+                        let count = (rows.len() * columns.len()) as f32;
+                        result.push([sum[0] / count, sum[1] / count, sum[2] / count]);
+                    }
+                }
+                result
+            })
+            .collect();
+        Cubemap { size, faces }
+    }
+
+    /// Convolve the cubemap with a Phong lobe, into a cubemap of the given size. An exponent of `1.0` is a cosine lobe, which gives the irradiance of a surface with that normal.
+    ///
+    /// The environment is downsampled to the new size first, to keep the amount of work reasonable.
+    fn convolve(&self, size: usize, exponent: f32) -> Cubemap {
+        let downsampled;
+        let source = if self.size > size {
+            downsampled = self.downsample(size);
+            &downsampled
+        } else {
+            self
+        };
+        // Every texel of the source, with its direction and its radiance weighted by the solid
+        // angle that it covers
+        let samples = (0..6)
+            .flat_map(|face| (0..source.size * source.size).map(move |index| (face, index)))
+            .map(|(face, index)| {
+                let (x, y) = (index % source.size, index / source.size);
+                let (direction, solid_angle) = texel_direction(face, x, y, source.size);
+                (direction, solid_angle, source.faces[face][index])
+            })
+            .collect::<Vec<_>>();
+
+        let faces = (0..6)
+            .map(|face| {
+                (0..size * size)
+                    .map(|index| {
+                        let (normal, _) = texel_direction(face, index % size, index / size, size);
+                        let mut sum = [0.0; 3];
+                        let mut total_weight = 0.0;
+                        for (direction, solid_angle, radiance) in &samples {
+                            let cos = dot(normal, *direction);
+                            if cos <= 0.0 {
+                                continue;
+                            }
+                            let weight = cos.powf(exponent) * solid_angle;
+                            for (sum, value) in sum.iter_mut().zip(radiance) {
+                                *sum += value * weight;
+                            }
+                            total_weight += weight;
+                        }
+                        if total_weight > 0.0 {
+                            [
+                                sum[0] / total_weight,
+                                sum[1] / total_weight,
+                                sum[2] / total_weight,
+                            ]
+                        } else {
+                            sum
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        Cubemap { size, faces }
+    }
+
+    fn upload(&self, queue: Arc<Queue>) -> Result<(Texture, Box<dyn GpuFuture>), UploadError> {
+        let pixels = self
+            .faces
+            .iter()
+            .flatten()
+            .flat_map(|rgb| rgb.iter().copied().chain(std::iter::once(1.0)))
+            .map(f32_to_f16)
+            .collect::<Vec<u16>>();
+        let (texture, future) = ImmutableImage::from_iter(
+            pixels.into_iter(),
+            Dimensions::Cubemap {
+                size: self.size as u32,
+            },
+            FORMAT,
+            queue.clone(),
+        )
+        .map_err(UploadError::CouldNotCreateImage)?;
+        Ok((texture, submit_upload(&queue, future)?))
+    }
+}
+
+/// The texels of a face of size `from` that are covered by texel `index` of a face of size `to`.
+fn footprint(index: usize, from: usize, to: usize) -> Range<usize> {
+    let start = index * from / to;
+    let end = ((index + 1) * from / to).max(start + 1);
+    start..end
+}
+
+/// The normalized direction of the center of a texel of a cubemap face, and the solid angle that the texel covers. This follows the face layout of the Vulkan specification.
+fn texel_direction(face: usize, x: usize, y: usize, size: usize) -> ([f32; 3], f32) {
+    let s = (x as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+    let t = (y as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+    let direction = match face {
+        0 => [1.0, -t, -s],
+        1 => [-1.0, -t, s],
+        2 => [s, 1.0, t],
+        3 => [s, -1.0, -t],
+        4 => [s, -t, 1.0],
+        _ => [-s, -t, -1.0],
+    };
+    let length = dot(direction, direction).sqrt();
+    // The texel covers (2 / size)^2 of the face at a distance of 1, and is seen at an angle
+    let texel_area = (2.0 / size as f32) * (2.0 / size as f32);
+    let solid_angle = texel_area / (length * length * length);
+    (
+        [
+            direction[0] / length,
+            direction[1] / length,
+            direction[2] / length,
+        ],
+        solid_angle,
+    )
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Convert a float to a half float. Values that are too large for a half float are clamped, and values that are too small become zero.
+fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xFF) as i32 - 127 + 15;
+    let mantissa = bits & 0x007F_FFFF;
+    if value.is_nan() {
+        sign | 0x7E00
+    } else if exponent >= 0x1F {
+        // The largest finite half float
+        sign | 0x7BFF
+    } else if exponent <= 0 {
+        sign
+    } else {
+        sign | ((exponent as u16) << 10) | (mantissa >> 13) as u16
+    }
+}
+
+#[test]
+fn test_environment_convolution() {
+    assert_eq!(0x3C00, f32_to_f16(1.0));
+    assert_eq!(0xC000, f32_to_f16(-2.0));
+    assert_eq!(0x3800, f32_to_f16(0.5));
+    assert_eq!(0x7BFF, f32_to_f16(1.0e6));
+    assert_eq!(0, f32_to_f16(1.0e-9));
+
+    // The solid angles of all texels add up to a full sphere
+    let total = (0..6)
+        .flat_map(|face| (0..64).map(move |index| texel_direction(face, index % 8, index / 8, 8).1))
+        .sum::<f32>();
+    assert!((total - 4.0 * std::f32::consts::PI).abs() < 0.05);
+
+    // A bright +Y face lights surfaces that face up, the upper half of surfaces that face
+    // sideways a bit less, and surfaces that face down not at all
+    let mut faces = vec![vec![[0.0; 3]; 16]; 6];
+    faces[2] = vec![[1.0; 3]; 16];
+    let environment = Cubemap { size: 4, faces };
+    let irradiance = environment.convolve(2, 1.0);
+    let (up, side_top, side_bottom) = (
+        irradiance.faces[2][0][0],
+        irradiance.faces[0][0][0],
+        irradiance.faces[0][2][0],
+    );
+    assert!(up > side_top && side_top > side_bottom && side_bottom > 0.0);
+    assert_eq!([0.0; 3], irradiance.faces[3][0]);
+
+    let downsampled = environment.downsample(1);
+    assert_eq!([1.0; 3], downsampled.faces[2][0]);
+    assert_eq!([0.0; 3], downsampled.faces[0][0]);
+}
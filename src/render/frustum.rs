@@ -0,0 +1,71 @@
+use cgmath::{InnerSpace, Matrix, Matrix4, Vector3, Vector4};
+
+/// The volume that is visible to the camera. This is used to skip models that are not on the screen.
+pub(crate) struct Frustum {
+    /// The left, right, bottom, top, near and far planes. The normals of the planes point inwards.
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// Extract the planes of the frustum from a `projection * view` matrix.
+    pub fn from_matrix(matrix: Matrix4<f32>) -> Self {
+        let row = |i| matrix.row(i);
+        let normalize = |plane: Vector4<f32>| plane / plane.truncate().magnitude();
+        Self {
+            planes: [
+                normalize(row(3) + row(0)),
+                normalize(row(3) - row(0)),
+                normalize(row(3) + row(1)),
+                normalize(row(3) - row(1)),
+                normalize(row(3) + row(2)),
+                normalize(row(3) - row(2)),
+            ],
+        }
+    }
+
+    /// Check if an axis-aligned box, given as `(min, max)` in world space, is at least partially inside of the frustum.
+    ///
+    /// This is conservative: boxes near the corners of the frustum can be reported as visible while they are not.
+    pub fn intersects_box(&self, (min, max): (Vector3<f32>, Vector3<f32>)) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner of the box that is the furthest along the normal of the plane
+            let corner = Vector3::new(
+                if plane.x >= 0.0 { max.x } else { min.x },
+                if plane.y >= 0.0 { max.y } else { min.y },
+                if plane.z >= 0.0 { max.z } else { min.z },
+            );
+            plane.truncate().dot(corner) + plane.w >= 0.0
+        })
+    }
+}
+
+#[test]
+fn test_frustum_intersects_box() {
+    use cgmath::{Deg, Point3};
+
+    let view = Matrix4::look_at(
+        Point3::new(0.0, 0.0, 5.0),
+        Point3::new(0.0, 0.0, 0.0),
+        Vector3::unit_y(),
+    );
+    let frustum = Frustum::from_matrix(cgmath::perspective(Deg(90.0), 1.0, 0.1, 100.0) * view);
+    let unit_box = |x: f32, y: f32, z: f32| {
+        (
+            Vector3::new(x - 0.5, y - 0.5, z - 0.5),
+            Vector3::new(x + 0.5, y + 0.5, z + 0.5),
+        )
+    };
+
+    assert!(frustum.intersects_box(unit_box(0.0, 0.0, 0.0)));
+    // Behind the camera
+    assert!(!frustum.intersects_box(unit_box(0.0, 0.0, 10.0)));
+    // Too far to the side
+    assert!(!frustum.intersects_box(unit_box(20.0, 0.0, 0.0)));
+    // Beyond the far plane
+    assert!(!frustum.intersects_box(unit_box(0.0, 0.0, -200.0)));
+    // Partially visible
+    assert!(frustum.intersects_box((
+        Vector3::new(-100.0, -0.5, -0.5),
+        Vector3::new(0.0, 0.5, 0.5)
+    )));
+}
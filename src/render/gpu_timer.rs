@@ -0,0 +1,272 @@
+use crate::profiler::GpuTimings;
+use std::{ptr, sync::Arc, time::Duration};
+use vulkano::{
+    device::{Device, Queue},
+    SynchronizedVulkanObject, VulkanObject,
+};
+
+/// The amount of timestamps that are written every frame, see [GpuTimestamp]
+const TIMESTAMP_COUNT: u32 = 4;
+/// The bits of [GpuTimer::written] when every timestamp of a frame was submitted
+const ALL_WRITTEN: u8 = (1 << TIMESTAMP_COUNT) - 1;
+
+/// The points in a frame where the GPU writes a timestamp. The passes of a frame are submitted separately, and a timestamp is submitted between them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum GpuTimestamp {
+    /// Before the scene render pass, including the uploads and the reflections that are rendered before it
+    SceneStart,
+    /// Before the post-processing, which runs outside of a render pass
+    PostProcessingStart,
+    /// Before the present pass, which tonemaps the frame and draws the GUI
+    PresentStart,
+    /// After the present pass and the copies of the frame
+    End,
+}
+
+/// Measures the time the GPU spends in every pass of a frame with timestamp queries.
+///
+/// Vulkano can not write timestamps into its command buffers, so every timestamp is written by a tiny command buffer that is recorded once, and submitted to the queue between the command buffers of the passes. The results are read when a frame is started again in the same slot, which is a few frames later, so the GPU is never waited on.
+pub(crate) struct GpuTimer {
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    query_pool: vk_sys::QueryPool,
+    command_pool: vk_sys::CommandPool,
+    /// A command buffer for every query, which resets the query and writes a timestamp into it
+    command_buffers: Vec<vk_sys::CommandBuffer>,
+    /// The timestamps of every slot that were submitted, as a bit per [GpuTimestamp]. A slot is only read when all of them were submitted.
+    written: Vec<u8>,
+    /// The slot of the frame that is being rendered
+    frame: usize,
+    /// The amount of nanoseconds per tick of a timestamp
+    timestamp_period: f64,
+    /// The bits of a timestamp that are valid, the other bits are garbage
+    timestamp_mask: u64,
+}
+
+impl GpuTimer {
+    /// Create a timer with a slot for `frames` frames. This has to be more than the amount of frames that can be in flight, so a slot is only read after its frame is finished.
+    ///
+    /// Returns `None` if the queue does not support timestamps, or if the queries could not be created.
+    pub fn new(device: Arc<Device>, queue: Arc<Queue>, frames: usize) -> Option<Self> {
+        let physical = device.physical_device();
+        let timestamp_valid_bits = {
+            let instance = device.instance().pointers();
+            let mut count = 0;
+            let mut families = Vec::new();
+            unsafe {
+                instance.GetPhysicalDeviceQueueFamilyProperties(
+                    physical.internal_object(),
+                    &mut count,
+                    ptr::null_mut(),
+                );
+                families.reserve_exact(count as usize);
+                instance.GetPhysicalDeviceQueueFamilyProperties(
+                    physical.internal_object(),
+                    &mut count,
+                    families.as_mut_ptr(),
+                );
+                families.set_len(count as usize);
+            }
+            families
+                .get(queue.family().id() as usize)?
+                .timestampValidBits
+        };
+        if timestamp_valid_bits == 0 {
+            return None;
+        }
+        let timestamp_mask = match timestamp_valid_bits {
+            64 => u64::MAX,
+            bits => (1 << bits) - 1,
+        };
+        let timestamp_period = physical.limits().timestamp_period() as f64;
+
+        let mut timer = Self {
+            device,
+            queue,
+            query_pool: vk_sys::NULL_HANDLE,
+            command_pool: vk_sys::NULL_HANDLE,
+            command_buffers: Vec::new(),
+            written: vec![0; frames],
+            frame: 0,
+            timestamp_period,
+            timestamp_mask,
+        };
+        // The timer destroys the pools that were created when it is dropped, so it can be returned halfway
+        match unsafe { timer.create_queries(frames as u32 * TIMESTAMP_COUNT) } {
+            vk_sys::SUCCESS => Some(timer),
+            result => {
+                eprintln!(
+                    "Could not create the GPU timestamp queries, the GPU time will not be measured: error {}",
+                    result
+                );
+                None
+            }
+        }
+    }
+
+    /// Create the query pool and record a command buffer for every query
+    unsafe fn create_queries(&mut self, count: u32) -> vk_sys::Result {
+        let vk = self.device.pointers();
+        let device = self.device.internal_object();
+
+        let query_pool_info = vk_sys::QueryPoolCreateInfo {
+            sType: vk_sys::STRUCTURE_TYPE_QUERY_POOL_CREATE_INFO,
+            pNext: ptr::null(),
+            flags: 0,
+            queryType: vk_sys::QUERY_TYPE_TIMESTAMP,
+            queryCount: count,
+            pipelineStatistics: 0,
+        };
+        let result =
+            vk.CreateQueryPool(device, &query_pool_info, ptr::null(), &mut self.query_pool);
+        if result != vk_sys::SUCCESS {
+            return result;
+        }
+
+        let command_pool_info = vk_sys::CommandPoolCreateInfo {
+            sType: vk_sys::STRUCTURE_TYPE_COMMAND_POOL_CREATE_INFO,
+            pNext: ptr::null(),
+            flags: 0,
+            queueFamilyIndex: self.queue.family().id(),
+        };
+        let result = vk.CreateCommandPool(
+            device,
+            &command_pool_info,
+            ptr::null(),
+            &mut self.command_pool,
+        );
+        if result != vk_sys::SUCCESS {
+            return result;
+        }
+
+        let allocate_info = vk_sys::CommandBufferAllocateInfo {
+            sType: vk_sys::STRUCTURE_TYPE_COMMAND_BUFFER_ALLOCATE_INFO,
+            pNext: ptr::null(),
+            commandPool: self.command_pool,
+            level: vk_sys::COMMAND_BUFFER_LEVEL_PRIMARY,
+            commandBufferCount: count,
+        };
+        let mut command_buffers = vec![0; count as usize];
+        let result =
+            vk.AllocateCommandBuffers(device, &allocate_info, command_buffers.as_mut_ptr());
+        if result != vk_sys::SUCCESS {
+            return result;
+        }
+        // The command buffers are freed together with the pool
+        self.command_buffers = command_buffers;
+
+        // A command buffer can be submitted again while the GPU is still busy with an earlier frame
+        let begin_info = vk_sys::CommandBufferBeginInfo {
+            sType: vk_sys::STRUCTURE_TYPE_COMMAND_BUFFER_BEGIN_INFO,
+            pNext: ptr::null(),
+            flags: vk_sys::COMMAND_BUFFER_USAGE_SIMULTANEOUS_USE_BIT,
+            pInheritanceInfo: ptr::null(),
+        };
+        for (query, &command_buffer) in self.command_buffers.iter().enumerate() {
+            let result = vk.BeginCommandBuffer(command_buffer, &begin_info);
+            if result != vk_sys::SUCCESS {
+                return result;
+            }
+            vk.CmdResetQueryPool(command_buffer, self.query_pool, query as u32, 1);
+            // The timestamp is written when every command that was submitted before it is finished
+            vk.CmdWriteTimestamp(
+                command_buffer,
+                vk_sys::PIPELINE_STAGE_BOTTOM_OF_PIPE_BIT,
+                self.query_pool,
+                query as u32,
+            );
+            let result = vk.EndCommandBuffer(command_buffer);
+            if result != vk_sys::SUCCESS {
+                return result;
+            }
+        }
+        vk_sys::SUCCESS
+    }
+
+    /// Start a new frame in the next slot. Returns the timings of the last frame that was rendered in that slot, or `None` if that frame was not measured or is not finished yet.
+    pub fn next_frame(&mut self) -> Option<GpuTimings> {
+        self.frame = (self.frame + 1) % self.written.len();
+        let written = std::mem::replace(&mut self.written[self.frame], 0);
+        if written != ALL_WRITTEN {
+            return None;
+        }
+
+        let mut timestamps = [0u64; TIMESTAMP_COUNT as usize];
+        let result = unsafe {
+            self.device.pointers().GetQueryPoolResults(
+                self.device.internal_object(),
+                self.query_pool,
+                self.frame as u32 * TIMESTAMP_COUNT,
+                TIMESTAMP_COUNT,
+                std::mem::size_of_val(&timestamps),
+                timestamps.as_mut_ptr() as *mut _,
+                std::mem::size_of::<u64>() as u64,
+                vk_sys::QUERY_RESULT_64_BIT,
+            )
+        };
+        // NOT_READY means the frame is still being rendered, which only happens when the CPU is more frames ahead than the timer has slots for
+        if result != vk_sys::SUCCESS {
+            return None;
+        }
+        let elapsed = |start: GpuTimestamp, end: GpuTimestamp| {
+            let ticks = timestamps[end as usize].wrapping_sub(timestamps[start as usize])
+                & self.timestamp_mask;
+            Duration::from_nanos((ticks as f64 * self.timestamp_period) as u64)
+        };
+        Some(GpuTimings {
+            scene: elapsed(GpuTimestamp::SceneStart, GpuTimestamp::PostProcessingStart),
+            post_processing: elapsed(
+                GpuTimestamp::PostProcessingStart,
+                GpuTimestamp::PresentStart,
+            ),
+            present: elapsed(GpuTimestamp::PresentStart, GpuTimestamp::End),
+        })
+    }
+
+    /// Submit a timestamp of the current frame to the queue. It is written when the command buffers that were submitted before it are finished, so the previous pass has to be flushed first.
+    ///
+    /// If the timestamp can not be submitted, the frame is not measured. A lost device is reported when the next pass is submitted.
+    pub fn write(&mut self, timestamp: GpuTimestamp) {
+        let query = self.frame as u32 * TIMESTAMP_COUNT + timestamp as u32;
+        let submit_info = vk_sys::SubmitInfo {
+            sType: vk_sys::STRUCTURE_TYPE_SUBMIT_INFO,
+            pNext: ptr::null(),
+            waitSemaphoreCount: 0,
+            pWaitSemaphores: ptr::null(),
+            pWaitDstStageMask: ptr::null(),
+            commandBufferCount: 1,
+            pCommandBuffers: &self.command_buffers[query as usize],
+            signalSemaphoreCount: 0,
+            pSignalSemaphores: ptr::null(),
+        };
+        let result = {
+            // Vulkano submits to the queue behind the same lock
+            let queue = self.queue.internal_object_guard();
+            unsafe {
+                self.device
+                    .pointers()
+                    .QueueSubmit(*queue, 1, &submit_info, vk_sys::NULL_HANDLE)
+            }
+        };
+        if result == vk_sys::SUCCESS {
+            self.written[self.frame] |= 1 << timestamp as u32;
+        }
+    }
+}
+
+impl Drop for GpuTimer {
+    fn drop(&mut self) {
+        // The timer is dropped after the frames in flight of the render pipeline, so the GPU is
+        // done with the command buffers
+        let vk = self.device.pointers();
+        let device = self.device.internal_object();
+        unsafe {
+            if self.command_pool != vk_sys::NULL_HANDLE {
+                vk.DestroyCommandPool(device, self.command_pool, ptr::null());
+            }
+            if self.query_pool != vk_sys::NULL_HANDLE {
+                vk.DestroyQueryPool(device, self.query_pool, ptr::null());
+            }
+        }
+    }
+}
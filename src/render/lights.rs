@@ -1,4 +1,5 @@
-use crate::model::vs as model_vs;
+use super::environment::EnvironmentMap;
+use crate::{error::LightError, model::fs as model_fs};
 use cgmath::{Vector3, Zero};
 
 /// A direction lightsource in the world.
@@ -7,11 +8,36 @@ use cgmath::{Vector3, Zero};
 /// -1.0, 0.0)`
 ///
 /// For more information, see the amazing tutorial at [https://learnopengl.com/Lighting/Colors](https://learnopengl.com/Lighting/Colors)
+#[derive(Clone)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub struct DirectionalLight {
     /// The direction of the light source
     pub direction: Vector3<f32>,
     /// The color of the light source.
     pub color: LightColor,
+    /// Whether the light shines. A disabled light keeps its place in the [FixedVec], so it can be turned back on without changing the index of the other lights. This defaults to `true`.
+    pub enabled: bool,
+}
+
+impl DirectionalLight {
+    /// Convert the light to the value of the forward shaders. The colors of the light are multiplied with `brightness`, e.g. to dim the lights when it rains.
+    pub(crate) fn to_shader_value(&self, brightness: f32) -> model_fs::ty::DirectionalLight {
+        let (ambient, diffuse, specular) = self.color.to_shader_value(self.enabled, brightness);
+        model_fs::ty::DirectionalLight {
+            direction_x: self.direction.x,
+            direction_y: self.direction.y,
+            direction_z: self.direction.z,
+            color_ambient_r: ambient.x,
+            color_ambient_g: ambient.y,
+            color_ambient_b: ambient.z,
+            color_diffuse_r: diffuse.x,
+            color_diffuse_g: diffuse.y,
+            color_diffuse_b: diffuse.z,
+            color_specular_r: specular.x,
+            color_specular_g: specular.y,
+            color_specular_b: specular.z,
+        }
+    }
 }
 
 impl Default for DirectionalLight {
@@ -19,15 +45,18 @@ impl Default for DirectionalLight {
         Self {
             direction: Vector3::zero(),
             color: LightColor::default(),
+            enabled: true,
         }
     }
 }
 
 /// A pointlight in the world.
 ///
-/// Note: Not implemented yet
+/// Note: point lights are only rendered by the [deferred rendering path](../enum.RenderingPath.html#variant.Deferred).
 ///
 /// For more information, see the amazing tutorial at [https://learnopengl.com/Lighting/Colors](https://learnopengl.com/Lighting/Colors)
+#[derive(Clone)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub struct PointLight {
     /// The position of the light in the world.
     pub position: Vector3<f32>,
@@ -38,6 +67,9 @@ pub struct PointLight {
     /// `PointLightAttenuation` implements `Default` so you can take a good initial value, or you
     /// can tune this until the end of time.
     pub attenuation: PointLightAttenuation,
+
+    /// Whether the light shines. A disabled light keeps its place in [LightState::point](./struct.LightState.html#structfield.point), e.g. to make a lamp flicker. This defaults to `true`.
+    pub enabled: bool,
 }
 
 impl Default for PointLight {
@@ -46,6 +78,7 @@ impl Default for PointLight {
             position: Vector3::zero(),
             color: LightColor::default(),
             attenuation: PointLightAttenuation::default(),
+            enabled: true,
         }
     }
 }
@@ -53,6 +86,8 @@ impl Default for PointLight {
 /// The color of the light. This is divided in 3 fields: ambient, diffuse and specular. See each field for the definition.
 ///
 /// For more information, see the amazing tutorial at [https://learnopengl.com/Lighting/Colors](https://learnopengl.com/Lighting/Colors)
+#[derive(Clone)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub struct LightColor {
     /// Even when it is dark there is usually still some light somewhere in the world (the moon, a distant light) so objects are almost never completely dark.
     /// To simulate this we use an ambient lighting constant that always gives the object some color.
@@ -72,6 +107,29 @@ pub struct LightColor {
     ///
     /// This will be merged with the specular factor of the material of your model.
     pub specular: Vector3<f32>,
+
+    /// The brightness of the light. The ambient, diffuse and specular colors are multiplied with this, so a light can be dimmed without changing its colors. This defaults to `1.0`.
+    pub intensity: f32,
+}
+
+impl LightColor {
+    /// Get the `(ambient, diffuse, specular)` colors of the shaders. The colors are multiplied with the intensity and with `brightness`, and are black when the light is not `enabled`.
+    pub(crate) fn to_shader_value(
+        &self,
+        enabled: bool,
+        brightness: f32,
+    ) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>) {
+        let factor = if enabled {
+            self.intensity * brightness
+        } else {
+            0.0
+        };
+        (
+            self.ambient * factor,
+            self.diffuse * factor,
+            self.specular * factor,
+        )
+    }
 }
 
 impl Default for LightColor {
@@ -80,12 +138,59 @@ impl Default for LightColor {
             ambient: Vector3::zero(),
             diffuse: Vector3::zero(),
             specular: Vector3::zero(),
+            intensity: 1.0,
+        }
+    }
+}
+
+/// A light that comes from every direction at once, with one color from above and another from below. This is a cheap way to light the parts of a scene that no directional light reaches, e.g. a blue sky and a brown ground.
+///
+/// Surfaces that face up get the [sky_color](#structfield.sky_color), surfaces that face down get the [ground_color](#structfield.ground_color), and surfaces in between get a mix of both. The light is multiplied with the ambient color of the material.
+///
+/// ```no_run
+/// # use crystal_engine::*;
+/// # use crystal_engine::state::HemisphereLight;
+/// # use cgmath::Vector3;
+/// # let mut state: GameState = unsafe { std::mem::zeroed() };
+/// state.light.hemisphere = HemisphereLight {
+///     sky_color: Vector3::new(0.6, 0.7, 1.0),
+///     ground_color: Vector3::new(0.3, 0.25, 0.2),
+///     intensity: 0.5,
+/// };
+/// ```
+#[derive(Clone)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct HemisphereLight {
+    /// The color of the light that comes from above.
+    pub sky_color: Vector3<f32>,
+    /// The color of the light that comes from below.
+    pub ground_color: Vector3<f32>,
+    /// The brightness of the light. The light is disabled when this is `0.0`, which is the default.
+    pub intensity: f32,
+}
+
+impl HemisphereLight {
+    /// Get the `(sky, ground)` colors of the shaders. The colors are multiplied with the intensity and with `brightness`, e.g. to dim the light when it rains.
+    pub(crate) fn to_shader_value(&self, brightness: f32) -> (Vector3<f32>, Vector3<f32>) {
+        let factor = self.intensity * brightness;
+        (self.sky_color * factor, self.ground_color * factor)
+    }
+}
+
+impl Default for HemisphereLight {
+    fn default() -> Self {
+        Self {
+            sky_color: Vector3::new(1.0, 1.0, 1.0),
+            ground_color: Vector3::zero(),
+            intensity: 0.0,
         }
     }
 }
 
 /// The attenuation of the pointlight, or how much the light impacts objects based on their
 /// distance.
+#[derive(Clone)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub struct PointLightAttenuation {
     /// The constant or base attenuation. This will always reduce the effect of the light source,
     /// regardless on how far away the object is.
@@ -102,6 +207,10 @@ pub struct PointLightAttenuation {
     pub quadratic: f32,
 }
 
+/// The default values assume that a single world unit is a meter. If this is not the case, use
+/// [WorldUnits::default_attenuation] instead.
+///
+/// [WorldUnits::default_attenuation]: ./struct.WorldUnits.html#method.default_attenuation
 impl Default for PointLightAttenuation {
     fn default() -> Self {
         // Values taken from https://learnopengl.com/Lighting/Multiple-lights
@@ -119,65 +228,64 @@ impl Default for PointLightAttenuation {
 ///
 /// Point lights: lights that shine equally in all directions, e.g. a lightbulb.
 ///
-/// Note: lights are limited to 100 of each type. Currently the shaders do not support more than
-/// 100 light sources at a time. Please open an issue if you need more light sources.
+/// Note: the amount of directional lights is limited by the GPU, see [DeviceLimits::max_directional_lights](./struct.DeviceLimits.html#structfield.max_directional_lights). This is enough for every GPU that supports Vulkan.
+#[derive(Clone)]
 pub struct LightState {
     /// A `FixedVec` of directional lights
     pub directional: FixedVec<DirectionalLight>,
-    /// A `FixedVec` of point lights.
+    /// The point lights in the world. There is no limit on the amount of point lights.
     ///
-    /// Note: not implemented yet
-    pub point: FixedVec<PointLight>,
+    /// Note: point lights are only rendered by the [deferred rendering path](../enum.RenderingPath.html#variant.Deferred). The forward rendering path, which is the default, ignores every point light.
+    pub point: Vec<PointLight>,
+    /// Image based lighting. When this is set, models are lit by the environment from every direction, and shiny models reflect it. Materials with a higher shininess have sharper reflections.
+    ///
+    /// The diffuse color of the material is multiplied with the light of the environment, and the specular color with the reflection.
+    pub environment: Option<EnvironmentMap>,
+    /// The brightness of the [environment](#structfield.environment). This defaults to `1.0`.
+    pub environment_intensity: f32,
+    /// A sky and ground light that lights every model from every direction. This is disabled by default.
+    pub hemisphere: HemisphereLight,
 }
 
 impl LightState {
-    pub(crate) fn new() -> Self {
+    /// Create an empty light state, that can hold up to `directional_capacity` directional lights.
+    pub(crate) fn new(directional_capacity: usize) -> Self {
         Self {
-            directional: FixedVec::<DirectionalLight>::new(),
-            point: FixedVec::<PointLight>::new(),
+            directional: FixedVec::with_capacity(directional_capacity),
+            point: Vec::new(),
+            environment: None,
+            environment_intensity: 1.0,
+            hemisphere: HemisphereLight::default(),
         }
     }
 }
 
-const LIGHT_COUNT: usize = 100;
-/// A fixed vec of light sources. This is limited to 100 entries because of a limitation in the way
-/// Crystal's shaders are implemented. Please open an issue if you need more light sources.
+/// A vec of light sources with a fixed capacity. The lights are passed to the shaders in a storage buffer, so the capacity is the amount of lights that fit in the largest storage buffer of the GPU.
 ///
 /// This should mirror most functions that exist on [Vec]. If you're missing a function, feel free to open an issue or PR!
+#[derive(Clone)]
 pub struct FixedVec<T> {
-    pub(crate) data: [T; LIGHT_COUNT],
-    len: usize,
+    data: Vec<T>,
+    capacity: usize,
 }
 
 impl FixedVec<DirectionalLight> {
-    pub(crate) fn to_shader_value(&self) -> (i32, [model_vs::ty::DirectionalLight; LIGHT_COUNT]) {
-        let result = array_init::array_init(|i| {
-            let light = &self.data[i];
-            model_vs::ty::DirectionalLight {
-                direction_x: light.direction.x,
-                direction_y: light.direction.y,
-                direction_z: light.direction.z,
-                color_ambient_r: light.color.ambient.x,
-                color_ambient_g: light.color.ambient.y,
-                color_ambient_b: light.color.ambient.z,
-                color_diffuse_r: light.color.diffuse.x,
-                color_diffuse_g: light.color.diffuse.x,
-                color_diffuse_b: light.color.diffuse.z,
-                color_specular_r: light.color.specular.x,
-                color_specular_g: light.color.specular.y,
-                color_specular_b: light.color.specular.z,
-            }
-        });
-        (self.len() as i32, result)
-    }
-}
-
-impl<T: Default> FixedVec<T> {
-    pub(crate) fn new() -> Self {
+    /// Convert the lights to the values of the forward shaders, see [DirectionalLight::to_shader_value].
+    pub(crate) fn to_shader_value(
+        &self,
+        brightness: f32,
+    ) -> impl ExactSizeIterator<Item = model_fs::ty::DirectionalLight> + '_ {
+        self.data
+            .iter()
+            .map(move |light| light.to_shader_value(brightness))
+    }
+}
+
+impl<T> FixedVec<T> {
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
         Self {
-            // safe because this is a `DirectionalLight` which has a size and is not a reference
-            data: array_init::array_init(|_| T::default()),
-            len: 0,
+            data: Vec::new(),
+            capacity,
         }
     }
 }
@@ -188,21 +296,26 @@ impl<T> FixedVec<T> {
     ///
     /// Equivalent to `&s[..]`.
     pub fn as_slice(&self) -> &[T] {
-        &self.data[..self.len]
+        &self.data
     }
 
     /// Extracts a mutable slice of the entire fixed vec.
     ///
     /// Equivalent to `&mut s[..]`.
     pub fn as_mut_slice(&mut self) -> &mut [T] {
-        &mut self.data[..self.len]
+        &mut self.data
     }
 
     /// Get the amount of lights that are stored in this `FixedVec`.
     ///
-    /// Note: this is always 100 or lower.
+    /// Note: this is never more than the [capacity](#method.capacity).
     pub fn len(&self) -> usize {
-        self.len
+        self.data.len()
+    }
+
+    /// Get the maximum amount of lights that can be stored in this `FixedVec`.
+    pub fn capacity(&self) -> usize {
+        self.capacity
     }
 
     /// Returns `true` if this `FixedVec` is empty.
@@ -214,33 +327,74 @@ impl<T> FixedVec<T> {
 
     /// Add a new light to this `FixedVec`.
     ///
-    /// This will panic if more than 100 lights are added.
-    pub fn push(&mut self, t: T) {
-        assert!(self.len() < LIGHT_COUNT);
-        self.data[self.len] = t;
-        self.len += 1;
+    /// This returns an error, and does not add the light, when the `FixedVec` is full.
+    pub fn push(&mut self, t: T) -> Result<(), LightError> {
+        if self.len() >= self.capacity {
+            return Err(LightError::CapacityReached {
+                capacity: self.capacity,
+            });
+        }
+        self.data.push(t);
+        Ok(())
     }
 
     /// Remove the last light source from this `FixedVec`.
     ///
     /// This will panic if the `FixedVec` is empty.
     pub fn pop(&mut self) {
-        assert!(self.len > 0);
-        self.len -= 1;
+        assert!(!self.is_empty());
+        self.data.pop();
+    }
+
+    /// Remove all light sources from this `FixedVec`.
+    pub fn clear(&mut self) {
+        self.data.clear();
     }
 }
 
 impl<T> std::ops::Index<usize> for FixedVec<T> {
     type Output = T;
     fn index(&self, index: usize) -> &T {
-        assert!(index < self.len());
         &self.data[index]
     }
 }
 
 impl<T> std::ops::IndexMut<usize> for FixedVec<T> {
     fn index_mut(&mut self, index: usize) -> &mut T {
-        assert!(index < self.len());
         &mut self.data[index]
     }
 }
+
+#[test]
+fn test_fixed_vec_capacity() {
+    let mut lights = FixedVec::with_capacity(2);
+    assert!(lights.push(1).is_ok());
+    assert!(lights.push(2).is_ok());
+    match lights.push(3) {
+        Err(LightError::CapacityReached { capacity: 2 }) => {}
+        other => panic!("Expected the capacity to be reached, got {:?}", other),
+    }
+    assert_eq!(&[1, 2], lights.as_slice());
+
+    lights.pop();
+    assert!(lights.push(4).is_ok());
+    lights[0] = 5;
+    assert_eq!(&[5, 4], lights.as_slice());
+}
+
+#[test]
+fn test_light_color_intensity() {
+    let color = LightColor {
+        ambient: Vector3::new(0.1, 0.1, 0.1),
+        diffuse: Vector3::new(0.5, 0.25, 0.0),
+        specular: Vector3::new(1.0, 1.0, 1.0),
+        intensity: 2.0,
+    };
+    let (ambient, diffuse, specular) = color.to_shader_value(true, 0.5);
+    assert_eq!(Vector3::new(0.1, 0.1, 0.1), ambient);
+    assert_eq!(Vector3::new(0.5, 0.25, 0.0), diffuse);
+    assert_eq!(Vector3::new(1.0, 1.0, 1.0), specular);
+
+    let (ambient, diffuse, specular) = color.to_shader_value(false, 1.0);
+    assert_eq!(Vector3::zero(), ambient + diffuse + specular);
+}
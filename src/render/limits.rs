@@ -0,0 +1,52 @@
+use crate::model::fs as model_fs;
+use std::mem;
+use vulkano::device::Device;
+
+/// The limits of the GPU that the game is running on, see [GameState::device_limits](../struct.GameState.html#method.device_limits). Every GPU that supports Vulkan has at least the minimum values that are mentioned for each limit, but most GPUs support a lot more.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeviceLimits {
+    /// The largest width and height of a texture, in pixels. This is at least `4096`.
+    pub max_image_dimension_2d: u32,
+    /// The largest width of the window that can be rendered to, in pixels. This is at least `4096`.
+    pub max_framebuffer_width: u32,
+    /// The largest height of the window that can be rendered to, in pixels. This is at least `4096`.
+    pub max_framebuffer_height: u32,
+    /// The largest size of a uniform buffer, in bytes. This is at least `16384`.
+    pub max_uniform_buffer_range: u32,
+    /// The largest size of a storage buffer, in bytes. This is at least `134217728`.
+    pub max_storage_buffer_range: u32,
+    /// The largest size of the push constants of a shader, in bytes. This is at least `128`, which is the size of [ModelData::custom_data](../models/struct.ModelData.html#structfield.custom_data).
+    pub max_push_constants_size: u32,
+    /// The highest anisotropic filtering that textures can be sampled with. This is at least `16.0` on GPUs that support anisotropic filtering.
+    pub max_sampler_anisotropy: f32,
+    /// The amount of clip distances a shader can write. When this is `0`, [ClipPlane]s are ignored.
+    ///
+    /// [ClipPlane]: ./struct.ClipPlane.html
+    pub max_clip_distances: u32,
+    /// The amount of [directional lights](./struct.LightState.html#structfield.directional) that can be in the world at the same time. The lights are stored in a storage buffer, so this is the amount of lights that fit in [max_storage_buffer_range](#structfield.max_storage_buffer_range).
+    pub max_directional_lights: usize,
+}
+
+impl DeviceLimits {
+    pub(crate) fn from_device(device: &Device) -> Self {
+        let physical = device.physical_device();
+        let limits = physical.limits();
+        let max_storage_buffer_range = limits.max_storage_buffer_range();
+        Self {
+            max_image_dimension_2d: limits.max_image_dimension_2d(),
+            max_framebuffer_width: limits.max_framebuffer_width(),
+            max_framebuffer_height: limits.max_framebuffer_height(),
+            max_uniform_buffer_range: limits.max_uniform_buffer_range(),
+            max_storage_buffer_range,
+            max_push_constants_size: limits.max_push_constants_size(),
+            max_sampler_anisotropy: limits.max_sampler_anisotropy(),
+            max_clip_distances: if device.enabled_features().shader_clip_distance {
+                limits.max_clip_distances()
+            } else {
+                0
+            },
+            max_directional_lights: max_storage_buffer_range as usize
+                / mem::size_of::<model_fs::ty::DirectionalLight>(),
+        }
+    }
+}
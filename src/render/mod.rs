@@ -1,3 +1,26 @@
+mod auto_exposure;
+pub mod clip_plane;
+pub mod context;
+pub mod day_night;
+pub mod debug_draw;
+mod deferred;
+pub mod device;
+mod effects;
+pub(crate) mod environment;
+pub(crate) mod frustum;
+mod gpu_timer;
 pub mod lights;
+pub mod limits;
+pub mod monitor;
+pub mod occlusion;
+pub mod outline;
+pub(crate) mod picking;
 pub mod pipeline;
+pub mod post_processing;
+pub mod reflection;
+pub(crate) mod ruler;
+pub(crate) mod texture;
+mod tonemap;
+pub mod transition;
+pub mod weather;
 pub mod window;
@@ -0,0 +1,223 @@
+use crate::state::MonitorError;
+use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    monitor::{MonitorHandle, VideoMode},
+    window::Fullscreen,
+};
+
+/// A monitor that is connected to the computer, see [GameState::monitors](../struct.GameState.html#method.monitors). This can be used to build a display settings menu.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonitorInfo {
+    /// The index of the monitor, which is used to pick this monitor in a [WindowMode].
+    ///
+    /// The index is the position of the monitor in the list of the platform, so it can change when monitors are connected or disconnected.
+    pub index: usize,
+    /// The name of the monitor, e.g. `"DELL U2415"`. Platforms that do not name their monitors get a name like `"Monitor 1"`.
+    pub name: String,
+    /// The current resolution of the monitor in physical pixels.
+    pub resolution: (u32, u32),
+    /// The position of the top-left corner of the monitor on the desktop, in physical pixels.
+    pub position: (i32, i32),
+    /// The amount of physical pixels per logical pixel of the monitor, see [GameState::scale_factor](../struct.GameState.html#method.scale_factor).
+    pub scale_factor: f64,
+    /// The highest refresh rate in Hz at the current resolution, or `None` if the platform does not report the video modes of the monitor.
+    pub refresh_rate: Option<u16>,
+    /// True if this is the main monitor of the desktop.
+    pub primary: bool,
+    /// The video modes that can be used for [WindowMode::Exclusive], with the highest resolution and refresh rate first.
+    pub video_modes: Vec<VideoModeInfo>,
+}
+
+impl MonitorInfo {
+    pub(crate) fn new(index: usize, monitor: &MonitorHandle, primary: &MonitorHandle) -> Self {
+        let size = monitor.size();
+        let resolution = (size.width, size.height);
+        let mut video_modes = monitor
+            .video_modes()
+            .map(|mode| VideoModeInfo::from(&mode))
+            .collect::<Vec<_>>();
+        video_modes.sort_by(|a, b| b.cmp(a));
+        video_modes.dedup();
+        let position = monitor.position();
+        Self {
+            index,
+            name: monitor
+                .name()
+                .unwrap_or_else(|| format!("Monitor {}", index + 1)),
+            resolution,
+            position: (position.x, position.y),
+            scale_factor: monitor.scale_factor(),
+            refresh_rate: refresh_rate(resolution, &video_modes),
+            primary: monitor == primary,
+            video_modes,
+        }
+    }
+}
+
+/// A resolution and refresh rate that a monitor supports, see [MonitorInfo::video_modes].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct VideoModeInfo {
+    /// The resolution in physical pixels
+    pub resolution: (u32, u32),
+    /// The refresh rate in Hz
+    pub refresh_rate: u16,
+    /// The amount of bits per pixel, usually `32`
+    pub bit_depth: u16,
+}
+
+impl From<&VideoMode> for VideoModeInfo {
+    fn from(mode: &VideoMode) -> Self {
+        let size = mode.size();
+        Self {
+            resolution: (size.width, size.height),
+            refresh_rate: mode.refresh_rate(),
+            bit_depth: mode.bit_depth(),
+        }
+    }
+}
+
+/// How the window is shown on the monitors of the computer. This can be changed with [GameState::set_window_mode](../struct.GameState.html#method.set_window_mode), or set before the window is created with [WindowBuilder::with_window_mode](../struct.WindowBuilder.html#method.with_window_mode).
+///
+/// ```no_run
+/// # use crystal_engine::*;
+/// # use crystal_engine::state::WindowMode;
+/// # let mut state: GameState = unsafe { std::mem::zeroed() };
+/// // Cover the second monitor with the game, if there is one
+/// if let Some(monitor) = state.monitors().get(1) {
+///     state
+///         .set_window_mode(WindowMode::Borderless {
+///             monitor: monitor.index,
+///         })
+///         .unwrap();
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum WindowMode {
+    /// A regular window with borders. This is the default.
+    Windowed,
+    /// A window without borders that covers the whole monitor, at the current resolution of the monitor. Switching to and from this mode is fast, and other windows can be shown on top of the game.
+    Borderless {
+        /// The index of the monitor, see [MonitorInfo::index]
+        monitor: usize,
+    },
+    /// Exclusive fullscreen, which changes the resolution and refresh rate of the monitor. This can have a lower latency than borderless fullscreen, but switching to another window is slow.
+    Exclusive {
+        /// The index of the monitor, see [MonitorInfo::index]
+        monitor: usize,
+        /// One of the [video modes](struct.MonitorInfo.html#structfield.video_modes) of the monitor
+        video_mode: VideoModeInfo,
+    },
+}
+
+impl Default for WindowMode {
+    fn default() -> Self {
+        WindowMode::Windowed
+    }
+}
+
+impl WindowMode {
+    /// Get the fullscreen state of the window in this mode. `monitors` are the available monitors, in the order of their index.
+    pub(crate) fn to_fullscreen(
+        self,
+        monitors: &[MonitorHandle],
+    ) -> Result<Option<Fullscreen>, MonitorError> {
+        let monitor = |index: usize| {
+            monitors.get(index).ok_or(MonitorError::UnknownMonitor {
+                index,
+                count: monitors.len(),
+            })
+        };
+        match self {
+            WindowMode::Windowed => Ok(None),
+            WindowMode::Borderless { monitor: index } => {
+                Ok(Some(Fullscreen::Borderless(monitor(index)?.clone())))
+            }
+            WindowMode::Exclusive {
+                monitor: index,
+                video_mode,
+            } => monitor(index)?
+                .video_modes()
+                .find(|mode| VideoModeInfo::from(mode) == video_mode)
+                .map(|mode| Some(Fullscreen::Exclusive(mode)))
+                .ok_or(MonitorError::UnknownVideoMode {
+                    monitor: index,
+                    video_mode,
+                }),
+        }
+    }
+
+    /// Get the mode of a window with the given fullscreen state. `monitors` are the available monitors, in the order of their index.
+    pub(crate) fn from_fullscreen(
+        fullscreen: Option<Fullscreen>,
+        monitors: &[MonitorHandle],
+    ) -> Self {
+        let index_of = |monitor: &MonitorHandle| {
+            monitors
+                .iter()
+                .position(|m| m == monitor)
+                .unwrap_or_default()
+        };
+        match fullscreen {
+            None => WindowMode::Windowed,
+            Some(Fullscreen::Borderless(monitor)) => WindowMode::Borderless {
+                monitor: index_of(&monitor),
+            },
+            Some(Fullscreen::Exclusive(mode)) => WindowMode::Exclusive {
+                monitor: index_of(&mode.monitor()),
+                video_mode: VideoModeInfo::from(&mode),
+            },
+        }
+    }
+}
+
+/// Get the position of the top-left corner of a window with the given outer size, so that it is centered on the monitor.
+pub(crate) fn centered_position(
+    monitor: &MonitorHandle,
+    window_size: PhysicalSize<u32>,
+) -> PhysicalPosition<i32> {
+    let position = monitor.position();
+    let size = monitor.size();
+    let (x, y) = center(
+        (position.x, position.y),
+        (size.width, size.height),
+        (window_size.width, window_size.height),
+    );
+    PhysicalPosition::new(x, y)
+}
+
+/// Center a rectangle of `size` in the area at `position` with `area_size`. A rectangle that is larger than the area is aligned to its top-left corner, so the title bar of a window stays visible.
+fn center(position: (i32, i32), area_size: (u32, u32), size: (u32, u32)) -> (i32, i32) {
+    let offset = |area: u32, size: u32| (area.saturating_sub(size) / 2) as i32;
+    (
+        position.0 + offset(area_size.0, size.0),
+        position.1 + offset(area_size.1, size.1),
+    )
+}
+
+/// Get the highest refresh rate of the video modes with the given resolution.
+fn refresh_rate(resolution: (u32, u32), video_modes: &[VideoModeInfo]) -> Option<u16> {
+    video_modes
+        .iter()
+        .filter(|mode| mode.resolution == resolution)
+        .map(|mode| mode.refresh_rate)
+        .max()
+}
+
+#[test]
+fn test_monitor_layout() {
+    // A second monitor to the right of a 1920x1080 monitor
+    assert_eq!((2560, 180), center((1920, 0), (2560, 1440), (1280, 1080)));
+    assert_eq!((1920, -100), center((1920, -100), (1280, 720), (1600, 900)));
+
+    let mode = |width: u32, refresh_rate: u16| VideoModeInfo {
+        resolution: (width, width * 9 / 16),
+        refresh_rate,
+        bit_depth: 32,
+    };
+    let modes = [mode(2560, 60), mode(1920, 144), mode(1920, 60)];
+    assert_eq!(Some(144), refresh_rate((1920, 1080), &modes));
+    assert_eq!(Some(60), refresh_rate((2560, 1440), &modes));
+    assert_eq!(None, refresh_rate((1280, 720), &modes));
+}
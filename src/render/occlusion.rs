@@ -0,0 +1,470 @@
+use super::frustum::Frustum;
+use crate::GameState;
+use cgmath::{SquareMatrix, Vector3, Zero};
+use std::{
+    collections::{HashMap, VecDeque},
+    mem,
+    sync::Arc,
+};
+use vulkano::{
+    buffer::{BufferUsage, CpuAccessibleBuffer, CpuBufferPool},
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    descriptor::descriptor_set::{PersistentDescriptorSet, StdDescriptorPool},
+    device::Device,
+    framebuffer::{RenderPassAbstract, Subpass},
+    pipeline::{
+        blend::AttachmentBlend,
+        depth_stencil::{Compare, DepthStencil},
+        GraphicsPipeline, GraphicsPipelineAbstract,
+    },
+};
+
+/// The amount of tests in a row that a model has to be hidden in, before it is skipped in [OcclusionCulling::Conservative] mode
+const CONSERVATIVE_HIDDEN_FRAMES: u32 = 4;
+/// How much the bounding boxes are enlarged in [OcclusionCulling::Conservative] mode, as a fraction of their largest side
+const CONSERVATIVE_MARGIN: f32 = 0.1;
+/// Models whose bounding box is closer to the camera than this are never skipped, because the near plane of the camera can cut away the front of the box
+const CAMERA_MARGIN_METERS: f32 = 0.05;
+/// The amount of frames whose tests can be waiting for the GPU at the same time. No new tests are rendered while this many are waiting.
+const MAX_PENDING_FRAMES: usize = 4;
+
+/// The corners of the triangles of a box, see [box_corner]
+#[rustfmt::skip]
+const BOX_INDICES: [usize; 36] = [
+    0, 2, 6, 0, 6, 4, // -x
+    1, 3, 7, 1, 7, 5, // +x
+    0, 1, 5, 0, 5, 4, // -y
+    2, 3, 7, 2, 7, 6, // +y
+    0, 1, 3, 0, 3, 2, // -z
+    4, 5, 7, 4, 7, 6, // +z
+];
+
+/// Skips models that are hidden behind other models, on top of the models outside of the view of the camera that are always skipped. See [GameState::occlusion_culling](../struct.GameState.html#structfield.occlusion_culling).
+///
+/// Every frame, the bounding boxes of the models are tested against the depth buffer after the models are rendered. The result of this test is known a few frames later, so a model that comes into view from behind a wall can appear a little late. [Conservative](#variant.Conservative) mode hides this in most cases.
+///
+/// Occlusion culling pays off in worlds with large models that hide many other models, like the buildings of a city or the walls of a dungeon. In open worlds, testing the boxes can take longer than rendering the models.
+///
+/// ```no_run
+/// # use crystal_engine::*;
+/// # use crystal_engine::state::OcclusionCulling;
+/// # let mut state: GameState = unsafe { std::mem::zeroed() };
+/// state.occlusion_culling = OcclusionCulling::Conservative;
+/// ```
+///
+/// Note: the test writes to a storage buffer from a fragment shader, so occlusion culling is ignored on GPUs that do not support the `fragment_stores_and_atomics` feature. Models with [depth_test](../models/struct.ModelData.html#structfield.depth_test) disabled, and models with moved [groups](../models/struct.ModelData.html#structfield.groups), are never skipped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OcclusionCulling {
+    /// Only the models outside of the view of the camera are skipped. This is the default.
+    Disabled,
+    /// Models are skipped as soon as their bounding box was hidden in a single test.
+    Enabled,
+    /// The bounding boxes are enlarged, and models are only skipped after they were hidden in several tests in a row. Models come into view before they are visible, so they do not pop up when the camera moves, at the cost of skipping fewer models.
+    Conservative,
+}
+
+impl Default for OcclusionCulling {
+    fn default() -> Self {
+        OcclusionCulling::Disabled
+    }
+}
+
+impl OcclusionCulling {
+    /// The amount of tests in a row that a model has to be hidden in before it is skipped
+    fn hidden_tests(self) -> u32 {
+        match self {
+            OcclusionCulling::Conservative => CONSERVATIVE_HIDDEN_FRAMES,
+            _ => 1,
+        }
+    }
+
+    /// How much the bounding boxes are enlarged, as a fraction of their largest side
+    fn margin(self) -> f32 {
+        match self {
+            OcclusionCulling::Conservative => CONSERVATIVE_MARGIN,
+            _ => 0.0,
+        }
+    }
+}
+
+/// The results of the tests that have finished. Models that were not tested are never skipped.
+#[derive(Default)]
+pub struct OcclusionResults {
+    /// The amount of tests in a row that the hidden models were hidden in, by the id of the model
+    hidden: HashMap<u64, u32>,
+    /// The amount of tests in a row that a model has to be hidden in before it is skipped
+    hidden_tests: u32,
+}
+
+impl OcclusionResults {
+    /// Check if the model with the given id should be skipped.
+    pub fn is_occluded(&self, id: u64) -> bool {
+        self.hidden
+            .get(&id)
+            .map_or(false, |tests| *tests >= self.hidden_tests)
+    }
+
+    /// Apply the results of a test. `visible` has a value for every model in `ids`, which is `0` if the bounding box of the model was hidden.
+    fn apply(&mut self, ids: &[u64], visible: &[u32]) {
+        let previous = mem::take(&mut self.hidden);
+        self.hidden = ids
+            .iter()
+            .zip(visible)
+            .filter(|(_, visible)| **visible == 0)
+            .map(|(id, _)| (*id, previous.get(id).copied().unwrap_or(0) + 1))
+            .collect();
+    }
+}
+
+/// A test that was rendered, but whose results have not been read yet
+struct PendingTest {
+    /// Every fragment of a bounding box that passes the depth test sets the value of its model to `1`
+    visible: Arc<CpuAccessibleBuffer<[u32]>>,
+    /// The ids of the models, in the order of their values in `visible`
+    ids: Vec<u64>,
+}
+
+#[derive(Default, Copy, Clone)]
+struct Vertex {
+    position: [f32; 3],
+    /// The index of the model in the visibility buffer of the test
+    slot: u32,
+}
+vulkano::impl_vertex!(Vertex, position, slot);
+
+/// Tests the bounding boxes of the models against the depth buffer, see [OcclusionCulling]. The boxes are drawn in the first subpass, after the models.
+pub(crate) struct Pipeline {
+    device: Arc<Device>,
+    pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    vertex_buffer: CpuBufferPool<Vertex>,
+    uniform_buffer: CpuBufferPool<vs::ty::Data>,
+    /// The tests that are waiting for the GPU, the oldest first
+    pending: VecDeque<PendingTest>,
+    /// The visibility buffers of tests that were read, which can be used again
+    free: Vec<Arc<CpuAccessibleBuffer<[u32]>>>,
+    results: OcclusionResults,
+}
+
+impl Pipeline {
+    /// Create the pipeline. This should only be called when the `fragment_stores_and_atomics` feature of the device is enabled.
+    pub fn create(
+        device: Arc<Device>,
+        subpass: Subpass<Arc<dyn RenderPassAbstract + Send + Sync>>,
+    ) -> Self {
+        // These should never fail, as the shaders are hard-coded and the device is assumed to be
+        // valid.
+        let vs = vs::Shader::load(device.clone()).expect("failed to create shader module");
+        let fs = fs::Shader::load(device.clone()).expect("failed to create shader module");
+
+        let pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input_single_buffer::<Vertex>()
+                .vertex_shader(vs.main_entry_point(), ())
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(fs.main_entry_point(), ())
+                // Both sides of the boxes are tested, so a box is visible as long as any part of it
+                // is in front of the depth buffer
+                .cull_mode_disabled()
+                .depth_stencil(DepthStencil {
+                    depth_write: false,
+                    depth_compare: Compare::LessOrEqual,
+                    ..DepthStencil::simple_depth_test()
+                })
+                // The boxes only test the depth, so they never change the colors of the scene
+                .blend_collective(AttachmentBlend {
+                    mask_red: false,
+                    mask_green: false,
+                    mask_blue: false,
+                    mask_alpha: false,
+                    ..AttachmentBlend::pass_through()
+                })
+                .render_pass(subpass)
+                .build(device.clone())
+                // This should never fail because all arguments are hard-coded
+                .unwrap(),
+        );
+
+        Self {
+            pipeline,
+            vertex_buffer: CpuBufferPool::vertex_buffer(device.clone()),
+            uniform_buffer: CpuBufferPool::uniform_buffer(device.clone()),
+            device,
+            pending: VecDeque::new(),
+            free: Vec::new(),
+            results: OcclusionResults::default(),
+        }
+    }
+
+    /// The results of the tests that have finished.
+    pub fn results(&self) -> &OcclusionResults {
+        &self.results
+    }
+
+    /// Read the results of the tests that the GPU has finished. This has to be called before the models are rendered.
+    pub fn update(&mut self, mode: OcclusionCulling) {
+        if mode == OcclusionCulling::Disabled {
+            // Start from scratch when occlusion culling is turned on again
+            self.results.hidden.clear();
+            return;
+        }
+        self.results.hidden_tests = mode.hidden_tests();
+        loop {
+            let finished = match self.pending.front() {
+                // The buffer is locked until the GPU has finished the frame that the test was
+                // rendered in
+                Some(test) => match test.visible.read() {
+                    Ok(visible) => {
+                        self.results.apply(&test.ids, &visible);
+                        true
+                    }
+                    Err(_) => false,
+                },
+                None => false,
+            };
+            if !finished {
+                break;
+            }
+            // The test was found above, so this should never fail
+            let test = self.pending.pop_front().unwrap();
+            self.free.push(test.visible);
+        }
+    }
+
+    /// Draw the bounding boxes of the models in the view of the camera. This has to be called in the first subpass, after the models are rendered.
+    pub fn render(
+        &mut self,
+        mode: OcclusionCulling,
+        dimensions: [f32; 2],
+        game_state: &GameState,
+        command_buffer_builder: &mut AutoCommandBufferBuilder,
+        dynamic_state: &DynamicState,
+        descriptor_pool: &mut Arc<StdDescriptorPool>,
+    ) {
+        if mode == OcclusionCulling::Disabled || self.pending.len() >= MAX_PENDING_FRAMES {
+            return;
+        }
+        let view = game_state.view_matrix();
+        let view_projection = game_state.perspective_matrix(dimensions[0] / dimensions[1]) * view;
+        let frustum = Frustum::from_matrix(view_projection);
+        // A camera matrix that can not be inverted is invalid, so fall back to the origin of the
+        // world
+        let camera_position = view
+            .invert()
+            .map(|m| m.w.truncate())
+            .unwrap_or_else(Vector3::zero);
+        let camera_margin = game_state.units.meters_to_units(CAMERA_MARGIN_METERS);
+
+        let mut ids = Vec::new();
+        let mut vertices = Vec::new();
+        for (id, model) in &game_state.model_handles {
+            {
+                let data = model.data.read();
+                // The bounding box does not contain groups that are moved, see
+                // `ModelRef::is_visible`
+                if !data.depth_test || data.groups.iter().any(|g| !g.matrix.is_identity()) {
+                    continue;
+                }
+            }
+            let bounding_box = model.world_bounding_box();
+            if !frustum.intersects_box(bounding_box) {
+                continue;
+            }
+            let bounding_box = enlarge_box(bounding_box, mode.margin());
+            if box_contains(enlarge_box_by(bounding_box, camera_margin), camera_position) {
+                continue;
+            }
+            vertices.extend(box_vertices(bounding_box, ids.len() as u32));
+            ids.push(*id);
+        }
+        if ids.is_empty() {
+            return;
+        }
+
+        let visible = self.visibility_buffer(ids.len());
+        // The vertex_buffer is assumed to be valid so this should never fail
+        let vertex_buffer = self.vertex_buffer.chunk(vertices).unwrap();
+        let data = vs::ty::Data {
+            view_projection: view_projection.into(),
+        };
+        // The uniform_buffer is assumed to be valid so this should never fail
+        let uniform_buffer_subbuffer = self.uniform_buffer.next(data).unwrap();
+
+        // The pipeline and the layout index are hard-coded so this is assumed to never fail
+        let layout = self.pipeline.descriptor_set_layout(0).unwrap();
+        let set = Arc::new(
+            PersistentDescriptorSet::start(layout.clone())
+                .add_buffer(uniform_buffer_subbuffer)
+                // The uniform subbuffer is assumed to be valid so this should never fail
+                .unwrap()
+                .add_buffer(visible.clone())
+                // The visibility buffer is created as a storage buffer so this should never fail
+                .unwrap()
+                .build_with_pool(descriptor_pool)
+                // The pool is assumed to be valid so this should never fail
+                .unwrap(),
+        );
+        command_buffer_builder
+            .draw(
+                self.pipeline.clone(),
+                dynamic_state,
+                vec![Arc::new(vertex_buffer)],
+                set,
+                (),
+            )
+            // the builder and arguments are assumed to be valid so this should never fail
+            .unwrap();
+        self.pending.push_back(PendingTest { visible, ids });
+    }
+
+    /// Get a visibility buffer with room for `count` models, with the first `count` values set to `0`.
+    fn visibility_buffer(&mut self, count: usize) -> Arc<CpuAccessibleBuffer<[u32]>> {
+        if let Some(index) = self.free.iter().position(|b| b.len() >= count) {
+            let buffer = self.free.swap_remove(index);
+            // The results of this buffer were read, so the GPU is no longer using it and this
+            // should never fail
+            for value in buffer.write().unwrap().iter_mut().take(count) {
+                *value = 0;
+            }
+            return buffer;
+        }
+        // The size is rounded up, so the buffer can be used again when a few models are added. The
+        // device is assumed to be valid so this should never fail.
+        CpuAccessibleBuffer::from_iter(
+            self.device.clone(),
+            BufferUsage::storage_buffer(),
+            false,
+            std::iter::repeat(0).take(count.next_power_of_two()),
+        )
+        .unwrap()
+    }
+}
+
+/// Get a corner of a box. Every bit of `index` picks the `max` of an axis, in the order x, y, z.
+fn box_corner((min, max): (Vector3<f32>, Vector3<f32>), index: usize) -> [f32; 3] {
+    [
+        if index & 1 == 0 { min.x } else { max.x },
+        if index & 2 == 0 { min.y } else { max.y },
+        if index & 4 == 0 { min.z } else { max.z },
+    ]
+}
+
+/// Get the vertices of the triangles of a box, for the model at the given slot of the visibility buffer.
+fn box_vertices(
+    bounding_box: (Vector3<f32>, Vector3<f32>),
+    slot: u32,
+) -> impl Iterator<Item = Vertex> {
+    BOX_INDICES.iter().map(move |index| Vertex {
+        position: box_corner(bounding_box, *index),
+        slot,
+    })
+}
+
+/// Enlarge a box on every side by a fraction of its largest side.
+fn enlarge_box(
+    (min, max): (Vector3<f32>, Vector3<f32>),
+    fraction: f32,
+) -> (Vector3<f32>, Vector3<f32>) {
+    let size = max - min;
+    enlarge_box_by((min, max), size.x.max(size.y).max(size.z) * fraction)
+}
+
+/// Enlarge a box on every side by the given distance.
+fn enlarge_box_by(
+    (min, max): (Vector3<f32>, Vector3<f32>),
+    distance: f32,
+) -> (Vector3<f32>, Vector3<f32>) {
+    let margin = Vector3::new(distance, distance, distance);
+    (min - margin, max + margin)
+}
+
+/// Check if a point is inside of a box, or on its surface.
+fn box_contains((min, max): (Vector3<f32>, Vector3<f32>), point: Vector3<f32>) -> bool {
+    point.x >= min.x
+        && point.x <= max.x
+        && point.y >= min.y
+        && point.y <= max.y
+        && point.z >= min.z
+        && point.z <= max.z
+}
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "#version 450
+layout(location = 0) in vec3 position;
+layout(location = 1) in uint slot;
+
+layout(location = 0) flat out uint fragment_slot;
+
+layout(set = 0, binding = 0) uniform Data {
+    mat4 view_projection;
+} uniforms;
+
+void main() {
+    gl_Position = uniforms.view_projection * vec4(position, 1.0);
+    fragment_slot = slot;
+}
+"
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "#version 450
+// The depth test has to run before the shader, so fragments that are hidden never mark their
+// model as visible
+layout(early_fragment_tests) in;
+
+layout(location = 0) flat in uint fragment_slot;
+
+layout(set = 0, binding = 1) buffer Visibility {
+    uint visible[];
+} visibility;
+
+void main() {
+    visibility.visible[fragment_slot] = 1;
+}
+"
+    }
+}
+
+#[test]
+fn test_occlusion_results() {
+    let mut results = OcclusionResults {
+        hidden_tests: 2,
+        ..OcclusionResults::default()
+    };
+    results.apply(&[1, 2, 3], &[0, 1, 0]);
+    assert!(!results.is_occluded(1));
+    results.apply(&[1, 2, 3], &[0, 0, 1]);
+    assert!(results.is_occluded(1));
+    assert!(!results.is_occluded(2));
+    assert!(!results.is_occluded(3));
+    // Models that were not tested, e.g. because they left the view, start over
+    results.apply(&[2], &[0]);
+    assert!(!results.is_occluded(1));
+    results.hidden_tests = 1;
+    assert!(results.is_occluded(2));
+    assert!(!results.is_occluded(4));
+}
+
+#[test]
+fn test_occlusion_boxes() {
+    let bounding_box = (Vector3::new(0.0, 0.0, 0.0), Vector3::new(2.0, 1.0, 4.0));
+    let vertices = box_vertices(bounding_box, 7).collect::<Vec<_>>();
+    assert_eq!(36, vertices.len());
+    assert!(vertices.iter().all(|v| v.slot == 7));
+    // Every corner is used by the triangles
+    for index in 0..8 {
+        let corner = box_corner(bounding_box, index);
+        assert!(vertices.iter().any(|v| v.position == corner));
+    }
+    assert_eq!([2.0, 0.0, 4.0], box_corner(bounding_box, 5));
+
+    let (min, max) = enlarge_box(bounding_box, 0.25);
+    assert_eq!(Vector3::new(-1.0, -1.0, -1.0), min);
+    assert_eq!(Vector3::new(3.0, 2.0, 5.0), max);
+    assert!(box_contains((min, max), Vector3::new(-0.5, 1.5, 4.5)));
+    assert!(!box_contains(bounding_box, Vector3::new(-0.5, 0.5, 2.0)));
+}
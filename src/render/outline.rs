@@ -0,0 +1,379 @@
+use super::frustum::Frustum;
+use crate::{
+    model::{ModelRef, Vertex},
+    profiler::RenderStats,
+    GameState,
+};
+use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector3, Zero};
+use std::{mem, sync::Arc};
+use vulkano::{
+    buffer::CpuBufferPool,
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet, StdDescriptorPool},
+    device::Device,
+    framebuffer::{RenderPassAbstract, Subpass},
+    image::attachment::AttachmentImage,
+    pipeline::{
+        depth_stencil::{Compare, DepthStencil},
+        GraphicsPipeline, GraphicsPipelineAbstract,
+    },
+};
+
+/// The largest thickness of an outline, in pixels
+const MAX_THICKNESS: f32 = 32.0;
+
+/// A solid line around the silhouette of a model, which is the usual way to show that a unit or an object is selected. See [ModelHandle::set_outline].
+///
+/// The outline is drawn by rendering the back of the model a second time, with every vertex moved outwards along its normal. Only the part that sticks out from behind the model is visible. The outline is hidden by models in front of it, just like the model itself.
+///
+/// ```no_run
+/// # use crystal_engine::*;
+/// # use crystal_engine::state::Outline;
+/// # let mut state: GameState = unsafe { std::mem::zeroed() };
+/// # let unit: ModelHandle = state.new_cube_model().build().unwrap();
+/// // Select the unit
+/// unit.set_outline(Some(Outline::new([0.2, 1.0, 0.2, 1.0], 3.0)));
+/// // Deselect it again
+/// unit.set_outline(None);
+/// ```
+///
+/// Note: the vertices are moved along their normals, so models with hard edges, like a cube, can have small gaps in the outline at their corners. [Morph targets](../struct.ModelHandle.html#method.set_morph_weight) are not applied to the outline.
+///
+/// [ModelHandle::set_outline]: ../struct.ModelHandle.html#method.set_outline
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct Outline {
+    /// The color of the outline as `[r, g, b, a]`. The outline is drawn before the post-processing, so values above `1.0` glow with bloom. Defaults to orange.
+    pub color: [f32; 4],
+    /// The width of the outline in pixels of the window, up to `32.0`. The outline has the same width at every distance from the camera. Defaults to `3.0`.
+    pub thickness: f32,
+}
+
+impl Default for Outline {
+    fn default() -> Self {
+        Self {
+            color: [1.0, 0.6, 0.1, 1.0],
+            thickness: 3.0,
+        }
+    }
+}
+
+impl Outline {
+    /// Create an outline with the given color and thickness in pixels.
+    pub fn new(color: [f32; 4], thickness: f32) -> Self {
+        Self { color, thickness }
+    }
+}
+
+/// Get the distance that the vertices of an outline are moved in normalized device coordinates, in a window of the given size. A distance of `1.0` is half of the window.
+fn clip_offset(thickness: f32, dimensions: [f32; 2]) -> [f32; 2] {
+    let pixels = if thickness.is_finite() {
+        thickness.max(0.0).min(MAX_THICKNESS)
+    } else {
+        0.0
+    };
+    [
+        pixels * 2.0 / dimensions[0].max(1.0),
+        pixels * 2.0 / dimensions[1].max(1.0),
+    ]
+}
+
+/// Draws the [Outline] of the models that have one. The outlines are drawn in the same subpass as the weather, after the models are lit.
+pub(crate) struct Pipeline {
+    pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    uniform_buffer: CpuBufferPool<vs::ty::Data>,
+    deferred: bool,
+    /// The draw calls and triangles since the last call to `take_draw_statistics`
+    statistics: RenderStats,
+}
+
+impl Pipeline {
+    /// Create the pipeline. `subpass` is the subpass that the outlines are drawn in, `deferred` is true if this is the lighting pass of the deferred rendering path.
+    pub fn create(
+        device: Arc<Device>,
+        subpass: Subpass<Arc<dyn RenderPassAbstract + Send + Sync>>,
+        deferred: bool,
+    ) -> Self {
+        // These should never fail, as the shaders are hard-coded and the device is assumed to be
+        // valid.
+        let vs = vs::Shader::load(device.clone()).expect("failed to create shader module");
+        // Only the back of the enlarged model is drawn, so the model itself stays visible in front
+        // of its outline
+        let builder = GraphicsPipeline::start()
+            .vertex_input_single_buffer::<Vertex>()
+            .vertex_shader(vs.main_entry_point(), ())
+            .viewports_dynamic_scissors_irrelevant(1)
+            .cull_mode_front()
+            .blend_alpha_blending();
+
+        // This should never fail because all arguments are hard-coded
+        let pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync> = if deferred {
+            let fs =
+                fs_deferred::Shader::load(device.clone()).expect("failed to create shader module");
+            Arc::new(
+                builder
+                    .fragment_shader(fs.main_entry_point(), ())
+                    .render_pass(subpass)
+                    .build(device.clone())
+                    .unwrap(),
+            )
+        } else {
+            let fs = fs::Shader::load(device.clone()).expect("failed to create shader module");
+            Arc::new(
+                builder
+                    .fragment_shader(fs.main_entry_point(), ())
+                    // The outline does not write depth, so it never hides the models and the
+                    // weather that are drawn after it
+                    .depth_stencil(DepthStencil {
+                        depth_write: false,
+                        depth_compare: Compare::LessOrEqual,
+                        ..DepthStencil::simple_depth_test()
+                    })
+                    .render_pass(subpass)
+                    .build(device.clone())
+                    .unwrap(),
+            )
+        };
+
+        Self {
+            uniform_buffer: CpuBufferPool::uniform_buffer(device),
+            pipeline,
+            deferred,
+            statistics: RenderStats::default(),
+        }
+    }
+
+    /// Get the amount of draw calls and triangles since the last call to this function.
+    pub fn take_draw_statistics(&mut self) -> RenderStats {
+        mem::take(&mut self.statistics)
+    }
+
+    /// Draw the outlines of the visible models. `dimensions` is the size of the window, which the thickness of the outlines is relative to. `depth` is only used by the deferred rendering path.
+    pub fn render(
+        &mut self,
+        dimensions: [f32; 2],
+        game_state: &GameState,
+        depth: Arc<AttachmentImage>,
+        command_buffer_builder: &mut AutoCommandBufferBuilder,
+        dynamic_state: &DynamicState,
+        descriptor_pool: &mut Arc<StdDescriptorPool>,
+    ) {
+        let view = game_state.view_matrix();
+        let proj = game_state.perspective_matrix(dimensions[0] / dimensions[1]);
+        let frustum = Frustum::from_matrix(proj * view);
+        // A camera matrix that can not be inverted is invalid, so fall back to the origin of the
+        // world
+        let camera_position = view
+            .invert()
+            .map(|m| m.w.truncate())
+            .unwrap_or_else(Vector3::zero);
+        let models = game_state
+            .model_handles
+            .values()
+            .filter(|model| model.data.read().outline.is_some() && model.is_visible(&frustum));
+        for model in models {
+            self.render_model(
+                model,
+                view,
+                proj,
+                camera_position,
+                dimensions,
+                game_state,
+                &depth,
+                command_buffer_builder,
+                dynamic_state,
+                descriptor_pool,
+            );
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn render_model(
+        &mut self,
+        model: &ModelRef,
+        view: Matrix4<f32>,
+        proj: Matrix4<f32>,
+        camera_position: Vector3<f32>,
+        dimensions: [f32; 2],
+        game_state: &GameState,
+        depth: &Arc<AttachmentImage>,
+        command_buffer_builder: &mut AutoCommandBufferBuilder,
+        dynamic_state: &DynamicState,
+        descriptor_pool: &mut Arc<StdDescriptorPool>,
+    ) {
+        let model_data = model.data.read();
+        let outline = match model_data.outline {
+            Some(outline) => outline,
+            None => return,
+        };
+        let offset = clip_offset(outline.thickness, dimensions);
+        let base_matrix = game_state.model_matrix(model, &model_data);
+        let mut data = vs::ty::Data {
+            world: Matrix4::identity().into(),
+            view: view.into(),
+            proj: proj.into(),
+            color: outline.color,
+            offset_x: offset[0],
+            offset_y: offset[1],
+        };
+
+        // The pipeline and the layout index are hard-coded so this is assumed to never fail
+        let layout = self.pipeline.descriptor_set_layout(0).unwrap();
+        let mesh = model.mesh();
+        let groups = mesh.draw_groups((model_data.position - camera_position).magnitude());
+        for (index, draw_group) in groups.into_iter().enumerate() {
+            let triangles = draw_group.triangle_count();
+            let vertex_buffer = match draw_group.vertex_buffer {
+                Some(vertex_buffer) => vertex_buffer,
+                // A mesh that was changed at runtime can be empty
+                None => continue,
+            };
+            // Levels of detail can have more groups than the model itself
+            let group_matrix = model_data
+                .groups
+                .get(index)
+                .map(|g| g.matrix)
+                .unwrap_or_else(Matrix4::identity);
+            data.world = (base_matrix * group_matrix).into();
+            // The uniform_buffer is assumed to be valid so this should never fail
+            let uniform_buffer_subbuffer = self.uniform_buffer.next(data).unwrap();
+
+            // The uniform subbuffer is assumed to be valid so this should never fail
+            let set = PersistentDescriptorSet::start(layout.clone())
+                .add_buffer(uniform_buffer_subbuffer)
+                .unwrap();
+            // The depth buffer is an input attachment of the lighting pass and the pool is assumed
+            // to be valid, so these should never fail
+            let set: Arc<dyn DescriptorSet + Send + Sync> = if self.deferred {
+                Arc::new(
+                    set.add_image(depth.clone())
+                        .unwrap()
+                        .build_with_pool(descriptor_pool)
+                        .unwrap(),
+                )
+            } else {
+                Arc::new(set.build_with_pool(descriptor_pool).unwrap())
+            };
+
+            self.statistics.draw_calls += 1;
+            self.statistics.triangles += triangles;
+            // the builder and arguments are assumed to be valid so these should never fail
+            if let Some(index) = draw_group.index {
+                command_buffer_builder
+                    .draw_indexed(
+                        self.pipeline.clone(),
+                        dynamic_state,
+                        vec![vertex_buffer],
+                        index,
+                        set,
+                        (),
+                    )
+                    .unwrap();
+            } else {
+                command_buffer_builder
+                    .draw(
+                        self.pipeline.clone(),
+                        dynamic_state,
+                        vec![vertex_buffer],
+                        set,
+                        (),
+                    )
+                    .unwrap();
+            }
+        }
+    }
+}
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "#version 450
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+
+layout(set = 0, binding = 0) uniform Data {
+    mat4 world;
+    mat4 view;
+    mat4 proj;
+    vec4 color;
+    // The thickness of the outline in normalized device coordinates
+    float offset_x;
+    float offset_y;
+} uniforms;
+
+void main() {
+    // This is the same calculation as the model shaders, so the outline lines up with the model
+    mat4 worldview = uniforms.view * uniforms.world;
+    vec4 clip_position = uniforms.proj * worldview * vec4(position, 1.0);
+    // The vertex is moved along the direction of its normal on the screen. The offset is
+    // multiplied with w, so the outline has the same thickness at every distance.
+    vec3 view_normal = transpose(inverse(mat3(worldview))) * normal;
+    vec2 screen_normal = (uniforms.proj * vec4(view_normal, 0.0)).xy;
+    if (length(screen_normal) > 0.0001) {
+        clip_position.xy += normalize(screen_normal)
+            * vec2(uniforms.offset_x, uniforms.offset_y)
+            * clip_position.w;
+    }
+    gl_Position = clip_position;
+}
+"
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "#version 450
+layout(set = 0, binding = 0) uniform Data {
+    mat4 world;
+    mat4 view;
+    mat4 proj;
+    vec4 color;
+    float offset_x;
+    float offset_y;
+} uniforms;
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+    f_color = uniforms.color;
+}
+"
+    }
+}
+
+mod fs_deferred {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "#version 450
+layout(set = 0, binding = 0) uniform Data {
+    mat4 world;
+    mat4 view;
+    mat4 proj;
+    vec4 color;
+    float offset_x;
+    float offset_y;
+} uniforms;
+// The depth buffer of the G-buffer pass, see render/deferred.rs
+layout(input_attachment_index = 4, set = 0, binding = 1) uniform subpassInput g_depth;
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+    // The lighting pass has no depth attachment, so the depth test is done here
+    if (gl_FragCoord.z > subpassLoad(g_depth).x) {
+        discard;
+    }
+    f_color = uniforms.color;
+}
+"
+    }
+}
+
+#[test]
+fn test_outline_offset() {
+    assert_eq!([0.005, 0.01], clip_offset(5.0, [2000.0, 1000.0]));
+    assert_eq!([0.064, 0.064], clip_offset(100.0, [1000.0, 1000.0]));
+    assert_eq!([0.0, 0.0], clip_offset(-2.0, [1000.0, 1000.0]));
+    assert_eq!([0.0, 0.0], clip_offset(std::f32::NAN, [1000.0, 1000.0]));
+}
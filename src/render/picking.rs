@@ -0,0 +1,96 @@
+use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector2, Vector3, Vector4};
+
+/// A half-line in the world, used to find what is under a position on the screen.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Ray {
+    pub origin: Vector3<f32>,
+    /// The normalized direction of the ray
+    pub direction: Vector3<f32>,
+}
+
+impl Ray {
+    /// Create the ray that goes from the camera through a point on the screen, in normalized device coordinates with the y-axis going up. Returns `None` if the `projection * view` matrix can not be inverted.
+    pub fn from_screen(view_projection: Matrix4<f32>, position: Vector2<f32>) -> Option<Self> {
+        let inverse = view_projection.invert()?;
+        let unproject = |depth: f32| {
+            let point = inverse * Vector4::new(position.x, position.y, depth, 1.0);
+            point.truncate() / point.w
+        };
+        let near = unproject(-1.0);
+        let far = unproject(1.0);
+        Some(Self {
+            origin: near,
+            direction: (far - near).normalize(),
+        })
+    }
+
+    /// The point at the given distance along the ray.
+    pub fn at(&self, distance: f32) -> Vector3<f32> {
+        self.origin + self.direction * distance
+    }
+
+    /// The distance along the ray to an axis-aligned box, given as `(min, max)`. Returns `None` if the ray misses the box. If the ray starts inside of the box, the distance is `0.0`.
+    pub fn intersect_box(&self, (min, max): (Vector3<f32>, Vector3<f32>)) -> Option<f32> {
+        let mut near = 0.0f32;
+        let mut far = std::f32::INFINITY;
+        for axis in 0..3 {
+            let (origin, direction) = (self.origin[axis], self.direction[axis]);
+            if direction.abs() < std::f32::EPSILON {
+                // The ray is parallel to this slab, so it has to start between the planes
+                if origin < min[axis] || origin > max[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let a = (min[axis] - origin) / direction;
+            let b = (max[axis] - origin) / direction;
+            near = near.max(a.min(b));
+            far = far.min(a.max(b));
+            if near > far {
+                return None;
+            }
+        }
+        Some(near)
+    }
+
+    /// The distance along the ray to the plane `y = height`. Returns `None` if the ray points away from the plane.
+    pub fn intersect_ground(&self, height: f32) -> Option<f32> {
+        if self.direction.y.abs() < std::f32::EPSILON {
+            return None;
+        }
+        let distance = (height - self.origin.y) / self.direction.y;
+        if distance >= 0.0 {
+            Some(distance)
+        } else {
+            None
+        }
+    }
+}
+
+#[test]
+fn test_ray_intersections() {
+    let ray = Ray {
+        origin: Vector3::new(0.0, 5.0, -10.0),
+        direction: Vector3::new(0.0, 0.0, 1.0),
+    };
+    let hit = ray.intersect_box((Vector3::new(-1.0, 4.0, -1.0), Vector3::new(1.0, 6.0, 1.0)));
+    assert_eq!(Some(9.0), hit);
+    assert_eq!(
+        None,
+        ray.intersect_box((Vector3::new(2.0, 4.0, -1.0), Vector3::new(3.0, 6.0, 1.0)))
+    );
+    assert_eq!(None, ray.intersect_ground(0.0));
+
+    let ray = Ray {
+        origin: Vector3::new(0.0, 5.0, 0.0),
+        direction: Vector3::new(0.6, -0.8, 0.0),
+    };
+    let distance = ray.intersect_ground(0.0).unwrap();
+    assert!((ray.at(distance) - Vector3::new(3.75, 0.0, 0.0)).magnitude() < 0.001);
+
+    // A camera at the origin that looks down the negative z-axis sees the center of the screen
+    // straight ahead
+    let projection = cgmath::perspective(cgmath::Deg(90.0), 1.0, 0.1, 100.0);
+    let ray = Ray::from_screen(projection, Vector2::new(0.0, 0.0)).unwrap();
+    assert!((ray.direction - Vector3::new(0.0, 0.0, -1.0)).magnitude() < 0.001);
+}
@@ -1,14 +1,43 @@
+use super::{
+    auto_exposure::Pipeline as AutoExposurePipeline,
+    context::RenderContext,
+    debug_draw::Pipeline as DebugDrawPipeline,
+    deferred::{GBuffer, Pipeline as LightingPipeline, GBUFFER_FORMAT},
+    effects::Pipeline as EffectsPipeline,
+    environment::EnvironmentMap,
+    gpu_timer::{GpuTimer, GpuTimestamp},
+    occlusion::{OcclusionCulling, Pipeline as OcclusionPipeline},
+    outline::Pipeline as OutlinePipeline,
+    post_processing::PostEffect,
+    reflection::Pipeline as ReflectionPipeline,
+    tonemap::Pipeline as TonemapPipeline,
+    transition::TransitionOverlay,
+    weather::Pipeline as WeatherPipeline,
+};
 use crate::{
-    gui::Pipeline as GuiPipeline, model::Pipeline as ModelPipeline, state::InitError, GameState,
+    gui::Pipeline as GuiPipeline,
+    model::{Pipeline as ModelPipeline, RenderView},
+    profiler::ProfilerStage,
+    state::{InitError, RenderError},
+    storage::{screenshot_image, ScreenshotRequest},
+    GameState, RenderingPath,
+};
+use image::RgbaImage;
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{Duration, Instant},
 };
-use std::sync::Arc;
 use vulkano::{
+    buffer::{BufferUsage, CpuAccessibleBuffer},
     command_buffer::{AutoCommandBufferBuilder, DynamicState},
     descriptor::descriptor_set::StdDescriptorPool,
     device::{Device, Queue},
-    format::Format,
-    framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract},
-    image::{attachment::AttachmentImage, SwapchainImage},
+    format::{ClearValue, Format},
+    framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract, Subpass},
+    image::{
+        attachment::AttachmentImage, ImageAccess, ImageUsage, ImageViewAccess, SwapchainImage,
+    },
     instance::PhysicalDevice,
     pipeline::viewport::Viewport,
     swapchain::{
@@ -18,20 +47,139 @@ use vulkano::{
     sync::{FenceSignalFuture, FlushError, GpuFuture},
 };
 
+/// The format of the buffer that the scene is rendered into, before it is tonemapped
+pub(crate) const HDR_FORMAT: Format = Format::R16G16B16A16Sfloat;
+/// The color of the background
+const CLEAR_COLOR: [f32; 4] = [0.5, 0.5, 1.0, 1.0];
+/// The format of the image that headless windows render into
+const OFFSCREEN_FORMAT: Format = Format::R8G8B8A8Srgb;
+/// The amount of frames that can be rendered by the GPU at once. The CPU prepares the next frame while the GPU renders the previous one, and only waits when it gets too far ahead.
+const MAX_FRAMES_IN_FLIGHT: usize = 2;
+
+/// The images that the scene is rendered into, before it is drawn to the swapchain image
+struct RenderTargets {
+    /// The framebuffer of the scene render pass, which contains the images below
+    framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    /// The size of the images, which is the size of the frame multiplied with the render scale
+    dimensions: [u32; 2],
+    /// The viewport of the scene render pass
+    dynamic_state: DynamicState,
+    hdr: Arc<AttachmentImage>,
+    depth: Arc<AttachmentImage>,
+    /// Only used by the deferred rendering path
+    gbuffer: Option<GBuffer>,
+}
+
+/// The images that the final frame is rendered into
+enum FrameOutput {
+    Swapchain {
+        swapchain: Arc<Swapchain<winit::window::Window>>,
+        images: Vec<Arc<SwapchainImage<winit::window::Window>>>,
+        needs_refresh: bool,
+    },
+    /// Used by headless windows. Every frame is copied into `buffer`, so it can be read after the frame is finished.
+    Offscreen {
+        image: Arc<AttachmentImage>,
+        buffer: Arc<CpuAccessibleBuffer<[u8]>>,
+    },
+}
+
+impl FrameOutput {
+    fn image(&self, image_num: usize) -> Arc<dyn ImageAccess + Send + Sync> {
+        match self {
+            FrameOutput::Swapchain { images, .. } => images[image_num].clone(),
+            FrameOutput::Offscreen { image, .. } => image.clone(),
+        }
+    }
+
+    fn format(&self) -> Format {
+        match self {
+            FrameOutput::Swapchain { swapchain, .. } => swapchain.format(),
+            FrameOutput::Offscreen { .. } => OFFSCREEN_FORMAT,
+        }
+    }
+
+    /// Create an offscreen image of the given size, and the buffer that it is copied into.
+    fn offscreen(device: &Arc<Device>, size: [u32; 2]) -> Self {
+        let image = AttachmentImage::with_usage(
+            device.clone(),
+            size,
+            OFFSCREEN_FORMAT,
+            ImageUsage {
+                color_attachment: true,
+                transfer_source: true,
+                ..ImageUsage::none()
+            },
+        )
+        .unwrap(); // This can only fail if we run out of memory
+        let buffer = CpuAccessibleBuffer::from_iter(
+            device.clone(),
+            BufferUsage::transfer_destination(),
+            false,
+            (0..size[0] * size[1] * 4).map(|_| 0u8),
+        )
+        .unwrap(); // This can only fail if we run out of memory
+        FrameOutput::Offscreen { image, buffer }
+    }
+}
+
 pub(crate) struct RenderPipeline {
     device: Arc<Device>,
     queue: Arc<Queue>,
     dimensions: [f32; 2],
     dynamic_state: DynamicState,
+    /// The framebuffers of the present pass, one for every swapchain image
     framebuffers: Vec<Arc<dyn FramebufferAbstract + Send + Sync>>,
     render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
-    swapchain: Arc<Swapchain<winit::window::Window>>,
-    swapchain_images: Vec<Arc<SwapchainImage<winit::window::Window>>>,
-    swapchain_needs_refresh: bool,
+    present_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    output: FrameOutput,
 
     descriptor_pool: Arc<StdDescriptorPool>,
     model_pipeline: ModelPipeline,
     gui_pipeline: GuiPipeline,
+    lighting_pipeline: Option<LightingPipeline>,
+    tonemap_pipeline: TonemapPipeline,
+    effects_pipeline: EffectsPipeline,
+    auto_exposure_pipeline: AutoExposurePipeline,
+    debug_draw_pipeline: DebugDrawPipeline,
+    weather_pipeline: WeatherPipeline,
+    /// This is created the first time a model has a [PlanarReflection]
+    ///
+    /// [PlanarReflection]: ../reflection/struct.PlanarReflection.html
+    reflection_pipeline: Option<ReflectionPipeline>,
+    /// This is created the first time a model has an [Outline]
+    ///
+    /// [Outline]: ../outline/struct.Outline.html
+    outline_pipeline: Option<OutlinePipeline>,
+    /// This is created the first time [OcclusionCulling] is enabled, on GPUs that support it
+    ///
+    /// [OcclusionCulling]: ../occlusion/enum.OcclusionCulling.html
+    occlusion_pipeline: Option<OcclusionPipeline>,
+    rendering_path: RenderingPath,
+    targets: RenderTargets,
+    /// The render scale that the targets were created with, see [GameState::render_scale](../../struct.GameState.html#method.render_scale)
+    render_scale: f32,
+    transition_snapshot: Option<Arc<AttachmentImage>>,
+    /// The time spent recreating the swapchain since it was last recorded by the profiler
+    swapchain_recreation_time: Duration,
+    /// The environment map that is bound when [LightState::environment] is not set
+    ///
+    /// [LightState::environment]: ../lights/struct.LightState.html#structfield.environment
+    empty_environment: EnvironmentMap,
+    /// The screenshots of the frame that is being rendered, which are saved when the frame is finished
+    pending_screenshots: Option<PendingScreenshots>,
+    /// The frames that were submitted and can still be rendering, the oldest first. Every frame starts after the last one, so the images and buffers that every frame uses are handed over on the GPU.
+    frames_in_flight: VecDeque<Arc<FenceSignalFuture<Box<dyn GpuFuture>>>>,
+    /// Measures the GPU time of the passes of every frame. This is `None` on GPUs that do not support timestamps.
+    ///
+    /// This is dropped after the frames in flight, which wait for the GPU to finish the timestamps.
+    gpu_timer: Option<GpuTimer>,
+}
+
+struct PendingScreenshots {
+    requests: Vec<ScreenshotRequest>,
+    buffer: Arc<CpuAccessibleBuffer<[u8]>>,
+    dimensions: [u32; 2],
 }
 
 impl RenderPipeline {
@@ -41,36 +189,18 @@ impl RenderPipeline {
         surface: Arc<Surface<winit::window::Window>>,
         physical: PhysicalDevice,
         dimensions: [f32; 2],
+        rendering_path: RenderingPath,
     ) -> Result<Self, InitError> {
         let caps = surface
             .capabilities(physical)
             .map_err(InitError::CouldNotLoadSurfaceCapabilities)?;
-        let format = caps.supported_formats[0].0;
-        let render_pass = Arc::new(
-            vulkano::single_pass_renderpass!(device.clone(),
-                attachments: {
-                    color: {
-                        load: Clear,
-                        store: Store,
-                        format: format,
-                        samples: 1,
-                    },
-                    depth: {
-                        load: Clear,
-                        store: DontCare,
-                        format: Format::D16Unorm,
-                        samples: 1,
-                    }
-                },
-                pass: {
-                    color: [color],
-                    depth_stencil: {depth}
-                }
-            )
-            .unwrap(), // should never fail because the device should be valid and the parameters are hard-coded
-        );
-
-        let mut dynamic_state = DynamicState::none();
+        // Prefer an sRGB swapchain, so the GPU does the gamma correction for us
+        let format = caps
+            .supported_formats
+            .iter()
+            .map(|(format, _)| *format)
+            .find(|format| matches!(format, Format::B8G8R8A8Srgb | Format::R8G8B8A8Srgb))
+            .unwrap_or(caps.supported_formats[0].0);
 
         let usage = caps.supported_usage_flags;
         let alpha = caps
@@ -78,7 +208,6 @@ impl RenderPipeline {
             .iter()
             .next()
             .ok_or(InitError::NoCompositeAlpha)?;
-        let format = caps.supported_formats[0].0;
 
         let (swapchain, swapchain_images) = Swapchain::new(
             device.clone(),
@@ -98,76 +227,434 @@ impl RenderPipeline {
         )
         .map_err(InitError::CouldNotInitSwapchain)?;
 
-        let framebuffers = Self::build_framebuffers(
+        let output = FrameOutput::Swapchain {
+            swapchain,
+            images: swapchain_images,
+            needs_refresh: false,
+        };
+        Self::with_output(device, queue, output, dimensions, rendering_path)
+    }
+
+    /// Create a pipeline that renders into an offscreen image instead of a swapchain. Every frame can be read with [offscreen_frame](#method.offscreen_frame) after it is finished.
+    pub fn create_headless(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        dimensions: [f32; 2],
+        rendering_path: RenderingPath,
+    ) -> Result<Self, InitError> {
+        let size = [dimensions[0] as u32, dimensions[1] as u32];
+        let output = FrameOutput::offscreen(&device, size);
+        Self::with_output(device, queue, output, dimensions, rendering_path)
+    }
+
+    /// Create the pipeline again on a new device, after the old device was lost. A window gets a new swapchain on the same surface.
+    ///
+    /// The frames in flight are leaked instead of dropped, because dropping a frame waits for it on the lost device. When a leaked frame still holds on to the old swapchain, the surface can not get a new swapchain and this returns [InitError::CouldNotInitSwapchain].
+    pub fn recreate(
+        &mut self,
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        physical: PhysicalDevice,
+    ) -> Result<(), InitError> {
+        for frame in self.frames_in_flight.drain(..) {
+            std::mem::forget(frame);
+        }
+        let surface = match &self.output {
+            FrameOutput::Swapchain { swapchain, .. } => Some(swapchain.surface().clone()),
+            FrameOutput::Offscreen { .. } => None,
+        };
+        *self = match surface {
+            Some(surface) => {
+                // The old swapchain has to be released before the surface can get a new one
+                self.framebuffers.clear();
+                self.output = FrameOutput::offscreen(&device, [1, 1]);
+                Self::create(
+                    device,
+                    queue,
+                    surface,
+                    physical,
+                    self.dimensions,
+                    self.rendering_path,
+                )?
+            }
+            None => Self::create_headless(device, queue, self.dimensions, self.rendering_path)?,
+        };
+        Ok(())
+    }
+
+    fn with_output(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        output: FrameOutput,
+        dimensions: [f32; 2],
+        rendering_path: RenderingPath,
+    ) -> Result<Self, InitError> {
+        let format = output.format();
+        let render_pass = Self::create_render_pass(device.clone(), rendering_path);
+        let present_pass = Self::create_present_pass(device.clone(), format);
+
+        let mut dynamic_state = DynamicState::none();
+        let (framebuffers, targets) = Self::build_framebuffers(
             device.clone(),
-            &swapchain_images,
+            &output,
             render_pass.clone(),
+            present_pass.clone(),
+            rendering_path,
+            1.0,
             &mut dynamic_state,
         )?;
 
         let descriptor_pool = Arc::new(StdDescriptorPool::new(device.clone()));
 
-        let model_pipeline =
-            ModelPipeline::create(device.clone(), queue.clone(), render_pass.clone());
-        let gui_pipeline = GuiPipeline::create(device.clone(), render_pass.clone());
+        // The subpasses are hard-coded in `create_render_pass` and `create_present_pass`, so these
+        // should never fail
+        let subpass = |index| Subpass::from(render_pass.clone(), index).unwrap();
+        let present_subpass = Subpass::from(present_pass.clone(), 0).unwrap();
+        let lighting_pipeline = match rendering_path {
+            RenderingPath::Forward => None,
+            RenderingPath::Deferred => Some(LightingPipeline::create(device.clone(), subpass(1))),
+        };
+        // The weather is drawn into the HDR buffer, after the models are lit
+        let weather_pipeline = match rendering_path {
+            RenderingPath::Forward => WeatherPipeline::create(device.clone(), subpass(0), false),
+            RenderingPath::Deferred => WeatherPipeline::create(device.clone(), subpass(1), true),
+        };
+
+        let model_pipeline = ModelPipeline::create(
+            device.clone(),
+            queue.clone(),
+            subpass(0),
+            rendering_path,
+            false,
+        );
+        let gui_pipeline =
+            GuiPipeline::create(device.clone(), queue.clone(), present_subpass.clone());
+        let gamma_correct = !matches!(format, Format::B8G8R8A8Srgb | Format::R8G8B8A8Srgb);
+        let tonemap_pipeline =
+            TonemapPipeline::create(device.clone(), present_subpass.clone(), gamma_correct);
+        let effects_pipeline =
+            EffectsPipeline::create(device.clone(), present_subpass.clone(), gamma_correct);
+        let debug_draw_pipeline =
+            DebugDrawPipeline::create(device.clone(), present_subpass, gamma_correct);
+        let auto_exposure_pipeline = AutoExposurePipeline::create(queue.clone());
+        let empty_environment = EnvironmentMap::empty(queue.clone());
+        // The timestamps of a frame are read before the next frame in the same slot starts, so
+        // there is a slot for every frame in flight and one for the frame that is being rendered
+        let gpu_timer = GpuTimer::new(device.clone(), queue.clone(), MAX_FRAMES_IN_FLIGHT + 1);
         Ok(Self {
             device,
             queue,
+            rendering_path,
             gui_pipeline,
+            lighting_pipeline,
+            tonemap_pipeline,
+            effects_pipeline,
+            auto_exposure_pipeline,
+            debug_draw_pipeline,
+            weather_pipeline,
+            reflection_pipeline: None,
+            outline_pipeline: None,
+            occlusion_pipeline: None,
+            targets,
+            render_scale: 1.0,
             dynamic_state,
             framebuffers,
             render_pass,
-            swapchain,
-            swapchain_images,
-            swapchain_needs_refresh: false,
+            present_pass,
+            output,
             dimensions,
             descriptor_pool,
             model_pipeline,
+            transition_snapshot: None,
+            swapchain_recreation_time: Duration::default(),
+            empty_environment,
+            pending_screenshots: None,
+            frames_in_flight: VecDeque::new(),
+            gpu_timer,
         })
     }
 
+    /// Create the render pass that renders the scene into the HDR buffer, for the given rendering
+    /// path.
+    ///
+    /// With the forward rendering path, the models are lit and rendered into the HDR buffer in a
+    /// single subpass.
+    ///
+    /// With the deferred rendering path, the models are rendered into a G-buffer in the first
+    /// subpass. The second subpass calculates the lighting and writes the result into the HDR
+    /// buffer.
+    ///
+    /// The HDR buffer is stored, so the post-processing of the present pass can sample it at any
+    /// position.
+    fn create_render_pass(
+        device: Arc<Device>,
+        rendering_path: RenderingPath,
+    ) -> Arc<dyn RenderPassAbstract + Send + Sync> {
+        match rendering_path {
+            RenderingPath::Forward => Arc::new(
+                vulkano::ordered_passes_renderpass!(device,
+                    attachments: {
+                        hdr: {
+                            load: Clear,
+                            store: Store,
+                            format: HDR_FORMAT,
+                            samples: 1,
+                        },
+                        depth: {
+                            load: Clear,
+                            store: DontCare,
+                            format: Format::D16Unorm,
+                            samples: 1,
+                        }
+                    },
+                    passes: [
+                        {
+                            color: [hdr],
+                            depth_stencil: {depth},
+                            input: []
+                        }
+                    ]
+                )
+                .unwrap(), // should never fail because the device should be valid and the parameters are hard-coded
+            ),
+            RenderingPath::Deferred => Arc::new(
+                vulkano::ordered_passes_renderpass!(device,
+                    attachments: {
+                        ambient: {
+                            load: Clear,
+                            store: DontCare,
+                            format: GBUFFER_FORMAT,
+                            samples: 1,
+                        },
+                        diffuse: {
+                            load: Clear,
+                            store: DontCare,
+                            format: GBUFFER_FORMAT,
+                            samples: 1,
+                        },
+                        specular: {
+                            load: Clear,
+                            store: DontCare,
+                            format: GBUFFER_FORMAT,
+                            samples: 1,
+                        },
+                        normal: {
+                            load: Clear,
+                            store: DontCare,
+                            format: GBUFFER_FORMAT,
+                            samples: 1,
+                        },
+                        depth: {
+                            load: Clear,
+                            store: DontCare,
+                            format: Format::D16Unorm,
+                            samples: 1,
+                        },
+                        hdr: {
+                            load: DontCare,
+                            store: Store,
+                            format: HDR_FORMAT,
+                            samples: 1,
+                        }
+                    },
+                    passes: [
+                        {
+                            color: [ambient, diffuse, specular, normal],
+                            depth_stencil: {depth},
+                            input: []
+                        },
+                        {
+                            color: [hdr],
+                            depth_stencil: {},
+                            input: [ambient, diffuse, specular, normal, depth]
+                        }
+                    ]
+                )
+                .unwrap(), // should never fail because the device should be valid and the parameters are hard-coded
+            ),
+        }
+    }
+
+    /// Create the render pass that tonemaps the HDR buffer into the swapchain image, and draws the
+    /// debug lines, the GUI and the transitions on top of it.
+    fn create_present_pass(
+        device: Arc<Device>,
+        format: Format,
+    ) -> Arc<dyn RenderPassAbstract + Send + Sync> {
+        Arc::new(
+            vulkano::single_pass_renderpass!(device,
+                attachments: {
+                    color: {
+                        load: DontCare,
+                        store: Store,
+                        format: format,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color],
+                    depth_stencil: {}
+                }
+            )
+            .unwrap(), // should never fail because the device should be valid and the parameters are hard-coded
+        )
+    }
+
     fn build_framebuffers(
         device: Arc<Device>,
-        images: &[Arc<SwapchainImage<winit::window::Window>>],
+        output: &FrameOutput,
         render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        present_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        rendering_path: RenderingPath,
+        render_scale: f32,
         dynamic_state: &mut DynamicState,
-    ) -> Result<Vec<Arc<dyn FramebufferAbstract + Send + Sync>>, InitError> {
-        let dimensions = images[0].dimensions();
+    ) -> Result<
+        (
+            Vec<Arc<dyn FramebufferAbstract + Send + Sync>>,
+            RenderTargets,
+        ),
+        InitError,
+    > {
+        match output {
+            FrameOutput::Swapchain { images, .. } => Self::build_framebuffers_for(
+                device,
+                images,
+                images[0].dimensions().width_height(),
+                render_pass,
+                present_pass,
+                rendering_path,
+                render_scale,
+                dynamic_state,
+            ),
+            FrameOutput::Offscreen { image, .. } => Self::build_framebuffers_for(
+                device,
+                std::slice::from_ref(image),
+                image.dimensions().width_height(),
+                render_pass,
+                present_pass,
+                rendering_path,
+                render_scale,
+                dynamic_state,
+            ),
+        }
+    }
 
-        let viewport = Viewport {
-            origin: [0.0, dimensions[1] as f32],
-            dimensions: [dimensions[0] as f32, -(dimensions[1] as f32)],
+    /// Create the framebuffers of the present pass for the given images, and the images that the scene is rendered into. The scene images have the size of the output images multiplied with `render_scale`.
+    #[allow(clippy::too_many_arguments)]
+    fn build_framebuffers_for<I>(
+        device: Arc<Device>,
+        images: &[I],
+        dimensions: [u32; 2],
+        render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        present_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        rendering_path: RenderingPath,
+        render_scale: f32,
+        dynamic_state: &mut DynamicState,
+    ) -> Result<
+        (
+            Vec<Arc<dyn FramebufferAbstract + Send + Sync>>,
+            RenderTargets,
+        ),
+        InitError,
+    >
+    where
+        I: ImageViewAccess + Clone + Send + Sync + 'static,
+    {
+        let viewport = |[width, height]: [u32; 2]| Viewport {
+            origin: [0.0, height as f32],
+            dimensions: [width as f32, -(height as f32)],
             depth_range: 0.0..1.0,
         };
-        dynamic_state.viewports = Some(vec![viewport]);
+        dynamic_state.viewports = Some(vec![viewport(dimensions)]);
+        let dimensions = scaled_dimensions(dimensions, render_scale);
+        let mut scene_dynamic_state = DynamicState::none();
+        scene_dynamic_state.viewports = Some(vec![viewport(dimensions)]);
 
-        let depth_buffer =
-            AttachmentImage::transient(device, dimensions, Format::D16Unorm).unwrap(); // this should always be valid as long as the device is valid
+        // The HDR buffer is sampled by the post-processing and the auto exposure after the scene
+        // render pass, so it can not be transient
+        let hdr = AttachmentImage::with_usage(
+            device.clone(),
+            dimensions,
+            HDR_FORMAT,
+            ImageUsage {
+                sampled: true,
+                ..ImageUsage::none()
+            },
+        )
+        .unwrap(); // this should always be valid as long as the device is valid
+        let depth = AttachmentImage::transient_input_attachment(
+            device.clone(),
+            dimensions,
+            Format::D16Unorm,
+        )
+        .unwrap(); // this should always be valid as long as the device is valid
+        let gbuffer = match rendering_path {
+            RenderingPath::Forward => None,
+            RenderingPath::Deferred => Some(GBuffer::new(device, dimensions)),
+        };
 
-        images
+        let framebuffer = match &gbuffer {
+            None => Framebuffer::start(render_pass)
+                .add(hdr.clone())
+                .and_then(|f| f.add(depth.clone()))
+                .and_then(|f| f.build())
+                .map(|fb| Arc::new(fb) as Arc<dyn FramebufferAbstract + Send + Sync>),
+            Some(gbuffer) => Framebuffer::start(render_pass)
+                .add(gbuffer.ambient.clone())
+                .and_then(|f| f.add(gbuffer.diffuse.clone()))
+                .and_then(|f| f.add(gbuffer.specular.clone()))
+                .and_then(|f| f.add(gbuffer.normal.clone()))
+                .and_then(|f| f.add(depth.clone()))
+                .and_then(|f| f.add(hdr.clone()))
+                .and_then(|f| f.build())
+                .map(|fb| Arc::new(fb) as Arc<dyn FramebufferAbstract + Send + Sync>),
+        }
+        .map_err(InitError::CouldNotBuildSwapchainImages)?;
+        let targets = RenderTargets {
+            framebuffer,
+            dimensions,
+            dynamic_state: scene_dynamic_state,
+            hdr,
+            depth,
+            gbuffer,
+        };
+
+        let framebuffers = images
             .iter()
             .map(|image| {
-                Framebuffer::start(render_pass.clone())
+                Framebuffer::start(present_pass.clone())
                     .add(image.clone())
-                    .and_then(|f| f.add(depth_buffer.clone()))
                     .and_then(|f| f.build())
                     .map(|fb| Arc::new(fb) as Arc<dyn FramebufferAbstract + Send + Sync>)
             })
             .collect::<Result<Vec<_>, _>>()
-            .map_err(InitError::CouldNotBuildSwapchainImages)
+            .map_err(InitError::CouldNotBuildSwapchainImages)?;
+        Ok((framebuffers, targets))
     }
 
+    /// Resize the swapchain to the new size of the window. Offscreen images keep their size.
     pub fn resize(&mut self, dimensions: [f32; 2]) {
-        self.dimensions = dimensions;
-        self.swapchain_needs_refresh = true;
+        if let FrameOutput::Swapchain { needs_refresh, .. } = &mut self.output {
+            self.dimensions = dimensions;
+            *needs_refresh = true;
+        }
     }
 
     fn get_swapchain_num(
         &mut self,
     ) -> Result<Option<(usize, SwapchainAcquireFuture<winit::window::Window>)>, InitError> {
-        if self.swapchain_needs_refresh {
-            let (new_swapchain, new_images) = match self
-                .swapchain
+        let (swapchain, images, needs_refresh) = match &mut self.output {
+            FrameOutput::Swapchain {
+                swapchain,
+                images,
+                needs_refresh,
+            } => (swapchain, images, needs_refresh),
+            // There is only one offscreen image, which never has to be acquired
+            FrameOutput::Offscreen { .. } => return Ok(None),
+        };
+        if *needs_refresh {
+            let start = Instant::now();
+            let (new_swapchain, new_images) = match swapchain
                 .recreate_with_dimensions([self.dimensions[0] as u32, self.dimensions[1] as u32])
             {
                 Ok(r) => r,
@@ -176,26 +663,33 @@ impl RenderPipeline {
                 Err(SwapchainCreationError::UnsupportedDimensions) => return Ok(None),
                 Err(e) => return Err(InitError::CouldNotRecreateSwapchain(e)),
             };
-            self.framebuffers = Self::build_framebuffers(
+            *swapchain = new_swapchain;
+            *images = new_images;
+            *needs_refresh = false;
+
+            let (framebuffers, targets) = Self::build_framebuffers(
                 self.device.clone(),
-                &new_images,
+                &self.output,
                 self.render_pass.clone(),
+                self.present_pass.clone(),
+                self.rendering_path,
+                self.render_scale,
                 &mut self.dynamic_state,
             )?;
-
-            self.swapchain = new_swapchain;
-            self.swapchain_images = new_images;
-            self.swapchain_needs_refresh = false;
+            self.framebuffers = framebuffers;
+            self.targets = targets;
+            self.swapchain_recreation_time += start.elapsed();
+            return self.get_swapchain_num();
         }
-        match vulkano::swapchain::acquire_next_image(self.swapchain.clone(), None) {
+        match vulkano::swapchain::acquire_next_image(swapchain.clone(), None) {
             Ok((num, suboptimal, acquire_future)) => {
                 if suboptimal {
-                    self.swapchain_needs_refresh = true;
+                    *needs_refresh = true;
                 }
                 Ok(Some((num, acquire_future)))
             }
             Err(AcquireError::OutOfDate) => {
-                self.swapchain_needs_refresh = true;
+                *needs_refresh = true;
                 self.get_swapchain_num()
             }
             Err(e) => Err(InitError::CouldNotAcquireSwapchainImage(e)),
@@ -204,81 +698,662 @@ impl RenderPipeline {
 
     pub fn render(
         &mut self,
-        dimensions: [f32; 2],
         game_state: &mut GameState,
-    ) -> Result<Option<FenceSignalFuture<Box<dyn GpuFuture>>>, InitError> {
-        let (image_num, acquire_future) = match self.get_swapchain_num()? {
-            Some(r) => r,
-            None => return Ok(None),
+        custom_render: &mut dyn FnMut(&mut RenderContext),
+    ) -> Result<Option<FenceSignalFuture<Box<dyn GpuFuture>>>, RenderError> {
+        if game_state.render_scale() != self.render_scale {
+            self.set_render_scale(game_state.render_scale())
+                .map_err(RenderError::CouldNotRecreateTargets)?;
+        }
+        // The swapchain image is only used by the present pass, so the scene and the
+        // post-processing do not have to wait until it is acquired
+        let (image_num, acquire_future) = match &self.output {
+            FrameOutput::Offscreen { .. } => (0, vulkano::sync::now(self.device.clone()).boxed()),
+            FrameOutput::Swapchain { .. } => {
+                match self.get_swapchain_num().map_err(|e| match e {
+                    InitError::CouldNotAcquireSwapchainImage(AcquireError::DeviceLost) => {
+                        RenderError::DeviceLost
+                    }
+                    e => RenderError::CouldNotRecreateTargets(e),
+                })? {
+                    Some((image_num, acquire_future)) => (image_num, acquire_future.boxed()),
+                    None => return Ok(None),
+                }
+            }
         };
-        let mut command_buffer_builder = AutoCommandBufferBuilder::primary_one_time_submit(
-            self.device.clone(),
-            self.queue.family(),
-        )
-        .unwrap(); // this can only throw an OomError, which we assume will not happen
+        if let Some(timings) = self.gpu_timer.as_mut().and_then(GpuTimer::next_frame) {
+            game_state.profiler.record_gpu_timings(timings);
+        }
+        // Build a list of futures that need to be processed before this frame is drawn
+        let mut start_future = vulkano::sync::now(self.device.clone()).boxed();
+        for frame in &mut self.frames_in_flight {
+            frame.cleanup_finished();
+        }
+        if let Some(previous) = self.frames_in_flight.back() {
+            start_future = previous.clone().join(start_future).boxed();
+        }
+        game_state.profiler.record_nested(
+            ProfilerStage::SwapchainRecreation,
+            std::mem::take(&mut self.swapchain_recreation_time),
+        );
+        let output_dimensions = self.output.image(image_num).dimensions().width_height();
+        // The scene is rendered at the size of its images, which is the size of the window
+        // multiplied with the render scale
+        let scene_dimensions = [
+            self.targets.dimensions[0] as f32,
+            self.targets.dimensions[1] as f32,
+        ];
+        let mut command_buffer_builder = self.command_buffer_builder()?;
+
+        // The environment map is uploaded in the background when it is loaded
+        let upload_start = Instant::now();
+        let environment = game_state
+            .light
+            .environment
+            .as_ref()
+            .unwrap_or(&self.empty_environment);
+        for future in environment.take_upload_futures() {
+            start_future = start_future.join(future).boxed();
+        }
+        for effect in &game_state.post_processing.effects {
+            if let PostEffect::ColorGrading(grading) = effect {
+                for future in grading.lut.take_upload_futures() {
+                    start_future = start_future.join(future).boxed();
+                }
+            }
+        }
+
+        // Double buffered textures have to be uploaded before the render pass starts
+        for element in game_state.gui_elements.values() {
+            self.gui_pipeline
+                .upload_element(element, &mut command_buffer_builder);
+        }
+        game_state
+            .profiler
+            .record_nested(ProfilerStage::AssetUpload, upload_start.elapsed());
+
+        self.auto_exposure_pipeline.prepare(
+            game_state.post_processing.auto_exposure.as_ref(),
+            &mut command_buffer_builder,
+        );
+
+        let clear_color = game_state.background_color(CLEAR_COLOR);
+
+        // The reflection is rendered into its own image, before the scene that shows it
+        if self.reflection_pipeline.is_none()
+            && game_state
+                .model_handles
+                .values()
+                .any(|model| model.data.read().reflection.is_some())
+        {
+            // The reflection is drawn in the same subpass as the weather
+            let (index, deferred) = match self.rendering_path {
+                RenderingPath::Forward => (0, false),
+                RenderingPath::Deferred => (1, true),
+            };
+            // The subpasses are hard-coded in `create_render_pass`, so this should never fail
+            let subpass = Subpass::from(self.render_pass.clone(), index).unwrap();
+            self.reflection_pipeline = Some(ReflectionPipeline::create(
+                self.device.clone(),
+                self.queue.clone(),
+                subpass,
+                deferred,
+            ));
+        }
+        // The outlines are drawn in the same subpass as the weather as well
+        if self.outline_pipeline.is_none()
+            && game_state
+                .model_handles
+                .values()
+                .any(|model| model.data.read().outline.is_some())
+        {
+            let (index, deferred) = match self.rendering_path {
+                RenderingPath::Forward => (0, false),
+                RenderingPath::Deferred => (1, true),
+            };
+            // The subpasses are hard-coded in `create_render_pass`, so this should never fail
+            let subpass = Subpass::from(self.render_pass.clone(), index).unwrap();
+            self.outline_pipeline = Some(OutlinePipeline::create(
+                self.device.clone(),
+                subpass,
+                deferred,
+            ));
+        }
+        // The bounding boxes are tested with a fragment shader that writes to a storage buffer
+        if self.occlusion_pipeline.is_none()
+            && game_state.occlusion_culling != OcclusionCulling::Disabled
+            && self.device.enabled_features().fragment_stores_and_atomics
+        {
+            // The boxes are tested in the first subpass, which has the depth buffer as its depth
+            // attachment. The subpasses are hard-coded in `create_render_pass`, so this should
+            // never fail.
+            let subpass = Subpass::from(self.render_pass.clone(), 0).unwrap();
+            self.occlusion_pipeline = Some(OcclusionPipeline::create(self.device.clone(), subpass));
+        }
+        if let Some(occlusion_pipeline) = &mut self.occlusion_pipeline {
+            occlusion_pipeline.update(game_state.occlusion_culling);
+        }
+        if let Some(reflection_pipeline) = &mut self.reflection_pipeline {
+            reflection_pipeline.render_reflection(
+                &mut start_future,
+                &mut command_buffer_builder,
+                scene_dimensions,
+                game_state,
+                environment,
+                clear_color,
+                &mut self.descriptor_pool,
+            )?;
+        }
+
+        let clear_values = match self.rendering_path {
+            RenderingPath::Forward => vec![clear_color.into(), 1f32.into()],
+            RenderingPath::Deferred => vec![
+                // The lighting pass uses the ambient color of the background as-is
+                clear_color.into(),
+                [0.0, 0.0, 0.0, 0.0].into(),
+                [0.0, 0.0, 0.0, 0.0].into(),
+                [0.0, 0.0, 0.0, 0.0].into(),
+                1f32.into(),
+                ClearValue::None,
+            ],
+        };
+        command_buffer_builder
+            .begin_render_pass(self.targets.framebuffer.clone(), false, clear_values)
+            .unwrap(); // This can only error if we're in the wrong state of the command buffer, and the state is hard-coded
+
+        self.model_pipeline.render(
+            &mut start_future,
+            &mut command_buffer_builder,
+            scene_dimensions,
+            game_state,
+            &RenderView {
+                occlusion: self
+                    .occlusion_pipeline
+                    .as_ref()
+                    .map(OcclusionPipeline::results),
+                ..RenderView::camera(game_state)
+            },
+            environment,
+            &self.targets.dynamic_state,
+            &mut self.descriptor_pool,
+        )?;
+        // The boxes are tested against the depth of the models that were just rendered
+        if let Some(occlusion_pipeline) = &mut self.occlusion_pipeline {
+            occlusion_pipeline.render(
+                game_state.occlusion_culling,
+                scene_dimensions,
+                game_state,
+                &mut command_buffer_builder,
+                &self.targets.dynamic_state,
+                &mut self.descriptor_pool,
+            );
+        }
+        let mut descriptor_creation_time = self.model_pipeline.take_descriptor_creation_time();
+        let mut render_stats = self.model_pipeline.take_draw_statistics();
+        if let Some(reflection_pipeline) = &mut self.reflection_pipeline {
+            descriptor_creation_time += reflection_pipeline.take_descriptor_creation_time();
+            let reflection_stats = reflection_pipeline.take_draw_statistics();
+            render_stats.draw_calls += reflection_stats.draw_calls;
+            render_stats.triangles += reflection_stats.triangles;
+            render_stats.visible_models += reflection_stats.visible_models;
+        }
+        game_state
+            .profiler
+            .record_nested(ProfilerStage::DescriptorCreation, descriptor_creation_time);
+
+        let view_projection = game_state
+            .perspective_matrix(scene_dimensions[0] / scene_dimensions[1])
+            * game_state.view_matrix();
+        if self.rendering_path == RenderingPath::Forward {
+            if let Some(reflection_pipeline) = &self.reflection_pipeline {
+                reflection_pipeline.render(
+                    scene_dimensions,
+                    game_state,
+                    self.targets.depth.clone(),
+                    &mut command_buffer_builder,
+                    &self.targets.dynamic_state,
+                    &mut self.descriptor_pool,
+                );
+            }
+            if let Some(outline_pipeline) = &mut self.outline_pipeline {
+                outline_pipeline.render(
+                    self.dimensions,
+                    game_state,
+                    self.targets.depth.clone(),
+                    &mut command_buffer_builder,
+                    &self.targets.dynamic_state,
+                    &mut self.descriptor_pool,
+                );
+            }
+            self.weather_pipeline.render(
+                &game_state.weather,
+                &game_state.units,
+                view_projection,
+                self.targets.depth.clone(),
+                &mut command_buffer_builder,
+                &self.targets.dynamic_state,
+                &mut self.descriptor_pool,
+            );
+        }
+
+        if let (Some(lighting_pipeline), Some(gbuffer)) =
+            (&self.lighting_pipeline, &self.targets.gbuffer)
+        {
+            command_buffer_builder.next_subpass(false).unwrap(); // This can only error if we're in the wrong state of the command buffer, and the state is hard-coded
+
+            lighting_pipeline.render(
+                gbuffer,
+                self.targets.depth.clone(),
+                scene_dimensions,
+                game_state,
+                environment,
+                &mut command_buffer_builder,
+                &self.targets.dynamic_state,
+                &mut self.descriptor_pool,
+            );
+            if let Some(reflection_pipeline) = &self.reflection_pipeline {
+                reflection_pipeline.render(
+                    scene_dimensions,
+                    game_state,
+                    self.targets.depth.clone(),
+                    &mut command_buffer_builder,
+                    &self.targets.dynamic_state,
+                    &mut self.descriptor_pool,
+                );
+            }
+            if let Some(outline_pipeline) = &mut self.outline_pipeline {
+                outline_pipeline.render(
+                    self.dimensions,
+                    game_state,
+                    self.targets.depth.clone(),
+                    &mut command_buffer_builder,
+                    &self.targets.dynamic_state,
+                    &mut self.descriptor_pool,
+                );
+            }
+            self.weather_pipeline.render(
+                &game_state.weather,
+                &game_state.units,
+                view_projection,
+                self.targets.depth.clone(),
+                &mut command_buffer_builder,
+                &self.targets.dynamic_state,
+                &mut self.descriptor_pool,
+            );
+        }
+
+        // Custom draw calls of the game are recorded in the same subpass as the weather
+        let subpass_index = match self.rendering_path {
+            RenderingPath::Forward => 0,
+            RenderingPath::Deferred => 1,
+        };
+        custom_render(&mut RenderContext {
+            device: self.device.clone(),
+            queue: self.queue.clone(),
+            // The subpasses are hard-coded in `create_render_pass`, so this should never fail
+            subpass: Subpass::from(self.render_pass.clone(), subpass_index).unwrap(),
+            framebuffer: self.targets.framebuffer.clone(),
+            depth: self.targets.depth.clone(),
+            command_buffer_builder: &mut command_buffer_builder,
+            dynamic_state: &self.targets.dynamic_state,
+            future: &mut start_future,
+            rendering_path: self.rendering_path,
+            dimensions: scene_dimensions,
+            view: game_state.view_matrix(),
+            projection: game_state.perspective_matrix(scene_dimensions[0] / scene_dimensions[1]),
+        });
+
+        command_buffer_builder.end_render_pass().unwrap(); // This can only error if we're in the wrong state of the command buffer, and the state is hard-coded
+
+        // The post-processing and the present pass are recorded into their own command buffers,
+        // so a timestamp can be submitted between them
+        let scene_command_buffer =
+            std::mem::replace(&mut command_buffer_builder, self.command_buffer_builder()?)
+                .build()
+                .map_err(RenderError::CouldNotBuildCommandBuffer)?;
+
+        self.auto_exposure_pipeline.adapt(
+            game_state.post_processing.auto_exposure.as_ref(),
+            game_state.time.real_delta(),
+            self.targets.hdr.clone(),
+            &mut command_buffer_builder,
+            &mut self.descriptor_pool,
+        );
+
+        // The effects are rendered into their own images, so they have to be applied before the
+        // present pass starts
+        let effects_image = if game_state.post_processing.effects.is_empty() {
+            None
+        } else {
+            Some(self.effects_pipeline.apply(
+                self.targets.hdr.clone(),
+                self.auto_exposure_pipeline.exposure(),
+                &game_state.post_processing,
+                &mut command_buffer_builder,
+                &mut self.descriptor_pool,
+            ))
+        };
+        let post_processing_command_buffer =
+            std::mem::replace(&mut command_buffer_builder, self.command_buffer_builder()?)
+                .build()
+                .map_err(RenderError::CouldNotBuildCommandBuffer)?;
 
         command_buffer_builder
             .begin_render_pass(
                 self.framebuffers[image_num].clone(),
                 false,
-                vec![[0.5, 0.5, 1.0, 1.0].into(), 1f32.into()],
+                vec![ClearValue::None],
             )
             .unwrap(); // This can only error if we're in the wrong state of the command buffer, and the state is hard-coded
 
-        // Build a list of futures that need to be processed before this frame is drawn
-        let mut start_future = acquire_future.boxed();
+        match effects_image {
+            Some(image) => self.effects_pipeline.present(
+                image,
+                output_dimensions,
+                &mut command_buffer_builder,
+                &self.dynamic_state,
+                &mut self.descriptor_pool,
+            ),
+            None => self.tonemap_pipeline.render(
+                self.targets.hdr.clone(),
+                self.auto_exposure_pipeline.exposure(),
+                &game_state.post_processing,
+                output_dimensions,
+                &mut command_buffer_builder,
+                &self.dynamic_state,
+                &mut self.descriptor_pool,
+            ),
+        }
 
-        self.model_pipeline.render(
-            &mut start_future,
-            &mut command_buffer_builder,
-            dimensions,
+        self.debug_draw_pipeline.render(
             game_state,
+            view_projection,
+            &mut command_buffer_builder,
             &self.dynamic_state,
             &mut self.descriptor_pool,
         );
 
+        let gui_projection = game_state.gui.projection(self.dimensions);
+        let running = game_state.time.running();
         let mut elements = game_state.gui_elements.values_mut().collect::<Vec<_>>();
         elements.sort_by_cached_key(|e| e.data.read().z_index);
 
         for element in elements {
-            self.gui_pipeline.render_element(
+            let rendered = self.gui_pipeline.render_element(
                 element,
                 &mut command_buffer_builder,
                 &mut start_future,
+                gui_projection,
                 self.dimensions,
+                running,
                 &self.dynamic_state,
                 &mut self.descriptor_pool,
-            );
+            )?;
+            if rendered {
+                render_stats.gui_elements += 1;
+            }
         }
+        // The outlined models were already counted by the model pipeline
+        if let Some(outline_pipeline) = &mut self.outline_pipeline {
+            let outline_stats = outline_pipeline.take_draw_statistics();
+            render_stats.draw_calls += outline_stats.draw_calls;
+            render_stats.triangles += outline_stats.triangles;
+        }
+        game_state.profiler.render_stats = render_stats;
+
+        self.render_transition(game_state, &mut command_buffer_builder, &mut start_future)?;
 
         command_buffer_builder.end_render_pass().unwrap(); // This can only error if we're in the wrong state of the command buffer, and the state is hard-coded
 
-        let command_buffer = command_buffer_builder.build().unwrap(); // This can only error if we're in the wrong state, or we run out of memory
+        if let Some(transition) = game_state.transition.as_mut() {
+            if transition.needs_snapshot() {
+                let image = self.output.image(image_num);
+                let dimensions = image.dimensions().width_height();
+                let snapshot = AttachmentImage::with_usage(
+                    self.device.clone(),
+                    dimensions,
+                    self.output.format(),
+                    ImageUsage {
+                        transfer_destination: true,
+                        sampled: true,
+                        ..ImageUsage::none()
+                    },
+                )
+                .map_err(RenderError::CouldNotAllocateImage)?;
+                command_buffer_builder
+                    .copy_image(
+                        image,
+                        [0, 0, 0],
+                        0,
+                        0,
+                        snapshot.clone(),
+                        [0, 0, 0],
+                        0,
+                        0,
+                        [dimensions[0], dimensions[1], 1],
+                        1,
+                    )
+                    .unwrap(); // The images have the same format and dimensions, so this should never fail
+                self.transition_snapshot = Some(snapshot);
+                transition.snapshot_taken();
+            }
+        } else {
+            self.transition_snapshot = None;
+        }
 
+        let requests = std::mem::take(&mut game_state.storage.screenshots);
+        if !requests.is_empty() {
+            let image = self.output.image(image_num);
+            let dimensions = image.dimensions().width_height();
+            let buffer = CpuAccessibleBuffer::from_iter(
+                self.device.clone(),
+                BufferUsage::transfer_destination(),
+                false,
+                (0..dimensions[0] * dimensions[1] * 4).map(|_| 0u8),
+            )
+            .map_err(RenderError::CouldNotAllocateBuffer)?;
+            command_buffer_builder
+                .copy_image_to_buffer(image, buffer.clone())
+                .unwrap(); // The buffer has the size of the image, so this should never fail
+            self.pending_screenshots = Some(PendingScreenshots {
+                requests,
+                buffer,
+                dimensions,
+            });
+        }
+
+        if let FrameOutput::Offscreen { image, buffer } = &self.output {
+            command_buffer_builder
+                .copy_image_to_buffer(image.clone(), buffer.clone())
+                .unwrap(); // The buffer has the size of the image, so this should never fail
+        }
+
+        let present_command_buffer = command_buffer_builder
+            .build()
+            .map_err(RenderError::CouldNotBuildCommandBuffer)?;
+
+        // Every pass is flushed before its timestamp is submitted, so the timestamp is written
+        // after the pass on the queue
+        self.write_timestamp(GpuTimestamp::SceneStart);
         let future = start_future
-            .then_execute(self.queue.clone(), command_buffer)
-            .unwrap() // This error seems to never trigger
-            .then_swapchain_present(self.queue.clone(), self.swapchain.clone(), image_num)
-            .boxed();
+            .then_execute(self.queue.clone(), scene_command_buffer)
+            .map_err(RenderError::CouldNotExecuteCommandBuffer)?;
+        future.flush().map_err(flush_error)?;
+        self.write_timestamp(GpuTimestamp::PostProcessingStart);
+        let future = future
+            .then_execute(self.queue.clone(), post_processing_command_buffer)
+            .map_err(RenderError::CouldNotExecuteCommandBuffer)?;
+        future.flush().map_err(flush_error)?;
+        self.write_timestamp(GpuTimestamp::PresentStart);
+        let future = acquire_future
+            .join(future)
+            .then_execute(self.queue.clone(), present_command_buffer)
+            .map_err(RenderError::CouldNotExecuteCommandBuffer)?;
+        future.flush().map_err(flush_error)?;
+        self.write_timestamp(GpuTimestamp::End);
+
+        let future = match &self.output {
+            FrameOutput::Swapchain { swapchain, .. } => future
+                .then_swapchain_present(self.queue.clone(), swapchain.clone(), image_num)
+                .boxed(),
+            FrameOutput::Offscreen { .. } => future.boxed(),
+        };
 
         let future = future.then_signal_fence_and_flush();
 
-        match future {
-            Ok(f) => Ok(Some(f)),
-            Err(e) => {
-                if let FlushError::OutOfDate = e {
-                    self.swapchain_needs_refresh = true;
-                } else {
-                    eprintln!("Failed to flush future: {:?}", e);
-                }
+        match (future, &mut self.output) {
+            (Ok(f), _) => Ok(Some(f)),
+            // The swapchain is recreated in the next frame
+            (Err(FlushError::OutOfDate), FrameOutput::Swapchain { needs_refresh, .. }) => {
+                *needs_refresh = true;
                 Ok(None)
             }
+            (Err(e), _) => Err(flush_error(e)),
         }
     }
 
-    pub fn finish_render(&mut self, future: Option<FenceSignalFuture<Box<dyn GpuFuture>>>) {
+    fn command_buffer_builder(&self) -> Result<AutoCommandBufferBuilder, RenderError> {
+        AutoCommandBufferBuilder::primary_one_time_submit(self.device.clone(), self.queue.family())
+            .map_err(RenderError::CouldNotAllocateCommandBuffer)
+    }
+
+    fn write_timestamp(&mut self, timestamp: GpuTimestamp) {
+        if let Some(gpu_timer) = &mut self.gpu_timer {
+            gpu_timer.write(timestamp);
+        }
+    }
+
+    fn render_transition(
+        &mut self,
+        game_state: &GameState,
+        command_buffer_builder: &mut AutoCommandBufferBuilder,
+        future: &mut Box<dyn GpuFuture>,
+    ) -> Result<(), RenderError> {
+        let overlay = match game_state.transition.as_ref().and_then(|t| t.overlay()) {
+            Some(overlay) => overlay,
+            None => return Ok(()),
+        };
+        let [width, height] = self.dimensions;
+        match overlay {
+            TransitionOverlay::Color { color, rect } => {
+                self.gui_pipeline.render_color(
+                    color,
+                    [rect.0 * width, 0.0],
+                    [rect.1 * width, height],
+                    self.dimensions,
+                    command_buffer_builder,
+                    future,
+                    &self.dynamic_state,
+                    &mut self.descriptor_pool,
+                )?;
+            }
+            TransitionOverlay::Snapshot { alpha } => {
+                if let Some(snapshot) = self.transition_snapshot.clone() {
+                    self.gui_pipeline.render_quad(
+                        snapshot,
+                        [0.0, 0.0],
+                        self.dimensions,
+                        [1.0, 1.0, 1.0, alpha],
+                        self.dimensions,
+                        command_buffer_builder,
+                        &self.dynamic_state,
+                        &mut self.descriptor_pool,
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Recreate the images that the scene is rendered into with a new render scale. The swapchain and its framebuffers keep their size.
+    fn set_render_scale(&mut self, render_scale: f32) -> Result<(), InitError> {
+        let (framebuffers, targets) = Self::build_framebuffers(
+            self.device.clone(),
+            &self.output,
+            self.render_pass.clone(),
+            self.present_pass.clone(),
+            self.rendering_path,
+            render_scale,
+            &mut self.dynamic_state,
+        )?;
+        self.framebuffers = framebuffers;
+        self.targets = targets;
+        self.render_scale = render_scale;
+        Ok(())
+    }
+
+    /// Keep track of a frame that was submitted. This only waits for the GPU when more than [MAX_FRAMES_IN_FLIGHT] frames are being rendered, or when the frame is read on the CPU for a screenshot or by a headless window.
+    ///
+    /// The frames were submitted, so waiting on them only fails when the device is lost.
+    pub fn finish_render(
+        &mut self,
+        future: Option<FenceSignalFuture<Box<dyn GpuFuture>>>,
+    ) -> Result<(), RenderError> {
+        let pending_screenshots = self.pending_screenshots.take();
         if let Some(future) = future {
-            future.wait(None).unwrap(); // This future seems to never fail
+            let future = Arc::new(future);
+            self.frames_in_flight.push_back(future.clone());
+            while self.frames_in_flight.len() > MAX_FRAMES_IN_FLIGHT {
+                // There are more than MAX_FRAMES_IN_FLIGHT frames, so this should never fail
+                let oldest = self.frames_in_flight.pop_front().unwrap();
+                oldest.wait(None).map_err(flush_error)?;
+            }
+            let offscreen = matches!(self.output, FrameOutput::Offscreen { .. });
+            if offscreen || pending_screenshots.is_some() {
+                future.wait(None).map_err(flush_error)?;
+            }
+
+            if let Some(pending) = pending_screenshots {
+                // The frame is finished, so the buffer can be read. This should never fail.
+                let pixels = pending.buffer.read().unwrap().to_vec();
+                let bgra = matches!(
+                    self.output.format(),
+                    Format::B8G8R8A8Srgb | Format::B8G8R8A8Unorm
+                );
+                let [width, height] = pending.dimensions;
+                for request in pending.requests {
+                    request.save(pixels.clone(), (width, height), bgra);
+                }
+            }
+        } else if pending_screenshots.is_some() {
+            eprintln!("Could not take a screenshot because the frame was not rendered");
+        }
+        Ok(())
+    }
+
+    /// Read the last frame that was rendered into the offscreen image. This has to be called after [finish_render](#method.finish_render). Returns `None` if the frame is rendered to a swapchain instead.
+    pub fn offscreen_frame(&self) -> Option<RgbaImage> {
+        match &self.output {
+            FrameOutput::Offscreen { image, buffer } => {
+                // The frame is finished, so the buffer can be read. This should never fail.
+                let pixels = buffer.read().unwrap().to_vec();
+                let [width, height] = image.dimensions().width_height();
+                Some(screenshot_image(pixels, (width, height), false, None))
+            }
+            FrameOutput::Swapchain { .. } => None,
         }
     }
 }
+
+/// Get the error of a frame that could not be submitted or waited on.
+fn flush_error(error: FlushError) -> RenderError {
+    match error {
+        FlushError::DeviceLost => RenderError::DeviceLost,
+        error => RenderError::CouldNotSubmitFrame(error),
+    }
+}
+
+/// Get the size of the images that the scene is rendered into, for a frame of the given size. The images are at least a single pixel.
+fn scaled_dimensions(dimensions: [u32; 2], render_scale: f32) -> [u32; 2] {
+    let scale = |size: u32| ((size as f32 * render_scale).round() as u32).max(1);
+    [scale(dimensions[0]), scale(dimensions[1])]
+}
+
+#[test]
+fn test_scaled_dimensions() {
+    assert_eq!([1920, 1080], scaled_dimensions([1920, 1080], 1.0));
+    assert_eq!([960, 540], scaled_dimensions([1920, 1080], 0.5));
+    assert_eq!([1067, 600], scaled_dimensions([1600, 900], 0.6667));
+    assert_eq!([3840, 2160], scaled_dimensions([1920, 1080], 2.0));
+    assert_eq!([1, 1], scaled_dimensions([2, 1], 0.1));
+}
@@ -0,0 +1,310 @@
+use crate::{error::ColorLutError, render::texture::Texture};
+use parking_lot::RwLock;
+use std::{sync::Arc, time::Duration};
+use vulkano::sync::GpuFuture;
+
+/// Settings of the final pass of the engine, which turns the HDR image of the scene into the image that is shown on the screen.
+///
+/// ```no_run
+/// # use crystal_engine::*;
+/// # use crystal_engine::state::{
+/// #     AutoExposure, Bloom, ColorAdjustments, PostEffect, Tonemapping, Vignette,
+/// # };
+/// # let mut state: GameState = unsafe { std::mem::zeroed() };
+/// state.post_processing.exposure = 1.5;
+/// state.post_processing.tonemapping = Tonemapping::Aces;
+///
+/// // Adapt the exposure to the brightness of the scene, e.g. when walking from a dark
+/// // interior into the sunlight
+/// state.post_processing.auto_exposure = Some(AutoExposure {
+///     max_exposure: 4.0,
+///     ..AutoExposure::default()
+/// });
+///
+/// // A retro look
+/// state.post_processing.chromatic_aberration = 0.004;
+/// state.post_processing.film_grain = 0.05;
+/// state.post_processing.scanlines = 0.3;
+///
+/// // A cold and washed out mood
+/// state.post_processing.color_adjustments = ColorAdjustments {
+///     saturation: 0.6,
+///     contrast: 1.1,
+///     ..ColorAdjustments::default()
+/// };
+///
+/// // Effects that run after the tonemapping, in this order
+/// state.post_processing.effects = vec![
+///     PostEffect::Bloom(Bloom::default()),
+///     PostEffect::Vignette(Vignette::default()),
+///     PostEffect::Fxaa,
+/// ];
+/// ```
+pub struct PostProcessingState {
+    /// The exposure of the camera. The colors of the scene are multiplied by this value before they are tonemapped. Defaults to `1.0`.
+    pub exposure: f32,
+    /// The tonemapping operator that is used to map the HDR colors of the scene to the screen. Defaults to [Tonemapping::None].
+    ///
+    /// [Tonemapping::None]: ./enum.Tonemapping.html#variant.None
+    pub tonemapping: Tonemapping,
+    /// Automatically adapt the exposure to the average brightness of the scene. The adapted exposure is multiplied with [exposure](#structfield.exposure), so that can still be used to make the scene brighter or darker. Defaults to `None`.
+    pub auto_exposure: Option<AutoExposure>,
+    /// Moves the red and blue channels of the image apart towards the edges of the screen, like a cheap camera lens. This is the distance between the channels at the edges, as a fraction of the size of the screen, so values around `0.005` are already noticeable. Defaults to `0.0`, which disables the effect.
+    pub chromatic_aberration: f32,
+    /// The strength of the noise that is added to every pixel, which changes every frame like the grain of a film. Defaults to `0.0`, which disables the effect.
+    pub film_grain: f32,
+    /// How much the dark lines of an old CRT screen darken the image, between `0.0` and `1.0`. Defaults to `0.0`, which disables the effect.
+    pub scanlines: f32,
+    /// Simple changes to the colors of the image, like brightness and saturation. These are applied right after the tonemapping, before the [effects](#structfield.effects). Defaults to [ColorAdjustments::default], which does not change the image.
+    ///
+    /// For more control over the colors, see [PostEffect::ColorGrading].
+    ///
+    /// [ColorAdjustments::default]: ./struct.ColorAdjustments.html
+    /// [PostEffect::ColorGrading]: ./enum.PostEffect.html#variant.ColorGrading
+    pub color_adjustments: ColorAdjustments,
+    /// Fullscreen effects that are applied to the image after it is tonemapped, in order. Every effect is an extra pass over the whole screen, so the GUI is not affected by them. Defaults to no effects.
+    pub effects: Vec<PostEffect>,
+}
+
+impl Default for PostProcessingState {
+    fn default() -> Self {
+        Self {
+            exposure: 1.0,
+            tonemapping: Tonemapping::None,
+            auto_exposure: None,
+            chromatic_aberration: 0.0,
+            film_grain: 0.0,
+            scanlines: 0.0,
+            color_adjustments: ColorAdjustments::default(),
+            effects: Vec::new(),
+        }
+    }
+}
+
+/// The operator that is used to map the HDR colors of the scene to the screen.
+///
+/// For more information, see [https://learnopengl.com/Advanced-Lighting/HDR](https://learnopengl.com/Advanced-Lighting/HDR)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tonemapping {
+    /// Colors that are brighter than white are clamped to white.
+    None,
+    /// The Reinhard operator. Bright colors are smoothly compressed, but the image can look a bit washed out.
+    Reinhard,
+    /// An approximation of the ACES filmic curve. This has more contrast than Reinhard.
+    Aces,
+}
+
+impl Tonemapping {
+    pub(crate) fn to_shader_value(self) -> i32 {
+        match self {
+            Tonemapping::None => 0,
+            Tonemapping::Reinhard => 1,
+            Tonemapping::Aces => 2,
+        }
+    }
+}
+
+/// Settings of [PostProcessingState::color_adjustments]. The adjustments are applied to the sRGB colors of the image, so the values behave like the sliders of an image editor. The default values do not change the image.
+///
+/// [PostProcessingState::color_adjustments]: ./struct.PostProcessingState.html#structfield.color_adjustments
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColorAdjustments {
+    /// A value that is added to every channel, e.g. `0.1` to make the image brighter or `-0.1` to make it darker. Defaults to `0.0`.
+    pub brightness: f32,
+    /// How far the colors are pushed away from grey. Values above `1.0` make dark colors darker and bright colors brighter, values below `1.0` make the image flat. Defaults to `1.0`.
+    pub contrast: f32,
+    /// How colorful the image is. `0.0` makes the image black and white, and values above `1.0` make the colors more intense. Defaults to `1.0`.
+    pub saturation: f32,
+    /// Brightens the mid-tones of the image when this is above `1.0`, and darkens them when it is below `1.0`, without changing black and white. This is the gamma slider of the display settings of many games. Defaults to `1.0`.
+    pub gamma: f32,
+}
+
+impl Default for ColorAdjustments {
+    fn default() -> Self {
+        Self {
+            brightness: 0.0,
+            contrast: 1.0,
+            saturation: 1.0,
+            gamma: 1.0,
+        }
+    }
+}
+
+/// An effect of [PostProcessingState::effects].
+///
+/// [PostProcessingState::effects]: ./struct.PostProcessingState.html#structfield.effects
+#[derive(Clone)]
+pub enum PostEffect {
+    /// Makes bright parts of the image glow into their surroundings.
+    Bloom(Bloom),
+    /// Darkens the edges of the screen.
+    Vignette(Vignette),
+    /// Fast approximate anti-aliasing, which smooths the jagged edges of models. This is cheap, but can blur small details like text on textures. This should usually be the last effect.
+    Fxaa,
+    /// Changes the colors of the image with a lookup table, see [ColorGrading].
+    ColorGrading(ColorGrading),
+}
+
+/// Settings of [PostEffect::Bloom]. The bright parts of the image are blurred at half the resolution of the screen, and added on top of the image.
+///
+/// [PostEffect::Bloom]: ./enum.PostEffect.html#variant.Bloom
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bloom {
+    /// The brightness above which pixels start to glow, between `0.0` and `1.0`. Defaults to `0.8`.
+    pub threshold: f32,
+    /// How strong the glow is added to the image. Defaults to `0.6`.
+    pub intensity: f32,
+    /// How far the glow spreads, in pixels of the blurred image. Defaults to `1.5`.
+    pub radius: f32,
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Self {
+            threshold: 0.8,
+            intensity: 0.6,
+            radius: 1.5,
+        }
+    }
+}
+
+/// Settings of [PostEffect::Vignette].
+///
+/// [PostEffect::Vignette]: ./enum.PostEffect.html#variant.Vignette
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vignette {
+    /// How dark the corners of the screen become, from `0.0` to `1.0`. Defaults to `0.4`.
+    pub intensity: f32,
+    /// The distance from the center of the screen where the darkening starts, where `1.0` is the distance to the corners. Defaults to `0.6`.
+    pub radius: f32,
+    /// The distance over which the darkening fades in, relative to the distance to the corners. Defaults to `0.4`.
+    pub smoothness: f32,
+}
+
+impl Default for Vignette {
+    fn default() -> Self {
+        Self {
+            intensity: 0.4,
+            radius: 0.6,
+            smoothness: 0.4,
+        }
+    }
+}
+
+/// Settings of [PostEffect::ColorGrading].
+///
+/// [PostEffect::ColorGrading]: ./enum.PostEffect.html#variant.ColorGrading
+#[derive(Clone)]
+pub struct ColorGrading {
+    /// The lookup table, which is loaded with [GameState::load_color_lut](../struct.GameState.html#method.load_color_lut).
+    pub lut: ColorLut,
+    /// How much the graded colors replace the original colors, from `0.0` to `1.0`. This can be animated to fade a mood in and out.
+    pub intensity: f32,
+}
+
+/// A color lookup table, which maps every color of the image to a new color. This is created with [GameState::load_color_lut].
+///
+/// A lookup table of size `N` is an image of `N * N` by `N` pixels, made of `N` squares next to each other. Within a square the red channel increases from left to right, and the green channel from top to bottom. The blue channel increases from square to square. The colors are sRGB colors, so a lookup table can be made by color grading a screenshot together with the neutral lookup table in an image editor.
+///
+/// This is cheap to clone, the image is shared between clones.
+///
+/// [GameState::load_color_lut]: ../struct.GameState.html#method.load_color_lut
+#[derive(Clone)]
+pub struct ColorLut {
+    pub(crate) texture: Texture,
+    pub(crate) size: u32,
+    /// The upload of the image, which has to finish before the first frame that uses it
+    upload: Arc<RwLock<Vec<Box<dyn GpuFuture>>>>,
+}
+
+impl ColorLut {
+    pub(crate) fn new(texture: Texture, size: u32, upload: Box<dyn GpuFuture>) -> Self {
+        Self {
+            texture,
+            size,
+            upload: Arc::new(RwLock::new(vec![upload])),
+        }
+    }
+
+    /// Get the size of the lookup table, see [ColorLut].
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub(crate) fn take_upload_futures(&self) -> Vec<Box<dyn GpuFuture>> {
+        if self.upload.read().is_empty() {
+            return Vec::new();
+        }
+        std::mem::replace(&mut *self.upload.write(), Vec::new())
+    }
+
+    /// Check the dimensions of a lookup table image, and return the size of the lookup table.
+    pub(crate) fn validate(path: &str, width: u32, height: u32) -> Result<u32, ColorLutError> {
+        if height < 2 || width != height * height {
+            return Err(ColorLutError::InvalidSize {
+                path: path.to_owned(),
+                width,
+                height,
+            });
+        }
+        Ok(height)
+    }
+}
+
+/// Settings of the automatic exposure of [PostProcessingState::auto_exposure].
+///
+/// Every frame the average brightness of the HDR image of the scene is measured on the GPU. The exposure then slowly moves towards the value that maps this brightness to [key](#structfield.key), like an eye that adapts to the dark. Pixels that are completely black, like an empty background, are not counted.
+///
+/// [PostProcessingState::auto_exposure]: ./struct.PostProcessingState.html#structfield.auto_exposure
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AutoExposure {
+    /// The brightness that the average brightness of the scene is mapped to. Defaults to `0.18`, which is middle grey.
+    pub key: f32,
+    /// The lowest exposure, which is used in very bright scenes. Defaults to `0.1`.
+    pub min_exposure: f32,
+    /// The highest exposure, which is used in very dark scenes. This prevents dark scenes from becoming grey and noisy. Defaults to `8.0`.
+    pub max_exposure: f32,
+    /// How fast the exposure adapts to changes in brightness. After `1.0 / adaptation_speed` seconds the exposure has moved about two thirds of the way to the new value. Defaults to `1.5`.
+    pub adaptation_speed: f32,
+}
+
+impl Default for AutoExposure {
+    fn default() -> Self {
+        Self {
+            key: 0.18,
+            min_exposure: 0.1,
+            max_exposure: 8.0,
+            adaptation_speed: 1.5,
+        }
+    }
+}
+
+impl AutoExposure {
+    /// The fraction of the distance to the target exposure that the exposure moves over `delta`. This does not depend on the frame rate.
+    pub(crate) fn adaptation(&self, delta: Duration) -> f32 {
+        1.0 - (-delta.as_secs_f32() * self.adaptation_speed.max(0.0)).exp()
+    }
+}
+
+#[test]
+fn test_auto_exposure_adaptation() {
+    let settings = AutoExposure::default();
+    assert_eq!(0.0, settings.adaptation(Duration::from_secs(0)));
+
+    // Two frames of 10ms adapt as much as a single frame of 20ms
+    let short = settings.adaptation(Duration::from_millis(10));
+    let long = settings.adaptation(Duration::from_millis(20));
+    let remaining = (1.0 - short) * (1.0 - short);
+    assert!((1.0 - remaining - long).abs() < 0.0001);
+
+    // After a long time the exposure has reached its target
+    assert!(settings.adaptation(Duration::from_secs(60)) > 0.999);
+}
+
+#[test]
+fn test_color_lut_size() {
+    assert_eq!(16, ColorLut::validate("lut.png", 256, 16).unwrap());
+    assert_eq!(32, ColorLut::validate("lut.png", 1024, 32).unwrap());
+    assert!(ColorLut::validate("lut.png", 256, 256).is_err());
+    assert!(ColorLut::validate("lut.png", 1, 1).is_err());
+}
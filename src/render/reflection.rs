@@ -0,0 +1,617 @@
+use super::{
+    clip_plane::ClipPlane, environment::EnvironmentMap, frustum::Frustum, pipeline::HDR_FORMAT,
+};
+use crate::{
+    model::{ModelRef, Pipeline as ModelPipeline, RenderView, Vertex},
+    profiler::RenderStats,
+    state::RenderError,
+    GameState, RenderingPath,
+};
+use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector3, Zero};
+use std::{sync::Arc, time::Duration};
+use vulkano::{
+    buffer::CpuBufferPool,
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet, StdDescriptorPool},
+    device::{Device, Queue},
+    format::Format,
+    framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract, Subpass},
+    image::attachment::AttachmentImage,
+    pipeline::{
+        depth_stencil::{Compare, DepthStencil},
+        viewport::Viewport,
+        GraphicsPipeline, GraphicsPipelineAbstract,
+    },
+    sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
+    sync::GpuFuture,
+};
+
+/// Makes a model a mirror or the surface of water, see [ModelData::reflection].
+///
+/// The world is rendered a second time, mirrored in the plane of the model, into an offscreen image. The model is then drawn again with this image on top of it, so the model itself is still visible through the reflection.
+///
+/// ```no_run
+/// # use crystal_engine::*;
+/// # use crystal_engine::state::PlanarReflection;
+/// # use cgmath::Vector3;
+/// # let mut state: GameState = unsafe { std::mem::zeroed() };
+/// let water = state
+///     .new_plane_model(1)
+///     .with_position(Vector3::new(0.0, -1.0, 0.0))
+///     .with_reflection(PlanarReflection {
+///         distortion: 0.01,
+///         ..PlanarReflection::default()
+///     })
+///     .build()
+///     .unwrap();
+/// ```
+///
+/// Only the visible reflective model that is closest to the camera is reflected every frame. Reflections of reflections are not rendered.
+///
+/// Note: the part of the world behind the plane is cut with a [ClipPlane], so it shows up in the reflection when the GPU does not support the `shader_clip_distance` feature.
+///
+/// [ModelData::reflection]: ../models/struct.ModelData.html#structfield.reflection
+/// [ClipPlane]: ./struct.ClipPlane.html
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct PlanarReflection {
+    /// The normal of the reflective surface, relative to the model. Defaults to up, which is the visible side of [GameState::new_plane_model](../struct.GameState.html#method.new_plane_model).
+    pub normal: Vector3<f32>,
+    /// How much of the reflection is drawn over the model, from `0.0` for only the model to `1.0` for a perfect mirror. Defaults to `0.6`.
+    pub strength: f32,
+    /// How far the reflection is moved by ripples, as a fraction of the size of the screen. `0.0` is a perfectly still mirror, around `0.01` looks like water. Defaults to `0.0`.
+    pub distortion: f32,
+    /// How fast the ripples of the [distortion](#structfield.distortion) move. Defaults to `1.0`.
+    pub distortion_speed: f32,
+    /// The size of the reflection image, relative to the size of the window. Smaller images are faster to render, but blurrier. Defaults to `0.5`.
+    pub resolution_scale: f32,
+}
+
+impl Default for PlanarReflection {
+    fn default() -> Self {
+        Self {
+            normal: Vector3::unit_y(),
+            strength: 0.6,
+            distortion: 0.0,
+            distortion_speed: 1.0,
+            resolution_scale: 0.5,
+        }
+    }
+}
+
+impl PlanarReflection {
+    /// The plane of the reflection in the world, for a model with the given matrix. The normal of the plane points to the side of the camera, so only the world in front of the mirror is reflected.
+    fn world_plane(&self, model_matrix: Matrix4<f32>, camera_position: Vector3<f32>) -> ClipPlane {
+        let point = model_matrix.w.truncate();
+        // Models are only rotated and uniformly scaled, so the normal can be transformed by the
+        // matrix itself
+        let normal = (model_matrix * self.normal.extend(0.0)).truncate();
+        let plane = ClipPlane::new(point, normal);
+        if plane.signed_distance(camera_position) < 0.0 {
+            ClipPlane::new(point, -normal)
+        } else {
+            plane
+        }
+    }
+
+    /// The size of the reflection image, for a window of the given size
+    fn image_size(&self, dimensions: [f32; 2]) -> [u32; 2] {
+        let scale = self.resolution_scale.max(0.01).min(1.0);
+        [
+            ((dimensions[0] * scale) as u32).max(1),
+            ((dimensions[1] * scale) as u32).max(1),
+        ]
+    }
+}
+
+/// The matrix that mirrors the world in the given plane.
+fn reflection_matrix(plane: &ClipPlane) -> Matrix4<f32> {
+    let n = plane.normal;
+    let d = plane.distance;
+    #[rustfmt::skip]
+    let matrix = Matrix4::new(
+        1.0 - 2.0 * n.x * n.x, -2.0 * n.x * n.y, -2.0 * n.x * n.z, 0.0,
+        -2.0 * n.x * n.y, 1.0 - 2.0 * n.y * n.y, -2.0 * n.y * n.z, 0.0,
+        -2.0 * n.x * n.z, -2.0 * n.y * n.z, 1.0 - 2.0 * n.z * n.z, 0.0,
+        -2.0 * d * n.x, -2.0 * d * n.y, -2.0 * d * n.z, 1.0,
+    );
+    matrix
+}
+
+/// The reflection that is rendered this frame
+struct ActiveReflection {
+    model_id: u64,
+    reflection: PlanarReflection,
+}
+
+/// The image that the mirrored world is rendered into
+struct ReflectionTarget {
+    framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    color: Arc<AttachmentImage>,
+    size: [u32; 2],
+    dynamic_state: DynamicState,
+}
+
+/// Renders the planar reflections of models, see [PlanarReflection].
+///
+/// The mirrored world is rendered with the forward rendering path into its own render pass, before the scene is rendered. The reflection is drawn over the model after the models are lit, like the weather.
+pub(crate) struct Pipeline {
+    device: Arc<Device>,
+    render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    model_pipeline: ModelPipeline,
+    pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    uniform_buffer: CpuBufferPool<vs::ty::Data>,
+    sampler: Arc<Sampler>,
+    deferred: bool,
+    target: Option<ReflectionTarget>,
+    active: Option<ActiveReflection>,
+}
+
+impl Pipeline {
+    /// Create the pipeline. `subpass` is the subpass that the reflection is drawn in, `deferred` is true if this is the lighting pass of the deferred rendering path.
+    pub fn create(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        subpass: Subpass<Arc<dyn RenderPassAbstract + Send + Sync>>,
+        deferred: bool,
+    ) -> Self {
+        let render_pass: Arc<dyn RenderPassAbstract + Send + Sync> = Arc::new(
+            vulkano::single_pass_renderpass!(device.clone(),
+                attachments: {
+                    color: {
+                        load: Clear,
+                        store: Store,
+                        format: HDR_FORMAT,
+                        samples: 1,
+                    },
+                    depth: {
+                        load: Clear,
+                        store: DontCare,
+                        format: Format::D16Unorm,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color],
+                    depth_stencil: {depth}
+                }
+            )
+            .unwrap(), // should never fail because the device should be valid and the parameters are hard-coded
+        );
+        // The subpass is hard-coded above, so this should never fail
+        let reflection_subpass = Subpass::from(render_pass.clone(), 0).unwrap();
+        let model_pipeline = ModelPipeline::create(
+            device.clone(),
+            queue,
+            reflection_subpass,
+            RenderingPath::Forward,
+            true,
+        );
+
+        // These should never fail, as the shaders are hard-coded and the device is assumed to be
+        // valid.
+        let vs = vs::Shader::load(device.clone()).expect("failed to create shader module");
+        let builder = GraphicsPipeline::start()
+            .vertex_input_single_buffer::<Vertex>()
+            .vertex_shader(vs.main_entry_point(), ())
+            .viewports_dynamic_scissors_irrelevant(1)
+            .cull_mode_disabled()
+            .blend_alpha_blending();
+
+        // This should never fail because all arguments are hard-coded
+        let pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync> = if deferred {
+            let fs =
+                fs_deferred::Shader::load(device.clone()).expect("failed to create shader module");
+            Arc::new(
+                builder
+                    .fragment_shader(fs.main_entry_point(), ())
+                    .render_pass(subpass)
+                    .build(device.clone())
+                    .unwrap(),
+            )
+        } else {
+            let fs = fs::Shader::load(device.clone()).expect("failed to create shader module");
+            Arc::new(
+                builder
+                    .fragment_shader(fs.main_entry_point(), ())
+                    // The reflection is drawn on the surface of the model that was just rendered
+                    .depth_stencil(DepthStencil {
+                        depth_write: false,
+                        depth_compare: Compare::LessOrEqual,
+                        ..DepthStencil::simple_depth_test()
+                    })
+                    .render_pass(subpass)
+                    .build(device.clone())
+                    .unwrap(),
+            )
+        };
+
+        let sampler = Sampler::new(
+            device.clone(),
+            Filter::Linear,
+            Filter::Linear,
+            MipmapMode::Nearest,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            0.0,
+            1.0,
+            0.0,
+            0.0,
+        )
+        // The arguments are hard-coded so this is assumed to never fail
+        .unwrap();
+
+        Self {
+            uniform_buffer: CpuBufferPool::uniform_buffer(device.clone()),
+            device,
+            render_pass,
+            model_pipeline,
+            pipeline,
+            sampler,
+            deferred,
+            target: None,
+            active: None,
+        }
+    }
+
+    /// Get the time that was spent creating descriptor sets since the last call to this function.
+    pub fn take_descriptor_creation_time(&mut self) -> Duration {
+        self.model_pipeline.take_descriptor_creation_time()
+    }
+
+    /// Get the amount of draw calls, triangles and visible models since the last call to this function.
+    pub fn take_draw_statistics(&mut self) -> RenderStats {
+        self.model_pipeline.take_draw_statistics()
+    }
+
+    /// Render the mirrored world of the reflective model that is closest to the camera. This has to be called outside of a render pass. Nothing is rendered when no reflective model is visible.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_reflection(
+        &mut self,
+        future: &mut Box<dyn GpuFuture>,
+        command_buffer_builder: &mut AutoCommandBufferBuilder,
+        dimensions: [f32; 2],
+        game_state: &GameState,
+        environment: &EnvironmentMap,
+        clear_color: [f32; 4],
+        descriptor_pool: &mut Arc<StdDescriptorPool>,
+    ) -> Result<(), RenderError> {
+        self.active = None;
+        let view = game_state.view_matrix();
+        let frustum = Frustum::from_matrix(
+            game_state.perspective_matrix(dimensions[0] / dimensions[1]) * view,
+        );
+        // A camera matrix that can not be inverted is invalid, so fall back to the origin of the
+        // world
+        let camera_position = view
+            .invert()
+            .map(|m| m.w.truncate())
+            .unwrap_or_else(Vector3::zero);
+        let (model_id, model, reflection) = match closest_reflection(
+            game_state
+                .model_handles
+                .iter()
+                .filter(|(_, model)| model.is_visible(&frustum)),
+            camera_position,
+        ) {
+            Some(closest) => closest,
+            None => return Ok(()),
+        };
+        let plane = {
+            let data = model.data.read();
+            reflection.world_plane(game_state.model_matrix(model, &data), camera_position)
+        };
+
+        let size = reflection.image_size(dimensions);
+        if self.target.as_ref().map(|t| t.size) != Some(size) {
+            self.target = Some(self.create_target(size));
+        }
+        // The target was created above, so this should never fail
+        let target = self.target.as_ref().unwrap();
+
+        command_buffer_builder
+            .begin_render_pass(
+                target.framebuffer.clone(),
+                false,
+                vec![clear_color.into(), 1f32.into()],
+            )
+            .unwrap(); // This can only error if we're in the wrong state of the command buffer, and the state is hard-coded
+        self.model_pipeline.render(
+            future,
+            command_buffer_builder,
+            dimensions,
+            game_state,
+            &RenderView {
+                view: view * reflection_matrix(&plane),
+                clip_plane: Some(plane),
+                hidden_model: Some(model_id),
+                occlusion: None,
+            },
+            environment,
+            &target.dynamic_state,
+            descriptor_pool,
+        )?;
+        command_buffer_builder.end_render_pass().unwrap(); // This can only error if we're in the wrong state of the command buffer, and the state is hard-coded
+
+        self.active = Some(ActiveReflection {
+            model_id,
+            reflection,
+        });
+        Ok(())
+    }
+
+    fn create_target(&self, size: [u32; 2]) -> ReflectionTarget {
+        // These should always be valid as long as the device is valid
+        let color = AttachmentImage::sampled(self.device.clone(), size, HDR_FORMAT).unwrap();
+        let depth =
+            AttachmentImage::transient(self.device.clone(), size, Format::D16Unorm).unwrap();
+        let framebuffer = Arc::new(
+            Framebuffer::start(self.render_pass.clone())
+                .add(color.clone())
+                .and_then(|f| f.add(depth))
+                .and_then(|f| f.build())
+                // The attachments match the render pass, so this should never fail
+                .unwrap(),
+        );
+        let mut dynamic_state = DynamicState::none();
+        dynamic_state.viewports = Some(vec![Viewport {
+            origin: [0.0, size[1] as f32],
+            dimensions: [size[0] as f32, -(size[1] as f32)],
+            depth_range: 0.0..1.0,
+        }]);
+        ReflectionTarget {
+            framebuffer,
+            color,
+            size,
+            dynamic_state,
+        }
+    }
+
+    /// Draw the reflection that was rendered this frame over its model. `depth` is only used by the deferred rendering path.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render(
+        &self,
+        dimensions: [f32; 2],
+        game_state: &GameState,
+        depth: Arc<AttachmentImage>,
+        command_buffer_builder: &mut AutoCommandBufferBuilder,
+        dynamic_state: &DynamicState,
+        descriptor_pool: &mut Arc<StdDescriptorPool>,
+    ) {
+        let (active, target) = match (&self.active, &self.target) {
+            (Some(active), Some(target)) => (active, target),
+            _ => return,
+        };
+        // The model was looked up when the reflection was rendered, earlier in this frame
+        let model = match game_state.model_handles.get(&active.model_id) {
+            Some(model) => model,
+            None => return,
+        };
+        let model_data = model.data.read();
+        let base_matrix = game_state.model_matrix(model, &model_data);
+        let view = game_state.view_matrix();
+        let camera_position = view
+            .invert()
+            .map(|m| m.w.truncate())
+            .unwrap_or_else(Vector3::zero);
+        let reflection = &active.reflection;
+        let mut data = vs::ty::Data {
+            world: Matrix4::identity().into(),
+            view: view.into(),
+            proj: game_state
+                .perspective_matrix(dimensions[0] / dimensions[1])
+                .into(),
+            screen_width: dimensions[0],
+            screen_height: dimensions[1],
+            strength: reflection.strength.max(0.0).min(1.0),
+            distortion: reflection.distortion.max(0.0),
+            time: game_state.time.running().as_secs_f32() * reflection.distortion_speed,
+        };
+
+        // The pipeline and the layout index are hard-coded so this is assumed to never fail
+        let layout = self.pipeline.descriptor_set_layout(0).unwrap();
+        let mesh = model.mesh();
+        let groups = mesh.draw_groups((model_data.position - camera_position).magnitude());
+        for (index, draw_group) in groups.into_iter().enumerate() {
+            let vertex_buffer = match draw_group.vertex_buffer {
+                Some(vertex_buffer) => vertex_buffer,
+                // A mesh that was changed at runtime can be empty
+                None => continue,
+            };
+            // Levels of detail can have more groups than the model itself
+            let group_matrix = model_data
+                .groups
+                .get(index)
+                .map(|g| g.matrix)
+                .unwrap_or_else(Matrix4::identity);
+            data.world = (base_matrix * group_matrix).into();
+            // The uniform_buffer is assumed to be valid so this should never fail
+            let uniform_buffer_subbuffer = self.uniform_buffer.next(data).unwrap();
+
+            // The uniform subbuffer, the image and the sampler are assumed to be valid so these
+            // should never fail
+            let set = PersistentDescriptorSet::start(layout.clone())
+                .add_buffer(uniform_buffer_subbuffer)
+                .unwrap()
+                .add_sampled_image(target.color.clone(), self.sampler.clone())
+                .unwrap();
+            // The depth buffer is an input attachment of the lighting pass and the pool is assumed
+            // to be valid, so these should never fail
+            let set: Arc<dyn DescriptorSet + Send + Sync> = if self.deferred {
+                Arc::new(
+                    set.add_image(depth.clone())
+                        .unwrap()
+                        .build_with_pool(descriptor_pool)
+                        .unwrap(),
+                )
+            } else {
+                Arc::new(set.build_with_pool(descriptor_pool).unwrap())
+            };
+            // the builder and arguments are assumed to be valid so these should never fail
+            if let Some(index) = draw_group.index {
+                command_buffer_builder
+                    .draw_indexed(
+                        self.pipeline.clone(),
+                        dynamic_state,
+                        vec![vertex_buffer],
+                        index,
+                        set,
+                        (),
+                    )
+                    .unwrap();
+            } else {
+                command_buffer_builder
+                    .draw(
+                        self.pipeline.clone(),
+                        dynamic_state,
+                        vec![vertex_buffer],
+                        set,
+                        (),
+                    )
+                    .unwrap();
+            }
+        }
+    }
+}
+
+/// Find the reflective model that is closest to the camera
+fn closest_reflection<'a>(
+    models: impl Iterator<Item = (&'a u64, &'a ModelRef)>,
+    camera_position: Vector3<f32>,
+) -> Option<(u64, &'a ModelRef, PlanarReflection)> {
+    models
+        .filter_map(|(id, model)| {
+            let data = model.data.read();
+            let reflection = data.reflection?;
+            let distance = (data.position - camera_position).magnitude2();
+            Some((distance, *id, model, reflection))
+        })
+        .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal))
+        .map(|(_, id, model, reflection)| (id, model, reflection))
+}
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "#version 450
+layout(location = 0) in vec3 position;
+
+layout(location = 0) out vec3 world_position;
+
+layout(set = 0, binding = 0) uniform Data {
+    mat4 world;
+    mat4 view;
+    mat4 proj;
+    float screen_width;
+    float screen_height;
+    float strength;
+    float distortion;
+    float time;
+} uniforms;
+
+void main() {
+    // This is the same calculation as the model shaders, so the depth matches the model exactly
+    mat4 worldview = uniforms.view * uniforms.world;
+    gl_Position = uniforms.proj * worldview * vec4(position, 1.0);
+    world_position = (uniforms.world * vec4(position, 1.0)).xyz;
+}
+"
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "#version 450
+layout(location = 0) in vec3 world_position;
+
+layout(set = 0, binding = 0) uniform Data {
+    mat4 world;
+    mat4 view;
+    mat4 proj;
+    float screen_width;
+    float screen_height;
+    float strength;
+    float distortion;
+    float time;
+} uniforms;
+layout(set = 0, binding = 1) uniform sampler2D reflection;
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+    // The reflection is rendered from the mirrored camera, so it lines up with the screen
+    vec2 uv = gl_FragCoord.xy / vec2(uniforms.screen_width, uniforms.screen_height);
+    vec2 ripple = vec2(
+        sin(world_position.x * 4.0 + uniforms.time * 2.0),
+        cos(world_position.z * 4.0 + uniforms.time * 1.7)
+    );
+    uv += ripple * uniforms.distortion;
+    f_color = vec4(texture(reflection, uv).rgb, uniforms.strength);
+}
+"
+    }
+}
+
+mod fs_deferred {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "#version 450
+layout(location = 0) in vec3 world_position;
+
+layout(set = 0, binding = 0) uniform Data {
+    mat4 world;
+    mat4 view;
+    mat4 proj;
+    float screen_width;
+    float screen_height;
+    float strength;
+    float distortion;
+    float time;
+} uniforms;
+layout(set = 0, binding = 1) uniform sampler2D reflection;
+// The depth buffer of the G-buffer pass, see render/deferred.rs
+layout(input_attachment_index = 4, set = 0, binding = 2) uniform subpassInput g_depth;
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+    // The lighting pass has no depth attachment, so the depth test is done here. The model itself
+    // is in the depth buffer, so a small margin is allowed.
+    if (gl_FragCoord.z > subpassLoad(g_depth).x + 0.00005) {
+        discard;
+    }
+    vec2 uv = gl_FragCoord.xy / vec2(uniforms.screen_width, uniforms.screen_height);
+    vec2 ripple = vec2(
+        sin(world_position.x * 4.0 + uniforms.time * 2.0),
+        cos(world_position.z * 4.0 + uniforms.time * 1.7)
+    );
+    uv += ripple * uniforms.distortion;
+    f_color = vec4(texture(reflection, uv).rgb, uniforms.strength);
+}
+"
+    }
+}
+
+#[test]
+fn test_reflection_matrix() {
+    use cgmath::Vector4;
+
+    let reflection = PlanarReflection::default();
+    let model = Matrix4::from_translation(Vector3::new(0.0, 2.0, 0.0));
+    let plane = reflection.world_plane(model, Vector3::new(0.0, 10.0, 0.0));
+    assert_eq!(Vector3::unit_y(), plane.normal);
+    assert_eq!(
+        Vector4::new(3.0, 1.0, -4.0, 1.0),
+        reflection_matrix(&plane) * Vector4::new(3.0, 3.0, -4.0, 1.0)
+    );
+
+    // Looking at the mirror from below reflects the world below it
+    let plane = reflection.world_plane(model, Vector3::new(0.0, -10.0, 0.0));
+    assert_eq!(-Vector3::unit_y(), plane.normal);
+    assert_eq!(
+        Vector4::new(0.0, 5.0, 0.0, 1.0),
+        reflection_matrix(&plane) * Vector4::new(0.0, -1.0, 0.0, 1.0)
+    );
+}
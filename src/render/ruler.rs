@@ -0,0 +1,130 @@
+use crate::{Font, GameState, GuiElement};
+use cgmath::{InnerSpace, Vector3};
+use winit::event::{ElementState, MouseButton, WindowEvent};
+
+/// The color of the lines of the ruler
+const RULER_COLOR: [u8; 4] = [255, 255, 0, 255];
+/// The size of the markers at the measured points, in world units
+const MARKER_SIZE: f32 = 0.1;
+/// The size of the label with the measurement, in GUI pixels
+const LABEL_SIZE: (u32, u32) = (340, 28);
+/// The font size of the label with the measurement
+const LABEL_FONT_SIZE: u16 = 16;
+
+/// A debug tool that measures the distance between two points in the world. See [GameState::show_ruler](../struct.GameState.html#method.show_ruler).
+pub(crate) struct Ruler {
+    font: Font,
+    cursor: (f64, f64),
+    points: Vec<Vector3<f32>>,
+    label: Option<GuiElement>,
+}
+
+impl Ruler {
+    pub fn new(font: Font) -> Self {
+        Self {
+            font,
+            cursor: (0.0, 0.0),
+            points: Vec::with_capacity(2),
+            label: None,
+        }
+    }
+
+    /// Handle a window event. Returns `true` if the event was used by the ruler.
+    pub fn handle_event(&mut self, game_state: &GameState, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor = (position.x, position.y);
+                false
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } => {
+                if let Some(point) = game_state.pick(self.cursor) {
+                    // A third click starts a new measurement
+                    if self.points.len() == 2 {
+                        self.points.clear();
+                    }
+                    self.points.push(point);
+                    self.label = None;
+                }
+                true
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Right,
+                ..
+            } => {
+                self.points.clear();
+                self.label = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Draw the measurement, and move the label to the middle of the measured line.
+    pub fn refresh(&mut self, game_state: &mut GameState) {
+        for &point in &self.points {
+            let offset = Vector3::new(MARKER_SIZE, MARKER_SIZE, MARKER_SIZE);
+            game_state
+                .debug_draw
+                .draw_box((point - offset, point + offset), RULER_COLOR);
+        }
+        let (from, to) = match self.points[..] {
+            [from, to] => (from, to),
+            _ => return,
+        };
+        game_state.debug_draw.draw_line(from, to, RULER_COLOR);
+
+        // The label is hidden when the middle of the line is behind the camera
+        let dimensions = match game_state.world_to_gui((from + to) / 2.0) {
+            Some((x, y)) => game_state.gui.rect(
+                x - LABEL_SIZE.0 as f32 / 2.0,
+                y,
+                LABEL_SIZE.0 as f32,
+                LABEL_SIZE.1 as f32,
+            ),
+            None => (0, 0, 0, 0),
+        };
+        match &self.label {
+            Some(label) => label.modify(|data| data.dimensions = dimensions),
+            // The label is created when it is visible, so its text can be rasterized
+            None if dimensions.2 == 0 => {}
+            None => {
+                let label = game_state
+                    .new_gui_element(dimensions)
+                    .canvas()
+                    .with_background_color([0, 0, 0, 160])
+                    .with_text(
+                        self.font.clone(),
+                        LABEL_FONT_SIZE,
+                        measurement_text(from, to),
+                        crate::color::WHITE,
+                    )
+                    .build();
+                match label {
+                    Ok(label) => self.label = Some(label),
+                    Err(e) => {
+                        eprintln!("Could not show the ruler label: {:?}", e);
+                        // Drop the measurement, so the error is not repeated every frame
+                        self.points.clear();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The distance and the difference between two points, in world units.
+fn measurement_text(from: Vector3<f32>, to: Vector3<f32>) -> String {
+    let delta = to - from;
+    format!(
+        "{:.2} (dx {:.2}, dy {:.2}, dz {:.2})",
+        delta.magnitude(),
+        delta.x,
+        delta.y,
+        delta.z
+    )
+}
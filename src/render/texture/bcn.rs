@@ -0,0 +1,152 @@
+//! Decoders for the block compressed formats, for GPUs that can not sample them directly. Every block contains 4x4 pixels.
+
+use super::CompressedFormat;
+
+/// Decode the blocks of an image into RGBA pixels. Returns `None` if the format can not be decoded on the CPU.
+pub fn decode(format: CompressedFormat, width: u32, height: u32, data: &[u8]) -> Option<Vec<u8>> {
+    let decode_block: fn(&[u8], &mut [[u8; 4]; 16]) = match format {
+        CompressedFormat::Bc1 { .. } => |block, pixels| decode_color(block, pixels, true),
+        CompressedFormat::Bc2 { .. } => decode_bc2,
+        CompressedFormat::Bc3 { .. } => decode_bc3,
+        CompressedFormat::Bc4 => decode_bc4,
+        CompressedFormat::Bc5 => decode_bc5,
+        CompressedFormat::Bc6h | CompressedFormat::Bc7 { .. } => return None,
+    };
+    let block_size = format.block_size();
+    let blocks_wide = (width as usize + 3) / 4;
+    let blocks_high = (height as usize + 3) / 4;
+    if data.len() < blocks_wide * blocks_high * block_size {
+        return None;
+    }
+
+    let mut rgba = vec![0; width as usize * height as usize * 4];
+    let mut pixels = [[0; 4]; 16];
+    for (index, block) in data
+        .chunks_exact(block_size)
+        .take(blocks_wide * blocks_high)
+        .enumerate()
+    {
+        decode_block(block, &mut pixels);
+        let block_x = (index % blocks_wide) * 4;
+        let block_y = (index / blocks_wide) * 4;
+        for (pixel_index, pixel) in pixels.iter().enumerate() {
+            let x = block_x + pixel_index % 4;
+            let y = block_y + pixel_index / 4;
+            // Blocks at the right and bottom edge can be partially outside of the image
+            if x < width as usize && y < height as usize {
+                let offset = (y * width as usize + x) * 4;
+                rgba[offset..offset + 4].copy_from_slice(pixel);
+            }
+        }
+    }
+    Some(rgba)
+}
+
+fn rgb565(value: u16) -> [u32; 3] {
+    let r = (value >> 11) as u32 & 0x1F;
+    let g = (value >> 5) as u32 & 0x3F;
+    let b = value as u32 & 0x1F;
+    [
+        (r << 3) | (r >> 2),
+        (g << 2) | (g >> 4),
+        (b << 3) | (b >> 2),
+    ]
+}
+
+/// Decode the color part of a BC1, BC2 or BC3 block. Only BC1 blocks can have a transparent color.
+fn decode_color(block: &[u8], pixels: &mut [[u8; 4]; 16], bc1: bool) {
+    let color0 = u16::from_le_bytes([block[0], block[1]]);
+    let color1 = u16::from_le_bytes([block[2], block[3]]);
+    let (c0, c1) = (rgb565(color0), rgb565(color1));
+    let mix = |weight0: u32, weight1: u32| {
+        let mut color = [0, 0, 0, 255];
+        for (channel, (a, b)) in color.iter_mut().zip(c0.iter().zip(&c1)) {
+            *channel = ((a * weight0 + b * weight1) / (weight0 + weight1)) as u8;
+        }
+        color
+    };
+    let palette = if color0 > color1 || !bc1 {
+        [mix(1, 0), mix(0, 1), mix(2, 1), mix(1, 2)]
+    } else {
+        // The fourth color of a BC1 block in three-color mode is transparent black
+        [mix(1, 0), mix(0, 1), mix(1, 1), [0, 0, 0, 0]]
+    };
+
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    for (index, pixel) in pixels.iter_mut().enumerate() {
+        *pixel = palette[(indices >> (index * 2)) as usize & 0b11];
+    }
+}
+
+/// Decode an interpolated alpha block of BC3, BC4 and BC5 into a single channel.
+fn decode_alpha(block: &[u8], pixels: &mut [[u8; 4]; 16], channel: usize) {
+    let (a0, a1) = (block[0] as u32, block[1] as u32);
+    let mut palette = [a0 as u8, a1 as u8, 0, 0, 0, 0, 0, 255];
+    if a0 > a1 {
+        for (i, value) in (1..).zip(palette[2..].iter_mut()) {
+            *value = ((a0 * (7 - i) + a1 * i) / 7) as u8;
+        }
+    } else {
+        // The last two values are always 0 and 255 in this mode
+        for (i, value) in (1..).zip(palette[2..6].iter_mut()) {
+            *value = ((a0 * (5 - i) + a1 * i) / 5) as u8;
+        }
+    }
+
+    let mut bits = [0u8; 8];
+    bits[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(bits);
+    for (index, pixel) in pixels.iter_mut().enumerate() {
+        pixel[channel] = palette[(indices >> (index * 3)) as usize & 0b111];
+    }
+}
+
+fn decode_bc2(block: &[u8], pixels: &mut [[u8; 4]; 16]) {
+    decode_color(&block[8..], pixels, false);
+    for (index, pixel) in pixels.iter_mut().enumerate() {
+        let alpha = (block[index / 2] >> ((index % 2) * 4)) & 0x0F;
+        pixel[3] = alpha * 17;
+    }
+}
+
+fn decode_bc3(block: &[u8], pixels: &mut [[u8; 4]; 16]) {
+    decode_color(&block[8..], pixels, false);
+    decode_alpha(&block[..8], pixels, 3);
+}
+
+/// BC4 only contains a red channel, the other channels are sampled as 0 and the alpha as 1
+fn decode_bc4(block: &[u8], pixels: &mut [[u8; 4]; 16]) {
+    *pixels = [[0, 0, 0, 255]; 16];
+    decode_alpha(block, pixels, 0);
+}
+
+/// BC5 contains a red and a green channel, the other channels are sampled as 0 and the alpha as 1
+fn decode_bc5(block: &[u8], pixels: &mut [[u8; 4]; 16]) {
+    *pixels = [[0, 0, 0, 255]; 16];
+    decode_alpha(&block[..8], pixels, 0);
+    decode_alpha(&block[8..], pixels, 1);
+}
+
+#[test]
+fn test_decode_bc1_and_bc3() {
+    // Pure red and pure blue, with the first two rows red and the last two rows blue
+    let bc1 = [0x00, 0xF8, 0x1F, 0x00, 0x00, 0x00, 0x55, 0x55];
+    let rgba = decode(CompressedFormat::Bc1 { srgb: false }, 4, 4, &bc1).unwrap();
+    assert_eq!(&[255, 0, 0, 255], &rgba[0..4]);
+    assert_eq!(&[0, 0, 255, 255], &rgba[15 * 4..16 * 4]);
+
+    // A partial block is cropped to the size of the image
+    let rgba = decode(CompressedFormat::Bc1 { srgb: false }, 3, 3, &bc1).unwrap();
+    assert_eq!(3 * 3 * 4, rgba.len());
+
+    // An alpha of 255 in the first pixel and 0 in all other pixels, on top of the BC1 colors
+    let indices = (1..16).fold(0u64, |indices, pixel| indices | 1 << (pixel * 3));
+    let mut bc3 = vec![255, 0];
+    bc3.extend_from_slice(&indices.to_le_bytes()[..6]);
+    bc3.extend_from_slice(&bc1);
+    let rgba = decode(CompressedFormat::Bc3 { srgb: false }, 4, 4, &bc3).unwrap();
+    assert_eq!(&[255, 0, 0, 255], &rgba[0..4]);
+    assert_eq!(0, rgba[4 + 3]);
+
+    assert!(decode(CompressedFormat::Bc7 { srgb: false }, 4, 4, &[0; 16]).is_none());
+}
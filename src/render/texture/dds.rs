@@ -0,0 +1,100 @@
+//! Parser of the DirectDraw Surface (`.dds`) format. Only block compressed images are supported.
+
+use super::{CompressedFormat, CompressedImage};
+
+const MAGIC: &[u8; 4] = b"DDS ";
+/// The size of the magic number and the header
+const HEADER_SIZE: usize = 128;
+/// The size of the extra header of files with a DXGI format
+const DX10_HEADER_SIZE: usize = 20;
+
+pub fn is_dds(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub fn parse(bytes: &[u8]) -> Result<CompressedImage<'_>, String> {
+    if !is_dds(bytes) || bytes.len() < HEADER_SIZE {
+        return Err(String::from("Not a DDS file"));
+    }
+    let read_u32 = |offset: usize| {
+        let mut value = [0; 4];
+        value.copy_from_slice(&bytes[offset..offset + 4]);
+        u32::from_le_bytes(value)
+    };
+    let height = read_u32(12);
+    let width = read_u32(16);
+    let four_cc = &bytes[84..88];
+
+    let (format, data_offset) = if four_cc == b"DX10" {
+        if bytes.len() < HEADER_SIZE + DX10_HEADER_SIZE {
+            return Err(String::from("The DX10 header is missing"));
+        }
+        let dxgi_format = read_u32(HEADER_SIZE);
+        let format = from_dxgi_format(dxgi_format)
+            .ok_or_else(|| format!("Unsupported DXGI format {}", dxgi_format))?;
+        (format, HEADER_SIZE + DX10_HEADER_SIZE)
+    } else {
+        let format = from_four_cc(four_cc).ok_or_else(|| {
+            format!(
+                "Unsupported format {:?}, only block compressed formats are supported",
+                String::from_utf8_lossy(four_cc)
+            )
+        })?;
+        (format, HEADER_SIZE)
+    };
+
+    CompressedImage::new(format, width, height, &bytes[data_offset..])
+}
+
+fn from_four_cc(four_cc: &[u8]) -> Option<CompressedFormat> {
+    Some(match four_cc {
+        b"DXT1" => CompressedFormat::Bc1 { srgb: false },
+        b"DXT2" | b"DXT3" => CompressedFormat::Bc2 { srgb: false },
+        b"DXT4" | b"DXT5" => CompressedFormat::Bc3 { srgb: false },
+        b"ATI1" | b"BC4U" => CompressedFormat::Bc4,
+        b"ATI2" | b"BC5U" => CompressedFormat::Bc5,
+        _ => return None,
+    })
+}
+
+fn from_dxgi_format(format: u32) -> Option<CompressedFormat> {
+    Some(match format {
+        71 => CompressedFormat::Bc1 { srgb: false },
+        72 => CompressedFormat::Bc1 { srgb: true },
+        74 => CompressedFormat::Bc2 { srgb: false },
+        75 => CompressedFormat::Bc2 { srgb: true },
+        77 => CompressedFormat::Bc3 { srgb: false },
+        78 => CompressedFormat::Bc3 { srgb: true },
+        80 => CompressedFormat::Bc4,
+        83 => CompressedFormat::Bc5,
+        95 => CompressedFormat::Bc6h,
+        98 => CompressedFormat::Bc7 { srgb: false },
+        99 => CompressedFormat::Bc7 { srgb: true },
+        _ => return None,
+    })
+}
+
+#[test]
+fn test_parse_dds() {
+    let mut bytes = vec![0; HEADER_SIZE + DX10_HEADER_SIZE];
+    bytes[..4].copy_from_slice(MAGIC);
+    bytes[12..16].copy_from_slice(&8u32.to_le_bytes());
+    bytes[16..20].copy_from_slice(&4u32.to_le_bytes());
+    bytes[84..88].copy_from_slice(b"DXT5");
+    assert!(parse(&bytes).is_err(), "The image data is missing");
+
+    bytes.truncate(HEADER_SIZE);
+    bytes.extend(std::iter::repeat(0).take(2 * 16));
+    let image = parse(&bytes).unwrap();
+    assert_eq!(CompressedFormat::Bc3 { srgb: false }, image.format);
+    assert_eq!((4, 8), (image.width, image.height));
+    assert_eq!(32, image.data.len());
+
+    bytes[84..88].copy_from_slice(b"DX10");
+    bytes.splice(
+        HEADER_SIZE..HEADER_SIZE,
+        vec![99, 0, 0, 0].into_iter().chain(vec![0; 16]),
+    );
+    let image = parse(&bytes).unwrap();
+    assert_eq!(CompressedFormat::Bc7 { srgb: true }, image.format);
+}
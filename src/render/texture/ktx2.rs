@@ -0,0 +1,92 @@
+//! Parser of the KTX 2.0 (`.ktx2`) format. Only block compressed images without supercompression are supported.
+
+use super::{CompressedFormat, CompressedImage};
+
+const IDENTIFIER: &[u8; 12] = &[
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+/// The size of the identifier, the header and the index, up to the first level
+const HEADER_SIZE: usize = 80;
+/// The size of a single entry in the level index
+const LEVEL_SIZE: usize = 24;
+
+pub fn is_ktx2(bytes: &[u8]) -> bool {
+    bytes.starts_with(IDENTIFIER)
+}
+
+pub fn parse(bytes: &[u8]) -> Result<CompressedImage<'_>, String> {
+    if !is_ktx2(bytes) || bytes.len() < HEADER_SIZE + LEVEL_SIZE {
+        return Err(String::from("Not a KTX2 file"));
+    }
+    let read_u32 = |offset: usize| {
+        let mut value = [0; 4];
+        value.copy_from_slice(&bytes[offset..offset + 4]);
+        u32::from_le_bytes(value)
+    };
+    let read_u64 = |offset: usize| {
+        let mut value = [0; 8];
+        value.copy_from_slice(&bytes[offset..offset + 8]);
+        u64::from_le_bytes(value)
+    };
+
+    let vk_format = read_u32(12);
+    let width = read_u32(20);
+    let height = read_u32(24);
+    let supercompression_scheme = read_u32(44);
+    if supercompression_scheme != 0 {
+        // Basis Universal textures are supercompressed, and need to be transcoded first
+        return Err(format!(
+            "Supercompression scheme {} is not supported",
+            supercompression_scheme
+        ));
+    }
+    let format = from_vk_format(vk_format)
+        .ok_or_else(|| format!("Unsupported Vulkan format {}", vk_format))?;
+
+    // The first level is the base level of the image
+    let offset = read_u64(HEADER_SIZE) as usize;
+    let length = read_u64(HEADER_SIZE + 8) as usize;
+    let data = offset
+        .checked_add(length)
+        .and_then(|end| bytes.get(offset..end))
+        .ok_or_else(|| String::from("The level index points outside of the file"))?;
+    CompressedImage::new(format, width, height, data)
+}
+
+fn from_vk_format(format: u32) -> Option<CompressedFormat> {
+    Some(match format {
+        131 | 133 => CompressedFormat::Bc1 { srgb: false },
+        132 | 134 => CompressedFormat::Bc1 { srgb: true },
+        135 => CompressedFormat::Bc2 { srgb: false },
+        136 => CompressedFormat::Bc2 { srgb: true },
+        137 => CompressedFormat::Bc3 { srgb: false },
+        138 => CompressedFormat::Bc3 { srgb: true },
+        139 => CompressedFormat::Bc4,
+        141 => CompressedFormat::Bc5,
+        143 => CompressedFormat::Bc6h,
+        145 => CompressedFormat::Bc7 { srgb: false },
+        146 => CompressedFormat::Bc7 { srgb: true },
+        _ => return None,
+    })
+}
+
+#[test]
+fn test_parse_ktx2() {
+    let mut bytes = vec![0; HEADER_SIZE + LEVEL_SIZE];
+    bytes[..12].copy_from_slice(IDENTIFIER);
+    bytes[12..16].copy_from_slice(&146u32.to_le_bytes());
+    bytes[20..24].copy_from_slice(&6u32.to_le_bytes());
+    bytes[24..28].copy_from_slice(&4u32.to_le_bytes());
+    let level_offset = bytes.len() as u64;
+    bytes[HEADER_SIZE..HEADER_SIZE + 8].copy_from_slice(&level_offset.to_le_bytes());
+    bytes[HEADER_SIZE + 8..HEADER_SIZE + 16].copy_from_slice(&32u64.to_le_bytes());
+    assert!(parse(&bytes).is_err(), "The level is missing");
+
+    bytes.extend(std::iter::repeat(0).take(32));
+    let image = parse(&bytes).unwrap();
+    assert_eq!(CompressedFormat::Bc7 { srgb: true }, image.format);
+    assert_eq!((6, 4), (image.width, image.height));
+
+    bytes[44..48].copy_from_slice(&1u32.to_le_bytes());
+    assert!(parse(&bytes).is_err(), "BasisLZ is not supported");
+}
@@ -0,0 +1,263 @@
+//! Loading of textures. Most image formats are decoded into RGBA pixels with the `image` crate, but DDS and KTX2 files with block compressed (BCn) images are uploaded to the GPU as-is. This makes them a lot faster to load, and they use 4 to 8 times less memory.
+//!
+//! When the GPU does not support block compressed textures, the images are decompressed when they are loaded. This is not possible for BC6H and BC7 images.
+
+mod bcn;
+mod dds;
+mod ktx2;
+
+use crate::state::UploadError;
+use image::{
+    error::{DecodingError, ImageError, ImageFormatHint},
+    ImageFormat,
+};
+use std::{path::Path, sync::Arc};
+use vulkano::{
+    device::Queue,
+    format::Format,
+    image::{Dimensions, ImageAccess, ImmutableImage},
+    sync::GpuFuture,
+};
+
+/// A texture that is sampled by the model and GUI pipelines.
+pub(crate) type Texture = Arc<ImmutableImage<Format>>;
+
+/// A block compressed format. Every block contains 4x4 pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum CompressedFormat {
+    Bc1 { srgb: bool },
+    Bc2 { srgb: bool },
+    Bc3 { srgb: bool },
+    Bc4,
+    Bc5,
+    Bc6h,
+    Bc7 { srgb: bool },
+}
+
+impl CompressedFormat {
+    /// The size of a single block, in bytes.
+    pub fn block_size(self) -> usize {
+        match self {
+            CompressedFormat::Bc1 { .. } | CompressedFormat::Bc4 => 8,
+            _ => 16,
+        }
+    }
+
+    /// The size of an image of this format, in bytes.
+    pub fn image_size(self, width: u32, height: u32) -> usize {
+        let blocks_wide = (width as usize + 3) / 4;
+        let blocks_high = (height as usize + 3) / 4;
+        blocks_wide * blocks_high * self.block_size()
+    }
+
+    fn is_srgb(self) -> bool {
+        match self {
+            CompressedFormat::Bc1 { srgb }
+            | CompressedFormat::Bc2 { srgb }
+            | CompressedFormat::Bc3 { srgb }
+            | CompressedFormat::Bc7 { srgb } => srgb,
+            CompressedFormat::Bc4 | CompressedFormat::Bc5 | CompressedFormat::Bc6h => false,
+        }
+    }
+
+    fn to_vulkan_format(self) -> Format {
+        match self {
+            CompressedFormat::Bc1 { srgb: false } => Format::BC1_RGBAUnormBlock,
+            CompressedFormat::Bc1 { srgb: true } => Format::BC1_RGBASrgbBlock,
+            CompressedFormat::Bc2 { srgb: false } => Format::BC2UnormBlock,
+            CompressedFormat::Bc2 { srgb: true } => Format::BC2SrgbBlock,
+            CompressedFormat::Bc3 { srgb: false } => Format::BC3UnormBlock,
+            CompressedFormat::Bc3 { srgb: true } => Format::BC3SrgbBlock,
+            CompressedFormat::Bc4 => Format::BC4UnormBlock,
+            CompressedFormat::Bc5 => Format::BC5UnormBlock,
+            CompressedFormat::Bc6h => Format::BC6HUfloatBlock,
+            CompressedFormat::Bc7 { srgb: false } => Format::BC7UnormBlock,
+            CompressedFormat::Bc7 { srgb: true } => Format::BC7SrgbBlock,
+        }
+    }
+
+    fn from_vulkan_format(format: Format) -> Option<Self> {
+        Some(match format {
+            Format::BC1_RGBAUnormBlock => CompressedFormat::Bc1 { srgb: false },
+            Format::BC1_RGBASrgbBlock => CompressedFormat::Bc1 { srgb: true },
+            Format::BC2UnormBlock => CompressedFormat::Bc2 { srgb: false },
+            Format::BC2SrgbBlock => CompressedFormat::Bc2 { srgb: true },
+            Format::BC3UnormBlock => CompressedFormat::Bc3 { srgb: false },
+            Format::BC3SrgbBlock => CompressedFormat::Bc3 { srgb: true },
+            Format::BC4UnormBlock => CompressedFormat::Bc4,
+            Format::BC5UnormBlock => CompressedFormat::Bc5,
+            Format::BC6HUfloatBlock => CompressedFormat::Bc6h,
+            Format::BC7UnormBlock => CompressedFormat::Bc7 { srgb: false },
+            Format::BC7SrgbBlock => CompressedFormat::Bc7 { srgb: true },
+            _ => return None,
+        })
+    }
+}
+
+/// The base level of a block compressed image in a DDS or KTX2 file. Smaller mip levels are ignored.
+pub(crate) struct CompressedImage<'a> {
+    pub format: CompressedFormat,
+    pub width: u32,
+    pub height: u32,
+    pub data: &'a [u8],
+}
+
+impl<'a> CompressedImage<'a> {
+    fn new(
+        format: CompressedFormat,
+        width: u32,
+        height: u32,
+        data: &'a [u8],
+    ) -> Result<Self, String> {
+        if width == 0 || height == 0 {
+            return Err(String::from("The image is empty"));
+        }
+        let size = format.image_size(width, height);
+        if data.len() < size {
+            return Err(format!(
+                "Expected {} bytes of image data, found {}",
+                size,
+                data.len()
+            ));
+        }
+        Ok(Self {
+            format,
+            width,
+            height,
+            data: &data[..size],
+        })
+    }
+}
+
+/// Load a texture from a file. See the module documentation for the supported formats.
+pub(crate) fn load(
+    queue: Arc<Queue>,
+    path: &Path,
+) -> Result<(Texture, Box<dyn GpuFuture>), ImageError> {
+    load_with_format(queue, path, Format::R8G8B8A8Srgb)
+}
+
+/// Load a texture that contains data instead of colors, e.g. a roughness map. Images that are decoded by the `image` crate are not converted from sRGB. Block compressed images keep the format of the file.
+pub(crate) fn load_linear(
+    queue: Arc<Queue>,
+    path: &Path,
+) -> Result<(Texture, Box<dyn GpuFuture>), ImageError> {
+    load_with_format(queue, path, Format::R8G8B8A8Unorm)
+}
+
+fn load_with_format(
+    queue: Arc<Queue>,
+    path: &Path,
+    rgba_format: Format,
+) -> Result<(Texture, Box<dyn GpuFuture>), ImageError> {
+    let bytes = std::fs::read(path).map_err(ImageError::IoError)?;
+    let (hint, parsed) = if dds::is_dds(&bytes) {
+        ("DDS", dds::parse(&bytes))
+    } else if ktx2::is_ktx2(&bytes) {
+        ("KTX2", ktx2::parse(&bytes))
+    } else {
+        // Formats like TGA can not be recognized by their content, so the extension is used first
+        let format = ImageFormat::from_path(path).or_else(|_| image::guess_format(&bytes))?;
+        let image = image::load_from_memory_with_format(&bytes, format)?.into_rgba();
+        let (width, height) = image.dimensions();
+        return from_rgba(queue, width, height, image.into_raw(), rgba_format)
+            .map_err(upload_error);
+    };
+    let decoding_error =
+        |e: String| ImageError::Decoding(DecodingError::new(ImageFormatHint::Name(hint.into()), e));
+    let image = parsed.map_err(decoding_error)?;
+    let format = image.format;
+    from_compressed(queue, image)
+        .map_err(upload_error)?
+        .ok_or_else(|| {
+            decoding_error(format!(
+                "{:?} is not supported by the GPU, and can not be decompressed",
+                format
+            ))
+        })
+}
+
+/// Textures are loaded with the errors of the `image` crate, so an upload that failed is reported as an I/O error.
+fn upload_error(error: UploadError) -> ImageError {
+    ImageError::IoError(std::io::Error::new(std::io::ErrorKind::Other, error))
+}
+
+/// Upload a block compressed image, or decompress it if the GPU does not support block compressed formats. Returns `None` if the format can not be decompressed.
+fn from_compressed(
+    queue: Arc<Queue>,
+    image: CompressedImage,
+) -> Result<Option<(Texture, Box<dyn GpuFuture>)>, UploadError> {
+    let CompressedImage {
+        format,
+        width,
+        height,
+        data,
+    } = image;
+    if queue.device().enabled_features().texture_compression_bc {
+        let (texture, future) = ImmutableImage::from_iter(
+            data.iter().copied(),
+            Dimensions::Dim2d { width, height },
+            format.to_vulkan_format(),
+            queue.clone(),
+        )
+        .map_err(UploadError::CouldNotCreateImage)?;
+        return Ok(Some((texture, submit_upload(&queue, future)?)));
+    }
+
+    let pixels = match bcn::decode(format, width, height, data) {
+        Some(pixels) => pixels,
+        None => return Ok(None),
+    };
+    let rgba_format = if format.is_srgb() {
+        Format::R8G8B8A8Srgb
+    } else {
+        Format::R8G8B8A8Unorm
+    };
+    from_rgba(queue, width, height, pixels, rgba_format).map(Some)
+}
+
+/// Upload RGBA pixels to a texture. `format` has to be one of the `R8G8B8A8` formats.
+pub(crate) fn from_rgba(
+    queue: Arc<Queue>,
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+    format: Format,
+) -> Result<(Texture, Box<dyn GpuFuture>), UploadError> {
+    let (texture, future) = ImmutableImage::from_iter(
+        pixels.into_iter(),
+        Dimensions::Dim2d { width, height },
+        format,
+        queue.clone(),
+    )
+    .map_err(UploadError::CouldNotCreateImage)?;
+    Ok((texture, submit_upload(&queue, future)?))
+}
+
+/// Submit the upload of a texture. Uploads on a dedicated transfer queue are submitted right away, and signal a semaphore that the graphics queue waits on before it uses the texture. This way the upload runs while the GPU is rendering, instead of in front of the next frame.
+///
+/// Immutable images are shared between every queue family of the device, so they can be uploaded on one queue and sampled on another.
+///
+/// The command buffer of the upload is valid, so this only fails when the device is lost.
+pub(crate) fn submit_upload(
+    queue: &Queue,
+    future: impl GpuFuture + 'static,
+) -> Result<Box<dyn GpuFuture>, UploadError> {
+    if queue.family().supports_graphics() {
+        return Ok(future.boxed());
+    }
+    let future = future
+        .then_signal_semaphore_and_flush()
+        .map_err(UploadError::CouldNotSubmit)?;
+    Ok(future.boxed())
+}
+
+/// The amount of GPU memory that a texture uses, in bytes.
+pub(crate) fn size_in_bytes(texture: &Texture) -> usize {
+    let [width, height] = texture.dimensions().width_height();
+    match CompressedFormat::from_vulkan_format(ImageAccess::format(&**texture)) {
+        Some(format) => format.image_size(width, height),
+        // Every other texture is one of the R8G8B8A8 formats, without mipmaps
+        None => width as usize * height as usize * 4,
+    }
+}
@@ -0,0 +1,289 @@
+use super::post_processing::PostProcessingState;
+use std::sync::Arc;
+use vulkano::{
+    buffer::{BufferUsage, CpuAccessibleBuffer, CpuBufferPool},
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    descriptor::descriptor_set::{PersistentDescriptorSet, StdDescriptorPool},
+    device::Device,
+    format::R32Sfloat,
+    framebuffer::{RenderPassAbstract, Subpass},
+    image::{attachment::AttachmentImage, StorageImage},
+    pipeline::{GraphicsPipeline, GraphicsPipelineAbstract},
+    sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
+};
+
+/// The pipeline that reads the HDR image of the scene, and writes the tonemapped result to the swapchain image. This also applies the stylization effects of [PostProcessingState].
+///
+/// [PostProcessingState]: ../post_processing/struct.PostProcessingState.html
+pub struct Pipeline {
+    pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    vertex_buffer: Arc<CpuAccessibleBuffer<[Vertex]>>,
+    uniform_buffer: CpuBufferPool<fs::ty::Data>,
+    sampler: Arc<Sampler>,
+    gamma_correct: bool,
+    /// The amount of frames that have been rendered, which is used to animate the film grain
+    frame: u32,
+}
+
+impl Pipeline {
+    /// Create the pipeline. If `gamma_correct` is true, the shader converts the output to sRGB itself. This is needed when the swapchain does not have an sRGB format.
+    pub fn create(
+        device: Arc<Device>,
+        subpass: Subpass<Arc<dyn RenderPassAbstract + Send + Sync>>,
+        gamma_correct: bool,
+    ) -> Self {
+        // These should never fail, as the shaders are hard-coded and the device is assumed to be
+        // valid.
+        let vs = vs::Shader::load(device.clone()).expect("failed to create shader module");
+        let fs = fs::Shader::load(device.clone()).expect("failed to create shader module");
+
+        let pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input_single_buffer::<Vertex>()
+                .vertex_shader(vs.main_entry_point(), ())
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(fs.main_entry_point(), ())
+                .render_pass(subpass)
+                .build(device.clone())
+                // This should never fail because all arguments are hard-coded
+                .unwrap(),
+        );
+
+        // A single triangle that covers the entire screen
+        let vertex_buffer = CpuAccessibleBuffer::from_iter(
+            device.clone(),
+            BufferUsage::all(),
+            false,
+            [
+                Vertex {
+                    position: [-1.0, -1.0],
+                },
+                Vertex {
+                    position: [3.0, -1.0],
+                },
+                Vertex {
+                    position: [-1.0, 3.0],
+                },
+            ]
+            .iter()
+            .cloned(),
+        )
+        // This should never fail because the arguments are hard-coded
+        .unwrap();
+
+        let uniform_buffer = CpuBufferPool::<fs::ty::Data>::uniform_buffer(device.clone());
+
+        // The chromatic aberration samples outside of the screen at the edges, which should repeat
+        // the edge instead of wrapping around
+        let sampler = Sampler::new(
+            device,
+            Filter::Linear,
+            Filter::Linear,
+            MipmapMode::Nearest,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            0.0,
+            1.0,
+            0.0,
+            0.0,
+        )
+        // This should never fail because the arguments are hard-coded
+        .unwrap();
+
+        Self {
+            pipeline,
+            vertex_buffer,
+            uniform_buffer,
+            sampler,
+            gamma_correct,
+            frame: 0,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn render(
+        &mut self,
+        hdr_buffer: Arc<AttachmentImage>,
+        auto_exposure: Arc<StorageImage<R32Sfloat>>,
+        settings: &PostProcessingState,
+        output_size: [u32; 2],
+        command_buffer_builder: &mut AutoCommandBufferBuilder,
+        dynamic_state: &DynamicState,
+        descriptor_pool: &mut Arc<StdDescriptorPool>,
+    ) {
+        let data = fs::ty::Data {
+            exposure: settings.exposure,
+            tonemapping: settings.tonemapping.to_shader_value(),
+            gamma_correct: self.gamma_correct as i32,
+            auto_exposure: settings.auto_exposure.is_some() as i32,
+            chromatic_aberration: settings.chromatic_aberration.max(0.0),
+            film_grain: settings.film_grain.max(0.0),
+            scanlines: settings.scanlines.max(0.0).min(1.0),
+            frame: self.frame,
+            output_width: output_size[0] as f32,
+            output_height: output_size[1] as f32,
+            brightness: settings.color_adjustments.brightness,
+            contrast: settings.color_adjustments.contrast.max(0.0),
+            saturation: settings.color_adjustments.saturation.max(0.0),
+            gamma: settings.color_adjustments.gamma.max(0.01),
+        };
+        self.frame = self.frame.wrapping_add(1);
+        // The uniform_buffer is assumed to be valid so this should never fail
+        let uniform_buffer_subbuffer = self.uniform_buffer.next(data).unwrap();
+
+        // The pipeline and the layout index are hard-coded so this is assumed to never fail
+        let layout = self.pipeline.descriptor_set_layout(0).unwrap();
+        let set = Arc::new(
+            PersistentDescriptorSet::start(layout.clone())
+                .add_sampled_image(hdr_buffer, self.sampler.clone())
+                // The HDR buffer is created as a sampled image so this should never fail
+                .unwrap()
+                .add_buffer(uniform_buffer_subbuffer)
+                // The uniform subbuffer is assumed to be valid so this should never fail
+                .unwrap()
+                .add_image(auto_exposure)
+                // The exposure is created as a storage image so this should never fail
+                .unwrap()
+                .build_with_pool(descriptor_pool)
+                // The pool is assumed to be valid so this should never fail
+                .unwrap(),
+        );
+
+        command_buffer_builder
+            .draw(
+                self.pipeline.clone(),
+                dynamic_state,
+                vec![self.vertex_buffer.clone()],
+                set,
+                (),
+            )
+            // the builder and arguments are assumed to be valid so this should never fail
+            .unwrap();
+    }
+}
+
+#[derive(Default, Debug, Clone)]
+struct Vertex {
+    position: [f32; 2],
+}
+
+vulkano::impl_vertex!(Vertex, position);
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "#version 450
+
+layout(location = 0) in vec2 position;
+
+void main() {
+    gl_Position = vec4(position, 0.0, 1.0);
+}
+"
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "#version 450
+
+layout(set = 0, binding = 0) uniform sampler2D hdr;
+layout(set = 0, binding = 1) uniform Data {
+    float exposure;
+    int tonemapping;
+    int gamma_correct;
+    int auto_exposure;
+    float chromatic_aberration;
+    float film_grain;
+    float scanlines;
+    uint frame;
+    // The size of the image that is rendered into, which is larger than the HDR buffer when the
+    // render scale is below 1
+    float output_width;
+    float output_height;
+    // The color adjustments, see `ColorAdjustments`
+    float brightness;
+    float contrast;
+    float saturation;
+    float gamma;
+} uniforms;
+layout(set = 0, binding = 2, r32f) uniform readonly image2D auto_exposure;
+
+layout(location = 0) out vec4 f_color;
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve
+vec3 aces(vec3 x) {
+    const float a = 2.51;
+    const float b = 0.03;
+    const float c = 2.43;
+    const float d = 0.59;
+    const float e = 0.14;
+    return (x * (a * x + b)) / (x * (c * x + d) + e);
+}
+
+// A pseudo-random number between 0 and 1 for every pixel and frame
+float noise(vec2 position, uint frame) {
+    vec3 seed = vec3(position, float(frame % 1024u));
+    return fract(sin(dot(seed, vec3(12.9898, 78.233, 37.719))) * 43758.5453);
+}
+
+// Apply the color adjustments to the sRGB version of a linear color
+vec3 adjust_colors(vec3 color) {
+    vec3 srgb = pow(clamp(color, 0.0, 1.0), vec3(1.0 / 2.2));
+    srgb = pow(srgb, vec3(1.0 / uniforms.gamma));
+    srgb += uniforms.brightness;
+    srgb = (srgb - 0.5) * uniforms.contrast + 0.5;
+    float grey = dot(srgb, vec3(0.2126, 0.7152, 0.0722));
+    srgb = mix(vec3(grey), srgb, uniforms.saturation);
+    return pow(clamp(srgb, 0.0, 1.0), vec3(2.2));
+}
+
+void main() {
+    vec2 uv = gl_FragCoord.xy / vec2(uniforms.output_width, uniforms.output_height);
+
+    // The red and blue channels are moved apart towards the edges of the screen
+    vec3 color;
+    if (uniforms.chromatic_aberration > 0.0) {
+        vec2 offset = (uv - 0.5) * 2.0 * uniforms.chromatic_aberration;
+        color.r = texture(hdr, uv + offset).r;
+        color.g = texture(hdr, uv).g;
+        color.b = texture(hdr, uv - offset).b;
+    } else {
+        color = texture(hdr, uv).rgb;
+    }
+    color *= uniforms.exposure;
+    if (uniforms.auto_exposure != 0) {
+        // The exposure is 0 in the first frame, before it has been measured
+        float adapted = imageLoad(auto_exposure, ivec2(0, 0)).r;
+        if (adapted > 0.0) {
+            color *= adapted;
+        }
+    }
+
+    if (uniforms.tonemapping == 1) {
+        color = color / (color + vec3(1.0));
+    } else if (uniforms.tonemapping == 2) {
+        color = aces(color);
+    }
+    color = adjust_colors(color);
+
+    if (uniforms.film_grain > 0.0) {
+        color += (noise(gl_FragCoord.xy, uniforms.frame) - 0.5) * uniforms.film_grain;
+    }
+    // Darken every other line of two pixels
+    if (uniforms.scanlines > 0.0) {
+        float line = 0.5 + 0.5 * cos(gl_FragCoord.y * 3.14159265 / 2.0);
+        color *= 1.0 - uniforms.scanlines * line;
+    }
+    color = clamp(color, 0.0, 1.0);
+
+    if (uniforms.gamma_correct != 0) {
+        color = pow(color, vec3(1.0 / 2.2));
+    }
+    f_color = vec4(color, 1.0);
+}
+"
+    }
+}
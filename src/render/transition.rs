@@ -0,0 +1,162 @@
+use crate::GameState;
+use std::time::Duration;
+
+/// A transition effect, e.g. to switch between levels. See [GameState::transition] for more information.
+///
+/// [GameState::transition]: ../struct.GameState.html#method.transition
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transition {
+    /// Fade the screen to black, call the callback, and fade back in. Each fade takes the given duration.
+    FadeToBlack(Duration),
+    /// Fade the screen to the given color, call the callback, and fade back in. Each fade takes the given duration.
+    FadeToColor([u8; 4], Duration),
+    /// Take a snapshot of the screen, call the callback, and fade from the snapshot to the new scene in the given duration.
+    Crossfade(Duration),
+    /// Cover the screen with black from left to right, call the callback, and uncover the screen from left to right. Each wipe takes the given duration.
+    Wipe(Duration),
+}
+
+impl Transition {
+    fn duration(self) -> Duration {
+        match self {
+            Transition::FadeToBlack(d)
+            | Transition::FadeToColor(_, d)
+            | Transition::Crossfade(d)
+            | Transition::Wipe(d) => d,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    /// The screen is being covered
+    Out,
+    /// Waiting for the renderer to take a snapshot of the screen
+    Capture,
+    /// The renderer has taken a snapshot
+    Captured,
+    /// The screen is being uncovered
+    In,
+    Finished,
+}
+
+pub(crate) struct ActiveTransition {
+    kind: Transition,
+    phase: Phase,
+    elapsed: Duration,
+    callback: Option<Box<dyn FnOnce(&mut GameState)>>,
+}
+
+/// What the renderer should draw on top of the screen for the current transition
+pub(crate) enum TransitionOverlay {
+    /// A colored rectangle. The rectangle is `(x, width)` as a fraction of the screen width, and covers the full height of the screen.
+    Color { color: [f32; 4], rect: (f32, f32) },
+    /// The snapshot that was taken at the start of the transition, with the given opacity
+    Snapshot { alpha: f32 },
+}
+
+impl ActiveTransition {
+    pub fn new(kind: Transition, callback: Box<dyn FnOnce(&mut GameState)>) -> Self {
+        let phase = match kind {
+            Transition::Crossfade(_) => Phase::Capture,
+            _ => Phase::Out,
+        };
+        Self {
+            kind,
+            phase,
+            elapsed: Duration::from_secs(0),
+            callback: Some(callback),
+        }
+    }
+
+    /// Advance the transition. Returns the callback if it should be called this frame.
+    pub fn update(&mut self, delta: Duration) -> Option<Box<dyn FnOnce(&mut GameState)>> {
+        match self.phase {
+            Phase::Out => {
+                self.elapsed += delta;
+                if self.elapsed >= self.kind.duration() {
+                    self.phase = Phase::In;
+                    self.elapsed = Duration::from_secs(0);
+                    return self.callback.take();
+                }
+            }
+            Phase::Captured => {
+                self.phase = Phase::In;
+                self.elapsed = Duration::from_secs(0);
+                return self.callback.take();
+            }
+            Phase::In => {
+                self.elapsed += delta;
+                if self.elapsed >= self.kind.duration() {
+                    self.phase = Phase::Finished;
+                }
+            }
+            Phase::Capture | Phase::Finished => {}
+        }
+        None
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.phase == Phase::Finished
+    }
+
+    pub fn needs_snapshot(&self) -> bool {
+        self.phase == Phase::Capture
+    }
+
+    pub fn snapshot_taken(&mut self) {
+        if self.phase == Phase::Capture {
+            self.phase = Phase::Captured;
+        }
+    }
+
+    pub fn overlay(&self) -> Option<TransitionOverlay> {
+        let progress = if self.kind.duration() == Duration::from_secs(0) {
+            1.0
+        } else {
+            (self.elapsed.as_secs_f32() / self.kind.duration().as_secs_f32()).min(1.0)
+        };
+        let coverage = match self.phase {
+            Phase::Out => progress,
+            Phase::In => 1.0 - progress,
+            Phase::Capture | Phase::Captured | Phase::Finished => return None,
+        };
+        Some(match self.kind {
+            Transition::FadeToBlack(_) => TransitionOverlay::Color {
+                color: [0.0, 0.0, 0.0, coverage],
+                rect: (0.0, 1.0),
+            },
+            Transition::FadeToColor(color, _) => TransitionOverlay::Color {
+                color: [
+                    color[0] as f32 / 255.0,
+                    color[1] as f32 / 255.0,
+                    color[2] as f32 / 255.0,
+                    color[3] as f32 / 255.0 * coverage,
+                ],
+                rect: (0.0, 1.0),
+            },
+            Transition::Crossfade(_) => TransitionOverlay::Snapshot { alpha: coverage },
+            Transition::Wipe(_) => TransitionOverlay::Color {
+                color: [0.0, 0.0, 0.0, 1.0],
+                rect: if self.phase == Phase::Out {
+                    (0.0, coverage)
+                } else {
+                    (1.0 - coverage, coverage)
+                },
+            },
+        })
+    }
+}
+
+#[test]
+fn test_fade_calls_callback_when_covered() {
+    let mut transition = ActiveTransition::new(
+        Transition::FadeToBlack(Duration::from_millis(100)),
+        Box::new(|_| {}),
+    );
+    assert!(transition.update(Duration::from_millis(50)).is_none());
+    assert!(transition.update(Duration::from_millis(50)).is_some());
+    assert!(!transition.is_finished());
+    assert!(transition.update(Duration::from_millis(100)).is_none());
+    assert!(transition.is_finished());
+}
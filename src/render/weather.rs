@@ -0,0 +1,602 @@
+use super::debug_draw::Vertex;
+use crate::game_state::{WorldUnits, CAMERA_FAR_METERS};
+use cgmath::{Matrix4, Vector3, Zero};
+use std::{sync::Arc, time::Duration};
+use vulkano::{
+    buffer::CpuBufferPool,
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet, StdDescriptorPool},
+    device::Device,
+    framebuffer::{RenderPassAbstract, Subpass},
+    image::attachment::AttachmentImage,
+    pipeline::{
+        depth_stencil::{Compare, DepthStencil},
+        GraphicsPipeline, GraphicsPipelineAbstract,
+    },
+};
+
+/// The amount of rain drops or snowflakes that fall around the camera at an intensity of `1.0`
+const MAX_PARTICLES: usize = 4000;
+/// The horizontal distance from the camera in which the rain and snow falls, in meters
+const AREA_RADIUS_METERS: f32 = 15.0;
+/// The distance above and below the camera in which the rain and snow falls, in meters
+const AREA_HEIGHT_METERS: f32 = 10.0;
+/// The speed at which rain drops fall, in meters per second
+const RAIN_SPEED_METERS: f32 = 9.0;
+/// The speed at which snowflakes fall, in meters per second
+const SNOW_SPEED_METERS: f32 = 1.2;
+/// The speed at which snowflakes sway from side to side, in meters per second
+const SNOW_SWAY_METERS: f32 = 0.4;
+/// The length of a rain streak, in seconds of movement of the rain drop
+const RAIN_STREAK_SECONDS: f32 = 0.04;
+/// The size of a snowflake, in meters
+const SNOWFLAKE_SIZE_METERS: f32 = 0.03;
+/// The color of rain drops, in linear space
+const RAIN_COLOR: [f32; 4] = [0.6, 0.65, 0.7, 0.3];
+/// The color of snowflakes, in linear space
+const SNOW_COLOR: [f32; 4] = [0.9, 0.9, 0.95, 0.9];
+/// The color of the fog when it rains, in linear space
+const RAIN_FOG_COLOR: [f32; 3] = [0.35, 0.38, 0.42];
+/// The color of the fog when it snows, in linear space
+const SNOW_FOG_COLOR: [f32; 3] = [0.75, 0.77, 0.8];
+/// How fast surfaces get wet in heavy rain, per second
+const WETTING_SPEED: f32 = 0.2;
+/// How fast surfaces dry when the rain stops, per second
+const DRYING_SPEED: f32 = 0.02;
+
+/// The kind of weather, see [WeatherState].
+///
+/// [WeatherState]: ./struct.WeatherState.html
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum Weather {
+    /// No rain or snow. This is the default.
+    Clear,
+    /// Rain, with an intensity from `0.0` for a light drizzle to `1.0` for a heavy downpour.
+    Rain {
+        /// The intensity of the rain, from `0.0` to `1.0`
+        intensity: f32,
+    },
+    /// Snow, with an intensity from `0.0` for a few flakes to `1.0` for a blizzard.
+    Snow {
+        /// The intensity of the snow, from `0.0` to `1.0`
+        intensity: f32,
+    },
+}
+
+impl Default for Weather {
+    fn default() -> Self {
+        Weather::Clear
+    }
+}
+
+impl Weather {
+    fn rain(self) -> f32 {
+        match self {
+            Weather::Rain { intensity } => intensity.max(0.0).min(1.0),
+            _ => 0.0,
+        }
+    }
+
+    fn snow(self) -> f32 {
+        match self {
+            Weather::Snow { intensity } => intensity.max(0.0).min(1.0),
+            _ => 0.0,
+        }
+    }
+}
+
+/// The weather of the world. Rain and snow fall around the camera, and the weather fogs the world, dims the directional lights and makes surfaces wet.
+///
+/// Changes to the weather fade in over [transition_duration](#structfield.transition_duration), so the rain slowly starts and stops.
+///
+/// ```no_run
+/// # use crystal_engine::*;
+/// # use crystal_engine::state::Weather;
+/// # use cgmath::Vector3;
+/// # let mut state: GameState = unsafe { std::mem::zeroed() };
+/// state.weather.set(Weather::Rain { intensity: 0.8 });
+/// state.weather.wind = Vector3::new(2.0, 0.0, 0.0);
+/// ```
+pub struct WeatherState {
+    /// The wind, in world units per second. This blows the rain and snow sideways. Defaults to no wind.
+    pub wind: Vector3<f32>,
+    /// How long it takes to change to a new weather. Defaults to 5 seconds.
+    pub transition_duration: Duration,
+    /// How much rain and snow at full intensity dims the directional lights and the environment map, from `0.0` to `1.0`. Defaults to `0.5`.
+    pub light_dimming: f32,
+    /// The density of the fog when it rains or snows at full intensity, per meter. Defaults to `0.03`, which hides models at a distance of about 100 meters.
+    pub fog_density: f32,
+    /// Make surfaces that face up look wet when it rains. Wet surfaces are darker, and get a glossy highlight from the directional lights. They slowly dry when the rain stops. Defaults to `true`.
+    pub wet_surfaces: bool,
+    weather: Weather,
+    rain: f32,
+    snow: f32,
+    wetness: f32,
+    particles: Vec<Particle>,
+    random: Random,
+}
+
+impl Default for WeatherState {
+    fn default() -> Self {
+        Self {
+            wind: Vector3::zero(),
+            transition_duration: Duration::from_secs(5),
+            light_dimming: 0.5,
+            fog_density: 0.03,
+            wet_surfaces: true,
+            weather: Weather::Clear,
+            rain: 0.0,
+            snow: 0.0,
+            wetness: 0.0,
+            particles: Vec::new(),
+            random: Random(0x2545_f491),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ParticleKind {
+    Rain,
+    Snow,
+}
+
+struct Particle {
+    kind: ParticleKind,
+    position: Vector3<f32>,
+    /// The phase of the swaying of snowflakes, in radians
+    phase: f32,
+}
+
+impl WeatherState {
+    /// Change the weather. The new weather fades in over [transition_duration](#structfield.transition_duration).
+    pub fn set(&mut self, weather: Weather) {
+        self.weather = weather;
+    }
+
+    /// The weather that was last [set](#method.set). This can still be fading in.
+    pub fn get(&self) -> Weather {
+        self.weather
+    }
+
+    /// The current intensity of the rain, from `0.0` to `1.0`. This can be used to e.g. fade the sound of the rain in and out.
+    pub fn rain_intensity(&self) -> f32 {
+        self.rain
+    }
+
+    /// The current intensity of the snow, from `0.0` to `1.0`.
+    pub fn snow_intensity(&self) -> f32 {
+        self.snow
+    }
+
+    /// How wet the surfaces are, from `0.0` to `1.0`. Surfaces get wet when it rains, and slowly dry when it stops.
+    pub fn wetness(&self) -> f32 {
+        self.wetness
+    }
+
+    /// The combined intensity of the rain and the snow
+    fn precipitation(&self) -> f32 {
+        (self.rain + self.snow).min(1.0)
+    }
+
+    /// Fade the weather towards the weather that was set, and move the rain and snow around the camera.
+    pub(crate) fn update(
+        &mut self,
+        delta: Duration,
+        camera_position: Vector3<f32>,
+        units: &WorldUnits,
+    ) {
+        let delta = delta.as_secs_f32();
+        let step = if self.transition_duration == Duration::from_secs(0) {
+            1.0
+        } else {
+            delta / self.transition_duration.as_secs_f32()
+        };
+        self.rain = approach(self.rain, self.weather.rain(), step);
+        self.snow = approach(self.snow, self.weather.snow(), step);
+        self.wetness = if self.wetness < self.rain {
+            approach(self.wetness, self.rain, WETTING_SPEED * delta)
+        } else {
+            approach(self.wetness, self.rain, DRYING_SPEED * delta)
+        };
+
+        let radius = units.meters_to_units(AREA_RADIUS_METERS);
+        let height = units.meters_to_units(AREA_HEIGHT_METERS);
+        let rain_velocity = Vector3::new(0.0, -units.meters_to_units(RAIN_SPEED_METERS), 0.0);
+        let snow_velocity = Vector3::new(0.0, -units.meters_to_units(SNOW_SPEED_METERS), 0.0);
+        let sway = units.meters_to_units(SNOW_SWAY_METERS);
+
+        let mut rain_count = (self.rain * MAX_PARTICLES as f32) as usize;
+        let mut snow_count = (self.snow * MAX_PARTICLES as f32) as usize;
+        let wind = self.wind;
+        let random = &mut self.random;
+        // Remove the particles that are over the amount of the current intensity
+        self.particles.retain(|particle| {
+            let count = match particle.kind {
+                ParticleKind::Rain => &mut rain_count,
+                ParticleKind::Snow => &mut snow_count,
+            };
+            if *count == 0 {
+                return false;
+            }
+            *count -= 1;
+            true
+        });
+        for particle in &mut self.particles {
+            let velocity = match particle.kind {
+                ParticleKind::Rain => rain_velocity,
+                ParticleKind::Snow => {
+                    particle.phase += delta;
+                    snow_velocity
+                        + Vector3::new(particle.phase.sin(), 0.0, particle.phase.cos()) * sway
+                }
+            };
+            particle.position += (velocity + wind) * delta;
+
+            // Particles that fall out of the area are respawned at the top, particles that leave
+            // the area in any other direction are moved to the other side
+
+            let offset = particle.position - camera_position;
+            if offset.y < -height {
+                particle.position = camera_position
+                    + Vector3::new(random.range(radius), height, random.range(radius));
+                continue;
+            }
+            particle.position.x -= wrap(offset.x, radius);
+            particle.position.y -= wrap(offset.y, height);
+            particle.position.z -= wrap(offset.z, radius);
+        }
+
+        // New particles are spread over the entire area, so the rain does not start as a single
+        // sheet of drops
+        for (kind, count) in &[
+            (ParticleKind::Rain, rain_count),
+            (ParticleKind::Snow, snow_count),
+        ] {
+            for _ in 0..*count {
+                let position = camera_position
+                    + Vector3::new(
+                        random.range(radius),
+                        random.range(height),
+                        random.range(radius),
+                    );
+                self.particles.push(Particle {
+                    kind: *kind,
+                    position,
+                    phase: random.range(std::f32::consts::PI),
+                });
+            }
+        }
+    }
+
+    /// The color of the fog, which is a mix of the colors of the rain and the snow
+    fn fog_color(&self) -> [f32; 3] {
+        let total = self.rain + self.snow;
+        if total <= 0.0 {
+            return RAIN_FOG_COLOR;
+        }
+        let rain = self.rain / total;
+        let snow = self.snow / total;
+        [
+            RAIN_FOG_COLOR[0] * rain + SNOW_FOG_COLOR[0] * snow,
+            RAIN_FOG_COLOR[1] * rain + SNOW_FOG_COLOR[1] * snow,
+            RAIN_FOG_COLOR[2] * rain + SNOW_FOG_COLOR[2] * snow,
+        ]
+    }
+
+    /// The density of the fog, per meter
+    fn fog_density_meters(&self) -> f32 {
+        self.fog_density.max(0.0) * self.precipitation()
+    }
+
+    /// The color of the fog, and the density of the fog per world unit
+    pub(crate) fn fog(&self, units: &WorldUnits) -> ([f32; 3], f32) {
+        (
+            self.fog_color(),
+            self.fog_density_meters() * units.meters_per_unit(),
+        )
+    }
+
+    /// How much the background is hidden by the fog, from `0.0` to `1.0`. The background is as far away as the far plane of the camera.
+    pub(crate) fn background_fog(&self) -> f32 {
+        1.0 - (-self.fog_density_meters() * CAMERA_FAR_METERS).exp()
+    }
+
+    /// The factor that the directional lights and the environment map are multiplied with
+    pub(crate) fn light_factor(&self) -> f32 {
+        1.0 - self.light_dimming.max(0.0).min(1.0) * self.precipitation()
+    }
+
+    /// How wet the surfaces look, or `0.0` if wet surfaces are disabled
+    pub(crate) fn surface_wetness(&self) -> f32 {
+        if self.wet_surfaces {
+            self.wetness
+        } else {
+            0.0
+        }
+    }
+
+    /// The lines of the rain drops and snowflakes. Rain drops are drawn as streaks along their movement, snowflakes as three small crossing lines.
+    fn vertices<'a>(&'a self, units: &WorldUnits) -> impl Iterator<Item = Vertex> + 'a {
+        let light = self.light_factor();
+        let lit = |color: [f32; 4]| {
+            [
+                color[0] * light,
+                color[1] * light,
+                color[2] * light,
+                color[3],
+            ]
+        };
+        let (rain_color, snow_color) = (lit(RAIN_COLOR), lit(SNOW_COLOR));
+        let streak = (Vector3::new(0.0, -units.meters_to_units(RAIN_SPEED_METERS), 0.0)
+            + self.wind)
+            * RAIN_STREAK_SECONDS;
+        let size = units.meters_to_units(SNOWFLAKE_SIZE_METERS) / 2.0;
+
+        self.particles.iter().flat_map(move |particle| {
+            let p = particle.position;
+            let lines = match particle.kind {
+                ParticleKind::Rain => vec![(p, p - streak, rain_color)],
+                ParticleKind::Snow => [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()]
+                    .iter()
+                    .map(|&axis| (p - axis * size, p + axis * size, snow_color))
+                    .collect(),
+            };
+            lines.into_iter().flat_map(|(from, to, color)| {
+                let vertex = |position: Vector3<f32>| Vertex {
+                    position: position.into(),
+                    color,
+                };
+                vec![vertex(from), vertex(to)]
+            })
+        })
+    }
+}
+
+/// Move `value` towards `target`, by at most `step`
+fn approach(value: f32, target: f32, step: f32) -> f32 {
+    if value < target {
+        (value + step).min(target)
+    } else {
+        (value - step).max(target)
+    }
+}
+
+/// The distance that a particle at `offset` from the camera has to move to wrap around to the other side of an area that goes from `-size` to `size`
+fn wrap(offset: f32, size: f32) -> f32 {
+    if offset > size {
+        size * 2.0
+    } else if offset < -size {
+        -size * 2.0
+    } else {
+        0.0
+    }
+}
+
+/// A xorshift random number generator. The positions of the rain drops only have to look random.
+struct Random(u32);
+
+impl Random {
+    /// A random number from `-size` to `size`
+    fn range(&mut self, size: f32) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        let unit = (self.0 >> 8) as f32 / (1 << 24) as f32;
+        (unit * 2.0 - 1.0) * size
+    }
+}
+
+/// The pipeline that draws the rain and snow of [WeatherState].
+///
+/// With the forward rendering path, the lines are drawn after the models with a depth test. With the deferred rendering path, they are drawn after the lighting pass, and the depth test is done in the fragment shader.
+pub struct Pipeline {
+    pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    vertex_buffer: CpuBufferPool<Vertex>,
+    uniform_buffer: CpuBufferPool<vs::ty::Data>,
+    deferred: bool,
+}
+
+impl Pipeline {
+    /// Create the pipeline. `deferred` is true if the subpass is the lighting pass of the deferred rendering path.
+    pub fn create(
+        device: Arc<Device>,
+        subpass: Subpass<Arc<dyn RenderPassAbstract + Send + Sync>>,
+        deferred: bool,
+    ) -> Self {
+        // These should never fail, as the shaders are hard-coded and the device is assumed to be
+        // valid.
+        let vs = vs::Shader::load(device.clone()).expect("failed to create shader module");
+        let builder = GraphicsPipeline::start()
+            .vertex_input_single_buffer::<Vertex>()
+            .vertex_shader(vs.main_entry_point(), ())
+            .line_list()
+            .viewports_dynamic_scissors_irrelevant(1)
+            .blend_alpha_blending();
+
+        // This should never fail because all arguments are hard-coded
+        let pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync> = if deferred {
+            let fs =
+                fs_deferred::Shader::load(device.clone()).expect("failed to create shader module");
+            Arc::new(
+                builder
+                    .fragment_shader(fs.main_entry_point(), ())
+                    .render_pass(subpass)
+                    .build(device.clone())
+                    .unwrap(),
+            )
+        } else {
+            let fs = fs::Shader::load(device.clone()).expect("failed to create shader module");
+            Arc::new(
+                builder
+                    .fragment_shader(fs.main_entry_point(), ())
+                    // The rain is transparent, so it does not hide the models behind it
+                    .depth_stencil(DepthStencil {
+                        depth_write: false,
+                        depth_compare: Compare::LessOrEqual,
+                        ..DepthStencil::simple_depth_test()
+                    })
+                    .render_pass(subpass)
+                    .build(device.clone())
+                    .unwrap(),
+            )
+        };
+
+        Self {
+            pipeline,
+            vertex_buffer: CpuBufferPool::vertex_buffer(device.clone()),
+            uniform_buffer: CpuBufferPool::uniform_buffer(device),
+            deferred,
+        }
+    }
+
+    /// Draw the rain and snow around the camera. `depth` is only used by the deferred rendering path.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render(
+        &self,
+        weather: &WeatherState,
+        units: &WorldUnits,
+        view_projection: Matrix4<f32>,
+        depth: Arc<AttachmentImage>,
+        command_buffer_builder: &mut AutoCommandBufferBuilder,
+        dynamic_state: &DynamicState,
+        descriptor_pool: &mut Arc<StdDescriptorPool>,
+    ) {
+        if weather.particles.is_empty() {
+            return;
+        }
+        // The vertex_buffer is assumed to be valid so this should never fail
+        let vertex_buffer = self.vertex_buffer.chunk(weather.vertices(units)).unwrap();
+
+        let data = vs::ty::Data {
+            view_projection: view_projection.into(),
+        };
+        // The uniform_buffer is assumed to be valid so this should never fail
+        let uniform_buffer_subbuffer = self.uniform_buffer.next(data).unwrap();
+
+        // The pipeline and the layout index are hard-coded so this is assumed to never fail
+        let layout = self.pipeline.descriptor_set_layout(0).unwrap();
+        let set = PersistentDescriptorSet::start(layout.clone())
+            .add_buffer(uniform_buffer_subbuffer)
+            // The uniform subbuffer is assumed to be valid so this should never fail
+            .unwrap();
+        // The depth buffer is an input attachment of the lighting pass and the pool is assumed to
+        // be valid, so these should never fail
+        let set: Arc<dyn DescriptorSet + Send + Sync> = if self.deferred {
+            Arc::new(
+                set.add_image(depth)
+                    .unwrap()
+                    .build_with_pool(descriptor_pool)
+                    .unwrap(),
+            )
+        } else {
+            Arc::new(set.build_with_pool(descriptor_pool).unwrap())
+        };
+
+        command_buffer_builder
+            .draw(
+                self.pipeline.clone(),
+                dynamic_state,
+                vec![Arc::new(vertex_buffer)],
+                set,
+                (),
+            )
+            // the builder and arguments are assumed to be valid so this should never fail
+            .unwrap();
+    }
+}
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "#version 450
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec4 color;
+
+layout(location = 0) out vec4 fragment_color;
+
+layout(set = 0, binding = 0) uniform Data {
+    mat4 view_projection;
+} uniforms;
+
+void main() {
+    gl_Position = uniforms.view_projection * vec4(position, 1.0);
+    fragment_color = color;
+}
+"
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "#version 450
+layout(location = 0) in vec4 fragment_color;
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+    f_color = fragment_color;
+}
+"
+    }
+}
+
+mod fs_deferred {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "#version 450
+layout(location = 0) in vec4 fragment_color;
+
+// The depth buffer of the G-buffer pass, see render/deferred.rs
+layout(input_attachment_index = 4, set = 0, binding = 1) uniform subpassInput g_depth;
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+    // The lighting pass has no depth attachment, so the depth test is done here
+    if (gl_FragCoord.z > subpassLoad(g_depth).x) {
+        discard;
+    }
+    f_color = fragment_color;
+}
+"
+    }
+}
+
+#[test]
+fn test_weather_transition() {
+    let units = WorldUnits::default();
+    let camera = Vector3::new(100.0, 20.0, -50.0);
+    let mut weather = WeatherState::default();
+    weather.set(Weather::Rain { intensity: 1.0 });
+
+    weather.update(Duration::from_millis(2500), camera, &units);
+    assert!((weather.rain_intensity() - 0.5).abs() < 0.001);
+    assert_eq!(weather.particles.len(), MAX_PARTICLES / 2);
+
+    for _ in 0..100 {
+        weather.update(Duration::from_millis(100), camera, &units);
+    }
+    assert_eq!(weather.rain_intensity(), 1.0);
+    assert_eq!(weather.wetness(), 1.0);
+    assert_eq!(weather.particles.len(), MAX_PARTICLES);
+    for particle in &weather.particles {
+        let offset = particle.position - camera;
+        assert!(offset.x.abs() <= AREA_RADIUS_METERS);
+        assert!(offset.y.abs() <= AREA_HEIGHT_METERS);
+        assert!(offset.z.abs() <= AREA_RADIUS_METERS);
+    }
+
+    // The rain stops after the transition, but the surfaces stay wet for a while
+    weather.set(Weather::Snow { intensity: 0.5 });
+    weather.update(Duration::from_secs(5), camera, &units);
+    assert_eq!(weather.rain_intensity(), 0.0);
+    assert_eq!(weather.snow_intensity(), 0.5);
+    assert!(weather.wetness() > 0.8);
+    assert!(weather
+        .particles
+        .iter()
+        .all(|particle| particle.kind == ParticleKind::Snow));
+    assert_eq!(weather.particles.len(), MAX_PARTICLES / 2);
+}
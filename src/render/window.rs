@@ -1,8 +1,22 @@
-use super::pipeline::RenderPipeline;
-use crate::{internal::UpdateMessage, state::InitError, Game, GameState};
-use std::sync::mpsc::{channel, Receiver};
+use super::{
+    device::{DevicePreference, DeviceType},
+    monitor::WindowMode,
+    pipeline::RenderPipeline,
+};
+use crate::{
+    game_state::RenderOutput,
+    profiler::ProfilerStage,
+    state::{InitError, MouseMode, RenderError},
+    Game, GameState,
+};
+use image::RgbaImage;
+use std::{
+    marker::PhantomData,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use vulkano::{
-    device::{Device, DeviceExtensions, Features},
+    device::{Device, DeviceExtensions, Features, Queue},
     instance::{
         debug::{DebugCallback, MessageSeverity},
         Instance, InstanceExtensions, PhysicalDevice, QueueFamily, Version,
@@ -10,9 +24,9 @@ use vulkano::{
 };
 use vulkano_win::VkSurfaceBuild;
 use winit::{
-    event::{ElementState, Event, KeyboardInput, WindowEvent},
+    event::{DeviceEvent, ElementState, Event, KeyboardInput, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
+    window::WindowBuilder as WinitWindowBuilder,
 };
 
 /// A handle to the window and the game state. This will be your main entrypoint of the game.
@@ -25,11 +39,26 @@ pub struct Window<GAME: Game + 'static> {
 struct WindowState<GAME: Game + 'static> {
     dimensions: [f32; 2],
     game_state: GameState,
-    model_handle_receiver: Receiver<UpdateMessage>,
     game: GAME,
+    /// The earliest time the next frame can be rendered, if the frame rate is limited
+    next_frame: Option<Instant>,
+    /// The instance and the GPU that a new device is created on when the device is lost
+    instance: Arc<Instance>,
+    physical_index: usize,
     _dbg: Option<DebugCallback>,
 }
 
+/// The Vulkan instance and the device that the game is rendered with.
+struct Vulkan {
+    instance: Arc<Instance>,
+    physical_index: usize,
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    /// The queue that assets are uploaded on. This is a dedicated transfer queue when the GPU has one, otherwise this is `queue`.
+    transfer_queue: Arc<Queue>,
+    debug_callback: Option<DebugCallback>,
+}
+
 fn msg_severity(s: MessageSeverity) -> char {
     if s.error {
         'E'
@@ -46,92 +75,78 @@ fn msg_severity(s: MessageSeverity) -> char {
 
 impl<GAME: Game + 'static> Window<GAME> {
     /// Create a new instance of the window. This will immediately instantiate an instance of [Game].
+    ///
+    /// To configure the window before it is created, use [Window::builder] instead.
     pub fn new(width: f32, height: f32) -> Result<Self, InitError> {
-        let instance = {
-            let extensions = InstanceExtensions {
-                ext_debug_utils: true,
-                ..vulkano_win::required_extensions()
-            };
-            Instance::new(None, &extensions, None).map_err(InitError::CouldNotInitVulkano)?
-        };
-
-        let _dbg = if cfg!(debug_assertions) {
-            DebugCallback::errors_and_warnings(&instance, |msg| {
-                println!("{}> {}", msg_severity(msg.severity), msg.description);
-            })
-            .ok()
-        } else {
-            None
-        };
+        Self::builder(width, height).build()
+    }
 
-        let mut physical = None;
-        let mut queue_family = None;
-        for device in PhysicalDevice::enumerate(&instance) {
-            let picked = if physical.is_none() {
-                physical = Some(device);
-                queue_family = Some(
-                    device
-                        .queue_families()
-                        .find(|q| q.supports_graphics())
-                        .ok_or(InitError::CouldNotFindValidGraphicsQueue)?,
-                );
-                true
-            } else {
-                false
-            };
-            print_physical_device_info(&device, picked, if picked { queue_family } else { None });
+    /// Create a [WindowBuilder], which can be used to configure the window before it is created.
+    ///
+    /// ```no_run
+    /// # use crystal_engine::*;
+    /// # struct Game;
+    /// # impl crystal_engine::Game for Game {
+    /// #     fn init(_: &mut GameState) -> Self { Game }
+    /// #     fn update(&mut self, _: &mut GameState) {}
+    /// # }
+    /// let window = Window::<Game>::builder(800., 600.)
+    ///     .with_rendering_path(RenderingPath::Deferred)
+    ///     .build()
+    ///     .unwrap();
+    /// window.run();
+    /// ```
+    pub fn builder(width: f32, height: f32) -> WindowBuilder<GAME> {
+        WindowBuilder {
+            width,
+            height,
+            rendering_path: RenderingPath::Forward,
+            device_preference: DevicePreference::default(),
+            window_mode: WindowMode::default(),
+            #[cfg(feature = "hot-reload")]
+            game_library: None,
+            _game: PhantomData,
         }
-        let physical = physical.ok_or(InitError::CouldNotFindPhysicalDevice)?;
-        let queue_family = queue_family.ok_or(InitError::CouldNotFindValidGraphicsQueue)?;
-
-        let (device, queue) = {
-            let (device, mut queues) = Device::new(
-                physical,
-                &Features::none(),
-                &DeviceExtensions {
-                    khr_storage_buffer_storage_class: true,
-                    khr_swapchain: true,
-                    ..DeviceExtensions::none()
-                },
-                [(queue_family, 0.5)].iter().cloned(),
-            )
-            .map_err(InitError::CouldNotCreateDevice)?;
-            (
-                device,
-                queues
-                    .next()
-                    .ok_or(InitError::CouldNotFindValidGraphicsQueue)?,
-            )
-        };
+    }
+
+    /// Create a game that is rendered into an offscreen image of the given size, instead of into a window. See [HeadlessWindow] for more information.
+    ///
+    /// To configure the game before it is created, use [WindowBuilder::build_headless] instead.
+    pub fn headless(width: u32, height: u32) -> Result<HeadlessWindow<GAME>, InitError> {
+        Self::builder(width as f32, height as f32).build_headless()
+    }
+
+    fn create(builder: WindowBuilder<GAME>) -> Result<Self, InitError> {
+        let [width, height] = [builder.width, builder.height];
+        let vulkan = Vulkan::new(false, &builder.device_preference)?;
+        // The index was just enumerated, so this should never fail
+        let physical = PhysicalDevice::from_index(&vulkan.instance, vulkan.physical_index).unwrap();
+
         let events_loop = EventLoop::new();
-        let surface = WindowBuilder::new()
-            .build_vk_surface(&events_loop, instance.clone())
+        let monitors = events_loop.available_monitors().collect::<Vec<_>>();
+        let fullscreen = builder
+            .window_mode
+            .to_fullscreen(&monitors)
+            .map_err(InitError::InvalidWindowMode)?;
+        let surface = WinitWindowBuilder::new()
+            .with_fullscreen(fullscreen)
+            .build_vk_surface(&events_loop, vulkan.instance.clone())
             .map_err(InitError::CouldNotCreateWindow)?;
 
         let pipeline = RenderPipeline::create(
-            device.clone(),
-            queue.clone(),
+            vulkan.device.clone(),
+            vulkan.queue.clone(),
             surface.clone(),
             physical,
             [width, height],
+            builder.rendering_path,
         )?;
 
-        let (sender, receiver) = channel();
-
-        let mut game_state = GameState::new(device, queue, sender, surface);
-
-        let game = GAME::init(&mut game_state);
-
+        let output = RenderOutput::Window(surface);
         Ok(Window {
             pipeline,
             events_loop,
-            state: WindowState {
-                dimensions: [width, height],
-                model_handle_receiver: receiver,
-                game_state,
-                game,
-                _dbg,
-            },
+            state: WindowState::new(vulkan, output, [width, height], builder),
         })
     }
 
@@ -143,14 +158,57 @@ impl<GAME: Game + 'static> Window<GAME> {
             mut state,
         } = self;
         events_loop.run(move |event, _, control_flow| {
+            match &event {
+                Event::WindowEvent { event, .. } => {
+                    state.game_state.input.handle_window_event(event);
+                    if let Some(controller) = &mut state.game_state.camera_controller {
+                        controller.handle_window_event(event);
+                    }
+                }
+                Event::DeviceEvent { device_id, event } => state
+                    .game_state
+                    .input
+                    .handle_device_event(*device_id, event),
+                _ => {}
+            }
             match event {
                 Event::WindowEvent {
                     event: WindowEvent::Resized(newsize),
                     ..
                 } => {
-                    state.dimensions = [newsize.width as f32, newsize.height as f32];
+                    state.resized((newsize.width, newsize.height));
                     pipeline.resize(state.dimensions);
                 }
+                Event::WindowEvent {
+                    event:
+                        WindowEvent::ScaleFactorChanged {
+                            scale_factor,
+                            ref new_inner_size,
+                        },
+                    ..
+                } => {
+                    state.game_state.gui.scale_factor = scale_factor;
+                    state.resized((new_inner_size.width, new_inner_size.height));
+                    pipeline.resize(state.dimensions);
+                }
+                Event::WindowEvent {
+                    event: WindowEvent::Focused(focused),
+                    ..
+                } => state.focus_changed(focused),
+                Event::WindowEvent {
+                    event: WindowEvent::CursorEntered { .. },
+                    ..
+                } => state.cursor_inside_changed(true),
+                Event::WindowEvent {
+                    event: WindowEvent::CursorLeft { .. },
+                    ..
+                } => state.cursor_inside_changed(false),
+                Event::WindowEvent {
+                    event: WindowEvent::CursorMoved { position, .. },
+                    ..
+                } => state
+                    .game_state
+                    .set_cursor_position((position.x, position.y)),
                 Event::WindowEvent {
                     event: WindowEvent::CloseRequested,
                     ..
@@ -158,26 +216,83 @@ impl<GAME: Game + 'static> Window<GAME> {
                     *control_flow = ControlFlow::Exit
                 }
                 Event::RedrawEventsCleared => {
-                    match pipeline.render(state.dimensions, &mut state.game_state) {
-                        Err(e) => {
-                            eprintln!("Engine encountered a fatal error");
-                            eprintln!();
-                            eprintln!("{:?}", e);
-                            eprintln!();
-                            eprintln!("Exiting now");
+                    if let Some(next_frame) = state.next_frame {
+                        if Instant::now() < next_frame {
+                            // An event woke up the event loop before the next frame is due
+                            *control_flow = ControlFlow::WaitUntil(next_frame);
+                            return;
+                        }
+                    }
+                    let start = state.game_state.profiler.start();
+                    let game = &mut state.game;
+                    let result = pipeline.render(&mut state.game_state, &mut |context| {
+                        game.custom_render(context)
+                    });
+                    state
+                        .game_state
+                        .profiler
+                        .record(ProfilerStage::CommandBufferBuilding, start);
+                    // A frame that could not be rendered is skipped, the game keeps updating
+                    // unless it decides to stop
+                    let result = result.or_else(|e| state.recover(&mut pipeline, e).map(|_| None));
+                    let future = match result {
+                        Ok(future) => future,
+                        Err(e) if state.game.on_error(&mut state.game_state, &e) => None,
+                        Err(_) => {
                             *control_flow = ControlFlow::Exit;
                             return;
                         }
-                        Ok(future) => {
-                            state.update();
-                            pipeline.finish_render(future);
+                    };
+                    state.update();
+                    let start = state.game_state.profiler.start();
+                    let finished = pipeline
+                        .finish_render(future)
+                        .or_else(|e| state.recover(&mut pipeline, e));
+                    state
+                        .game_state
+                        .profiler
+                        .record(ProfilerStage::GpuWait, start);
+                    if let Err(e) = finished {
+                        if !state.game.on_error(&mut state.game_state, &e) {
+                            *control_flow = ControlFlow::Exit;
+                            return;
                         }
                     }
+                    state.game_state.profiler.finish_frame();
+                    *control_flow = state.control_flow(start.instant());
+                }
+                Event::DeviceEvent {
+                    event: DeviceEvent::MouseMotion { delta },
+                    ..
+                } if state.game_state.mouse_mode == MouseMode::Relative
+                    && state.game_state.has_focus() =>
+                {
+                    if let Some(controller) = &mut state.game_state.camera_controller {
+                        controller.mouse_moved(delta);
+                    }
+                    let start = state.game_state.profiler.start();
+                    state.game.mouse_moved(&mut state.game_state, delta);
+                    state
+                        .game_state
+                        .profiler
+                        .record(ProfilerStage::EventHandling, start);
                 }
                 _ => {}
             }
+            if let Event::DeviceEvent { event, .. } = &event {
+                let start = state.game_state.profiler.start();
+                state.game.device_event(&mut state.game_state, event);
+                state
+                    .game_state
+                    .profiler
+                    .record(ProfilerStage::EventHandling, start);
+            }
             if let Event::WindowEvent { event, .. } = event {
-                state.game.event(&mut state.game_state, &event);
+                let start = state.game_state.profiler.start();
+                // Clicks and drags on debug widgets are not passed on to the game
+                if !state.game_state.handle_debug_widget_event(&event) {
+                    state.game.event(&mut state.game_state, &event);
+                }
                 if let WindowEvent::KeyboardInput {
                     input:
                         KeyboardInput {
@@ -189,13 +304,17 @@ impl<GAME: Game + 'static> Window<GAME> {
                 } = event
                 {
                     if keystate == ElementState::Pressed {
-                        state.game_state.keyboard.pressed.insert(key);
+                        state.game_state.keyboard.press(key);
                         state.game.keydown(&mut state.game_state, key);
                     } else {
-                        state.game_state.keyboard.pressed.remove(&key);
+                        state.game_state.keyboard.release(key);
                         state.game.keyup(&mut state.game_state, key);
                     }
                 }
+                state
+                    .game_state
+                    .profiler
+                    .record(ProfilerStage::EventHandling, start);
             }
 
             if !state.game_state.is_running {
@@ -205,15 +324,422 @@ impl<GAME: Game + 'static> Window<GAME> {
     }
 }
 
+/// A builder for a [Window]. This is created with [Window::builder].
+pub struct WindowBuilder<GAME: Game + 'static> {
+    width: f32,
+    height: f32,
+    rendering_path: RenderingPath,
+    device_preference: DevicePreference,
+    window_mode: WindowMode,
+    #[cfg(feature = "hot-reload")]
+    game_library: Option<std::path::PathBuf>,
+    _game: PhantomData<GAME>,
+}
+
+impl<GAME: Game + 'static> WindowBuilder<GAME> {
+    /// Set the way the world is rendered. Defaults to [RenderingPath::Forward].
+    pub fn with_rendering_path(mut self, rendering_path: RenderingPath) -> Self {
+        self.rendering_path = rendering_path;
+        self
+    }
+
+    /// Set which GPU the game is rendered on, when the computer has more than one. Defaults to [DevicePreference::Discrete].
+    ///
+    /// The available devices are printed when the window is created, and the picked device can be found with [GameState::device_info](struct.GameState.html#method.device_info).
+    ///
+    /// [DevicePreference::Discrete]: state/enum.DevicePreference.html#variant.Discrete
+    pub fn with_device_preference(mut self, device_preference: DevicePreference) -> Self {
+        self.device_preference = device_preference;
+        self
+    }
+
+    /// Set if the window starts as a regular window or fullscreen, and on which monitor. Defaults to [WindowMode::Windowed]. This can be changed later with [GameState::set_window_mode](struct.GameState.html#method.set_window_mode).
+    ///
+    /// Creating the window fails when the monitor or the video mode does not exist. The window mode is ignored by [build_headless](#method.build_headless).
+    ///
+    /// [WindowMode::Windowed]: state/enum.WindowMode.html#variant.Windowed
+    pub fn with_window_mode(mut self, window_mode: WindowMode) -> Self {
+        self.window_mode = window_mode;
+        self
+    }
+
+    /// Create the window. This will immediately instantiate an instance of [Game].
+    pub fn build(self) -> Result<Window<GAME>, InitError> {
+        Window::create(self)
+    }
+
+    /// Create a game that is rendered into an offscreen image with the size of this builder, instead of into a window. This will immediately instantiate an instance of [Game].
+    pub fn build_headless(self) -> Result<HeadlessWindow<GAME>, InitError> {
+        let dimensions = [self.width.floor().max(1.0), self.height.floor().max(1.0)];
+        let vulkan = Vulkan::new(true, &self.device_preference)?;
+        let pipeline = RenderPipeline::create_headless(
+            vulkan.device.clone(),
+            vulkan.queue.clone(),
+            dimensions,
+            self.rendering_path,
+        )?;
+        let output = RenderOutput::Headless {
+            width: dimensions[0] as u32,
+            height: dimensions[1] as u32,
+        };
+        Ok(HeadlessWindow {
+            pipeline,
+            state: WindowState::new(vulkan, output, dimensions, self),
+        })
+    }
+}
+
+#[cfg(feature = "hot-reload")]
+impl WindowBuilder<crate::HotReload> {
+    /// Set the dynamic library that the [HotReload](struct.HotReload.html) game is loaded from. The library is reloaded when it changes.
+    ///
+    /// This is only available if the `hot-reload` feature is enabled
+    pub fn with_game_library(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.game_library = Some(path.into());
+        self
+    }
+}
+
+/// A game that is rendered into an offscreen image instead of into a window. This is created with [Window::headless] or [WindowBuilder::build_headless].
+///
+/// No window, surface or swapchain is created, so this works on machines without a display, like CI servers, as long as a Vulkan driver is available (e.g. lavapipe or SwiftShader). This makes it possible to test the rendering of a game, e.g. by comparing frames to golden images.
+///
+/// There is no event loop, so frames are only rendered when [render_frame](#method.render_frame) is called, and the game does not receive any input events. Methods of [GameState] that change the window, like [set_title](struct.GameState.html#method.set_title), do nothing.
+///
+/// ```no_run
+/// # use crystal_engine::*;
+/// # struct Game;
+/// # impl crystal_engine::Game for Game {
+/// #     fn init(_: &mut GameState) -> Self { Game }
+/// #     fn update(&mut self, _: &mut GameState) {}
+/// # }
+/// let mut window = Window::<Game>::headless(320, 240).unwrap();
+/// let frame = window.render_frame();
+/// let golden = image::open("tests/golden/start.png").unwrap().into_rgba();
+/// assert!(frame == golden);
+/// ```
+pub struct HeadlessWindow<GAME: Game + 'static> {
+    pipeline: RenderPipeline,
+    state: WindowState<GAME>,
+}
+
+impl<GAME: Game + 'static> HeadlessWindow<GAME> {
+    /// Render a single frame and return it. Like in [Window::run], [Game::update] is called after the frame is rendered, so changes made by the update are visible in the next frame.
+    ///
+    /// The time between two frames is the real time that passed between the calls to this method.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the frame could not be rendered, e.g. when the GPU runs out of memory or the device is lost. [Game::on_error] is not called, because a headless window has no frame to skip.
+    pub fn render_frame(&mut self) -> RgbaImage {
+        let start = self.state.game_state.profiler.start();
+        let game = &mut self.state.game;
+        let future = self
+            .pipeline
+            .render(&mut self.state.game_state, &mut |context| {
+                game.custom_render(context)
+            })
+            .unwrap_or_else(|e| panic!("Could not render the frame: {}", e));
+        self.state
+            .game_state
+            .profiler
+            .record(ProfilerStage::CommandBufferBuilding, start);
+        let rendered = future.is_some();
+
+        self.state.update();
+        let start = self.state.game_state.profiler.start();
+        if let Err(e) = self.pipeline.finish_render(future) {
+            panic!("Could not render the frame: {}", e);
+        }
+        self.state
+            .game_state
+            .profiler
+            .record(ProfilerStage::GpuWait, start);
+        self.state.game_state.profiler.finish_frame();
+
+        assert!(rendered, "Could not render the frame");
+        // The pipeline is created with `create_headless`, so this should never fail
+        self.pipeline.offscreen_frame().unwrap()
+    }
+
+    /// Get a reference to the state of the game.
+    pub fn game_state(&self) -> &GameState {
+        &self.state.game_state
+    }
+
+    /// Get a mutable reference to the state of the game, e.g. to move the camera before the next frame is rendered.
+    pub fn game_state_mut(&mut self) -> &mut GameState {
+        &mut self.state.game_state
+    }
+
+    /// Get a reference to the instance of [Game].
+    pub fn game(&self) -> &GAME {
+        &self.state.game
+    }
+
+    /// Get a mutable reference to the instance of [Game].
+    pub fn game_mut(&mut self) -> &mut GAME {
+        &mut self.state.game
+    }
+}
+
+/// The way the world is rendered. This can be set with [WindowBuilder::with_rendering_path].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderingPath {
+    /// Every model is lit while it is being rendered. This supports transparent models, but the
+    /// lighting is calculated for every fragment of every model, including fragments that are
+    /// hidden behind other models later on.
+    ///
+    /// Only [directional lights](state/struct.DirectionalLight.html) are supported.
+    Forward,
+    /// The models are rendered into a G-buffer first, after which the lighting is calculated
+    /// once for every pixel on the screen. This is a lot faster when there are many lights, and
+    /// supports [point lights](state/struct.PointLight.html) without a limit on the amount of lights.
+    ///
+    /// Transparent models are not supported, and are rendered as if they are opaque.
+    Deferred,
+}
+
 impl<GAME: Game + 'static> WindowState<GAME> {
+    /// Create the game state with the settings of the builder, and immediately instantiate an instance of [Game].
+    #[cfg_attr(not(feature = "hot-reload"), allow(unused_variables))]
+    fn new(
+        vulkan: Vulkan,
+        output: RenderOutput,
+        dimensions: [f32; 2],
+        builder: WindowBuilder<GAME>,
+    ) -> Self {
+        let mut game_state =
+            GameState::new(vulkan.device, vulkan.queue, vulkan.transfer_queue, output);
+        #[cfg(feature = "hot-reload")]
+        {
+            game_state.hot_reload_library = builder.game_library;
+        }
+        let game = GAME::init(&mut game_state);
+        WindowState {
+            dimensions,
+            game_state,
+            game,
+            next_frame: None,
+            instance: vulkan.instance,
+            physical_index: vulkan.physical_index,
+            _dbg: vulkan.debug_callback,
+        }
+    }
+
     fn update(&mut self) {
         self.game_state.update();
+        let start = self.game_state.profiler.start();
+        while self.game_state.time.next_fixed_update() {
+            self.game_state.begin_fixed_update();
+            self.game.fixed_update(&mut self.game_state);
+            self.game_state.end_fixed_update();
+        }
         self.game.update(&mut self.game_state);
+        self.game_state.run_timers();
+        #[cfg(feature = "ecs")]
+        crate::ecs::sync(&mut self.game_state);
+        self.game_state
+            .profiler
+            .record(ProfilerStage::Update, start);
+        self.game_state.keyboard.update();
+        self.game_state.input.update();
+        self.game_state.refresh_profiler_overlay();
+        self.game_state.refresh_ruler();
+        self.game_state.refresh_notifications();
+        self.game_state.refresh_custom_cursor();
+        self.game_state.update_streaming();
+        self.game_state.presence.update();
+
+        self.game_state.apply_internal_updates();
+    }
+
+    /// Handle a change in the size of the window, in physical pixels, and notify the game.
+    fn resized(&mut self, size: (u32, u32)) {
+        self.dimensions = [size.0 as f32, size.1 as f32];
+        // The GUI is also updated every frame, but the game should see the new size right away
+        self.game_state.gui.window_size = size;
+        let start = self.game_state.profiler.start();
+        self.game.resized(&mut self.game_state, size);
+        self.game_state
+            .profiler
+            .record(ProfilerStage::EventHandling, start);
+    }
+
+    /// Handle the window gaining or losing focus, and notify the game.
+    fn focus_changed(&mut self, focused: bool) {
+        self.game_state.set_focus(focused);
+        let start = self.game_state.profiler.start();
+        self.game.focus_changed(&mut self.game_state, focused);
+        self.game_state
+            .profiler
+            .record(ProfilerStage::EventHandling, start);
+    }
+
+    /// Handle the cursor entering or leaving the window, and notify the game.
+    fn cursor_inside_changed(&mut self, inside: bool) {
+        self.game_state.set_cursor_inside(inside);
+        let start = self.game_state.profiler.start();
+        if inside {
+            self.game.cursor_entered(&mut self.game_state);
+        } else {
+            self.game.cursor_left(&mut self.game_state);
+        }
+        self.game_state
+            .profiler
+            .record(ProfilerStage::EventHandling, start);
+    }
 
-        while let Ok(msg) = self.model_handle_receiver.try_recv() {
-            msg.apply(&mut self.game_state);
+    /// Create a new device when the device was lost, and upload the models and the render targets again. The game is notified with [Game::device_restored] when this succeeds. Other errors are returned as they are.
+    fn recover(
+        &mut self,
+        pipeline: &mut RenderPipeline,
+        error: RenderError,
+    ) -> Result<(), RenderError> {
+        if !matches!(error, RenderError::DeviceLost) {
+            return Err(error);
         }
+        // The GPU can disappear with the device, e.g. when an external GPU is unplugged
+        let physical = PhysicalDevice::from_index(&self.instance, self.physical_index).ok_or(
+            RenderError::CouldNotRestoreDevice(InitError::CouldNotFindPhysicalDevice),
+        )?;
+        let (device, queue, transfer_queue) =
+            create_device(physical, false).map_err(RenderError::CouldNotRestoreDevice)?;
+        pipeline
+            .recreate(device.clone(), queue.clone(), physical)
+            .map_err(RenderError::CouldNotRestoreDevice)?;
+        self.game_state
+            .restore_device(device, queue, transfer_queue)
+            .map_err(RenderError::CouldNotRestoreModel)?;
+        let start = self.game_state.profiler.start();
+        self.game.device_restored(&mut self.game_state);
+        self.game_state
+            .profiler
+            .record(ProfilerStage::EventHandling, start);
+        Ok(())
     }
+
+    /// Get the control flow of the event loop after a frame that started at `frame_start` is rendered.
+    fn control_flow(&mut self, frame_start: Instant) -> ControlFlow {
+        self.next_frame = self
+            .game_state
+            .target_fps
+            .map(|fps| frame_start + Duration::from_secs_f64(1.0 / fps as f64));
+        match (self.next_frame, self.game_state.power_saving) {
+            (Some(next_frame), _) => ControlFlow::WaitUntil(next_frame),
+            (None, true) => ControlFlow::Wait,
+            (None, false) => ControlFlow::Poll,
+        }
+    }
+}
+
+impl Vulkan {
+    /// Create a Vulkan instance and a device. Headless games do not need the swapchain extension, so they can run on devices without a display.
+    fn new(headless: bool, device_preference: &DevicePreference) -> Result<Self, InitError> {
+        let instance = {
+            let extensions = if headless {
+                InstanceExtensions {
+                    ext_debug_utils: true,
+                    ..InstanceExtensions::none()
+                }
+            } else {
+                InstanceExtensions {
+                    ext_debug_utils: true,
+                    ..vulkano_win::required_extensions()
+                }
+            };
+            Instance::new(None, &extensions, None).map_err(InitError::CouldNotInitVulkano)?
+        };
+
+        let debug_callback = if cfg!(debug_assertions) {
+            DebugCallback::errors_and_warnings(&instance, |msg| {
+                println!("{}> {}", msg_severity(msg.severity), msg.description);
+            })
+            .ok()
+        } else {
+            None
+        };
+
+        let devices = PhysicalDevice::enumerate(&instance)
+            .filter(|device| device.queue_families().any(|q| q.supports_graphics()))
+            .collect::<Vec<_>>();
+        let names = devices
+            .iter()
+            .map(|device| device.name().to_owned())
+            .collect::<Vec<_>>();
+        let candidates = devices
+            .iter()
+            .zip(&names)
+            .map(|(device, name)| (device.index(), name.as_str(), DeviceType::from(device.ty())))
+            .collect::<Vec<_>>();
+        let picked = device_preference.pick(&candidates);
+        for device in PhysicalDevice::enumerate(&instance) {
+            let is_picked = picked.map(|i| devices[i].index()) == Some(device.index());
+            let queue_family = if is_picked {
+                device.queue_families().find(|q| q.supports_graphics())
+            } else {
+                None
+            };
+            print_physical_device_info(&device, is_picked, queue_family);
+        }
+        let physical = devices[picked.ok_or(InitError::CouldNotFindPhysicalDevice)?];
+        let (device, queue, transfer_queue) = create_device(physical, headless)?;
+
+        Ok(Vulkan {
+            physical_index: physical.index(),
+            instance,
+            device,
+            queue,
+            transfer_queue,
+            debug_callback,
+        })
+    }
+}
+
+/// Create a device with a graphics queue, and a dedicated transfer queue if the GPU has one. Returns the device, the graphics queue and the transfer queue, which is the graphics queue when the GPU has no dedicated transfer queue.
+fn create_device(
+    physical: PhysicalDevice,
+    headless: bool,
+) -> Result<(Arc<Device>, Arc<Queue>, Arc<Queue>), InitError> {
+    // Only devices with a graphics queue can be picked, so this should never fail
+    let queue_family = physical
+        .queue_families()
+        .find(|q| q.supports_graphics())
+        .unwrap();
+    // A queue family that only supports transfers is usually a separate DMA engine, which can
+    // upload textures while the graphics queue is rendering
+    let transfer_family = physical.queue_families().find(|q| {
+        q.explicitly_supports_transfers() && !q.supports_graphics() && !q.supports_compute()
+    });
+    let queue_families = std::iter::once((queue_family, 0.5))
+        .chain(transfer_family.map(|family| (family, 0.5)))
+        .collect::<Vec<_>>();
+
+    let (device, mut queues) = Device::new(
+        physical,
+        &Features {
+            // Clip planes are ignored on GPUs without this feature
+            shader_clip_distance: physical.supported_features().shader_clip_distance,
+            // Compressed textures are decompressed when they are loaded on GPUs without
+            // this feature
+            texture_compression_bc: physical.supported_features().texture_compression_bc,
+            // Occlusion culling is ignored on GPUs without this feature
+            fragment_stores_and_atomics: physical.supported_features().fragment_stores_and_atomics,
+            ..Features::none()
+        },
+        &DeviceExtensions {
+            khr_storage_buffer_storage_class: true,
+            khr_swapchain: !headless,
+            ..DeviceExtensions::none()
+        },
+        queue_families,
+    )
+    .map_err(InitError::CouldNotCreateDevice)?;
+    let queue = queues
+        .next()
+        .ok_or(InitError::CouldNotFindValidGraphicsQueue)?;
+    let transfer_queue = queues.next().unwrap_or_else(|| queue.clone());
+    Ok((device, queue, transfer_queue))
 }
 
 fn print_physical_device_info(
@@ -222,9 +748,11 @@ fn print_physical_device_info(
     queue_family: Option<QueueFamily>,
 ) {
     println!(
-        "{} {}",
+        "{} {}: {} ({:?})",
         if picked { "\u{2192}" } else { "-" },
+        device.index(),
         device.name(),
+        device.ty(),
     );
     println!("  - api verison: {}", device.api_version());
     println!(
@@ -236,12 +764,13 @@ fn print_physical_device_info(
     for family in device.queue_families() {
         let picked = queue_family.as_ref() == Some(&family);
         println!(
-            "  {} {}, queue count: {:2}, graphics: {:5}, compute: {:5}",
+            "  {} {}, queue count: {:2}, graphics: {:5}, compute: {:5}, transfer: {:5}",
             if picked { "\u{2192}" } else { "-" },
             family.id(),
             family.queues_count(),
             family.supports_graphics(),
             family.supports_compute(),
+            family.explicitly_supports_transfers(),
         );
     }
 }
@@ -0,0 +1,292 @@
+use crate::{
+    assets::AssetPaths,
+    model::{
+        loader::ParsedModel, ModelBuilder, ModelData, ModelHandle, PbrMaterial, Progress,
+        SourceOrShape,
+    },
+    render::lights::{DirectionalLight, LightState, PointLight},
+    state::SceneError,
+    GameState,
+};
+use cgmath::{Matrix4, Rad, Vector3};
+
+/// The source that a model was loaded from. This is stored with every model, so that a [Scene] can load the model again.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum ModelSource {
+    /// Loaded with [GameState::new_obj_model](../struct.GameState.html#method.new_obj_model)
+    Obj(String),
+    /// Loaded with [GameState::new_fbx_model](../struct.GameState.html#method.new_fbx_model)
+    Fbx(String),
+    /// Created with [GameState::new_triangle_model](../struct.GameState.html#method.new_triangle_model)
+    Triangle,
+    /// Created with [GameState::new_rectangle_model](../struct.GameState.html#method.new_rectangle_model)
+    Rectangle,
+    /// Created with [GameState::new_cube_model](../struct.GameState.html#method.new_cube_model)
+    Cube,
+    /// Created with [GameState::new_uv_sphere_model](../struct.GameState.html#method.new_uv_sphere_model)
+    UvSphere {
+        /// The amount of slices around the Y axis
+        segments: u32,
+        /// The amount of stacks from the top to the bottom
+        rings: u32,
+    },
+    /// Created with [GameState::new_cylinder_model](../struct.GameState.html#method.new_cylinder_model)
+    Cylinder {
+        /// The amount of slices around the Y axis
+        segments: u32,
+    },
+    /// Created with [GameState::new_plane_model](../struct.GameState.html#method.new_plane_model)
+    Plane {
+        /// The amount of quads along each side
+        subdivisions: u32,
+    },
+    /// Created with [GameState::new_capsule_model](../struct.GameState.html#method.new_capsule_model)
+    Capsule {
+        /// The amount of slices around the Y axis
+        segments: u32,
+        /// The amount of stacks of each half sphere
+        rings: u32,
+        /// The height of the cylinder in the middle
+        height: f32,
+    },
+}
+
+impl ModelSource {
+    /// Read and parse the model. This does not need the GPU, so it can run on any thread.
+    pub(crate) fn parse(&self, assets: &AssetPaths) -> Result<ParsedModel, SceneError> {
+        let source = match self {
+            #[cfg(feature = "format-obj")]
+            ModelSource::Obj(path) => SourceOrShape::Obj(path),
+            #[cfg(feature = "format-fbx")]
+            ModelSource::Fbx(path) => SourceOrShape::Fbx(path),
+            #[cfg(not(feature = "format-obj"))]
+            ModelSource::Obj(path) => return Err(SceneError::FormatNotEnabled(path.clone())),
+            #[cfg(not(feature = "format-fbx"))]
+            ModelSource::Fbx(path) => return Err(SceneError::FormatNotEnabled(path.clone())),
+            ModelSource::Triangle => SourceOrShape::Triangle,
+            ModelSource::Rectangle => SourceOrShape::Rectangle,
+            ModelSource::Cube => SourceOrShape::Cube,
+            ModelSource::UvSphere { segments, rings } => SourceOrShape::UvSphere {
+                segments: *segments,
+                rings: *rings,
+            },
+            ModelSource::Cylinder { segments } => SourceOrShape::Cylinder {
+                segments: *segments,
+            },
+            ModelSource::Plane { subdivisions } => SourceOrShape::Plane {
+                subdivisions: *subdivisions,
+            },
+            ModelSource::Capsule {
+                segments,
+                rings,
+                height,
+            } => SourceOrShape::Capsule {
+                segments: *segments,
+                rings: *rings,
+                height: *height,
+            },
+        };
+        source
+            .parse(assets, &mut Progress::new(None))
+            .map_err(SceneError::Model)
+    }
+}
+
+/// The assets that a model was created from: the source of its mesh, and the settings of the [ModelBuilder](../struct.ModelBuilder.html) that are needed to load it again.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct ModelAsset {
+    /// The source of the mesh of the model.
+    pub source: ModelSource,
+    /// The path of the texture of the model, set with `ModelBuilder::with_texture_from_file`.
+    pub texture: Option<String>,
+    /// The fallback color of the model, set with `ModelBuilder::with_fallback_color`.
+    pub fallback_color: Option<Vector3<f32>>,
+    /// The PBR material of the model, set with `ModelBuilder::with_pbr_material`.
+    pub pbr_material: Option<PbrMaterial>,
+    /// The path of the metallic map of the model, set with `ModelBuilder::with_metallic_map`.
+    pub metallic_map: Option<String>,
+    /// The path of the roughness map of the model, set with `ModelBuilder::with_roughness_map`.
+    pub roughness_map: Option<String>,
+    /// The path of the ambient occlusion map of the model, set with `ModelBuilder::with_ambient_occlusion_map`.
+    pub ambient_occlusion_map: Option<String>,
+    /// The paths of the texture layers of the model, set with `ModelBuilder::with_texture_layers`.
+    pub texture_layers: Vec<String>,
+    /// The path of the splat map of the texture layers, set with `ModelBuilder::with_texture_layers`.
+    pub splat_map: Option<String>,
+    /// How often the texture layers repeat, set with `ModelBuilder::with_texture_layer_scale`.
+    pub texture_layer_scale: f32,
+}
+
+/// A model in a [Scene].
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct SceneModel {
+    /// The assets that the model is loaded from.
+    pub asset: ModelAsset,
+    /// The position, rotation, scale and other settings of the model.
+    pub data: ModelData,
+}
+
+/// The state of the world: the models, the lights and the camera. This can be created with [GameState::scene] and loaded again with [GameState::spawn_scene], e.g. for a level editor or save games.
+///
+/// With the `serialize` feature enabled, a scene implements `serde::Serialize` and `serde::Deserialize`, and it can be saved to and loaded from a [RON](https://github.com/ron-rs/ron) file with [GameState::save_scene] and [GameState::load_scene].
+///
+/// Only models that are created from a file or one of the built-in shapes are part of a scene, models that are created from a [ParsedModel] are skipped. The levels of detail of models are not stored either.
+///
+/// [GameState::scene]: ../struct.GameState.html#method.scene
+/// [GameState::spawn_scene]: ../struct.GameState.html#method.spawn_scene
+/// [GameState::save_scene]: ../struct.GameState.html#method.save_scene
+/// [GameState::load_scene]: ../struct.GameState.html#method.load_scene
+/// [ParsedModel]: ../models/struct.ParsedModel.html
+#[derive(Clone)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct Scene {
+    /// The models in the world.
+    pub models: Vec<SceneModel>,
+    /// The directional lights in the world.
+    pub directional_lights: Vec<DirectionalLight>,
+    /// The point lights in the world.
+    pub point_lights: Vec<PointLight>,
+    /// The matrix of the camera.
+    pub camera: Matrix4<f32>,
+    /// The vertical field of view of the camera.
+    pub camera_fov: Rad<f32>,
+}
+
+impl Scene {
+    pub(crate) fn new(game_state: &GameState) -> Self {
+        // Models are sorted by their id, so that saving the same world twice results in the same file
+        let mut models = game_state.model_handles.iter().collect::<Vec<_>>();
+        models.sort_by_key(|(id, _)| **id);
+        let models = models
+            .into_iter()
+            .filter_map(|(_, model)| {
+                Some(SceneModel {
+                    asset: (**model.asset.as_ref()?).clone(),
+                    data: model.data.read().clone(),
+                })
+            })
+            .collect();
+
+        Self {
+            models,
+            directional_lights: game_state.light.directional.as_slice().to_vec(),
+            point_lights: game_state.light.point.clone(),
+            camera: game_state.camera,
+            camera_fov: game_state.camera_fov,
+        }
+    }
+
+    pub(crate) fn spawn(&self, game_state: &mut GameState) -> Result<Vec<ModelHandle>, SceneError> {
+        let mut light = LightState::new(game_state.light.directional.capacity());
+        for directional in &self.directional_lights {
+            // Lights that do not fit on this GPU are left out
+            if light.directional.push(directional.clone()).is_err() {
+                break;
+            }
+        }
+        light.point = self.point_lights.clone();
+        game_state.light = light;
+        game_state.camera = self.camera;
+        game_state.camera_fov = self.camera_fov;
+
+        self.models
+            .iter()
+            .map(|model| spawn_model(game_state, model))
+            .collect()
+    }
+}
+
+fn spawn_model(game_state: &mut GameState, model: &SceneModel) -> Result<ModelHandle, SceneError> {
+    let builder = match &model.asset.source {
+        #[cfg(feature = "format-obj")]
+        ModelSource::Obj(path) => game_state.new_obj_model(path),
+        #[cfg(feature = "format-fbx")]
+        ModelSource::Fbx(path) => game_state.new_fbx_model(path),
+        #[cfg(not(feature = "format-obj"))]
+        ModelSource::Obj(path) => return Err(SceneError::FormatNotEnabled(path.clone())),
+        #[cfg(not(feature = "format-fbx"))]
+        ModelSource::Fbx(path) => return Err(SceneError::FormatNotEnabled(path.clone())),
+        ModelSource::Triangle => game_state.new_triangle_model(),
+        ModelSource::Rectangle => game_state.new_rectangle_model(),
+        ModelSource::Cube => game_state.new_cube_model(),
+        ModelSource::UvSphere { segments, rings } => {
+            game_state.new_uv_sphere_model(*segments, *rings)
+        }
+        ModelSource::Cylinder { segments } => game_state.new_cylinder_model(*segments),
+        ModelSource::Plane { subdivisions } => game_state.new_plane_model(*subdivisions),
+        ModelSource::Capsule {
+            segments,
+            rings,
+            height,
+        } => game_state.new_capsule_model(*segments, *rings, *height),
+    };
+    build_model(builder, model)
+}
+
+/// Apply the textures and materials of the asset of a scene model to the builder, and build the model with the data of the scene model.
+pub(crate) fn build_model(
+    mut builder: ModelBuilder,
+    model: &SceneModel,
+) -> Result<ModelHandle, SceneError> {
+    let asset = &model.asset;
+    if let Some(texture) = asset.texture.as_ref() {
+        builder = builder.with_texture_from_file(texture);
+    }
+    if let Some(color) = asset.fallback_color {
+        builder = builder.with_fallback_color(color);
+    }
+    if let Some(material) = asset.pbr_material {
+        builder = builder.with_pbr_material(material);
+    }
+    if let Some(map) = asset.metallic_map.as_ref() {
+        builder = builder.with_metallic_map(map);
+    }
+    if let Some(map) = asset.roughness_map.as_ref() {
+        builder = builder.with_roughness_map(map);
+    }
+    if let Some(map) = asset.ambient_occlusion_map.as_ref() {
+        builder = builder.with_ambient_occlusion_map(map);
+    }
+    let layers = asset
+        .texture_layers
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    if let Some(splat_map) = asset.splat_map.as_ref() {
+        builder = builder
+            .with_texture_layers(&layers, splat_map)
+            .with_texture_layer_scale(asset.texture_layer_scale);
+    }
+    let handle = builder.build().map_err(SceneError::Model)?;
+    handle.modify(|data| {
+        let groups = std::mem::take(&mut data.groups);
+        *data = model.data.clone();
+        // The model file could have changed since the scene was saved
+        if data.groups.len() != groups.len() {
+            data.groups = groups;
+        }
+    });
+    Ok(handle)
+}
+
+#[cfg(feature = "serialize")]
+pub(crate) fn save(scene: &Scene, path: &std::path::Path) -> Result<(), SceneError> {
+    let text = ron::ser::to_string_pretty(scene, ron::ser::PrettyConfig::default())
+        .map_err(SceneError::Serialize)?;
+    std::fs::write(path, text).map_err(|inner| SceneError::CouldNotWriteFile {
+        path: path.display().to_string(),
+        inner,
+    })
+}
+
+#[cfg(feature = "serialize")]
+pub(crate) fn load(path: &std::path::Path) -> Result<Scene, SceneError> {
+    let text = std::fs::read_to_string(path).map_err(|inner| SceneError::CouldNotReadFile {
+        path: path.display().to_string(),
+        inner,
+    })?;
+    ron::de::from_str(&text).map_err(SceneError::Deserialize)
+}
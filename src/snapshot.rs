@@ -0,0 +1,68 @@
+use crate::{
+    camera::{CameraController, CameraEffects},
+    model::ModelData,
+    render::lights::LightState,
+    GameState,
+};
+use cgmath::{Matrix4, Rad};
+use std::{collections::HashMap, time::Duration};
+
+/// A copy of the state of the world at a point in time, created with [GameState::debug_snapshot]. This can be restored with [GameState::restore] to rewind the world while debugging.
+///
+/// This contains the data of every model, the lights, the camera and the running time of the game. GPU resources like meshes and textures are not copied, and the state of the [Game] itself is not part of the snapshot.
+///
+/// [GameState::debug_snapshot]: ../struct.GameState.html#method.debug_snapshot
+/// [GameState::restore]: ../struct.GameState.html#method.restore
+/// [Game]: ../trait.Game.html
+#[derive(Clone)]
+pub struct DebugSnapshot {
+    models: HashMap<u64, ModelData>,
+    light: LightState,
+    camera: Matrix4<f32>,
+    camera_fov: Rad<f32>,
+    camera_effects: CameraEffects,
+    camera_controller: Option<CameraController>,
+    running: Duration,
+}
+
+impl DebugSnapshot {
+    pub(crate) fn new(game_state: &GameState) -> Self {
+        Self {
+            models: game_state
+                .model_handles
+                .iter()
+                .map(|(id, model)| (*id, model.data.read().clone()))
+                .collect(),
+            light: game_state.light.clone(),
+            camera: game_state.camera,
+            camera_fov: game_state.camera_fov,
+            camera_effects: game_state.camera_effects.clone(),
+            camera_controller: game_state.camera_controller.clone(),
+            running: game_state.time.running(),
+        }
+    }
+
+    pub(crate) fn restore(&self, game_state: &mut GameState) {
+        for (id, model) in &game_state.model_handles {
+            if let Some(data) = self.models.get(id) {
+                *model.data.write() = data.clone();
+            }
+        }
+        game_state.light = self.light.clone();
+        game_state.camera = self.camera;
+        game_state.camera_fov = self.camera_fov;
+        game_state.camera_effects = self.camera_effects.clone();
+        game_state.camera_controller = self.camera_controller.clone();
+        game_state.time.set_running(self.running);
+    }
+
+    /// The amount of models in this snapshot.
+    pub fn model_count(&self) -> usize {
+        self.models.len()
+    }
+
+    /// The running time of the game when this snapshot was taken.
+    pub fn running(&self) -> Duration {
+        self.running
+    }
+}
@@ -0,0 +1,220 @@
+use image::{imageops::FilterType, RgbaImage};
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+/// The width of the thumbnails of save slots, in pixels. The height is based on the aspect ratio of the window.
+pub const THUMBNAIL_WIDTH: u32 = 320;
+
+/// The extension of the files that contain the data of a save slot
+const DATA_EXTENSION: &str = "sav";
+/// The extension of the thumbnails of a save slot
+const THUMBNAIL_EXTENSION: &str = "png";
+
+/// Save slots on disk, e.g. for save games. Every slot is a file in the [directory](#method.directory) of the storage, optionally with a thumbnail of the game at the moment it was saved.
+///
+/// ```no_run
+/// # use crystal_engine::*;
+/// # let mut state: GameState = unsafe { std::mem::zeroed() };
+/// # let data: Vec<u8> = Vec::new();
+/// state.storage.save_with_thumbnail("slot1", &data).unwrap();
+///
+/// // In the load menu
+/// for slot in state.storage.slots().unwrap() {
+///     if let Some(thumbnail) = state.storage.thumbnail_path(&slot) {
+///         // Show the thumbnail with `GameState::new_gui_element`
+///     }
+/// }
+/// ```
+pub struct Storage {
+    directory: PathBuf,
+    pub(crate) screenshots: Vec<ScreenshotRequest>,
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("saves"),
+            screenshots: Vec::new(),
+        }
+    }
+}
+
+impl Storage {
+    /// The directory that the save slots are stored in. This defaults to `saves`, relative to the working directory of the game.
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Change the directory that the save slots are stored in. The directory is created when the first slot is saved.
+    pub fn set_directory(&mut self, directory: impl Into<PathBuf>) {
+        self.directory = directory.into();
+    }
+
+    /// Write the data of a save slot, replacing the slot if it already exists.
+    ///
+    /// The name of a slot is used as a file name, so it can not contain path separators.
+    pub fn save(&self, slot: &str, data: &[u8]) -> io::Result<()> {
+        let path = self.slot_path(slot, DATA_EXTENSION)?;
+        std::fs::create_dir_all(&self.directory)?;
+        std::fs::write(path, data)
+    }
+
+    /// Write the data of a save slot, and save a thumbnail of the next frame that is rendered. The thumbnail is [THUMBNAIL_WIDTH] pixels wide, and can be found with [thumbnail_path](#method.thumbnail_path) once the frame is rendered.
+    ///
+    /// [THUMBNAIL_WIDTH]: ./constant.THUMBNAIL_WIDTH.html
+    pub fn save_with_thumbnail(&mut self, slot: &str, data: &[u8]) -> io::Result<()> {
+        self.save(slot, data)?;
+        let path = self.slot_path(slot, THUMBNAIL_EXTENSION)?;
+        self.screenshots.push(ScreenshotRequest {
+            path,
+            max_width: Some(THUMBNAIL_WIDTH),
+        });
+        Ok(())
+    }
+
+    /// Read the data of a save slot.
+    pub fn load(&self, slot: &str) -> io::Result<Vec<u8>> {
+        std::fs::read(self.slot_path(slot, DATA_EXTENSION)?)
+    }
+
+    /// Remove a save slot and its thumbnail.
+    pub fn delete(&self, slot: &str) -> io::Result<()> {
+        std::fs::remove_file(self.slot_path(slot, DATA_EXTENSION)?)?;
+        match std::fs::remove_file(self.slot_path(slot, THUMBNAIL_EXTENSION)?) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Get the names of all save slots, sorted by name. This is empty when nothing has been saved yet.
+    pub fn slots(&self) -> io::Result<Vec<String>> {
+        let entries = match std::fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut slots = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(DATA_EXTENSION) {
+                continue;
+            }
+            if let Some(slot) = path.file_stem().and_then(|s| s.to_str()) {
+                slots.push(slot.to_owned());
+            }
+        }
+        slots.sort();
+        Ok(slots)
+    }
+
+    /// Get the path of the thumbnail of a save slot, if the slot has a thumbnail. This can be passed to [GuiElementBuilder::with_texture](./struct.GuiElementBuilder.html#method.with_texture) to show the thumbnail.
+    pub fn thumbnail_path(&self, slot: &str) -> Option<PathBuf> {
+        self.slot_path(slot, THUMBNAIL_EXTENSION)
+            .ok()
+            .filter(|path| path.is_file())
+    }
+
+    fn slot_path(&self, slot: &str, extension: &str) -> io::Result<PathBuf> {
+        let is_file_name = !slot.is_empty()
+            && slot != "."
+            && slot != ".."
+            && !slot.contains(|c: char| c == '/' || c == '\\');
+        if !is_file_name {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid save slot name {:?}", slot),
+            ));
+        }
+        Ok(self.directory.join(format!("{}.{}", slot, extension)))
+    }
+}
+
+/// A request to save the next frame that is rendered to a file.
+pub(crate) struct ScreenshotRequest {
+    pub path: PathBuf,
+    /// The image is scaled down to this width, keeping the aspect ratio
+    pub max_width: Option<u32>,
+}
+
+impl ScreenshotRequest {
+    /// Save the pixels of the frame. `bgra` is true when the pixels are in the BGRA order of the swapchain, instead of RGBA.
+    pub fn save(&self, pixels: Vec<u8>, (width, height): (u32, u32), bgra: bool) {
+        if let Err(e) = self.try_save(pixels, (width, height), bgra) {
+            eprintln!("Could not save screenshot {:?}: {:?}", self.path, e);
+        }
+    }
+
+    fn try_save(
+        &self,
+        pixels: Vec<u8>,
+        (width, height): (u32, u32),
+        bgra: bool,
+    ) -> image::ImageResult<()> {
+        let image = screenshot_image(pixels, (width, height), bgra, self.max_width);
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent).map_err(image::ImageError::IoError)?;
+        }
+        image.save(&self.path)
+    }
+}
+
+pub(crate) fn screenshot_image(
+    mut pixels: Vec<u8>,
+    (width, height): (u32, u32),
+    bgra: bool,
+    max_width: Option<u32>,
+) -> RgbaImage {
+    if bgra {
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+    }
+    // The frame is presented as-is, so the alpha channel is meaningless
+    for pixel in pixels.chunks_exact_mut(4) {
+        pixel[3] = 255;
+    }
+    // The buffer is created with the size of the image, so this should never fail
+    let image = RgbaImage::from_raw(width, height, pixels).expect("Invalid screenshot size");
+    match max_width {
+        Some(max_width) if width > max_width => {
+            let scaled_height = (height as u64 * max_width as u64 / width as u64).max(1) as u32;
+            image::imageops::resize(&image, max_width, scaled_height, FilterType::Triangle)
+        }
+        _ => image,
+    }
+}
+
+#[test]
+fn test_screenshot_image() {
+    let pixels = (0..8 * 4)
+        .flat_map(|_| vec![1, 2, 3, 0])
+        .collect::<Vec<u8>>();
+    let image = screenshot_image(pixels.clone(), (8, 4), true, Some(4));
+    assert_eq!((4, 2), image.dimensions());
+    assert_eq!(&[3, 2, 1, 255], &image.get_pixel(0, 0).0);
+
+    let image = screenshot_image(pixels, (8, 4), false, None);
+    assert_eq!((8, 4), image.dimensions());
+    assert_eq!(&[1, 2, 3, 255], &image.get_pixel(0, 0).0);
+}
+
+#[test]
+fn test_storage_slots() {
+    let mut storage = Storage::default();
+    let directory = std::env::temp_dir().join(format!("crystal-storage-{}", std::process::id()));
+    storage.set_directory(&directory);
+
+    assert!(storage.slots().unwrap().is_empty());
+    storage.save("b", &[1, 2, 3]).unwrap();
+    storage.save("a", &[4]).unwrap();
+    assert_eq!(vec!["a", "b"], storage.slots().unwrap());
+    assert_eq!(vec![1, 2, 3], storage.load("b").unwrap());
+    assert!(storage.thumbnail_path("b").is_none());
+    assert!(storage.save("../escape", &[]).is_err());
+
+    storage.delete("b").unwrap();
+    assert_eq!(vec!["a"], storage.slots().unwrap());
+    std::fs::remove_dir_all(directory).unwrap();
+}
@@ -0,0 +1,479 @@
+use crate::{
+    assets::AssetPaths,
+    model::{loader::ParsedModel, ModelHandle},
+    scene::{ModelSource, SceneModel},
+    state::SceneError,
+};
+use cgmath::{InnerSpace, Vector3};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, Receiver, Sender},
+        Arc,
+    },
+    thread,
+};
+
+/// The most chunks that are parsed in the background at the same time. New chunks are only queued when the worker thread is almost done, so a chunk that comes close to the focus point is picked before chunks that were in range earlier.
+const MAX_PARSING_CHUNKS: usize = 2;
+
+/// The id of a chunk that was added with [StreamingState::add_chunk](./struct.StreamingState.html#method.add_chunk).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChunkId(u64);
+
+/// Whether the models of a chunk are in the world, see [StreamingState::chunk_state](./struct.StreamingState.html#method.chunk_state).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkState {
+    /// The chunk is too far away from the focus point, none of its models are in the world.
+    Unloaded,
+    /// The models of the chunk are being parsed and uploaded. Some of the models can already be in the world.
+    Loading,
+    /// Every model of the chunk is in the world. Models that could not be loaded are skipped.
+    Loaded,
+}
+
+/// Loads and unloads the models of an open world in chunks, based on the distance to a [focus](#structfield.focus) point. This is the [streaming](../struct.GameState.html#structfield.streaming) field of the [GameState](../struct.GameState.html).
+///
+/// Model files are read and parsed on a background thread, starting with the chunks that are closest to the focus point. The parsed models are uploaded to the GPU at the end of every frame, at most [uploads_per_frame](#structfield.uploads_per_frame) at a time, so loading a chunk does not cause a hitch. Chunks that are further away than [unload_distance](#structfield.unload_distance) are removed from the world.
+///
+/// The textures that are set on the [ModelAsset](./struct.ModelAsset.html) of a model are loaded when the model is uploaded. Streamed models are not part of a [Scene](./struct.Scene.html).
+///
+/// ```no_run
+/// # use crystal_engine::*;
+/// # use crystal_engine::state::SceneModel;
+/// # use cgmath::Vector3;
+/// # let mut state: GameState = unsafe { std::mem::zeroed() };
+/// # let trees_of_chunk: Vec<SceneModel> = Vec::new();
+/// state.streaming.add_chunk(Vector3::new(64.0, 0.0, 128.0), trees_of_chunk);
+///
+/// // Every frame, load the chunks around the player
+/// # let player_position = Vector3::new(0.0, 0.0, 0.0);
+/// state.streaming.focus = player_position;
+/// ```
+pub struct StreamingState {
+    /// The point that chunks are loaded around, usually the position of the player or the camera. Defaults to the origin.
+    pub focus: Vector3<f32>,
+    /// Chunks that are closer than this to the focus point are loaded. Defaults to `100.0`.
+    pub load_distance: f32,
+    /// Chunks that are further away than this from the focus point are unloaded. This should be larger than the [load_distance](#structfield.load_distance), so a chunk is not loaded and unloaded over and over when the focus point moves back and forth on its edge. Defaults to `150.0`.
+    pub unload_distance: f32,
+    /// The maximum amount of models that are uploaded to the GPU every frame. Defaults to `4`.
+    pub uploads_per_frame: usize,
+    chunks: Vec<Chunk>,
+    next_id: u64,
+    next_load_id: u64,
+    /// The background thread that parses the models, which is started when the first chunk is loaded
+    worker: Option<Worker>,
+}
+
+impl Default for StreamingState {
+    fn default() -> Self {
+        Self {
+            focus: Vector3::new(0.0, 0.0, 0.0),
+            load_distance: 100.0,
+            unload_distance: 150.0,
+            uploads_per_frame: 4,
+            chunks: Vec::new(),
+            next_id: 0,
+            next_load_id: 0,
+            worker: None,
+        }
+    }
+}
+
+struct Chunk {
+    id: ChunkId,
+    position: Vector3<f32>,
+    models: Vec<SceneModel>,
+    load: Load,
+}
+
+enum Load {
+    Unloaded,
+    Loading {
+        /// Results of an earlier load of the same chunk are ignored
+        load_id: u64,
+        /// Tells the worker thread to skip the models that it has not parsed yet
+        cancelled: Arc<AtomicBool>,
+        /// The amount of models that the worker thread has not parsed yet
+        unparsed: usize,
+        /// The models that are parsed, but not uploaded yet, with their index in the chunk
+        parsed: Vec<(usize, ParsedModel)>,
+        /// The amount of models that are not uploaded yet
+        remaining: usize,
+        handles: Vec<ModelHandle>,
+    },
+    Loaded(Vec<ModelHandle>),
+}
+
+/// A parsed model that has to be uploaded by the game state, see `StreamingState::poll`.
+pub(crate) struct Upload {
+    pub chunk: ChunkId,
+    pub load_id: u64,
+    pub model: SceneModel,
+    pub parsed: ParsedModel,
+}
+
+struct Job {
+    chunk: ChunkId,
+    load_id: u64,
+    cancelled: Arc<AtomicBool>,
+    sources: Vec<ModelSource>,
+    assets: AssetPaths,
+}
+
+struct Parsed {
+    chunk: ChunkId,
+    load_id: u64,
+    index: usize,
+    result: Result<ParsedModel, SceneError>,
+}
+
+struct Worker {
+    jobs: Sender<Job>,
+    results: Receiver<Parsed>,
+}
+
+impl Worker {
+    fn spawn() -> Self {
+        let (jobs, job_receiver) = channel::<Job>();
+        let (result_sender, results) = channel();
+        thread::Builder::new()
+            .name("crystal-engine streaming".to_owned())
+            .spawn(move || {
+                for job in job_receiver {
+                    for (index, source) in job.sources.iter().enumerate() {
+                        if job.cancelled.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        let parsed = Parsed {
+                            chunk: job.chunk,
+                            load_id: job.load_id,
+                            index,
+                            result: source.parse(&job.assets),
+                        };
+                        // The receiver is only dropped when the streaming state is dropped, in
+                        // which case the thread is no longer needed
+                        if result_sender.send(parsed).is_err() {
+                            return;
+                        }
+                    }
+                }
+            })
+            .unwrap(); // This can only fail if the OS can not create a new thread
+        Self { jobs, results }
+    }
+}
+
+impl Chunk {
+    fn distance(&self, focus: Vector3<f32>) -> f32 {
+        (self.position - focus).magnitude()
+    }
+
+    fn is_parsing(&self) -> bool {
+        match &self.load {
+            Load::Loading { unparsed, .. } => *unparsed > 0,
+            _ => false,
+        }
+    }
+
+    fn unload(&mut self) {
+        if let Load::Loading { cancelled, .. } = &self.load {
+            cancelled.store(true, Ordering::Relaxed);
+        }
+        // Dropping the handles removes the models from the world
+        self.load = Load::Unloaded;
+    }
+
+    fn receive(&mut self, parsed: Parsed) {
+        if let Load::Loading {
+            load_id,
+            unparsed,
+            parsed: parsed_models,
+            remaining,
+            ..
+        } = &mut self.load
+        {
+            if *load_id != parsed.load_id {
+                return;
+            }
+            *unparsed -= 1;
+            match parsed.result {
+                Ok(model) => parsed_models.push((parsed.index, model)),
+                Err(e) => {
+                    eprintln!("Could not load model {} of a chunk: {}", parsed.index, e);
+                    *remaining -= 1;
+                }
+            }
+        }
+        self.finish_if_done();
+    }
+
+    fn finish_if_done(&mut self) {
+        if let Load::Loading {
+            remaining: 0,
+            handles,
+            ..
+        } = &mut self.load
+        {
+            self.load = Load::Loaded(std::mem::take(handles));
+        }
+    }
+}
+
+impl StreamingState {
+    /// Add a chunk with the given models. The chunk is loaded when the [focus](#structfield.focus) point comes within the [load_distance](#structfield.load_distance) of `position`, which is usually the center of the chunk.
+    ///
+    /// The models are loaded from their [ModelAsset](./struct.ModelAsset.html), and placed with their [ModelData](../models/struct.ModelData.html), just like the models of a [Scene](./struct.Scene.html).
+    pub fn add_chunk(
+        &mut self,
+        position: impl Into<Vector3<f32>>,
+        models: Vec<SceneModel>,
+    ) -> ChunkId {
+        let id = ChunkId(self.next_id);
+        self.next_id += 1;
+        self.chunks.push(Chunk {
+            id,
+            position: position.into(),
+            models,
+            load: Load::Unloaded,
+        });
+        id
+    }
+
+    /// Remove a chunk, and its models from the world. Returns `false` if the chunk was already removed.
+    pub fn remove_chunk(&mut self, id: ChunkId) -> bool {
+        match self.chunks.iter().position(|c| c.id == id) {
+            Some(index) => {
+                self.chunks.remove(index).unload();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Get whether the models of a chunk are in the world. Returns `None` if the chunk was removed.
+    pub fn chunk_state(&self, id: ChunkId) -> Option<ChunkState> {
+        let chunk = self.chunks.iter().find(|c| c.id == id)?;
+        Some(match chunk.load {
+            Load::Unloaded => ChunkState::Unloaded,
+            Load::Loading { .. } => ChunkState::Loading,
+            Load::Loaded(_) => ChunkState::Loaded,
+        })
+    }
+
+    /// Get the handles of the models of a chunk that are in the world, e.g. to animate them. The handles are dropped when the chunk is unloaded.
+    pub fn chunk_models(&self, id: ChunkId) -> &[ModelHandle] {
+        match self.chunks.iter().find(|c| c.id == id).map(|c| &c.load) {
+            Some(Load::Loading { handles, .. }) | Some(Load::Loaded(handles)) => handles,
+            _ => &[],
+        }
+    }
+
+    /// Unload the chunks that are too far away, start loading the closest chunks that are in range, and collect the parsed models that should be uploaded this frame.
+    pub(crate) fn poll(&mut self, assets: &AssetPaths) -> Vec<Upload> {
+        let focus = self.focus;
+        for chunk in &mut self.chunks {
+            let loaded = !matches!(chunk.load, Load::Unloaded);
+            if loaded && chunk.distance(focus) > self.unload_distance {
+                chunk.unload();
+            }
+        }
+
+        if let Some(worker) = &self.worker {
+            while let Ok(parsed) = worker.results.try_recv() {
+                if let Some(chunk) = self.chunks.iter_mut().find(|c| c.id == parsed.chunk) {
+                    chunk.receive(parsed);
+                }
+            }
+        }
+
+        let parsing = self.chunks.iter().filter(|c| c.is_parsing()).count();
+        let load_distance = self.load_distance;
+        let mut in_range = self
+            .chunks
+            .iter_mut()
+            .filter(|c| matches!(c.load, Load::Unloaded) && c.distance(focus) <= load_distance)
+            .collect::<Vec<_>>();
+        sort_by_distance(&mut in_range, focus);
+        for chunk in in_range
+            .into_iter()
+            .take(MAX_PARSING_CHUNKS.saturating_sub(parsing))
+        {
+            let load_id = self.next_load_id;
+            self.next_load_id += 1;
+            let cancelled = Arc::new(AtomicBool::new(false));
+            chunk.load = Load::Loading {
+                load_id,
+                cancelled: cancelled.clone(),
+                unparsed: chunk.models.len(),
+                parsed: Vec::new(),
+                remaining: chunk.models.len(),
+                handles: Vec::new(),
+            };
+            // A chunk without models is done right away
+            chunk.finish_if_done();
+            if chunk.models.is_empty() {
+                continue;
+            }
+            let worker = self.worker.get_or_insert_with(Worker::spawn);
+            // The worker thread only stops when the job sender is dropped, so this should never
+            // fail
+            worker
+                .jobs
+                .send(Job {
+                    chunk: chunk.id,
+                    load_id,
+                    cancelled,
+                    sources: chunk
+                        .models
+                        .iter()
+                        .map(|m| m.asset.source.clone())
+                        .collect(),
+                    assets: assets.clone(),
+                })
+                .unwrap();
+        }
+
+        let mut loading = self
+            .chunks
+            .iter_mut()
+            .filter(|c| match &c.load {
+                Load::Loading { parsed, .. } => !parsed.is_empty(),
+                _ => false,
+            })
+            .collect::<Vec<_>>();
+        sort_by_distance(&mut loading, focus);
+        let mut uploads = Vec::new();
+        for chunk in loading {
+            if let Load::Loading {
+                load_id, parsed, ..
+            } = &mut chunk.load
+            {
+                while uploads.len() < self.uploads_per_frame {
+                    let (index, parsed) = match parsed.pop() {
+                        Some(parsed) => parsed,
+                        None => break,
+                    };
+                    uploads.push(Upload {
+                        chunk: chunk.id,
+                        load_id: *load_id,
+                        model: chunk.models[index].clone(),
+                        parsed,
+                    });
+                }
+            }
+        }
+        uploads
+    }
+
+    /// Add a model that was uploaded to its chunk. The model is dropped if the chunk was unloaded in the meantime.
+    pub(crate) fn finish_upload(
+        &mut self,
+        chunk: ChunkId,
+        upload_load_id: u64,
+        result: Result<ModelHandle, SceneError>,
+    ) {
+        let chunk = match self.chunks.iter_mut().find(|c| c.id == chunk) {
+            Some(chunk) => chunk,
+            None => return,
+        };
+        if let Load::Loading {
+            load_id,
+            remaining,
+            handles,
+            ..
+        } = &mut chunk.load
+        {
+            if *load_id != upload_load_id {
+                return;
+            }
+            *remaining -= 1;
+            match result {
+                Ok(handle) => handles.push(handle),
+                Err(e) => eprintln!("Could not load a model of a chunk: {}", e),
+            }
+        }
+        chunk.finish_if_done();
+    }
+}
+
+fn sort_by_distance(chunks: &mut [&mut Chunk], focus: Vector3<f32>) {
+    chunks.sort_by(|a, b| {
+        a.distance(focus)
+            .partial_cmp(&b.distance(focus))
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+}
+
+#[test]
+fn test_streaming() {
+    use crate::{
+        model::ModelData,
+        scene::{ModelAsset, ModelSource},
+    };
+    use std::time::{Duration, Instant};
+
+    let cube = || SceneModel {
+        asset: ModelAsset {
+            source: ModelSource::Cube,
+            texture: None,
+            fallback_color: None,
+            pbr_material: None,
+            metallic_map: None,
+            roughness_map: None,
+            ambient_occlusion_map: None,
+            texture_layers: Vec::new(),
+            splat_map: None,
+            texture_layer_scale: 1.0,
+        },
+        data: ModelData::default(),
+    };
+    let mut streaming = StreamingState {
+        uploads_per_frame: 1,
+        ..StreamingState::default()
+    };
+    let far = streaming.add_chunk(Vector3::new(50.0, 0.0, 0.0), vec![cube(), cube()]);
+    let near = streaming.add_chunk(Vector3::new(10.0, 0.0, 0.0), vec![cube()]);
+    let out_of_range = streaming.add_chunk(Vector3::new(500.0, 0.0, 0.0), vec![cube()]);
+    let empty = streaming.add_chunk(Vector3::new(0.0, 0.0, 0.0), Vec::new());
+    let assets = AssetPaths::default();
+
+    let mut uploads = Vec::new();
+    let start = Instant::now();
+    while uploads.len() < 3 && start.elapsed() < Duration::from_secs(10) {
+        let frame = streaming.poll(&assets);
+        assert!(frame.len() <= 1);
+        for upload in frame {
+            uploads.push(upload.chunk);
+            // Uploading needs a GPU, so every model fails to load
+            streaming.finish_upload(
+                upload.chunk,
+                upload.load_id,
+                Err(SceneError::FormatNotEnabled(String::new())),
+            );
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(3, uploads.len());
+    // The empty chunk is the closest, so it is loaded first but does not need uploads
+    assert_eq!(Some(ChunkState::Loaded), streaming.chunk_state(empty));
+    assert_eq!(Some(ChunkState::Loaded), streaming.chunk_state(near));
+    assert_eq!(Some(ChunkState::Loaded), streaming.chunk_state(far));
+    assert_eq!(
+        Some(ChunkState::Unloaded),
+        streaming.chunk_state(out_of_range)
+    );
+
+    streaming.focus = Vector3::new(500.0, 0.0, 0.0);
+    streaming.poll(&assets);
+    assert_eq!(Some(ChunkState::Unloaded), streaming.chunk_state(near));
+    assert_eq!(
+        Some(ChunkState::Loading),
+        streaming.chunk_state(out_of_range)
+    );
+
+    assert!(streaming.remove_chunk(out_of_range));
+    assert!(!streaming.remove_chunk(out_of_range));
+    assert_eq!(None, streaming.chunk_state(out_of_range));
+}
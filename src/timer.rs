@@ -0,0 +1,222 @@
+use crate::GameState;
+use std::time::Duration;
+
+/// A handle to a timer that was started with [GameState::after] or [GameState::every]. This can be used to stop the timer with [GameState::cancel_timer].
+///
+/// [GameState::after]: ../struct.GameState.html#method.after
+/// [GameState::every]: ../struct.GameState.html#method.every
+/// [GameState::cancel_timer]: ../struct.GameState.html#method.cancel_timer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TimerId(u64);
+
+/// The maximum amount of times a repeating timer is called per frame. When the game can not keep up, e.g. after hitting a breakpoint, the time that is left is dropped.
+const MAX_CALLS_PER_FRAME: u32 = 8;
+
+/// The callback of a timer. This is generic over the state that is passed to the callbacks, so the timers can be tested without a [GameState].
+pub(crate) enum TimerCallback<S = GameState> {
+    Once(Box<dyn FnOnce(&mut S)>),
+    Repeat(Box<dyn FnMut(&mut S)>),
+}
+
+struct Timer<S> {
+    id: TimerId,
+    /// The time until the timer is due
+    remaining: Duration,
+    /// The time between two calls of a repeating timer, `None` for a timer that is called once
+    interval: Option<Duration>,
+    /// This is `None` while a repeating timer is being called
+    callback: Option<TimerCallback<S>>,
+}
+
+/// The timers of the game, which are advanced with the scaled time of [TimeState](../state/struct.TimeState.html).
+pub(crate) struct Timers<S = GameState> {
+    next_id: u64,
+    timers: Vec<Timer<S>>,
+}
+
+impl<S> Default for Timers<S> {
+    fn default() -> Self {
+        Self {
+            next_id: 0,
+            timers: Vec::new(),
+        }
+    }
+}
+
+impl<S> Timers<S> {
+    pub(crate) fn add(
+        &mut self,
+        delay: Duration,
+        interval: Option<Duration>,
+        callback: TimerCallback<S>,
+    ) -> TimerId {
+        let id = TimerId(self.next_id);
+        self.next_id += 1;
+        self.timers.push(Timer {
+            id,
+            remaining: delay,
+            interval,
+            callback: Some(callback),
+        });
+        id
+    }
+
+    pub(crate) fn cancel(&mut self, id: TimerId) -> bool {
+        let count = self.timers.len();
+        self.timers.retain(|t| t.id != id);
+        self.timers.len() != count
+    }
+
+    /// Advance every timer by `delta`. Returns the timers that are due, once for every time they should be called.
+    pub(crate) fn advance(&mut self, delta: Duration) -> Vec<TimerId> {
+        let mut due = Vec::new();
+        for timer in &mut self.timers {
+            if timer.remaining > delta {
+                timer.remaining -= delta;
+                continue;
+            }
+            let mut left = delta - timer.remaining;
+            due.push(timer.id);
+            match timer.interval {
+                Some(interval) => {
+                    let mut calls = 1;
+                    // A timer with an interval of zero is called once every frame
+                    while interval > Duration::from_secs(0)
+                        && left >= interval
+                        && calls < MAX_CALLS_PER_FRAME
+                    {
+                        left -= interval;
+                        calls += 1;
+                        due.push(timer.id);
+                    }
+                    if calls == MAX_CALLS_PER_FRAME {
+                        left = Duration::from_secs(0);
+                    }
+                    timer.remaining = interval.checked_sub(left).unwrap_or_default();
+                }
+                None => timer.remaining = Duration::from_secs(0),
+            }
+        }
+        due
+    }
+
+    /// Take the callback of a timer that is due. Timers that are called once are removed.
+    pub(crate) fn take_callback(&mut self, id: TimerId) -> Option<TimerCallback<S>> {
+        let index = self.timers.iter().position(|t| t.id == id)?;
+        if self.timers[index].interval.is_none() {
+            self.timers.remove(index).callback
+        } else {
+            self.timers[index].callback.take()
+        }
+    }
+
+    /// Put the callback of a repeating timer back, unless the timer was cancelled by its callback.
+    pub(crate) fn restore_callback(&mut self, id: TimerId, callback: TimerCallback<S>) {
+        if let Some(timer) = self.timers.iter_mut().find(|t| t.id == id) {
+            timer.callback = Some(callback);
+        }
+    }
+
+    /// Advance the timers of `state` by `delta`, and call the callbacks of the timers that are due. The callbacks can add and cancel timers.
+    pub(crate) fn run(state: &mut S, timers: fn(&mut S) -> &mut Self, delta: Duration) {
+        for id in timers(state).advance(delta) {
+            match timers(state).take_callback(id) {
+                Some(TimerCallback::Once(callback)) => callback(state),
+                Some(TimerCallback::Repeat(mut callback)) => {
+                    callback(state);
+                    timers(state).restore_callback(id, TimerCallback::Repeat(callback));
+                }
+                // The timer was cancelled by an earlier callback
+                None => {}
+            }
+        }
+    }
+}
+
+#[test]
+fn test_timers() {
+    let mut timers = Timers::<()>::default();
+    let once = timers.add(
+        Duration::from_millis(100),
+        None,
+        TimerCallback::Once(Box::new(|_| {})),
+    );
+    let every = timers.add(
+        Duration::from_millis(30),
+        Some(Duration::from_millis(30)),
+        TimerCallback::Repeat(Box::new(|_| {})),
+    );
+
+    assert_eq!(
+        vec![every, every],
+        timers.advance(Duration::from_millis(70))
+    );
+    assert_eq!(vec![once, every], timers.advance(Duration::from_millis(30)));
+    assert!(timers.take_callback(once).is_some());
+    assert!(timers.take_callback(once).is_none());
+    assert_eq!(1, timers.timers.len());
+
+    let callback = timers.take_callback(every).unwrap();
+    timers.restore_callback(every, callback);
+    assert!(timers.cancel(every));
+    assert!(!timers.cancel(every));
+    assert_eq!(0, timers.timers.len());
+
+    // A long frame calls a repeating timer a limited amount of times
+    let every = timers.add(
+        Duration::from_millis(1),
+        Some(Duration::from_millis(1)),
+        TimerCallback::Repeat(Box::new(|_| {})),
+    );
+    assert_eq!(
+        MAX_CALLS_PER_FRAME as usize,
+        timers.advance(Duration::from_secs(10)).len()
+    );
+    assert_eq!(vec![every], timers.advance(Duration::from_millis(1)));
+}
+
+#[test]
+fn test_timers_run_after_update() {
+    #[derive(Default)]
+    struct State {
+        timers: Timers<State>,
+        log: Vec<&'static str>,
+        health: u32,
+    }
+    fn timers(state: &mut State) -> &mut Timers<State> {
+        &mut state.timers
+    }
+    // A frame in the same order as WindowState::update: Game::update, then the timers
+    let frame = |state: &mut State, update: &mut dyn FnMut(&mut State)| {
+        update(state);
+        Timers::run(state, timers, Duration::from_millis(10));
+    };
+
+    let mut state = State::default();
+    frame(&mut state, &mut |state| {
+        state.log.push("update");
+        state.health = 10;
+        state.timers.add(
+            Duration::from_millis(10),
+            None,
+            TimerCallback::Once(Box::new(|state: &mut State| {
+                // The callback sees the changes of the update in the same frame
+                assert_eq!(10, state.health);
+                state.log.push("timer");
+            })),
+        );
+    });
+    assert_eq!(vec!["update", "timer"], state.log);
+
+    // A repeating timer can cancel itself
+    let id = state.timers.add(
+        Duration::from_millis(5),
+        Some(Duration::from_millis(5)),
+        TimerCallback::Repeat(Box::new(|state: &mut State| state.log.push("repeat"))),
+    );
+    frame(&mut state, &mut |_| {});
+    assert_eq!(vec!["update", "timer", "repeat", "repeat"], state.log);
+    assert!(state.timers.cancel(id));
+    frame(&mut state, &mut |_| {});
+    assert_eq!(4, state.log.len());
+}